@@ -0,0 +1,129 @@
+//! Compares the tree-walking interpreter with the bytecode VM. Run with
+//! `cargo bench`.
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use blixt::ast::Ast;
+use blixt::common::Context;
+use blixt::{builtins, compiler, interpreter, lexer, parser, typecheck, vm};
+
+const RUNS: usize = 5;
+
+const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "fib",
+        "fn fib(n: int) -> int {
+             if n < 2 { return n }
+             return fib(n - 1) + fib(n - 2)
+         }
+         fib(25)",
+    ),
+    (
+        "loops",
+        "fn sum(n: int) -> int {
+             total := 0
+             i := 0
+             while i < n {
+                 if i % 3 == 0 || i % 5 == 0 { total += i }
+                 i += 1
+             }
+             return total
+         }
+         sum(1000000)",
+    ),
+    (
+        "arrays",
+        "fn sieve(n: int) -> int {
+             primes := [false, false]
+             while len(primes) < n { push(primes, true) }
+             count := 0
+             i := 2
+             while i < n {
+                 if primes[i] {
+                     count += 1
+                     j := i * 2
+                     while j < n {
+                         primes[j] = false
+                         j += i
+                     }
+                 }
+                 i += 1
+             }
+             return count
+         }
+         sieve(200000)",
+    ),
+    (
+        "maps",
+        "fn count(n: int) -> int {
+             m := {0: 0}
+             i := 0
+             while i < n {
+                 m[i % 100] = i
+                 i += 1
+             }
+             total := 0
+             for k in m { total += m[k] }
+             return total
+         }
+         count(200000)",
+    ),
+];
+
+fn main() {
+    println!(
+        "{:<10}{:>14}{:>14}{:>10}",
+        "program", "interpreter", "vm", "speedup"
+    );
+
+    for (name, source) in PROGRAMS {
+        let interpreted = median(|| {
+            let (ast, mut context) = prepare(source);
+            time(|| interpreter::interpret(&ast, &mut context))
+        });
+        let compiled = median(|| {
+            let (ast, mut context) = prepare(source);
+            time(|| {
+                let program = compiler::compile(&ast, &mut context);
+                vm::run(&program, &mut context)
+            })
+        });
+
+        println!(
+            "{:<10}{:>14.2?}{:>14.2?}{:>9.1}x",
+            name,
+            interpreted,
+            compiled,
+            interpreted.as_secs_f64() / compiled.as_secs_f64()
+        );
+    }
+}
+
+fn prepare(source: &str) -> (Ast, Context) {
+    let mut context = Context::new();
+    builtins::register(&mut context);
+    let file = context.interner.intern("bench.bl");
+    context
+        .source_code
+        .insert(PathBuf::from("bench.bl"), source.to_string());
+
+    let tokens =
+        lexer::generate_tokens(source.as_bytes(), file, &mut context).unwrap();
+    let ast = parser::parse_ast(tokens, &mut context).unwrap();
+    typecheck::typecheck(&ast, &mut context).unwrap();
+
+    (ast, context)
+}
+
+fn time(run: impl FnOnce() -> Result<(), ()>) -> Duration {
+    let start = Instant::now();
+    run().expect("Benchmark failed");
+    start.elapsed()
+}
+
+fn median(mut sample: impl FnMut() -> Duration) -> Duration {
+    let mut times: Vec<_> = (0..RUNS).map(|_| sample()).collect();
+    times.sort();
+    times[RUNS / 2]
+}
@@ -0,0 +1,69 @@
+//! Times the lexer on a large generated script. Run with
+//! `cargo bench --bench lexer`, and with `--features logos` to compare the
+//! backends.
+
+use std::time::{Duration, Instant};
+
+use blixt::common::Context;
+use blixt::lexer::{self, Backend};
+
+const RUNS: usize = 5;
+const FUNCTIONS: usize = 20000;
+
+fn main() {
+    let source = script();
+    bench("hand-written", Backend::HandWritten, &source);
+    #[cfg(feature = "logos")]
+    bench("logos", Backend::Logos, &source);
+}
+
+fn bench(name: &str, backend: Backend, source: &str) {
+    let (tokens, time) = median(|| {
+        let mut context = Context::new();
+        context.limits.max_tokens = usize::MAX;
+        let file = context.interner.intern("bench.bl");
+
+        let start = Instant::now();
+        let tokens = lexer::generate_tokens_with(
+            backend,
+            source.as_bytes(),
+            file,
+            &mut context,
+        )
+        .expect("Lexing failed");
+        (tokens.len(), start.elapsed())
+    });
+
+    println!(
+        "{}: {} tokens in {:.2?}, {:.1} MB/s",
+        name,
+        tokens,
+        time,
+        source.len() as f64 / time.as_secs_f64() / 1e6
+    );
+}
+
+/// A script with many distinct names and string literals, some of them
+/// with escape sequences, like generated code tends to have.
+fn script() -> String {
+    (0..FUNCTIONS)
+        .map(|i| {
+            format!(
+                "fn step_{i}(count_{i}: int, label: string) -> string {{
+                     total := count_{i} * {i} + 1.5e-3
+                     if total >= 10 && label != \"skip\" {{
+                         return label + \"\\tstep {i}\\n\"
+                     }}
+                     return \"done\" // {i}
+                 }}\n",
+                i = i
+            )
+        })
+        .collect()
+}
+
+fn median(mut sample: impl FnMut() -> (usize, Duration)) -> (usize, Duration) {
+    let mut samples: Vec<_> = (0..RUNS).map(|_| sample()).collect();
+    samples.sort_by_key(|(_, time)| *time);
+    samples[RUNS / 2]
+}
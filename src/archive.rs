@@ -0,0 +1,195 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Writes a zip archive to `path` with an entry for each of `files`, given
+/// as names in the archive and paths to copy. A directory is added with
+/// everything under it. Returns the number of files added.
+pub fn zip_create(
+    path: &Path,
+    files: &[(String, String)],
+) -> io::Result<usize> {
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(path)?));
+    let mut added = 0;
+    for (name, source) in files {
+        added +=
+            add_to_zip(&mut zip, name, Path::new(source)).map_err(|e| {
+                io::Error::new(e.kind(), format!("{}: {}", source, e))
+            })?;
+    }
+    zip.finish()?;
+    Ok(added)
+}
+
+fn add_to_zip(
+    zip: &mut ZipWriter<BufWriter<File>>,
+    name: &str,
+    source: &Path,
+) -> io::Result<usize> {
+    let metadata = fs::metadata(source)?;
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .unix_permissions(permissions(&metadata));
+
+    if !metadata.is_dir() {
+        zip.start_file(name, options)?;
+        io::copy(&mut File::open(source)?, zip)?;
+        return Ok(1);
+    }
+
+    zip.add_directory(name, options)?;
+    let mut entries = fs::read_dir(source)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let mut added = 0;
+    for entry in entries {
+        let child = format!("{}/{}", name, entry.file_name().to_string_lossy());
+        added += add_to_zip(zip, &child, &entry.path())?;
+    }
+    Ok(added)
+}
+
+/// Extracts the zip archive at `path` into `dir`, creating it if needed.
+/// Entries that would end up outside `dir` are skipped. Returns the number
+/// of files extracted.
+pub fn zip_extract(path: &Path, dir: &Path) -> io::Result<usize> {
+    let mut zip = ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let mut extracted = 0;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let target = match entry.enclosed_name() {
+            Some(name) => dir.join(name),
+            None => continue,
+        };
+
+        if entry.is_dir() {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut entry, &mut File::create(&target)?)?;
+        if let Some(mode) = entry.unix_mode() {
+            set_permissions(&target, mode)?;
+        }
+        extracted += 1;
+    }
+    Ok(extracted)
+}
+
+/// Extracts the gzipped tar archive at `path` into `dir`, creating it if
+/// needed. Entries that would end up outside `dir` are skipped. Returns
+/// the number of files extracted.
+pub fn targz_extract(path: &Path, dir: &Path) -> io::Result<usize> {
+    let file = BufReader::new(File::open(path)?);
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    fs::create_dir_all(dir)?;
+
+    let mut extracted = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let is_file = entry.header().entry_type().is_file();
+        if entry.unpack_in(dir)? && is_file {
+            extracted += 1;
+        }
+    }
+    Ok(extracted)
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn permissions(metadata: &fs::Metadata) -> u32 {
+    if metadata.is_dir() {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_: &Path, _: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    #[test]
+    fn zips() {
+        let dir = std::env::temp_dir().join("blixt_archive_zip");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("site/css")).unwrap();
+        fs::write(dir.join("site/index.html"), "<p>hi</p>").unwrap();
+        fs::write(dir.join("site/css/main.css"), "p {}").unwrap();
+        fs::write(dir.join("notes.txt"), "notes").unwrap();
+
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let files = vec![
+            ("public".to_string(), path("site")),
+            ("docs/notes.txt".to_string(), path("notes.txt")),
+        ];
+        let zip = dir.join("out.zip");
+        assert_eq!(zip_create(&zip, &files).unwrap(), 3);
+
+        let out = dir.join("out");
+        assert_eq!(zip_extract(&zip, &out).unwrap(), 3);
+        let read = |name: &str| fs::read_to_string(out.join(name)).unwrap();
+        assert_eq!(read("public/index.html"), "<p>hi</p>");
+        assert_eq!(read("public/css/main.css"), "p {}");
+        assert_eq!(read("docs/notes.txt"), "notes");
+
+        let missing = vec![("a".to_string(), path("missing"))];
+        assert!(zip_create(&zip, &missing).is_err());
+        assert!(zip_extract(&dir.join("notes.txt"), &out).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tarballs() {
+        let dir = std::env::temp_dir().join("blixt_archive_tar");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("release.tar.gz");
+        let gz =
+            GzEncoder::new(File::create(&path).unwrap(), Compression::fast());
+        let mut tar = tar::Builder::new(gz);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o755);
+        header.set_cksum();
+        tar.append_data(&mut header, "bin/run", &b"hello"[..])
+            .unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let out = dir.join("out");
+        assert_eq!(targz_extract(&path, &out).unwrap(), 1);
+        assert_eq!(fs::read_to_string(out.join("bin/run")).unwrap(), "hello");
+        assert!(targz_extract(&dir.join("missing.tar.gz"), &out).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,7 +2,7 @@ use std::cmp;
 use std::mem;
 use std::ops::{Index, IndexMut};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Id {
     arena: u32,
     id: u32,
@@ -32,6 +32,14 @@ impl<T> Arena<T> {
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.blocks.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn alloc(&mut self, elem: T) -> Id {
         if self.blocks.is_empty() {
             self.blocks[0].push(elem);
@@ -21,9 +21,12 @@ pub enum Stmt {
     Decl(Decl),
     Expr(Expr),
     For(For),
+    While(While),
     Print(Print),
     If(If),
     Return(Return),
+    Break(Location),
+    Continue(Location),
     Param(Param),
 }
 
@@ -42,6 +45,28 @@ macro_rules! accessor {
 
 accessor!(assignment, Assignment, Stmt::Assignment(a), a);
 accessor!(expr, Expr, Stmt::Expr(a), a);
+accessor!(param, Param, Stmt::Param(a), a);
+accessor!(function, FunctionDecl, Stmt::Decl(Decl::Function(a)), a);
+
+impl Stmt {
+    /// The kind of statement, as `--stats` counts it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stmt::Assignment(_) => "Assignment",
+            Stmt::Block(_) => "Block",
+            Stmt::Decl(_) => "Decl",
+            Stmt::Expr(_) => "ExprStmt",
+            Stmt::For(_) => "For",
+            Stmt::While(_) => "While",
+            Stmt::Print(_) => "Print",
+            Stmt::If(_) => "If",
+            Stmt::Return(_) => "Return",
+            Stmt::Break(_) => "Break",
+            Stmt::Continue(_) => "Continue",
+            Stmt::Param(_) => "Param",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Return {
@@ -57,9 +82,12 @@ pub struct Expr {
 
 #[derive(Debug, Clone)]
 pub enum ExprKind {
-    Float(f32),
+    Float(f64),
     Integer(i32),
     StringLiteral(Symbol),
+    /// The text and expressions of a string literal with `${...}` in it,
+    /// in order.
+    Interpolation(ArgList),
     Ident(Symbol),
     Range(Range),
     Input(Input),
@@ -67,11 +95,42 @@ pub enum ExprKind {
     UnaryOp(UnaryOp),
     BinaryOp(BinaryOp),
     FunctionCall(FunctionCall),
+    Try(Try),
+    Array(ArgList),
+    /// Key and value pairs of a map literal.
+    Map(Vec<(AstNodeId, AstNodeId)>),
+    Index(Index),
+}
+
+impl ExprKind {
+    /// The kind of expression, as `--stats` counts it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExprKind::Float(_) => "Float",
+            ExprKind::Integer(_) => "Integer",
+            ExprKind::StringLiteral(_) => "StringLiteral",
+            ExprKind::Interpolation(_) => "Interpolation",
+            ExprKind::Ident(_) => "Ident",
+            ExprKind::Range(_) => "Range",
+            ExprKind::Input(_) => "Input",
+            ExprKind::Bool(_) => "Bool",
+            ExprKind::UnaryOp(_) => "UnaryOp",
+            ExprKind::BinaryOp(_) => "BinaryOp",
+            ExprKind::FunctionCall(_) => "FunctionCall",
+            ExprKind::Try(_) => "Try",
+            ExprKind::Array(_) => "Array",
+            ExprKind::Map(_) => "Map",
+            ExprKind::Index(_) => "Index",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Assignment {
+    /// The variable assigned to, which `location` starts with.
     pub ident: Symbol,
+    /// Indices into the array stored in `ident`, for `xs[i][j] = value`.
+    pub indices: Vec<AstNodeId>,
     pub value: AstNodeId,
     pub op: AssignmentKind,
     pub location: Location,
@@ -106,7 +165,7 @@ pub struct BinaryOp {
     pub op: BinaryOpKind,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BinaryOpKind {
     And,
     Or,
@@ -138,12 +197,16 @@ pub struct VarDecl {
     pub name: Symbol,
     pub value: AstNodeId,
     pub kind: ValueKind,
+    /// Where the name of the variable is.
+    pub location: Location,
 }
 
 #[derive(Debug, Clone)]
 pub struct StructDecl {
     pub name: Symbol,
     pub fields: Vec<AstNodeId>,
+    /// Where the `struct` keyword is.
+    pub location: Location,
 }
 
 #[derive(Debug, Clone)]
@@ -152,12 +215,16 @@ pub struct FunctionDecl {
     pub body: StmtList,
     pub params: Vec<AstNodeId>,
     pub return_type: Option<ValueKind>,
+    /// Where the name of the function is.
+    pub location: Location,
 }
 
 #[derive(Debug, Clone)]
 pub struct Param {
     pub name: Symbol,
     pub kind: ValueKind,
+    /// Where the name of the parameter is.
+    pub location: Location,
 }
 
 #[derive(Debug, Clone)]
@@ -170,8 +237,27 @@ pub struct If {
 #[derive(Debug, Clone)]
 pub struct For {
     pub ident: Symbol,
-    pub range: Range,
+    /// The names after the first in `for a, b in items`, where each item
+    /// is unpacked into one variable per element.
+    pub unpack: Vec<Symbol>,
+    pub iter: ForIter,
     pub block: StmtList,
+    pub location: Location,
+    /// Where `ident` and the names in `unpack` are, in order.
+    pub locations: Vec<Location>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ForIter {
+    Range(Range),
+    /// The elements of an array or the keys of a map.
+    Expr(AstNodeId),
+}
+
+#[derive(Debug, Clone)]
+pub struct While {
+    pub cond: AstNodeId,
+    pub body: StmtList,
 }
 
 #[derive(Debug, Clone)]
@@ -184,11 +270,29 @@ pub struct Range {
 pub struct FunctionCall {
     pub name: Symbol,
     pub args: Vec<AstNodeId>,
+    /// Where the name of the function is.
+    pub location: Location,
 }
 
 #[derive(Debug, Clone)]
 pub struct Print {
     pub args: ArgList,
+    /// `println` ends the output with a newline.
+    pub newline: bool,
+}
+
+/// Postfix `?`: unwraps an `Ok` value or returns the `Err` from the
+/// enclosing function.
+#[derive(Debug, Clone)]
+pub struct Try {
+    pub value: AstNodeId,
+}
+
+/// `value[index]`
+#[derive(Debug, Clone)]
+pub struct Index {
+    pub value: AstNodeId,
+    pub index: AstNodeId,
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,88 @@
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A record of every call to a builtin that needs a capability, written as
+/// one JSON object per line.
+pub struct AuditLog {
+    writer: Box<dyn Write>,
+}
+
+/// One call to a gated builtin.
+pub struct Entry<'a> {
+    pub function: &'a str,
+    pub capability: &'a str,
+    /// What the call works on, like the path given to `read_file`.
+    pub target: Option<&'a str>,
+    pub allowed: bool,
+}
+
+impl AuditLog {
+    pub fn new(writer: Box<dyn Write>) -> Self {
+        AuditLog { writer }
+    }
+
+    /// Writes `entry` with the current time in milliseconds since the Unix
+    /// epoch. A log that cannot be written to is not worth stopping the
+    /// script for, so errors are ignored.
+    pub fn record(&mut self, entry: &Entry) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis());
+        let _ = writeln!(self.writer, "{}", format_entry(time, entry));
+        let _ = self.writer.flush();
+    }
+}
+
+fn format_entry(time: u128, entry: &Entry) -> String {
+    format!(
+        "{{\"time\":{},\"function\":{},\"capability\":{},\"target\":{},\"allowed\":{}}}",
+        time,
+        quote(entry.function),
+        quote(entry.capability),
+        entry.target.map_or("null".to_string(), quote),
+        entry.allowed
+    )
+}
+
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                quoted.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_as_json() {
+        let entry = Entry {
+            function: "read_file",
+            capability: "fs",
+            target: Some("a \"b\"\n\u{1}"),
+            allowed: false,
+        };
+
+        assert_eq!(
+            format_entry(12, &entry),
+            "{\"time\":12,\"function\":\"read_file\",\"capability\":\"fs\",\
+             \"target\":\"a \\\"b\\\"\\n\\u0001\",\"allowed\":false}"
+        );
+    }
+}
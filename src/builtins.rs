@@ -0,0 +1,3927 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::env::{self, consts};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, ChildStdout, Command, Stdio};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "archive")]
+use crate::archive;
+use crate::ast::{Ast, BinaryOpKind, StmtList};
+use crate::audit;
+use crate::common::{Context, Diagnostic, Limits, StringInterner, Symbol};
+#[cfg(feature = "compression")]
+use crate::compress;
+use crate::diff::{self, Hunk};
+use crate::engine::NativeFunction;
+use crate::form;
+use crate::fuzzy;
+use crate::human;
+use crate::interpreter::{arithmetic, kind_of, lookup, map_key};
+use crate::interrupt::{self, Signal};
+use crate::json;
+use crate::lexer;
+#[cfg(feature = "xml")]
+use crate::markup::{self, Element, Node, Selector};
+use crate::messages::{Language, Message};
+use crate::metadata::CAPABILITIES;
+use crate::parser;
+use crate::pretty::{self, Outline};
+use crate::primitives::{
+    int_divmod, Callee, Function, Iter, Map, MapKey, Value, ValueKind,
+};
+use crate::progress::Bar;
+use crate::random;
+use crate::schema;
+use crate::scope::Scope;
+use crate::sha256::Sha256;
+use crate::statistics;
+use crate::table::{self, Style};
+use crate::text;
+use crate::typecheck;
+use crate::url;
+#[cfg(feature = "websocket")]
+use crate::websocket::Socket;
+
+/// Language features that scripts can check for with
+/// `contains(lang.features, name)`, so that they can run on interpreters
+/// that don't have them yet.
+pub const FEATURES: &[&str] = &[
+    "interpolation",
+    "negative_indices",
+    "results",
+    "maps",
+    "heaps",
+    "deques",
+    "iterators",
+    "tuples",
+    "unpacking",
+    "method_calls",
+    "modules",
+    "templates",
+    #[cfg(feature = "websocket")]
+    "websocket",
+    #[cfg(feature = "xml")]
+    "xml",
+    #[cfg(feature = "archive")]
+    "archive",
+    #[cfg(feature = "compression")]
+    "compression",
+];
+
+/// The builtins that work on several types. They are checked by the
+/// typechecker and run by the interpreter and the VM directly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    Len,
+    Push,
+    Pop,
+    Reserve,
+    Resize,
+    Insort,
+    PushFront,
+    PopFront,
+    Heap,
+    Deque,
+    ArrayWithCapacity,
+    Keys,
+    Values,
+    Contains,
+    Get,
+    BinarySearch,
+    Abs,
+    Divmod,
+    Min,
+    Max,
+    Range,
+    Iter,
+    Map,
+    Filter,
+    Take,
+    Zip,
+    Enumerate,
+    GroupBy,
+    Fold,
+    Sum,
+    Mean,
+    Median,
+    Stddev,
+    Percentile,
+    Histogram,
+    Count,
+    CountIf,
+    MinBy,
+    MaxBy,
+    Collect,
+    Sort,
+    SortByKey,
+    Reverse,
+    Partial,
+    Compose,
+    Retry,
+    WithTimeout,
+    Eval,
+    Arity,
+}
+
+/// What the builtins that run iterators or call functions need from the
+/// interpreter or the VM, to call the functions of the script.
+pub(crate) trait Caller {
+    /// Calls a function declared by the script or a native one, with
+    /// `args`. Errors are reported before returning.
+    fn call_declared(
+        &mut self,
+        func: &Function,
+        args: Vec<Value>,
+    ) -> Result<Value, ()>;
+
+    /// Calls `func` with `args`, unwrapping functions made by `partial` and
+    /// `compose` without going through the script.
+    fn call_function(
+        &mut self,
+        func: &Function,
+        mut args: Vec<Value>,
+    ) -> Result<Value, ()> {
+        match &func.callee {
+            Callee::Partial(partial) => {
+                let (func, bound) = &**partial;
+                let mut all = Vec::with_capacity(bound.len() + args.len());
+                all.extend_from_slice(bound);
+                all.append(&mut args);
+                self.call_function(func, all)
+            }
+            Callee::Compose(funcs) => {
+                let value = self.call_function(&funcs.0, args)?;
+                self.call_function(&funcs.1, vec![value])
+            }
+            _ => self.call_declared(func, args),
+        }
+    }
+
+    /// Calls `func` like `call_function`, but stops it at the first loop or
+    /// call after `deadline`. Returns None if it was stopped.
+    fn call_until(
+        &mut self,
+        func: &Function,
+        args: Vec<Value>,
+        deadline: Instant,
+    ) -> Result<Option<Value>, ()>;
+
+    /// Runs the handlers of the signals that arrived, like the script does
+    /// between statements. Fails if Ctrl-C stops the script or the
+    /// deadline of `with_timeout` has passed.
+    fn poll_interrupt(&mut self) -> Result<(), ()>;
+
+    fn interner(&mut self) -> &mut StringInterner;
+
+    /// The number of parameters of `func`, a function declared by the
+    /// script.
+    fn declared_arity(&mut self, func: &Function) -> usize;
+
+    /// The context of the running script, for `eval` to run more code in.
+    fn context(&mut self) -> &mut Context;
+
+    /// Runs `statements` with the variables in `scope`, with the input and
+    /// output of the running script. Errors are reported before returning.
+    fn evaluate(
+        &mut self,
+        ast: &Ast,
+        statements: &StmtList,
+        scope: &mut Scope,
+    ) -> Result<Option<Value>, ()>;
+
+    /// Reports an error in the builtin being run.
+    fn report(&mut self, message: Message);
+}
+
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        Some(match name {
+            "len" => Builtin::Len,
+            "push" => Builtin::Push,
+            "pop" => Builtin::Pop,
+            "reserve" => Builtin::Reserve,
+            "resize" => Builtin::Resize,
+            "insort" => Builtin::Insort,
+            "push_front" => Builtin::PushFront,
+            "pop_front" => Builtin::PopFront,
+            "heap" => Builtin::Heap,
+            "deque" => Builtin::Deque,
+            "binary_search" => Builtin::BinarySearch,
+            "array_with_capacity" => Builtin::ArrayWithCapacity,
+            "keys" => Builtin::Keys,
+            "values" => Builtin::Values,
+            "contains" => Builtin::Contains,
+            "get" => Builtin::Get,
+            "abs" => Builtin::Abs,
+            "divmod" => Builtin::Divmod,
+            "min" => Builtin::Min,
+            "max" => Builtin::Max,
+            "range" => Builtin::Range,
+            "iter" => Builtin::Iter,
+            "map" => Builtin::Map,
+            "filter" => Builtin::Filter,
+            "take" => Builtin::Take,
+            "zip" => Builtin::Zip,
+            "enumerate" => Builtin::Enumerate,
+            "group_by" => Builtin::GroupBy,
+            "fold" => Builtin::Fold,
+            "sum" => Builtin::Sum,
+            "count" => Builtin::Count,
+            "mean" => Builtin::Mean,
+            "median" => Builtin::Median,
+            "stddev" => Builtin::Stddev,
+            "percentile" => Builtin::Percentile,
+            "histogram" => Builtin::Histogram,
+            "count_if" => Builtin::CountIf,
+            "min_by" => Builtin::MinBy,
+            "max_by" => Builtin::MaxBy,
+            "collect" => Builtin::Collect,
+            "sort" => Builtin::Sort,
+            "sort_by_key" => Builtin::SortByKey,
+            "reverse" => Builtin::Reverse,
+            "partial" => Builtin::Partial,
+            "compose" => Builtin::Compose,
+            "retry" => Builtin::Retry,
+            "with_timeout" => Builtin::WithTimeout,
+            "eval" => Builtin::Eval,
+            "arity" => Builtin::Arity,
+            _ => return None,
+        })
+    }
+
+    /// The name scripts call the builtin by, which is the name of the
+    /// variant in snake case.
+    pub fn name(self) -> String {
+        let mut name = String::new();
+        for (i, c) in format!("{:?}", self).chars().enumerate() {
+            if i > 0 && c.is_ascii_uppercase() {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        }
+        name
+    }
+
+    /// The error for arguments the builtin can't take. The typechecker
+    /// only lets them through when it doesn't know their types.
+    fn invalid(self, args: &[Value]) -> Message {
+        Message::InvalidArguments {
+            name: self.name(),
+            found: args.iter().map(kind_of).collect(),
+        }
+    }
+
+    /// Whether the builtin changes the array that its first argument
+    /// refers to, instead of taking it by value.
+    pub fn changes_array(self) -> bool {
+        matches!(
+            self,
+            Builtin::Push
+                | Builtin::Pop
+                | Builtin::Reserve
+                | Builtin::Resize
+                | Builtin::Insort
+                | Builtin::PushFront
+                | Builtin::PopFront
+        )
+    }
+
+    /// Whether the builtin runs an iterator or calls a function, either of
+    /// which may call functions of the script. These are run with `run`
+    /// instead of `call`.
+    pub fn calls_functions(self) -> bool {
+        matches!(
+            self,
+            Builtin::Retry
+                | Builtin::WithTimeout
+                | Builtin::Eval
+                | Builtin::Arity
+                | Builtin::Fold
+                | Builtin::Sum
+                | Builtin::Count
+                | Builtin::Collect
+                | Builtin::Sort
+                | Builtin::SortByKey
+                | Builtin::Reverse
+                | Builtin::GroupBy
+                | Builtin::Mean
+                | Builtin::Median
+                | Builtin::Stddev
+                | Builtin::Percentile
+                | Builtin::Histogram
+                | Builtin::CountIf
+                | Builtin::MinBy
+                | Builtin::MaxBy
+        )
+    }
+
+    /// Runs a builtin that takes its arguments by value. Returns the error
+    /// to report if it fails.
+    pub fn call(
+        self,
+        args: &[Value],
+        limits: &Limits,
+        interner: &mut StringInterner,
+    ) -> Result<Value, Message> {
+        match (self, args) {
+            (Builtin::Len, [value]) => Ok(Value::Int(match value {
+                Value::Array(array) => array.len() as i32,
+                Value::Heap(heap) => heap.len() as i32,
+                Value::Deque(deque) => deque.len() as i32,
+                Value::Map(map) => map.len() as i32,
+                Value::String(s) => interner.get(*s).chars().count() as i32,
+                other => return Err(Message::CannotTakeLength(kind_of(other))),
+            })),
+            (Builtin::Keys, [Value::Map(map)]) => Ok(Value::Array(Rc::new(
+                map.iter().map(|(key, _)| key.to_value()).collect(),
+            ))),
+            (Builtin::Values, [Value::Map(map)]) => Ok(Value::Array(Rc::new(
+                map.iter().map(|(_, value)| value.clone()).collect(),
+            ))),
+            (Builtin::Contains, [Value::Map(map), key]) => {
+                Ok(Value::Bool(map.contains(map_key(key)?)))
+            }
+            (Builtin::Get, [value, index]) => {
+                Ok(match lookup(value, index, interner) {
+                    Ok(element) => Value::Ok(Box::new(element)),
+                    Err(_) => Value::Err(Box::new(index.clone())),
+                })
+            }
+            (Builtin::BinarySearch, [Value::Array(array), value]) => {
+                let i = bisect(array, value, false, interner)?;
+                let found = array.get(i).is_some_and(|item| {
+                    item.compare(value, interner) == Some(Ordering::Equal)
+                });
+                let i = Box::new(Value::Int(i as i32));
+                Ok(if found { Value::Ok(i) } else { Value::Err(i) })
+            }
+            (Builtin::Heap, []) => Ok(Value::Heap(Rc::new(vec![]))),
+            (Builtin::Deque, []) => Ok(Value::Deque(Rc::new(VecDeque::new()))),
+            (Builtin::ArrayWithCapacity, [Value::Int(n)]) => {
+                let n = length(*n, limits)?;
+                Ok(Value::Array(Rc::new(Vec::with_capacity(n))))
+            }
+            (Builtin::Abs, [Value::Int(n)]) => Ok(Value::Int(n.wrapping_abs())),
+            (Builtin::Abs, [Value::Float(n)]) => Ok(Value::Float(n.abs())),
+            (Builtin::Divmod, [Value::Int(a), Value::Int(b)]) => {
+                let (q, r) = int_divmod(*a, *b)?;
+                Ok(pair(Value::Int(q), Value::Int(r)))
+            }
+            (Builtin::Min, [a, b]) => min_max(a, b, true),
+            (Builtin::Max, [a, b]) => min_max(a, b, false),
+            (Builtin::Range, [Value::Int(n)]) => {
+                Ok(Value::Iter(Rc::new(Iter::Range(0, *n))))
+            }
+            (Builtin::Iter, [value]) => Ok(Value::Iter(iter(value)?)),
+            (Builtin::Map, [value, Value::Function(func)]) => {
+                Ok(Value::Iter(Rc::new(Iter::Map(iter(value)?, func.clone()))))
+            }
+            (Builtin::Filter, [value, Value::Function(func)]) => Ok(
+                Value::Iter(Rc::new(Iter::Filter(iter(value)?, func.clone()))),
+            ),
+            (Builtin::Take, [value, Value::Int(n)]) => Ok(Value::Iter(
+                Rc::new(Iter::Take(iter(value)?, (*n).max(0) as usize)),
+            )),
+            (Builtin::Zip, [a, b]) => {
+                Ok(Value::Iter(Rc::new(Iter::Zip(iter(a)?, iter(b)?))))
+            }
+            (Builtin::Enumerate, [value]) => {
+                Ok(Value::Iter(Rc::new(Iter::Enumerate(iter(value)?))))
+            }
+            (Builtin::Partial, [Value::Function(func), bound @ ..]) => {
+                Ok(Value::Function(Function {
+                    name: func.name,
+                    callee: Callee::Partial(Rc::new((
+                        func.clone(),
+                        bound.to_vec(),
+                    ))),
+                }))
+            }
+            (Builtin::Compose, [Value::Function(f), Value::Function(g)]) => {
+                Ok(Value::Function(Function {
+                    name: f.name,
+                    callee: Callee::Compose(Rc::new((f.clone(), g.clone()))),
+                }))
+            }
+            (builtin, args) => Err(builtin.invalid(args)),
+        }
+    }
+
+    /// Runs `push`, `pop` or another builtin that changes an array on
+    /// `place`, which has to hold one. `args` are the arguments after the
+    /// array.
+    pub fn change(
+        self,
+        place: &mut Value,
+        args: Vec<Value>,
+        limits: &Limits,
+        interner: &StringInterner,
+    ) -> Result<Value, Message> {
+        let front = matches!(self, Builtin::PushFront | Builtin::PopFront);
+        let array = match place {
+            Value::Deque(deque) => {
+                return change_deque(self, Rc::make_mut(deque), args)
+            }
+            other if front => {
+                return Err(Message::ExpectedDeque(kind_of(other)))
+            }
+            Value::Array(array) => Rc::make_mut(array),
+            Value::Heap(heap) => {
+                return change_heap(self, Rc::make_mut(heap), args, interner)
+            }
+            other => return Err(Message::ExpectedArray(kind_of(other))),
+        };
+
+        match (self, args.as_slice()) {
+            (Builtin::Push, [value]) => {
+                array.push(value.clone());
+                Ok(Value::Nil)
+            }
+            (Builtin::Pop, []) => array.pop().ok_or(Message::PopEmpty),
+            (Builtin::Reserve, [Value::Int(n)]) => {
+                array.reserve(length(*n, limits)?);
+                Ok(Value::Nil)
+            }
+            (Builtin::Resize, [Value::Int(n), fill]) => {
+                array.resize(length(*n, limits)?, fill.clone());
+                Ok(Value::Nil)
+            }
+            (Builtin::Insort, [value]) => {
+                let i = bisect(array, value, true, interner)?;
+                array.insert(i, value.clone());
+                Ok(Value::Nil)
+            }
+            (builtin, args) => Err(builtin.invalid(args)),
+        }
+    }
+
+    /// Runs a builtin that goes through the iterator, array or map in its
+    /// first argument, or calls the function in it.
+    pub(crate) fn run(
+        self,
+        args: &[Value],
+        caller: &mut dyn Caller,
+    ) -> Result<Value, ()> {
+        match (self, args) {
+            (
+                Builtin::Retry,
+                [Value::Function(func), Value::Int(attempts), Value::Int(backoff)],
+            ) => return retry(func, *attempts, *backoff, caller),
+            (Builtin::WithTimeout, [Value::Function(func), Value::Int(ms)]) => {
+                let limit = Duration::from_millis((*ms).max(0) as u64);
+                let deadline = Instant::now() + limit;
+                return Ok(
+                    match caller.call_until(func, vec![], deadline)? {
+                        Some(value) => Value::Ok(Box::new(value)),
+                        None => {
+                            let message = format!("Timed out after {} ms", ms);
+                            Value::Err(Box::new(Value::String(
+                                caller.interner().intern(&message),
+                            )))
+                        }
+                    },
+                );
+            }
+            (Builtin::Arity, [Value::Function(func)]) => {
+                return Ok(Value::Int(arity(func, caller) as i32));
+            }
+            (Builtin::Eval, [Value::String(source), env @ ..]) => {
+                let source = caller.interner().get(*source).to_string();
+                let env = match env {
+                    [] => None,
+                    [Value::Map(env)] => Some(&**env),
+                    _ => {
+                        caller.report(self.invalid(args));
+                        return Err(());
+                    }
+                };
+                return Ok(eval(&source, env, caller));
+            }
+            (
+                Builtin::Retry
+                | Builtin::WithTimeout
+                | Builtin::Arity
+                | Builtin::Eval,
+                _,
+            ) => {
+                caller.report(self.invalid(args));
+                return Err(());
+            }
+            _ => {}
+        }
+
+        let iter = iter(&args[0]).map_err(|message| caller.report(message))?;
+        let mut items = Cursor::new(&iter);
+
+        match (self, args) {
+            (Builtin::Fold, [_, init, Value::Function(func)]) => {
+                let mut result = init.clone();
+                while let Some(item) = items.next(caller)? {
+                    result = caller.call_function(func, vec![result, item])?;
+                }
+                Ok(result)
+            }
+            (Builtin::Sum, [_]) => {
+                let mut total = Value::Int(0);
+                while let Some(item) = items.next(caller)? {
+                    let result = arithmetic(
+                        BinaryOpKind::Add,
+                        total,
+                        item,
+                        caller.interner(),
+                    );
+                    total = result.map_err(|message| caller.report(message))?;
+                }
+                Ok(total)
+            }
+            (Builtin::Mean, [_]) => {
+                let mut total = 0.0;
+                let mut count = 0;
+                while let Some(item) = items.next(caller)? {
+                    total += match item {
+                        Value::Int(n) => f64::from(n),
+                        Value::Float(n) => n,
+                        other => {
+                            caller.report(Message::NotNumbers {
+                                name: "mean".to_string(),
+                                found: kind_of(&other),
+                            });
+                            return Err(());
+                        }
+                    };
+                    count += 1;
+                }
+                if count == 0 {
+                    caller.report(Message::NoItems("mean".to_string()));
+                    return Err(());
+                }
+                Ok(Value::Float(total / f64::from(count)))
+            }
+            (Builtin::Median, [_]) | (Builtin::Stddev, [_]) => {
+                let name = match self {
+                    Builtin::Median => "median",
+                    _ => "stddev",
+                };
+                let values = numbers(&mut items, name, caller)?;
+                let result = match self {
+                    Builtin::Median => statistics::median(&values),
+                    _ => statistics::stddev(&values),
+                };
+                match result {
+                    Some(result) => Ok(Value::Float(result)),
+                    None => {
+                        caller.report(Message::NoItems(name.to_string()));
+                        Err(())
+                    }
+                }
+            }
+            (Builtin::Percentile, [_, p]) => {
+                let p = match p {
+                    Value::Int(p) => f64::from(*p),
+                    Value::Float(p) => *p,
+                    _ => {
+                        caller.report(self.invalid(args));
+                        return Err(());
+                    }
+                };
+                if !(0.0..=100.0).contains(&p) {
+                    caller.report(Message::OutOfRange {
+                        name: "percentile".to_string(),
+                        found: p.to_string(),
+                        min: 0,
+                        max: Some(100),
+                    });
+                    return Err(());
+                }
+                let values = numbers(&mut items, "percentile", caller)?;
+                match statistics::percentile(&values, p) {
+                    Some(result) => Ok(Value::Float(result)),
+                    None => {
+                        caller
+                            .report(Message::NoItems("percentile".to_string()));
+                        Err(())
+                    }
+                }
+            }
+            (Builtin::Histogram, [_, Value::Int(buckets)]) => {
+                if *buckets < 1 {
+                    caller.report(Message::OutOfRange {
+                        name: "histogram".to_string(),
+                        found: buckets.to_string(),
+                        min: 1,
+                        max: None,
+                    });
+                    return Err(());
+                }
+                let values = numbers(&mut items, "histogram", caller)?;
+                let buckets = statistics::histogram(&values, *buckets as usize)
+                    .into_iter()
+                    .map(|(start, count)| {
+                        pair(Value::Float(start), Value::Int(count as i32))
+                    })
+                    .collect();
+                Ok(Value::Array(Rc::new(buckets)))
+            }
+            (Builtin::CountIf, [_, Value::Function(func)]) => {
+                let mut count = 0;
+                while let Some(item) = items.next(caller)? {
+                    let keep = caller.call_function(func, vec![item])?;
+                    if keep.is_truthy(caller.interner()) {
+                        count += 1;
+                    }
+                }
+                Ok(Value::Int(count))
+            }
+            (Builtin::MinBy, [_, Value::Function(func)])
+            | (Builtin::MaxBy, [_, Value::Function(func)]) => {
+                let wanted = match self {
+                    Builtin::MinBy => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                let mut best: Option<(Value, Value)> = None;
+                while let Some(item) = items.next(caller)? {
+                    let key = caller.call_function(func, vec![item.clone()])?;
+                    let better = match &best {
+                        Some((best_key, _)) => {
+                            let ordering =
+                                key.compare(best_key, caller.interner());
+                            match ordering {
+                                Some(ordering) => ordering == wanted,
+                                None => {
+                                    caller.report(Message::CannotOrder(
+                                        kind_of(&key),
+                                        kind_of(best_key),
+                                    ));
+                                    return Err(());
+                                }
+                            }
+                        }
+                        None => true,
+                    };
+                    if better {
+                        best = Some((key, item));
+                    }
+                }
+                match best {
+                    Some((_, item)) => Ok(item),
+                    None => {
+                        let name = match self {
+                            Builtin::MinBy => "min_by",
+                            _ => "max_by",
+                        };
+                        caller.report(Message::NoItems(name.to_string()));
+                        Err(())
+                    }
+                }
+            }
+            (Builtin::Count, [_]) => {
+                let mut count = 0;
+                while items.next(caller)?.is_some() {
+                    count += 1;
+                }
+                Ok(Value::Int(count))
+            }
+            (Builtin::Collect, [_]) => {
+                Ok(Value::Array(Rc::new(collect(&mut items, caller)?)))
+            }
+            (Builtin::Sort, [_]) => {
+                let values = collect(&mut items, caller)?;
+                let sorted = sort(values, |value| value, caller.interner());
+                let sorted =
+                    sorted.map_err(|message| caller.report(message))?;
+                Ok(Value::Array(Rc::new(sorted)))
+            }
+            (Builtin::SortByKey, [_, Value::Function(func)]) => {
+                let mut keyed = Vec::new();
+                while let Some(item) = items.next(caller)? {
+                    let key = caller.call_function(func, vec![item.clone()])?;
+                    keyed.push((key, item));
+                }
+                let sorted = sort(keyed, |(key, _)| key, caller.interner());
+                let sorted =
+                    sorted.map_err(|message| caller.report(message))?;
+                Ok(Value::Array(Rc::new(
+                    sorted.into_iter().map(|(_, item)| item).collect(),
+                )))
+            }
+            (Builtin::GroupBy, [_, Value::Function(func)]) => {
+                let mut groups = Map::new();
+                while let Some(item) = items.next(caller)? {
+                    let key = caller.call_function(func, vec![item.clone()])?;
+                    let key = map_key(&key).map_err(|m| caller.report(m))?;
+                    match groups.get_mut(key) {
+                        Some(Value::Array(group)) => {
+                            Rc::make_mut(group).push(item)
+                        }
+                        _ => groups
+                            .insert(key, Value::Array(Rc::new(vec![item]))),
+                    }
+                }
+                Ok(Value::Map(Rc::new(groups)))
+            }
+            (Builtin::Reverse, [_]) => {
+                let mut values = collect(&mut items, caller)?;
+                values.reverse();
+                Ok(Value::Array(Rc::new(values)))
+            }
+            (builtin, args) => {
+                caller.report(builtin.invalid(args));
+                Err(())
+            }
+        }
+    }
+}
+
+/// The number of arguments `func` takes.
+fn arity(func: &Function, caller: &mut dyn Caller) -> usize {
+    match &func.callee {
+        Callee::Partial(partial) => {
+            let (func, bound) = &**partial;
+            arity(func, caller) - bound.len()
+        }
+        Callee::Compose(funcs) => arity(&funcs.0, caller),
+        Callee::Native => {
+            caller.context().native_functions[&func.name].params.len()
+        }
+        Callee::Node(_) | Callee::Compiled(_) => caller.declared_arity(func),
+    }
+}
+
+/// Runs `source` on its own, with the entries of `env` as its only
+/// variables, and returns `Ok` with the value of its last statement if
+/// that is an expression. Errors are returned as an `Err` instead of being
+/// shown. The code can use what the script can, but functions and
+/// iterators can't be passed in or out, and its functions can't be left
+/// as handlers, since they belong to the code they come from.
+fn eval(source: &str, env: Option<&Map>, caller: &mut dyn Caller) -> Value {
+    let mut types = Scope::new();
+    let mut values = Scope::new();
+    for (key, value) in env.into_iter().flat_map(Map::iter) {
+        let interner = caller.interner();
+        let name = match key {
+            MapKey::String(name) => name,
+            _ => {
+                let message = "eval takes a map with string keys";
+                return error(message.to_string(), interner);
+            }
+        };
+        if !portable(value) {
+            let message =
+                format!("'{}' can't be passed to eval", interner.get(name));
+            return error(message, interner);
+        }
+        types.add_variable(name, Value::Nil, kind_of(value));
+        values.add_variable(name, value.clone(), kind_of(value));
+    }
+
+    // Functions declared by the code belong to its syntax tree, which is
+    // dropped once it has run, so handlers it registers are taken back.
+    let handlers = caller.context().handlers.borrow().clone();
+    let outer = caller.context().collected.replace(vec![]);
+    let result = run_source(source, &mut types, &mut values, caller);
+    let diagnostics = mem::replace(&mut caller.context().collected, outer);
+    let registered = {
+        let mut current = caller.context().handlers.borrow_mut();
+        let registered = *current != handlers;
+        *current = handlers;
+        registered
+    };
+
+    let language = caller.context().language;
+    let interner = caller.interner();
+    match (result, diagnostics.unwrap_or_default().first()) {
+        (_, Some(diagnostic)) => {
+            syntax_error(diagnostic, source, language, interner)
+        }
+        _ if registered => {
+            let message = "eval can't register handlers with on_exit or \
+                           on_signal";
+            error(message.to_string(), interner)
+        }
+        (Ok(value), None) => {
+            let value = value.unwrap_or(Value::Nil);
+            if portable(&value) {
+                Value::Ok(Box::new(value))
+            } else {
+                let message = "eval can't return functions or iterators";
+                error(message.to_string(), interner)
+            }
+        }
+        (Err(()), None) => error("eval failed".to_string(), interner),
+    }
+}
+
+/// Parses, checks and runs the source given to `eval`.
+fn run_source(
+    source: &str,
+    types: &mut Scope,
+    values: &mut Scope,
+    caller: &mut dyn Caller,
+) -> Result<Option<Value>, ()> {
+    let context = caller.context();
+    let file = context.interner.intern("<eval>");
+    let ast = parser::parse_quietly(source, file, context)
+        .map_err(|diagnostics| context.report_all(diagnostics))?;
+    typecheck::typecheck_statements(&ast, &ast.statements, types, context)?;
+    caller.evaluate(&ast, &ast.statements, values)
+}
+
+/// Whether `value` holds no functions or iterators, which only work in
+/// the code they come from.
+fn portable(value: &Value) -> bool {
+    match value {
+        Value::Function(_) | Value::Iter(_) => false,
+        Value::Array(items) | Value::Heap(items) => items.iter().all(portable),
+        Value::Deque(items) => items.iter().all(portable),
+        Value::Map(map) => map.iter().all(|(_, value)| portable(value)),
+        Value::Ok(value) | Value::Err(value) => portable(value),
+        _ => true,
+    }
+}
+
+/// The longest `retry` waits between two calls.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How long `retry` sleeps at a time, between checks for signals and the
+/// deadline of `with_timeout`.
+const SLEEP_STEP: Duration = Duration::from_millis(10);
+
+/// Calls `func` until it returns something other than an `Err`, at most
+/// `attempts` times. Waits `backoff` milliseconds before the second call,
+/// twice as long before the third, and so on, up to `MAX_BACKOFF`.
+fn retry(
+    func: &Function,
+    attempts: i32,
+    backoff: i32,
+    caller: &mut dyn Caller,
+) -> Result<Value, ()> {
+    let mut wait = Duration::from_millis(backoff.max(0) as u64);
+    let mut result = caller.call_function(func, vec![])?;
+    for _ in 1..attempts {
+        if !matches!(result, Value::Err(_)) {
+            break;
+        }
+        sleep(wait.min(MAX_BACKOFF), caller)?;
+        wait = wait.saturating_mul(2);
+        result = caller.call_function(func, vec![])?;
+    }
+    Ok(result)
+}
+
+/// Sleeps for `wait` in steps of `SLEEP_STEP`, so that Ctrl-C or the
+/// deadline of `with_timeout` cuts it short.
+fn sleep(wait: Duration, caller: &mut dyn Caller) -> Result<(), ()> {
+    let end = Instant::now() + wait;
+    loop {
+        caller.poll_interrupt()?;
+        let now = Instant::now();
+        if now >= end {
+            return Ok(());
+        }
+        thread::sleep((end - now).min(SLEEP_STEP));
+    }
+}
+
+/// The values of an iterator, array or map, in the order a for loop goes
+/// over them.
+pub(crate) fn items(
+    value: &Value,
+    caller: &mut dyn Caller,
+) -> Result<Vec<Value>, ()> {
+    let iter = iter(value).map_err(|message| caller.report(message))?;
+    collect(&mut Cursor::new(&iter), caller)
+}
+
+/// An iterator over the elements of an array or deque or the keys of a map, or
+/// `value` itself if it is an iterator.
+fn iter(value: &Value) -> Result<Rc<Iter>, Message> {
+    Ok(match value {
+        Value::Iter(iter) => Rc::clone(iter),
+        Value::Array(array) => Rc::new(Iter::Values(Rc::clone(array))),
+        Value::Deque(deque) => {
+            Rc::new(Iter::Values(Rc::new(deque.iter().cloned().collect())))
+        }
+        Value::Map(map) => Rc::new(Iter::Values(Rc::new(
+            map.iter().map(|(key, _)| key.to_value()).collect(),
+        ))),
+        other => return Err(Message::CannotIterate(kind_of(other))),
+    })
+}
+
+fn collect(
+    items: &mut Cursor,
+    caller: &mut dyn Caller,
+) -> Result<Vec<Value>, ()> {
+    let mut values = Vec::new();
+    while let Some(item) = items.next(caller)? {
+        values.push(item);
+    }
+    Ok(values)
+}
+
+/// Where `value` would go in the sorted `array`: before any equal items, or
+/// after them with `after_equal`.
+fn bisect(
+    array: &[Value],
+    value: &Value,
+    after_equal: bool,
+    interner: &StringInterner,
+) -> Result<usize, Message> {
+    let mut error = None;
+    let i = array.partition_point(|item| match item.compare(value, interner) {
+        Some(Ordering::Less) => true,
+        Some(Ordering::Equal) => after_equal,
+        Some(Ordering::Greater) => false,
+        None => {
+            error.get_or_insert_with(|| {
+                Message::CannotOrder(kind_of(item), kind_of(value))
+            });
+            false
+        }
+    });
+
+    match error {
+        Some(message) => Err(message),
+        None => Ok(i),
+    }
+}
+
+/// Sorts `items` by the value `key` gives for each, keeping items with
+/// equal keys in the order they were in.
+fn sort<T>(
+    mut items: Vec<T>,
+    key: impl Fn(&T) -> &Value,
+    interner: &StringInterner,
+) -> Result<Vec<T>, Message> {
+    let mut error = None;
+    items.sort_by(|a, b| {
+        let (a, b) = (key(a), key(b));
+        a.compare(b, interner).unwrap_or_else(|| {
+            error.get_or_insert_with(|| {
+                Message::CannotOrder(kind_of(a), kind_of(b))
+            });
+            Ordering::Equal
+        })
+    });
+
+    match error {
+        Some(message) => Err(message),
+        None => Ok(items),
+    }
+}
+
+/// How far an iterator has been run.
+enum Cursor {
+    Range(i32, i32),
+    Values(Rc<Vec<Value>>, usize),
+    Map(Box<Cursor>, Function),
+    Filter(Box<Cursor>, Function),
+    Take(Box<Cursor>, usize),
+    Zip(Box<Cursor>, Box<Cursor>),
+    /// The position of the next value.
+    Enumerate(Box<Cursor>, i32),
+}
+
+impl Cursor {
+    fn new(iter: &Iter) -> Cursor {
+        match iter {
+            Iter::Range(start, end) => Cursor::Range(*start, *end),
+            Iter::Values(values) => Cursor::Values(Rc::clone(values), 0),
+            Iter::Map(iter, func) => {
+                Cursor::Map(Box::new(Cursor::new(iter)), func.clone())
+            }
+            Iter::Filter(iter, func) => {
+                Cursor::Filter(Box::new(Cursor::new(iter)), func.clone())
+            }
+            Iter::Take(iter, n) => {
+                Cursor::Take(Box::new(Cursor::new(iter)), *n)
+            }
+            Iter::Zip(a, b) => {
+                Cursor::Zip(Box::new(Cursor::new(a)), Box::new(Cursor::new(b)))
+            }
+            Iter::Enumerate(iter) => {
+                Cursor::Enumerate(Box::new(Cursor::new(iter)), 0)
+            }
+        }
+    }
+
+    /// The next value, calling the functions of the iterator through
+    /// `caller` on the way.
+    fn next(&mut self, caller: &mut dyn Caller) -> Result<Option<Value>, ()> {
+        match self {
+            Cursor::Range(next, end) => {
+                if next < end {
+                    *next += 1;
+                    Ok(Some(Value::Int(*next - 1)))
+                } else {
+                    Ok(None)
+                }
+            }
+            Cursor::Values(values, next) => {
+                let value = values.get(*next).cloned();
+                *next += 1;
+                Ok(value)
+            }
+            Cursor::Map(items, func) => match items.next(caller)? {
+                Some(item) => caller.call_function(func, vec![item]).map(Some),
+                None => Ok(None),
+            },
+            Cursor::Filter(items, func) => {
+                while let Some(item) = items.next(caller)? {
+                    let keep =
+                        caller.call_function(func, vec![item.clone()])?;
+                    if keep.is_truthy(caller.interner()) {
+                        return Ok(Some(item));
+                    }
+                }
+                Ok(None)
+            }
+            Cursor::Take(items, left) => {
+                if *left == 0 {
+                    return Ok(None);
+                }
+                *left -= 1;
+                items.next(caller)
+            }
+            Cursor::Zip(a, b) => match (a.next(caller)?, b.next(caller)?) {
+                (Some(a), Some(b)) => Ok(Some(pair(a, b))),
+                _ => Ok(None),
+            },
+            Cursor::Enumerate(items, position) => {
+                let item = items.next(caller)?;
+                *position += 1;
+                Ok(item.map(|item| pair(Value::Int(*position - 1), item)))
+            }
+        }
+    }
+}
+
+/// Collects the items of a builtin that works on numbers, as floats.
+/// Reports an error naming the builtin if an item is not a number.
+fn numbers(
+    items: &mut Cursor,
+    name: &str,
+    caller: &mut dyn Caller,
+) -> Result<Vec<f64>, ()> {
+    let mut numbers = Vec::new();
+    while let Some(item) = items.next(caller)? {
+        numbers.push(match item {
+            Value::Int(n) => f64::from(n),
+            Value::Float(n) => n,
+            other => {
+                caller.report(Message::NotNumbers {
+                    name: name.to_string(),
+                    found: kind_of(&other),
+                });
+                return Err(());
+            }
+        });
+    }
+    Ok(numbers)
+}
+
+/// The value of a pair that `zip` and `enumerate` give, an array of two.
+fn pair(a: Value, b: Value) -> Value {
+    Value::Array(Rc::new(vec![a, b]))
+}
+
+fn min_max(a: &Value, b: &Value, min: bool) -> Result<Value, Message> {
+    let (a, b) = match (a, b) {
+        (Value::Int(a), Value::Int(b)) => {
+            return Ok(Value::Int(if min { *a.min(b) } else { *a.max(b) }));
+        }
+        (Value::Float(a), Value::Float(b)) => (*a, *b),
+        (Value::Int(a), Value::Float(b)) => (f64::from(*a), *b),
+        (Value::Float(a), Value::Int(b)) => (*a, f64::from(*b)),
+        (a, b) => {
+            let builtin = if min { Builtin::Min } else { Builtin::Max };
+            return Err(builtin.invalid(&[a.clone(), b.clone()]));
+        }
+    };
+
+    Ok(Value::Float(if min { a.min(b) } else { a.max(b) }))
+}
+
+const FS: Option<&str> = Some("fs");
+const PROC: Option<&str> = Some("proc");
+const RANDOM: Option<&str> = Some("random");
+#[cfg(feature = "websocket")]
+const NET: Option<&str> = Some("net");
+
+/// Checks that `args` are of the types the native function `name` takes.
+/// The typechecker has checked them already, except where it didn't know
+/// the type of an argument, as with the value unwrapped by `?`. The helpers
+/// like `string` and `int` that natives read their arguments with count on
+/// this.
+pub(crate) fn check_arguments(
+    name: Symbol,
+    func: &NativeFunction,
+    args: &[Value],
+    interner: &StringInterner,
+) -> Result<(), Message> {
+    let mismatch = func
+        .params
+        .iter()
+        .zip(args)
+        .position(|(param, arg)| !conforms(arg, param));
+
+    match mismatch {
+        Some(i) => Err(Message::ArgumentMismatch {
+            position: i + 1,
+            name: interner.get(name).to_string(),
+            expected: func.params[i].clone(),
+            found: kind_of(&args[i]),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Whether `value` is of the type `kind`, where `Nil` is any type, as in
+/// the typechecker.
+fn conforms(value: &Value, kind: &ValueKind) -> bool {
+    match (kind, value) {
+        (ValueKind::Nil, _)
+        | (ValueKind::Bool, Value::Bool(_))
+        | (ValueKind::Integer, Value::Int(_))
+        | (ValueKind::Float, Value::Float(_))
+        | (ValueKind::String, Value::String(_))
+        | (ValueKind::Struct(_), Value::Struct(_))
+        | (ValueKind::Function(..), Value::Function(_))
+        | (ValueKind::Iter(_), Value::Iter(_))
+        | (ValueKind::Result, Value::Ok(_))
+        | (ValueKind::Result, Value::Err(_)) => true,
+        (ValueKind::Array(kind), Value::Array(items))
+        | (ValueKind::Heap(kind), Value::Heap(items)) => {
+            items.iter().all(|item| conforms(item, kind))
+        }
+        (ValueKind::Deque(kind), Value::Deque(items)) => {
+            items.iter().all(|item| conforms(item, kind))
+        }
+        (ValueKind::Tuple(kinds), Value::Array(items)) => {
+            kinds.len() == items.len()
+                && items
+                    .iter()
+                    .zip(kinds)
+                    .all(|(item, kind)| conforms(item, kind))
+        }
+        (ValueKind::Map(keys, values), Value::Map(map)) => {
+            map.iter().all(|(key, value)| {
+                conforms(&key.to_value(), keys) && conforms(value, values)
+            })
+        }
+        _ => false,
+    }
+}
+
+/// Checks that the script may call `func`, the native function `name`.
+/// If it needs a capability that has not been granted, the user is asked
+/// for it on `input` when prompting is enabled. Calls that need a
+/// capability are written to the audit log, if there is one.
+pub(crate) fn check_capability(
+    name: Symbol,
+    func: &NativeFunction,
+    args: &[Value],
+    context: &mut Context,
+    input: &mut dyn BufRead,
+) -> Result<(), Message> {
+    let capability = match func.capability {
+        Some(capability) => capability,
+        None => return Ok(()),
+    };
+    let name = context.interner.get(name).to_string();
+    let target = match args.first() {
+        Some(Value::String(s)) => Some(context.interner.get(*s).to_string()),
+        _ => None,
+    };
+
+    let result = if context.grants.allows(capability) {
+        Ok(())
+    } else if context.prompt {
+        if ask_to_grant(capability, target.clone(), context, input) {
+            Ok(())
+        } else {
+            Err(Message::PermissionDenied(name.clone()))
+        }
+    } else {
+        Err(Message::CapabilityRequired {
+            name: name.clone(),
+            capability: capability.to_string(),
+        })
+    };
+
+    if let Some(audit) = &mut context.audit {
+        audit.record(&audit::Entry {
+            function: &name,
+            capability,
+            target: target.as_deref(),
+            allowed: result.is_ok(),
+        });
+    }
+
+    result
+}
+
+/// Asks whether to allow `capability`, and grants it for the rest of the
+/// run on a yes.
+pub fn ask_to_grant(
+    capability: &str,
+    target: Option<String>,
+    context: &mut Context,
+    input: &mut dyn BufRead,
+) -> bool {
+    let question = Message::CapabilityPrompt {
+        capability: capability.to_string(),
+        target,
+    };
+    eprint!("{} ", question.text(context.language));
+
+    // Standard input that is closed or can't be read denies the request.
+    let mut answer = String::new();
+    if input.read_line(&mut answer).is_err() {
+        answer.clear();
+    }
+
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" | "j" | "ja" => {
+            context.grants.grant(capability);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Adds the functions for strings and files that every script can call.
+/// Functions declared by a script take precedence over them.
+///
+/// `print`, `println` and `input` are parsed as part of the language, and
+/// the builtins that work on several types, like `len` and `min`, are
+/// handled by the typechecker and interpreter directly.
+pub fn register(context: &mut Context) {
+    use ValueKind::*;
+
+    let strings = Array(Box::new(String));
+
+    define(
+        context,
+        "split",
+        &[String, String],
+        Some(strings.clone()),
+        None,
+        split,
+    );
+    define(context, "trim", &[String], Some(String), None, trim);
+    define(context, "to_upper", &[String], Some(String), None, to_upper);
+    define(context, "to_lower", &[String], Some(String), None, to_lower);
+    define(
+        context,
+        "strip_ansi",
+        &[String],
+        Some(String),
+        None,
+        strip_ansi,
+    );
+    define(
+        context,
+        "display_width",
+        &[String],
+        Some(Integer),
+        None,
+        display_width,
+    );
+    define(
+        context,
+        "wrap",
+        &[String, Integer],
+        Some(String),
+        None,
+        wrap,
+    );
+    define(
+        context,
+        "edit_distance",
+        &[String, String],
+        Some(Integer),
+        None,
+        edit_distance,
+    );
+    define(
+        context,
+        "fuzzy_match",
+        &[String, Array(Box::new(String))],
+        Some(Array(Box::new(String))),
+        None,
+        fuzzy_match,
+    );
+    define(
+        context,
+        "format_bytes",
+        &[Integer],
+        Some(String),
+        None,
+        format_bytes,
+    );
+    define(
+        context,
+        "format_number",
+        &[Integer, String],
+        Some(String),
+        None,
+        format_number,
+    );
+    define(
+        context,
+        "format_duration",
+        &[Integer],
+        Some(String),
+        None,
+        format_duration,
+    );
+    define(
+        context,
+        "matches_glob",
+        &[String, String],
+        Some(Bool),
+        None,
+        matches_glob,
+    );
+    define(
+        context,
+        "url_parse",
+        &[String],
+        Some(Result),
+        None,
+        url_parse,
+    );
+    define(
+        context,
+        "url_encode",
+        &[String],
+        Some(String),
+        None,
+        url_encode,
+    );
+    define(
+        context,
+        "url_decode",
+        &[String],
+        Some(Result),
+        None,
+        url_decode,
+    );
+    let params = || Map(Box::new(String), Box::new(String));
+    define(
+        context,
+        "url_query",
+        &[params()],
+        Some(String),
+        None,
+        url_query,
+    );
+    define(
+        context,
+        "form_encode",
+        &[params()],
+        Some(String),
+        None,
+        form_encode,
+    );
+    define(
+        context,
+        "multipart_write",
+        &[String, params(), params()],
+        Some(Result),
+        FS,
+        multipart_write,
+    );
+
+    // Hunks are `[old_start, old_len, new_start, new_len, lines]`.
+    let hunk = Tuple(vec![Integer, Integer, Integer, Integer, strings.clone()]);
+    let hunks = Array(Box::new(hunk));
+    define(
+        context,
+        "diff_lines",
+        &[String, String],
+        Some(hunks.clone()),
+        None,
+        diff_lines,
+    );
+    define(
+        context,
+        "apply_patch",
+        &[String, hunks.clone()],
+        Some(Result),
+        None,
+        apply_patch,
+    );
+    define(
+        context,
+        "render_diff",
+        &[hunks, Bool],
+        Some(String),
+        None,
+        render_diff,
+    );
+    let rows = Array(Box::new(Array(Box::new(Nil))));
+    define(
+        context,
+        "format_table",
+        &[rows.clone(), strings.clone(), String],
+        Some(Result),
+        None,
+        format_table,
+    );
+    define(
+        context,
+        "print_table",
+        &[rows, strings.clone()],
+        None,
+        None,
+        print_table,
+    );
+    define(
+        context,
+        "validate",
+        &[Nil, Map(Box::new(String), Box::new(String))],
+        Some(Result),
+        None,
+        validate,
+    );
+    define(context, "read_file", &[String], Some(Result), FS, read_file);
+    define(
+        context,
+        "write_file",
+        &[String, String],
+        Some(Result),
+        FS,
+        write_file,
+    );
+    let bytes = || Array(Box::new(Integer));
+    define(
+        context,
+        "read_bytes",
+        &[String],
+        Some(Result),
+        FS,
+        read_bytes,
+    );
+    define(
+        context,
+        "write_bytes",
+        &[String, bytes()],
+        Some(Result),
+        FS,
+        write_bytes,
+    );
+    define(
+        context,
+        "to_bytes",
+        &[String],
+        Some(bytes()),
+        None,
+        to_bytes,
+    );
+    define(
+        context,
+        "from_bytes",
+        &[bytes()],
+        Some(Result),
+        None,
+        from_bytes,
+    );
+    define(
+        context,
+        "file_sha256",
+        &[String],
+        Some(Result),
+        FS,
+        file_sha256,
+    );
+    define(
+        context,
+        "dir_digest",
+        &[String],
+        Some(Result),
+        FS,
+        dir_digest,
+    );
+    define(context, "uuid4", &[], Some(Result), RANDOM, uuid4);
+    define(
+        context,
+        "random_bytes",
+        &[Integer],
+        Some(Result),
+        RANDOM,
+        random_bytes,
+    );
+    define(
+        context,
+        "random_token",
+        &[Integer],
+        Some(Result),
+        RANDOM,
+        random_token,
+    );
+
+    register_temp_paths(context);
+    register_progress(context);
+    register_processes(context);
+    #[cfg(feature = "websocket")]
+    register_sockets(context);
+    #[cfg(feature = "xml")]
+    register_markup(context);
+    #[cfg(feature = "archive")]
+    register_archives(context);
+    #[cfg(feature = "compression")]
+    register_compression(context);
+    register_handlers(context);
+    set_args(context, &[]);
+    set_cache_dir(context, cache_dir(None));
+    register_lang(context);
+}
+
+/// Adds the `lang` namespace, with what the interpreter is, what it can do
+/// and where it runs.
+fn register_lang(context: &mut Context) {
+    let version = |part: &str| Value::Int(part.parse().unwrap_or(0));
+    set_lang(context, "major", version(env!("CARGO_PKG_VERSION_MAJOR")));
+    set_lang(context, "minor", version(env!("CARGO_PKG_VERSION_MINOR")));
+    set_lang(context, "patch", version(env!("CARGO_PKG_VERSION_PATCH")));
+
+    let strings = [
+        ("version", env!("CARGO_PKG_VERSION")),
+        ("os", consts::OS),
+        ("arch", consts::ARCH),
+        ("family", consts::FAMILY),
+    ];
+    for (name, value) in &strings {
+        let value = Value::String(context.interner.intern(value));
+        set_lang(context, name, value);
+    }
+
+    let sets = [("features", FEATURES), ("capabilities", CAPABILITIES)];
+    for (name, members) in &sets {
+        let mut map = Map::new();
+        for member in members.iter() {
+            let key = MapKey::String(context.interner.intern(member));
+            map.insert(key, Value::Bool(true));
+        }
+        set_lang(context, name, Value::Map(Rc::new(map)));
+    }
+
+    register_syntax(context);
+    let lang = context.interner.intern("lang");
+    context.namespaces.insert(lang);
+}
+
+/// Adds `lang.tokenize` and `lang.parse`, which give scripts the tokens
+/// and syntax tree of some source as maps, to write tools for blixt in
+/// blixt. Their errors are in the language of `context`.
+fn register_syntax(context: &mut Context) {
+    use ValueKind::*;
+
+    let language = context.language;
+    define(
+        context,
+        "lang.tokenize",
+        &[String],
+        Some(Result),
+        None,
+        move |args, interner| {
+            let source = string(&args[0], interner).to_string();
+            let mut context = Context::new();
+            let file = context.interner.intern("source");
+            let lexed = lexer::lex_all(source.as_bytes(), file, &mut context);
+            if let Some(diagnostic) = lexed.diagnostics.first() {
+                return syntax_error(diagnostic, &source, language, interner);
+            }
+
+            let tokens = lexed
+                .tokens
+                .iter()
+                .map(|token| {
+                    let span = token.location.span;
+                    let start = span.start as usize;
+                    let text = &source[start..start + span.len as usize];
+                    let kind = format!("{:?}", token.kind);
+                    let kind = kind.split('(').next().unwrap_or_default();
+                    let column = token.location.column(source.as_bytes());
+                    let parts = [
+                        ("kind", Value::String(interner.intern(kind))),
+                        ("text", Value::String(interner.intern(text))),
+                        ("line", Value::Int(token.location.line as i32)),
+                        ("column", Value::Int(column as i32)),
+                    ];
+                    string_map(&parts, interner)
+                })
+                .collect();
+            Value::Ok(Box::new(Value::Array(Rc::new(tokens))))
+        },
+    );
+    define(
+        context,
+        "lang.parse",
+        &[String],
+        Some(Result),
+        None,
+        move |args, interner| {
+            let source = string(&args[0], interner).to_string();
+            let mut context = Context::new();
+            let file = context.interner.intern("source");
+            match parser::parse_quietly(&source, file, &mut context) {
+                Ok(ast) => {
+                    let nodes = pretty::outline(&ast, &context.interner)
+                        .iter()
+                        .map(|node| outline_value(node, interner))
+                        .collect();
+                    Value::Ok(Box::new(Value::Array(Rc::new(nodes))))
+                }
+                Err(diagnostics) => {
+                    syntax_error(&diagnostics[0], &source, language, interner)
+                }
+            }
+        },
+    );
+}
+
+/// A map with the `kind`, `text` and `children` of a node of a syntax
+/// tree.
+fn outline_value(node: &Outline, interner: &mut StringInterner) -> Value {
+    let children = node
+        .children
+        .iter()
+        .map(|child| outline_value(child, interner))
+        .collect();
+    let parts = [
+        ("kind", Value::String(interner.intern(node.kind))),
+        ("text", Value::String(interner.intern(&node.text))),
+        ("children", Value::Array(Rc::new(children))),
+    ];
+    string_map(&parts, interner)
+}
+
+fn string_map(parts: &[(&str, Value)], interner: &mut StringInterner) -> Value {
+    let mut map = Map::new();
+    for (name, part) in parts.iter().cloned() {
+        map.insert(MapKey::String(interner.intern(name)), part);
+    }
+    Value::Map(Rc::new(map))
+}
+
+/// An `Err` with the first error in some source that was lexed or parsed
+/// for a script, and where it is.
+fn syntax_error(
+    diagnostic: &Diagnostic,
+    source: &str,
+    language: Language,
+    interner: &mut StringInterner,
+) -> Value {
+    let location = diagnostic.location;
+    let message = format!(
+        "{}:{}: {}",
+        location.line,
+        location.column(source.as_bytes()),
+        diagnostic.message.text(language)
+    );
+    error(message, interner)
+}
+
+fn set_lang(context: &mut Context, name: &str, value: Value) {
+    let name = context.interner.intern(&format!("lang.{}", name));
+    context.constants.insert(name, value);
+}
+
+/// Makes `args()` return `args`, the arguments given to the script.
+pub fn set_args(context: &mut Context, args: &[String]) {
+    let args: Vec<_> = args
+        .iter()
+        .map(|arg| Value::String(context.interner.intern(arg)))
+        .collect();
+    let args = Rc::new(args);
+
+    let strings = ValueKind::Array(Box::new(ValueKind::String));
+    define(context, "args", &[], Some(strings), None, move |_, _| {
+        Value::Array(Rc::clone(&args))
+    });
+}
+
+/// Makes `cache_get` and `cache_set` keep their values in `dir`.
+pub fn set_cache_dir(context: &mut Context, dir: PathBuf) {
+    use ValueKind::*;
+
+    let dir = Rc::new(dir);
+    let read = Rc::clone(&dir);
+    define(
+        context,
+        "cache_get",
+        &[String],
+        Some(Result),
+        FS,
+        move |args, interner| cache_get(&read, args, interner),
+    );
+    define(
+        context,
+        "cache_set",
+        &[String, Nil, Integer],
+        Some(Result),
+        FS,
+        move |args, interner| cache_set(&dir, args, interner),
+    );
+}
+
+/// The directory `cache_get` and `cache_set` use for the script at
+/// `script`, under the user's cache directory. Each script gets its own,
+/// and code that is not in a file shares one.
+pub fn cache_dir(script: Option<&Path>) -> PathBuf {
+    let root = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| Path::new(&home).join(".cache"))
+        })
+        .unwrap_or_else(env::temp_dir)
+        .join("blixt");
+
+    let script = match script {
+        Some(script) => script,
+        None => return root.join("default"),
+    };
+
+    // Scripts with the same name in different places are told apart by
+    // their full paths.
+    let path = fs::canonicalize(script).unwrap_or_else(|_| script.to_owned());
+    let mut hash = Sha256::new();
+    hash.update(path.to_string_lossy().as_bytes());
+    let stem = script
+        .file_stem()
+        .map_or("script".into(), |stem| stem.to_string_lossy());
+    root.join(format!("{}-{}", stem, &hash.finish_hex()[..16]))
+}
+
+/// The file the value cached under `key` is kept in.
+fn cache_file(dir: &Path, key: &str) -> PathBuf {
+    let mut hash = Sha256::new();
+    hash.update(key.as_bytes());
+    dir.join(format!("{}.json", &hash.finish_hex()[..32]))
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |time| time.as_secs_f64())
+}
+
+/// Returns `Ok` with the value cached under the key, or `Err` if there is
+/// none or it has expired.
+fn cache_get(
+    dir: &Path,
+    args: &[Value],
+    interner: &mut StringInterner,
+) -> Value {
+    let key = string(&args[0], interner).to_string();
+    let path = cache_file(dir, &key);
+    let missing = |interner: &mut StringInterner| {
+        error(format!("Nothing cached for {}", key), interner)
+    };
+
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return missing(interner),
+        Err(e) => return file_error(&path.to_string_lossy(), &e, interner),
+    };
+    let entry = match json::from_json(&text, interner) {
+        Ok(Value::Map(entry)) => entry,
+        _ => return missing(interner),
+    };
+
+    let expires = |interner: &mut StringInterner| match entry
+        .get(MapKey::String(interner.intern("expires")))
+    {
+        Some(Value::Int(time)) => Some(f64::from(*time)),
+        Some(Value::Float(time)) => Some(*time),
+        _ => None,
+    };
+    let expired = match expires(interner) {
+        Some(time) => time <= now(),
+        None => false,
+    };
+    if expired {
+        let _ = fs::remove_file(&path);
+        return missing(interner);
+    }
+
+    match entry.get(MapKey::String(interner.intern("value"))) {
+        Some(value) => Value::Ok(Box::new(value.clone())),
+        None => missing(interner),
+    }
+}
+
+/// Caches the second argument under the key for as many seconds as the
+/// third, or until it is replaced if that is not positive.
+fn cache_set(
+    dir: &Path,
+    args: &[Value],
+    interner: &mut StringInterner,
+) -> Value {
+    let key = string(&args[0], interner).to_string();
+    let value = match json::to_json(&args[1], interner) {
+        Some(value) => value,
+        None => {
+            let kind = kind_of(&args[1]);
+            return error(format!("Cannot cache a {:?}", kind), interner);
+        }
+    };
+    let expires = match int(&args[2]) {
+        ttl if ttl > 0 => (now() + f64::from(ttl)).to_string(),
+        _ => "null".to_string(),
+    };
+
+    let path = cache_file(dir, &key);
+    let entry = format!("{{\"expires\":{},\"value\":{}}}", expires, value);
+    match fs::create_dir_all(dir).and_then(|_| fs::write(&path, entry)) {
+        Ok(()) => Value::Ok(Box::new(Value::Nil)),
+        Err(e) => file_error(&path.to_string_lossy(), &e, interner),
+    }
+}
+
+fn define(
+    context: &mut Context,
+    name: &str,
+    params: &[ValueKind],
+    return_type: Option<ValueKind>,
+    capability: Option<&'static str>,
+    func: impl Fn(&[Value], &mut StringInterner) -> Value + 'static,
+) {
+    let name = context.interner.intern(name);
+    let func = NativeFunction {
+        params: params.to_vec(),
+        return_type,
+        func: Box::new(func),
+        capability,
+    };
+
+    context.native_functions.insert(name, Rc::new(func));
+}
+
+fn string<'a>(value: &Value, interner: &'a StringInterner) -> &'a str {
+    match value {
+        Value::String(s) => interner.get(*s),
+        other => unreachable!("Expected a string, found {:?}", other),
+    }
+}
+
+/// Splits the first argument on every occurrence of the second, or into
+/// characters if the separator is empty.
+fn split(args: &[Value], interner: &mut StringInterner) -> Value {
+    let s = string(&args[0], interner).to_string();
+    let separator = string(&args[1], interner).to_string();
+
+    let parts: Vec<_> = if separator.is_empty() {
+        s.chars().map(|c| c.to_string()).collect()
+    } else {
+        s.split(separator.as_str()).map(str::to_string).collect()
+    };
+
+    Value::Array(Rc::new(
+        parts
+            .iter()
+            .map(|part| Value::String(interner.intern(part)))
+            .collect(),
+    ))
+}
+
+fn trim(args: &[Value], interner: &mut StringInterner) -> Value {
+    let s = string(&args[0], interner).trim().to_string();
+    Value::String(interner.intern(&s))
+}
+
+fn to_upper(args: &[Value], interner: &mut StringInterner) -> Value {
+    let s = string(&args[0], interner).to_uppercase();
+    Value::String(interner.intern(&s))
+}
+
+fn to_lower(args: &[Value], interner: &mut StringInterner) -> Value {
+    let s = string(&args[0], interner).to_lowercase();
+    Value::String(interner.intern(&s))
+}
+
+fn strip_ansi(args: &[Value], interner: &mut StringInterner) -> Value {
+    let s = text::strip_ansi(string(&args[0], interner));
+    Value::String(interner.intern(&s))
+}
+
+/// Returns the number of terminal columns the string takes up.
+fn display_width(args: &[Value], interner: &mut StringInterner) -> Value {
+    Value::Int(text::display_width(string(&args[0], interner)) as i32)
+}
+
+/// Returns the string with its lines broken between words so that none is
+/// wider than the second argument, or 1 if that is less.
+fn wrap(args: &[Value], interner: &mut StringInterner) -> Value {
+    let width = usize::try_from(int(&args[1])).unwrap_or(0);
+    let s = text::wrap(string(&args[0], interner), width);
+    Value::String(interner.intern(&s))
+}
+
+/// Returns the number of characters that have to be inserted, removed or
+/// replaced to turn the first string into the second.
+fn edit_distance(args: &[Value], interner: &mut StringInterner) -> Value {
+    let a = string(&args[0], interner);
+    let b = string(&args[1], interner);
+    Value::Int(fuzzy::edit_distance(a, b) as i32)
+}
+
+/// Returns the strings in the second argument that have the characters of
+/// the first in order, best matches first.
+fn fuzzy_match(args: &[Value], interner: &mut StringInterner) -> Value {
+    let needle = string(&args[0], interner);
+    let haystack: Vec<_> = strings_of(&args[1], interner).collect();
+    let found: Vec<_> = fuzzy::fuzzy_match(needle, &haystack)
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let found = found
+        .iter()
+        .map(|found| Value::String(interner.intern(found)))
+        .collect();
+    Value::Array(Rc::new(found))
+}
+
+/// Returns the number of bytes in the largest binary unit it has at least
+/// one of, like `1.5 MiB`.
+fn format_bytes(args: &[Value], interner: &mut StringInterner) -> Value {
+    let s = human::bytes(i64::from(int(&args[0])));
+    Value::String(interner.intern(&s))
+}
+
+/// Returns the int with the second argument between groups of three
+/// digits.
+fn format_number(args: &[Value], interner: &mut StringInterner) -> Value {
+    let separator = string(&args[1], interner);
+    let s = human::number(i64::from(int(&args[0])), separator);
+    Value::String(interner.intern(&s))
+}
+
+/// Returns the number of milliseconds as a duration like `1.5s` or
+/// `1h2m3s`.
+fn format_duration(args: &[Value], interner: &mut StringInterner) -> Value {
+    let s = human::duration(i64::from(int(&args[0])));
+    Value::String(interner.intern(&s))
+}
+
+fn matches_glob(args: &[Value], interner: &mut StringInterner) -> Value {
+    let s: Vec<_> = string(&args[0], interner).chars().collect();
+    let pattern: Vec<_> = string(&args[1], interner).chars().collect();
+    Value::Bool(glob(&s, &pattern))
+}
+
+/// Whether all of `s` matches `pattern`, where `*` matches any run of
+/// characters, `?` any one character and `\` makes the next one literal.
+fn glob(s: &[char], pattern: &[char]) -> bool {
+    let (mut si, mut pi) = (0, 0);
+    // Where to go on from when the last `*` has to match one more char.
+    let mut retry = None;
+
+    while si < s.len() {
+        match pattern.get(pi) {
+            Some('*') => {
+                pi += 1;
+                retry = Some((si, pi));
+                continue;
+            }
+            Some('?') => {
+                si += 1;
+                pi += 1;
+                continue;
+            }
+            Some('\\') if pattern.get(pi + 1) == Some(&s[si]) => {
+                si += 1;
+                pi += 2;
+                continue;
+            }
+            Some(c) if *c != '\\' && *c == s[si] => {
+                si += 1;
+                pi += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        match retry {
+            Some((star_si, star_pi)) => {
+                si = star_si + 1;
+                pi = star_pi;
+                retry = Some((si, pi));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[pi..].iter().all(|c| *c == '*')
+}
+
+/// Runs `push` or `pop` on a heap, which keeps the smallest value first.
+fn change_heap(
+    builtin: Builtin,
+    heap: &mut Vec<Value>,
+    args: Vec<Value>,
+    interner: &StringInterner,
+) -> Result<Value, Message> {
+    let less = |a: &Value, b: &Value| {
+        a.compare(b, interner)
+            .map(|ordering| ordering == Ordering::Less)
+            .ok_or_else(|| Message::CannotOrder(kind_of(a), kind_of(b)))
+    };
+
+    match (builtin, args.as_slice()) {
+        (Builtin::Push, [value]) => {
+            heap.push(value.clone());
+            let mut i = heap.len() - 1;
+            while i > 0 && less(&heap[i], &heap[(i - 1) / 2])? {
+                heap.swap(i, (i - 1) / 2);
+                i = (i - 1) / 2;
+            }
+            Ok(Value::Nil)
+        }
+        (Builtin::Pop, []) => {
+            if heap.is_empty() {
+                return Err(Message::PopEmpty);
+            }
+            let smallest = heap.swap_remove(0);
+            let mut i = 0;
+            loop {
+                let mut next = i;
+                for child in (2 * i + 1..heap.len()).take(2) {
+                    if less(&heap[child], &heap[next])? {
+                        next = child;
+                    }
+                }
+                if next == i {
+                    break;
+                }
+                heap.swap(i, next);
+                i = next;
+            }
+            Ok(smallest)
+        }
+        _ => Err(Message::ExpectedArray(ValueKind::Heap(Box::new(
+            ValueKind::Nil,
+        )))),
+    }
+}
+
+/// Runs `push` or `pop` on the back of a deque, or `push_front` or
+/// `pop_front` on its front.
+fn change_deque(
+    builtin: Builtin,
+    deque: &mut VecDeque<Value>,
+    args: Vec<Value>,
+) -> Result<Value, Message> {
+    match (builtin, args.as_slice()) {
+        (Builtin::Push, [value]) => deque.push_back(value.clone()),
+        (Builtin::PushFront, [value]) => deque.push_front(value.clone()),
+        (Builtin::Pop, []) => return deque.pop_back().ok_or(Message::PopEmpty),
+        (Builtin::PopFront, []) => {
+            return deque.pop_front().ok_or(Message::PopEmpty)
+        }
+        _ => {
+            return Err(Message::ExpectedArray(ValueKind::Deque(Box::new(
+                ValueKind::Nil,
+            ))))
+        }
+    }
+    Ok(Value::Nil)
+}
+
+/// Converts the length given to a builtin, which cannot be negative or
+/// more than arrays are allowed to grow by at once.
+fn length(n: i32, limits: &Limits) -> Result<usize, Message> {
+    let n = usize::try_from(n).map_err(|_| Message::NegativeLength(n))?;
+    if n > limits.max_array_len {
+        return Err(Message::ArrayTooLong(limits.max_array_len));
+    }
+    Ok(n)
+}
+
+/// Returns `Ok` with a map of the parts of the URL, which are empty when
+/// the URL does not have them.
+fn url_parse(args: &[Value], interner: &mut StringInterner) -> Value {
+    let url = match url::parse(string(&args[0], interner)) {
+        Ok(url) => url,
+        Err(message) => return error(message, interner),
+    };
+
+    let port = url.port.map_or_else(String::new, |port| port.to_string());
+    let parts = [
+        ("scheme", url.scheme),
+        ("user", url.user),
+        ("host", url.host),
+        ("port", port),
+        ("path", url.path),
+        ("query", url.query),
+        ("fragment", url.fragment),
+    ];
+    let mut map = Map::new();
+    for (name, part) in &parts {
+        map.insert(
+            MapKey::String(interner.intern(name)),
+            Value::String(interner.intern(part)),
+        );
+    }
+    Value::Ok(Box::new(Value::Map(Rc::new(map))))
+}
+
+fn url_encode(args: &[Value], interner: &mut StringInterner) -> Value {
+    let encoded = url::encode(string(&args[0], interner));
+    Value::String(interner.intern(&encoded))
+}
+
+fn url_decode(args: &[Value], interner: &mut StringInterner) -> Value {
+    match url::decode(string(&args[0], interner)) {
+        Ok(decoded) => {
+            Value::Ok(Box::new(Value::String(interner.intern(&decoded))))
+        }
+        Err(message) => error(message, interner),
+    }
+}
+
+/// Returns a query string with the keys and values of the map, in the
+/// order they were added.
+fn url_query(args: &[Value], interner: &mut StringInterner) -> Value {
+    let pairs = string_pairs(&args[0], interner);
+    Value::String(interner.intern(&url::query(&pairs)))
+}
+
+/// Returns the map as an `application/x-www-form-urlencoded` body.
+fn form_encode(args: &[Value], interner: &mut StringInterner) -> Value {
+    let fields = string_pairs(&args[0], interner);
+    Value::String(interner.intern(&form::encode(&fields)))
+}
+
+/// Writes a `multipart/form-data` body with the fields in the second
+/// argument and the files named by the third to the file at the first.
+/// Returns `Ok` with the content type to send it with.
+fn multipart_write(args: &[Value], interner: &mut StringInterner) -> Value {
+    let path = string(&args[0], interner).to_string();
+    let fields = string_pairs(&args[1], interner);
+    let files = string_pairs(&args[2], interner);
+
+    let boundary = match random::token(24) {
+        Ok(token) => format!("blixt-{}", token),
+        Err(e) => return error(e.to_string(), interner),
+    };
+    let mut output = match fs::File::create(&path) {
+        Ok(file) => std::io::BufWriter::new(file),
+        Err(e) => return file_error(&path, &e, interner),
+    };
+
+    match form::write_multipart(&mut output, &boundary, &fields, &files)
+        .and_then(|_| output.flush())
+    {
+        Ok(()) => {
+            let content_type =
+                format!("multipart/form-data; boundary={}", boundary);
+            Value::Ok(Box::new(Value::String(interner.intern(&content_type))))
+        }
+        Err(e) => {
+            // Half a body would only be sent by mistake.
+            let _ = fs::remove_file(&path);
+            error(e.to_string(), interner)
+        }
+    }
+}
+
+/// The keys and values of a map from strings to strings, in the order they
+/// were added.
+fn string_pairs(
+    value: &Value,
+    interner: &StringInterner,
+) -> Vec<(String, String)> {
+    match value {
+        Value::Map(map) => map
+            .iter()
+            .map(|(key, value)| {
+                let key = key.to_value().format(interner);
+                (key, string(value, interner).to_string())
+            })
+            .collect(),
+        other => unreachable!("Expected a map, found {:?}", other),
+    }
+}
+
+/// Returns the hunks that turn the lines of the first argument into those
+/// of the second.
+fn diff_lines(args: &[Value], interner: &mut StringInterner) -> Value {
+    let old: Vec<_> = string(&args[0], interner).lines().collect();
+    let new: Vec<_> = string(&args[1], interner).lines().collect();
+    let hunks = diff::diff_lines(&old, &new, diff::CONTEXT);
+
+    let hunks = hunks
+        .into_iter()
+        .map(|hunk| {
+            let lines = hunk
+                .lines
+                .iter()
+                .map(|line| Value::String(interner.intern(line)))
+                .collect();
+            Value::Array(Rc::new(vec![
+                Value::Int(hunk.old_start as i32),
+                Value::Int(hunk.old_len as i32),
+                Value::Int(hunk.new_start as i32),
+                Value::Int(hunk.new_len as i32),
+                Value::Array(Rc::new(lines)),
+            ]))
+        })
+        .collect();
+    Value::Array(Rc::new(hunks))
+}
+
+/// Returns `Ok` with the text after applying the hunks made by
+/// `diff_lines`, or `Err` if they don't fit it.
+fn apply_patch(args: &[Value], interner: &mut StringInterner) -> Value {
+    let text = string(&args[0], interner);
+    let old: Vec<_> = text.lines().collect();
+    let hunks = hunks_of(&args[1], interner);
+
+    match diff::apply(&old, &hunks) {
+        Ok(mut new) => {
+            if text.ends_with('\n') {
+                new.push(std::string::String::new());
+            }
+            let new = interner.intern(&new.join("\n"));
+            Value::Ok(Box::new(Value::String(new)))
+        }
+        Err(message) => error(message, interner),
+    }
+}
+
+/// Formats hunks as a unified diff, in color if the second argument is
+/// true.
+fn render_diff(args: &[Value], interner: &mut StringInterner) -> Value {
+    let hunks = hunks_of(&args[0], interner);
+    let color = matches!(args[1], Value::Bool(true));
+    Value::String(interner.intern(&diff::render(&hunks, color)))
+}
+
+/// Converts hunks given by a script back from arrays.
+fn hunks_of(value: &Value, interner: &StringInterner) -> Vec<Hunk> {
+    let hunks = match value {
+        Value::Array(hunks) => hunks,
+        other => unreachable!("Expected hunks, found {:?}", other),
+    };
+
+    hunks
+        .iter()
+        .map(|hunk| match hunk {
+            Value::Array(parts) => {
+                let number = |i: usize| match parts[i] {
+                    Value::Int(n) => n.max(0) as usize,
+                    ref other => {
+                        unreachable!("Expected an int, found {:?}", other)
+                    }
+                };
+                Hunk {
+                    old_start: number(0),
+                    old_len: number(1),
+                    new_start: number(2),
+                    new_len: number(3),
+                    lines: strings_of(&parts[4], interner)
+                        .map(|line| line.to_string())
+                        .collect(),
+                }
+            }
+            other => unreachable!("Expected a hunk, found {:?}", other),
+        })
+        .collect()
+}
+
+/// Returns `Ok` with the rows laid out under the headers in the style
+/// named by the third argument.
+fn format_table(args: &[Value], interner: &mut StringInterner) -> Value {
+    let name = string(&args[2], interner);
+    let style = match Style::from_name(name) {
+        Some(style) => style,
+        None => return error(format!("No table style {}", name), interner),
+    };
+
+    let (headers, rows) = table_cells(args, interner);
+    let output = table::format(&headers, &rows, style);
+    Value::Ok(Box::new(Value::String(interner.intern(&output))))
+}
+
+/// Prints the rows under the headers with padded columns, cut to the width
+/// of the terminal.
+fn print_table(args: &[Value], interner: &mut StringInterner) -> Value {
+    let (headers, rows) = table_cells(args, interner);
+    let mut output = table::format(&headers, &rows, Style::Plain);
+    if let Some(width) = table::terminal_width() {
+        output = table::truncate(&output, width);
+    }
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    stdout
+        .write_all(output.as_bytes())
+        .and_then(|_| stdout.flush())
+        .expect("Failed to write to stdout");
+    Value::Nil
+}
+
+/// Checks a value against a schema, returning `Ok` with a map of the path
+/// and the message for each problem found, or `Err` if the schema is
+/// invalid.
+fn validate(args: &[Value], interner: &mut StringInterner) -> Value {
+    let schema: Vec<_> = match &args[1] {
+        Value::Map(map) => map
+            .iter()
+            .map(|(key, rule)| {
+                let path = key.to_value().format(interner);
+                (path, string(rule, interner).to_string())
+            })
+            .collect(),
+        other => unreachable!("Expected a map, found {:?}", other),
+    };
+
+    match schema::validate(&args[0], &schema, interner) {
+        Ok(problems) => {
+            let problems = problems
+                .into_iter()
+                .map(|problem| {
+                    let path = Value::String(interner.intern(&problem.path));
+                    let message =
+                        Value::String(interner.intern(&problem.message));
+                    string_map(
+                        &[("path", path), ("message", message)],
+                        interner,
+                    )
+                })
+                .collect();
+            Value::Ok(Box::new(Value::Array(Rc::new(problems))))
+        }
+        Err(message) => error(message, interner),
+    }
+}
+
+/// The headers and the formatted cells of the rows given to
+/// `format_table` and `print_table`.
+fn table_cells(
+    args: &[Value],
+    interner: &StringInterner,
+) -> (Vec<std::string::String>, Vec<Vec<std::string::String>>) {
+    let headers = strings_of(&args[1], interner).map(str::to_string).collect();
+    let rows = match &args[0] {
+        Value::Array(rows) => rows
+            .iter()
+            .map(|row| match row {
+                Value::Array(cells) => {
+                    cells.iter().map(|cell| cell.format(interner)).collect()
+                }
+                other => unreachable!("Expected an array, found {:?}", other),
+            })
+            .collect(),
+        other => unreachable!("Expected an array, found {:?}", other),
+    };
+    (headers, rows)
+}
+
+/// Returns `Ok` with the contents of the file, or `Err` with the reason it
+/// could not be read.
+fn read_file(args: &[Value], interner: &mut StringInterner) -> Value {
+    let path = string(&args[0], interner).to_string();
+
+    match fs::read_to_string(&path) {
+        Ok(contents) => {
+            Value::Ok(Box::new(Value::String(interner.intern(&contents))))
+        }
+        Err(e) => file_error(&path, &e, interner),
+    }
+}
+
+/// Replaces the contents of the file with the second argument. Returns
+/// `Ok` with the number of bytes written.
+fn write_file(args: &[Value], interner: &mut StringInterner) -> Value {
+    let path = string(&args[0], interner).to_string();
+    let contents = string(&args[1], interner);
+
+    match fs::write(&path, contents) {
+        Ok(()) => Value::Ok(Box::new(Value::Int(contents.len() as i32))),
+        Err(e) => file_error(&path, &e, interner),
+    }
+}
+
+/// Returns `Ok` with the contents of the file as an array of bytes, for
+/// files that are not text.
+fn read_bytes(args: &[Value], interner: &mut StringInterner) -> Value {
+    let path = string(&args[0], interner).to_string();
+
+    match fs::read(&path) {
+        Ok(contents) => Value::Ok(Box::new(bytes_value(&contents))),
+        Err(e) => file_error(&path, &e, interner),
+    }
+}
+
+/// Replaces the contents of the file with the bytes in the second
+/// argument. Returns `Ok` with the number of bytes written.
+fn write_bytes(args: &[Value], interner: &mut StringInterner) -> Value {
+    let path = string(&args[0], interner).to_string();
+    let contents = match bytes_of(&args[1]) {
+        Ok(contents) => contents,
+        Err(message) => return error(message, interner),
+    };
+
+    match fs::write(&path, &contents) {
+        Ok(()) => Value::Ok(Box::new(Value::Int(contents.len() as i32))),
+        Err(e) => file_error(&path, &e, interner),
+    }
+}
+
+/// Returns the UTF-8 bytes of the string.
+fn to_bytes(args: &[Value], interner: &mut StringInterner) -> Value {
+    bytes_value(string(&args[0], interner).as_bytes())
+}
+
+/// Returns `Ok` with the string the bytes encode, or `Err` if they are not
+/// UTF-8.
+fn from_bytes(args: &[Value], interner: &mut StringInterner) -> Value {
+    let bytes = match bytes_of(&args[0]) {
+        Ok(bytes) => bytes,
+        Err(message) => return error(message, interner),
+    };
+
+    match std::str::from_utf8(&bytes) {
+        Ok(text) => Value::Ok(Box::new(Value::String(interner.intern(text)))),
+        Err(e) => error(format!("Invalid UTF-8: {}", e), interner),
+    }
+}
+
+/// Bytes as the array of ints scripts use for them.
+fn bytes_value(bytes: &[u8]) -> Value {
+    let bytes = bytes.iter().map(|b| Value::Int(i32::from(*b))).collect();
+    Value::Array(Rc::new(bytes))
+}
+
+/// The bytes in an array of ints, or the reason if one of them is not
+/// between 0 and 255.
+fn bytes_of(value: &Value) -> Result<Vec<u8>, String> {
+    let values = match value {
+        Value::Array(values) => values,
+        other => unreachable!("Expected an array, found {:?}", other),
+    };
+    values
+        .iter()
+        .map(|value| {
+            let n = int(value);
+            u8::try_from(n).map_err(|_| format!("Invalid byte {}", n))
+        })
+        .collect()
+}
+
+/// Returns `Ok` with the SHA-256 hash of the file in hex, reading it a
+/// piece at a time.
+fn file_sha256(args: &[Value], interner: &mut StringInterner) -> Value {
+    let path = string(&args[0], interner).to_string();
+    let mut hash = Sha256::new();
+
+    match fs::File::open(&path).and_then(|mut file| hash.update_from(&mut file))
+    {
+        Ok(()) => {
+            let hex = hash.finish_hex();
+            Value::Ok(Box::new(Value::String(interner.intern(&hex))))
+        }
+        Err(e) => file_error(&path, &e, interner),
+    }
+}
+
+/// Returns `Ok` with a SHA-256 hash of the names and contents of every
+/// file under the directory, which only changes when one of them does.
+fn dir_digest(args: &[Value], interner: &mut StringInterner) -> Value {
+    let path = string(&args[0], interner).to_string();
+    let mut hash = Sha256::new();
+
+    match digest_dir(Path::new(&path), "", &mut hash) {
+        Ok(()) => {
+            let hex = hash.finish_hex();
+            Value::Ok(Box::new(Value::String(interner.intern(&hex))))
+        }
+        Err(e) => file_error(&path, &e, interner),
+    }
+}
+
+/// Adds the files under `dir` to `hash` in order of their names, each as
+/// its path from the top directory, `prefix` here, and the hash of its
+/// contents.
+fn digest_dir(
+    dir: &Path,
+    prefix: &str,
+    hash: &mut Sha256,
+) -> std::io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            digest_dir(&entry.path(), &format!("{}/", name), hash)?;
+            continue;
+        }
+
+        let mut contents = Sha256::new();
+        contents.update_from(&mut fs::File::open(entry.path())?)?;
+        hash.update(name.as_bytes());
+        hash.update(&[0]);
+        hash.update(&contents.finish());
+    }
+
+    Ok(())
+}
+
+/// Returns `Ok` with a random version 4 UUID.
+fn uuid4(_: &[Value], interner: &mut StringInterner) -> Value {
+    match random::uuid4() {
+        Ok(uuid) => Value::Ok(Box::new(Value::String(interner.intern(&uuid)))),
+        Err(e) => error(e.to_string(), interner),
+    }
+}
+
+/// Returns `Ok` with the given number of random bytes, as ints from 0 to
+/// 255.
+fn random_bytes(args: &[Value], interner: &mut StringInterner) -> Value {
+    let n = match usize::try_from(int(&args[0])) {
+        Ok(n) => n,
+        Err(_) => {
+            return error(format!("Invalid length {}", int(&args[0])), interner)
+        }
+    };
+    let mut bytes = vec![0; n];
+    match random::fill(&mut bytes) {
+        Ok(()) => Value::Ok(Box::new(bytes_value(&bytes))),
+        Err(e) => error(e.to_string(), interner),
+    }
+}
+
+/// Returns `Ok` with a random string of the given length, made of letters,
+/// digits, `-` and `_`.
+fn random_token(args: &[Value], interner: &mut StringInterner) -> Value {
+    let len = match usize::try_from(int(&args[0])) {
+        Ok(len) => len,
+        Err(_) => {
+            return error(format!("Invalid length {}", int(&args[0])), interner)
+        }
+    };
+    match random::token(len) {
+        Ok(token) => {
+            Value::Ok(Box::new(Value::String(interner.intern(&token))))
+        }
+        Err(e) => error(e.to_string(), interner),
+    }
+}
+
+fn file_error(
+    path: &str,
+    error: &std::io::Error,
+    interner: &mut StringInterner,
+) -> Value {
+    let message = format!("{}: {}", path, error);
+    Value::Err(Box::new(Value::String(interner.intern(&message))))
+}
+
+/// Files and directories made by `temp_file` and `temp_dir`. They are
+/// removed when the builtins are dropped along with the context.
+#[derive(Default)]
+struct TempPaths {
+    made: Vec<PathBuf>,
+    next: u32,
+}
+
+impl TempPaths {
+    /// Makes an empty file or directory with a name no other one has,
+    /// returning `Ok` with its path.
+    fn make(&mut self, dir: bool, interner: &mut StringInterner) -> Value {
+        loop {
+            self.next += 1;
+            let name = format!("blixt-{}-{}", process::id(), self.next);
+            let path = env::temp_dir().join(name);
+            let made = if dir {
+                fs::create_dir(&path)
+            } else {
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                    .map(|_| ())
+            };
+
+            let shown = path.to_string_lossy().into_owned();
+            match made {
+                Ok(()) => {
+                    self.made.push(path);
+                    let path = interner.intern(&shown);
+                    return Value::Ok(Box::new(Value::String(path)));
+                }
+                // Made by another context in this process, or left behind
+                // by an earlier one with the same process id.
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return file_error(&shown, &e, interner),
+            }
+        }
+    }
+}
+
+impl Drop for TempPaths {
+    fn drop(&mut self) {
+        for path in &self.made {
+            // The script may have removed or replaced it already.
+            let _ = if path.is_dir() {
+                fs::remove_dir_all(path)
+            } else {
+                fs::remove_file(path)
+            };
+        }
+    }
+}
+
+/// Adds `temp_file` and `temp_dir`, which need the `fs` capability.
+fn register_temp_paths(context: &mut Context) {
+    let temps = Rc::new(RefCell::new(TempPaths::default()));
+
+    let removed = Rc::clone(&temps);
+    context.releases.push(Box::new(move || {
+        *removed.borrow_mut() = TempPaths::default();
+    }));
+
+    let made = Rc::clone(&temps);
+    define(
+        context,
+        "temp_file",
+        &[],
+        Some(ValueKind::Result),
+        FS,
+        move |_, interner| made.borrow_mut().make(false, interner),
+    );
+    define(
+        context,
+        "temp_dir",
+        &[],
+        Some(ValueKind::Result),
+        FS,
+        move |_, interner| temps.borrow_mut().make(true, interner),
+    );
+}
+
+/// Adds `progress`, which starts a progress bar and returns a number for
+/// it, and `tick` and `finish`, which move it along and end it.
+fn register_progress(context: &mut Context) {
+    use ValueKind::*;
+
+    let bars = Rc::new(RefCell::new(HashMap::new()));
+    let next = Rc::new(RefCell::new(0));
+
+    let finished = Rc::clone(&bars);
+    context
+        .releases
+        .push(Box::new(move || finished.borrow_mut().clear()));
+
+    let started = Rc::clone(&bars);
+    define(
+        context,
+        "progress",
+        &[Integer],
+        Some(Integer),
+        None,
+        move |args, _| {
+            let total = int(&args[0]).max(0) as u64;
+            *next.borrow_mut() += 1;
+            let id = *next.borrow();
+            started.borrow_mut().insert(id, Bar::new(total));
+            Value::Int(id)
+        },
+    );
+
+    // Bars that are unknown or already finished are left alone, since a
+    // progress bar is not worth stopping the script for.
+    let ticked = Rc::clone(&bars);
+    define(context, "tick", &[Integer], None, None, move |args, _| {
+        if let Some(bar) = ticked.borrow_mut().get_mut(&int(&args[0])) {
+            bar.tick();
+        }
+        Value::Nil
+    });
+    define(context, "finish", &[Integer], None, None, move |args, _| {
+        if let Some(mut bar) = bars.borrow_mut().remove(&int(&args[0])) {
+            bar.finish();
+        }
+        Value::Nil
+    });
+}
+
+/// Adds `on_signal` and `on_exit`, which keep the functions they are given
+/// for the interpreter or the VM to call.
+fn register_handlers(context: &mut Context) {
+    use ValueKind::*;
+
+    let handler = Function(vec![], Box::new(Nil));
+
+    let handlers = Rc::clone(&context.handlers);
+    define(
+        context,
+        "on_signal",
+        &[String, handler.clone()],
+        Some(Result),
+        None,
+        move |args, interner| {
+            let name = string(&args[0], interner);
+            let signal = match Signal::from_name(name) {
+                Some(signal) => signal,
+                None => return error(format!("No signal {}", name), interner),
+            };
+            let func = match &args[1] {
+                Value::Function(func) => func.clone(),
+                other => unreachable!("Expected a function, found {:?}", other),
+            };
+
+            let signals = &mut handlers.borrow_mut().signals;
+            signals.retain(|(caught, _)| *caught != signal);
+            signals.push((signal, func));
+            interrupt::catch(signal);
+            Value::Ok(Box::new(args[0].clone()))
+        },
+    );
+
+    let handlers = Rc::clone(&context.handlers);
+    define(
+        context,
+        "on_exit",
+        &[handler],
+        None,
+        None,
+        move |args, _| {
+            if let Value::Function(func) = &args[0] {
+                handlers.borrow_mut().exit.push(func.clone());
+            }
+            Value::Nil
+        },
+    );
+}
+
+/// Child processes started by `spawn` and `pipe`, which scripts refer to
+/// by number.
+#[derive(Default)]
+struct Processes {
+    running: HashMap<i32, Process>,
+    next: i32,
+}
+
+struct Process {
+    command: String,
+    child: Child,
+    /// None once the output has been given to another process by `pipe`.
+    stdout: Option<BufReader<ChildStdout>>,
+}
+
+impl Processes {
+    /// Adds a process that was just spawned, returning `Ok` with its
+    /// number.
+    fn add(
+        &mut self,
+        command: String,
+        spawned: std::io::Result<Child>,
+        interner: &mut StringInterner,
+    ) -> Value {
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => return file_error(&command, &e, interner),
+        };
+
+        self.next += 1;
+        let stdout = child.stdout.take().map(BufReader::new);
+        self.running.insert(
+            self.next,
+            Process {
+                command,
+                child,
+                stdout,
+            },
+        );
+        Value::Ok(Box::new(Value::Int(self.next)))
+    }
+
+    fn get(
+        &mut self,
+        id: &Value,
+        interner: &mut StringInterner,
+    ) -> Result<&mut Process, Value> {
+        let id = process_id(id);
+        match self.running.get_mut(&id) {
+            Some(process) => Ok(process),
+            None => {
+                let message = format!("No process {}", id);
+                Err(error(message, interner))
+            }
+        }
+    }
+}
+
+/// Adds the functions that run other programs, and stream text to and
+/// from them. They need the `proc` capability.
+fn register_processes(context: &mut Context) {
+    use ValueKind::*;
+
+    let strings = Array(Box::new(String));
+    let processes = Rc::new(RefCell::new(Processes::default()));
+
+    // Processes left running are killed rather than handed to the next
+    // script.
+    let table = Rc::clone(&processes);
+    context.releases.push(Box::new(move || {
+        let mut processes = table.borrow_mut();
+        for process in processes.running.values_mut() {
+            let _ = process.child.kill();
+            let _ = process.child.wait();
+        }
+        *processes = Processes::default();
+    }));
+
+    let table = Rc::clone(&processes);
+    define(
+        context,
+        "spawn",
+        &[String, strings.clone()],
+        Some(Result),
+        PROC,
+        move |args, interner| {
+            let command = string(&args[0], interner).to_string();
+            let spawned = Command::new(&command)
+                .args(strings_of(&args[1], interner))
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn();
+            table.borrow_mut().add(command, spawned, interner)
+        },
+    );
+
+    let table = Rc::clone(&processes);
+    define(
+        context,
+        "pipe",
+        &[Integer, String, strings],
+        Some(Result),
+        PROC,
+        move |args, interner| {
+            let mut table = table.borrow_mut();
+            let from = match table.get(&args[0], interner) {
+                Ok(process) => process,
+                Err(e) => return e,
+            };
+            // Lines already read may have taken more than they returned.
+            let stdout = match from.stdout.take() {
+                Some(stdout) if stdout.buffer().is_empty() => {
+                    stdout.into_inner()
+                }
+                _ => {
+                    let message = format!(
+                        "{}: output was already read or piped",
+                        from.command
+                    );
+                    return error(message, interner);
+                }
+            };
+
+            let command = string(&args[1], interner).to_string();
+            let spawned = Command::new(&command)
+                .args(strings_of(&args[2], interner))
+                .stdin(Stdio::from(stdout))
+                .stdout(Stdio::piped())
+                .spawn();
+            table.add(command, spawned, interner)
+        },
+    );
+
+    let table = Rc::clone(&processes);
+    define(
+        context,
+        "write_stdin",
+        &[Integer, String],
+        Some(Result),
+        PROC,
+        move |args, interner| {
+            let mut table = table.borrow_mut();
+            let process = match table.get(&args[0], interner) {
+                Ok(process) => process,
+                Err(e) => return e,
+            };
+            let text = string(&args[1], interner);
+            let written = match &mut process.child.stdin {
+                Some(stdin) => stdin.write_all(text.as_bytes()),
+                None => {
+                    let message =
+                        format!("{}: input is closed", process.command);
+                    return error(message, interner);
+                }
+            };
+
+            match written {
+                Ok(()) => Value::Ok(Box::new(Value::Int(text.len() as i32))),
+                Err(e) => file_error(&process.command.clone(), &e, interner),
+            }
+        },
+    );
+
+    let table = Rc::clone(&processes);
+    define(
+        context,
+        "close_stdin",
+        &[Integer],
+        None,
+        PROC,
+        move |args, interner| {
+            if let Ok(process) = table.borrow_mut().get(&args[0], interner) {
+                process.child.stdin = None;
+            }
+            Value::Nil
+        },
+    );
+
+    let table = Rc::clone(&processes);
+    define(
+        context,
+        "read_line",
+        &[Integer],
+        Some(Result),
+        PROC,
+        move |args, interner| {
+            let mut table = table.borrow_mut();
+            let process = match table.get(&args[0], interner) {
+                Ok(process) => process,
+                Err(e) => return e,
+            };
+            let command = process.command.clone();
+            let stdout = match &mut process.stdout {
+                Some(stdout) => stdout,
+                None => {
+                    let message = format!("{}: output is piped", command);
+                    return error(message, interner);
+                }
+            };
+
+            let mut line = std::string::String::new();
+            match stdout.read_line(&mut line) {
+                Ok(0) => error(format!("{}: end of output", command), interner),
+                Ok(_) => {
+                    let line = line.trim_end_matches(&['\n', '\r'][..]);
+                    Value::Ok(Box::new(Value::String(interner.intern(line))))
+                }
+                Err(e) => file_error(&command, &e, interner),
+            }
+        },
+    );
+
+    define(
+        context,
+        "wait",
+        &[Integer],
+        Some(Result),
+        PROC,
+        move |args, interner| {
+            let id = process_id(&args[0]);
+            let removed = processes.borrow_mut().running.remove(&id);
+            let mut process = match removed {
+                Some(process) => process,
+                None => return error(format!("No process {}", id), interner),
+            };
+
+            // Output that was not read is thrown away, so that a process
+            // blocked on writing it can finish.
+            process.child.stdin = None;
+            process.stdout = None;
+            match process.child.wait() {
+                Ok(status) => {
+                    Value::Ok(Box::new(Value::Int(status.code().unwrap_or(-1))))
+                }
+                Err(e) => file_error(&process.command, &e, interner),
+            }
+        },
+    );
+}
+
+/// Adds `ws_connect`, which connects to a WebSocket server and returns a
+/// number for the connection, and `send`, `recv` and `close`, which use
+/// it. Only `ws://` URLs can be connected to, since there is no TLS; a
+/// `wss://` URL is an `Err` rather than a connection in plain text.
+#[cfg(feature = "websocket")]
+fn register_sockets(context: &mut Context) {
+    use ValueKind::*;
+
+    let sockets = Rc::new(RefCell::new(HashMap::<i32, Socket>::new()));
+    let next = Rc::new(RefCell::new(0));
+
+    let table = Rc::clone(&sockets);
+    context.releases.push(Box::new(move || {
+        for (_, mut socket) in table.borrow_mut().drain() {
+            let _ = socket.close();
+        }
+    }));
+
+    let table = Rc::clone(&sockets);
+    define(
+        context,
+        "ws_connect",
+        &[String],
+        Some(Result),
+        NET,
+        move |args, interner| {
+            let url = string(&args[0], interner).to_string();
+            match Socket::connect(&url) {
+                Ok(socket) => {
+                    *next.borrow_mut() += 1;
+                    let id = *next.borrow();
+                    table.borrow_mut().insert(id, socket);
+                    Value::Ok(Box::new(Value::Int(id)))
+                }
+                Err(e) => file_error(&url, &e, interner),
+            }
+        },
+    );
+
+    let table = Rc::clone(&sockets);
+    define(
+        context,
+        "send",
+        &[Integer, String],
+        Some(Result),
+        NET,
+        move |args, interner| {
+            let id = int(&args[0]);
+            let sent = match table.borrow_mut().get_mut(&id) {
+                Some(socket) => socket.send(string(&args[1], interner)),
+                None => {
+                    return error(format!("No connection {}", id), interner)
+                }
+            };
+            match sent {
+                Ok(()) => Value::Ok(Box::new(Value::Nil)),
+                Err(e) => error(e.to_string(), interner),
+            }
+        },
+    );
+
+    let table = Rc::clone(&sockets);
+    define(
+        context,
+        "recv",
+        &[Integer],
+        Some(Result),
+        NET,
+        move |args, interner| {
+            let id = int(&args[0]);
+            let received = match table.borrow_mut().get_mut(&id) {
+                Some(socket) => socket.recv(),
+                None => {
+                    return error(format!("No connection {}", id), interner)
+                }
+            };
+            match received {
+                Ok(Some(message)) => Value::Ok(Box::new(Value::String(
+                    interner.intern(&message),
+                ))),
+                Ok(None) => {
+                    table.borrow_mut().remove(&id);
+                    error(format!("Connection {} closed", id), interner)
+                }
+                Err(e) => error(e.to_string(), interner),
+            }
+        },
+    );
+
+    define(context, "close", &[Integer], None, NET, move |args, _| {
+        if let Some(mut socket) = sockets.borrow_mut().remove(&int(&args[0])) {
+            // The server may already have gone, which is what was wanted.
+            let _ = socket.close();
+        }
+        Value::Nil
+    });
+}
+
+/// Adds `xml_parse` and `html_parse`, which turn a document into nested
+/// node maps, and `html_select`, which finds the elements in one that
+/// match a CSS selector.
+#[cfg(feature = "xml")]
+fn register_markup(context: &mut Context) {
+    use ValueKind::*;
+
+    let node = || Map(Box::new(String), Box::new(Nil));
+    define(
+        context,
+        "xml_parse",
+        &[String],
+        Some(Result),
+        None,
+        |args, interner| match markup::parse_xml(string(&args[0], interner)) {
+            Ok(document) => {
+                Value::Ok(Box::new(element_value(&document, interner)))
+            }
+            Err(message) => error(message, interner),
+        },
+    );
+    define(
+        context,
+        "html_parse",
+        &[String],
+        Some(node()),
+        None,
+        |args, interner| {
+            let document = markup::parse_html(string(&args[0], interner));
+            element_value(&document, interner)
+        },
+    );
+    define(
+        context,
+        "html_select",
+        &[node(), String],
+        Some(Result),
+        None,
+        html_select,
+    );
+}
+
+/// Returns `Ok` with the elements under the node that match the selector,
+/// in document order.
+#[cfg(feature = "xml")]
+fn html_select(args: &[Value], interner: &mut StringInterner) -> Value {
+    let selector = match Selector::parse(string(&args[1], interner)) {
+        Ok(selector) => selector,
+        Err(message) => return error(message, interner),
+    };
+    let root = match value_node(&args[0], interner) {
+        Some(Node::Element(root)) => root,
+        _ => return error("Expected an element".to_string(), interner),
+    };
+
+    let found = selector
+        .select(&root)
+        .into_iter()
+        .map(|element| element_value(element, interner))
+        .collect();
+    Value::Ok(Box::new(Value::Array(Rc::new(found))))
+}
+
+/// A map with the `tag`, `attrs`, `children` and `text` of an element.
+/// Text nodes have the same keys, with the tag `#text`.
+#[cfg(feature = "xml")]
+fn element_value(element: &Element, interner: &mut StringInterner) -> Value {
+    let mut attrs = Map::new();
+    for (name, value) in &element.attrs {
+        attrs.insert(
+            MapKey::String(interner.intern(name)),
+            Value::String(interner.intern(value)),
+        );
+    }
+    let children = element
+        .children
+        .iter()
+        .map(|child| match child {
+            Node::Element(element) => element_value(element, interner),
+            Node::Text(text) => {
+                node_value("#text", Map::new(), vec![], text, interner)
+            }
+        })
+        .collect();
+    let text = element.text();
+    node_value(&element.name, attrs, children, &text, interner)
+}
+
+#[cfg(feature = "xml")]
+fn node_value(
+    tag: &str,
+    attrs: Map,
+    children: Vec<Value>,
+    text: &str,
+    interner: &mut StringInterner,
+) -> Value {
+    let parts = [
+        ("tag", Value::String(interner.intern(tag))),
+        ("attrs", Value::Map(Rc::new(attrs))),
+        ("children", Value::Array(Rc::new(children))),
+        ("text", Value::String(interner.intern(text))),
+    ];
+    string_map(&parts, interner)
+}
+
+/// Turns a node map back into a node, or `None` if it is not one.
+#[cfg(feature = "xml")]
+fn value_node(value: &Value, interner: &mut StringInterner) -> Option<Node> {
+    let map = match value {
+        Value::Map(map) => map,
+        _ => return None,
+    };
+    let mut parts = ["tag", "attrs", "children", "text"]
+        .iter()
+        .map(|name| map.get(MapKey::String(interner.intern(name))));
+
+    let (tag, attrs, children, text) = match (
+        parts.next()??,
+        parts.next()??,
+        parts.next()??,
+        parts.next()??,
+    ) {
+        (
+            Value::String(tag),
+            Value::Map(attrs),
+            Value::Array(children),
+            Value::String(text),
+        ) => (*tag, attrs, children, *text),
+        _ => return None,
+    };
+
+    let tag = interner.get(tag).to_string();
+    if tag == "#text" {
+        return Some(Node::Text(interner.get(text).to_string()));
+    }
+    let attrs = attrs
+        .iter()
+        .map(|(key, value)| {
+            (key.to_value().format(interner), value.format(interner))
+        })
+        .collect();
+    let children = children
+        .iter()
+        .map(|child| value_node(child, interner))
+        .collect::<Option<_>>()?;
+    Some(Node::Element(Element {
+        name: tag,
+        attrs,
+        children,
+    }))
+}
+
+/// Adds `zip_create`, which writes a zip archive, and `zip_extract` and
+/// `targz_extract`, which unpack one into a directory.
+#[cfg(feature = "archive")]
+fn register_archives(context: &mut Context) {
+    use ValueKind::*;
+
+    let files = Map(Box::new(String), Box::new(String));
+    define(
+        context,
+        "zip_create",
+        &[String, files],
+        Some(Result),
+        FS,
+        |args, interner| {
+            let path = string(&args[0], interner).to_string();
+            let files = string_pairs(&args[1], interner);
+            let created = archive::zip_create(Path::new(&path), &files);
+            count_or_error(&path, created, interner)
+        },
+    );
+
+    type Extract = fn(&Path, &Path) -> std::io::Result<usize>;
+    let extractors: [(&str, Extract); 2] = [
+        ("zip_extract", archive::zip_extract),
+        ("targz_extract", archive::targz_extract),
+    ];
+    for (name, extract) in extractors.iter().cloned() {
+        define(
+            context,
+            name,
+            &[String, String],
+            Some(Result),
+            FS,
+            move |args, interner| {
+                let path = string(&args[0], interner).to_string();
+                let dir = string(&args[1], interner);
+                let extracted = extract(Path::new(&path), Path::new(dir));
+                count_or_error(&path, extracted, interner)
+            },
+        );
+    }
+}
+
+/// Returns `Ok` with the number of files an archive builtin handled, or
+/// `Err` with what went wrong with the archive at `path`.
+#[cfg(feature = "archive")]
+fn count_or_error(
+    path: &str,
+    count: std::io::Result<usize>,
+    interner: &mut StringInterner,
+) -> Value {
+    match count {
+        Ok(count) => Value::Ok(Box::new(Value::Int(count as i32))),
+        Err(e) => file_error(path, &e, interner),
+    }
+}
+
+/// Adds `gzip_compress`, `gzip_decompress`, `zstd_compress` and
+/// `zstd_decompress`, which work on arrays of bytes.
+#[cfg(feature = "compression")]
+fn register_compression(context: &mut Context) {
+    use ValueKind::*;
+
+    let bytes = || Array(Box::new(Integer));
+    type Compress = fn(&[u8]) -> Vec<u8>;
+    let compressors: [(&str, Compress); 2] = [
+        ("gzip_compress", compress::gzip),
+        ("zstd_compress", compress::zstd),
+    ];
+    for (name, compress) in compressors.iter().cloned() {
+        define(
+            context,
+            name,
+            &[bytes()],
+            Some(Result),
+            None,
+            move |args, interner| match bytes_of(&args[0]) {
+                Ok(bytes) => {
+                    Value::Ok(Box::new(bytes_value(&compress(&bytes))))
+                }
+                Err(message) => error(message, interner),
+            },
+        );
+    }
+
+    define(
+        context,
+        "gzip_decompress",
+        &[bytes()],
+        Some(Result),
+        None,
+        |args, interner| {
+            let decompressed = bytes_of(&args[0]).and_then(|bytes| {
+                compress::gunzip(&bytes).map_err(|e| e.to_string())
+            });
+            decompressed_value(decompressed, interner)
+        },
+    );
+    define(
+        context,
+        "zstd_decompress",
+        &[bytes()],
+        Some(Result),
+        None,
+        |args, interner| {
+            let decompressed =
+                bytes_of(&args[0]).and_then(|bytes| compress::unzstd(&bytes));
+            decompressed_value(decompressed, interner)
+        },
+    );
+}
+
+#[cfg(feature = "compression")]
+fn decompressed_value(
+    decompressed: Result<Vec<u8>, String>,
+    interner: &mut StringInterner,
+) -> Value {
+    match decompressed {
+        Ok(bytes) => Value::Ok(Box::new(bytes_value(&bytes))),
+        Err(message) => error(message, interner),
+    }
+}
+
+fn process_id(value: &Value) -> i32 {
+    match value {
+        Value::Int(id) => *id,
+        other => unreachable!("Expected an int, found {:?}", other),
+    }
+}
+
+fn int(value: &Value) -> i32 {
+    match value {
+        Value::Int(n) => *n,
+        other => unreachable!("Expected an int, found {:?}", other),
+    }
+}
+
+fn strings_of<'a>(
+    value: &'a Value,
+    interner: &'a StringInterner,
+) -> impl Iterator<Item = &'a str> {
+    let values = match value {
+        Value::Array(values) => values.iter(),
+        other => unreachable!("Expected an array, found {:?}", other),
+    };
+    values.map(move |value| string(value, interner))
+}
+
+fn error(message: String, interner: &mut StringInterner) -> Value {
+    Value::Err(Box::new(Value::String(interner.intern(&message))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::Grants;
+
+    fn call(name: &str, args: &[&str]) -> String {
+        call_in(Language::English, name, args)
+    }
+
+    fn call_in(language: Language, name: &str, args: &[&str]) -> String {
+        let mut context = Context::new();
+        context.language = language;
+        register(&mut context);
+        let name = context.interner.intern(name);
+        let args: Vec<_> = args
+            .iter()
+            .map(|arg| Value::String(context.interner.intern(arg)))
+            .collect();
+
+        let func = Rc::clone(&context.native_functions[&name]);
+        (func.func)(&args, &mut context.interner).format(&context.interner)
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(call("split", &["a,b,,c", ","]), "[a, b, , c]");
+        assert_eq!(call("split", &["åb", ""]), "[å, b]");
+        assert_eq!(call("trim", &["  hi \n"]), "hi");
+        assert_eq!(call("to_upper", &["straße"]), "STRASSE");
+        assert_eq!(call("to_lower", &["ABC"]), "abc");
+        assert_eq!(call("strip_ansi", &["\x1b[31mred\x1b[0m"]), "red");
+        assert_eq!(call("display_width", &["日本 \x1b[1mx"]), "6");
+        assert_eq!(call("edit_distance", &["kitten", "sitting"]), "3");
+    }
+
+    #[test]
+    fn prompt_without_an_answer_denies() {
+        let ask = |input: &[u8]| {
+            let mut context = Context::new();
+            context.grants = Grants::Only(vec![]);
+            let mut input = input;
+            let granted = ask_to_grant("fs", None, &mut context, &mut input);
+            (granted, context.grants.allows("fs"))
+        };
+
+        assert_eq!(ask(b"yes\n"), (true, true));
+        assert_eq!(ask(b""), (false, false));
+        assert_eq!(ask(b"\xff\n"), (false, false));
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            call("url_parse", &["http://example.com:80/a?b=c"]),
+            "Ok({scheme: http, user: , host: example.com, port: 80, \
+             path: /a, query: b=c, fragment: })"
+        );
+        assert!(call("url_parse", &["example.com"]).starts_with("Err("));
+        assert_eq!(call("url_encode", &["a b&c"]), "a%20b%26c");
+        assert_eq!(call("url_decode", &["a%20b+c"]), "Ok(a b c)");
+        assert!(call("url_decode", &["%"]).starts_with("Err("));
+    }
+
+    #[cfg(feature = "xml")]
+    #[test]
+    fn syntax() {
+        assert_eq!(
+            call("lang.tokenize", &["n := 1"]),
+            "Ok([{kind: Ident, text: n, line: 1, column: 1}, \
+             {kind: VarDecl, text: :=, line: 1, column: 3}, \
+             {kind: Integer, text: 1, line: 1, column: 6}])"
+        );
+        assert_eq!(
+            call("lang.tokenize", &["\n\"a"]),
+            "Err(2:1: Unterminated string literal)"
+        );
+        assert_eq!(
+            call("lang.parse", &["-x"]),
+            "Ok([{kind: UnaryOp, text: Neg, children: \
+             [{kind: Ident, text: x, children: []}]}])"
+        );
+        assert_eq!(
+            call("lang.parse", &["x := "]),
+            "Err(1:3: Expected expression)"
+        );
+    }
+
+    #[test]
+    fn syntax_errors_in_the_context_language() {
+        assert_eq!(
+            call_in(Language::Swedish, "lang.tokenize", &["\"a"]),
+            "Err(1:1: Strängen tar aldrig slut)"
+        );
+    }
+
+    #[test]
+    fn markup() {
+        assert_eq!(
+            call("xml_parse", &["<a x='1'>hi</a>"]),
+            "Ok({tag: #document, attrs: {}, children: [{tag: a, \
+             attrs: {x: 1}, children: [{tag: #text, attrs: {}, \
+             children: [], text: hi}], text: hi}], text: hi})"
+        );
+        assert!(call("xml_parse", &["<a>"]).starts_with("Err("));
+
+        let mut context = Context::new();
+        register(&mut context);
+        let run = |name: &str, args: &[&str], context: &mut Context| {
+            let name = context.interner.intern(name);
+            let func = Rc::clone(&context.native_functions[&name]);
+            let args: Vec<_> = args
+                .iter()
+                .map(|arg| Value::String(context.interner.intern(arg)))
+                .collect();
+            (func.func)(&args, &mut context.interner)
+        };
+        let html = "<ul><li class=x>1<li>2<li class=x><b>3</b></ul>";
+        let doc = run("html_parse", &[html], &mut context);
+        let select = |selector: &str, context: &mut Context| {
+            let name = context.interner.intern("html_select");
+            let func = Rc::clone(&context.native_functions[&name]);
+            let selector = Value::String(context.interner.intern(selector));
+            let found =
+                (func.func)(&[doc.clone(), selector], &mut context.interner);
+            found.format(&context.interner)
+        };
+
+        let found = select("ul > .x b", &mut context);
+        assert_eq!(
+            found,
+            "Ok([{tag: b, attrs: {}, children: [{tag: #text, attrs: {}, \
+             children: [], text: 3}], text: 3}])"
+        );
+        assert_eq!(select("li", &mut context).matches("tag: li").count(), 3);
+        assert!(select("a >", &mut context).starts_with("Err("));
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn archives() {
+        let dir = std::env::temp_dir().join("blixt_builtins_archives");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+
+        let mut context = Context::new();
+        register(&mut context);
+        let mut files = Map::new();
+        let name = context.interner.intern("x/a.txt");
+        let source = dir.join("a.txt");
+        let source = context.interner.intern(source.to_str().unwrap());
+        files.insert(MapKey::String(name), Value::String(source));
+
+        let path = |name: &str, context: &mut Context| {
+            let path = dir.join(name);
+            Value::String(context.interner.intern(path.to_str().unwrap()))
+        };
+        let zip = path("a.zip", &mut context);
+        let args = [zip.clone(), Value::Map(Rc::new(files))];
+        let out = path("out", &mut context);
+        let tar = path("a.tar.gz", &mut context);
+
+        let call = |name: &str, args: &[Value], context: &mut Context| {
+            let name = context.interner.intern(name);
+            let func = Rc::clone(&context.native_functions[&name]);
+            let value = (func.func)(args, &mut context.interner);
+            value.format(&context.interner)
+        };
+        assert_eq!(call("zip_create", &args, &mut context), "Ok(1)");
+        assert_eq!(
+            call("zip_extract", &[zip, out.clone()], &mut context),
+            "Ok(1)"
+        );
+        assert_eq!(fs::read_to_string(dir.join("out/x/a.txt")).unwrap(), "a");
+        assert!(call("targz_extract", &[tar, out], &mut context)
+            .starts_with("Err("));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bytes() {
+        let dir = std::env::temp_dir().join("blixt_builtins_bytes");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.bin");
+        let path = path.to_str().unwrap();
+
+        assert_eq!(call("to_bytes", &["hé"]), "[104, 195, 169]");
+        assert!(call("read_bytes", &[path]).starts_with("Err("));
+        fs::write(path, [0, 255]).unwrap();
+        assert_eq!(call("read_bytes", &[path]), "Ok([0, 255])");
+
+        let mut context = Context::new();
+        register(&mut context);
+        let call = |name: &str, args: &[Value], context: &mut Context| {
+            let name = context.interner.intern(name);
+            let func = Rc::clone(&context.native_functions[&name]);
+            let value = (func.func)(args, &mut context.interner);
+            value.format(&context.interner)
+        };
+        let bytes = |bytes: &[i32]| {
+            Value::Array(Rc::new(
+                bytes.iter().map(|b| Value::Int(*b)).collect(),
+            ))
+        };
+        assert_eq!(
+            call("from_bytes", &[bytes(&[104, 105])], &mut context),
+            "Ok(hi)"
+        );
+        assert!(call("from_bytes", &[bytes(&[255])], &mut context)
+            .starts_with("Err("));
+        assert!(call("from_bytes", &[bytes(&[256])], &mut context)
+            .starts_with("Err("));
+
+        let path = Value::String(context.interner.intern(path));
+        let args = [path, bytes(&[1, 2, 3])];
+        assert_eq!(call("write_bytes", &args, &mut context), "Ok(3)");
+        assert_eq!(fs::read(dir.join("a.bin")).unwrap(), [1, 2, 3]);
+
+        #[cfg(feature = "compression")]
+        for (compress, decompress) in &[
+            ("gzip_compress", "gzip_decompress"),
+            ("zstd_compress", "zstd_decompress"),
+        ] {
+            let name = context.interner.intern(compress);
+            let func = Rc::clone(&context.native_functions[&name]);
+            let compressed = match (func.func)(
+                &[bytes(&[1, 2, 3])],
+                &mut context.interner,
+            ) {
+                Value::Ok(compressed) => *compressed,
+                other => panic!("Expected Ok, found {:?}", other),
+            };
+            assert_eq!(
+                call(decompress, &[compressed], &mut context),
+                "Ok([1, 2, 3])"
+            );
+            assert!(call(decompress, &[bytes(&[1])], &mut context)
+                .starts_with("Err("));
+            assert!(call(compress, &[bytes(&[-1])], &mut context)
+                .starts_with("Err("));
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn formatting() {
+        let mut context = Context::new();
+        register(&mut context);
+        let call = |name: &str, args: &[Value], context: &mut Context| {
+            let name = context.interner.intern(name);
+            let func = Rc::clone(&context.native_functions[&name]);
+            let value = (func.func)(args, &mut context.interner);
+            value.format(&context.interner)
+        };
+
+        let args = [Value::Int(1536)];
+        assert_eq!(call("format_bytes", &args, &mut context), "1.5 KiB");
+        let args = [Value::Int(90_500)];
+        assert_eq!(call("format_duration", &args, &mut context), "1m30s");
+        let args = [
+            Value::Int(-1234567),
+            Value::String(context.interner.intern(",")),
+        ];
+        assert_eq!(call("format_number", &args, &mut context), "-1,234,567");
+    }
+
+    #[test]
+    fn globs() {
+        let cases = [
+            ("foo1.txt", "foo*.txt", true),
+            ("foo.txt", "foo*.txt", true),
+            ("foo.txt.bak", "foo*.txt", false),
+            ("abcabd", "*ab?", true),
+            ("åäö", "?ä*", true),
+            ("a*b", "a\\*b", true),
+            ("axb", "a\\*b", false),
+            ("", "*", true),
+            ("a", "", false),
+        ];
+
+        for (s, pattern, matches) in cases {
+            let expected = if matches { "true" } else { "false" };
+            assert_eq!(call("matches_glob", &[s, pattern]), expected, "{}", s);
+        }
+    }
+
+    #[test]
+    fn script_arguments() {
+        assert_eq!(call("args", &[]), "[]");
+
+        let mut context = Context::new();
+        register(&mut context);
+        set_args(&mut context, &["-v".to_string(), "x".to_string()]);
+
+        let name = context.interner.intern("args");
+        let func = Rc::clone(&context.native_functions[&name]);
+        let value = (func.func)(&[], &mut context.interner);
+        assert_eq!(value.format(&context.interner), "[-v, x]");
+    }
+
+    #[test]
+    fn files() {
+        let path = std::env::temp_dir().join("blixt_builtins_files.txt");
+        let path = path.to_str().unwrap();
+
+        assert_eq!(call("write_file", &[path, "hej"]), "Ok(3)");
+        assert_eq!(call("read_file", &[path]), "Ok(hej)");
+        fs::remove_file(path).unwrap();
+
+        assert!(call("read_file", &[path]).starts_with("Err("));
+    }
+
+    #[test]
+    fn diffs() {
+        let mut context = Context::new();
+        register(&mut context);
+        let old = Value::String(context.interner.intern("a\nb\nc\n"));
+        let new = Value::String(context.interner.intern("a\nx\nc\n"));
+        let mut call = |name: &str, args: Vec<Value>| {
+            let name = context.interner.intern(name);
+            let func = Rc::clone(&context.native_functions[&name]);
+            (func.func)(&args, &mut context.interner)
+        };
+
+        let hunks = call("diff_lines", vec![old.clone(), new.clone()]);
+        let patched = call("apply_patch", vec![old, hunks.clone()]);
+        assert_eq!(patched, Value::Ok(Box::new(new.clone())));
+        assert!(matches!(
+            call("apply_patch", vec![new, hunks.clone()]),
+            Value::Err(_)
+        ));
+
+        let rendered = call("render_diff", vec![hunks, Value::Bool(false)]);
+        assert_eq!(
+            rendered.format(&context.interner),
+            "@@ -1,3 +1,3 @@\n a\n-b\n+x\n c\n"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let mut context = Context::new();
+        register(&mut context);
+        let cell = |s: &str, context: &mut Context| {
+            Value::String(context.interner.intern(s))
+        };
+        let row = Value::Array(Rc::new(vec![
+            cell("ann", &mut context),
+            Value::Int(31),
+        ]));
+        let args = [
+            Value::Array(Rc::new(vec![row])),
+            Value::Array(Rc::new(vec![
+                cell("name", &mut context),
+                cell("age", &mut context),
+            ])),
+            cell("csv", &mut context),
+        ];
+
+        let name = context.interner.intern("format_table");
+        let func = Rc::clone(&context.native_functions[&name]);
+        let table = (func.func)(&args, &mut context.interner);
+        assert_eq!(table.format(&context.interner), "Ok(name,age\nann,31\n)");
+
+        let args =
+            [args[0].clone(), args[1].clone(), cell("xml", &mut context)];
+        let table = (func.func)(&args, &mut context.interner);
+        assert!(matches!(table, Value::Err(_)));
+    }
+
+    #[test]
+    fn cache() {
+        let dir = std::env::temp_dir().join("blixt_builtins_cache");
+        let _ = fs::remove_dir_all(&dir);
+        let mut context = Context::new();
+        register(&mut context);
+        set_cache_dir(&mut context, dir.clone());
+
+        let call = |name: &str, args: &[Value], context: &mut Context| {
+            let name = context.interner.intern(name);
+            let func = Rc::clone(&context.native_functions[&name]);
+            let value = (func.func)(args, &mut context.interner);
+            value.format(&context.interner)
+        };
+        let key = Value::String(context.interner.intern("k"));
+        let value = Value::Array(Rc::new(vec![Value::Int(1), Value::Nil]));
+        let get = [key.clone()];
+
+        assert!(call("cache_get", &get, &mut context).starts_with("Err("));
+        let args = [key.clone(), value, Value::Int(0)];
+        assert_eq!(call("cache_set", &args, &mut context), "Ok(nil)");
+        assert_eq!(call("cache_get", &get, &mut context), "Ok([1, nil])");
+
+        let args = [key.clone(), Value::Int(2), Value::Int(-1)];
+        assert_eq!(call("cache_set", &args, &mut context), "Ok(nil)");
+        assert_eq!(call("cache_get", &get, &mut context), "Ok(2)");
+
+        let iter = Value::Iter(Rc::new(Iter::Range(0, 1)));
+        let args = [key, iter, Value::Int(0)];
+        assert!(call("cache_set", &args, &mut context).starts_with("Err("));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hashes() {
+        let dir = std::env::temp_dir().join("blixt_builtins_hashes");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), "abc").unwrap();
+        fs::write(dir.join("sub").join("b.txt"), "").unwrap();
+
+        let file = dir.join("a.txt");
+        assert_eq!(
+            call("file_sha256", &[file.to_str().unwrap()]),
+            "Ok(ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad)"
+        );
+
+        let dir_name = dir.to_str().unwrap();
+        let digest = call("dir_digest", &[dir_name]);
+        assert!(digest.starts_with("Ok("));
+        assert_eq!(call("dir_digest", &[dir_name]), digest);
+        fs::write(dir.join("sub").join("b.txt"), "x").unwrap();
+        assert_ne!(call("dir_digest", &[dir_name]), digest);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            call("file_sha256", &[file.to_str().unwrap()]).starts_with("Err(")
+        );
+    }
+
+    #[test]
+    fn random() {
+        let mut context = Context::new();
+        register(&mut context);
+        let mut call = |name: &str, n: i32| {
+            let name = context.interner.intern(name);
+            let func = Rc::clone(&context.native_functions[&name]);
+            let value = (func.func)(&[Value::Int(n)], &mut context.interner);
+            value.format(&context.interner)
+        };
+
+        assert_eq!(call("random_bytes", 3).matches(", ").count(), 2);
+        assert_eq!(call("random_token", 5).len(), "Ok()".len() + 5);
+        assert_eq!(call("random_token", 0), "Ok()");
+        assert!(call("random_bytes", -1).starts_with("Err("));
+        assert!(call("uuid4", 0).starts_with("Ok("));
+    }
+
+    #[test]
+    fn temp_paths() {
+        let mut context = Context::new();
+        register(&mut context);
+
+        let mut paths = vec![];
+        for name in &["temp_file", "temp_dir"] {
+            let name = context.interner.intern(name);
+            let func = Rc::clone(&context.native_functions[&name]);
+            match (func.func)(&[], &mut context.interner) {
+                Value::Ok(path) => {
+                    paths.push(path.format(&context.interner));
+                }
+                other => panic!("Expected Ok, found {:?}", other),
+            }
+        }
+
+        assert!(Path::new(&paths[0]).is_file());
+        assert!(Path::new(&paths[1]).is_dir());
+        drop(context);
+        assert!(!Path::new(&paths[0]).exists());
+        assert!(!Path::new(&paths[1]).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn processes() {
+        let mut context = Context::new();
+        register(&mut context);
+        let mut run = |name: &str, args: &[&str]| {
+            let args: Vec<_> = args
+                .iter()
+                .map(|arg| match arg.parse() {
+                    Ok(n) => Value::Int(n),
+                    Err(_) => Value::String(context.interner.intern(arg)),
+                })
+                .chain(std::iter::once(Value::Array(Rc::new(vec![]))))
+                .collect();
+            let name = context.interner.intern(name);
+            let func = Rc::clone(&context.native_functions[&name]);
+            (func.func)(&args, &mut context.interner).format(&context.interner)
+        };
+
+        assert_eq!(run("spawn", &["cat"]), "Ok(1)");
+        assert_eq!(run("write_stdin", &["1", "a\nb\n"]), "Ok(4)");
+        run("close_stdin", &["1"]);
+        assert_eq!(run("read_line", &["1"]), "Ok(a)");
+        assert_eq!(run("read_line", &["1"]), "Ok(b)");
+        assert_eq!(run("read_line", &["1"]), "Err(cat: end of output)");
+        assert_eq!(run("wait", &["1"]), "Ok(0)");
+        assert_eq!(run("wait", &["1"]), "Err(No process 1)");
+
+        assert_eq!(run("spawn", &["cat"]), "Ok(2)");
+        assert_eq!(run("pipe", &["2", "cat"]), "Ok(3)");
+        assert_eq!(run("read_line", &["2"]), "Err(cat: output is piped)");
+        run("write_stdin", &["2", "c\n"]);
+        run("close_stdin", &["2"]);
+        assert_eq!(run("read_line", &["3"]), "Ok(c)");
+        assert_eq!(run("wait", &["2"]), "Ok(0)");
+        assert_eq!(run("wait", &["3"]), "Ok(0)");
+    }
+}
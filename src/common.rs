@@ -1,14 +1,23 @@
 use copy_arena::Arena;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use termcolor::{BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
 
+use std::cell::RefCell;
+use std::env::consts;
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
+use std::rc::Rc;
 use std::slice;
 use std::str;
 
+use crate::audit::AuditLog;
+use crate::builtins::FEATURES;
+use crate::engine::NativeFunction;
+use crate::interrupt::Handlers;
 use crate::location::Location;
+use crate::messages::{Language, Message};
+use crate::primitives::Value;
 
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, Hash, PartialEq)]
 pub struct Symbol(u32);
@@ -20,10 +29,127 @@ impl Symbol {
     }
 }
 
+/// Bounds on the size of a program, so that pathological input is rejected
+/// with an error instead of exhausting memory or the native stack.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_depth: usize,
+    pub max_tokens: usize,
+    pub max_string_len: usize,
+    /// The most items `array_with_capacity`, `reserve` and `resize` can
+    /// make room for in one call.
+    pub max_array_len: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: 1024,
+            max_tokens: 1_000_000,
+            max_string_len: 65_536,
+            max_array_len: 16_777_216,
+        }
+    }
+}
+
+/// Stack left free before the passes go one level deeper into a program.
+const RED_ZONE: usize = 128 * 1024;
+/// Size of the stack segments added when that runs out.
+const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
+/// Runs `f`, on a new segment of stack if the current one is nearly used
+/// up. The parser, the checks, the compiler and the interpreter recurse
+/// once for each level of nesting in a program, and call this each time so
+/// that the limits above are all that bounds them, whatever thread they
+/// run on.
+pub fn grow_stack<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, f)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// The capabilities, like `fs`, that a script may use without asking.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Grants {
+    All,
+    Only(Vec<String>),
+}
+
+impl Grants {
+    pub fn allows(&self, capability: &str) -> bool {
+        match self {
+            Grants::All => true,
+            Grants::Only(granted) => granted.iter().any(|c| c == capability),
+        }
+    }
+
+    pub fn grant(&mut self, capability: &str) {
+        if let Grants::Only(granted) = self {
+            granted.push(capability.to_string());
+        }
+    }
+}
+
+/// An error or warning found in the source, to be reported to the user.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: Message,
+    pub location: Location,
+}
+
 pub struct Context {
     pub source_code: HashMap<PathBuf, String>,
     pub interner: StringInterner,
     pub debug_mode: bool,
+    pub strict_mode: bool,
+    pub limits: Limits,
+    pub language: Language,
+    pub grants: Grants,
+    /// Ask the user before a builtin uses a capability that is not granted,
+    /// instead of failing.
+    pub prompt: bool,
+    pub audit: Option<AuditLog>,
+    pub native_functions: HashMap<Symbol, Rc<NativeFunction>>,
+    /// Values set by the host, which scripts can read but not change.
+    pub constants: HashMap<Symbol, Value>,
+    /// Enums registered by the host. Their variants are constants written
+    /// `Enum.Variant`.
+    pub namespaces: HashSet<Symbol>,
+    /// Flags that `@cfg(key = "value")` attributes on declarations are
+    /// checked against. Hosts can add their own.
+    pub cfg: Vec<(String, String)>,
+    /// Shared with `on_signal` and `on_exit`, which add to it.
+    pub handlers: Rc<RefCell<Handlers>>,
+    /// Number of errors and warnings reported so far.
+    pub errors: usize,
+    pub warnings: usize,
+    /// Where diagnostics go instead of being shown, while `eval` runs code
+    /// whose errors are returned to the script.
+    pub collected: Option<Vec<Diagnostic>>,
+    /// How many times each kind of instruction or syntax tree node ran,
+    /// counted while it is set, for `--stats`.
+    pub profile: Option<HashMap<&'static str, usize>>,
+    /// Close what builtins opened for scripts, like processes, sockets and
+    /// temporary files, so that the next script starts without them.
+    pub releases: Vec<Box<dyn Fn()>>,
+}
+
+/// The platform blixt runs on, and the language features it has.
+fn default_cfg() -> Vec<(String, String)> {
+    let mut cfg = vec![
+        ("os".to_string(), consts::OS.to_string()),
+        ("arch".to_string(), consts::ARCH.to_string()),
+        ("family".to_string(), consts::FAMILY.to_string()),
+    ];
+    for feature in FEATURES {
+        cfg.push(("feature".to_string(), feature.to_string()));
+    }
+    cfg
 }
 
 impl Context {
@@ -32,77 +158,169 @@ impl Context {
             source_code: HashMap::default(),
             interner: StringInterner::new(),
             debug_mode: false,
+            strict_mode: false,
+            limits: Limits::default(),
+            language: Language::default(),
+            grants: Grants::All,
+            prompt: false,
+            audit: None,
+            native_functions: HashMap::default(),
+            constants: HashMap::default(),
+            namespaces: HashSet::default(),
+            cfg: default_cfg(),
+            handlers: Rc::default(),
+            errors: 0,
+            warnings: 0,
+            collected: None,
+            profile: None,
+            releases: vec![],
         }
     }
 
-    pub fn report_error(&mut self, message: &str, location: Location) {
+    /// Closes the processes, sockets and other things scripts have open
+    /// through builtins.
+    pub fn release(&self) {
+        for release in &self.releases {
+            release();
+        }
+    }
+
+    /// Counts a run of `kind` of instruction or node, when profiling.
+    pub fn profile(&mut self, kind: &'static str) {
+        if let Some(profile) = &mut self.profile {
+            *profile.entry(kind).or_insert(0) += 1;
+        }
+    }
+
+    pub fn report_error(&mut self, message: Message, location: Location) {
+        self.report_all(vec![Diagnostic {
+            severity: Severity::Error,
+            message,
+            location,
+        }]);
+    }
+
+    pub fn report_warning(&mut self, message: Message, location: Location) {
+        self.report_all(vec![Diagnostic {
+            severity: Severity::Warning,
+            message,
+            location,
+        }]);
+    }
+
+    /// Reports `diagnostics` in the order they appear in the source.
+    pub fn report_all(&mut self, mut diagnostics: Vec<Diagnostic>) {
+        diagnostics.sort_by_key(|diagnostic| diagnostic.location.span.start);
+        if let Some(collected) = &mut self.collected {
+            collected.append(&mut diagnostics);
+            return;
+        }
+
+        for diagnostic in &diagnostics {
+            match diagnostic.severity {
+                Severity::Error => self.errors += 1,
+                Severity::Warning => self.warnings += 1,
+            }
+            self.report(diagnostic);
+        }
+
+        if self.debug_mode
+            && diagnostics
+                .iter()
+                .any(|diagnostic| diagnostic.severity == Severity::Error)
+        {
+            panic!();
+        }
+    }
+
+    fn report(&mut self, diagnostic: &Diagnostic) {
+        let (label, color) = match diagnostic.severity {
+            Severity::Error => (self.language.error(), Color::Red),
+            Severity::Warning => (self.language.warning(), Color::Yellow),
+        };
+        let message = diagnostic.message.text(self.language);
+        let location = diagnostic.location;
+
         let filename = self.interner.get(location.file);
 
+        // A file that can't be read is shown without its source line.
         let source = self
             .source_code
             .entry(PathBuf::from(filename))
-            .or_insert_with(|| fs::read_to_string(filename).unwrap());
+            .or_insert_with(|| {
+                fs::read_to_string(filename).unwrap_or_default()
+            });
 
         let stderr = BufferWriter::stderr(ColorChoice::Always);
         let mut buf = stderr.buffer();
 
         let line = source
             .lines()
-            .nth((location.line - 1) as usize)
-            .expect("Invalid line");
-        let prelude = format!("{}: Line {}: ", filename, location.line);
+            .nth(location.line.saturating_sub(1) as usize)
+            .unwrap_or("");
+        let column = location.column(source.as_bytes());
+        let prelude = format!("{}:{}:{}: ", filename, location.line, column);
+        let gutter = " ".repeat(label.len() + 2);
 
-        writeln!(&mut buf, "       | {}{}", prelude, line).unwrap();
+        writeln!(&mut buf, "{}| {}{}", gutter, prelude, line).unwrap();
 
         if buf.supports_color() {
-            buf.set_color(
-                &ColorSpec::new().set_bold(true).set_fg(Some(Color::Red)),
-            )
-            .unwrap();
-            write!(&mut buf, "Error: ").unwrap();
+            buf.set_color(ColorSpec::new().set_bold(true).set_fg(Some(color)))
+                .unwrap();
+            write!(&mut buf, "{}: ", label).unwrap();
             buf.reset().unwrap();
         } else {
-            write!(&mut buf, "Error: ").unwrap();
+            write!(&mut buf, "{}: ", label).unwrap();
         }
 
-        write!(&mut buf, "|").unwrap();
+        write!(&mut buf, "| {}", " ".repeat(prelude.len())).unwrap();
 
-        for _ in 0..(prelude.len() as u32 + location.span.start) {
-            write!(&mut buf, " ").unwrap();
+        // Keep tabs so that the underline lines up with the source line.
+        let before = &line.as_bytes()
+            [..line.len().min(column.saturating_sub(1) as usize)];
+        for &b in before {
+            write!(&mut buf, "{}", if b == b'\t' { '\t' } else { ' ' })
+                .unwrap();
         }
 
+        // Locations joined across lines are only underlined to the end of
+        // the first one.
+        let underline = location
+            .span
+            .len
+            .min((line.len() - before.len()) as u32)
+            .max(1);
+
         if buf.supports_color() {
             buf.set_color(
-                &ColorSpec::new().set_bold(true).set_fg(Some(Color::Blue)),
+                ColorSpec::new().set_bold(true).set_fg(Some(Color::Blue)),
             )
             .unwrap();
 
-            for _ in 0..location.span.len {
-                write!(&mut buf, "^").unwrap();
-            }
+            write!(&mut buf, "{}", "^".repeat(underline as usize)).unwrap();
 
             buf.reset().unwrap();
         } else {
-            for _ in 0..location.span.len {
-                write!(&mut buf, "^").unwrap();
-            }
+            write!(&mut buf, "{}", "^".repeat(underline as usize)).unwrap();
         }
 
         writeln!(&mut buf).unwrap();
 
         if buf.supports_color() {
-            buf.set_color(&ColorSpec::new().set_bold(true)).unwrap();
-            writeln!(&mut buf, "       | {}\n\n", message).unwrap();
+            buf.set_color(ColorSpec::new().set_bold(true)).unwrap();
+            writeln!(&mut buf, "{}| {}\n\n", gutter, message).unwrap();
             buf.reset().unwrap();
         } else {
-            writeln!(&mut buf, "       | {}\n\n", message).unwrap();
+            writeln!(&mut buf, "{}| {}\n\n", gutter, message).unwrap();
         }
 
-        stderr.print(&buf).unwrap();
+        let _ = stderr.print(&buf);
+    }
+}
 
-        if self.debug_mode {
-            panic!();
-        }
+impl Default for Context {
+    fn default() -> Self {
+        Context::new()
     }
 }
 
@@ -141,6 +359,12 @@ impl StringInterner {
     }
 
     pub fn get(&self, string: Symbol) -> &str {
-        &self.strings[string.0 as usize]
+        self.strings[string.0 as usize]
+    }
+}
+
+impl Default for StringInterner {
+    fn default() -> Self {
+        StringInterner::new()
     }
 }
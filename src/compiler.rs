@@ -0,0 +1,934 @@
+use std::mem;
+
+use log::trace;
+
+use crate::ast::{
+    Assignment, AssignmentKind, Ast, AstNodeId, BinaryOp, BinaryOpKind, Decl,
+    Expr, ExprKind, For, ForIter, FunctionCall, FunctionDecl, If, Print,
+    Return, Stmt, StmtList, UnaryOpKind, VarDecl, While,
+};
+use crate::builtins::Builtin;
+use crate::common::{grow_stack, Context, Symbol};
+use crate::location::Location;
+use crate::primitives::{self, Callee, Value};
+
+/// A program lowered to bytecode. The first function is the top level of
+/// the program.
+pub struct Program {
+    pub constants: Vec<Value>,
+    pub functions: Vec<Function>,
+}
+
+pub struct Function {
+    pub name: Symbol,
+    pub params: u32,
+    /// Number of local variable slots, parameters included.
+    pub slots: u32,
+    pub code: Vec<Op>,
+    /// Where in the source each instruction comes from, for errors. Only
+    /// instructions that are part of an expression or statement have one.
+    pub locations: Vec<Option<Location>>,
+}
+
+/// Where a variable is stored. Variables declared at the top level of the
+/// program are globals, which functions look up by name when they are
+/// called, everything else is a slot in the frame of the running function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Place {
+    Local(u32),
+    Global(Symbol),
+}
+
+/// Instructions for a stack machine. Operands are popped in the order they
+/// were pushed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Pushes `constants[i]`.
+    Constant(u32),
+    Nil,
+    Pop,
+    Load(Place),
+    /// Pops a value into a variable that is already declared.
+    Store(Place),
+    /// Pops a value into a new global, shadowing any with the same name.
+    DefineGlobal(Symbol),
+    /// Removes the latest global with the name, when its block ends.
+    DropGlobal(Symbol),
+    /// Pops a value and then `indices` indices, and stores the value in the
+    /// element of the variable they lead to, combined with the current
+    /// value by `op` for compound assignments.
+    Assign {
+        place: Place,
+        indices: u32,
+        op: Option<BinaryOpKind>,
+    },
+    /// Pops `args` arguments and then `indices` indices, and runs the
+    /// builtin on the array in the variable the indices lead to.
+    ChangeArray {
+        builtin: Builtin,
+        place: Place,
+        indices: u32,
+        args: u32,
+    },
+    /// Any binary operator except `&&` and `||`, which are jumps.
+    Binary(BinaryOpKind),
+    Not,
+    Neg,
+    /// Replaces the value on top with whether it is truthy.
+    Truthy,
+    Jump(u32),
+    /// Pops a condition and jumps if it is not truthy.
+    JumpIfFalse(u32),
+    /// Jumps back to the start of a loop, stopping if Ctrl-C was pressed.
+    Loop(u32),
+    Call {
+        func: u32,
+        args: u32,
+    },
+    CallNative {
+        name: Symbol,
+        args: u32,
+    },
+    Builtin {
+        builtin: Builtin,
+        args: u32,
+    },
+    Return,
+    MakeOk,
+    MakeErr,
+    /// Unwraps an `Ok`, or returns an `Err` from the running function.
+    Try,
+    Array(u32),
+    /// Pops `n` values and joins them into a string the way `print` shows
+    /// them.
+    Concat(u32),
+    /// Pops `n` key and value pairs.
+    Map(u32),
+    Index,
+    Print {
+        args: u32,
+        newline: bool,
+    },
+    Input {
+        message: bool,
+    },
+    /// Replaces an array or map with the items a for loop goes over, the
+    /// elements of the array or the keys of the map.
+    IntoIter,
+    /// Pushes the next item of the loop over the items in slot `items` and
+    /// advances the position in slot `index`, or jumps to `exit` when there
+    /// are no more. The items of a loop over a range is the end of it.
+    ForNext {
+        items: u32,
+        index: u32,
+        exit: u32,
+    },
+    /// Replaces an array of `n` elements with the elements, the last on
+    /// top.
+    Unpack(u32),
+}
+
+impl Op {
+    /// The kind of instruction, as `--stats` counts it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Constant(_) => "Constant",
+            Op::Nil => "Nil",
+            Op::Pop => "Pop",
+            Op::Load(_) => "Load",
+            Op::Store(_) => "Store",
+            Op::DefineGlobal(_) => "DefineGlobal",
+            Op::DropGlobal(_) => "DropGlobal",
+            Op::Assign { .. } => "Assign",
+            Op::ChangeArray { .. } => "ChangeArray",
+            Op::Binary(_) => "Binary",
+            Op::Not => "Not",
+            Op::Neg => "Neg",
+            Op::Truthy => "Truthy",
+            Op::Jump(_) => "Jump",
+            Op::JumpIfFalse(_) => "JumpIfFalse",
+            Op::Loop(_) => "Loop",
+            Op::Call { .. } => "Call",
+            Op::CallNative { .. } => "CallNative",
+            Op::Builtin { .. } => "Builtin",
+            Op::Return => "Return",
+            Op::MakeOk => "MakeOk",
+            Op::MakeErr => "MakeErr",
+            Op::Try => "Try",
+            Op::Array(_) => "Array",
+            Op::Concat(_) => "Concat",
+            Op::Map(_) => "Map",
+            Op::Index => "Index",
+            Op::Print { .. } => "Print",
+            Op::Input { .. } => "Input",
+            Op::IntoIter => "IntoIter",
+            Op::ForNext { .. } => "ForNext",
+            Op::Unpack(_) => "Unpack",
+        }
+    }
+}
+
+/// Lowers a typechecked program to bytecode.
+pub fn compile(ast: &Ast, context: &mut Context) -> Program {
+    let main = context.interner.intern("<main>");
+    let mut compiler = Compiler {
+        ast,
+        context,
+        constants: Vec::new(),
+        functions: Vec::new(),
+        names: Vec::new(),
+        globals: Vec::new(),
+        func: FunctionState::new(main, 0, true),
+    };
+
+    compiler.functions.push(None);
+    compiler.compile_stmt_list(&ast.statements);
+    let main = compiler.finish_function();
+    compiler.functions[0] = Some(main);
+
+    Program {
+        constants: compiler.constants,
+        functions: compiler
+            .functions
+            .into_iter()
+            .map(|func| func.expect("Every declared function is compiled"))
+            .collect(),
+    }
+}
+
+struct Compiler<'a> {
+    ast: &'a Ast,
+    context: &'a mut Context,
+    constants: Vec<Value>,
+    /// Filled in as function bodies are compiled.
+    functions: Vec<Option<Function>>,
+    /// The functions that calls can refer to, innermost last.
+    names: Vec<(Symbol, u32)>,
+    /// The globals in scope where the function being compiled is declared.
+    /// A function of the same name is hidden by them.
+    globals: Vec<Symbol>,
+    func: FunctionState,
+}
+
+/// The function being compiled.
+struct FunctionState {
+    name: Symbol,
+    params: u32,
+    /// Whether this is the top level, where variables are globals.
+    top_level: bool,
+    /// Variables in slots, innermost last. Slots used by the compiler
+    /// itself have no name.
+    locals: Vec<(Option<Symbol>, usize)>,
+    globals: Vec<(Symbol, usize)>,
+    depth: usize,
+    loops: Vec<LoopState>,
+    slots: u32,
+    code: Vec<Op>,
+    locations: Vec<Option<Location>>,
+    location: Option<Location>,
+}
+
+impl FunctionState {
+    fn new(name: Symbol, params: u32, top_level: bool) -> Self {
+        FunctionState {
+            name,
+            params,
+            top_level,
+            locals: Vec::new(),
+            globals: Vec::new(),
+            depth: 0,
+            loops: Vec::new(),
+            slots: 0,
+            code: Vec::new(),
+            locations: Vec::new(),
+            location: None,
+        }
+    }
+}
+
+/// A loop being compiled, for `break` and `continue`.
+struct LoopState {
+    /// Where `continue` jumps to.
+    start: u32,
+    /// The scope depth outside the body. Globals declared deeper are
+    /// dropped when leaving the body early.
+    depth: usize,
+    /// The jumps of every `break`, patched to the end of the loop.
+    breaks: Vec<usize>,
+}
+
+impl<'a> Compiler<'a> {
+    fn emit(&mut self, op: Op) -> usize {
+        self.func.code.push(op);
+        self.func.locations.push(self.func.location);
+        self.func.code.len() - 1
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.func.code.len() as u32;
+        match &mut self.func.code[at] {
+            Op::Jump(to) | Op::JumpIfFalse(to) => *to = target,
+            Op::ForNext { exit, .. } => *exit = target,
+            op => unreachable!("Cannot patch {:?}", op),
+        }
+    }
+
+    fn constant(&mut self, value: Value) {
+        self.constants.push(value);
+        self.emit(Op::Constant(self.constants.len() as u32 - 1));
+    }
+
+    fn finish_function(&mut self) -> Function {
+        self.emit(Op::Nil);
+        self.emit(Op::Return);
+
+        Function {
+            name: self.func.name,
+            params: self.func.params,
+            slots: self.func.slots,
+            code: mem::take(&mut self.func.code),
+            locations: mem::take(&mut self.func.locations),
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.func.depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let depth = self.func.depth;
+        while let Some((name, _)) = self
+            .func
+            .globals
+            .last()
+            .filter(|(_, d)| *d == depth)
+            .copied()
+        {
+            self.func.globals.pop();
+            self.emit(Op::DropGlobal(name));
+        }
+
+        let func = &mut self.func;
+        func.locals.retain(|(_, d)| *d != depth);
+        func.depth -= 1;
+    }
+
+    fn add_local(&mut self, name: Option<Symbol>) -> u32 {
+        self.func.locals.push((name, self.func.depth));
+        let slot = self.func.locals.len() as u32 - 1;
+        self.func.slots = self.func.slots.max(slot + 1);
+        slot
+    }
+
+    /// Pops the value on top into a new variable.
+    fn declare(&mut self, name: Symbol) {
+        if self.func.top_level {
+            self.func.globals.push((name, self.func.depth));
+            self.emit(Op::DefineGlobal(name));
+        } else {
+            let slot = self.add_local(Some(name));
+            self.emit(Op::Store(Place::Local(slot)));
+        }
+    }
+
+    fn resolve(&self, name: Symbol) -> Place {
+        self.func
+            .locals
+            .iter()
+            .rposition(|(local, _)| *local == Some(name))
+            .map_or(Place::Global(name), |slot| Place::Local(slot as u32))
+    }
+
+    fn compile_stmt_list(&mut self, stmts: &StmtList) {
+        trace!("stmt_list");
+
+        let names = self.names.len();
+
+        // Functions can be called before they are declared.
+        for stmt in stmts {
+            if let Stmt::Decl(Decl::Function(func)) = &self.ast.arena[*stmt] {
+                self.functions.push(None);
+                let index = self.functions.len() as u32 - 1;
+                self.names.push((func.name, index));
+            }
+        }
+
+        for stmt in stmts {
+            self.compile_stmt(&self.ast.arena[*stmt]);
+        }
+
+        self.names.truncate(names);
+    }
+
+    /// The index of the function that `name` refers to, if any.
+    fn function(&self, name: Symbol) -> Option<u32> {
+        self.names
+            .iter()
+            .rev()
+            .find(|(func, _)| *func == name)
+            .map(|(_, index)| *index)
+    }
+
+    fn compile_function(&mut self, decl: &FunctionDecl) -> Function {
+        trace!("Function");
+
+        let state =
+            FunctionState::new(decl.name, decl.params.len() as u32, false);
+        let outer = mem::replace(&mut self.func, state);
+        let globals = if outer.top_level {
+            let names = outer.globals.iter().map(|(name, _)| *name).collect();
+            Some(mem::replace(&mut self.globals, names))
+        } else {
+            None
+        };
+
+        for param in &decl.params {
+            self.add_local(Some(self.ast.arena[*param].param().name));
+        }
+        self.compile_stmt_list(&decl.body);
+
+        let function = self.finish_function();
+        self.func = outer;
+        if let Some(globals) = globals {
+            self.globals = globals;
+        }
+        function
+    }
+
+    fn compile_block(&mut self, stmts: &StmtList) {
+        self.begin_scope();
+        self.compile_stmt_list(stmts);
+        self.end_scope();
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt) {
+        grow_stack(|| {
+            match stmt {
+                Stmt::Assignment(v) => self.compile_assignment(v),
+                Stmt::Block(v) => self.compile_block(v),
+                Stmt::Decl(Decl::Variable(v)) => self.compile_var_decl(v),
+                // Compiled where it is declared, so that it sees the globals
+                // declared before it.
+                Stmt::Decl(Decl::Function(v)) => {
+                    let index = self.function(v.name).expect("Declared above");
+                    let function = self.compile_function(v);
+                    self.functions[index as usize] = Some(function);
+                }
+                Stmt::Decl(Decl::Struct(_)) => {
+                    unreachable!("Structs are rejected by the typechecker")
+                }
+                Stmt::Expr(v) => {
+                    self.compile_expr(v);
+                    self.emit(Op::Pop);
+                }
+                Stmt::For(v) => self.compile_for(v),
+                Stmt::While(v) => self.compile_while(v),
+                Stmt::Print(v) => self.compile_print(v),
+                Stmt::If(v) => self.compile_if(v),
+                Stmt::Return(v) => self.compile_return(v),
+                Stmt::Break(location) => self.compile_break(*location),
+                Stmt::Continue(location) => self.compile_continue(*location),
+                Stmt::Param(_) => unreachable!(),
+            }
+        })
+    }
+
+    fn compile_var_decl(&mut self, var: &VarDecl) {
+        trace!("Var decl");
+
+        self.compile(var.value);
+        self.declare(var.name);
+    }
+
+    fn compile_assignment(&mut self, assignment: &Assignment) {
+        trace!("Assignment");
+
+        self.compile(assignment.value);
+        for index in &assignment.indices {
+            self.compile(*index);
+        }
+
+        let op = match assignment.op {
+            AssignmentKind::Assign => None,
+            AssignmentKind::Add => Some(BinaryOpKind::Add),
+            AssignmentKind::Sub => Some(BinaryOpKind::Sub),
+            AssignmentKind::Mul => Some(BinaryOpKind::Mul),
+            AssignmentKind::Div => Some(BinaryOpKind::Div),
+            AssignmentKind::Mod => Some(BinaryOpKind::Mod),
+        };
+
+        let place = self.resolve(assignment.ident);
+        self.func.location = Some(assignment.location);
+        self.emit(Op::Assign {
+            place,
+            indices: assignment.indices.len() as u32,
+            op,
+        });
+    }
+
+    fn compile_if(&mut self, if_stmt: &If) {
+        trace!("If");
+
+        self.compile(if_stmt.cond);
+        let else_jump = self.emit(Op::JumpIfFalse(0));
+        self.compile_block(&if_stmt.body);
+
+        match &if_stmt.else_body {
+            Some(else_body) => {
+                let end_jump = self.emit(Op::Jump(0));
+                self.patch(else_jump);
+                self.compile_block(else_body);
+                self.patch(end_jump);
+            }
+            None => self.patch(else_jump),
+        }
+    }
+
+    fn compile_while(&mut self, while_loop: &While) {
+        trace!("While");
+
+        let start = self.func.code.len() as u32;
+        self.compile(while_loop.cond);
+        let exit = self.emit(Op::JumpIfFalse(0));
+        self.compile_loop_body(start, &while_loop.body);
+        self.patch(exit);
+        self.patch_breaks();
+    }
+
+    fn compile_for(&mut self, for_loop: &For) {
+        trace!("For");
+
+        self.begin_scope();
+        let items = self.add_local(None);
+        let index = self.add_local(None);
+
+        match &for_loop.iter {
+            ForIter::Range(range) => {
+                self.constant(Value::Int(range.end));
+                self.emit(Op::Store(Place::Local(items)));
+                self.constant(Value::Int(range.start));
+            }
+            ForIter::Expr(node) => {
+                self.compile(*node);
+                self.func.location = Some(for_loop.location);
+                self.emit(Op::IntoIter);
+                self.emit(Op::Store(Place::Local(items)));
+                self.constant(Value::Int(0));
+            }
+        }
+        self.emit(Op::Store(Place::Local(index)));
+
+        let names: Vec<_> = std::iter::once(&for_loop.ident)
+            .chain(&for_loop.unpack)
+            .collect();
+        for name in &names {
+            self.emit(Op::Nil);
+            self.declare(**name);
+        }
+
+        let start = self.func.code.len() as u32;
+        let next = self.emit(Op::ForNext {
+            items,
+            index,
+            exit: 0,
+        });
+        if names.len() > 1 {
+            self.func.location = Some(for_loop.location);
+            self.emit(Op::Unpack(names.len() as u32));
+        }
+        for name in names.iter().rev() {
+            let variable = self.resolve(**name);
+            self.emit(Op::Store(variable));
+        }
+        self.compile_loop_body(start, &for_loop.block);
+        self.patch(next);
+        self.patch_breaks();
+
+        self.end_scope();
+    }
+
+    /// Compiles the body of a loop that starts at `start`, followed by the
+    /// jump back to it. The breaks are left for `patch_breaks`.
+    fn compile_loop_body(&mut self, start: u32, body: &StmtList) {
+        self.func.loops.push(LoopState {
+            start,
+            depth: self.func.depth,
+            breaks: Vec::new(),
+        });
+        self.compile_block(body);
+        self.emit(Op::Loop(start));
+    }
+
+    fn patch_breaks(&mut self) {
+        let state = self.func.loops.pop().expect("Not in a loop");
+        for at in state.breaks {
+            self.patch(at);
+        }
+    }
+
+    /// Drops the globals declared in the body of the innermost loop, which
+    /// are still in scope where `break` or `continue` is.
+    fn leave_loop_body(&mut self) {
+        let depth = self.func.loops.last().expect("Not in a loop").depth;
+        let globals: Vec<_> = self
+            .func
+            .globals
+            .iter()
+            .rev()
+            .take_while(|(_, d)| *d > depth)
+            .map(|(name, _)| *name)
+            .collect();
+        for name in globals {
+            self.emit(Op::DropGlobal(name));
+        }
+    }
+
+    fn compile_break(&mut self, location: Location) {
+        trace!("Break");
+
+        self.func.location = Some(location);
+        self.leave_loop_body();
+        let jump = self.emit(Op::Jump(0));
+        self.func.loops.last_mut().unwrap().breaks.push(jump);
+    }
+
+    fn compile_continue(&mut self, location: Location) {
+        trace!("Continue");
+
+        self.func.location = Some(location);
+        self.leave_loop_body();
+        let start = self.func.loops.last().unwrap().start;
+        self.emit(Op::Loop(start));
+    }
+
+    fn compile_print(&mut self, print: &Print) {
+        trace!("Print");
+
+        for arg in &print.args {
+            self.compile(*arg);
+        }
+
+        if let Some(format) = print.args.first() {
+            self.func.location = Some(self.ast.arena[*format].expr().location);
+        }
+        self.emit(Op::Print {
+            args: print.args.len() as u32,
+            newline: print.newline,
+        });
+    }
+
+    fn compile_return(&mut self, ret: &Return) {
+        trace!("Return");
+
+        match ret.value {
+            Some(value) => self.compile(value),
+            None => {
+                self.emit(Op::Nil);
+            }
+        }
+
+        self.func.location = Some(ret.location);
+        self.emit(Op::Return);
+    }
+
+    fn compile(&mut self, node: AstNodeId) {
+        self.compile_expr(self.ast.arena[node].expr());
+    }
+
+    /// Instructions for the operands of an expression are emitted with
+    /// their own locations, and those for the expression itself with its
+    /// location.
+    fn compile_expr(&mut self, expr: &Expr) {
+        trace!("Expr");
+
+        grow_stack(|| {
+            let outer = self.func.location.replace(expr.location);
+
+            match &expr.kind {
+                ExprKind::Bool(v) => self.constant(Value::Bool(*v)),
+                ExprKind::Float(v) => self.constant(Value::Float(*v)),
+                ExprKind::Integer(v) => self.constant(Value::Int(*v)),
+                ExprKind::StringLiteral(v) => self.constant(Value::String(*v)),
+                ExprKind::Interpolation(v) => {
+                    for part in v {
+                        self.compile(*part);
+                    }
+                    self.func.location = Some(expr.location);
+                    self.emit(Op::Concat(v.len() as u32));
+                }
+                ExprKind::Ident(v) => self.compile_ident(*v),
+                ExprKind::Range(_) => unreachable!(
+                    "Ranges outside for loops are rejected by the typechecker"
+                ),
+                ExprKind::Input(v) => {
+                    if let Some(message) = v.message {
+                        self.compile(message);
+                    }
+                    self.emit(Op::Input {
+                        message: v.message.is_some(),
+                    });
+                }
+                ExprKind::UnaryOp(v) => {
+                    self.compile(v.value);
+                    self.func.location = Some(expr.location);
+                    self.emit(match v.op {
+                        UnaryOpKind::Not => Op::Not,
+                        UnaryOpKind::Neg => Op::Neg,
+                    });
+                }
+                ExprKind::BinaryOp(v) => self.compile_binop(v, expr.location),
+                ExprKind::FunctionCall(v) => {
+                    self.compile_call(v, expr.location)
+                }
+                ExprKind::Try(v) => {
+                    self.compile(v.value);
+                    self.func.location = Some(expr.location);
+                    self.emit(Op::Try);
+                }
+                ExprKind::Array(v) => {
+                    for element in v {
+                        self.compile(*element);
+                    }
+                    self.func.location = Some(expr.location);
+                    self.emit(Op::Array(v.len() as u32));
+                }
+                ExprKind::Map(v) => {
+                    for (key, value) in v {
+                        self.compile(*key);
+                        self.compile(*value);
+                    }
+                    self.func.location = Some(expr.location);
+                    self.emit(Op::Map(v.len() as u32));
+                }
+                ExprKind::Index(v) => {
+                    self.compile(v.value);
+                    self.compile(v.index);
+                    self.func.location = Some(expr.location);
+                    self.emit(Op::Index);
+                }
+            }
+
+            self.func.location = outer;
+        })
+    }
+
+    /// Loads a variable, or the function with the name if there is no
+    /// such variable.
+    fn compile_ident(&mut self, name: Symbol) {
+        let place = self.resolve(name);
+        let is_variable = match place {
+            Place::Local(_) => true,
+            Place::Global(_) if self.func.top_level => {
+                self.func.globals.iter().any(|(global, _)| *global == name)
+            }
+            Place::Global(_) => self.globals.contains(&name),
+        };
+
+        let callee = match self.function(name) {
+            _ if is_variable => None,
+            Some(index) => Some(Callee::Compiled(index)),
+            None if self.context.native_functions.contains_key(&name) => {
+                Some(Callee::Native)
+            }
+            None => None,
+        };
+
+        if let (false, Some(value)) =
+            (is_variable, self.context.constants.get(&name))
+        {
+            self.constant(value.clone());
+            return;
+        }
+
+        match callee {
+            Some(callee) => {
+                let func = primitives::Function { name, callee };
+                self.constant(Value::Function(func));
+            }
+            None => {
+                self.emit(Op::Load(place));
+            }
+        }
+    }
+
+    fn compile_binop(&mut self, binop: &BinaryOp, location: Location) {
+        trace!("Binop");
+
+        // The logical operators short circuit, so the right hand side is
+        // only evaluated when needed.
+        match binop.op {
+            BinaryOpKind::And => {
+                self.compile(binop.lhs);
+                let short = self.emit(Op::JumpIfFalse(0));
+                self.compile(binop.rhs);
+                self.emit(Op::Truthy);
+                let end = self.emit(Op::Jump(0));
+                self.patch(short);
+                self.constant(Value::Bool(false));
+                self.patch(end);
+            }
+            BinaryOpKind::Or => {
+                self.compile(binop.lhs);
+                let rhs = self.emit(Op::JumpIfFalse(0));
+                self.constant(Value::Bool(true));
+                let end = self.emit(Op::Jump(0));
+                self.patch(rhs);
+                self.compile(binop.rhs);
+                self.emit(Op::Truthy);
+                self.patch(end);
+            }
+            BinaryOpKind::Field => {
+                unreachable!("Field access is rejected by the typechecker")
+            }
+            op => {
+                self.compile(binop.lhs);
+                self.compile(binop.rhs);
+                self.func.location = Some(location);
+                self.emit(Op::Binary(op));
+            }
+        }
+    }
+
+    fn compile_call(&mut self, call: &FunctionCall, location: Location) {
+        trace!("Function call");
+
+        let name = self.context.interner.get(call.name);
+        let args = call.args.len() as u32;
+
+        if name == "Ok" || name == "Err" {
+            let op = if name == "Ok" {
+                Op::MakeOk
+            } else {
+                Op::MakeErr
+            };
+            self.compile(call.args[0]);
+            self.func.location = Some(location);
+            self.emit(op);
+            return;
+        }
+
+        // Functions declared by the script take precedence over builtins.
+        let func = self.function(call.name);
+        if let Some(builtin) =
+            Builtin::from_name(name).filter(|_| func.is_none())
+        {
+            if builtin.changes_array() {
+                self.compile_change_array(builtin, call, location);
+                return;
+            }
+
+            for arg in &call.args {
+                self.compile(*arg);
+            }
+            self.func.location = Some(location);
+            self.emit(Op::Builtin { builtin, args });
+            return;
+        }
+
+        for arg in &call.args {
+            self.compile(*arg);
+        }
+        self.func.location = Some(location);
+
+        match func {
+            Some(func) => self.emit(Op::Call { func, args }),
+            None => self.emit(Op::CallNative {
+                name: call.name,
+                args,
+            }),
+        };
+    }
+
+    /// `push(xs[i], value)`, `pop(xs[i])` and the like change the array in
+    /// place, so the first argument is compiled as somewhere to store a
+    /// value.
+    fn compile_change_array(
+        &mut self,
+        builtin: Builtin,
+        call: &FunctionCall,
+        location: Location,
+    ) {
+        let mut node = call.args[0];
+        let mut indices = Vec::new();
+        let ident = loop {
+            match &self.ast.arena[node].expr().kind {
+                ExprKind::Ident(ident) => break *ident,
+                ExprKind::Index(index) => {
+                    indices.push(index.index);
+                    node = index.value;
+                }
+                _ => unreachable!("Checked by typechecker"),
+            }
+        };
+
+        for index in indices.iter().rev() {
+            self.compile(*index);
+        }
+        for arg in &call.args[1..] {
+            self.compile(*arg);
+        }
+
+        let place = self.resolve(ident);
+        self.func.location = Some(location);
+        self.emit(Op::ChangeArray {
+            builtin,
+            place,
+            indices: indices.len() as u32,
+            args: call.args.len() as u32 - 1,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::lexer;
+    use crate::parser;
+
+    fn compile_source(source: &str) -> (Program, Context) {
+        let mut context = Context::new();
+        let file = context.interner.intern("test.bl");
+        context
+            .source_code
+            .insert(PathBuf::from("test.bl"), source.to_string());
+
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+        let ast = parser::parse_ast(tokens, &mut context).unwrap();
+        let program = compile(&ast, &mut context);
+
+        (program, context)
+    }
+
+    #[test]
+    fn locals_use_slots() {
+        let (program, mut context) = compile_source(
+            "a := 1 fn f(n: int) -> int { b := n { c := b } d := 2 return a }",
+        );
+        let a = context.interner.intern("a");
+
+        assert_eq!(program.functions.len(), 2);
+        assert_eq!(program.functions[0].code[1], Op::DefineGlobal(a));
+
+        let f = &program.functions[1];
+        assert_eq!(f.params, 1);
+        assert_eq!(f.slots, 3);
+        assert!(f.code.contains(&Op::Store(Place::Local(2))));
+        assert!(f.code.contains(&Op::Load(Place::Global(a))));
+    }
+
+    #[test]
+    fn functions_declared_later() {
+        let (program, _) = compile_source(
+            "fn f() -> int { return g() } fn g() -> int { return 1 } f()",
+        );
+
+        assert_eq!(program.functions[1].code[0], Op::Call { func: 2, args: 0 });
+        assert_eq!(program.functions[0].code[0], Op::Call { func: 1, args: 0 });
+    }
+}
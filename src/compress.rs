@@ -0,0 +1,79 @@
+use std::io::{self, Read, Write};
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use ruzstd::decoding::StreamingDecoder;
+use ruzstd::encoding::{self, CompressionLevel};
+
+/// Compresses `bytes` into the gzip format at the default level.
+pub fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(bytes)
+        .and_then(|_| encoder.finish())
+        .expect("Writing to memory does not fail")
+}
+
+/// Decompresses gzip data. Several gzip members one after another, as in
+/// logs that were appended to while compressed, come out as one.
+pub fn gunzip(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    MultiGzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// Compresses `bytes` into a Zstandard frame, at about the speed of level
+/// 1 of the `zstd` tool.
+pub fn zstd(bytes: &[u8]) -> Vec<u8> {
+    encoding::compress_to_vec(bytes, CompressionLevel::Fastest)
+}
+
+/// Decompresses Zstandard data, which may be several frames one after
+/// another. Returns the reason if it is not valid.
+pub fn unzstd(mut bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decompressed = Vec::new();
+    while !bytes.is_empty() {
+        let mut decoder =
+            StreamingDecoder::new(&mut bytes).map_err(|e| e.to_string())?;
+        decoder
+            .read_to_end(&mut decompressed)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gzip_round_trips() {
+        let text = b"GET /index.html 200\n".repeat(100);
+        let compressed = gzip(&text);
+        assert!(compressed.len() < text.len() / 10);
+        assert_eq!(gunzip(&compressed).unwrap(), text);
+
+        let mut appended = gzip(b"a\n");
+        appended.extend(gzip(b"b\n"));
+        assert_eq!(gunzip(&appended).unwrap(), b"a\nb\n");
+
+        assert!(gunzip(b"not gzip").is_err());
+        assert_eq!(gunzip(&gzip(b"")).unwrap(), b"");
+    }
+
+    #[test]
+    fn zstd_round_trips() {
+        let text = b"GET /index.html 200\n".repeat(100);
+        let compressed = zstd(&text);
+        assert!(compressed.len() < text.len() / 10);
+        assert_eq!(unzstd(&compressed).unwrap(), text);
+
+        let mut appended = zstd(b"a\n");
+        appended.extend(zstd(b"b\n"));
+        assert_eq!(unzstd(&appended).unwrap(), b"a\nb\n");
+
+        assert!(unzstd(b"not zstd").is_err());
+        assert_eq!(unzstd(b"").unwrap(), b"");
+    }
+}
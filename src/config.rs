@@ -0,0 +1,344 @@
+use std::fmt;
+
+use crate::common::{Context, Grants, Limits};
+use crate::messages::Language;
+use crate::metadata::CAPABILITIES;
+
+/// The file the command line reads settings from, in the directory it is
+/// run in. Only its `[runtime]` section is read, so other tools can keep
+/// their settings in it too.
+pub const CONFIG_FILE: &str = "gentlemen.toml";
+
+/// Where a setting came from, in order of precedence: a setting from a
+/// later layer replaces one from an earlier layer, whatever order they
+/// are read in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    Default,
+    File,
+    Env,
+    Flag,
+}
+
+impl Layer {
+    fn describe(self) -> &'static str {
+        match self {
+            Layer::Default => "default",
+            Layer::File => CONFIG_FILE,
+            Layer::Env => "environment",
+            Layer::Flag => "command line",
+        }
+    }
+}
+
+/// The names of the settings, as written in the config file. Each can also
+/// be set with an environment variable named like `BLIXT_MAX_DEPTH`.
+pub const KEYS: &[&str] = &[
+    "max_depth",
+    "max_tokens",
+    "max_string_length",
+    "max_array_length",
+    "strict",
+    "lang",
+    "capabilities",
+];
+
+/// The settings scripts run with, merged from defaults, a config file,
+/// environment variables and command line flags.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub limits: Limits,
+    pub strict: bool,
+    pub language: Language,
+    /// Capabilities scripts may use without asking. The command line
+    /// grants none unless this is set, an engine every one.
+    pub capabilities: Vec<String>,
+    /// The layer each of `KEYS` was last set by.
+    layers: Vec<Layer>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            limits: Limits::default(),
+            strict: false,
+            language: Language::English,
+            capabilities: Vec::new(),
+            layers: vec![Layer::Default; KEYS.len()],
+        }
+    }
+}
+
+impl Config {
+    /// The layer `key` was last set by.
+    pub fn layer(&self, key: &str) -> Option<Layer> {
+        let i = KEYS.iter().position(|k| *k == key)?;
+        Some(self.layers[i])
+    }
+
+    /// Sets `key` from text like `100`, `true`, `sv` or `fs,net`, unless a
+    /// later layer has already set it.
+    pub fn set(
+        &mut self,
+        key: &str,
+        value: &str,
+        layer: Layer,
+    ) -> Result<(), String> {
+        let i = match KEYS.iter().position(|k| *k == key) {
+            Some(i) => i,
+            None => return Err(format!("Unknown setting {}", key)),
+        };
+        let invalid = |expected: &str| {
+            format!(
+                "Invalid value '{}' for {}, expected {}",
+                value, key, expected
+            )
+        };
+        let limit = || match value.parse() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(invalid("a positive number")),
+        };
+
+        if self.layers[i] > layer {
+            return Ok(());
+        }
+        match key {
+            "max_depth" => self.limits.max_depth = limit()?,
+            "max_tokens" => self.limits.max_tokens = limit()?,
+            "max_string_length" => self.limits.max_string_len = limit()?,
+            "max_array_length" => self.limits.max_array_len = limit()?,
+            "strict" => match value {
+                "true" => self.strict = true,
+                "false" => self.strict = false,
+                _ => return Err(invalid("true or false")),
+            },
+            "lang" => match Language::from_code(value) {
+                Some(language) => self.language = language,
+                None => return Err(invalid(&Language::CODES.join(" or "))),
+            },
+            _ => {
+                let capabilities: Vec<_> = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|c| !c.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if !capabilities.iter().all(|c| CAPABILITIES.contains(&&**c)) {
+                    let expected =
+                        format!("some of {}", CAPABILITIES.join(", "));
+                    return Err(invalid(&expected));
+                }
+                self.capabilities = capabilities;
+            }
+        }
+        self.layers[i] = layer;
+        Ok(())
+    }
+
+    /// Reads the `[runtime]` section of a config file in TOML, with one
+    /// `key = value` per line where the value is a number, a bool, a
+    /// string or an array of strings. Errors start with `file` and the
+    /// line.
+    pub fn read_file(&mut self, file: &str, text: &str) -> Result<(), String> {
+        let mut runtime = false;
+        for (i, line) in text.lines().enumerate() {
+            let error =
+                |message: String| format!("{}:{}: {}", file, i + 1, message);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                runtime = line == "[runtime]";
+                continue;
+            }
+            if !runtime {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or_default().trim();
+            let value = match parts.next().map(|v| toml_value(v.trim())) {
+                Some(Some(value)) => value,
+                _ => return Err(error(format!("Invalid line '{}'", line))),
+            };
+            self.set(key, &value, Layer::File).map_err(error)?;
+        }
+        Ok(())
+    }
+
+    /// Reads `BLIXT_` followed by a key in capitals, like `BLIXT_LANG`,
+    /// from `vars`. Other variables are left alone.
+    pub fn read_env(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), String> {
+        for (name, value) in vars {
+            let key = match name.strip_prefix("BLIXT_") {
+                Some(key) => key.to_lowercase(),
+                None => continue,
+            };
+            if KEYS.contains(&key.as_str()) {
+                self.set(&key, &value, Layer::Env)
+                    .map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets up `context` to run scripts with these settings.
+    pub fn apply(&self, context: &mut Context) {
+        context.limits = self.limits;
+        context.strict_mode = self.strict;
+        context.language = self.language;
+        context.grants = match self.layer("capabilities") {
+            Some(Layer::Default) => Grants::All,
+            _ => Grants::Only(self.capabilities.clone()),
+        };
+    }
+}
+
+/// The settings as a `[runtime]` section, with where each one came from.
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let capabilities: Vec<_> = self
+            .capabilities
+            .iter()
+            .map(|c| format!("{:?}", c))
+            .collect();
+        let values = [
+            self.limits.max_depth.to_string(),
+            self.limits.max_tokens.to_string(),
+            self.limits.max_string_len.to_string(),
+            self.limits.max_array_len.to_string(),
+            self.strict.to_string(),
+            format!("{:?}", self.language.code()),
+            format!("[{}]", capabilities.join(", ")),
+        ];
+
+        writeln!(f, "[runtime]")?;
+        for ((key, value), layer) in KEYS.iter().zip(&values).zip(&self.layers)
+        {
+            let setting = format!("{} = {}", key, value);
+            writeln!(f, "{:<32}# {}", setting, layer.describe())?;
+        }
+        Ok(())
+    }
+}
+
+/// `line` without a `#` comment at the end, unless the `#` is in a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('#', None) => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// A TOML value as the text `Config::set` takes, with strings unquoted and
+/// the strings of an array joined by commas.
+fn toml_value(value: &str) -> Option<String> {
+    let unquote = |s: &str| {
+        let quoted = s.len() >= 2
+            && (s.starts_with('"') && s.ends_with('"')
+                || s.starts_with('\'') && s.ends_with('\''));
+        if quoted {
+            Some(s[1..s.len() - 1].to_string())
+        } else {
+            None
+        }
+    };
+
+    if value.starts_with('[') && value.ends_with(']') {
+        let items = value[1..value.len() - 1]
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(unquote)
+            .collect::<Option<Vec<_>>>()?;
+        return Some(items.join(","));
+    }
+    match unquote(value) {
+        Some(s) => Some(s),
+        None if !value.is_empty() && !value.contains(char::is_whitespace) => {
+            Some(value.to_string())
+        }
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_take_precedence_in_order() {
+        let mut config = Config::default();
+
+        config.set("max_depth", "10", Layer::Flag).unwrap();
+        let env = vec![
+            ("BLIXT_MAX_DEPTH".to_string(), "20".to_string()),
+            ("BLIXT_LANG".to_string(), "sv".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+        config.read_env(env).unwrap();
+        let file = "# settings\n\
+                    [other]\n\
+                    lang = 'ignored'\n\
+                    [runtime]\n\
+                    lang = \"en\"  # replaced by BLIXT_LANG\n\
+                    strict = true\n\
+                    capabilities = [\"fs\", \"net\"]\n";
+        config.read_file("gentlemen.toml", file).unwrap();
+
+        assert_eq!(config.limits.max_depth, 10);
+        assert_eq!(config.language, Language::Swedish);
+        assert!(config.strict);
+        assert_eq!(config.capabilities, ["fs", "net"]);
+        assert_eq!(config.layer("max_depth"), Some(Layer::Flag));
+        assert_eq!(config.layer("lang"), Some(Layer::Env));
+        assert_eq!(config.layer("strict"), Some(Layer::File));
+        assert_eq!(config.layer("max_tokens"), Some(Layer::Default));
+
+        let shown = config.to_string();
+        assert!(shown.starts_with("[runtime]\n"));
+        assert!(shown.contains("max_depth = 10"));
+        assert!(shown.contains("# command line\n"));
+        assert!(shown.contains("capabilities = [\"fs\", \"net\"]"));
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let mut config = Config::default();
+
+        assert!(config.set("max_depth", "0", Layer::Flag).is_err());
+        assert!(config.set("strict", "yes", Layer::Flag).is_err());
+        assert!(config.set("lang", "de", Layer::Flag).is_err());
+        assert!(config.set("capabilities", "fs,gpu", Layer::Flag).is_err());
+        assert!(config.set("speed", "1", Layer::Flag).is_err());
+
+        let error = config
+            .read_file("gentlemen.toml", "[runtime]\n\nmax_tokens = many\n")
+            .unwrap_err();
+        assert!(error.starts_with("gentlemen.toml:3: Invalid value 'many'"));
+        assert!(config.read_file("a.toml", "[runtime]\nstrict\n").is_err());
+    }
+
+    #[test]
+    fn grants() {
+        let mut context = Context::new();
+        let mut config = Config::default();
+
+        config.apply(&mut context);
+        assert_eq!(context.grants, Grants::All);
+
+        config.set("capabilities", "", Layer::Env).unwrap();
+        config.apply(&mut context);
+        assert_eq!(context.grants, Grants::Only(vec![]));
+    }
+}
@@ -0,0 +1,347 @@
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::builtins;
+use crate::common::Context;
+use crate::compiler;
+use crate::lexer;
+use crate::modules;
+use crate::parser;
+use crate::semantic;
+use crate::token::TokenKind;
+use crate::typecheck;
+
+/// The phases that can be run again on a smaller source without running
+/// the script, in order. A crash in a later phase is reported with the
+/// whole source, since running it again could repeat what it did.
+const PHASES: &[&str] = &["lex", "parse", "analyze", "typecheck", "compile"];
+
+/// How many smaller sources to try before settling for the smallest one
+/// found so far.
+const MAX_ATTEMPTS: usize = 500;
+
+/// What the thread running a script was doing, for the report if it
+/// panics.
+#[derive(Default)]
+struct State {
+    phase: Option<&'static str>,
+    file: Option<String>,
+    source: Option<String>,
+    /// The message and where in blixt the last panic happened.
+    panic: Option<(String, String)>,
+    /// Whether the panic hook should stay silent, while the source is
+    /// being minimized.
+    quiet: bool,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+/// Makes panics remember their message and location for `write_report`,
+/// before printing them as usual.
+pub fn install() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = info.payload().downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "Box<dyn Any>".to_string()
+        };
+        let location = info
+            .location()
+            .map_or(String::new(), |l| format!("{}:{}", l.file(), l.line()));
+
+        let quiet = STATE
+            .try_with(|state| match state.try_borrow_mut() {
+                Ok(mut state) => {
+                    state.panic = Some((message, location));
+                    state.quiet
+                }
+                Err(_) => false,
+            })
+            .unwrap_or(false);
+        if !quiet {
+            default(info);
+        }
+    }));
+}
+
+/// Notes that `phase`, like `typecheck`, is starting.
+pub fn enter(phase: &'static str) {
+    STATE.with(|state| state.borrow_mut().phase = Some(phase));
+}
+
+/// Notes the script that is about to run.
+pub fn set_source(file: &str, source: &[u8]) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.file = Some(file.to_string());
+        state.source = Some(String::from_utf8_lossy(source).into_owned());
+    });
+}
+
+/// Writes a report of the last panic to the temporary directory and
+/// returns where, or the report itself if it could not be written. The
+/// source of the script is shrunk to the smallest part of it that still
+/// panics in the same place.
+pub fn write_report() -> Result<PathBuf, String> {
+    let (phase, file, source, panic) = STATE.with(|state| {
+        let state = state.borrow();
+        (
+            state.phase,
+            state.file.clone(),
+            state.source.clone(),
+            state.panic.clone(),
+        )
+    });
+    let (message, location) = panic.unwrap_or_default();
+
+    let mut report = String::new();
+    let _ =
+        writeln!(report, "blixt {} crash report", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "os: {} {}", env::consts::OS, env::consts::ARCH);
+    let _ = writeln!(report, "phase: {}", phase.unwrap_or("none"));
+    let _ = writeln!(report, "panic: {} at {}", message, location);
+
+    if let (Some(file), Some(source)) = (file, source) {
+        let _ = writeln!(report, "file: {}\n", file);
+        let total = source.lines().count();
+        match phase.filter(|phase| PHASES.contains(phase)) {
+            Some(phase) => {
+                let lines: Vec<_> = source.lines().collect();
+                let mut crashes = |lines: &[&str]| {
+                    reproduces(&lines.join("\n"), &file, phase, &location)
+                };
+                let smallest = minimize(lines, &mut crashes);
+                let smallest = drop_blocks(smallest, &mut crashes);
+                let _ = writeln!(
+                    report,
+                    "Smallest source found that crashes the same way \
+                     ({} of {} lines):\n",
+                    smallest.len(),
+                    total
+                );
+                for line in smallest {
+                    let _ = writeln!(report, "{}", line);
+                }
+            }
+            None => {
+                let _ = writeln!(
+                    report,
+                    "The source was not minimized, since the crash happened \
+                     while it ran ({} lines):\n",
+                    total
+                );
+                let _ = writeln!(report, "{}", source);
+            }
+        }
+    }
+
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let path = env::temp_dir().join(format!(
+        "blixt-crash-{}-{}.txt",
+        seconds,
+        process::id()
+    ));
+    match fs::write(&path, &report) {
+        Ok(()) => Ok(path),
+        Err(_) => Err(report),
+    }
+}
+
+/// Whether running the phases up to `phase` on `source` panics at
+/// `location`. Diagnostics are collected rather than shown, and the
+/// panic hook is silent meanwhile.
+fn reproduces(source: &str, file: &str, phase: &str, location: &str) -> bool {
+    let last = PHASES.iter().position(|p| *p == phase).unwrap_or(0);
+    let run = || {
+        let mut context = Context::new();
+        builtins::register(&mut context);
+        context.collected = Some(Vec::new());
+        let file = context.interner.intern(file);
+
+        let tokens =
+            modules::generate_tokens(source.as_bytes(), file, &mut context);
+        let ast = match tokens {
+            Ok(tokens) if last > 0 => parser::parse_ast(tokens, &mut context),
+            _ => return,
+        };
+        let ast = match ast {
+            Ok(ast) if last > 1 => ast,
+            _ => return,
+        };
+        if semantic::analyze(&ast, &mut context).is_err() || last == 2 {
+            return;
+        }
+        if typecheck::typecheck(&ast, &mut context).is_err() || last == 3 {
+            return;
+        }
+        compiler::compile(&ast, &mut context);
+    };
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.quiet = true;
+        state.panic = None;
+    });
+    let panicked = panic::catch_unwind(AssertUnwindSafe(run)).is_err();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.quiet = false;
+        panicked
+            && state.panic.as_ref().map(|(_, l)| l.as_str()) == Some(location)
+    })
+}
+
+/// Removes as many lines as it can while `crashes` still holds, first in
+/// large chunks and then in smaller ones, as in delta debugging. Gives up
+/// on going smaller after `MAX_ATTEMPTS` tries.
+fn minimize<'a>(
+    mut lines: Vec<&'a str>,
+    mut crashes: impl FnMut(&[&'a str]) -> bool,
+) -> Vec<&'a str> {
+    let mut chunks = 2;
+    let mut attempts = 0;
+
+    while lines.len() > 1 && attempts < MAX_ATTEMPTS {
+        let size = lines.len().div_ceil(chunks);
+        let mut removed = false;
+
+        for start in (0..lines.len()).step_by(size) {
+            let end = (start + size).min(lines.len());
+            let candidate: Vec<_> = lines[..start]
+                .iter()
+                .chain(&lines[end..])
+                .cloned()
+                .collect();
+            attempts += 1;
+            if crashes(&candidate) {
+                lines = candidate;
+                chunks = (chunks - 1).max(2);
+                removed = true;
+                break;
+            }
+            if attempts >= MAX_ATTEMPTS {
+                break;
+            }
+        }
+
+        if !removed {
+            if chunks >= lines.len() {
+                break;
+            }
+            chunks = (chunks * 2).min(lines.len());
+        }
+    }
+
+    lines
+}
+
+/// Removes whole blocks, from the line a `{` is on to the line of the `}`
+/// that closes it, while `crashes` still holds. `minimize` can't remove
+/// the two lines of an empty function, since neither parses without the
+/// other.
+fn drop_blocks<'a>(
+    mut lines: Vec<&'a str>,
+    mut crashes: impl FnMut(&[&'a str]) -> bool,
+) -> Vec<&'a str> {
+    'shrink: loop {
+        for (first, last) in blocks(&lines.join("\n")) {
+            let candidate: Vec<_> = lines[..first]
+                .iter()
+                .chain(&lines[last + 1..])
+                .cloned()
+                .collect();
+            if crashes(&candidate) {
+                lines = candidate;
+                continue 'shrink;
+            }
+        }
+        return lines;
+    }
+}
+
+/// The first and last line, counted from 0, of every block in `source`,
+/// largest first.
+fn blocks(source: &str) -> Vec<(usize, usize)> {
+    let mut context = Context::new();
+    context.collected = Some(Vec::new());
+    let file = context.interner.intern("<crash>");
+    let tokens =
+        match lexer::generate_tokens(source.as_bytes(), file, &mut context) {
+            Ok(tokens) => tokens,
+            Err(_) => return vec![],
+        };
+
+    let mut open = Vec::new();
+    let mut blocks = Vec::new();
+    for token in tokens {
+        let line = token.location.line as usize - 1;
+        match token.kind {
+            TokenKind::OpenBrace => open.push(line),
+            TokenKind::CloseBrace => {
+                if let Some(first) = open.pop() {
+                    blocks.push((first, line));
+                }
+            }
+            _ => {}
+        }
+    }
+    blocks.sort_by_key(|(first, last)| Reverse(last - first));
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimizes_to_the_lines_that_matter() {
+        let lines: Vec<_> = (0..40).map(|i| i.to_string()).collect();
+        let lines: Vec<_> = lines.iter().map(String::as_str).collect();
+
+        let smallest = minimize(lines.clone(), |lines| {
+            lines.contains(&"7") && lines.contains(&"31")
+        });
+        assert_eq!(smallest, ["7", "31"]);
+
+        let smallest = minimize(lines, |lines| lines.len() > 3);
+        assert_eq!(smallest.len(), 4);
+    }
+
+    #[test]
+    fn keeps_everything_when_nothing_can_go() {
+        let smallest = minimize(vec!["a", "b", "c"], |lines| lines.len() == 3);
+        assert_eq!(smallest, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn drops_whole_blocks() {
+        let source = "fn f() {\n}\nif true {\n  a := 1\n}\nb := 2";
+        assert_eq!(blocks(source), [(2, 4), (0, 1)]);
+
+        let lines: Vec<_> = source.lines().collect();
+        let smallest = drop_blocks(lines, |lines| lines.contains(&"b := 2"));
+        assert_eq!(smallest, ["b := 2"]);
+    }
+
+    #[test]
+    fn checks_only_up_to_the_phase() {
+        // A source that doesn't get through the parser can't reproduce
+        // anything later.
+        assert!(!reproduces("a := (", "test.bl", "typecheck", "src/x.rs:1"));
+        assert!(!reproduces("a := 1", "test.bl", "compile", "src/x.rs:1"));
+    }
+}
@@ -0,0 +1,320 @@
+/// How many unchanged lines are kept around the changes in a hunk.
+pub const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Edit {
+    Keep,
+    Remove,
+    Insert,
+}
+
+/// A run of changed lines with some unchanged ones around them. Each line
+/// starts with `' '` if it is in both texts, `'-'` if only in the old one
+/// and `'+'` if only in the new one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hunk {
+    /// One-based line in the old text where the hunk starts.
+    pub old_start: usize,
+    pub old_len: usize,
+    /// One-based line in the new text where the hunk starts.
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<String>,
+}
+
+/// The hunks that turn the lines of `old` into those of `new`, with
+/// `context` unchanged lines around each change.
+pub fn diff_lines(old: &[&str], new: &[&str], context: usize) -> Vec<Hunk> {
+    // Each edit with the lines it is at in `old` and `new`.
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for edit in shortest_edit(old, new) {
+        edits.push((edit, i, j));
+        match edit {
+            Edit::Keep => {
+                i += 1;
+                j += 1;
+            }
+            Edit::Remove => i += 1,
+            Edit::Insert => j += 1,
+        }
+    }
+
+    // Changes closer than twice the context share a hunk.
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (at, _) in edits
+        .iter()
+        .enumerate()
+        .filter(|(_, (edit, _, _))| *edit != Edit::Keep)
+    {
+        let start = at.saturating_sub(context);
+        let end = (at + context + 1).min(edits.len());
+        match ranges.last_mut() {
+            Some(last) if last.1 >= start => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let (_, i, j) = edits[start];
+            let mut hunk = Hunk {
+                old_start: i + 1,
+                old_len: 0,
+                new_start: j + 1,
+                new_len: 0,
+                lines: Vec::new(),
+            };
+
+            for &(edit, i, j) in &edits[start..end] {
+                let line = match edit {
+                    Edit::Keep => {
+                        hunk.old_len += 1;
+                        hunk.new_len += 1;
+                        format!(" {}", old[i])
+                    }
+                    Edit::Remove => {
+                        hunk.old_len += 1;
+                        format!("-{}", old[i])
+                    }
+                    Edit::Insert => {
+                        hunk.new_len += 1;
+                        format!("+{}", new[j])
+                    }
+                };
+                hunk.lines.push(line);
+            }
+
+            hunk
+        })
+        .collect()
+}
+
+/// Applies `hunks`, in order, to the lines of `old`. Returns the reason if
+/// the lines a hunk keeps or removes are not the ones in `old`.
+pub fn apply(old: &[&str], hunks: &[Hunk]) -> Result<Vec<String>, String> {
+    let mut new = Vec::with_capacity(old.len());
+    let mut at = 0;
+
+    for hunk in hunks {
+        let start = hunk.old_start.saturating_sub(1);
+        if start < at || start > old.len() {
+            return Err(format!("Hunk at line {} is out of place", start + 1));
+        }
+        new.extend(old[at..start].iter().map(|line| line.to_string()));
+        at = start;
+
+        for line in &hunk.lines {
+            let (kind, text) = line.split_at(line.len().min(1));
+            if kind == "+" {
+                new.push(text.to_string());
+                continue;
+            }
+
+            if old.get(at) != Some(&text) {
+                return Err(format!(
+                    "Line {} does not match the patch",
+                    at + 1
+                ));
+            }
+            if kind != "-" {
+                new.push(text.to_string());
+            }
+            at += 1;
+        }
+    }
+
+    new.extend(old[at..].iter().map(|line| line.to_string()));
+    Ok(new)
+}
+
+/// Formats `hunks` as a unified diff, with removed lines in red and added
+/// ones in green if `color` is set.
+pub fn render(hunks: &[Hunk], color: bool) -> String {
+    let paint = |code: &str, text: &str| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m\n", code, text)
+        } else {
+            format!("{}\n", text)
+        }
+    };
+
+    let mut output = String::new();
+    for hunk in hunks {
+        // An empty range is given as the line before it, as `diff -u` does.
+        let start = |start: usize, len: usize| {
+            if len == 0 {
+                start - 1
+            } else {
+                start
+            }
+        };
+        let header = format!(
+            "@@ -{},{} +{},{} @@",
+            start(hunk.old_start, hunk.old_len),
+            hunk.old_len,
+            start(hunk.new_start, hunk.new_len),
+            hunk.new_len
+        );
+        output += &paint("36", &header);
+
+        for line in &hunk.lines {
+            output += &match line.chars().next() {
+                Some('-') => paint("31", line),
+                Some('+') => paint("32", line),
+                _ => format!("{}\n", line),
+            };
+        }
+    }
+
+    output
+}
+
+/// The fewest removals and insertions that turn `old` into `new`, found
+/// with Myers' algorithm.
+fn shortest_edit(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    // The furthest `x` reached on each diagonal `k = x - y`, stored at
+    // `k + offset` since `k` goes down to `-max - 1`.
+    let offset = max + 1;
+    let at = |k: isize| (k + offset) as usize;
+    let mut furthest = vec![0; 2 * max as usize + 3];
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(furthest.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d
+                || (k != d && furthest[at(k - 1)] < furthest[at(k + 1)])
+            {
+                furthest[at(k + 1)]
+            } else {
+                furthest[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[at(k)] = x;
+
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walks back from the end through the furthest points of each step.
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, furthest) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if k == -d
+            || (k != d && furthest[at(k - 1)] < furthest[at(k + 1)])
+        {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = furthest[at(prev_k)];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            edits.push(if x == prev_x {
+                Edit::Insert
+            } else {
+                Edit::Remove
+            });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+
+    edits.reverse();
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<&str> {
+        text.lines().collect()
+    }
+
+    #[test]
+    fn shortest_edits() {
+        use Edit::*;
+
+        let edits = shortest_edit(&lines("a\nb\nc"), &lines("a\nx\nc\nd"));
+        assert_eq!(edits, vec![Keep, Remove, Insert, Keep, Insert]);
+        assert_eq!(shortest_edit(&[], &[]), vec![]);
+        assert_eq!(shortest_edit(&[], &["a"]), vec![Insert]);
+        assert_eq!(shortest_edit(&["a"], &[]), vec![Remove]);
+    }
+
+    #[test]
+    fn hunks_keep_context_around_changes() {
+        let old: Vec<String> = (1..=20).map(|i| i.to_string()).collect();
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let mut new = old.clone();
+        new[1] = "two";
+        new[17] = "eighteen";
+
+        let hunks = diff_lines(&old, &new, CONTEXT);
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(
+            hunks[0],
+            Hunk {
+                old_start: 1,
+                old_len: 5,
+                new_start: 1,
+                new_len: 5,
+                lines: vec![" 1", "-2", "+two", " 3", " 4", " 5"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            }
+        );
+        assert_eq!((hunks[1].old_start, hunks[1].old_len), (15, 6));
+
+        assert_eq!(apply(&old, &hunks), Ok(lines_of(&new)));
+        assert!(diff_lines(&old, &old, CONTEXT).is_empty());
+    }
+
+    #[test]
+    fn patches_only_apply_to_matching_text() {
+        let old = lines("a\nb\nc");
+        let new = lines("b\nc\nd");
+        let hunks = diff_lines(&old, &new, 1);
+        assert_eq!(apply(&old, &hunks), Ok(lines_of(&new)));
+        assert!(apply(&lines("a\nx\nc"), &hunks).is_err());
+        assert!(apply(&[], &hunks).is_err());
+    }
+
+    #[test]
+    fn rendering() {
+        let hunks = diff_lines(&lines("a\nb"), &lines("a\nc"), 1);
+        assert_eq!(render(&hunks, false), "@@ -1,2 +1,2 @@\n a\n-b\n+c\n");
+        assert_eq!(
+            render(&hunks, true),
+            "\x1b[36m@@ -1,2 +1,2 @@\x1b[0m\n a\n\x1b[31m-b\x1b[0m\n\
+             \x1b[32m+c\x1b[0m\n"
+        );
+
+        let hunks = diff_lines(&[], &lines("a"), 1);
+        assert_eq!(render(&hunks, false), "@@ -0,0 +1,1 @@\n+a\n");
+    }
+
+    fn lines_of(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+}
@@ -0,0 +1,692 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::arena::Arena;
+use crate::ast::{Ast, Decl, Stmt};
+use crate::builtins;
+use crate::common::{Context, StringInterner};
+use crate::config::{Config, Layer};
+use crate::interpreter;
+use crate::lexer;
+use crate::messages::Message;
+use crate::parser;
+use crate::primitives::{Value, ValueKind};
+use crate::scope::Scope;
+use crate::semantic;
+#[cfg(feature = "serde")]
+use crate::serialize::{Serializable, ValueSeed};
+use crate::snapshot::{self, Reader};
+use crate::typecheck;
+
+pub type NativeFn = dyn Fn(&[Value], &mut StringInterner) -> Value;
+
+/// A function implemented by the host and callable from scripts.
+pub struct NativeFunction {
+    pub params: Vec<ValueKind>,
+    pub return_type: Option<ValueKind>,
+    pub func: Box<NativeFn>,
+    /// The capability the function needs, if any, like `fs`.
+    pub capability: Option<&'static str>,
+}
+
+/// Runs scripts for a host application. Variables and functions declared by
+/// one call to `eval` are visible to the next.
+///
+/// Errors are reported on stderr as they are found, the `Error` returned
+/// only says how many there were. Input that fails leaves no declarations
+/// behind.
+pub struct Engine {
+    context: Context,
+    ast: Ast,
+    types: Scope,
+    values: Scope,
+    checkpoints: Vec<Checkpoint>,
+    /// The name and text of every input that was evaluated, for `save`.
+    sources: Vec<(String, String)>,
+}
+
+/// Why a call to an `Engine` failed. The errors themselves have been
+/// reported on stderr by the time it is returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error {
+    /// How many errors were reported, at least one.
+    pub errors: usize,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.errors {
+            1 => write!(f, "the script failed with an error"),
+            n => write!(f, "the script failed with {} errors", n),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Makes engines with settings merged from defaults, a config file,
+/// environment variables and settings given by the host, in that order of
+/// precedence, see `Config`.
+///
+/// ```text
+/// let engine = EngineBuilder::new()
+///     .config_file("gentlemen.toml")?
+///     .env()?
+///     .set("max_depth", "256")?
+///     .build();
+/// ```
+#[derive(Default)]
+pub struct EngineBuilder {
+    config: Config,
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        EngineBuilder::default()
+    }
+
+    /// Reads the `[runtime]` section of the config file at `path`, if there
+    /// is a file there.
+    pub fn config_file(
+        mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, String> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                self.config.read_file(&path.to_string_lossy(), &text)?
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        }
+        Ok(self)
+    }
+
+    /// Reads the `BLIXT_` environment variables for each setting, like
+    /// `BLIXT_MAX_DEPTH`.
+    pub fn env(mut self) -> Result<Self, String> {
+        self.config.read_env(env::vars())?;
+        Ok(self)
+    }
+
+    /// Sets `key` as a command line flag would, over the config file and
+    /// the environment.
+    pub fn set(mut self, key: &str, value: &str) -> Result<Self, String> {
+        self.config.set(key, value, Layer::Flag)?;
+        Ok(self)
+    }
+
+    /// The settings the engine will have.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn build(self) -> Engine {
+        let mut context = Context::new();
+        self.config.apply(&mut context);
+        Engine::with_context(context)
+    }
+}
+
+/// The declarations at the time of a call to `Engine::checkpoint`. Arrays
+/// are shared with the live scopes until one side changes them.
+struct Checkpoint {
+    types: Scope,
+    values: Scope,
+    statements: usize,
+    sources: usize,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine::with_context(Context::new())
+    }
+
+    /// Makes an engine that runs scripts with `context`, after adding the
+    /// builtin functions to it.
+    pub fn with_context(mut context: Context) -> Self {
+        builtins::register(&mut context);
+
+        Engine {
+            context,
+            ast: Ast {
+                arena: Arena::new(),
+                statements: vec![],
+            },
+            types: Scope::new(),
+            values: Scope::new(),
+            checkpoints: vec![],
+            sources: vec![],
+        }
+    }
+
+    pub fn context(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    /// Makes `func` callable from scripts as `name`. Arguments are checked
+    /// against `params` before the call.
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        params: &[ValueKind],
+        return_type: Option<ValueKind>,
+        func: impl Fn(&[Value], &mut StringInterner) -> Value + 'static,
+    ) {
+        let name = self.context.interner.intern(name);
+        let func = NativeFunction {
+            params: params.to_vec(),
+            return_type,
+            func: Box::new(func),
+            capability: None,
+        };
+
+        self.context.native_functions.insert(name, Rc::new(func));
+    }
+
+    /// Makes `value` readable from scripts as `name`. Scripts cannot assign
+    /// to it, but can declare a variable of their own with the same name.
+    pub fn set_global(&mut self, name: &str, value: impl Into<Value>) {
+        let name = self.context.interner.intern(name);
+        self.context.constants.insert(name, value.into());
+    }
+
+    /// Makes the variants of a host enum readable from scripts as
+    /// `name.Variant`, with the given values.
+    pub fn register_enum(&mut self, name: &str, variants: &[(&str, i32)]) {
+        for (variant, value) in variants {
+            self.set_global(&format!("{}.{}", name, variant), *value);
+        }
+
+        let name = self.context.interner.intern(name);
+        self.context.namespaces.insert(name);
+    }
+
+    /// Runs `source` and returns the value of its last statement if that is
+    /// an expression.
+    pub fn eval_str(&mut self, source: &str) -> Result<Option<Value>, Error> {
+        self.eval("<eval>", source)
+    }
+
+    /// Like `eval_str`, with `name` used as the file name in errors.
+    pub fn eval(
+        &mut self,
+        name: &str,
+        source: &str,
+    ) -> Result<Option<Value>, Error> {
+        self.eval_each(name, source, |_, _, _| {})
+    }
+
+    /// Like `eval`, also passing the value of every top level expression
+    /// statement to `echo` as soon as it has been evaluated, along with the
+    /// type the typechecker inferred for it.
+    pub fn eval_each(
+        &mut self,
+        name: &str,
+        source: &str,
+        echo: impl FnMut(&Value, &ValueKind, &StringInterner),
+    ) -> Result<Option<Value>, Error> {
+        let errors = self.context.errors;
+        let result = self.run(name, source, echo);
+        result.map_err(|()| self.failed(errors))
+    }
+
+    fn run(
+        &mut self,
+        name: &str,
+        source: &str,
+        mut echo: impl FnMut(&Value, &ValueKind, &StringInterner),
+    ) -> Result<Option<Value>, ()> {
+        let file = self.context.interner.intern(name);
+        self.context
+            .source_code
+            .insert(PathBuf::from(name), source.to_string());
+
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut self.context)?;
+        let statements = parser::parse_statements(
+            tokens,
+            &mut self.ast.arena,
+            &mut self.context,
+        )?;
+
+        let mut types = self.types.clone();
+        let kinds = typecheck::typecheck_statements(
+            &self.ast,
+            &statements,
+            &mut types,
+            &mut self.context,
+        )?;
+        semantic::check_call_order(&self.ast, &statements, &mut self.context)?;
+
+        let mut values = self.values.clone();
+        let mut kinds = kinds.iter();
+        let value = interpreter::evaluate_each(
+            &self.ast,
+            &statements,
+            &mut values,
+            &mut self.context,
+            &mut |value, interner| {
+                let kind = kinds.next().unwrap_or(&ValueKind::Nil);
+                echo(value, kind, interner)
+            },
+        )?;
+
+        self.types = types;
+        self.values = values;
+        self.ast.statements.extend(statements);
+        self.sources.push((name.to_string(), source.to_string()));
+
+        Ok(value)
+    }
+
+    /// Saves the declarations as bytes that `restore` can load, in this
+    /// process or another one.
+    ///
+    /// Variables holding iterators can't be saved. Functions registered by
+    /// the host are saved by name and have to be registered again before
+    /// restoring.
+    pub fn save(&self) -> Result<Vec<u8>, Error> {
+        snapshot::save(
+            &self.sources,
+            self.values.globals(),
+            &self.context.interner,
+        )
+        .map_err(|message| {
+            self.report(message);
+            Error { errors: 1 }
+        })
+    }
+
+    /// Loads declarations saved by `save` into a new engine. The saved
+    /// inputs are checked again but not run, so only the values of their
+    /// variables come from the snapshot.
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let errors = self.context.errors;
+        self.checkpoint();
+        let result = self.restore_snapshot(bytes);
+
+        if result.is_ok() {
+            self.checkpoints.pop();
+        } else {
+            self.rollback();
+        }
+
+        result.map_err(|()| self.failed(errors))
+    }
+
+    fn restore_snapshot(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        let mut reader = Reader::new(bytes).map_err(|m| self.report(m))?;
+        let sources = reader.sources().map_err(|m| self.report(m))?;
+
+        for (name, source) in sources {
+            let file = self.context.interner.intern(&name);
+            self.context
+                .source_code
+                .insert(PathBuf::from(&name), source.clone());
+
+            let tokens = lexer::generate_tokens(
+                source.as_bytes(),
+                file,
+                &mut self.context,
+            )?;
+            let statements = parser::parse_statements(
+                tokens,
+                &mut self.ast.arena,
+                &mut self.context,
+            )?;
+            typecheck::typecheck_statements(
+                &self.ast,
+                &statements,
+                &mut self.types,
+                &mut self.context,
+            )?;
+
+            for stmt in &statements {
+                if let Stmt::Decl(Decl::Function(func)) = &self.ast.arena[*stmt]
+                {
+                    self.values.add_function(func.name, *stmt);
+                }
+            }
+            self.ast.statements.extend(statements);
+            self.sources.push((name, source));
+        }
+
+        let globals = reader
+            .globals(&mut self.context.interner, &mut self.values)
+            .map_err(|m| self.report(m))?;
+        for (name, value) in globals {
+            let kind = match self.types.get_variable(name) {
+                Some(var) => var.kind.clone(),
+                None => {
+                    self.report(Message::InvalidSnapshot);
+                    return Err(());
+                }
+            };
+            self.values.add_variable(name, value, kind);
+        }
+
+        Ok(())
+    }
+
+    /// Reports an error that is not about any place in a script.
+    fn report(&self, message: Message) {
+        eprintln!("{}", message.text(self.context.language));
+    }
+
+    /// The error for a call that failed, after `before` errors had been
+    /// reported. Those not about a place in a script aren't counted.
+    fn failed(&self, before: usize) -> Error {
+        Error {
+            errors: (self.context.errors - before).max(1),
+        }
+    }
+
+    /// Saves the current declarations and returns how many checkpoints are
+    /// now saved.
+    pub fn checkpoint(&mut self) -> usize {
+        self.checkpoints.push(Checkpoint {
+            types: self.types.clone(),
+            values: self.values.clone(),
+            statements: self.ast.statements.len(),
+            sources: self.sources.len(),
+        });
+
+        self.checkpoints.len()
+    }
+
+    /// Restores the declarations saved by the latest checkpoint and
+    /// discards it. Returns false if there is no checkpoint.
+    pub fn rollback(&mut self) -> bool {
+        match self.checkpoints.pop() {
+            Some(checkpoint) => {
+                self.types = checkpoint.types;
+                self.values = checkpoint.values;
+                self.ast.statements.truncate(checkpoint.statements);
+                self.sources.truncate(checkpoint.sources);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Formats a value the way `print` shows it.
+    pub fn display(&self, value: &Value) -> String {
+        value.format(&self.context.interner)
+    }
+
+    /// Wraps a value of this engine for serializing with any serde format.
+    #[cfg(feature = "serde")]
+    pub fn serializable<'a>(&'a self, value: &'a Value) -> Serializable<'a> {
+        Serializable::new(value, &self.context.interner)
+    }
+
+    /// Deserializes a value for this engine from any serde format.
+    #[cfg(feature = "serde")]
+    pub fn value_seed(&mut self) -> ValueSeed<'_> {
+        ValueSeed::new(&mut self.context.interner)
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::Grants;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn values_through_serde() {
+        use serde::de::DeserializeSeed;
+
+        let mut engine = Engine::new();
+        let value = engine
+            .eval_str("m := {\"a\": [1, 2], \"b\": [3]} m")
+            .unwrap()
+            .unwrap();
+        let json = serde_json::to_string(&engine.serializable(&value)).unwrap();
+        assert_eq!(json, "{\"a\":[1,2],\"b\":[3]}");
+
+        let mut other = Engine::new();
+        let mut deserializer = serde_json::Deserializer::from_str(&json);
+        let value = other.value_seed().deserialize(&mut deserializer).unwrap();
+        other.set_global("config", value);
+        assert_eq!(
+            other.eval_str("len(config[\"a\"]) + config[\"b\"][0]"),
+            Ok(Some(Value::Int(5)))
+        );
+    }
+
+    #[test]
+    fn keeps_declarations_between_calls() {
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.eval_str("a := 2"), Ok(None));
+        assert_eq!(
+            engine.eval_str("fn double(n: int) -> int { return n * 2 }"),
+            Ok(None)
+        );
+        assert_eq!(engine.eval_str("a += 3"), Ok(None));
+        assert_eq!(engine.eval_str("double(a)"), Ok(Some(Value::Int(10))));
+    }
+
+    #[test]
+    fn call_before_global_is_declared() {
+        let mut engine = Engine::new();
+
+        let source = "x := f() n := 1 fn f() -> int { return n }";
+        assert!(engine.eval_str(source).is_err());
+        assert_eq!(engine.eval_str("n := 1"), Ok(None));
+        let source = "x := f() fn f() -> int { return n }";
+        assert_eq!(engine.eval_str(source), Ok(None));
+        assert_eq!(engine.eval_str("x"), Ok(Some(Value::Int(1))));
+    }
+
+    #[test]
+    fn failed_input_declares_nothing() {
+        let mut engine = Engine::new();
+
+        assert!(engine.eval_str("a := 1 b := 0 c := a / b").is_err());
+        assert!(engine.eval_str("a").is_err());
+    }
+
+    #[test]
+    fn errors_are_counted() {
+        let mut engine = Engine::new();
+
+        let error = engine.eval_str("a: int = \"x\" b: bool = 1");
+        assert_eq!(error, Err(Error { errors: 2 }));
+        let error = Engine::new().restore(b"not a snapshot").unwrap_err();
+        assert_eq!(error.to_string(), "the script failed with an error");
+    }
+
+    #[test]
+    fn echo_every_expression() {
+        let mut engine = Engine::new();
+        let mut echoed = vec![];
+
+        let value = engine.eval_each(
+            "<eval>",
+            "a := 2 a + 1 fn f() -> int { return 5 } f() a = 4",
+            |value, kind, interner| {
+                echoed.push(format!("{} {:?}", value.format(interner), kind))
+            },
+        );
+
+        assert_eq!(value, Ok(None));
+        assert_eq!(echoed, ["3 Integer", "5 Integer"]);
+    }
+
+    #[test]
+    fn rollback_to_checkpoint() {
+        let mut engine = Engine::new();
+
+        assert!(!engine.rollback());
+
+        engine.eval_str("xs := [1] n := 1").unwrap();
+        assert_eq!(engine.checkpoint(), 1);
+        engine.eval_str("push(xs, 2) n = 5 m := 3").unwrap();
+        assert_eq!(engine.checkpoint(), 2);
+        engine.eval_str("n = 7").unwrap();
+
+        assert!(engine.rollback());
+        assert_eq!(engine.eval_str("n"), Ok(Some(Value::Int(5))));
+
+        assert!(engine.rollback());
+        assert_eq!(engine.eval_str("n + len(xs)"), Ok(Some(Value::Int(2))));
+        assert!(engine.eval_str("m").is_err());
+        assert!(!engine.rollback());
+    }
+
+    #[test]
+    fn host_constants() {
+        let mut engine = Engine::new();
+        engine.set_global("MAX_PLAYERS", 4);
+        engine.set_global("SCALE", 1.5);
+        engine.register_enum("Color", &[("Red", 0), ("Green", 1)]);
+
+        assert_eq!(
+            engine.eval_str("MAX_PLAYERS * SCALE"),
+            Ok(Some(Value::Float(6.0)))
+        );
+        assert_eq!(
+            engine.eval_str("c := Color.Green c == Color.Red"),
+            Ok(Some(Value::Bool(false)))
+        );
+        assert!(engine.eval_str("MAX_PLAYERS = 5").is_err());
+        assert!(engine.eval_str("c = Color.Blue").is_err());
+        assert_eq!(
+            engine.eval_str("MAX_PLAYERS := \"many\" MAX_PLAYERS"),
+            Ok(Some(Value::String(
+                engine.context().interner.intern("many")
+            )))
+        );
+    }
+
+    #[test]
+    fn call_native_function() {
+        let mut engine = Engine::new();
+        engine.register_fn(
+            "shout",
+            &[ValueKind::String, ValueKind::Integer],
+            Some(ValueKind::String),
+            |args, interner| match args {
+                [Value::String(s), Value::Int(n)] => {
+                    let s = interner.get(*s).to_uppercase().repeat(*n as usize);
+                    Value::String(interner.intern(&s))
+                }
+                _ => unreachable!(),
+            },
+        );
+
+        let value = engine
+            .eval_str("shout(\"hi\", 2) + \"!\"")
+            .unwrap()
+            .unwrap();
+        assert_eq!(engine.display(&value), "HIHI!");
+
+        assert!(engine.eval_str("shout(\"hi\")").is_err());
+        assert!(engine.eval_str("shout(2, 2)").is_err());
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let mut engine = Engine::new();
+        engine.eval_str("print(\"saved \")").unwrap();
+        engine
+            .eval_str(
+                "fn add(a: int, b: int) -> int { return a + b } \
+                 n := 1 xs := [1.5] m := {\"a\": Ok(2)} d := deque() \
+                 f := partial(add, 10)",
+            )
+            .unwrap();
+        engine.eval_str("n += 1 push_front(d, \"x\")").unwrap();
+        let bytes = engine.save().unwrap();
+
+        let mut restored = Engine::new();
+        assert_eq!(restored.restore(&bytes), Ok(()));
+        assert_eq!(restored.save(), Ok(bytes.clone()));
+        let value = restored
+            .eval_str(
+                "\"${collect(map([n], f))} ${xs} ${m} ${d} ${add(n, 3)}\"",
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            restored.display(&value),
+            "[12] [1.5] {a: Ok(2)} deque[x] 5"
+        );
+
+        assert!(Engine::new().restore(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Engine::new().restore(b"not a snapshot").is_err());
+        assert!(engine.eval_str("it := range(3)").is_ok());
+        assert!(engine.save().is_err());
+    }
+
+    #[test]
+    fn failed_restore_declares_nothing() {
+        let mut engine = Engine::new();
+        engine.register_fn("one", &[], Some(ValueKind::Integer), |_, _| {
+            Value::Int(1)
+        });
+        engine.eval_str("a := one()").unwrap();
+        let bytes = engine.save().unwrap();
+
+        // Host functions have to be registered before restoring.
+        let mut restored = Engine::new();
+        assert!(restored.restore(&bytes).is_err());
+        assert!(restored.eval_str("a").is_err());
+        assert!(restored.eval_str("a := 2").is_ok());
+    }
+
+    #[test]
+    fn builder_settings() {
+        let builder = EngineBuilder::new()
+            .config_file("no/such/gentlemen.toml")
+            .unwrap()
+            .set("strict", "true")
+            .unwrap()
+            .set("capabilities", "fs")
+            .unwrap();
+        assert!(builder.config().strict);
+
+        let mut engine = builder.build();
+        assert!(engine.context().strict_mode);
+        assert_eq!(
+            engine.context().grants,
+            Grants::Only(vec!["fs".to_string()])
+        );
+        assert!(engine.eval_str("a := 1 a").is_err());
+
+        assert!(EngineBuilder::new().set("max_depth", "-1").is_err());
+    }
+
+    /// Programs as deep as the limits allow run on a thread with far less
+    /// stack than they need without growing it.
+    #[test]
+    fn deep_programs_on_a_small_stack() {
+        let nested = format!("{}1{}", "(".repeat(1000), ")".repeat(1000));
+        let source = format!(
+            "fn down(n: int) -> int {{ if n == 0 {{ return 0 }} \
+             return down(n - 1) + 1 }} \
+             down(9000) + {}",
+            nested
+        );
+
+        let runner = std::thread::Builder::new().stack_size(256 * 1024);
+        let result = runner.spawn(move || {
+            Engine::new().eval_str(&source) == Ok(Some(Value::Int(9001)))
+        });
+        assert!(result.unwrap().join().unwrap());
+    }
+}
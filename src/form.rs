@@ -0,0 +1,146 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::url;
+
+/// Joins `fields` into an `application/x-www-form-urlencoded` body, which
+/// is a query string with spaces written as `+`.
+pub fn encode(fields: &[(String, String)]) -> String {
+    let field = |text: &str| url::encode(text).replace("%20", "+");
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|(name, value)| format!("{}={}", field(name), field(value)))
+        .collect();
+    fields.join("&")
+}
+
+/// Writes a `multipart/form-data` body separated by `boundary`, with a
+/// part for each of `fields` and one for each of `files`, given as field
+/// names and paths. Files are copied a piece at a time, so they can be
+/// larger than memory.
+pub fn write_multipart(
+    output: &mut dyn Write,
+    boundary: &str,
+    fields: &[(String, String)],
+    files: &[(String, String)],
+) -> io::Result<()> {
+    for (name, value) in fields {
+        write!(
+            output,
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            boundary,
+            quote(name),
+            value
+        )?;
+    }
+
+    for (name, path) in files {
+        let mut file = File::open(path).map_err(|e| {
+            io::Error::new(e.kind(), format!("{}: {}", path, e))
+        })?;
+        let path = Path::new(path);
+        let file_name = path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into());
+        write!(
+            output,
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"; \
+             filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+            boundary,
+            quote(name),
+            quote(&file_name),
+            mime_type(path)
+        )?;
+        io::copy(&mut file, output)?;
+        output.write_all(b"\r\n")?;
+    }
+
+    write!(output, "--{}--\r\n", boundary)
+}
+
+/// The MIME type of a file, guessed from the extension of `path`.
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("txt") => "text/plain",
+        Some("html") | Some("htm") => "text/html",
+        Some("css") => "text/css",
+        Some("csv") => "text/csv",
+        Some("js") => "text/javascript",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Escapes the characters that would end a quoted header parameter.
+fn quote(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(&['\r', '\n'][..], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn url_encoded_forms() {
+        let fields = pairs(&[("name", "Ann Lee"), ("a&b", "1+1=2")]);
+        assert_eq!(encode(&fields), "name=Ann+Lee&a%26b=1%2B1%3D2");
+        assert_eq!(encode(&[]), "");
+    }
+
+    #[test]
+    fn multipart_bodies() {
+        let path = std::env::temp_dir().join("blixt_form_upload.txt");
+        fs::write(&path, "hello").unwrap();
+        let path_name = path.to_str().unwrap();
+
+        let mut body = Vec::new();
+        write_multipart(
+            &mut body,
+            "xyz",
+            &pairs(&[("title", "Hi")]),
+            &pairs(&[("file", path_name)]),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "--xyz\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\n\
+             Hi\r\n\
+             --xyz\r\nContent-Disposition: form-data; name=\"file\"; \
+             filename=\"blixt_form_upload.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\nhello\r\n--xyz--\r\n"
+        );
+
+        fs::remove_file(&path).unwrap();
+        let files = pairs(&[("file", path_name)]);
+        assert!(write_multipart(&mut Vec::new(), "xyz", &[], &files).is_err());
+    }
+
+    #[test]
+    fn mime_types() {
+        assert_eq!(mime_type(Path::new("a/b.PNG")), "image/png");
+        assert_eq!(mime_type(Path::new("README")), "application/octet-stream");
+    }
+}
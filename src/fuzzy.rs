@@ -0,0 +1,140 @@
+/// The number of characters that have to be inserted, removed or replaced
+/// to turn `a` into `b`, also known as the Levenshtein distance.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // The distances from the start of `a` read so far to each start of `b`.
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// How well `needle` matches `haystack` when its characters have to appear
+/// in order but not next to each other, ignoring case, or `None` if they
+/// don't all appear. Matches that are next to each other or start a word
+/// score higher, so `"fb"` matches `"foo_bar"` better than `"afbx"`, and
+/// every character skipped before the last match lowers the score.
+pub fn fuzzy_score(needle: &str, haystack: &str) -> Option<i32> {
+    let mut needle = needle.chars().flat_map(char::to_lowercase).peekable();
+    let mut score = 0;
+    let mut previous: Option<char> = None;
+    let mut matched_previous = false;
+
+    for c in haystack.chars() {
+        let wanted = match needle.peek() {
+            Some(wanted) => *wanted,
+            None => break,
+        };
+
+        let starts_word = match previous {
+            None => true,
+            Some(p) => {
+                !p.is_alphanumeric() || (p.is_lowercase() && c.is_uppercase())
+            }
+        };
+        previous = Some(c);
+
+        if c.to_lowercase().eq(std::iter::once(wanted)) {
+            needle.next();
+            score += 1;
+            if matched_previous {
+                score += 4;
+            }
+            if starts_word {
+                score += 6;
+            }
+            matched_previous = true;
+        } else {
+            // Characters skipped over make the match looser.
+            score -= 1;
+            matched_previous = false;
+        }
+    }
+
+    if needle.peek().is_some() {
+        None
+    } else {
+        Some(score)
+    }
+}
+
+/// The candidates that `needle` fuzzily matches, best first. Candidates
+/// that match equally well keep their order.
+pub fn fuzzy_match<'a>(needle: &str, haystack: &[&'a str]) -> Vec<&'a str> {
+    let mut matches: Vec<_> = haystack
+        .iter()
+        .filter_map(|candidate| {
+            fuzzy_score(needle, candidate).map(|score| (score, *candidate))
+        })
+        .collect();
+    matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    matches
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// The candidate closest to `name`, if one is close enough that `name` is
+/// likely a typo of it, for "did you mean" suggestions.
+pub fn closest<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    // A third of the name can be wrong, but always at least one character.
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+        assert_eq!(edit_distance("åäö", "aäö"), 1);
+    }
+
+    #[test]
+    fn fuzzy_matching() {
+        assert!(fuzzy_score("fb", "foo_bar") > fuzzy_score("fb", "afbx"));
+        assert!(fuzzy_score("FB", "fooBar").is_some());
+        assert_eq!(fuzzy_score("bf", "foo_bar"), None);
+        assert_eq!(fuzzy_score("", "x"), Some(0));
+
+        let haystack = ["readme.md", "src/main.rs", "Cargo.toml", "main.bl"];
+        assert_eq!(fuzzy_match("main", &haystack), ["main.bl", "src/main.rs"]);
+        assert_eq!(fuzzy_match("zzz", &haystack), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn suggestions() {
+        let names = ["println", "print", "parse_int", "len"];
+        assert_eq!(closest("prnt", names.iter().copied()), Some("print"));
+        assert_eq!(closest("printn", names.iter().copied()), Some("print"));
+        assert_eq!(closest("lne", names.iter().copied()), None);
+        assert_eq!(closest("ln", names.iter().copied()), Some("len"));
+        assert_eq!(closest("xyz", names.iter().copied()), None);
+        assert_eq!(
+            closest("parse_itn", names.iter().copied()),
+            Some("parse_int")
+        );
+    }
+}
@@ -0,0 +1,111 @@
+/// The binary units sizes are shown in, each 1024 times the one before.
+const BYTE_UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// Formats a number of bytes in the largest unit it has at least one of,
+/// with one decimal, like `"1.5 MiB"`. Less than a KiB is shown as is,
+/// like `"512 B"`.
+pub fn bytes(n: i64) -> String {
+    let sign = if n < 0 { "-" } else { "" };
+    let n = n.unsigned_abs();
+    if n < 1024 {
+        return format!("{}{} B", sign, n);
+    }
+
+    let mut size = n as f64 / 1024.0;
+    let mut unit = 0;
+    // Rounding could make it show 1024.0 of a unit instead of 1.0 of the
+    // next one.
+    while size >= 1023.95 && unit + 1 < BYTE_UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{}{:.1} {}", sign, size, BYTE_UNITS[unit])
+}
+
+/// Formats `n` with `separator` between each group of three digits, like
+/// `"1,234,567"`.
+pub fn number(n: i64, separator: &str) -> String {
+    let digits = n.unsigned_abs().to_string();
+    let mut formatted = String::with_capacity(digits.len() * 2);
+    if n < 0 {
+        formatted.push('-');
+    }
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted += separator;
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+/// Formats a number of milliseconds as `"250ms"` or `"1.5s"` below a
+/// minute, and as `"1h2m3s"` above, leaving out leading zero parts. Parts
+/// smaller than shown are cut off.
+pub fn duration(ms: i64) -> String {
+    let sign = if ms < 0 { "-" } else { "" };
+    let ms = ms.unsigned_abs();
+    if ms < 1000 {
+        return format!("{}{}ms", sign, ms);
+    }
+    if ms < 60_000 {
+        // Rounding down, so that it never shows 60 seconds.
+        let tenths = ms / 100;
+        let seconds = format!("{}.{}", tenths / 10, tenths % 10);
+        return format!("{}{}s", sign, seconds.trim_end_matches(".0"));
+    }
+
+    let seconds = ms / 1000;
+    let parts = [
+        (seconds / 86_400, "d"),
+        (seconds / 3600 % 24, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+    let first = parts.iter().position(|(n, _)| *n > 0).unwrap_or(3);
+    let parts: String = parts[first..]
+        .iter()
+        .map(|(n, unit)| format!("{}{}", n, unit))
+        .collect();
+    format!("{}{}", sign, parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(bytes(0), "0 B");
+        assert_eq!(bytes(1023), "1023 B");
+        assert_eq!(bytes(1024), "1.0 KiB");
+        assert_eq!(bytes(1536), "1.5 KiB");
+        assert_eq!(bytes(1024 * 1024 - 1), "1.0 MiB");
+        assert_eq!(bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        assert_eq!(bytes(-2048), "-2.0 KiB");
+        assert_eq!(bytes(i64::MIN), "-8.0 EiB");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(number(0, ","), "0");
+        assert_eq!(number(999, ","), "999");
+        assert_eq!(number(1000, ","), "1,000");
+        assert_eq!(number(1234567, " "), "1 234 567");
+        assert_eq!(number(-123456, "."), "-123.456");
+        assert_eq!(number(1000, ""), "1000");
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(duration(0), "0ms");
+        assert_eq!(duration(250), "250ms");
+        assert_eq!(duration(1500), "1.5s");
+        assert_eq!(duration(2000), "2s");
+        assert_eq!(duration(59_999), "59.9s");
+        assert_eq!(duration(60_000), "1m0s");
+        assert_eq!(duration(3_723_000), "1h2m3s");
+        assert_eq!(duration(90_061_000), "1d1h1m1s");
+        assert_eq!(duration(-1500), "-1.5s");
+    }
+}
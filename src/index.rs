@@ -0,0 +1,194 @@
+use crate::ast::Ast;
+use crate::common::{Context, Symbol};
+use crate::location::Location;
+use crate::semantic;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OccurrenceKind {
+    Definition,
+    Reference,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Occurrence {
+    pub name: Symbol,
+    pub kind: OccurrenceKind,
+    pub location: Location,
+    /// The file and number of the declaration this is or refers to, see
+    /// `semantic::Name`.
+    binding: Option<(Symbol, usize)>,
+}
+
+/// Definitions and references of every variable and function in a set of
+/// files.
+///
+/// Names are resolved by the semantic pass, so a local is told apart from
+/// a global or a variable in another function with the same name. A file
+/// has to parse to be indexed, but it doesn't have to typecheck.
+#[derive(Default)]
+pub struct SymbolIndex {
+    occurrences: Vec<Occurrence>,
+}
+
+impl SymbolIndex {
+    pub fn new() -> Self {
+        SymbolIndex::default()
+    }
+
+    /// Adds the names in `ast`, which was parsed from `file`.
+    pub fn add_file(&mut self, file: Symbol, ast: &Ast, context: &mut Context) {
+        let mut names = semantic::resolve(ast, context);
+        names.sort_by_key(|name| name.location.span.start);
+
+        self.occurrences
+            .extend(names.into_iter().map(|name| Occurrence {
+                name: name.name,
+                kind: if name.declaration {
+                    OccurrenceKind::Definition
+                } else {
+                    OccurrenceKind::Reference
+                },
+                location: name.location,
+                binding: name.binding.map(|binding| (file, binding)),
+            }));
+    }
+
+    pub fn occurrences(
+        &self,
+        name: Symbol,
+    ) -> impl Iterator<Item = &Occurrence> + '_ {
+        self.occurrences.iter().filter(move |occ| occ.name == name)
+    }
+
+    pub fn definitions(
+        &self,
+        name: Symbol,
+    ) -> impl Iterator<Item = &Occurrence> + '_ {
+        self.occurrences(name)
+            .filter(|occ| occ.kind == OccurrenceKind::Definition)
+    }
+
+    pub fn references(
+        &self,
+        name: Symbol,
+    ) -> impl Iterator<Item = &Occurrence> + '_ {
+        self.occurrences(name)
+            .filter(|occ| occ.kind == OccurrenceKind::Reference)
+    }
+
+    /// Returns the occurrence covering the byte `offset` of `file`.
+    pub fn at(&self, file: Symbol, offset: u32) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|occ| {
+            let span = occ.location.span;
+            occ.location.file == file
+                && span.start <= offset
+                && offset < span.start + span.len
+        })
+    }
+
+    /// Every occurrence of the variable or function `occurrence` is or
+    /// refers to, its definition included. Builtins, constants and
+    /// undefined names have none.
+    pub fn bound_with<'a>(
+        &'a self,
+        occurrence: &Occurrence,
+    ) -> impl Iterator<Item = &'a Occurrence> + 'a {
+        let binding = occurrence.binding;
+        self.occurrences
+            .iter()
+            .filter(move |occ| binding.is_some() && occ.binding == binding)
+    }
+
+    /// The definition of the variable or function `occurrence` is or
+    /// refers to.
+    pub fn definition(&self, occurrence: &Occurrence) -> Option<&Occurrence> {
+        self.bound_with(occurrence)
+            .find(|occ| occ.kind == OccurrenceKind::Definition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::builtins;
+    use crate::lexer;
+    use crate::parser;
+
+    fn index(source: &[u8], context: &mut Context) -> SymbolIndex {
+        builtins::register(context);
+        let file = context.interner.intern("test.bl");
+        let tokens = lexer::generate_tokens(source, file, context).unwrap();
+        let ast = parser::parse_ast(tokens, context).unwrap();
+        let mut index = SymbolIndex::new();
+        index.add_file(file, &ast, context);
+        index
+    }
+
+    #[test]
+    fn index_definitions_and_references() {
+        let mut context = Context::new();
+        let index = index(
+            b"fn double(n: int) -> int { return n * 2 }\n\
+              age := 20\n\
+              age = double(age)",
+            &mut context,
+        );
+
+        let age = context.interner.intern("age");
+        let double = context.interner.intern("double");
+        let n = context.interner.intern("n");
+
+        assert_eq!(index.definitions(age).count(), 1);
+        assert_eq!(index.references(age).count(), 2);
+        assert_eq!(index.definitions(double).count(), 1);
+        assert_eq!(index.references(double).count(), 1);
+        assert_eq!(index.definitions(n).count(), 1);
+        assert_eq!(index.references(n).count(), 1);
+    }
+
+    #[test]
+    fn index_lookup_by_offset() {
+        let mut context = Context::new();
+        let index = index(b"age := 20 age += 1", &mut context);
+        let file = context.interner.intern("test.bl");
+        let age = context.interner.intern("age");
+
+        let occurrence = index.at(file, 11).unwrap();
+        assert_eq!(occurrence.name, age);
+        assert_eq!(occurrence.kind, OccurrenceKind::Reference);
+        assert!(index.at(file, 4).is_none());
+    }
+
+    #[test]
+    fn definitions_follow_scopes() {
+        let mut context = Context::new();
+        let source = "n := 1\n\
+                      fn f(n: int) -> int { return n }\n\
+                      m := {n: f(n)}\n\
+                      for n in 0..3 { k := len([n]) }";
+        let index = index(source.as_bytes(), &mut context);
+        let file = context.interner.intern("test.bl");
+        let n = context.interner.intern("n");
+
+        // The map key is a reference, not a definition.
+        assert_eq!(index.definitions(n).count(), 3);
+        assert_eq!(index.references(n).count(), 4);
+
+        let line_of_definition = |offset: usize| {
+            let occurrence = index.at(file, offset as u32).unwrap();
+            index.definition(occurrence).unwrap().location.line
+        };
+        let in_function = source.find("return n").unwrap() + 7;
+        let map_key = source.find("{n").unwrap() + 1;
+        let in_loop = source.find("[n]").unwrap() + 1;
+        assert_eq!(line_of_definition(in_function), 2);
+        assert_eq!(line_of_definition(map_key), 1);
+        assert_eq!(line_of_definition(in_loop), 4);
+
+        let len = source.find("len").unwrap() as u32;
+        let builtin = index.at(file, len).unwrap();
+        assert!(index.definition(builtin).is_none());
+        assert_eq!(index.bound_with(builtin).count(), 0);
+    }
+}
@@ -1,368 +1,1822 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
+
+use log::trace;
+
 use crate::ast::{
-    Assignment, AssignmentKind, Ast, AstNode, AstNodeList, BinaryOp, BinaryOpKind, Decl, Expr,
-    ExprKind, For, FunctionCall, If, Input, Print, Return, UnaryOp, UnaryOpKind,
+    ArgList, Assignment, AssignmentKind, Ast, AstNodeId, BinaryOp,
+    BinaryOpKind, Decl, Expr, ExprKind, For, ForIter, FunctionCall, If, Index,
+    Input, Print, Return, Stmt, StmtList, Try, UnaryOp, UnaryOpKind, VarDecl,
+    While,
 };
-use crate::common::Context;
+use crate::builtins::{self, Builtin, Caller};
+use crate::common::{grow_stack, Context, StringInterner, Symbol};
+use crate::interrupt::{self, Interruptible};
 use crate::location::Location;
-use crate::primitives::{Value, ValueKind};
+use crate::messages::Message;
+use crate::primitives::{
+    int_div, int_rem, Callee, Function, Map, MapKey, Value, ValueKind,
+};
 use crate::scope::Scope;
 
-use std::io::{self, Write};
-use std::rc::Rc;
+#[allow(clippy::result_unit_err)]
+pub fn interpret(
+    ast: &Ast,
+    context: &mut Context,
+) -> std::result::Result<(), ()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = Interruptible(stdin.lock());
+    let mut output = stdout.lock();
+    let mut interpreter =
+        Interpreter::new(ast, context, &mut input, &mut output);
+
+    let result = interpreter.run(&ast.statements, &mut |_, _| {});
+    interpreter.exit(result.map(|_| ()))
+}
 
-pub fn interpret(ast: &Ast, context: &mut Context) {
-    let mut interp = Interpreter::new(context);
-    ast.accept(&mut interp);
+/// Runs `statements` with the variables in `scope`, which is left holding
+/// whatever they declare. Returns the value of the last statement when it
+/// is an expression.
+#[allow(clippy::result_unit_err)]
+pub fn evaluate(
+    ast: &Ast,
+    statements: &StmtList,
+    scope: &mut Scope,
+    context: &mut Context,
+) -> std::result::Result<Option<Value>, ()> {
+    evaluate_each(ast, statements, scope, context, &mut |_, _| {})
 }
 
-struct Interpreter<'a, 'ctxt> {
-    scope: Scope<'a>,
-    values: Vec<Value>,
-    context: &'ctxt mut Context,
+/// Like `evaluate`, also passing the value of every top level expression
+/// statement to `echo` as soon as it has been evaluated.
+pub(crate) fn evaluate_each(
+    ast: &Ast,
+    statements: &StmtList,
+    scope: &mut Scope,
+    context: &mut Context,
+    echo: &mut dyn FnMut(&Value, &StringInterner),
+) -> std::result::Result<Option<Value>, ()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = Interruptible(stdin.lock());
+    let mut output = stdout.lock();
+    evaluate_in(
+        ast,
+        statements,
+        scope,
+        context,
+        &mut input,
+        &mut output,
+        echo,
+    )
+}
+
+/// Like `evaluate_each`, reading from `input` and writing to `output`
+/// instead of standard input and output, which the script running `eval`
+/// already holds.
+pub(crate) fn evaluate_in(
+    ast: &Ast,
+    statements: &StmtList,
+    scope: &mut Scope,
+    context: &mut Context,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    echo: &mut dyn FnMut(&Value, &StringInterner),
+) -> std::result::Result<Option<Value>, ()> {
+    let mut interpreter = Interpreter::new(ast, context, input, output);
+    interpreter.scope = mem::take(scope);
+
+    let result = interpreter.run(statements, echo);
+    *scope = interpreter.scope;
+
+    result
+}
+
+/// Why evaluation of a statement or expression stopped early.
+enum Unwind {
+    /// A runtime error, which has already been reported.
+    Error,
+    /// A `return`, or a `?` on an `Err`, leaving the current function.
+    Return(Value),
+    /// A `break` or `continue`, leaving the innermost loop or its body.
+    Break,
+    Continue,
+}
+
+type Result<T> = std::result::Result<T, Unwind>;
+
+/// Deeper recursion than this would overflow the native stack.
+const MAX_CALL_DEPTH: usize = 10_000;
+
+/// Number of calls shown in the trace printed with a runtime error.
+const MAX_TRACE_LEN: usize = 10;
+
+struct Interpreter<'a> {
+    ast: &'a Ast,
+    scope: Scope,
+    context: &'a mut Context,
     location: Vec<Location>,
+    /// Functions being called and where they were called from.
+    calls: Vec<(Symbol, Location)>,
+    /// The top level expression evaluated last, which the functions given
+    /// to `on_exit` are called from.
+    stopped: Option<Location>,
+    interrupted: &'a AtomicBool,
+    /// When the innermost call to `with_timeout` has to stop, and whether
+    /// it is being stopped.
+    deadline: Option<Instant>,
+    timed_out: bool,
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
 }
 
-impl<'a, 'ctxt> Interpreter<'a, 'ctxt> {
-    pub fn new(context: &'ctxt mut Context) -> Self {
-        Self {
+impl<'a> Interpreter<'a> {
+    fn new(
+        ast: &'a Ast,
+        context: &'a mut Context,
+        input: &'a mut dyn BufRead,
+        output: &'a mut dyn Write,
+    ) -> Self {
+        Interpreter {
+            ast,
             scope: Scope::new(),
-            values: Vec::new(),
-            location: Vec::new(),
             context,
+            location: Vec::new(),
+            calls: Vec::new(),
+            stopped: None,
+            interrupted: interrupt::flag(),
+            deadline: None,
+            timed_out: false,
+            input,
+            output,
         }
     }
 
-    fn value_of<T: Visitable<'a>>(&mut self, node: &'a T) -> Value {
-        node.accept(self);
-        self.values.pop().unwrap_or(Value::Nil)
+    fn run(
+        &mut self,
+        statements: &'a StmtList,
+        echo: &mut dyn FnMut(&Value, &StringInterner),
+    ) -> std::result::Result<Option<Value>, ()> {
+        self.declare_functions(statements);
+
+        let mut last = None;
+        for stmt in statements {
+            let result = match &self.ast.arena[*stmt] {
+                Stmt::Expr(expr) => self.eval_expr(expr).map(Some),
+                stmt => self.exec_stmt(stmt).map(|_| None),
+            };
+
+            last = match result {
+                Ok(value) => value,
+                Err(Unwind::Return(_)) => return Ok(None),
+                Err(Unwind::Error) => return Err(()),
+                Err(Unwind::Break) | Err(Unwind::Continue) => {
+                    unreachable!(
+                        "Loop control outside loops rejected by parser"
+                    )
+                }
+            };
+
+            if let Some(value) = &last {
+                echo(value, &self.context.interner);
+            }
+        }
+
+        Ok(last)
     }
 
-    fn report_error(&mut self, message: &str) {
+    fn report_error(&mut self, message: Message) -> Unwind {
         self.context
-            .error(message, self.location[self.location.len() - 1]);
+            .report_error(message, self.location[self.location.len() - 1]);
+
+        for (name, location) in self.calls.iter().rev().take(MAX_TRACE_LEN) {
+            let file = self.context.interner.get(location.file);
+            let column = self
+                .context
+                .source_code
+                .get(&PathBuf::from(file))
+                .map_or(1, |source| location.column(source.as_bytes()));
+
+            let message = Message::CalledAt {
+                name: self.context.interner.get(*name).to_string(),
+                file: file.to_string(),
+                line: location.line,
+                column,
+            };
+            eprintln!("  {}", message.text(self.context.language));
+        }
+
+        if self.calls.len() > MAX_TRACE_LEN {
+            let message = Message::MoreCalls(self.calls.len() - MAX_TRACE_LEN);
+            eprintln!("  {}", message.text(self.context.language));
+        }
+
+        Unwind::Error
     }
-}
 
-impl<'a, 'ctxt> Visitor<'a> for Interpreter<'a, 'ctxt> {
-    fn visit_stmt_list(&mut self, node: &'a AstNodeList) {
-        trace!("Visit stmt_list");
+    /// Calls the handlers of the signals that arrived since the last check.
+    /// Fails with an error if Ctrl-C was pressed and there is no handler,
+    /// and without one if the deadline of `with_timeout` has passed.
+    fn check_interrupt(&mut self) -> Result<()> {
+        while let Some(signal) = interrupt::take(self.interrupted) {
+            let handler = self.context.handlers.borrow().signal(signal);
+            match handler {
+                Some(func) => {
+                    self.call_function(&func, vec![])
+                        .map_err(|()| Unwind::Error)?;
+                }
+                None => return Err(self.report_error(Message::Interrupted)),
+            }
+        }
+
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.timed_out = true;
+            return Err(Unwind::Error);
+        }
 
-        for stmt in node {
-            stmt.accept(self);
+        Ok(())
+    }
 
-            if let AstNode::Return(_) = stmt {
-                break;
+    /// Calls the functions given to `on_exit`, after the script has ended
+    /// with `result`.
+    fn exit(
+        &mut self,
+        result: std::result::Result<(), ()>,
+    ) -> std::result::Result<(), ()> {
+        // Nothing was evaluated, so nothing was given to `on_exit` either.
+        let location = match self.stopped {
+            Some(location) => location,
+            None => return result,
+        };
+
+        self.location.push(location);
+        let mut i = 0;
+        loop {
+            let handler = self.context.handlers.borrow().exit.get(i).cloned();
+            match handler {
+                Some(func) => self.call_function(&func, vec![])?,
+                None => return result,
+            };
+            i += 1;
+        }
+    }
+
+    fn exec_stmt_list(&mut self, stmts: &'a [AstNodeId]) -> Result<()> {
+        trace!("stmt_list");
+
+        self.declare_functions(stmts);
+
+        for stmt in stmts {
+            self.exec_stmt(&self.ast.arena[*stmt])?;
+        }
+
+        Ok(())
+    }
+
+    /// Functions can be called before they are declared, so every function
+    /// in a statement list is known before any of it runs.
+    fn declare_functions(&mut self, stmts: &'a [AstNodeId]) {
+        for stmt in stmts {
+            if let Stmt::Decl(Decl::Function(func)) = &self.ast.arena[*stmt] {
+                self.scope.add_function(func.name, *stmt);
+            }
+        }
+    }
+
+    fn exec_stmt(&mut self, stmt: &'a Stmt) -> Result<()> {
+        grow_stack(|| {
+            self.context.profile(stmt.name());
+
+            match stmt {
+                Stmt::Assignment(v) => self.exec_assignment(v),
+                Stmt::Block(v) => self.exec_block(v),
+                Stmt::Decl(v) => self.exec_decl(v),
+                Stmt::Expr(v) => self.eval_expr(v).map(|_| ()),
+                Stmt::For(v) => self.exec_for(v),
+                Stmt::While(v) => self.exec_while(v),
+                Stmt::Print(v) => self.exec_print(v),
+                Stmt::If(v) => self.exec_if(v),
+                Stmt::Return(v) => self.exec_return(v),
+                Stmt::Break(_) => Err(Unwind::Break),
+                Stmt::Continue(_) => Err(Unwind::Continue),
+                Stmt::Param(_) => unreachable!(),
+            }
+        })
+    }
+
+    fn exec_block(&mut self, stmts: &'a StmtList) -> Result<()> {
+        trace!("Block");
+
+        self.scope.new_scope_level();
+        let result = self.exec_stmt_list(stmts);
+        self.scope.pop_scope_level();
+
+        result
+    }
+
+    fn exec_decl(&mut self, decl: &'a Decl) -> Result<()> {
+        trace!("Decl");
+
+        match decl {
+            Decl::Variable(var) => self.exec_var_decl(var),
+            // Declared when entering the enclosing statement list.
+            Decl::Function(_) => Ok(()),
+            Decl::Struct(_) => {
+                unreachable!("Structs are rejected by the typechecker")
             }
         }
     }
 
-    fn visit_stmt(&mut self, node: &'a AstNode) {
-        trace!("Visit stmt");
-        node.accept(self)
+    fn exec_return(&mut self, ret: &'a Return) -> Result<()> {
+        trace!("Return");
+
+        let value = match ret.value {
+            Some(value) => self.eval(value)?,
+            None => Value::Nil,
+        };
+
+        Err(Unwind::Return(value))
     }
 
-    fn visit_expr(&mut self, node: &'a Expr) {
-        trace!("Visit expr");
-        self.location.push(node.location);
+    fn exec_var_decl(&mut self, var: &'a VarDecl) -> Result<()> {
+        trace!("Var decl");
+
+        let value = self.eval(var.value)?;
+        let kind = kind_of(&value);
+        self.scope.add_variable(var.name, value, kind);
 
-        match &node.kind {
-            ExprKind::Bool(v) => self.values.push(Value::Bool(*v)),
-            ExprKind::Float(v) => self.values.push(Value::Float(*v)),
-            ExprKind::Integer(v) => self.values.push(Value::Int(*v)),
-            ExprKind::StringLiteral(v) => self.values.push(Value::String(v.clone())),
-            ExprKind::Ident(v) => self.visit_ident(v),
-            ExprKind::UnaryOp(v) => self.visit_unary_op(v),
-            ExprKind::BinaryOp(v) => self.visit_binary_op(v),
-            ExprKind::FunctionCall(v) => self.visit_function_call(v),
-            ExprKind::Input(v) => self.visit_input(v),
-            ExprKind::Range(_v) => unimplemented!(),
+        Ok(())
+    }
+
+    fn exec_assignment(&mut self, assignment: &'a Assignment) -> Result<()> {
+        trace!("Assignment");
+
+        let value = self.eval(assignment.value)?;
+
+        let mut indices = Vec::with_capacity(assignment.indices.len());
+        for index in &assignment.indices {
+            indices.push(self.eval(*index)?);
         }
 
+        self.location.push(assignment.location);
+        let result = self.assign(assignment, &indices, value);
         self.location.pop();
+
+        result
     }
 
-    fn visit_decl(&mut self, node: &'a Decl) {
-        trace!("Visit decl");
+    fn assign(
+        &mut self,
+        assignment: &'a Assignment,
+        indices: &[Value],
+        value: Value,
+    ) -> Result<()> {
+        let op = match assignment.op {
+            AssignmentKind::Assign => None,
+            AssignmentKind::Add => Some(BinaryOpKind::Add),
+            AssignmentKind::Sub => Some(BinaryOpKind::Sub),
+            AssignmentKind::Mul => Some(BinaryOpKind::Mul),
+            AssignmentKind::Div => Some(BinaryOpKind::Div),
+            AssignmentKind::Mod => Some(BinaryOpKind::Mod),
+        };
 
-        match node {
-            Decl::Function(func) => self.scope.add_function(func),
-            Decl::Variable(var) => {
-                let value = self.value_of(&var.value);
-                self.scope.add_variable(&var.name, value, var.kind.clone());
+        let value = match op {
+            Some(op) => {
+                let current = self.with_place(
+                    assignment.ident,
+                    indices,
+                    false,
+                    |place, _| place.clone(),
+                )?;
+                self.arithmetic(op, current, value)?
             }
-            Decl::Struct(decl) => self.scope.add_struct(decl),
+            None => value,
+        };
+
+        self.with_place(assignment.ident, indices, true, |place, _| {
+            *place = value
+        })
+    }
+
+    /// Runs `f` on the variable `ident`, or on the element of the array or
+    /// map stored in it that `indices` lead to. With `insert`, a key missing
+    /// from the last map is added to it.
+    fn with_place<T>(
+        &mut self,
+        ident: Symbol,
+        indices: &[Value],
+        insert: bool,
+        f: impl FnOnce(&mut Value, &StringInterner) -> T,
+    ) -> Result<T> {
+        let var = self
+            .scope
+            .get_variable_mut(ident)
+            .expect("Assignment to undefined variable");
+
+        let interner = &self.context.interner;
+        let result = element_mut(&mut var.value, indices, insert, interner)
+            .map(|place| f(place, interner));
+
+        result.map_err(|message| self.report_error(message))
+    }
+
+    fn exec_if(&mut self, if_stmt: &'a If) -> Result<()> {
+        trace!("If");
+
+        if self.eval_condition(if_stmt.cond)? {
+            self.exec_block(&if_stmt.body)
+        } else if let Some(else_body) = &if_stmt.else_body {
+            self.exec_block(else_body)
+        } else {
+            Ok(())
         }
     }
 
-    fn visit_binary_op(&mut self, node: &'a BinaryOp) {
-        trace!("Visit binop");
+    fn exec_while(&mut self, while_loop: &'a While) -> Result<()> {
+        trace!("While");
 
-        match node.op {
-            BinaryOpKind::Add => {
-                let value = self.value_of(&*node.lhs) + self.value_of(&*node.rhs);
-                self.values.push(value);
+        while self.eval_condition(while_loop.cond)? {
+            match self.exec_block(&while_loop.body) {
+                Err(Unwind::Break) => break,
+                Ok(()) | Err(Unwind::Continue) => {}
+                Err(unwind) => return Err(unwind),
             }
-            BinaryOpKind::Sub => {
-                let value = self.value_of(&*node.lhs) - self.value_of(&*node.rhs);
-                self.values.push(value);
+        }
+
+        Ok(())
+    }
+
+    /// Stores all but the first element of `item` in the variables of
+    /// `for a, b in items` after the first, and returns the first.
+    fn unpack_item(&mut self, for_loop: &For, item: Value) -> Result<Value> {
+        if for_loop.unpack.is_empty() {
+            return Ok(item);
+        }
+
+        self.location.push(for_loop.location);
+        let elements = unpack(item, for_loop.unpack.len() + 1)
+            .map_err(|message| self.report_error(message));
+        self.location.pop();
+
+        let mut elements = elements?.into_iter();
+        let first = elements.next().unwrap();
+        for (name, element) in for_loop.unpack.iter().zip(elements) {
+            self.scope.get_variable_mut(*name).unwrap().value = element;
+        }
+        Ok(first)
+    }
+
+    fn exec_for(&mut self, for_loop: &'a For) -> Result<()> {
+        trace!("For");
+
+        let items: Box<dyn Iterator<Item = Value>> = match &for_loop.iter {
+            ForIter::Range(range) => {
+                Box::new((range.start..range.end).map(Value::Int))
             }
-            BinaryOpKind::Mul => {
-                let value = self.value_of(&*node.lhs) * self.value_of(&*node.rhs);
-                self.values.push(value);
+            ForIter::Expr(node) => match self.eval(*node)? {
+                Value::Array(array) => {
+                    Box::new((0..array.len()).map(move |i| array[i].clone()))
+                }
+                Value::Map(map) => {
+                    let keys: Vec<_> =
+                        map.iter().map(|(key, _)| key.to_value()).collect();
+                    Box::new(keys.into_iter())
+                }
+                // Run to the end first, the same way the VM does.
+                iter @ (Value::Iter(_) | Value::Deque(_)) => {
+                    self.location.push(for_loop.location);
+                    let items = builtins::items(&iter, self);
+                    self.location.pop();
+                    Box::new(items.map_err(|()| Unwind::Error)?.into_iter())
+                }
+                other => {
+                    self.location.push(for_loop.location);
+                    let unwind = self
+                        .report_error(Message::CannotIterate(kind_of(&other)));
+                    self.location.pop();
+                    return Err(unwind);
+                }
+            },
+        };
+
+        self.scope.new_scope_level();
+        self.scope
+            .add_variable(for_loop.ident, Value::Nil, ValueKind::Nil);
+
+        for name in &for_loop.unpack {
+            self.scope.add_variable(*name, Value::Nil, ValueKind::Nil);
+        }
+
+        let mut result = Ok(());
+        for item in items {
+            let item = match self.unpack_item(for_loop, item) {
+                Ok(item) => item,
+                Err(unwind) => {
+                    result = Err(unwind);
+                    break;
+                }
+            };
+            self.scope.get_variable_mut(for_loop.ident).unwrap().value = item;
+
+            self.location.push(for_loop.location);
+            result = self.check_interrupt();
+            self.location.pop();
+
+            if result.is_ok() {
+                result = match self.exec_block(&for_loop.block) {
+                    Err(Unwind::Continue) => Ok(()),
+                    Err(Unwind::Break) => break,
+                    result => result,
+                };
             }
-            BinaryOpKind::Div => {
-                let value = self.value_of(&*node.lhs) / self.value_of(&*node.rhs);
-                self.values.push(value);
+            if result.is_err() {
+                break;
             }
-            BinaryOpKind::Mod => {
-                let value = self.value_of(&*node.lhs) % self.value_of(&*node.rhs);
-                self.values.push(value);
+        }
+
+        self.scope.pop_scope_level();
+        result
+    }
+
+    fn exec_print(&mut self, print: &'a Print) -> Result<()> {
+        trace!("Print");
+
+        if print.args.is_empty() {
+            return self.write(if print.newline { "\n" } else { "" });
+        }
+
+        let mut args = Vec::with_capacity(print.args.len());
+        for arg in &print.args {
+            args.push(self.eval(*arg)?);
+        }
+
+        let format_location = self.ast.arena[print.args[0]].expr().location;
+        self.location.push(format_location);
+        let result =
+            match format_output(args, print.newline, &self.context.interner) {
+                Ok(output) => self.write(&output),
+                Err(message) => Err(self.report_error(message)),
+            };
+        self.location.pop();
+
+        result
+    }
+
+    fn write(&mut self, output: &str) -> Result<()> {
+        self.output
+            .write_all(output.as_bytes())
+            .and_then(|_| self.output.flush())
+            .expect("Failed to write to stdout");
+
+        Ok(())
+    }
+
+    fn eval(&mut self, node: AstNodeId) -> Result<Value> {
+        self.eval_expr(self.ast.arena[node].expr())
+    }
+
+    fn eval_condition(&mut self, node: AstNodeId) -> Result<bool> {
+        let value = self.eval(node)?;
+        Ok(value.is_truthy(&self.context.interner))
+    }
+
+    fn eval_expr(&mut self, expr: &'a Expr) -> Result<Value> {
+        trace!("Expr");
+
+        grow_stack(|| {
+            self.location.push(expr.location);
+            self.context.profile(expr.kind.name());
+
+            if let Err(unwind) = self.check_interrupt() {
+                self.location.pop();
+                return Err(unwind);
             }
-            BinaryOpKind::Equal => {
-                let value = Value::Bool(self.value_of(&*node.lhs) == self.value_of(&*node.rhs));
-                self.values.push(value);
+
+            let value = match &expr.kind {
+                ExprKind::Bool(v) => Ok(Value::Bool(*v)),
+                ExprKind::Float(v) => Ok(Value::Float(*v)),
+                ExprKind::Integer(v) => Ok(Value::Int(*v)),
+                ExprKind::StringLiteral(v) => Ok(Value::String(*v)),
+                ExprKind::Interpolation(v) => self.eval_interpolation(v),
+                ExprKind::Ident(v) => Ok(self.eval_ident(*v)),
+                ExprKind::Range(_) => unreachable!(
+                    "Ranges outside for loops are rejected by the typechecker"
+                ),
+                ExprKind::Input(v) => self.eval_input(v),
+                ExprKind::UnaryOp(v) => self.eval_unary_op(v),
+                ExprKind::BinaryOp(v) => self.eval_binop(v),
+                ExprKind::FunctionCall(v) => self.eval_function_call(v),
+                ExprKind::Try(v) => self.eval_try(v),
+                ExprKind::Array(v) => self.eval_array(v),
+                ExprKind::Map(v) => self.eval_map(v),
+                ExprKind::Index(v) => self.eval_index(v),
+            };
+
+            self.location.pop();
+            if self.location.is_empty() {
+                self.stopped = Some(expr.location);
             }
-            BinaryOpKind::NotEqual => {
-                let value = Value::Bool(self.value_of(&*node.lhs) != self.value_of(&*node.rhs));
-                self.values.push(value);
+
+            value
+        })
+    }
+
+    fn eval_ident(&mut self, ident: Symbol) -> Value {
+        trace!("Ident");
+
+        if let Some(var) = self.scope.get_variable(ident) {
+            return var.value.clone();
+        }
+
+        if let Some(value) = self.context.constants.get(&ident) {
+            return value.clone();
+        }
+
+        let callee = match self.scope.get_function(ident) {
+            Some(node) => Callee::Node(node),
+            None if self.context.native_functions.contains_key(&ident) => {
+                Callee::Native
             }
-            BinaryOpKind::Lesser => {
-                let value = Value::Bool(self.value_of(&*node.lhs) < self.value_of(&*node.rhs));
-                self.values.push(value);
+            None => unreachable!("Undefined variables rejected by typechecker"),
+        };
+
+        Value::Function(Function {
+            name: ident,
+            callee,
+        })
+    }
+
+    fn eval_interpolation(&mut self, parts: &'a ArgList) -> Result<Value> {
+        trace!("Interpolation");
+
+        let mut values = Vec::with_capacity(parts.len());
+        for part in parts {
+            values.push(self.eval(*part)?);
+        }
+
+        Ok(concat(&values, &mut self.context.interner))
+    }
+
+    fn eval_binop(&mut self, binop: &'a BinaryOp) -> Result<Value> {
+        trace!("Binop");
+
+        use BinaryOpKind::*;
+
+        // The logical operators short circuit, so the right hand side is
+        // only evaluated when needed.
+        match binop.op {
+            And => {
+                return if self.eval_condition(binop.lhs)? {
+                    self.eval_condition(binop.rhs).map(Value::Bool)
+                } else {
+                    Ok(Value::Bool(false))
+                };
             }
-            BinaryOpKind::LesserEqual => {
-                let value = Value::Bool(self.value_of(&*node.lhs) <= self.value_of(&*node.rhs));
-                self.values.push(value);
+            Or => {
+                return if self.eval_condition(binop.lhs)? {
+                    Ok(Value::Bool(true))
+                } else {
+                    self.eval_condition(binop.rhs).map(Value::Bool)
+                };
             }
-            BinaryOpKind::Greater => {
-                let value = Value::Bool(self.value_of(&*node.lhs) > self.value_of(&*node.rhs));
-                self.values.push(value);
+            _ => {}
+        }
+
+        let lhs = self.eval(binop.lhs)?;
+        let rhs = self.eval(binop.rhs)?;
+
+        match binop.op {
+            Equal => Ok(Value::Bool(lhs == rhs)),
+            NotEqual => Ok(Value::Bool(lhs != rhs)),
+            Greater | GreaterEqual | Lesser | LesserEqual => {
+                order(binop.op, &lhs, &rhs, &self.context.interner)
+                    .map(Value::Bool)
+                    .map_err(|message| self.report_error(message))
             }
-            BinaryOpKind::GreaterEqual => {
-                let value = Value::Bool(self.value_of(&*node.lhs) >= self.value_of(&*node.rhs));
-                self.values.push(value);
+            Add | Sub | Mul | Div | Mod => self.arithmetic(binop.op, lhs, rhs),
+            Field => {
+                unreachable!("Field access is rejected by the typechecker")
             }
-            BinaryOpKind::Field => unimplemented!(),
-            BinaryOpKind::And => match (self.value_of(&*node.lhs), self.value_of(&*node.rhs)) {
-                (Value::Bool(a), Value::Bool(b)) => self.values.push(Value::Bool(a && b)),
-                (a, b) => self.report_error(&format!(
-                    "Can only use logical operator AND with bools, found {} {}",
-                    a, b
-                )),
-            },
-            BinaryOpKind::Or => match (self.value_of(&*node.lhs), self.value_of(&*node.rhs)) {
-                (Value::Bool(a), Value::Bool(b)) => self.values.push(Value::Bool(a || b)),
-                (a, b) => self.report_error(&format!(
-                    "Can only use logical operators with bools OR, found {} {}",
-                    a, b
-                )),
-            },
+            And | Or => unreachable!(),
         }
     }
 
-    fn visit_unary_op(&mut self, node: &'a UnaryOp) {
-        trace!("Visit unary_op");
+    fn arithmetic(
+        &mut self,
+        op: BinaryOpKind,
+        lhs: Value,
+        rhs: Value,
+    ) -> Result<Value> {
+        arithmetic(op, lhs, rhs, &mut self.context.interner)
+            .map_err(|message| self.report_error(message))
+    }
 
-        match node.op {
-            UnaryOpKind::Not => match self.value_of(&*node.value) {
-                Value::Bool(n) => self.values.push(Value::Bool(!n)),
-                _ => panic!("Cannot negate non boolean expression"),
-            },
-            UnaryOpKind::Neg => match self.value_of(&*node.value) {
-                Value::Int(n) => self.values.push(Value::Int(-n)),
-                Value::Float(n) => self.values.push(Value::Float(-n)),
-                Value::Bool(_) => self.report_error("Cannot have negative booleans"),
-                Value::String(_) => self.report_error("Cannot have negative strings"),
-                Value::Nil => self.report_error("Cannot have negative nil"),
-                Value::Struct(_) => self.report_error("Cannot have negative struct"),
-            },
+    fn eval_unary_op(&mut self, unary_op: &'a UnaryOp) -> Result<Value> {
+        trace!("Unary op");
+
+        match unary_op.op {
+            UnaryOpKind::Not => {
+                let value = self.eval_condition(unary_op.value)?;
+                Ok(Value::Bool(!value))
+            }
+            UnaryOpKind::Neg => {
+                let value = self.eval(unary_op.value)?;
+                negate(value).map_err(|message| self.report_error(message))
+            }
         }
     }
 
-    fn visit_function_call(&mut self, node: &'a FunctionCall) {
-        trace!("Visit funcall");
+    fn eval_function_call(&mut self, call: &'a FunctionCall) -> Result<Value> {
+        trace!("Function call");
 
-        let func = self
-            .scope
-            .get_function(&node.name)
-            .unwrap_or_else(|| panic!("Unknown function {}", node.name));
-
-        assert!(
-            func.params.len() == node.args.len(),
-            format!(
-                "Arg/Param count mismatch. Expected {} args but got {}",
-                func.params.len(),
-                node.args.len()
-            )
-        );
+        let ok = self.context.interner.intern("Ok");
+        let err = self.context.interner.intern("Err");
+
+        if call.name == ok || call.name == err {
+            let value = self.eval(call.args[0])?;
+            return Ok(if call.name == ok {
+                Value::Ok(Box::new(value))
+            } else {
+                Value::Err(Box::new(value))
+            });
+        }
+
+        // Functions declared by the script take precedence over builtins.
+        let func = match self.scope.get_function(call.name) {
+            Some(func) => func,
+            None => match self.eval_builtin_call(call) {
+                Some(value) => return value,
+                None => return self.call_native(call),
+            },
+        };
+
+        let mut args = Vec::with_capacity(call.args.len());
+        for arg in &call.args {
+            args.push(self.eval(*arg)?);
+        }
+
+        self.call_script(call.name, func, args)
+    }
 
-        let arg_values: Vec<Value> = node.args.iter().map(|arg| self.value_of(arg)).collect();
+    /// Calls the function declared at `node`.
+    fn call_script(
+        &mut self,
+        name: Symbol,
+        node: AstNodeId,
+        args: Vec<Value>,
+    ) -> Result<Value> {
+        if self.calls.len() == MAX_CALL_DEPTH {
+            return Err(self.report_error(Message::TooManyCalls));
+        }
 
+        let func = self.ast.arena[node].function();
+        let location = self.location[self.location.len() - 1];
+        self.calls.push((name, location));
         self.scope.push_scope();
-        for (param, value) in func.params.iter().zip(arg_values) {
-            let kind = match value {
-                Value::Bool(_) => ValueKind::Bool,
-                Value::Int(_) => ValueKind::Integer,
-                Value::Float(_) => ValueKind::Float,
-                Value::String(_) => ValueKind::String,
-                _ => ValueKind::Nil,
-            };
-            self.scope.add_variable(&param.name, value, kind);
+
+        for (param, value) in func.params.iter().zip(args) {
+            let param = self.ast.arena[*param].param();
+            self.scope
+                .add_variable(param.name, value, param.kind.clone());
         }
 
-        func.body.accept(self);
+        let result = self.exec_stmt_list(&func.body);
+
         self.scope.pop_scope();
-    }
+        self.calls.pop();
 
-    fn visit_if_stmt(&mut self, node: &'a If) {
-        trace!("Visit if stmt");
+        match result {
+            Ok(()) => Ok(Value::Nil),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error) => Err(Unwind::Error),
+            Err(Unwind::Break) | Err(Unwind::Continue) => {
+                unreachable!("Loop control outside loops rejected by parser")
+            }
+        }
+    }
 
-        let condition = self.value_of(&node.cond);
-        if condition == Value::Bool(true) {
-            node.body.accept(self);
-        } else if let Some(ref else_body) = node.else_body {
-            else_body.accept(self);
+    fn call_native(&mut self, call: &'a FunctionCall) -> Result<Value> {
+        let mut args = Vec::with_capacity(call.args.len());
+        for arg in &call.args {
+            args.push(self.eval(*arg)?);
         }
+
+        self.call_native_with(call.name, args)
     }
 
-    fn visit_ident(&mut self, node: &'a str) {
-        trace!("Visit ident");
+    fn call_native_with(
+        &mut self,
+        name: Symbol,
+        args: Vec<Value>,
+    ) -> Result<Value> {
+        let func = match self.context.native_functions.get(&name) {
+            Some(func) => Rc::clone(func),
+            None => unreachable!("Undefined functions rejected by typechecker"),
+        };
 
-        let var = self
-            .scope
-            .get_variable(&node)
-            .unwrap_or_else(|| panic!("Unknown ident {}", node));
-        self.values.push(var.value.clone());
+        builtins::check_arguments(name, &func, &args, &self.context.interner)
+            .map_err(|message| self.report_error(message))?;
+        builtins::check_capability(
+            name,
+            &func,
+            &args,
+            self.context,
+            self.input,
+        )
+        .map_err(|message| self.report_error(message))?;
+
+        Ok((func.func)(&args, &mut self.context.interner))
     }
 
-    fn visit_return(&mut self, node: &'a Return) {
-        trace!("Visit return");
+    /// Runs a call to one of the builtins that work on several types.
+    /// Returns `None` if `call` is not one of them.
+    fn eval_builtin_call(
+        &mut self,
+        call: &'a FunctionCall,
+    ) -> Option<Result<Value>> {
+        let builtin = Builtin::from_name(self.context.interner.get(call.name))?;
+
+        if builtin.changes_array() {
+            return Some(self.change_array(builtin, call));
+        }
+
+        let mut args = Vec::with_capacity(call.args.len());
+        for arg in &call.args {
+            match self.eval(*arg) {
+                Ok(value) => args.push(value),
+                Err(unwind) => return Some(Err(unwind)),
+            }
+        }
 
-        if let Some(ref expr) = node.value {
-            let value = self.value_of(expr);
-            self.values.push(value);
+        if builtin.calls_functions() {
+            return Some(builtin.run(&args, self).map_err(|()| Unwind::Error));
         }
+
+        Some(
+            builtin
+                .call(&args, &self.context.limits, &mut self.context.interner)
+                .map_err(|message| self.report_error(message)),
+        )
     }
 
-    fn visit_block(&mut self, node: &'a AstNodeList) {
-        trace!("Visit block");
-        self.scope.new_scope_level();
-        self.visit_stmt_list(node);
-        self.scope.pop_scope_level();
+    /// Runs `push` or `pop` on the array that the first argument of `call`
+    /// refers to.
+    fn change_array(
+        &mut self,
+        builtin: Builtin,
+        call: &'a FunctionCall,
+    ) -> Result<Value> {
+        let (ident, indices) = self.eval_place(call.args[0])?;
+        let mut args = Vec::with_capacity(call.args.len() - 1);
+        for arg in &call.args[1..] {
+            args.push(self.eval(*arg)?);
+        }
+
+        let limits = self.context.limits;
+        let result =
+            self.with_place(ident, &indices, false, |place, interner| {
+                builtin.change(place, args, &limits, interner)
+            })?;
+
+        result.map_err(|message| self.report_error(message))
     }
 
-    fn visit_assignment(&mut self, node: &'a Assignment) {
-        trace!("Visit assignment");
+    /// Evaluates the indices of `xs[i][j]` or `m[key]`, or of a plain
+    /// variable.
+    fn eval_place(&mut self, node: AstNodeId) -> Result<(Symbol, Vec<Value>)> {
+        match &self.ast.arena[node].expr().kind {
+            ExprKind::Ident(ident) => Ok((*ident, vec![])),
+            ExprKind::Index(index) => {
+                let (ident, mut indices) = self.eval_place(index.value)?;
+                indices.push(self.eval(index.index)?);
+                Ok((ident, indices))
+            }
+            _ => unreachable!("Checked by typechecker"),
+        }
+    }
 
-        let value = self.value_of(&node.value);
-        let var = self
-            .scope
-            .get_variable_mut(&node.ident)
-            .unwrap_or_else(|| panic!("Unknown ident {}", node.ident));
+    fn eval_array(&mut self, elements: &'a [AstNodeId]) -> Result<Value> {
+        trace!("Array");
 
-        match node.op {
-            AssignmentKind::Assign => var.value = value,
-            AssignmentKind::Add => var.value += value,
-            AssignmentKind::Sub => var.value -= value,
-            AssignmentKind::Mul => var.value *= value,
-            AssignmentKind::Div => var.value /= value,
-            AssignmentKind::Mod => var.value %= value,
+        let mut values = Vec::with_capacity(elements.len());
+        for element in elements {
+            values.push(self.eval(*element)?);
         }
+
+        Ok(Value::Array(Rc::new(values)))
     }
 
-    fn visit_print(&mut self, node: &'a Print) {
-        trace!("Visit print");
+    fn eval_map(
+        &mut self,
+        entries: &'a [(AstNodeId, AstNodeId)],
+    ) -> Result<Value> {
+        trace!("Map");
 
-        if let Some(fmt_string) = node.args.get(0) {
-            let fmt_string = match self.value_of(fmt_string) {
-                Value::String(v) => v,
-                _ => panic!("Cannot print without format string"),
+        let mut map = Map::new();
+        for (key, value) in entries {
+            let key = self.eval(*key)?;
+            let key = match map_key(&key) {
+                Ok(key) => key,
+                Err(message) => return Err(self.report_error(message)),
             };
+            map.insert(key, self.eval(*value)?);
+        }
 
-            let mut num_fmt_args = 0;
-            let mut output = String::with_capacity(fmt_string.len());
-            let mut escaped = false;
-
-            for ch in fmt_string.chars() {
-                if escaped {
-                    match ch {
-                        '%' => output.push('%'),
-                        'n' => output.push('\n'),
-                        'r' => output.push('\r'),
-                        't' => output.push('\t'),
-                        c => output.push(c),
-                    }
-                    escaped = false;
-                } else if ch == '\\' {
-                    escaped = true;
-                } else if ch == '%' {
-                    match node
-                        .args
-                        .get(num_fmt_args + 1)
-                        .map(|arg| self.value_of(arg))
-                    {
-                        Some(Value::String(n)) => output.extend(n.chars()),
-                        Some(Value::Int(n)) => output.push_str(&format!("{}", n)),
-                        Some(Value::Float(n)) => output.push_str(&format!("{}", n)),
-                        Some(Value::Bool(n)) => output.push_str(&format!("{}", n)),
-                        Some(Value::Nil) => output.push_str("nil"),
-                        Some(Value::Struct(_)) => unimplemented!(),
-                        None => panic!("Expected format arg {}, but none found", num_fmt_args),
-                    }
-                    num_fmt_args += 1;
-                } else {
-                    output.push(ch);
-                }
+        Ok(Value::Map(Rc::new(map)))
+    }
+
+    fn eval_index(&mut self, index: &'a Index) -> Result<Value> {
+        trace!("Index");
+
+        let value = self.eval(index.value)?;
+        let index = self.eval(index.index)?;
+
+        match lookup(&value, &index, &mut self.context.interner) {
+            Ok(element) => Ok(element),
+            Err(message) => Err(self.report_error(message)),
+        }
+    }
+
+    fn eval_try(&mut self, try_expr: &'a Try) -> Result<Value> {
+        trace!("Try");
+
+        match self.eval(try_expr.value)? {
+            Value::Ok(value) => Ok(*value),
+            err @ Value::Err(_) => Err(Unwind::Return(err)),
+            other => {
+                Err(self.report_error(Message::TryOnNonResult(kind_of(&other))))
             }
+        }
+    }
 
-            assert!(
-                num_fmt_args == node.args.len() - 1,
-                format!(
-                    "Format string expected {} arguments, found {}",
-                    num_fmt_args,
-                    node.args.len() - 1
-                )
-            );
-            print!("{}", output);
-            io::stdout().flush().expect("Failed to flush stdout");
+    fn eval_input(&mut self, input: &'a Input) -> Result<Value> {
+        trace!("Input");
+
+        if let Some(message) = input.message {
+            let message = self.eval(message)?;
+            let message = message.format(&self.context.interner);
+            self.write(&message)?;
         }
+
+        let mut line = String::new();
+        while let Err(error) = self.input.read_line(&mut line) {
+            // A signal handled by the script lets it go back to waiting for
+            // the rest of the line.
+            if !interrupt::pending() {
+                let message = Message::CannotReadFile {
+                    path: "<stdin>".to_string(),
+                    error: error.to_string(),
+                };
+                return Err(self.report_error(message));
+            }
+            self.check_interrupt()?;
+        }
+
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        Ok(Value::String(self.context.interner.intern(line)))
+    }
+}
+
+impl Caller for Interpreter<'_> {
+    fn call_declared(
+        &mut self,
+        func: &Function,
+        args: Vec<Value>,
+    ) -> std::result::Result<Value, ()> {
+        let result = match func.callee {
+            Callee::Node(node) => self.call_script(func.name, node, args),
+            Callee::Native => self.call_native_with(func.name, args),
+            Callee::Compiled(_) => unreachable!("Only the VM runs bytecode"),
+            Callee::Partial(_) | Callee::Compose(_) => {
+                unreachable!("Unwrapped by call_function")
+            }
+        };
+        result.map_err(|_| ())
     }
 
-    fn visit_input(&mut self, node: &'a Input) {
-        trace!("Visit input");
+    fn call_until(
+        &mut self,
+        func: &Function,
+        args: Vec<Value>,
+        deadline: Instant,
+    ) -> std::result::Result<Option<Value>, ()> {
+        let outer = self.deadline;
+        self.deadline = Some(outer.map_or(deadline, |d| d.min(deadline)));
+        let result = self.call_function(func, args);
+        self.deadline = outer;
 
-        match node.message.as_ref().map(|m| self.value_of(&**m)) {
-            Some(Value::String(v)) => {
-                print!("{}", v);
-                io::stdout().flush().expect("Failed to flush stdout");
+        // Stopping for an outer deadline stops the outer call as well.
+        let outer_passed = outer.is_some_and(|d| Instant::now() >= d);
+        match result {
+            Err(()) if self.timed_out && !outer_passed => {
+                self.timed_out = false;
+                Ok(None)
             }
-            Some(_) => panic!("Can only print strings"),
-            None => {}
+            result => result.map(Some),
         }
+    }
 
-        let mut buf = String::new();
-        io::stdin()
-            .read_line(&mut buf)
-            .expect("Failed to read from stdin");
+    fn poll_interrupt(&mut self) -> std::result::Result<(), ()> {
+        self.check_interrupt().map_err(|_| ())
+    }
 
-        buf.pop(); // Remove newline
+    fn interner(&mut self) -> &mut StringInterner {
+        &mut self.context.interner
+    }
 
-        self.values.push(Value::String(Rc::new(buf)));
+    fn declared_arity(&mut self, func: &Function) -> usize {
+        match func.callee {
+            Callee::Node(node) => self.ast.arena[node].function().params.len(),
+            _ => unreachable!("Not a function of the script: {:?}", func),
+        }
     }
 
-    fn visit_for(&mut self, node: &'a For) {
-        trace!("Visit for");
+    fn context(&mut self) -> &mut Context {
+        self.context
+    }
 
-        self.scope.new_scope_level();
-        self.scope
-            .add_variable(&node.ident, Value::Int(0), ValueKind::Integer);
+    fn evaluate(
+        &mut self,
+        ast: &Ast,
+        statements: &StmtList,
+        scope: &mut Scope,
+    ) -> std::result::Result<Option<Value>, ()> {
+        let (input, output) = (&mut *self.input, &mut *self.output);
+        evaluate_in(
+            ast,
+            statements,
+            scope,
+            self.context,
+            input,
+            output,
+            &mut |_, _| {},
+        )
+    }
+
+    fn report(&mut self, message: Message) {
+        self.report_error(message);
+    }
+}
+
+/// Applies one of the arithmetic operators, or returns the error to report.
+pub(crate) fn arithmetic(
+    op: BinaryOpKind,
+    lhs: Value,
+    rhs: Value,
+    interner: &mut StringInterner,
+) -> std::result::Result<Value, Message> {
+    use BinaryOpKind::*;
+
+    match (&lhs, &rhs) {
+        (Value::Int(a), Value::Int(b)) if matches!(op, Div) => {
+            Ok(Value::Int(int_div(*a, *b)?))
+        }
+        (Value::Int(a), Value::Int(b)) if matches!(op, Mod) => {
+            Ok(Value::Int(int_rem(*a, *b)?))
+        }
+        (Value::Int(_), Value::Int(_))
+        | (Value::Int(_), Value::Float(_))
+        | (Value::Float(_), Value::Int(_))
+        | (Value::Float(_), Value::Float(_)) => Ok(match op {
+            Add => lhs + rhs,
+            Sub => lhs - rhs,
+            Mul => lhs * rhs,
+            Div => lhs / rhs,
+            Mod => lhs % rhs,
+            _ => unreachable!(),
+        }),
+        (Value::String(_), Value::String(_))
+        | (Value::String(_), Value::Int(_))
+        | (Value::String(_), Value::Float(_))
+        | (Value::String(_), Value::Bool(_))
+        | (Value::Int(_), Value::String(_))
+        | (Value::Float(_), Value::String(_))
+        | (Value::Bool(_), Value::String(_))
+            if matches!(op, Add) =>
+        {
+            Ok(concat(&[lhs, rhs], interner))
+        }
+        _ => Err(Message::InvalidOperands(kind_of(&lhs), kind_of(&rhs), op)),
+    }
+}
 
-        for i in node.range.start..node.range.end {
-            let var = self.scope.get_variable_mut(&node.ident).unwrap();
-            var.value = Value::Int(i);
-            node.block.accept(self);
+/// Joins `values` into a string, each shown the way `print` shows it.
+pub(crate) fn concat(values: &[Value], interner: &mut StringInterner) -> Value {
+    let mut string = String::new();
+    for value in values {
+        match value {
+            Value::String(s) => string.push_str(interner.get(*s)),
+            other => string.push_str(&other.format(interner)),
         }
+    }
 
-        self.scope.pop_scope_level();
+    Value::String(interner.intern(&string))
+}
+
+/// Applies `>`, `>=`, `<` or `<=`.
+pub(crate) fn order(
+    op: BinaryOpKind,
+    lhs: &Value,
+    rhs: &Value,
+    interner: &StringInterner,
+) -> std::result::Result<bool, Message> {
+    use BinaryOpKind::*;
+
+    let ordering = lhs
+        .compare(rhs, interner)
+        .ok_or_else(|| Message::CannotOrder(kind_of(lhs), kind_of(rhs)))?;
+
+    Ok(match op {
+        Greater => ordering == Ordering::Greater,
+        GreaterEqual => ordering != Ordering::Less,
+        Lesser => ordering == Ordering::Less,
+        LesserEqual => ordering != Ordering::Greater,
+        _ => unreachable!(),
+    })
+}
+
+pub(crate) fn negate(value: Value) -> std::result::Result<Value, Message> {
+    match value {
+        Value::Int(n) => Ok(Value::Int(n.wrapping_neg())),
+        Value::Float(n) => Ok(Value::Float(-n)),
+        other => Err(Message::CannotNegate(kind_of(&other))),
+    }
+}
+
+/// The text printed by `print` or `println`, where each `%` in the format
+/// string that is the first argument is replaced by the next argument.
+pub(crate) fn format_output(
+    args: Vec<Value>,
+    newline: bool,
+    interner: &StringInterner,
+) -> std::result::Result<String, Message> {
+    let mut args = args.into_iter();
+    let format = match args.next() {
+        Some(Value::String(format)) => interner.get(format),
+        Some(other) => return Err(Message::PrintFormat(kind_of(&other))),
+        None => "",
+    };
+
+    let mut output = String::with_capacity(format.len());
+    let mut chars = format.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            output.push(ch);
+        } else if chars.peek() == Some(&'%') {
+            chars.next();
+            output.push('%');
+        } else {
+            match args.next() {
+                Some(value) => output.push_str(&value.format(interner)),
+                None => {
+                    return Err(Message::NotEnoughFormatArgs);
+                }
+            }
+        }
+    }
+
+    if args.next().is_some() {
+        return Err(Message::TooManyFormatArgs);
+    }
+
+    if newline {
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+pub(crate) fn kind_of(value: &Value) -> ValueKind {
+    match value {
+        Value::Bool(_) => ValueKind::Bool,
+        Value::Int(_) => ValueKind::Integer,
+        Value::Float(_) => ValueKind::Float,
+        Value::String(_) => ValueKind::String,
+        Value::Struct(name) => ValueKind::Struct(*name),
+        Value::Array(array) => ValueKind::Array(Box::new(
+            array.first().map_or(ValueKind::Nil, kind_of),
+        )),
+        Value::Map(map) => match map.iter().next() {
+            Some((key, value)) => ValueKind::Map(
+                Box::new(kind_of(&key.to_value())),
+                Box::new(kind_of(value)),
+            ),
+            None => ValueKind::Map(
+                Box::new(ValueKind::Nil),
+                Box::new(ValueKind::Nil),
+            ),
+        },
+        Value::Heap(heap) => ValueKind::Heap(Box::new(
+            heap.first().map_or(ValueKind::Nil, kind_of),
+        )),
+        Value::Deque(deque) => ValueKind::Deque(Box::new(
+            deque.front().map_or(ValueKind::Nil, kind_of),
+        )),
+        Value::Function(_) => {
+            ValueKind::Function(vec![], Box::new(ValueKind::Nil))
+        }
+        Value::Iter(_) => ValueKind::Iter(Box::new(ValueKind::Nil)),
+        Value::Ok(_) | Value::Err(_) => ValueKind::Result,
+        Value::Nil => ValueKind::Nil,
+    }
+}
+
+/// The elements of `value`, which has to be an array of `count` of them to
+/// be unpacked into that many variables.
+pub(crate) fn unpack(
+    value: Value,
+    count: usize,
+) -> std::result::Result<Vec<Value>, Message> {
+    match value {
+        Value::Array(array) if array.len() == count => {
+            Ok(Rc::try_unwrap(array).unwrap_or_else(|array| (*array).clone()))
+        }
+        other => Err(Message::CannotUnpack {
+            count,
+            found: kind_of(&other),
+        }),
+    }
+}
+
+/// Where `index` points into an array or string of length `len`, or the
+/// error to report when it is not a valid index. Negative indices count
+/// from the end.
+pub(crate) fn position(
+    len: usize,
+    index: &Value,
+) -> std::result::Result<usize, Message> {
+    match index {
+        Value::Int(i) => {
+            let from_start = if *i < 0 {
+                len as i64 + *i as i64
+            } else {
+                *i as i64
+            };
+            usize::try_from(from_start)
+                .ok()
+                .filter(|i| *i < len)
+                .ok_or(Message::IndexOutOfBounds { index: *i, len })
+        }
+        other => Err(Message::IndexNotInt(kind_of(other))),
+    }
+}
+
+pub(crate) fn map_key(key: &Value) -> std::result::Result<MapKey, Message> {
+    MapKey::from_value(key).ok_or_else(|| Message::InvalidMapKey(kind_of(key)))
+}
+
+pub(crate) fn missing_key(key: MapKey, interner: &StringInterner) -> Message {
+    Message::MissingKey(key.to_value().format(interner))
+}
+
+/// The element of the array or map `value` at `index`, or the character of
+/// the string, or the error to report when there is none.
+pub(crate) fn lookup(
+    value: &Value,
+    index: &Value,
+    interner: &mut StringInterner,
+) -> std::result::Result<Value, Message> {
+    match value {
+        Value::Array(array) => Ok(array[position(array.len(), index)?].clone()),
+        Value::Map(map) => {
+            let key = map_key(index)?;
+            map.get(key)
+                .cloned()
+                .ok_or_else(|| missing_key(key, interner))
+        }
+        Value::String(s) => {
+            let text = interner.get(*s);
+            let i = position(text.chars().count(), index)?;
+            let c = text.chars().nth(i).unwrap().to_string();
+            Ok(Value::String(interner.intern(&c)))
+        }
+        other => Err(Message::CannotIndex(kind_of(other))),
+    }
+}
+
+/// Follows `indices` into nested arrays and maps. Arrays and maps on the
+/// way that are shared with other values are copied first, so that only
+/// this value changes. With `insert`, a key missing from the last map is
+/// added with a `nil` value rather than being an error.
+pub(crate) fn element_mut<'v>(
+    mut value: &'v mut Value,
+    indices: &[Value],
+    insert: bool,
+    interner: &StringInterner,
+) -> std::result::Result<&'v mut Value, Message> {
+    for (i, index) in indices.iter().enumerate() {
+        value = match value {
+            Value::Array(array) => {
+                let i = position(array.len(), index)?;
+                &mut Rc::make_mut(array)[i]
+            }
+            Value::Map(map) => {
+                let key = map_key(index)?;
+                let map = Rc::make_mut(map);
+                if insert && i + 1 == indices.len() && !map.contains(key) {
+                    map.insert(key, Value::Nil);
+                }
+                match map.get_mut(key) {
+                    Some(element) => element,
+                    None => return Err(missing_key(key, interner)),
+                }
+            }
+            other => return Err(Message::CannotIndex(kind_of(other))),
+        };
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+    use std::sync::atomic::Ordering as AtomicOrdering;
+
+    use crate::lexer;
+    use crate::parser;
+
+    fn run(source: &str, input: &str) -> (Context, Scope, String) {
+        try_run(source, input).expect("Execution failed")
+    }
+
+    fn try_run(
+        source: &str,
+        input: &str,
+    ) -> std::result::Result<(Context, Scope, String), ()> {
+        let mut context = Context::new();
+        builtins::register(&mut context);
+        let file = context.interner.intern("test.bl");
+        context
+            .source_code
+            .insert(PathBuf::from("test.bl"), source.to_string());
+
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .expect("Lexing failed");
+        let ast =
+            parser::parse_ast(tokens, &mut context).expect("Parsing failed");
+
+        let mut input = input.as_bytes();
+        let mut output = Vec::new();
+        let scope = {
+            let mut interpreter =
+                Interpreter::new(&ast, &mut context, &mut input, &mut output);
+            interpreter.run(&ast.statements, &mut |_, _| {})?;
+            interpreter.scope
+        };
+
+        Ok((context, scope, String::from_utf8(output).unwrap()))
+    }
+
+    fn variable(source: &str, name: &str) -> Value {
+        let (mut context, mut scope, _) = run(source, "");
+        let name = context.interner.intern(name);
+        scope.get_variable(name).unwrap().value.clone()
+    }
+
+    fn output(source: &str) -> String {
+        run(source, "").2
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(variable("a := 1 + 2 * 3", "a"), Value::Int(7));
+        assert_eq!(variable("a := (1 + 2) * 3", "a"), Value::Int(9));
+        assert_eq!(variable("a := 10 - 2 - 3", "a"), Value::Int(5));
+        assert_eq!(variable("a := 7 / 2 + 1.5", "a"), Value::Float(4.5));
+        assert_eq!(variable("a := -7 % 3", "a"), Value::Int(-1));
+    }
+
+    #[test]
+    fn divmod_floors() {
+        let source = "for d in [divmod(-7, 2), divmod(7, -2), divmod(6, 3)] { \
+                      print(\"%,% \", d[0], d[1]) }";
+        assert_eq!(output(source), "-4,1 -4,-1 2,0 ");
+        assert!(try_run("z := 0 d := divmod(1, z)", "").is_err());
+        assert!(try_run("z := 0 d := 1 % z", "").is_err());
+    }
+
+    #[test]
+    fn assignment() {
+        assert_eq!(variable("a := 1 a = 5 a += 2 a *= 3", "a"), Value::Int(21));
+        assert_eq!(variable("a := 10 a /= 3 a -= 1", "a"), Value::Int(2));
+    }
+
+    #[test]
+    fn comparison() {
+        assert_eq!(variable("a := 1 < 2", "a"), Value::Bool(true));
+        assert_eq!(variable("a := 2 == 2.0", "a"), Value::Bool(true));
+        assert_eq!(variable("a := 3 <= 2", "a"), Value::Bool(false));
+        assert_eq!(variable("a := \"a\" != \"b\"", "a"), Value::Bool(true));
+    }
+
+    #[test]
+    fn logical_operators() {
+        assert_eq!(
+            variable("a := true || false && false", "a"),
+            Value::Bool(true)
+        );
+        assert_eq!(variable("a := !false && !(1 > 2)", "a"), Value::Bool(true));
+        assert_eq!(variable("a := !true || false", "a"), Value::Bool(false));
+
+        // The right hand side would fail with a division by zero.
+        let source = "z := 0 a := false && 1 / z > 0 b := true || 1 / z > 0";
+        assert_eq!(variable(source, "a"), Value::Bool(false));
+        assert_eq!(variable(source, "b"), Value::Bool(true));
+    }
+
+    #[test]
+    fn if_else() {
+        let source = "a := 0 if 1 > 2 { a = 1 } else { a = 2 }";
+        assert_eq!(variable(source, "a"), Value::Int(2));
+
+        let source = "a := 0 if 2 > 1 { a = 1 }";
+        assert_eq!(variable(source, "a"), Value::Int(1));
+    }
+
+    #[test]
+    fn loops() {
+        let source = "n := 0 for i in 0..5 { n += i }";
+        assert_eq!(variable(source, "n"), Value::Int(10));
+
+        let source = "n := 1 while n < 100 { n *= 2 }";
+        assert_eq!(variable(source, "n"), Value::Int(128));
+
+        let source = "n := 0 for i in 0..5 { for j in 0..5 { \
+                      if j > i { break } if j == 1 { continue } n += 1 } }";
+        assert_eq!(variable(source, "n"), Value::Int(11));
+
+        let source = "n := 0 while true { n += 1 if n == 3 { break } }";
+        assert_eq!(variable(source, "n"), Value::Int(3));
+    }
+
+    #[test]
+    fn block_scope() {
+        let source = "n := 1 { n := 2 n = 3 } m := n";
+        assert_eq!(variable(source, "m"), Value::Int(1));
+    }
+
+    #[test]
+    fn print_format() {
+        assert_eq!(
+            output("a := 5 print(\"% + % = %\\n\", a, 2, a + 2)"),
+            "5 + 2 = 7\n"
+        );
+        assert_eq!(output("print(\"%\", \"a\" + \"b\")"), "ab");
+        assert_eq!(output("print(\"% %\", 2.0, 1.5e-3 * 2)"), "2.0 0.003");
+        assert_eq!(output("print(\"%%% \\\"%\\\"\", 5, 1)"), "%5 \"1\"");
+    }
+
+    #[test]
+    fn read_input() {
+        let (mut context, mut scope, output) =
+            run("name := input(\"Name: \")", "Jonas\n");
+        let name = context.interner.intern("name");
+        let jonas = context.interner.intern("Jonas");
+
+        assert_eq!(output, "Name: ");
+        assert_eq!(
+            scope.get_variable(name).unwrap().value,
+            Value::String(jonas)
+        );
+    }
+
+    #[test]
+    fn function_calls() {
+        let source = "fn add(a: int, b: int) -> int { return a + b } \
+                      c := add(2, 3) * 2";
+        assert_eq!(variable(source, "c"), Value::Int(10));
+
+        let source = "n := fib(10) \
+                      fn fib(n: int) -> int { \
+                          if n < 2 { return n } \
+                          return fib(n - 1) + fib(n - 2) \
+                      }";
+        assert_eq!(variable(source, "n"), Value::Int(55));
+    }
+
+    #[test]
+    fn function_locals_do_not_leak() {
+        let source = "n := 1 \
+                      fn set(value: int) { n := value n = 5 } \
+                      set(3)";
+        assert_eq!(variable(source, "n"), Value::Int(1));
+
+        let source = "n := 1 fn bump() { n += 1 } bump() bump()";
+        assert_eq!(variable(source, "n"), Value::Int(3));
+    }
+
+    #[test]
+    fn try_returns_err() {
+        let source = "fn half(n: int) -> result { \
+                          if n % 2 != 0 { return Err(n) } \
+                          return Ok(n / 2) \
+                      } \
+                      fn quarter(n: int) -> result { return Ok(half(half(n)?)?) } \
+                      a := quarter(12) \
+                      b := quarter(6)";
+        assert_eq!(variable(source, "a"), Value::Ok(Box::new(Value::Int(3))));
+        assert_eq!(variable(source, "b"), Value::Err(Box::new(Value::Int(3))));
+    }
+
+    #[test]
+    fn arrays() {
+        let source = "xs := [1, 2] push(xs, 3) xs[0] = 5 xs[1] *= 10 \
+                      n := len(xs) + pop(xs)";
+        assert_eq!(variable(source, "n"), Value::Int(6));
+        assert_eq!(output("print(\"%\", [[1, 2], []])"), "[[1, 2], []]");
+
+        let source = "grid := [[1, 2], [3]] push(grid[1], 4) grid[0][1] = 0";
+        assert_eq!(
+            output(&format!("{} print(\"%\", grid)", source)),
+            "[[1, 0], [3, 4]]"
+        );
+    }
+
+    #[test]
+    fn arrays_are_values() {
+        let source = "xs := [1] ys := xs ys[0] = 2 \
+                      fn change(zs: [int]) { push(zs, 3) } change(xs) \
+                      print(\"% %\", xs, ys)";
+        assert_eq!(output(source), "[1] [2]");
+    }
+
+    #[test]
+    fn growing_arrays() {
+        let source = "xs := array_with_capacity(4) ys := xs reserve(xs, 2) \
+                      resize(xs, 2, \"a\") push(xs, \"b\") \
+                      print(\"% %\", xs, ys)";
+        assert_eq!(output(source), "[a, a, b] []");
+        assert!(try_run("xs := [1] reserve(xs, -1)", "").is_err());
+        assert!(try_run("xs := [1] reserve(xs, 2147483647)", "").is_err());
+    }
+
+    #[test]
+    fn unknown_types_fail_at_runtime() {
+        let sources = [
+            "fn f() -> result { return Ok(len(Ok(3)?)) } print(\"%\", f())",
+            "fn f() -> result { return Ok(trim(Ok(3)?)) } print(\"%\", f())",
+            "fn f() -> result { return Ok(min(Ok(\"a\")?, 1)) } print(\"%\", f())",
+            "fn f() -> result { return Ok(sum(Ok(3)?)) } print(\"%\", f())",
+            "fn f() -> result { return Ok(count(Ok(3)?)) } print(\"%\", f())",
+        ];
+        for source in &sources {
+            assert!(try_run(source, "").is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn array_errors() {
+        assert!(try_run("xs := [1] a := xs[1]", "").is_err());
+        assert!(try_run("xs := [1] a := xs[-2]", "").is_err());
+        assert!(try_run("xs := [1] xs[2] = 3", "").is_err());
+        assert!(try_run("xs: [int] = [] pop(xs)", "").is_err());
+        assert!(try_run("s := \"ab\" c := s[2]", "").is_err());
+    }
+
+    #[test]
+    fn negative_and_string_indices() {
+        let source = "xs := [1, 2, 3] xs[-3] = 0 s := \"héj\" \
+                      print(\"% % % %\", xs, xs[-1], s[1], s[-1])";
+        assert_eq!(output(source), "[0, 2, 3] 3 é j");
+
+        let source = "m := {\"a\": 1} \
+                      print(\"% % % %\", get([1], -1), get([1], 1), \
+                            get(\"ab\", 1), get(m, \"b\"))";
+        assert_eq!(output(source), "Ok(1) Err(1) Ok(b) Err(b)");
+    }
+
+    #[test]
+    fn maps() {
+        let source = "m := {\"b\": 1, \"a\": 2} m[\"c\"] = 3 m[\"b\"] += 10 \
+                      n := m[\"b\"] + len(m)";
+        assert_eq!(variable(source, "n"), Value::Int(14));
+
+        let source = "m := {2: [1]} push(m[2], 5) m[1] = [] \
+                      print(\"% % % %\", m, keys(m), values(m), contains(m, 3))";
+        assert_eq!(
+            output(source),
+            "{2: [1, 5], 1: []} [2, 1] [[1, 5], []] false"
+        );
+
+        assert!(try_run("m := {1: 2} n := m[3]", "").is_err());
+        assert!(try_run("m := {1: 2} m[3] += 1", "").is_err());
+    }
+
+    #[test]
+    fn for_over_arrays_and_maps() {
+        let source = "xs := [1, 2, 3] \
+                      for x in xs { push(xs, x * 10) print(\"%\", x) } \
+                      for k in {\"b\": 1, \"a\": 2} { print(\" %\", k) }";
+        assert_eq!(output(source), "123 b a");
+    }
+
+    #[test]
+    fn builtins() {
+        let source = "println(\"% %\", abs(-2), abs(-1.5)) \
+                      println(\"% %\", min(3, 2), max(1, 2.5)) println() \
+                      words := split(to_upper(trim(\" a b \")), \" \") \
+                      print(\"% %\", words, len(words))";
+        assert_eq!(output(source), "2 1.5\n2 2.5\n\n[A, B] 2");
+
+        let source = "fn trim(s: string) -> string { return \"mine\" } \
+                      print(trim(\" a \"))";
+        assert_eq!(output(source), "mine");
+    }
+
+    #[test]
+    fn iterators() {
+        let source = "fn square(n: int) -> int { return n * n } \
+                      fn even(n: int) -> bool { return n % 2 == 0 } \
+                      fn add(a: int, b: int) -> int { return a + b } \
+                      squares := map(range(5), square) \
+                      print(\"% % % \", sum(squares), sum(squares), \
+                            collect(filter(squares, even))) \
+                      print(\"% % \", fold([1, 2], 10, add), count({1: 2})) \
+                      for x in take(map([\" a\", \"b \"], trim), 1) { print(x) }";
+        assert_eq!(output(source), "30 30 [0, 4, 16] 13 1 a");
+
+        // Only as many values as are needed are mapped.
+        let source = "fn show(n: int) -> int { print(\"%\", n) return n } \
+                      n := sum(take(map(range(100), show), 2))";
+        assert_eq!(output(source), "01");
+    }
+
+    #[test]
+    fn sorting() {
+        let source = "xs := [3, 1, 2] ys := sort(xs) \
+                      print(\"% % % %\", xs, ys, reverse(ys), \
+                            sort({\"b\": 1, \"a\": 2}))";
+        assert_eq!(output(source), "[3, 1, 2] [1, 2, 3] [3, 2, 1] [a, b]");
+        assert!(try_run("xs := sort([[2], [1]])", "").is_err());
+    }
+
+    #[test]
+    fn binary_search_and_insort() {
+        let source =
+            "xs := [\"b\", \"d\"] insort(xs, \"c\") insort(xs, \"a\") \
+                      print(\"% % %\", xs, binary_search(xs, \"d\"), \
+                            binary_search([1, 2, 2, 3], 2))";
+        assert_eq!(output(source), "[a, b, c, d] Ok(3) Ok(1)");
+        assert!(try_run("xs := [[1]] insort(xs, [0])", "").is_err());
+    }
+
+    #[test]
+    fn heaps_and_deques() {
+        let source =
+            "h := heap() push(h, \"b\") push(h, \"c\") push(h, \"a\") \
+                      first := pop(h) d := deque() push(d, 1) push_front(d, 2) \
+                      e := d pop(e) \
+                      print(\"% % % % %\", first, pop(h), len(h), d, e)";
+        assert_eq!(output(source), "a b 1 deque[2, 1] deque[2]");
+        assert!(try_run("d := deque() n := pop_front(d)", "").is_err());
+        assert!(try_run("xs := [1] push_front(xs, 0)", "").is_err());
+        assert!(try_run("h := heap() push(h, [1]) push(h, [2])", "").is_err());
+    }
+
+    #[test]
+    fn zip_enumerate_and_group_by() {
+        let source = "fn first(s: string) -> string { return s[0] } \
+                      for i, name in enumerate([\"ann\", \"bo\"]) { \
+                          print(\"% \", i) \
+                      } \
+                      print(\"% %\", collect(zip([1, 2, 3], \"ab\".len().range())), \
+                            group_by([\"ab\", \"c\", \"ad\"], first))";
+        assert_eq!(
+            output(source),
+            "0 1 [[1, 0], [2, 1]] {a: [ab, ad], c: [c]}"
+        );
+        assert!(try_run("for a, b in [1] {}", "").is_err());
+    }
+
+    #[test]
+    fn lang_namespace() {
+        let source = "lang := \"sv\" \
+                      print(\"% % %\", lang, contains(lang.capabilities, \"fs\"), \
+                            lang.arch == \"\")";
+        assert_eq!(output(source), "sv true false");
+    }
+
+    #[test]
+    fn syntax_from_scripts() {
+        let source = "fn kinds() -> result { \
+                          tokens := lang.tokenize(\"f(x)\")? \
+                          tree := lang.parse(\"f(x)\")? \
+                          print(\"% %\", tokens[0][\"kind\"], tree[0][\"kind\"]) \
+                          return Ok(0) \
+                      } \
+                      kinds()";
+        assert_eq!(output(source), "Ident Call");
+    }
+
+    #[test]
+    fn aggregates() {
+        let source = "fn size(s: string) -> int { return len(s) } \
+                      fn short(s: string) -> bool { return len(s) < 3 } \
+                      xs := [\"ab\", \"abc\", \"x\", \"xyz\"] \
+                      print(\"% % % %\", max_by(xs, size), min_by(xs, size), \
+                            count_if(xs, short), mean([0.5, 1.5]))";
+        assert_eq!(output(source), "abc x 2 1.0");
+        assert!(try_run("n := mean([\"a\"])", "").is_err());
+        let source = "xs := [4, 1, 3, 2] \
+                      print(\"% % % %\", median(xs), stddev([2, 4]), \
+                            percentile(xs, 50), histogram(xs, 3))";
+        assert_eq!(
+            output(source),
+            "2.5 1.0 2.5 [[1.0, 1], [2.0, 1], [3.0, 2]]"
+        );
+        assert!(try_run("n := percentile([1], 101)", "").is_err());
+        assert!(try_run("h := histogram([1], 0)", "").is_err());
+    }
+
+    #[test]
+    fn arities() {
+        let source = "fn add(a: int, b: int) -> int { return a + b } \
+                      fn neg(n: int) -> int { return -n } \
+                      print(\"% % % %\", arity(add), arity(partial(add, 1)), \
+                            arity(compose(add, neg)), arity(split))";
+        assert_eq!(output(source), "2 1 2 2");
+    }
+
+    #[test]
+    fn partial_and_compose() {
+        let source = "fn add(a: int, b: int) -> int { return a + b } \
+                      f := compose(partial(add, 2), partial(add, 3)) \
+                      print(\"%\", collect(map(range(2), f)))";
+        assert_eq!(output(source), "[5, 6]");
+    }
+
+    #[test]
+    fn retry_and_timeout() {
+        let source = "n := 0 \
+                      fn flaky() -> result { n += 1 \
+                          if n < 3 { return Err(n) } return Ok(n) } \
+                      fn spin() { while true {} } \
+                      print(\"% % \", retry(flaky, 2, 0), retry(flaky, 5, 1)) \
+                      print(\"%\", with_timeout(spin, 1))";
+        assert_eq!(output(source), "Err(2) Ok(3) Err(Timed out after 1 ms)");
+
+        // Waiting between tries is cut short by the deadline, and long
+        // backoffs don't overflow.
+        let source = "fn fail() -> result { return Err(0) } \
+                      fn patient() -> result { \
+                          return retry(fail, 40, 2000000000) } \
+                      print(\"%\", with_timeout(patient, 20))";
+        assert_eq!(output(source), "Err(Timed out after 20 ms)");
+    }
+
+    #[test]
+    fn eval_keeps_no_handlers() {
+        let source = "print(\"%\", eval(\"fn f() {} on_exit(f)\"))";
+        assert_eq!(
+            output(source),
+            "Err(eval can't register handlers with on_exit or on_signal)"
+        );
+    }
+
+    #[test]
+    fn string_interpolation() {
+        let source = "n := 3 s := \"n=${n * 2} ${n > 2}\" + \"!\" \
+                      print(\"% %\", s, 1 + \"x\" + 0.5)";
+        assert_eq!(output(source), "n=6 true! 1x0.5");
+    }
+
+    #[test]
+    fn interrupt_stops_execution() {
+        let mut context = Context::new();
+        let source = "n := 0 while true { n += 1 }";
+        let file = context.interner.intern("test.bl");
+        context
+            .source_code
+            .insert(PathBuf::from("test.bl"), source.to_string());
+
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+        let ast = parser::parse_ast(tokens, &mut context).unwrap();
+
+        let interrupted = AtomicBool::new(true);
+        let mut input = "".as_bytes();
+        let mut output = Vec::new();
+        let mut interpreter =
+            Interpreter::new(&ast, &mut context, &mut input, &mut output);
+        interpreter.interrupted = &interrupted;
+
+        assert!(interpreter.run(&ast.statements, &mut |_, _| {}).is_err());
+        assert!(!interrupted.load(AtomicOrdering::SeqCst));
     }
 }
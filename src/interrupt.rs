@@ -0,0 +1,146 @@
+use std::io::{self, BufRead, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::primitives::Function;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+static HUNG_UP: AtomicBool = AtomicBool::new(false);
+
+/// The signals a script can handle with `on_signal`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    Interrupt,
+    Terminate,
+    HangUp,
+}
+
+impl Signal {
+    /// The signal called `name` without its `SIG` prefix, like `"INT"`.
+    pub fn from_name(name: &str) -> Option<Signal> {
+        match name {
+            "INT" => Some(Signal::Interrupt),
+            "TERM" => Some(Signal::Terminate),
+            "HUP" => Some(Signal::HangUp),
+            _ => None,
+        }
+    }
+
+    fn flag(self) -> &'static AtomicBool {
+        match self {
+            Signal::Interrupt => &INTERRUPTED,
+            Signal::Terminate => &TERMINATED,
+            Signal::HangUp => &HUNG_UP,
+        }
+    }
+}
+
+/// Functions of the script to run when a signal arrives or the script
+/// ends, set by `on_signal` and `on_exit`.
+#[derive(Clone, Default, PartialEq)]
+pub struct Handlers {
+    pub signals: Vec<(Signal, Function)>,
+    pub exit: Vec<Function>,
+}
+
+impl Handlers {
+    pub fn signal(&self, signal: Signal) -> Option<Function> {
+        self.signals
+            .iter()
+            .find(|(caught, _)| *caught == signal)
+            .map(|(_, func)| func.clone())
+    }
+}
+
+/// Makes Ctrl-C set the flag returned by `flag` instead of killing the
+/// process. The interpreter polls the flag between statements.
+pub fn install() {
+    catch(Signal::Interrupt);
+}
+
+/// Makes `signal` set its flag instead of killing the process, so that
+/// `take` returns it. The handler is installed without `SA_RESTART`, so a
+/// blocking read the signal arrives during fails instead of carrying on,
+/// and `Interruptible` can give up on it.
+#[cfg(unix)]
+pub fn catch(signal: Signal) {
+    extern "C" fn handler(number: libc::c_int) {
+        let signal = match number {
+            libc::SIGTERM => Signal::Terminate,
+            libc::SIGHUP => Signal::HangUp,
+            _ => Signal::Interrupt,
+        };
+        signal.flag().store(true, Ordering::SeqCst);
+    }
+
+    let number = match signal {
+        Signal::Interrupt => libc::SIGINT,
+        Signal::Terminate => libc::SIGTERM,
+        Signal::HangUp => libc::SIGHUP,
+    };
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as *const () as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(number, &action, std::ptr::null_mut());
+    }
+}
+
+#[cfg(not(unix))]
+pub fn catch(_: Signal) {}
+
+pub fn flag() -> &'static AtomicBool {
+    &INTERRUPTED
+}
+
+/// Whether a signal has arrived that `take` has not returned yet.
+pub fn pending() -> bool {
+    [&INTERRUPTED, &TERMINATED, &HUNG_UP]
+        .iter()
+        .any(|flag| flag.load(Ordering::SeqCst))
+}
+
+/// Input that fails when a signal interrupts a read, where std would
+/// retry it and keep waiting. Scripts reading standard input can then be
+/// stopped with Ctrl-C before a line is entered.
+pub struct Interruptible<R>(pub R);
+
+impl<R: Read> Read for Interruptible<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(stop_on_signal)
+    }
+}
+
+impl<R: BufRead> BufRead for Interruptible<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.0.fill_buf().map_err(stop_on_signal)
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.0.consume(amount)
+    }
+}
+
+fn stop_on_signal(error: io::Error) -> io::Error {
+    if error.kind() == io::ErrorKind::Interrupted && pending() {
+        io::Error::other("interrupted by a signal")
+    } else {
+        error
+    }
+}
+
+/// Returns a signal that arrived since the last call and clears it. Ctrl-C
+/// is read from `interrupted`, the flag returned by `flag` outside tests.
+pub fn take(interrupted: &AtomicBool) -> Option<Signal> {
+    if interrupted.swap(false, Ordering::SeqCst) {
+        return Some(Signal::Interrupt);
+    }
+
+    [Signal::Terminate, Signal::HangUp]
+        .iter()
+        .copied()
+        .find(|signal| {
+            let flag = signal.flag();
+            flag.load(Ordering::Relaxed) && flag.swap(false, Ordering::SeqCst)
+        })
+}
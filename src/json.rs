@@ -0,0 +1,328 @@
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::common::StringInterner;
+use crate::primitives::{Map, MapKey, Value};
+
+/// Writes `value` as JSON. Map keys that are not strings are written as
+/// they are printed, since JSON only has string keys. Returns None for
+/// values that have no JSON form, like functions, or floats that are not
+/// finite.
+pub fn to_json(value: &Value, interner: &StringInterner) -> Option<String> {
+    let mut output = String::new();
+    write_value(value, interner, &mut output)?;
+    Some(output)
+}
+
+fn write_value(
+    value: &Value,
+    interner: &StringInterner,
+    output: &mut String,
+) -> Option<()> {
+    match value {
+        Value::Nil => output.push_str("null"),
+        Value::Bool(b) => output.push_str(if *b { "true" } else { "false" }),
+        Value::Int(n) => output.push_str(&n.to_string()),
+        Value::Float(f) if f.is_finite() => {
+            output.push_str(&format!("{:?}", f))
+        }
+        Value::String(s) => write_string(interner.get(*s), output),
+        Value::Array(values) => {
+            output.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                write_value(value, interner, output)?;
+            }
+            output.push(']');
+        }
+        Value::Map(map) => {
+            output.push('{');
+            for (i, (key, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                write_string(&key.to_value().format(interner), output);
+                output.push(':');
+                write_value(value, interner, output)?;
+            }
+            output.push('}');
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+fn write_string(s: &str, output: &mut String) {
+    output.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(output, "\\u{:04x}", c as u32);
+            }
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+/// Reads a JSON document. Numbers without a fraction or exponent that fit
+/// in an int become ints, other numbers floats, and objects maps with
+/// string keys. Returns the reason if `text` is not JSON.
+pub fn from_json(
+    text: &str,
+    interner: &mut StringInterner,
+) -> Result<Value, String> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        at: 0,
+        interner,
+    };
+
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.at < parser.text.len() {
+        return Err(parser.error("Expected the end of the document"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    at: usize,
+    interner: &'a mut StringInterner,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}", message, self.at)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') =
+            self.peek()
+        {
+            self.at += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.at).copied()
+    }
+
+    /// Skips over `expected` if it comes next.
+    fn eat(&mut self, expected: u8) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.at += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), String> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", expected as char)))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.word("null", Value::Nil),
+            Some(b't') => self.word("true", Value::Bool(true)),
+            Some(b'f') => self.word("false", Value::Bool(false)),
+            Some(b'"') => {
+                let s = self.string()?;
+                Ok(Value::String(self.interner.intern(&s)))
+            }
+            Some(b'[') => {
+                self.at += 1;
+                let mut values = Vec::new();
+                if !self.eat(b']') {
+                    loop {
+                        values.push(self.value()?);
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Value::Array(Rc::new(values)))
+            }
+            Some(b'{') => {
+                self.at += 1;
+                let mut map = Map::new();
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        self.expect(b':')?;
+                        let key = MapKey::String(self.interner.intern(&key));
+                        map.insert(key, self.value()?);
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Value::Map(Rc::new(map)))
+            }
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            _ => Err(self.error("Expected a value")),
+        }
+    }
+
+    fn word(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        if self.text[self.at..].starts_with(word.as_bytes()) {
+            self.at += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("Expected a value"))
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.at;
+        while let Some(b'-') | Some(b'+') | Some(b'.') | Some(b'e')
+        | Some(b'E') | Some(b'0'..=b'9') = self.peek()
+        {
+            self.at += 1;
+        }
+
+        let text = std::str::from_utf8(&self.text[start..self.at])
+            .expect("Only ASCII was read");
+        let is_float = text.contains(&['.', 'e', 'E'][..]);
+        match text.parse::<i32>() {
+            Ok(n) if !is_float => Ok(Value::Int(n)),
+            _ => match text.parse::<f64>() {
+                Ok(f) => Ok(Value::Float(f)),
+                Err(_) => {
+                    self.at = start;
+                    Err(self.error("Invalid number"))
+                }
+            },
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.peek() != Some(b'"') {
+            return Err(self.error("Expected a string"));
+        }
+        self.at += 1;
+
+        let mut s = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("Unterminated string")),
+                Some(b'"') => {
+                    self.at += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.at += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => return Err(self.error("Invalid escape")),
+                    };
+                    self.at += 1;
+                    let mut buffer = [0; 4];
+                    s.extend(escaped.encode_utf8(&mut buffer).as_bytes());
+                }
+                Some(b) => {
+                    s.push(b);
+                    self.at += 1;
+                }
+            }
+        }
+
+        String::from_utf8(s).map_err(|_| self.error("Invalid UTF-8"))
+    }
+
+    /// Reads the digits of a `\u` escape, and the escape after it if they
+    /// are the first half of a surrogate pair. Leaves `at` on the last
+    /// digit.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let first = self.hex_digits()?;
+        let code = if (0xd800..0xdc00).contains(&first)
+            && self.text[self.at + 1..].starts_with(b"\\u")
+        {
+            self.at += 2;
+            let second = self.hex_digits()?;
+            0x10000 + ((first - 0xd800) << 10) + (second.wrapping_sub(0xdc00))
+        } else {
+            first
+        };
+        std::char::from_u32(code).ok_or_else(|| self.error("Invalid escape"))
+    }
+
+    /// Reads the four hex digits after the current position.
+    fn hex_digits(&mut self) -> Result<u32, String> {
+        let digits = self
+            .text
+            .get(self.at + 1..self.at + 5)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("Invalid escape"))?;
+        self.at += 4;
+        Ok(digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(text: &str) -> String {
+        let mut interner = StringInterner::new();
+        let value = from_json(text, &mut interner).expect("Invalid JSON");
+        to_json(&value, &interner).expect("No JSON form")
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(round_trip("null"), "null");
+        assert_eq!(
+            round_trip(" [1, -2.5, true, \"a\"] "),
+            "[1,-2.5,true,\"a\"]"
+        );
+        assert_eq!(round_trip("{\"a\": {\"b\": []}}"), "{\"a\":{\"b\":[]}}");
+        assert_eq!(round_trip("3000000000"), "3000000000.0");
+        assert_eq!(round_trip("1e2"), "100.0");
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(round_trip(r#""a\"\\\n\u00e5""#), r#""a\"\\\nå""#);
+        assert_eq!(round_trip(r#""\ud83d\ude00""#), "\"😀\"");
+        assert_eq!(round_trip("\"\\u0001\""), "\"\\u0001\"");
+    }
+
+    #[test]
+    fn errors() {
+        let mut interner = StringInterner::new();
+        for text in &["", "[1,", "{\"a\" 1}", "tru", "\"a", "1 2", "-"] {
+            assert!(from_json(text, &mut interner).is_err(), "{}", text);
+        }
+
+        let nan = Value::Float(f64::NAN);
+        assert_eq!(to_json(&nan, &interner), None);
+    }
+}
@@ -1,35 +1,226 @@
 use std::collections::VecDeque;
+use std::mem;
+use std::str;
 
-use crate::common::{Context, Symbol};
+#[cfg(feature = "logos")]
+use logos::Logos;
+
+use crate::common::{Context, Diagnostic, Severity, Symbol};
 use crate::location::{Location, Span};
+use crate::messages::Message;
 use crate::token::{Token, TokenKind};
 
 macro_rules! str_or_err {
     ($self:expr, $start:expr) => {
-        match std::str::from_utf8(&$self.source[$start..$self.pos]) {
+        match str::from_utf8(&$self.source[$start..$self.pos]) {
             Ok(s) => s,
             Err(_) => {
-                $self
-                    .context
-                    .report_error("Invalid UTF-8", $self.make_location($start));
+                $self.report_error(
+                    Message::InvalidUtf8,
+                    $self.make_location($start),
+                );
                 return Err(());
             }
         }
     };
 }
 
+/// The implementations that split a source into tokens. They make the
+/// same tokens and report the same errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    HandWritten,
+    /// Scans with a state machine generated by `logos`, and leaves strings,
+    /// block comments and anything not ASCII to the hand-written lexer.
+    #[cfg(feature = "logos")]
+    Logos,
+}
+
+impl Default for Backend {
+    #[cfg(feature = "logos")]
+    fn default() -> Self {
+        Backend::Logos
+    }
+
+    #[cfg(not(feature = "logos"))]
+    fn default() -> Self {
+        Backend::HandWritten
+    }
+}
+
+#[allow(clippy::result_unit_err)]
 pub fn generate_tokens(
     source: &[u8],
     file: Symbol,
     context: &mut Context,
 ) -> Result<VecDeque<Token>, ()> {
+    generate_tokens_with(Backend::default(), source, file, context)
+}
+
+/// Like `generate_tokens`, with the tokens made by `backend`.
+#[allow(clippy::result_unit_err)]
+pub fn generate_tokens_with(
+    backend: Backend,
+    source: &[u8],
+    file: Symbol,
+    context: &mut Context,
+) -> Result<VecDeque<Token>, ()> {
+    let lexed = lex_with(backend, source, file, context);
+    let failed = !lexed.diagnostics.is_empty();
+    context.report_all(lexed.diagnostics);
+
+    if failed {
+        Err(())
+    } else {
+        Ok(lexed.tokens)
+    }
+}
+
+/// What `lex_all` found in a source.
+pub(crate) struct Lexed {
+    pub tokens: VecDeque<Token>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Whether the source ended inside a string or a comment.
+    pub unterminated: bool,
+}
+
+/// Lexes as much of `source` as possible. Errors are collected rather than
+/// reported, so that the caller can decide what to do with them.
+pub(crate) fn lex_all(
+    source: &[u8],
+    file: Symbol,
+    context: &mut Context,
+) -> Lexed {
+    lex_with(Backend::default(), source, file, context)
+}
+
+fn lex_with(
+    backend: Backend,
+    source: &[u8],
+    file: Symbol,
+    context: &mut Context,
+) -> Lexed {
     let mut lexer = Lexer::new(source, file, context);
     let mut tokens = VecDeque::new();
-    while let Some(token) = lexer.next_token()? {
+
+    loop {
+        let next = match backend {
+            Backend::HandWritten => lexer.next_token(),
+            #[cfg(feature = "logos")]
+            Backend::Logos => lexer.next_scanned(),
+        };
+
+        // Invalid input is skipped after it has been reported, so that all
+        // errors are found in one go.
+        let token = match next {
+            Ok(Some(token)) => token,
+            Ok(None) => break,
+            Err(()) => continue,
+        };
+
+        if tokens.len() == lexer.context.limits.max_tokens {
+            lexer.report_error(
+                Message::TooManyTokens(lexer.context.limits.max_tokens),
+                token.location,
+            );
+            break;
+        }
+
         tokens.push_back(token);
     }
 
-    Ok(tokens)
+    mark_line_breaks(&mut tokens);
+    Lexed {
+        tokens,
+        diagnostics: lexer.diagnostics,
+        unterminated: lexer.unterminated,
+    }
+}
+
+/// Marks the line breaks that end a statement. A line break ends one
+/// unless it is inside parentheses, brackets or the braces of a map, or
+/// comes after a token that needs something after it, like a binary
+/// operator or a comma. So `a := b` followed by `-1` on the next line is
+/// two statements, while `a := b -` followed by `1` is one.
+pub fn mark_line_breaks(tokens: &mut VecDeque<Token>) {
+    // Whether each open delimiter is around an expression, rather than
+    // being the brace of a block.
+    let mut nested = vec![];
+    let mut previous: Option<(u32, TokenKind)> = None;
+
+    for token in tokens.iter_mut() {
+        // The start of a file ends whatever came before it, for when
+        // files are joined by `import`.
+        token.line_break = match &previous {
+            Some((line, kind)) => {
+                token.location.line > *line
+                    && !needs_more(kind)
+                    && nested.last() != Some(&true)
+            }
+            None => true,
+        };
+
+        match token.kind {
+            TokenKind::OpenParen | TokenKind::OpenBracket => nested.push(true),
+            // A brace where a value is expected starts a map, any other
+            // starts a block.
+            TokenKind::OpenBrace => nested.push(match &previous {
+                Some((_, TokenKind::FatArrow)) | None => false,
+                Some((_, kind)) => {
+                    needs_more(kind)
+                        || matches!(
+                            kind,
+                            TokenKind::OpenParen
+                                | TokenKind::OpenBracket
+                                | TokenKind::Return
+                        )
+                }
+            }),
+            TokenKind::CloseParen
+            | TokenKind::CloseBracket
+            | TokenKind::CloseBrace => {
+                nested.pop();
+            }
+            _ => {}
+        }
+
+        previous = Some((token.location.line, token.kind.clone()));
+    }
+}
+
+/// Whether an expression or statement can't end with `kind`.
+fn needs_more(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::And
+            | TokenKind::Or
+            | TokenKind::Not
+            | TokenKind::Equal
+            | TokenKind::Greater
+            | TokenKind::GreaterEqual
+            | TokenKind::Lesser
+            | TokenKind::LesserEqual
+            | TokenKind::NotEqual
+            | TokenKind::Assign
+            | TokenKind::AddAssign
+            | TokenKind::DivAssign
+            | TokenKind::MulAssign
+            | TokenKind::SubAssign
+            | TokenKind::ModAssign
+            | TokenKind::Add
+            | TokenKind::Sub
+            | TokenKind::Mul
+            | TokenKind::Div
+            | TokenKind::Mod
+            | TokenKind::Field
+            | TokenKind::PathSep
+            | TokenKind::VarDecl
+            | TokenKind::Colon
+            | TokenKind::Comma
+            | TokenKind::ReturnDecl
+            | TokenKind::FatArrow
+            | TokenKind::In
+    )
 }
 
 struct Lexer<'a> {
@@ -39,6 +230,12 @@ struct Lexer<'a> {
     pos: usize,
     source: &'a [u8],
     context: &'a mut Context,
+    /// Where string literals with escape sequences are unescaped.
+    buffer: Vec<u8>,
+    /// Tokens lexed ahead, from a string literal with interpolations.
+    pending: VecDeque<Token>,
+    diagnostics: Vec<Diagnostic>,
+    unterminated: bool,
 }
 
 impl<'a> Lexer<'a> {
@@ -54,8 +251,20 @@ impl<'a> Lexer<'a> {
             column: 1,
             pos: 0,
             source,
+            buffer: Vec::new(),
+            pending: VecDeque::new(),
+            diagnostics: vec![],
+            unterminated: false,
         }
     }
+
+    fn report_error(&mut self, message: Message, location: Location) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            message,
+            location,
+        });
+    }
     fn make_location(&self, start: usize) -> Location {
         Location {
             line: self.line,
@@ -81,7 +290,450 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Consumes the exponent of a float literal, as in `1.5e-3`, if there
+    /// is one.
+    fn exponent(&mut self) {
+        if !matches!(self.source.get(self.pos), Some(b'e') | Some(b'E')) {
+            return;
+        }
+
+        let digits = match self.source.get(self.pos + 1) {
+            Some(b'+') | Some(b'-') => self.pos + 2,
+            _ => self.pos + 1,
+        };
+
+        if self.source.get(digits).is_some_and(u8::is_ascii_digit) {
+            while self.pos < digits {
+                self.advance();
+            }
+            self.advance_while(|c| c.is_ascii_digit());
+        }
+    }
+
+    /// Lexes a string literal starting at the opening quote. A literal with
+    /// `${...}` in it is lexed as `InterpolationStart`, its text and the
+    /// tokens of each expression in turn, starting and ending with text,
+    /// and `InterpolationEnd`. All but the first token are left pending.
+    fn string(&mut self, start: usize) -> Result<Token, ()> {
+        self.advance();
+        let opening = self.make_location(start);
+        let (text, mut interpolated) = self.string_part(start)?;
+        let text = Token {
+            kind: TokenKind::String(text),
+            location: self.make_location(start),
+            line_break: false,
+        };
+        if !interpolated {
+            return Ok(text);
+        }
+
+        let mut tokens = vec![text];
+        let mut failed = false;
+        while interpolated {
+            let expression_start = tokens.len();
+            let mut depth = 0;
+            loop {
+                let token = match self.next_token()? {
+                    Some(token) => token,
+                    None => {
+                        self.unterminated = true;
+                        self.report_error(Message::UnterminatedString, opening);
+                        return Err(());
+                    }
+                };
+
+                match token.kind {
+                    TokenKind::OpenBrace => depth += 1,
+                    TokenKind::CloseBrace if depth == 0 => {
+                        // The rest of the string is still taken, so that it
+                        // is not read as code.
+                        if tokens.len() == expression_start {
+                            self.report_error(
+                                Message::ExpectedExpression,
+                                token.location,
+                            );
+                            failed = true;
+                        }
+                        break;
+                    }
+                    TokenKind::CloseBrace => depth -= 1,
+                    _ => {}
+                }
+                tokens.push(token);
+            }
+
+            let part_start = self.pos;
+            let (text, more) = self.string_part(part_start)?;
+            tokens.push(Token {
+                kind: TokenKind::String(text),
+                location: self.make_location(part_start),
+                line_break: false,
+            });
+            interpolated = more;
+        }
+
+        if failed {
+            return Err(());
+        }
+
+        tokens.push(Token {
+            kind: TokenKind::InterpolationEnd,
+            location: self.make_location(self.pos - 1),
+            line_break: false,
+        });
+        self.pending.extend(tokens);
+
+        Ok(Token {
+            kind: TokenKind::InterpolationStart,
+            location: opening,
+            line_break: false,
+        })
+    }
+
+    /// Lexes the text of a string literal up to its closing quote or the
+    /// next `${`, and interns it with escape sequences replaced. Text
+    /// without escape sequences is interned straight from the source,
+    /// other text is unescaped into a buffer that is reused between
+    /// literals. Returns whether the text ends at a `${`.
+    fn string_part(&mut self, start: usize) -> Result<(Symbol, bool), ()> {
+        let part_start = self.pos;
+        let mut string = mem::take(&mut self.buffer);
+        string.clear();
+        let mut escaped = false;
+        let mut failed = false;
+
+        let (end, interpolated) = loop {
+            match self.source.get(self.pos) {
+                Some(b'"') => {
+                    self.advance();
+                    break (self.pos - 1, false);
+                }
+                Some(b'$') if self.source.get(self.pos + 1) == Some(&b'{') => {
+                    self.advance();
+                    self.advance();
+                    break (self.pos - 2, true);
+                }
+                Some(b'\\') => {
+                    if !escaped {
+                        string.extend_from_slice(
+                            &self.source[part_start..self.pos],
+                        );
+                        escaped = true;
+                    }
+                    let escape_start = self.pos;
+                    self.advance();
+                    // The rest of the string is still taken, so that it is
+                    // not read as code.
+                    failed |= self.escape(escape_start, &mut string).is_err();
+                }
+                Some(b'\n') | None => {
+                    self.unterminated = self.pos == self.source.len();
+                    self.report_error(
+                        Message::UnterminatedString,
+                        self.make_location(start),
+                    );
+                    return Err(());
+                }
+                Some(&b) => {
+                    if escaped {
+                        string.push(b);
+                    }
+                    self.advance();
+                }
+            }
+        };
+
+        if failed {
+            return Err(());
+        }
+
+        let source = self.source;
+        let contents = if escaped {
+            &string[..]
+        } else {
+            &source[part_start..end]
+        };
+
+        if contents.len() > self.context.limits.max_string_len {
+            self.report_error(
+                Message::StringTooLong(self.context.limits.max_string_len),
+                self.make_location(start),
+            );
+            return Err(());
+        }
+
+        let symbol = match str::from_utf8(contents) {
+            Ok(contents) => self.context.interner.intern(contents),
+            Err(_) => {
+                self.report_error(
+                    Message::InvalidUtf8,
+                    self.make_location(start),
+                );
+                return Err(());
+            }
+        };
+
+        self.buffer = string;
+        Ok((symbol, interpolated))
+    }
+
+    /// Lexes the escape sequence after a backslash into `string`.
+    fn escape(&mut self, start: usize, string: &mut Vec<u8>) -> Result<(), ()> {
+        let escaped = match self.source.get(self.pos) {
+            Some(b'n') => '\n',
+            Some(b't') => '\t',
+            Some(b'r') => '\r',
+            Some(b'0') => '\0',
+            Some(b'\\') => '\\',
+            Some(b'"') => '"',
+            Some(b'$') => '$',
+            Some(b'u') => {
+                self.advance();
+                return self.unicode_escape(start, string);
+            }
+            _ => {
+                self.advance();
+                self.report_error(
+                    Message::UnknownEscape,
+                    self.make_location(start),
+                );
+                return Err(());
+            }
+        };
+
+        self.advance();
+        string.push(escaped as u8);
+        Ok(())
+    }
+
+    /// Lexes the `{...}` part of a `\u{...}` escape.
+    fn unicode_escape(
+        &mut self,
+        start: usize,
+        string: &mut Vec<u8>,
+    ) -> Result<(), ()> {
+        let digits_start = self.pos + 1;
+
+        if self.source.get(self.pos) == Some(&b'{') {
+            self.advance();
+            self.advance_while(|c| c.is_ascii_hexdigit());
+        }
+
+        let ch = match self.source.get(self.pos) {
+            Some(b'}')
+                if self.pos > digits_start && self.pos - digits_start <= 6 =>
+            {
+                let digits = str_or_err!(self, digits_start);
+                u32::from_str_radix(digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+            }
+            _ => None,
+        };
+
+        if self.pos < self.source.len() {
+            self.advance();
+        }
+
+        match ch {
+            Some(ch) => {
+                let mut buf = [0; 4];
+                string.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+                Ok(())
+            }
+            None => {
+                self.report_error(
+                    Message::InvalidUnicodeEscape,
+                    self.make_location(start),
+                );
+                Err(())
+            }
+        }
+    }
+
+    /// Skips a `/* ... */` comment starting at the current position. Block
+    /// comments nest, so each `/*` inside needs its own `*/`.
+    fn block_comment(&mut self) -> Result<(), ()> {
+        let start = Location {
+            line: self.line,
+            file: self.file,
+            span: Span {
+                start: self.pos as u32,
+                len: 2,
+            },
+        };
+        let mut depth = 0;
+
+        while self.pos < self.source.len() {
+            match &self.source[self.pos..] {
+                [b'/', b'*', ..] => {
+                    depth += 1;
+                    self.advance();
+                }
+                [b'*', b'/', ..] => {
+                    depth -= 1;
+                    self.advance();
+                }
+                [b'\n', ..] => {
+                    self.line += 1;
+                    self.column = 0;
+                }
+                _ => {}
+            }
+            self.advance();
+
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+
+        self.unterminated = true;
+        self.report_error(Message::UnterminatedComment, start);
+        Err(())
+    }
+
+    /// Makes a keyword or identifier of the word from `start` to the
+    /// current position.
+    fn word(&mut self, start: usize) -> Result<Token, ()> {
+        let string = str_or_err!(self, start);
+
+        let kind = match string {
+            "if" => TokenKind::If,
+            "else" => TokenKind::Else,
+            "for" => TokenKind::For,
+            "in" => TokenKind::In,
+            "while" => TokenKind::While,
+            "break" => TokenKind::Break,
+            "continue" => TokenKind::Continue,
+            "import" => TokenKind::Import,
+            "match" => TokenKind::Match,
+            "fn" => TokenKind::FunctionDecl,
+            "return" => TokenKind::Return,
+            "string" => TokenKind::StringType,
+            "float" => TokenKind::FloatType,
+            "int" => TokenKind::IntType,
+            "bool" => TokenKind::BoolType,
+            "result" => TokenKind::ResultType,
+            "true" => TokenKind::Bool(true),
+            "false" => TokenKind::Bool(false),
+            "struct" => TokenKind::StructDecl,
+            other => TokenKind::Ident(self.context.interner.intern(other)),
+        };
+
+        Ok(Token {
+            kind,
+            location: self.make_location(start),
+            line_break: false,
+        })
+    }
+
+    /// Makes a number or range of the literal from `start` to the current
+    /// position.
+    fn number(&mut self, start: usize) -> Result<Token, ()> {
+        let string = str_or_err!(self, start);
+        let location = self.make_location(start);
+
+        let kind = if let Ok(integer) = string.parse() {
+            TokenKind::Integer(integer)
+        } else if string.bytes().all(|b| b.is_ascii_digit()) {
+            self.report_error(
+                Message::IntegerTooLarge(string.to_string()),
+                location,
+            );
+            return Err(());
+        } else if let Ok(float) = string.parse() {
+            TokenKind::Float(float)
+        } else {
+            let mut iter = string.split("..");
+            let start = iter.next();
+            let end = iter.next();
+            match (start.map(str::parse), end.map(str::parse)) {
+                (Some(Ok(start)), Some(Ok(end))) => {
+                    TokenKind::Range(start, end)
+                }
+                _ => {
+                    self.report_error(
+                        Message::InvalidRange(string.to_string()),
+                        location,
+                    );
+                    return Err(());
+                }
+            }
+        };
+
+        Ok(Token {
+            kind,
+            location,
+            line_break: false,
+        })
+    }
+
+    /// Like `next_token`, with the source scanned by `logos`. What it
+    /// can't scan on its own, like strings and invalid input, is lexed by
+    /// `next_token` from where the token starts, so that both backends
+    /// agree on every token and error.
+    #[cfg(feature = "logos")]
+    fn next_scanned(&mut self) -> Result<Option<Token>, ()> {
+        if let Some(token) = self.pending.pop_front() {
+            return Ok(Some(token));
+        }
+
+        let source = self.source;
+        let mut base = self.pos;
+        let mut scanner = Scanned::lexer(&source[base..]);
+
+        loop {
+            let scanned = scanner.next();
+            let span = scanner.span();
+            let (start, end) = (base + span.start, base + span.end);
+            self.pos = start;
+
+            // Words and numbers run on into letters and digits outside of
+            // ASCII, which only the hand-written lexer knows.
+            let ascii = source.get(end).is_none_or(u8::is_ascii);
+            let kind = match scanned {
+                None => {
+                    self.pos = source.len();
+                    return Ok(None);
+                }
+                Some(Ok(Scanned::Newline)) => {
+                    self.line += 1;
+                    self.pos = end;
+                    continue;
+                }
+                Some(Ok(Scanned::Token(kind))) if ascii => kind,
+                Some(Ok(Scanned::Word)) if ascii => {
+                    self.pos = end;
+                    return self.word(start).map(Some);
+                }
+                Some(Ok(Scanned::Number)) if ascii => {
+                    self.pos = end;
+                    self.exponent();
+                    return self.number(start).map(Some);
+                }
+                Some(Ok(Scanned::LineComment)) => {
+                    self.advance_while(|c| c != '\n');
+                    base = self.pos;
+                    scanner = Scanned::lexer(&source[base..]);
+                    continue;
+                }
+                _ => return self.next_token(),
+            };
+
+            self.pos = end;
+            return Ok(Some(Token {
+                kind,
+                location: self.make_location(start),
+                line_break: false,
+            }));
+        }
+    }
+
     pub fn next_token(&mut self) -> Result<Option<Token>, ()> {
+        if let Some(token) = self.pending.pop_front() {
+            return Ok(Some(token));
+        }
+
         while self.pos < self.source.len() {
             let start = self.pos;
 
@@ -92,103 +744,48 @@ impl<'a> Lexer<'a> {
                     self.pos += 1;
                 }
                 c if c.is_whitespace() => {
-                    self.advance_while(char::is_whitespace);
+                    self.advance_while(|c| c != '\n' && c.is_whitespace());
                 }
-                c if c.is_alphabetic() => {
+                c if c.is_alphabetic() || c == '_' => {
                     self.advance_while(|c| c.is_alphanumeric() || c == '_');
-                    let string = str_or_err!(self, start);
-
-                    let kind = match string {
-                        "if" => TokenKind::If,
-                        "else" => TokenKind::Else,
-                        "for" => TokenKind::For,
-                        "in" => TokenKind::In,
-                        "while" => TokenKind::While,
-                        "fn" => TokenKind::FunctionDecl,
-                        "return" => TokenKind::Return,
-                        "string" => TokenKind::StringType,
-                        "float" => TokenKind::FloatType,
-                        "int" => TokenKind::IntType,
-                        "bool" => TokenKind::BoolType,
-                        "true" => TokenKind::Bool(true),
-                        "false" => TokenKind::Bool(false),
-                        "struct" => TokenKind::StructDecl,
-                        other => TokenKind::Ident(
-                            self.context.interner.intern(other),
-                        ),
-                    };
-
-                    return Ok(Some(Token {
-                        kind,
-                        location: self.make_location(start),
-                    }));
+                    return self.word(start).map(Some);
                 }
                 c if c.is_numeric() => {
                     self.advance_while(|c| c.is_numeric() || c == '.');
-                    let string = str_or_err!(self, start);
-                    let location = self.make_location(start);
-
-                    let kind = if let Ok(integer) = string.parse() {
-                        TokenKind::Integer(integer)
-                    } else if let Ok(float) = string.parse() {
-                        TokenKind::Float(float)
-                    } else {
-                        let mut iter = string.split("..");
-                        let start = iter.next();
-                        let end = iter.next();
-                        match (start.map(str::parse), end.map(str::parse)) {
-                            (Some(Ok(start)), Some(Ok(end))) => {
-                                TokenKind::Range(start, end)
-                            }
-                            other => {
-                                self.context
-                                    .report_error(&format!("Invalid syntax. Expected range, found {:?}", other), location);
-                                return Err(());
-                            }
-                        }
-                    };
-
-                    return Ok(Some(Token { kind, location }));
+                    self.exponent();
+                    return self.number(start).map(Some);
                 }
                 c if is_operator(c) => {
                     self.advance_while(is_operator);
-                    let location = self.make_location(start);
                     let operator = str_or_err!(self, start);
 
-                    if operator == "//" {
+                    if operator.starts_with("//") {
                         self.advance_while(|c| c != '\n');
                         continue;
                     }
 
-                    let kind = match operator {
-                        "&&" => TokenKind::And,
-                        "||" => TokenKind::Or,
-                        "==" => TokenKind::Equal,
-                        "!=" => TokenKind::NotEqual,
-                        "<=" => TokenKind::LesserEqual,
-                        ">=" => TokenKind::GreaterEqual,
-                        "+=" => TokenKind::AddAssign,
-                        "-=" => TokenKind::SubAssign,
-                        "*=" => TokenKind::MulAssign,
-                        "/=" => TokenKind::DivAssign,
-                        "%=" => TokenKind::ModAssign,
-                        ":=" => TokenKind::VarDecl,
-                        "->" => TokenKind::ReturnDecl,
-                        "=" => TokenKind::Assign,
-                        ">" => TokenKind::Greater,
-                        "<" => TokenKind::Lesser,
-                        "!" => TokenKind::Not,
-                        "+" => TokenKind::Add,
-                        "-" => TokenKind::Sub,
-                        "*" => TokenKind::Mul,
-                        "/" => TokenKind::Div,
-                        "%" => TokenKind::Mod,
-                        ":" => TokenKind::Colon,
-                        other => {
-                            self.context.report_error(
-                                &format!(
-                                    "Could not lex unknown operator '{}'",
-                                    other
+                    if operator.starts_with("/*") {
+                        self.pos = start;
+                        self.column -= operator.len() as u32;
+                        self.block_comment()?;
+                        continue;
+                    }
+
+                    // Operators can follow each other without whitespace, as
+                    // in `a==-1` or `!!a`, so only the longest known operator
+                    // is taken.
+                    while self.pos - start > 1
+                        && operator_kind(str_or_err!(self, start)).is_none()
+                    {
+                        self.pos -= 1;
+                    }
+
+                    let kind = match operator_kind(str_or_err!(self, start)) {
+                        Some(kind) => kind,
+                        None => {
+                            self.report_error(
+                                Message::UnknownOperator(
+                                    str_or_err!(self, start).to_string(),
                                 ),
                                 self.make_location(start),
                             );
@@ -199,54 +796,55 @@ impl<'a> Lexer<'a> {
                     return Ok(Some(Token {
                         kind,
                         location: self.make_location(start),
+                        line_break: false,
                     }));
                 }
-                c if is_delimiter(c) => {
-                    let kind = match c {
-                        '(' => TokenKind::OpenParen,
-                        ')' => TokenKind::CloseParen,
-                        '[' => TokenKind::OpenBracket,
-                        ']' => TokenKind::CloseBracket,
-                        '{' => TokenKind::OpenBrace,
-                        '}' => TokenKind::CloseBrace,
-                        ':' => TokenKind::Colon,
-                        ';' => TokenKind::SemiColon,
-                        ',' => TokenKind::Comma,
-                        _ => unreachable!(),
-                    };
-
+                '"' => return self.string(start).map(Some),
+                '#' => {
+                    self.advance_while(|c| c != '\n');
+                }
+                '.' => {
                     self.advance();
 
                     return Ok(Some(Token {
-                        kind,
+                        kind: TokenKind::Field,
                         location: self.make_location(start),
+                        line_break: false,
                     }));
                 }
-                '"' => {
-                    self.advance();
-                    self.advance_while(|c| c != '"');
-                    let location = self.make_location(start);
-                    let string = str_or_err!(self, start);
-                    let kind =
-                        TokenKind::String(self.context.interner.intern(string));
+                '?' => {
                     self.advance();
 
                     return Ok(Some(Token {
-                        kind,
+                        kind: TokenKind::Try,
                         location: self.make_location(start),
+                        line_break: false,
                     }));
                 }
-                '.' => {
+                '@' => {
                     self.advance();
 
                     return Ok(Some(Token {
-                        kind: TokenKind::Field,
+                        kind: TokenKind::At,
                         location: self.make_location(start),
+                        line_break: false,
                     }));
                 }
-                other => {
-                    self.context.report_error(
-                        &format!("Could not lex unknown token '{}'", other),
+                c => {
+                    self.advance();
+
+                    if let Some(kind) = delimiter(c) {
+                        return Ok(Some(Token {
+                            kind,
+                            location: self.make_location(start),
+                            line_break: false,
+                        }));
+                    }
+
+                    self.advance_while(|c| c as u32 & 0xC0 == 0x80);
+                    let other = str_or_err!(self, start).chars().next();
+                    self.report_error(
+                        Message::UnknownToken(other.unwrap_or('\u{fffd}')),
                         self.make_location(start),
                     );
 
@@ -259,12 +857,117 @@ impl<'a> Lexer<'a> {
     }
 }
 
+/// The tokens `logos` finds for `Lexer::next_scanned`. Operators and
+/// delimiters are complete tokens, the rest are finished by the lexer.
+#[cfg(feature = "logos")]
+#[derive(Logos, Debug, Clone, PartialEq)]
+#[logos(source = [u8])]
+#[logos(skip r"[ \t\r\x0B\x0C]+")]
+enum Scanned {
+    #[token("\n")]
+    Newline,
+    #[regex("[A-Za-z_][A-Za-z0-9_]*")]
+    Word,
+    #[regex("[0-9][0-9.]*")]
+    Number,
+    #[token("//")]
+    #[token("#")]
+    LineComment,
+    #[token("/*")]
+    BlockComment,
+    #[token("\"")]
+    Quote,
+    #[token("&&", |_| TokenKind::And)]
+    #[token("||", |_| TokenKind::Or)]
+    #[token("==", |_| TokenKind::Equal)]
+    #[token("!=", |_| TokenKind::NotEqual)]
+    #[token("<=", |_| TokenKind::LesserEqual)]
+    #[token(">=", |_| TokenKind::GreaterEqual)]
+    #[token("+=", |_| TokenKind::AddAssign)]
+    #[token("-=", |_| TokenKind::SubAssign)]
+    #[token("*=", |_| TokenKind::MulAssign)]
+    #[token("/=", |_| TokenKind::DivAssign)]
+    #[token("%=", |_| TokenKind::ModAssign)]
+    #[token(":=", |_| TokenKind::VarDecl)]
+    #[token("->", |_| TokenKind::ReturnDecl)]
+    #[token("=>", |_| TokenKind::FatArrow)]
+    #[token("::", |_| TokenKind::PathSep)]
+    #[token("=", |_| TokenKind::Assign)]
+    #[token(">", |_| TokenKind::Greater)]
+    #[token("<", |_| TokenKind::Lesser)]
+    #[token("!", |_| TokenKind::Not)]
+    #[token("+", |_| TokenKind::Add)]
+    #[token("-", |_| TokenKind::Sub)]
+    #[token("*", |_| TokenKind::Mul)]
+    #[token("/", |_| TokenKind::Div)]
+    #[token("%", |_| TokenKind::Mod)]
+    #[token(":", |_| TokenKind::Colon)]
+    #[token(".", |_| TokenKind::Field)]
+    #[token("?", |_| TokenKind::Try)]
+    #[token("@", |_| TokenKind::At)]
+    #[token("(", |_| TokenKind::OpenParen)]
+    #[token(")", |_| TokenKind::CloseParen)]
+    #[token("[", |_| TokenKind::OpenBracket)]
+    #[token("]", |_| TokenKind::CloseBracket)]
+    #[token("{", |_| TokenKind::OpenBrace)]
+    #[token("}", |_| TokenKind::CloseBrace)]
+    #[token(";", |_| TokenKind::SemiColon)]
+    #[token(",", |_| TokenKind::Comma)]
+    Token(TokenKind),
+}
+
+fn operator_kind(operator: &str) -> Option<TokenKind> {
+    let kind = match operator {
+        "&&" => TokenKind::And,
+        "||" => TokenKind::Or,
+        "==" => TokenKind::Equal,
+        "!=" => TokenKind::NotEqual,
+        "<=" => TokenKind::LesserEqual,
+        ">=" => TokenKind::GreaterEqual,
+        "+=" => TokenKind::AddAssign,
+        "-=" => TokenKind::SubAssign,
+        "*=" => TokenKind::MulAssign,
+        "/=" => TokenKind::DivAssign,
+        "%=" => TokenKind::ModAssign,
+        ":=" => TokenKind::VarDecl,
+        "->" => TokenKind::ReturnDecl,
+        "=>" => TokenKind::FatArrow,
+        "::" => TokenKind::PathSep,
+        "=" => TokenKind::Assign,
+        ">" => TokenKind::Greater,
+        "<" => TokenKind::Lesser,
+        "!" => TokenKind::Not,
+        "+" => TokenKind::Add,
+        "-" => TokenKind::Sub,
+        "*" => TokenKind::Mul,
+        "/" => TokenKind::Div,
+        "%" => TokenKind::Mod,
+        ":" => TokenKind::Colon,
+        _ => return None,
+    };
+
+    Some(kind)
+}
+
 fn is_operator(ch: char) -> bool {
     "+-*<>=!&|:/%".contains(ch)
 }
 
-fn is_delimiter(ch: char) -> bool {
-    "(){}[]:,;".contains(ch)
+fn delimiter(ch: char) -> Option<TokenKind> {
+    let kind = match ch {
+        '(' => TokenKind::OpenParen,
+        ')' => TokenKind::CloseParen,
+        '[' => TokenKind::OpenBracket,
+        ']' => TokenKind::CloseBracket,
+        '{' => TokenKind::OpenBrace,
+        '}' => TokenKind::CloseBrace,
+        ':' => TokenKind::Colon,
+        ';' => TokenKind::SemiColon,
+        ',' => TokenKind::Comma,
+        _ => return None,
+    };
+
+    Some(kind)
 }
 
 #[cfg(test)]
@@ -284,13 +987,15 @@ mod tests {
     #[test]
     fn lex_keywords() {
         assert_lex(
-            b"if else for return while fn struct",
+            b"if else for return while break continue fn struct",
             &[
                 TokenKind::If,
                 TokenKind::Else,
                 TokenKind::For,
                 TokenKind::Return,
                 TokenKind::While,
+                TokenKind::Break,
+                TokenKind::Continue,
                 TokenKind::FunctionDecl,
                 TokenKind::StructDecl,
             ],
@@ -317,6 +1022,179 @@ mod tests {
         assert_lex(b" ! ", &[TokenKind::Not]);
     }
 
+    #[test]
+    fn lex_arrows_and_paths() {
+        assert_lex(
+            b"-> => a::b _ match @",
+            &[
+                TokenKind::ReturnDecl,
+                TokenKind::FatArrow,
+                TokenKind::Ident(Symbol::new(0)),
+                TokenKind::PathSep,
+                TokenKind::Ident(Symbol::new(1)),
+                TokenKind::Ident(Symbol::new(2)),
+                TokenKind::Match,
+                TokenKind::At,
+            ],
+        );
+    }
+
+    #[test]
+    fn line_breaks() {
+        let mut context = Context::new();
+        let source = "a := b\n-1 + (c\n- d) +\ne\nf\n.g()\n{\nh\n}";
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            generate_tokens(source.as_bytes(), file, &mut context).unwrap();
+
+        let breaks: Vec<_> = tokens
+            .iter()
+            .filter(|token| token.location.line > 1)
+            .map(|token| token.line_break)
+            .collect();
+        assert_eq!(
+            breaks,
+            [
+                true, false, false, false, false, // -1 + (c
+                false, false, false, false, // - d) +
+                false, // e
+                true,  // f
+                true, false, false, false, // .g()
+                true, true, true, // { h }
+            ]
+        );
+    }
+
+    #[test]
+    fn line_breaks_in_maps() {
+        let mut context = Context::new();
+        let source = "m := {\n1: 2\n- 3}\nif m {\nx\n- 1 }";
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            generate_tokens(source.as_bytes(), file, &mut context).unwrap();
+
+        let breaks: Vec<_> = tokens
+            .iter()
+            .filter(|token| token.location.line > 1)
+            .map(|token| (token.kind.clone(), token.line_break))
+            .filter(|(kind, _)| *kind == TokenKind::Sub)
+            .map(|(_, line_break)| line_break)
+            .collect();
+        assert_eq!(breaks, [false, true]);
+    }
+
+    #[test]
+    fn lex_adjacent_operators() {
+        assert_lex(
+            b"a==-1 !!b c&&!d",
+            &[
+                TokenKind::Ident(Symbol::new(0)),
+                TokenKind::Equal,
+                TokenKind::Sub,
+                TokenKind::Integer(1),
+                TokenKind::Not,
+                TokenKind::Not,
+                TokenKind::Ident(Symbol::new(1)),
+                TokenKind::Ident(Symbol::new(2)),
+                TokenKind::And,
+                TokenKind::Not,
+            ],
+        )
+    }
+
+    fn lex_string(source: &str) -> Result<String, ()> {
+        let mut context = Context::new();
+        context
+            .source_code
+            .insert("test.bl".into(), source.to_string());
+        let file = context.interner.intern("test.bl");
+        let tokens = generate_tokens(source.as_bytes(), file, &mut context)?;
+
+        match tokens[0].kind {
+            TokenKind::String(s) => Ok(context.interner.get(s).to_string()),
+            ref other => panic!("Expected string, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lex_strings() {
+        assert_eq!(lex_string(r#""""#), Ok("".to_string()));
+        assert_eq!(lex_string(r#""a b""#), Ok("a b".to_string()));
+        assert_eq!(lex_string(r#""\n\t\r\\\"""#), Ok("\n\t\r\\\"".to_string()));
+        assert_eq!(lex_string(r#""\u{e5}\u{1F600}""#), Ok("å😀".to_string()));
+        assert_eq!(lex_string(r#""ab\"c d""#), Ok("ab\"c d".to_string()));
+    }
+
+    #[test]
+    fn lex_interpolation() {
+        let mut context = Context::new();
+        let source = "\"a ${b + \"${c}\"} {}\"";
+        context
+            .source_code
+            .insert("test.bl".into(), source.to_string());
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            generate_tokens(source.as_bytes(), file, &mut context).unwrap();
+
+        let mut intern = |s| context.interner.intern(s);
+        let (a, b, c, empty, braces) = (
+            intern("a "),
+            intern("b"),
+            intern("c"),
+            intern(""),
+            intern(" {}"),
+        );
+        let expected = [
+            TokenKind::InterpolationStart,
+            TokenKind::String(a),
+            TokenKind::Ident(b),
+            TokenKind::Add,
+            TokenKind::InterpolationStart,
+            TokenKind::String(empty),
+            TokenKind::Ident(c),
+            TokenKind::String(empty),
+            TokenKind::InterpolationEnd,
+            TokenKind::String(braces),
+            TokenKind::InterpolationEnd,
+        ];
+        let kinds: Vec<_> =
+            tokens.into_iter().map(|token| token.kind).collect();
+        assert_eq!(kinds, expected);
+
+        assert_eq!(lex_string(r#""\${a}""#), Ok("${a}".to_string()));
+        assert!(lex_string(r#""${}""#).is_err());
+        assert!(lex_string(r#""${a""#).is_err());
+    }
+
+    #[test]
+    fn lex_invalid_strings() {
+        assert!(lex_string(r#""abc"#).is_err());
+        assert!(lex_string("\"abc\ndef\"").is_err());
+        assert!(lex_string(r#""\q""#).is_err());
+        assert!(lex_string(r#""\u{}""#).is_err());
+        assert!(lex_string(r#""\u{110000}""#).is_err());
+        assert!(lex_string(r#""\u00e5""#).is_err());
+    }
+
+    #[test]
+    fn lex_limits() {
+        let lex = |source: &str| {
+            let mut context = Context::new();
+            context.limits.max_tokens = 3;
+            context.limits.max_string_len = 4;
+            context
+                .source_code
+                .insert("test.bl".into(), source.to_string());
+            let file = context.interner.intern("test.bl");
+            generate_tokens(source.as_bytes(), file, &mut context)
+        };
+
+        assert!(lex("a := 1").is_ok());
+        assert!(lex("a := 1 + 2").is_err());
+        assert!(lex("\"abcd\"").is_ok());
+        assert!(lex("\"abcde\"").is_err());
+    }
+
     #[test]
     fn lex_assignment_operator() {
         assert_lex(
@@ -368,7 +1246,7 @@ mod tests {
     #[test]
     fn lex_comment() {
         assert_lex(
-            b"hello // there friend\no",
+            b"hello // there friend\no //! name: x\n//!=",
             &[
                 TokenKind::Ident(Symbol::new(0)),
                 TokenKind::Ident(Symbol::new(1)),
@@ -376,6 +1254,27 @@ mod tests {
         )
     }
 
+    #[test]
+    fn lex_block_comment() {
+        assert_lex(
+            b"a /* b /* nested */\n c */ d # e\nf/**/*g",
+            &[
+                TokenKind::Ident(Symbol::new(0)),
+                TokenKind::Ident(Symbol::new(1)),
+                TokenKind::Ident(Symbol::new(2)),
+                TokenKind::Mul,
+                TokenKind::Ident(Symbol::new(3)),
+            ],
+        );
+
+        let mut context = Context::new();
+        let file = context.interner.intern("test.bl");
+        context
+            .source_code
+            .insert("test.bl".into(), "a /* b /* c */".to_string());
+        assert!(generate_tokens(b"a /* b /* c */", file, &mut context).is_err());
+    }
+
     #[test]
     fn lex_range() {
         assert_lex(b" 5..10", &[TokenKind::Range(5, 10)]);
@@ -397,6 +1296,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn lex_float_exponent() {
+        assert_lex(
+            b" 1.5e-3 2E10 3e+2 4else",
+            &[
+                TokenKind::Float(1.5e-3),
+                TokenKind::Float(2e10),
+                TokenKind::Float(3e2),
+                TokenKind::Integer(4),
+                TokenKind::Else,
+            ],
+        );
+    }
+
+    #[test]
+    fn lex_int_out_of_range() {
+        let mut context = Context::new();
+        let source = "a := 99999999999";
+        context
+            .source_code
+            .insert("test.bl".into(), source.to_string());
+        let file = context.interner.intern("test.bl");
+
+        assert!(generate_tokens(source.as_bytes(), file, &mut context).is_err());
+    }
+
     #[test]
     fn lex_bool() {
         assert_lex(
@@ -449,16 +1374,95 @@ mod tests {
         )
     }
 
+    #[test]
+    fn lex_try() {
+        assert_lex(
+            b"parse(s)? + 1",
+            &[
+                TokenKind::Ident(Symbol::new(0)),
+                TokenKind::OpenParen,
+                TokenKind::Ident(Symbol::new(1)),
+                TokenKind::CloseParen,
+                TokenKind::Try,
+                TokenKind::Add,
+                TokenKind::Integer(1),
+            ],
+        );
+    }
+
     #[test]
     fn lex_types() {
         assert_lex(
-            b"string float int bool",
+            b"string float int bool result",
             &[
                 TokenKind::StringType,
                 TokenKind::FloatType,
                 TokenKind::IntType,
                 TokenKind::BoolType,
+                TokenKind::ResultType,
             ],
         );
     }
+
+    #[test]
+    fn trailing_whitespace_keeps_lines() {
+        let mut context = Context::new();
+        let source = b"a \t\nb  \n\nc";
+        let tokens =
+            generate_tokens(source, Symbol::new(0), &mut context).unwrap();
+
+        let lines: Vec<_> = tokens.iter().map(|t| t.location.line).collect();
+        assert_eq!(lines, [1, 2, 4]);
+    }
+
+    /// The tokens and errors for `source`, written out so that backends
+    /// can be compared.
+    #[cfg(feature = "logos")]
+    fn lexed_by(backend: Backend, source: &[u8]) -> String {
+        let mut context = Context::new();
+        let lexed = lex_with(backend, source, Symbol::new(0), &mut context);
+        format!(
+            "{:?} {:?} {}",
+            lexed.tokens, lexed.diagnostics, lexed.unterminated
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "logos")]
+    fn backends_agree() {
+        use std::fs;
+        use std::path::Path;
+
+        let mut sources: Vec<Vec<u8>> = [
+            "a==-1 !!b c&&!d x&=1 y|z ::: !== =>= <-",
+            "1.5e-3 2e 3E+ 0..10 1.2.3 99999999999 1.foo",
+            "a // b\n# c\nd /* e /* f */ g */ h /* i",
+            "\"a ${b + \"${c}\"} {}\" \"\\q\" \"open",
+            "x := 1 \t\n\x0b\x0cy;z, @p.q? [](){}",
+            "caf\u{e9} \u{e9}t\u{e9} 1\u{b2} a\u{a0}b ~ $ \u{1F600}",
+        ]
+        .iter()
+        .map(|source| source.as_bytes().to_vec())
+        .collect();
+        sources.push(b"ab\xff cd \xc3".to_vec());
+
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        for dir in &["test", "test/fuzz", "std"] {
+            for entry in fs::read_dir(root.join(dir)).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_file() {
+                    sources.push(fs::read(path).unwrap());
+                }
+            }
+        }
+
+        for source in &sources {
+            assert_eq!(
+                lexed_by(Backend::Logos, source),
+                lexed_by(Backend::HandWritten, source),
+                "{}",
+                String::from_utf8_lossy(source)
+            );
+        }
+    }
 }
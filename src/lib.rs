@@ -0,0 +1,55 @@
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod arena;
+pub mod ast;
+pub mod audit;
+pub mod builtins;
+pub mod common;
+pub mod compiler;
+#[cfg(feature = "compression")]
+pub mod compress;
+pub mod config;
+pub mod crash;
+pub mod diff;
+pub mod engine;
+pub mod form;
+pub mod fuzzy;
+pub mod human;
+pub mod index;
+pub mod interpreter;
+pub mod interrupt;
+pub mod json;
+pub mod lexer;
+pub mod location;
+#[cfg(feature = "xml")]
+pub mod markup;
+pub mod messages;
+pub mod metadata;
+pub mod modules;
+pub mod parser;
+pub mod pool;
+pub mod pretty;
+pub mod primitives;
+pub mod progress;
+pub mod random;
+pub mod refactor;
+pub mod schema;
+pub mod scope;
+pub mod semantic;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod sha256;
+pub mod snapshot;
+pub mod statistics;
+pub mod table;
+pub mod template;
+pub mod text;
+pub mod token;
+pub mod typecheck;
+pub mod url;
+pub mod vm;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub use crate::engine::{Engine, EngineBuilder, Error};
+pub use crate::pool::EnginePool;
@@ -9,25 +9,32 @@ pub struct Location {
     pub span: Span,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Span {
     pub start: u32,
     pub len: u32,
 }
 
+impl Location {
+    /// One-based column of the start of the location, counted in bytes from
+    /// the start of its line.
+    pub fn column(&self, source: &[u8]) -> u32 {
+        let start = (self.span.start as usize).min(source.len());
+        let line_start = source[..start]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |pos| pos + 1);
+
+        (start - line_start) as u32 + 1
+    }
+}
+
 fn join_locations(a: &Location, b: &Location) -> Location {
-    let (smaller, larger) = {
-        if a.span.start < b.span.start {
-            (a, b)
-        } else {
-            (b, a)
-        }
-    };
+    let mut location = if a.span.start < b.span.start { *a } else { *b };
 
-    let mut location = *smaller;
-    location.span.len += larger.span.start
-        - (location.span.start + location.span.len)
-        + larger.span.len;
+    // One span may lie within the other, so the end is the furthest one.
+    let end = (a.span.start + a.span.len).max(b.span.start + b.span.len);
+    location.span.len = end - location.span.start;
 
     location
 }
@@ -44,3 +51,37 @@ impl AddAssign for Location {
         *self = join_locations(self, &other);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(line: u32, start: u32) -> Location {
+        Location {
+            file: Symbol::new(0),
+            line,
+            span: Span { start, len: 1 },
+        }
+    }
+
+    #[test]
+    fn column_of_location() {
+        let source = b"a := 1\nbc := 2\n";
+        assert_eq!(location(1, 0).column(source), 1);
+        assert_eq!(location(1, 5).column(source), 6);
+        assert_eq!(location(2, 7).column(source), 1);
+        assert_eq!(location(2, 10).column(source), 4);
+    }
+
+    #[test]
+    fn join_locations() {
+        let mut outer = location(1, 2);
+        outer.span.len = 5;
+        assert_eq!(
+            (location(1, 0) + location(1, 4)).span,
+            Span { start: 0, len: 5 }
+        );
+        assert_eq!((outer + location(1, 3)).span, outer.span);
+        assert_eq!((location(1, 6) + outer).span, Span { start: 2, len: 5 });
+    }
+}
@@ -1,32 +1,74 @@
-mod arena;
-mod ast;
-mod common;
-// mod interpreter;
-mod lexer;
-mod location;
 mod options;
-mod parser;
-mod primitives;
-mod scope;
-mod token;
-mod typecheck;
+mod repl;
+mod stats;
 
 use std::env;
-use std::fs;
+use std::fmt::Display;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
 
-use log::info;
+use log::{info, log_enabled, trace, Level, LevelFilter};
 
-use common::Context;
-use options::Options;
+use blixt::audit::AuditLog;
+use blixt::common::{Context, Grants, Symbol};
+use blixt::config::{Config, Layer, CONFIG_FILE};
+use blixt::index::{OccurrenceKind, SymbolIndex};
+use blixt::messages::{Language, Message};
+use blixt::{
+    builtins, compiler, crash, interpreter, interrupt, lexer, metadata,
+    modules, parser, pretty, refactor, semantic, template, typecheck, vm,
+};
+
+use crate::options::{Command, Emit, Options, Rename, Stage};
+use crate::stats::{CountingAllocator, Stats};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
 
 fn main() {
-    env_logger::init();
-    let _ = run();
+    crash::install();
+    // Errors have been reported by the time `run` returns, so all that is
+    // left is to tell the shell.
+    if run().is_err() {
+        process::exit(1);
+    }
 }
 
 fn run() -> Result<(), ()> {
     let options = Options::parse();
+    init_logging(options.log_level);
+
+    let config = match load_config(&options) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return Err(());
+        }
+    };
+    if options.print_config {
+        print!("{}", config);
+        return Ok(());
+    }
+
     let mut context = Context::new();
+    config.apply(&mut context);
+    context.prompt = options.prompt;
+    if options.stats || options.trace_json {
+        stats::count_allocations();
+        context.profile = Some(Default::default());
+    }
+
+    if let Some(path) = &options.audit {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|error| report_write(path, error, context.language))?;
+        context.audit = Some(AuditLog::new(Box::new(file)));
+    }
 
     if let Ok(var) = env::var("BLIXT_DEBUG") {
         if var == "1" {
@@ -35,20 +77,423 @@ fn run() -> Result<(), ()> {
         }
     }
 
-    let interned_file = context.interner.intern(&options.file);
-    let source = fs::read(&options.file).expect("Cant open file");
+    builtins::register(&mut context);
+    builtins::set_args(&mut context, &options.args);
+    if let Command::Run { file } = &options.command {
+        if file != "-" {
+            let dir = builtins::cache_dir(Some(Path::new(file)));
+            builtins::set_cache_dir(&mut context, dir);
+        }
+    }
+    interrupt::install();
+
+    let language = context.language;
+    let result =
+        panic::catch_unwind(AssertUnwindSafe(|| match &options.command {
+            Command::Run { file } => run_file(file, &options, &mut context),
+            Command::Eval { code } => {
+                run_code("<eval>", code.as_bytes(), &options, &mut context)
+            }
+            Command::Info { file } => show_info(file, &mut context),
+            Command::Template { file } => {
+                run_template(file, &options, &mut context)
+            }
+            Command::Repl => repl::run(context),
+            Command::Rename(rename) => rename_symbol(rename, &mut context),
+            Command::References { name, files } => {
+                find_references(name, files, &mut context)
+            }
+        }));
+
+    match result {
+        Ok(result) => result,
+        Err(payload) => {
+            report_crash(language);
+            panic::resume_unwind(payload)
+        }
+    }
+}
+
+/// Saves a report of a panic in blixt itself and says where, so that it
+/// can be sent along with a bug report.
+fn report_crash(language: Language) {
+    match crash::write_report() {
+        Ok(path) => {
+            let path = path.display().to_string();
+            eprintln!("{}", Message::Crashed(path).text(language));
+        }
+        Err(report) => {
+            eprintln!("{}", Message::CrashReportUnsaved.text(language));
+            eprint!("{}", report);
+        }
+    }
+}
+
+/// Reads a file given on the command line.
+fn read_file(path: &str, context: &Context) -> Result<Vec<u8>, ()> {
+    fs::read(path).map_err(|error| report_read(path, error, context.language))
+}
+
+/// Reports a file given on the command line that can't be read. Unlike a
+/// crash this is not a bug in blixt, so only the error is shown.
+fn report_read(path: &str, error: io::Error, language: Language) {
+    let path = path.to_string();
+    let error = error.to_string();
+    let message = Message::CannotReadFile { path, error };
+    eprintln!("error: {}", message.text(language));
+}
+
+/// Reports a file given on the command line that can't be written.
+fn report_write(path: &str, error: io::Error, language: Language) {
+    let path = path.to_string();
+    let error = error.to_string();
+    let message = Message::CannotWriteFile { path, error };
+    eprintln!("error: {}", message.text(language));
+}
+
+/// The settings from defaults, the config file in the current directory,
+/// `BLIXT_` environment variables and flags, each over the ones before.
+fn load_config(options: &Options) -> Result<Config, String> {
+    let mut config = Config::default();
+    if let Ok(text) = fs::read_to_string(CONFIG_FILE) {
+        config.read_file(CONFIG_FILE, &text)?;
+    }
+    config.read_env(env::vars())?;
+    for (key, value) in &options.runtime {
+        config.set(key, value, Layer::Flag)?;
+    }
+    Ok(config)
+}
+
+/// Logs at `level`, with any filters in `RUST_LOG` applied on top so a
+/// single module can still be singled out.
+fn init_logging(level: LevelFilter) {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(level);
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse(&filters);
+    }
+    builder.init();
+}
+
+fn run_file(
+    file: &str,
+    options: &Options,
+    context: &mut Context,
+) -> Result<(), ()> {
+    if file == "-" {
+        let mut source = vec![];
+        io::stdin()
+            .read_to_end(&mut source)
+            .map_err(|error| report_read("<stdin>", error, context.language))?;
+        return run_code("<stdin>", &source, options, context);
+    }
+
+    let interned_file = context.interner.intern(file);
+    let source = read_file(file, context)?;
+
+    let mut stats = new_stats(options);
+    let result =
+        run_source(&source, interned_file, options, &mut stats, context);
+
+    stats.flush();
+    if options.stats {
+        eprint!("{}", stats);
+    }
+
+    result
+}
+
+/// Runs code that is not in a file, under a made up file name that errors
+/// can show lines from.
+fn run_code(
+    name: &str,
+    source: &[u8],
+    options: &Options,
+    context: &mut Context,
+) -> Result<(), ()> {
+    let file = context.interner.intern(name);
+    context.source_code.insert(
+        PathBuf::from(name),
+        String::from_utf8_lossy(source).into_owned(),
+    );
+
+    let mut stats = new_stats(options);
+    let result = run_source(source, file, options, &mut stats, context);
+
+    stats.flush();
+    if options.stats {
+        eprint!("{}", stats);
+    }
+
+    result
+}
+
+/// Runs the script a template turns into. Errors show the lines of the
+/// script, which are the same as those of the template.
+fn run_template(
+    file: &str,
+    options: &Options,
+    context: &mut Context,
+) -> Result<(), ()> {
+    let interned_file = context.interner.intern(file);
+    let source = read_file(file, context)?;
+    let source = String::from_utf8_lossy(&source).into_owned();
+    context
+        .source_code
+        .insert(PathBuf::from(file), source.clone());
+
+    let script = template::compile(&source, interned_file, context)?;
+    context
+        .source_code
+        .insert(PathBuf::from(file), script.clone());
+
+    let mut stats = new_stats(options);
+    let result = run_source(
+        script.as_bytes(),
+        interned_file,
+        options,
+        &mut stats,
+        context,
+    );
+
+    stats.flush();
+    if options.stats {
+        eprint!("{}", stats);
+    }
+
+    result
+}
+
+fn new_stats(options: &Options) -> Stats {
+    if options.trace_json {
+        Stats::with_trace(Box::new(io::stderr()))
+    } else {
+        Stats::new()
+    }
+}
+
+fn run_source(
+    source: &[u8],
+    file: Symbol,
+    options: &Options,
+    stats: &mut Stats,
+    context: &mut Context,
+) -> Result<(), ()> {
+    crash::set_source(context.interner.get(file), source);
+    let metadata = metadata::parse(source, file, context)?;
+    metadata.check_requirements(context)?;
+    // A script gets the capabilities granted by the config and `--allow`,
+    // and none when neither sets them, whatever it requires.
+    if context.grants == Grants::All {
+        context.grants = Grants::Only(vec![]);
+    }
+    metadata.check_grants(context, &mut io::stdin().lock())?;
+
+    let emitter = Emitter {
+        emit: &options.emit,
+        language: context.language,
+    };
 
     info!("Starting lexing");
-    let tokens = lexer::generate_tokens(&source, interned_file, &mut context)?;
+    let tokens = stats.time("lex", context, |context| {
+        modules::generate_tokens(source, file, context)
+    })?;
+    stats.count("tokens", tokens.len());
+
+    if emitter.wants(Stage::Tokens) {
+        let dump = pretty::Tokens {
+            tokens: &tokens,
+            source,
+            interner: &context.interner,
+        };
+        emitter.emit(Stage::Tokens, &dump)?;
+    }
+    if emitter.finished(Stage::Tokens) {
+        return Ok(());
+    }
 
     info!("Starting parsing");
-    let ast = parser::parse_ast(tokens, &mut context)?;
+    let ast = stats.time("parse", context, |context| {
+        parser::parse_ast(tokens, context)
+    })?;
+    stats.count("ast nodes", ast.arena.len());
+
+    if emitter.wants(Stage::Ast) {
+        let tree = pretty::Tree {
+            ast: &ast,
+            interner: &context.interner,
+        };
+        emitter.emit(Stage::Ast, &tree)?;
+    }
+    if emitter.finished(Stage::Ast) {
+        return Ok(());
+    }
+
+    info!("Starting semantic analysis");
+    stats.time("analyze", context, |context| {
+        semantic::analyze(&ast, context)
+    })?;
 
     info!("Starting typechecking");
-    typecheck::typecheck(&ast, &mut context)?;
+    stats.time("typecheck", context, |context| {
+        typecheck::typecheck(&ast, context)
+    })?;
     info!("Typechecking passed!");
 
-    // interpreter::interpret(&ast, &mut context);
+    if options.interpret && !emitter.wants(Stage::Bytecode) {
+        info!("Starting interpreter");
+        stats.time("run", context, |context| {
+            interpreter::interpret(&ast, context)
+        })?;
+        return Ok(());
+    }
+
+    info!("Starting compiling");
+    let program = stats.time("compile", context, |context| {
+        compiler::compile(&ast, context)
+    });
+    stats.count(
+        "instructions",
+        program.functions.iter().map(|func| func.code.len()).sum(),
+    );
+
+    if emitter.wants(Stage::Bytecode) {
+        let bytecode = pretty::Bytecode {
+            program: &program,
+            interner: &context.interner,
+        };
+        emitter.emit(Stage::Bytecode, &bytecode)?;
+    }
+    if emitter.finished(Stage::Bytecode) {
+        return Ok(());
+    }
+
+    info!("Starting VM");
+    stats.time("run", context, |context| vm::run(&program, context))?;
+
+    Ok(())
+}
+
+/// Writes the output of the stages given to `--emit` as running a file
+/// reaches them, and says when the last of them is done so the run can stop
+/// there. A new stage only needs a `Stage` and a call to `emit` where its
+/// output is ready.
+struct Emitter<'a> {
+    emit: &'a [Emit],
+    language: Language,
+}
+
+impl Emitter<'_> {
+    /// Whether the output of `stage` is needed, to write or to log.
+    fn wants(&self, stage: Stage) -> bool {
+        self.emit.iter().any(|emit| emit.stage == stage)
+            || log_enabled!(Level::Trace)
+    }
+
+    /// Writes `output` wherever `--emit` asked for `stage`, or logs it if
+    /// it was not asked for.
+    fn emit(&self, stage: Stage, output: &dyn Display) -> Result<(), ()> {
+        let mut emitted = false;
+        for emit in self.emit.iter().filter(|emit| emit.stage == stage) {
+            match &emit.file {
+                Some(file) => {
+                    fs::write(file, output.to_string()).map_err(|error| {
+                        report_write(file, error, self.language)
+                    })?
+                }
+                None => print!("{}", output),
+            }
+            emitted = true;
+        }
+        if !emitted {
+            trace!("{:?}:\n{}", stage, output);
+        }
+        Ok(())
+    }
+
+    /// Whether `stage` is the last one `--emit` asked for.
+    fn finished(&self, stage: Stage) -> bool {
+        self.emit.iter().map(|emit| emit.stage).max() == Some(stage)
+    }
+}
+
+fn show_info(file: &str, context: &mut Context) -> Result<(), ()> {
+    let interned_file = context.interner.intern(file);
+    let source = read_file(file, context)?;
+    let metadata = metadata::parse(&source, interned_file, context)?;
+
+    if metadata.is_empty() {
+        let message = Message::NoMetadata(file.to_string());
+        println!("{}", message.text(context.language));
+    } else {
+        print!("{}", metadata);
+    }
+
+    Ok(())
+}
+
+fn rename_symbol(rename: &Rename, context: &mut Context) -> Result<(), ()> {
+    let interned_file = context.interner.intern(&rename.file);
+    let source = read_file(&rename.file, context)?;
+
+    let output = refactor::rename(
+        &source,
+        interned_file,
+        &rename.old,
+        &rename.new,
+        rename.line,
+        rename.column,
+        context,
+    )?;
+
+    fs::write(&rename.file, output)
+        .map_err(|error| report_write(&rename.file, error, context.language))?;
+
+    Ok(())
+}
+
+fn find_references(
+    name: &str,
+    files: &[String],
+    context: &mut Context,
+) -> Result<(), ()> {
+    let mut index = SymbolIndex::new();
+    let mut sources = Vec::new();
+
+    for file in files {
+        let interned_file = context.interner.intern(file);
+        let source = read_file(file, context)?;
+        let tokens = lexer::generate_tokens(&source, interned_file, context)?;
+        let ast = parser::parse_ast(tokens, context)?;
+        index.add_file(interned_file, &ast, context);
+        sources.push((interned_file, source));
+    }
+
+    let name = context.interner.intern(name);
+
+    for occurrence in index.occurrences(name) {
+        let location = occurrence.location;
+        // Every occurrence is in one of the files read above.
+        let source =
+            match sources.iter().find(|(file, _)| *file == location.file) {
+                Some((_, source)) => source,
+                None => continue,
+            };
+
+        let kind = match occurrence.kind {
+            OccurrenceKind::Definition => "definition",
+            OccurrenceKind::Reference => "reference",
+        };
+
+        println!(
+            "{}:{}:{}: {}",
+            context.interner.get(location.file),
+            location.line,
+            location.column(source),
+            kind
+        );
+    }
 
     Ok(())
 }
@@ -0,0 +1,651 @@
+/// A node of an XML or HTML document.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Element {
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(attr, _)| attr == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The text of the element and everything in it, in order.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for child in &self.children {
+            match child {
+                Node::Element(element) => text += &element.text(),
+                Node::Text(s) => text += s,
+            }
+        }
+        text
+    }
+}
+
+/// Elements that never have children in HTML, so they need no end tag.
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta",
+    "source", "track", "wbr",
+];
+
+/// Elements whose content HTML does not parse as markup.
+const RAW_TEXT: &[&str] = &["script", "style"];
+
+/// Parses an XML document into an element named `#document` holding its
+/// top level nodes. Returns the reason if a tag is not closed or an end
+/// tag does not match.
+pub fn parse_xml(text: &str) -> Result<Element, String> {
+    Parser::new(text, false).document()
+}
+
+/// Parses an HTML document the way browsers do for the common cases. Tag
+/// and attribute names are made lowercase, elements like `img` need no
+/// end tag and end tags that match no open element are ignored, so this
+/// does not fail.
+pub fn parse_html(text: &str) -> Element {
+    Parser::new(text, true)
+        .document()
+        .expect("HTML is parsed leniently")
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    at: usize,
+    html: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str, html: bool) -> Self {
+        Parser { text, at: 0, html }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.at..]
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}", message, self.at)
+    }
+
+    fn document(&mut self) -> Result<Element, String> {
+        // The open elements, the document itself first.
+        let mut open = vec![Element {
+            name: "#document".to_string(),
+            ..Element::default()
+        }];
+
+        while self.at < self.text.len() {
+            let rest = self.rest();
+            if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<![CDATA[") {
+                self.at += "<![CDATA[".len();
+                let text = self.take_until("]]>")?;
+                self.at += "]]>".len();
+                push_text(&mut open, text.to_string());
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                self.skip_past(">")?;
+            } else if rest.starts_with("</") {
+                self.at += 2;
+                let name = self.name();
+                self.skip_past(">")?;
+                self.close(&mut open, &name)?;
+            } else if rest.starts_with('<')
+                && rest[1..].starts_with(|c: char| c.is_alphabetic())
+            {
+                self.at += 1;
+                self.open(&mut open)?;
+            } else {
+                let end = rest[1..].find('<').map_or(rest.len(), |i| i + 1);
+                self.at += end;
+                let text = self.unescape(&rest[..end])?;
+                push_text(&mut open, text);
+            }
+        }
+
+        if open.len() > 1 && !self.html {
+            let name = &open[open.len() - 1].name;
+            return Err(self.error(&format!("Expected </{}>", name)));
+        }
+        while open.len() > 1 {
+            close_last(&mut open);
+        }
+        Ok(open.pop().expect("The document is always open"))
+    }
+
+    /// Reads a start tag after its `<`, adding the element to the open
+    /// ones unless it has no content.
+    fn open(&mut self, open: &mut Vec<Element>) -> Result<(), String> {
+        let name = self.name();
+        let mut element = Element {
+            name,
+            ..Element::default()
+        };
+
+        let closed = loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("/>") {
+                self.at += 2;
+                break true;
+            } else if rest.starts_with('>') {
+                self.at += 1;
+                break false;
+            } else if rest.is_empty() {
+                return Err(self.error("Unterminated tag"));
+            }
+
+            let attr = self.name();
+            if attr.is_empty() {
+                return Err(self.error("Expected an attribute"));
+            }
+            self.skip_whitespace();
+            let value = if self.rest().starts_with('=') {
+                self.at += 1;
+                self.skip_whitespace();
+                self.attr_value()?
+            } else if self.html {
+                String::new()
+            } else {
+                return Err(self.error("Expected '='"));
+            };
+            element.attrs.push((attr, value));
+        };
+
+        if self.html {
+            // Another item or paragraph ends the one before it.
+            let last = &open[open.len() - 1].name;
+            let ends_last = match element.name.as_str() {
+                "li" | "p" | "tr" | "option" => *last == element.name,
+                "td" | "th" => last == "td" || last == "th",
+                _ => false,
+            };
+            if ends_last {
+                close_last(open);
+            }
+
+            if RAW_TEXT.contains(&element.name.as_str()) && !closed {
+                let end = format!("</{}", element.name);
+                let text = self.take_until_ignoring_case(&end);
+                if !text.is_empty() {
+                    element.children.push(Node::Text(text.to_string()));
+                }
+                self.skip_past(">").ok();
+                push_element(open, element);
+                return Ok(());
+            }
+        }
+
+        if closed || (self.html && VOID.contains(&element.name.as_str())) {
+            push_element(open, element);
+        } else {
+            open.push(element);
+        }
+        Ok(())
+    }
+
+    /// Closes the open element called `name`, which has to be the last one
+    /// in XML. HTML closes the elements in it as well, or ignores the end
+    /// tag if there is no such element.
+    fn close(
+        &mut self,
+        open: &mut Vec<Element>,
+        name: &str,
+    ) -> Result<(), String> {
+        if self.html {
+            if let Some(i) = open.iter().rposition(|e| e.name == name) {
+                if i > 0 {
+                    while open.len() > i {
+                        close_last(open);
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        let last = &open[open.len() - 1].name;
+        if open.len() == 1 || last != name {
+            let expected = if open.len() == 1 {
+                format!("Unexpected </{}>", name)
+            } else {
+                format!("Expected </{}>", last)
+            };
+            return Err(self.error(&expected));
+        }
+        close_last(open);
+        Ok(())
+    }
+
+    fn name(&mut self) -> String {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| {
+                c.is_whitespace() || c == '>' || c == '/' || c == '='
+            })
+            .unwrap_or(rest.len());
+        self.at += end;
+        if self.html {
+            rest[..end].to_ascii_lowercase()
+        } else {
+            rest[..end].to_string()
+        }
+    }
+
+    fn attr_value(&mut self) -> Result<String, String> {
+        let rest = self.rest();
+        let raw = match rest.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                let end = rest[1..]
+                    .find(quote)
+                    .ok_or_else(|| self.error("Unterminated attribute"))?;
+                self.at += end + 2;
+                &rest[1..end + 1]
+            }
+            _ if self.html => {
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or(rest.len());
+                self.at += end;
+                &rest[..end]
+            }
+            _ => return Err(self.error("Expected a quoted value")),
+        };
+        self.unescape(raw)
+    }
+
+    /// Replaces the entities in `text` with the characters they stand for.
+    /// HTML leaves unknown ones as they are.
+    fn unescape(&self, text: &str) -> Result<String, String> {
+        let mut unescaped = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(amp) = rest.find('&') {
+            unescaped += &rest[..amp];
+            rest = &rest[amp..];
+
+            let entity = rest[1..]
+                .find(';')
+                .filter(|end| *end <= 10)
+                .map(|end| &rest[1..end + 1]);
+            let c = entity.and_then(|entity| match entity {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" if self.html => Some('\u{a0}'),
+                _ => {
+                    let code = match entity.strip_prefix("#x") {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => entity.strip_prefix('#')?.parse().ok(),
+                    };
+                    code.and_then(std::char::from_u32)
+                }
+            });
+
+            match (c, entity) {
+                (Some(c), Some(entity)) => {
+                    unescaped.push(c);
+                    rest = &rest[entity.len() + 2..];
+                }
+                _ if self.html => {
+                    unescaped.push('&');
+                    rest = &rest[1..];
+                }
+                _ => return Err(self.error("Invalid entity")),
+            }
+        }
+        unescaped += rest;
+        Ok(unescaped)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.at += rest.len() - rest.trim_start().len();
+    }
+
+    fn skip_past(&mut self, end: &str) -> Result<(), String> {
+        self.take_until(end)?;
+        self.at += end.len();
+        Ok(())
+    }
+
+    /// The text up to `end`, which is left next.
+    fn take_until(&mut self, end: &str) -> Result<&'a str, String> {
+        let rest = self.rest();
+        match rest.find(end) {
+            Some(i) => {
+                self.at += i;
+                Ok(&rest[..i])
+            }
+            None => Err(self.error(&format!("Expected '{}'", end))),
+        }
+    }
+
+    /// The text up to `end` in any case, or to the end of the document.
+    fn take_until_ignoring_case(&mut self, end: &str) -> &'a str {
+        let rest = self.rest();
+        let i = rest.to_ascii_lowercase().find(end).unwrap_or(rest.len());
+        self.at += i;
+        &rest[..i]
+    }
+}
+
+fn push_text(open: &mut [Element], text: String) {
+    // Whitespace between tags is only there to indent them.
+    if text.trim().is_empty() {
+        return;
+    }
+    let last = open.len() - 1;
+    open[last].children.push(Node::Text(text));
+}
+
+fn push_element(open: &mut [Element], element: Element) {
+    let last = open.len() - 1;
+    open[last].children.push(Node::Element(element));
+}
+
+fn close_last(open: &mut Vec<Element>) {
+    let element = open.pop().expect("Only open elements are closed");
+    push_element(open, element);
+}
+
+/// A CSS selector, like `ul > li.item a[href]`, or several separated by
+/// commas.
+#[derive(Debug, PartialEq)]
+pub struct Selector {
+    /// Each alternative, as the parts an element and its ancestors have to
+    /// match, the element's own last.
+    alternatives: Vec<Vec<(Combinator, Compound)>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+/// The conditions on a single element, like `a.link[href]`.
+#[derive(Debug, Default, PartialEq)]
+struct Compound {
+    name: Option<String>,
+    ids: Vec<String>,
+    classes: Vec<String>,
+    /// Attributes that have to be there, with the value they have to have
+    /// if one is given.
+    attrs: Vec<(String, Option<String>)>,
+}
+
+impl Selector {
+    /// Parses a selector using names, `*`, `.class`, `#id`, `[attr]`,
+    /// `[attr=value]`, spaces and `>`. Returns the reason if it uses
+    /// anything else.
+    pub fn parse(text: &str) -> Result<Selector, String> {
+        let mut alternatives = Vec::new();
+        for alternative in text.split(',') {
+            let mut parts = Vec::new();
+            let mut combinator = Combinator::Descendant;
+            let spaced = alternative.replace('>', " > ");
+            for word in spaced.split_whitespace() {
+                if word == ">" {
+                    if parts.is_empty() {
+                        return Err(format!(
+                            "Nothing before '>' in '{}'",
+                            text
+                        ));
+                    }
+                    combinator = Combinator::Child;
+                    continue;
+                }
+                parts.push((combinator, Compound::parse(word)?));
+                combinator = Combinator::Descendant;
+            }
+
+            if parts.is_empty() || combinator == Combinator::Child {
+                return Err(format!("Incomplete selector '{}'", text));
+            }
+            alternatives.push(parts);
+        }
+        Ok(Selector { alternatives })
+    }
+
+    /// The elements under `root` that match, in document order.
+    pub fn select<'e>(&self, root: &'e Element) -> Vec<&'e Element> {
+        let mut found = Vec::new();
+        let mut ancestors = Vec::new();
+        self.select_in(root, &mut ancestors, &mut found);
+        found
+    }
+
+    fn select_in<'e>(
+        &self,
+        parent: &'e Element,
+        ancestors: &mut Vec<&'e Element>,
+        found: &mut Vec<&'e Element>,
+    ) {
+        ancestors.push(parent);
+        for child in &parent.children {
+            if let Node::Element(element) = child {
+                let matches = self
+                    .alternatives
+                    .iter()
+                    .any(|parts| matches(parts, element, ancestors));
+                if matches {
+                    found.push(element);
+                }
+                self.select_in(element, ancestors, found);
+            }
+        }
+        ancestors.pop();
+    }
+}
+
+/// Whether `element`, under `ancestors` with its parent last, matches the
+/// last of `parts` and its ancestors the ones before it.
+fn matches(
+    parts: &[(Combinator, Compound)],
+    element: &Element,
+    ancestors: &[&Element],
+) -> bool {
+    let (combinator, compound) = &parts[parts.len() - 1];
+    if !compound.matches(element) {
+        return false;
+    }
+    let before = &parts[..parts.len() - 1];
+    if before.is_empty() {
+        return true;
+    }
+
+    // The document itself is not an element that selectors can match.
+    let candidates = ancestors.len().saturating_sub(1);
+    match combinator {
+        Combinator::Child => {
+            candidates > 0
+                && matches(
+                    before,
+                    ancestors[ancestors.len() - 1],
+                    &ancestors[..ancestors.len() - 1],
+                )
+        }
+        Combinator::Descendant => (1..=candidates).rev().any(|i| {
+            let i = i + ancestors.len() - candidates - 1;
+            matches(before, ancestors[i], &ancestors[..i])
+        }),
+    }
+}
+
+impl Compound {
+    fn parse(text: &str) -> Result<Compound, String> {
+        let invalid = || format!("Invalid selector '{}'", text);
+        let mut compound = Compound::default();
+        let mut rest = text;
+
+        let end = rest.find(&['.', '#', '['][..]).unwrap_or(rest.len());
+        match &rest[..end] {
+            "" | "*" => {}
+            name => compound.name = Some(name.to_ascii_lowercase()),
+        }
+        rest = &rest[end..];
+
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[1..];
+            if c == '[' {
+                let end = rest.find(']').ok_or_else(invalid)?;
+                let attr = &rest[..end];
+                rest = &rest[end + 1..];
+                let (name, value) = match attr.split_once('=') {
+                    Some((name, value)) => {
+                        let value = value.trim_matches(&['"', '\''][..]);
+                        (name, Some(value.to_string()))
+                    }
+                    None => (attr, None),
+                };
+                if name.is_empty() {
+                    return Err(invalid());
+                }
+                compound.attrs.push((name.to_ascii_lowercase(), value));
+                continue;
+            }
+
+            let end = rest.find(&['.', '#', '['][..]).unwrap_or(rest.len());
+            let name = rest[..end].to_string();
+            rest = &rest[end..];
+            if name.is_empty() {
+                return Err(invalid());
+            }
+            match c {
+                '.' => compound.classes.push(name),
+                '#' => compound.ids.push(name),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(compound)
+    }
+
+    fn matches(&self, element: &Element) -> bool {
+        if let Some(name) = &self.name {
+            if !element.name.eq_ignore_ascii_case(name) {
+                return false;
+            }
+        }
+        let id = element.attr("id");
+        if self.ids.iter().any(|wanted| id != Some(wanted.as_str())) {
+            return false;
+        }
+        let classes = element.attr("class").unwrap_or("");
+        let has_class = |wanted: &String| {
+            classes.split_whitespace().any(|class| class == wanted)
+        };
+        if !self.classes.iter().all(has_class) {
+            return false;
+        }
+        self.attrs.iter().all(|(name, value)| {
+            match (element.attr(name), value) {
+                (Some(found), Some(value)) => found == value,
+                (found, None) => found.is_some(),
+                (None, Some(_)) => false,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(elements: &[&Element]) -> Vec<String> {
+        elements
+            .iter()
+            .map(|element| match element.attr("id") {
+                Some(id) => format!("{}#{}", element.name, id),
+                None => element.name.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn xml() {
+        let doc = parse_xml(
+            "<?xml version=\"1.0\"?>\n<!-- settings -->\n\
+             <config env='prod'>\n  <port>80</port>\n  <name>a &amp; b&#33;\
+             </name>\n  <empty/><![CDATA[<raw>]]></config>",
+        )
+        .unwrap();
+        assert_eq!(doc.children.len(), 1);
+        let config = match &doc.children[0] {
+            Node::Element(config) => config,
+            other => panic!("Expected an element, found {:?}", other),
+        };
+        assert_eq!(config.attr("env"), Some("prod"));
+        assert_eq!(config.children.len(), 4);
+        assert_eq!(config.text(), "80a & b!<raw>");
+
+        assert!(parse_xml("<a><b></a>").is_err());
+        assert!(parse_xml("<a>").is_err());
+        assert!(parse_xml("</a>").is_err());
+        assert!(parse_xml("<a b=c/>").is_err());
+        assert!(parse_xml("<a>&bogus;</a>").is_err());
+    }
+
+    #[test]
+    fn html() {
+        let doc = parse_html(
+            "<!DOCTYPE html><HTML><body class=main>\
+             <ul><li>one<li>two &copy;</ul><img src=x.png><br>\
+             <p>a<p>b</div><script>if (a < b) {}</script>",
+        );
+        let html = match &doc.children[0] {
+            Node::Element(html) => html,
+            other => panic!("Expected an element, found {:?}", other),
+        };
+        assert_eq!(html.name, "html");
+        let selected = Selector::parse("li").unwrap().select(&doc);
+        assert_eq!(selected.len(), 2);
+        assert_eq!(selected[1].text(), "two &copy;");
+        assert_eq!(Selector::parse("p").unwrap().select(&doc).len(), 2);
+        assert_eq!(
+            Selector::parse("script").unwrap().select(&doc)[0].text(),
+            "if (a < b) {}"
+        );
+        assert!(Selector::parse("img").unwrap().select(&doc)[0]
+            .children
+            .is_empty());
+    }
+
+    #[test]
+    fn selectors() {
+        let doc = parse_html(
+            "<div id=top><a class='link x' href=/a>1</a>\
+             <p><a class=link id=deep>2</a></p></div><a id=out>3</a>",
+        );
+        let select = |selector: &str| {
+            names(&Selector::parse(selector).unwrap().select(&doc))
+        };
+
+        assert_eq!(select("a.link"), ["a", "a#deep"]);
+        assert_eq!(select("div > a"), ["a"]);
+        assert_eq!(select("div a"), ["a", "a#deep"]);
+        assert_eq!(select("#top p a"), ["a#deep"]);
+        assert_eq!(select("a[href]"), ["a"]);
+        assert_eq!(select("a[href='/a'].x"), ["a"]);
+        assert_eq!(select("p, #out"), ["p", "a#out"]);
+        assert_eq!(select("* > p > a"), ["a#deep"]);
+        assert!(select("div > p > a.x").is_empty());
+
+        assert!(Selector::parse("").is_err());
+        assert!(Selector::parse("a >").is_err());
+        assert!(Selector::parse("> a").is_err());
+        assert!(Selector::parse("a..b").is_err());
+        assert!(Selector::parse("a:hover").is_ok());
+    }
+}
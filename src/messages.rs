@@ -0,0 +1,1354 @@
+use crate::ast::BinaryOpKind;
+use crate::primitives::ValueKind;
+use crate::token::TokenKind;
+
+/// The language diagnostics are shown in.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Language {
+    #[default]
+    English,
+    Swedish,
+}
+
+impl Language {
+    /// The codes accepted by `from_code`, as given to `--lang`.
+    pub const CODES: &'static [&'static str] = &["en", "sv"];
+
+    pub fn from_code(code: &str) -> Option<Language> {
+        match code {
+            "en" => Some(Language::English),
+            "sv" => Some(Language::Swedish),
+            _ => None,
+        }
+    }
+
+    /// The code `from_code` takes for the language.
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Swedish => "sv",
+        }
+    }
+
+    /// The label in front of an error.
+    pub fn error(self) -> &'static str {
+        match self {
+            Language::English => "Error",
+            Language::Swedish => "Fel",
+        }
+    }
+
+    /// The label in front of a warning.
+    pub fn warning(self) -> &'static str {
+        match self {
+            Language::English => "Warning",
+            Language::Swedish => "Varning",
+        }
+    }
+}
+
+/// Every message shown to the user, so that each can be translated. Types,
+/// tokens and operators are shown as they are named in the source of blixt.
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    // Lexing
+    InvalidUtf8,
+    TooManyTokens(usize),
+    UnterminatedString,
+    UnterminatedComment,
+    StringTooLong(usize),
+    UnknownEscape,
+    InvalidUnicodeEscape,
+    IntegerTooLarge(String),
+    InvalidRange(String),
+    UnknownOperator(String),
+    UnknownToken(char),
+
+    // Parsing
+    ExpectedToken {
+        expected: TokenKind,
+        found: TokenKind,
+    },
+    UnexpectedEnd(TokenKind),
+    NestedTooDeeply(usize),
+    ExpectedRangeOrExpression,
+    ExpectedWhileCondition,
+    ExpectedDeclaration(Option<TokenKind>),
+    ExpectedExpression,
+    ExpectedType(Option<TokenKind>),
+    MissingAssignedValue,
+    ExpectedValue,
+    ExpectedIndex,
+    ExpectedIdentifier(Option<TokenKind>),
+    ExpectedStatement(TokenKind),
+    OutsideLoop(&'static str),
+    ArmAfterWildcard,
+    UnknownAttribute(String),
+    UnknownCfgKey(String),
+    ExpectedCfgPredicate,
+    AttributeWithoutDeclaration,
+
+    // Metadata
+    InvalidMetadata,
+    UnknownCapability(String),
+    NoMetadata(String),
+
+    // Modules
+    ExpectedImportPath,
+    ImportNotTopLevel,
+    ModuleNotFound(String),
+    ImportCycle(Vec<String>),
+    DuplicateModule(String),
+
+    // Templates
+    UnclosedTag(&'static str),
+    UnclosedBlock(&'static str),
+    UnexpectedTag(&'static str),
+
+    // Semantic analysis
+    DuplicateFunction(String),
+    UpdateUndefined {
+        name: String,
+        op: &'static str,
+    },
+    UsedBeforeDeclared {
+        function: String,
+        variable: String,
+    },
+
+    // Typechecking
+    StructUnsupported,
+    FieldAccess(ValueKind),
+    RangeOutsideFor,
+    NonBoolCondition(ValueKind),
+    CannotIterate(ValueKind),
+    PrintFormat(ValueKind),
+    UndefinedVariable {
+        name: String,
+        /// A declared name that is close enough to be what was meant.
+        suggestion: Option<String>,
+    },
+    AssignConstant(String),
+    AssignMismatch {
+        name: String,
+        expected: ValueKind,
+        found: ValueKind,
+    },
+    ElementAssignMismatch {
+        name: String,
+        expected: ValueKind,
+        found: ValueKind,
+    },
+    ReturnOutsideFunction,
+    ReturnMismatch {
+        expected: ValueKind,
+        found: ValueKind,
+    },
+    NoReturnValue(String),
+    MissingReturnValue(ValueKind),
+    Shadowing(String),
+    UnusedResult,
+    AlwaysSame {
+        lhs: ValueKind,
+        rhs: ValueKind,
+        result: bool,
+    },
+    CannotOrder(ValueKind, ValueKind),
+    DivisionByZero,
+    ImplicitConversion(BinaryOpKind),
+    ImplicitConversionIn(String),
+    InvalidOperands(ValueKind, ValueKind, BinaryOpKind),
+    CannotNegate(ValueKind),
+    InputMessage(ValueKind),
+    ResultArity {
+        name: String,
+        found: usize,
+    },
+    UndefinedFunction {
+        name: String,
+        suggestion: Option<String>,
+    },
+    Arity {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    ArgumentMismatch {
+        position: usize,
+        name: String,
+        expected: ValueKind,
+        found: ValueKind,
+    },
+    TooFewArguments {
+        name: String,
+        at_least: usize,
+        found: usize,
+    },
+    NotAFunction {
+        position: usize,
+        name: String,
+        found: ValueKind,
+    },
+    /// More arguments given to `partial` than its function takes.
+    TooManyBound {
+        takes: usize,
+        found: usize,
+    },
+    CannotTakeLength(ValueKind),
+    /// Arguments of types the builtin can't take, which the typechecker
+    /// lets through when it doesn't know them, as with `?`.
+    InvalidArguments {
+        name: String,
+        found: Vec<ValueKind>,
+    },
+    NotAVariable(String),
+    NotAnArray {
+        name: String,
+        found: ValueKind,
+    },
+    PushMismatch {
+        value: ValueKind,
+        element: ValueKind,
+    },
+    NotNumbers {
+        name: String,
+        found: ValueKind,
+    },
+    NotAMap {
+        name: String,
+        found: ValueKind,
+    },
+    KeyMismatch {
+        found: ValueKind,
+        key: ValueKind,
+    },
+    InvalidMapKey(ValueKind),
+    MixedArrayElements(ValueKind, ValueKind),
+    MixedMapKeys(ValueKind, ValueKind),
+    MixedMapValues(ValueKind, ValueKind),
+    CannotIndex(ValueKind),
+    AssignStringIndex,
+    IndexMismatch {
+        expected: ValueKind,
+        found: ValueKind,
+    },
+    TryOutsideFunction,
+    TryOnNonResult(ValueKind),
+
+    // Running
+    Interrupted,
+    TooManyCalls,
+    NotEnoughFormatArgs,
+    TooManyFormatArgs,
+    IndexOutOfBounds {
+        index: i32,
+        len: usize,
+    },
+    IndexNotInt(ValueKind),
+    MissingKey(String),
+    ExpectedArray(ValueKind),
+    ExpectedDeque(ValueKind),
+    CannotUnpack {
+        count: usize,
+        found: ValueKind,
+    },
+    TupleIndex {
+        len: usize,
+    },
+    PopEmpty,
+    NoItems(String),
+    NegativeLength(i32),
+    ArrayTooLong(usize),
+    OutOfRange {
+        name: String,
+        found: String,
+        min: i64,
+        max: Option<i64>,
+    },
+    CapabilityRequired {
+        name: String,
+        capability: String,
+    },
+    CapabilityPrompt {
+        capability: String,
+        target: Option<String>,
+    },
+    PermissionDenied(String),
+    /// A capability in `//! requires:` that was not granted.
+    RequirementNotGranted(String),
+    RequirementDenied(String),
+    CalledAt {
+        name: String,
+        file: String,
+        line: u32,
+        column: u32,
+    },
+    MoreCalls(usize),
+
+    // Refactoring
+    PositionOutsideFile {
+        line: u32,
+        column: u32,
+    },
+    InvalidIdentifier(String),
+    RenameConflict(String),
+    NotRenamable(String),
+    ExpectedIdentifierAt(String),
+
+    // Snapshots
+    CannotSave(String),
+    InvalidSnapshot,
+
+    // REPL
+    SavedCheckpoint(usize),
+    RolledBack,
+    NoCheckpoint,
+    UnknownCommand(String),
+
+    // Files
+    /// A file named on the command line, and why it couldn't be read.
+    CannotReadFile {
+        path: String,
+        error: String,
+    },
+    CannotWriteFile {
+        path: String,
+        error: String,
+    },
+
+    // Crashes
+    /// Where the crash report was saved.
+    Crashed(String),
+    CrashReportUnsaved,
+}
+
+impl Message {
+    pub fn text(&self, language: Language) -> String {
+        match language {
+            Language::English => self.english(),
+            Language::Swedish => self.swedish(),
+        }
+    }
+
+    fn english(&self) -> String {
+        use Message::*;
+
+        match self {
+            InvalidUtf8 => "Invalid UTF-8".to_string(),
+            TooManyTokens(limit) => {
+                format!("Too many tokens, the limit is {}", limit)
+            }
+            UnterminatedString => "Unterminated string literal".to_string(),
+            UnterminatedComment => "Unterminated block comment".to_string(),
+            StringTooLong(limit) => format!(
+                "String literal is too long, the limit is {} bytes",
+                limit
+            ),
+            UnknownEscape => "Unknown escape sequence".to_string(),
+            InvalidUnicodeEscape => {
+                "Invalid unicode escape, expected \\u{XXXX}".to_string()
+            }
+            IntegerTooLarge(literal) => {
+                format!("Integer literal {} does not fit in an int", literal)
+            }
+            InvalidRange(literal) => {
+                format!("Invalid syntax. Expected range, found '{}'", literal)
+            }
+            UnknownOperator(operator) => {
+                format!("Could not lex unknown operator '{}'", operator)
+            }
+            UnknownToken(token) => {
+                format!("Could not lex unknown token '{}'", token)
+            }
+
+            ExpectedToken { expected, found } => {
+                format!("Expected {:?}, found {:?}", expected, found)
+            }
+            UnexpectedEnd(expected) => {
+                format!("Expected {:?}, but no tokens were left", expected)
+            }
+            NestedTooDeeply(limit) => {
+                format!("Nested too deeply, the limit is {} levels", limit)
+            }
+            ExpectedRangeOrExpression => {
+                "Expected range or expression".to_string()
+            }
+            ExpectedWhileCondition => {
+                "Expected expression after while".to_string()
+            }
+            OutsideLoop(keyword) => {
+                format!("Cannot use '{}' outside of a loop", keyword)
+            }
+            ArmAfterWildcard => {
+                "The '_' arm matches everything, so it has to come last"
+                    .to_string()
+            }
+            UnknownAttribute(name) => format!("Unknown attribute '@{}'", name),
+            UnknownCfgKey(key) => format!("Unknown cfg key '{}'", key),
+            ExpectedCfgPredicate => "Expected a condition like \
+                                     'os = \"linux\"', or all, any or not"
+                .to_string(),
+            AttributeWithoutDeclaration => {
+                "Attributes can only be put on declarations".to_string()
+            }
+            ExpectedDeclaration(Some(found)) => format!(
+                "Expected type or declaration operator, found {:?}",
+                found
+            ),
+            ExpectedDeclaration(None) => {
+                "Expected type or declaration operator".to_string()
+            }
+            ExpectedExpression => "Expected expression".to_string(),
+            ExpectedType(Some(found)) => {
+                format!("Expected type, found {:?}", found)
+            }
+            ExpectedType(None) => "Expected type".to_string(),
+            MissingAssignedValue => "Missing expr after assignment".to_string(),
+            ExpectedValue => "Expected value".to_string(),
+            ExpectedIndex => "Expected index".to_string(),
+            ExpectedIdentifier(Some(found)) => {
+                format!("Expected identifier, found {:?}", found)
+            }
+            ExpectedIdentifier(None) => {
+                "Expected identifier, but no tokens were left".to_string()
+            }
+            ExpectedStatement(found) => {
+                format!("Expected statement, found {:?}", found)
+            }
+
+            InvalidMetadata => {
+                "Expected 'key: value' in the metadata".to_string()
+            }
+            UnknownCapability(capability) => format!(
+                "The script requires '{}', which blixt does not provide",
+                capability
+            ),
+            NoMetadata(file) => format!("{} has no metadata", file),
+
+            ExpectedImportPath => {
+                "Expected the path of the module as a string".to_string()
+            }
+            ImportNotTopLevel => {
+                "Modules can only be imported at the top level".to_string()
+            }
+            ModuleNotFound(path) => format!("Cannot open module '{}'", path),
+            ImportCycle(cycle) => {
+                format!("Modules import each other: {}", cycle.join(" -> "))
+            }
+            DuplicateModule(name) => {
+                format!("Two different modules are named '{}'", name)
+            }
+
+            UnclosedTag(close) => {
+                format!("Expected '{}' to close the tag", close)
+            }
+            UnclosedBlock(block) => {
+                format!("'{}' is never closed with {{% end %}}", block)
+            }
+            UnexpectedTag(tag) => {
+                format!("'{}' without a block to continue or close", tag)
+            }
+
+            DuplicateFunction(name) => {
+                format!("Function '{}' is defined more than once", name)
+            }
+            UpdateUndefined { name, op } => format!(
+                "Cannot use '{}' on '{}', it is never declared",
+                op, name
+            ),
+            UsedBeforeDeclared { function, variable } => format!(
+                "'{}' uses the variable '{}', which is not declared when it \
+                 is called here",
+                function, variable
+            ),
+
+            StructUnsupported => "Structs are not supported yet".to_string(),
+            RangeOutsideFor => {
+                "A range can only be looped over with for".to_string()
+            }
+            FieldAccess(found) => format!(
+                "A {:?} has no fields, only methods called as value.name()",
+                found
+            ),
+            NonBoolCondition(found) => {
+                format!("Condition has to be a bool, found {:?}", found)
+            }
+            CannotIterate(found) => {
+                format!("Cannot iterate over a {:?}", found)
+            }
+            PrintFormat(found) => format!(
+                "The first argument to print has to be a format string, \
+                 found {:?}",
+                found
+            ),
+            UndefinedVariable { name, suggestion } => format!(
+                "Variable '{}' is undefined{}",
+                name,
+                did_you_mean(suggestion, "Did you mean")
+            ),
+            AssignConstant(name) => {
+                format!("Cannot assign to '{}', it is a constant", name)
+            }
+            AssignMismatch {
+                name,
+                expected,
+                found,
+            } => format!(
+                "Cannot assign a value of type {:?} to variable '{}' of \
+                 type {:?}",
+                found, name, expected
+            ),
+            ElementAssignMismatch {
+                name,
+                expected,
+                found,
+            } => format!(
+                "Cannot assign a value of type {:?} to an element of '{}' of \
+                 type {:?}",
+                found, name, expected
+            ),
+            ReturnOutsideFunction => {
+                "Cannot return outside of a function".to_string()
+            }
+            ReturnMismatch { expected, found } => format!(
+                "Expected a return value of type {:?}, found {:?}",
+                expected, found
+            ),
+            NoReturnValue(name) => {
+                format!("Function '{}' does not return a value", name)
+            }
+            MissingReturnValue(expected) => {
+                format!("Expected a return value of type {:?}", expected)
+            }
+            Shadowing(name) => {
+                format!("Variable '{}' shadows an earlier declaration", name)
+            }
+            UnusedResult => {
+                "The result of this expression is unused".to_string()
+            }
+            AlwaysSame { lhs, rhs, result } => format!(
+                "Comparing {:?} with {:?} is always {}",
+                lhs, rhs, result
+            ),
+            CannotOrder(lhs, rhs) => {
+                format!("Cannot order values of type {:?} and {:?}", lhs, rhs)
+            }
+            DivisionByZero => "Division by zero".to_string(),
+            ImplicitConversion(op) => format!(
+                "Implicit conversion between int and float for operator {:?}",
+                op
+            ),
+            ImplicitConversionIn(name) => format!(
+                "Implicit conversion between int and float for {}",
+                name
+            ),
+            InvalidOperands(lhs, rhs, op) => format!(
+                "Invalid types {:?}, {:?} for operator {:?}",
+                lhs, rhs, op
+            ),
+            CannotNegate(found) => format!("Cannot negate a {:?}", found),
+            InputMessage(found) => format!(
+                "The input message has to be a string, found {:?}",
+                found
+            ),
+            ResultArity { name, found } => {
+                format!("{} takes exactly one argument, found {}", name, found)
+            }
+            UndefinedFunction { name, suggestion } => format!(
+                "Function '{}' is undefined{}",
+                name,
+                did_you_mean(suggestion, "Did you mean")
+            ),
+            Arity {
+                name,
+                expected,
+                found,
+            } => format!(
+                "Function '{}' takes {} arguments, found {}",
+                name, expected, found
+            ),
+            ArgumentMismatch {
+                position,
+                name,
+                expected,
+                found,
+            } => format!(
+                "Argument {} to '{}' has to be of type {:?}, found {:?}",
+                position, name, expected, found
+            ),
+            TooFewArguments {
+                name,
+                at_least,
+                found,
+            } => format!(
+                "Function '{}' takes at least {} arguments, found {}",
+                name, at_least, found
+            ),
+            NotAFunction {
+                position,
+                name,
+                found,
+            } => format!(
+                "Argument {} to '{}' has to be a function, found {:?}",
+                position, name, found
+            ),
+            TooManyBound { takes, found } => format!(
+                "The function given to partial takes {} arguments, found {}",
+                takes, found
+            ),
+            CannotTakeLength(found) => {
+                format!("Cannot take the length of a {:?}", found)
+            }
+            InvalidArguments { name, found } => {
+                format!("'{}' can't take arguments of types {:?}", name, found)
+            }
+            NotAVariable(name) => {
+                format!("The first argument to {} has to be a variable", name)
+            }
+            NotAnArray { name, found } => format!(
+                "The first argument to {} has to be an array, found {:?}",
+                name, found
+            ),
+            PushMismatch { value, element } => format!(
+                "Cannot push a value of type {:?} to an array of {:?}",
+                value, element
+            ),
+            NotNumbers { name, found } => format!(
+                "The arguments to {} have to be numbers, found {:?}",
+                name, found
+            ),
+            NotAMap { name, found } => format!(
+                "The first argument to {} has to be a map, found {:?}",
+                name, found
+            ),
+            KeyMismatch { found, key } => format!(
+                "Cannot look up a {:?} in a map with {:?} keys",
+                found, key
+            ),
+            InvalidMapKey(found) => format!(
+                "Map keys have to be bool, int or string, found {:?}",
+                found
+            ),
+            MixedArrayElements(expected, found) => format!(
+                "Array elements have to be of the same type, expected {:?}, \
+                 found {:?}",
+                expected, found
+            ),
+            MixedMapKeys(expected, found) => format!(
+                "Map keys have to be of the same type, expected {:?}, found \
+                 {:?}",
+                expected, found
+            ),
+            MixedMapValues(expected, found) => format!(
+                "Map values have to be of the same type, expected {:?}, \
+                 found {:?}",
+                expected, found
+            ),
+            CannotIndex(found) => format!("Cannot index a {:?}", found),
+            AssignStringIndex => {
+                "Characters of a string cannot be assigned to".to_string()
+            }
+            IndexMismatch { expected, found } => format!(
+                "Index has to be of type {:?}, found {:?}",
+                expected, found
+            ),
+            TryOutsideFunction => {
+                "The ? operator can only be used inside a function".to_string()
+            }
+            TryOnNonResult(found) => format!(
+                "The ? operator can only be applied to results, found {:?}",
+                found
+            ),
+
+            Interrupted => "Interrupted".to_string(),
+            TooManyCalls => "Too many nested function calls".to_string(),
+            NotEnoughFormatArgs => {
+                "Not enough arguments for format string".to_string()
+            }
+            TooManyFormatArgs => {
+                "Too many arguments for format string".to_string()
+            }
+            IndexOutOfBounds { index, len } => format!(
+                "Index {} is out of bounds for a length of {}",
+                index, len
+            ),
+            IndexNotInt(found) => {
+                format!("Index has to be an int, found {:?}", found)
+            }
+            MissingKey(key) => format!("Key '{}' is not in the map", key),
+            ExpectedArray(found) => {
+                format!("Expected an array, found {:?}", found)
+            }
+            ExpectedDeque(found) => {
+                format!("Expected a deque, found {:?}", found)
+            }
+            CannotUnpack { count, found } => format!(
+                "Cannot unpack a value of type {:?} into {} variables",
+                found, count
+            ),
+            TupleIndex { len } => format!(
+                "A tuple can only be indexed by a number from 0 to {}",
+                len - 1
+            ),
+            PopEmpty => "Cannot pop from an empty collection".to_string(),
+            NoItems(name) => format!("'{}' needs at least one item", name),
+            NegativeLength(n) => {
+                format!("Length cannot be negative, found {}", n)
+            }
+            ArrayTooLong(limit) => {
+                format!("Array is too long, the limit is {} items", limit)
+            }
+            OutOfRange {
+                name,
+                found,
+                min,
+                max: Some(max),
+            } => format!(
+                "'{}' needs a value from {} to {}, found {}",
+                name, min, max, found
+            ),
+            OutOfRange {
+                name,
+                found,
+                min,
+                max: None,
+            } => format!(
+                "'{}' needs a value of at least {}, found {}",
+                name, min, found
+            ),
+            CapabilityRequired { name, capability } => format!(
+                "'{}' needs the capability '{}', grant it with --allow {} \
+                 or run with --prompt",
+                name, capability, capability
+            ),
+            CapabilityPrompt {
+                capability,
+                target: Some(target),
+            } => format!(
+                "Script wants '{}' access to '{}', allow? y/N",
+                capability, target
+            ),
+            CapabilityPrompt {
+                capability,
+                target: None,
+            } => format!("Script wants '{}' access, allow? y/N", capability),
+            PermissionDenied(name) => {
+                format!("Permission to call '{}' was denied", name)
+            }
+            RequirementNotGranted(capability) => format!(
+                "The script requires '{}', grant it with --allow {} or run \
+                 with --prompt",
+                capability, capability
+            ),
+            RequirementDenied(capability) => {
+                format!(
+                    "The script requires '{}', which was denied",
+                    capability
+                )
+            }
+            CalledAt {
+                name,
+                file,
+                line,
+                column,
+            } => {
+                format!("in '{}' called at {}:{}:{}", name, file, line, column)
+            }
+            MoreCalls(count) => format!("... {} more", count),
+
+            PositionOutsideFile { line, column } => {
+                format!("Position {}:{} is outside of the file", line, column)
+            }
+            InvalidIdentifier(name) => {
+                format!("'{}' is not a valid identifier", name)
+            }
+            RenameConflict(name) => format!(
+                "Renaming to '{}' would change what this name refers to",
+                name
+            ),
+            NotRenamable(name) => format!(
+                "'{}' is not declared in this file and can't be renamed",
+                name
+            ),
+            ExpectedIdentifierAt(name) => {
+                format!("Expected identifier '{}' at this position", name)
+            }
+
+            CannotSave(name) => {
+                format!("Cannot save '{}', it holds an iterator", name)
+            }
+            InvalidSnapshot => {
+                "The snapshot is damaged or from another version".to_string()
+            }
+
+            SavedCheckpoint(n) => format!("Saved checkpoint {}", n),
+            RolledBack => "Rolled back to the last checkpoint".to_string(),
+            NoCheckpoint => {
+                "There is no checkpoint to roll back to".to_string()
+            }
+            UnknownCommand(command) => format!(
+                "Unknown command '{}', expected :checkpoint or :rollback",
+                command
+            ),
+
+            CannotReadFile { path, error } => {
+                format!("Cannot read {}: {}", path, error)
+            }
+            CannotWriteFile { path, error } => {
+                format!("Cannot write {}: {}", path, error)
+            }
+
+            Crashed(path) => format!(
+                "blixt crashed, which is a bug in blixt. A report with a \
+                 minimized reproduction was saved to {}, please include it \
+                 when reporting the bug",
+                path
+            ),
+            CrashReportUnsaved => "blixt crashed, which is a bug in blixt. \
+                The report could not be saved, please include it when \
+                reporting the bug:"
+                .to_string(),
+        }
+    }
+
+    fn swedish(&self) -> String {
+        use Message::*;
+
+        match self {
+            InvalidUtf8 => "Ogiltig UTF-8".to_string(),
+            TooManyTokens(limit) => {
+                format!("För många symboler, gränsen är {}", limit)
+            }
+            UnterminatedString => "Strängen tar aldrig slut".to_string(),
+            UnterminatedComment => "Kommentaren tar aldrig slut".to_string(),
+            StringTooLong(limit) => {
+                format!("Strängen är för lång, gränsen är {} byte", limit)
+            }
+            UnknownEscape => "Okänd escape-sekvens".to_string(),
+            InvalidUnicodeEscape => {
+                "Ogiltig unicode-escape, förväntade \\u{XXXX}".to_string()
+            }
+            IntegerTooLarge(literal) => {
+                format!("Heltalet {} får inte plats i en int", literal)
+            }
+            InvalidRange(literal) => format!(
+                "Ogiltig syntax. Förväntade ett intervall, hittade '{}'",
+                literal
+            ),
+            UnknownOperator(operator) => {
+                format!("Okänd operator '{}'", operator)
+            }
+            UnknownToken(token) => format!("Okänt tecken '{}'", token),
+
+            ExpectedToken { expected, found } => {
+                format!("Förväntade {:?}, hittade {:?}", expected, found)
+            }
+            UnexpectedEnd(expected) => {
+                format!("Förväntade {:?}, men koden tog slut", expected)
+            }
+            NestedTooDeeply(limit) => {
+                format!("För djupt nästlad, gränsen är {} nivåer", limit)
+            }
+            ExpectedRangeOrExpression => {
+                "Förväntade ett intervall eller ett uttryck".to_string()
+            }
+            ExpectedWhileCondition => {
+                "Förväntade ett uttryck efter while".to_string()
+            }
+            OutsideLoop(keyword) => {
+                format!("Kan inte använda '{}' utanför en loop", keyword)
+            }
+            ArmAfterWildcard => {
+                "Grenen '_' matchar allt, så den måste komma sist".to_string()
+            }
+            UnknownAttribute(name) => format!("Okänt attribut '@{}'", name),
+            UnknownCfgKey(key) => format!("Okänd cfg-nyckel '{}'", key),
+            ExpectedCfgPredicate => "Förväntade ett villkor som \
+                                     'os = \"linux\"', eller all, any eller not"
+                .to_string(),
+            AttributeWithoutDeclaration => {
+                "Attribut kan bara sättas på deklarationer".to_string()
+            }
+            ExpectedDeclaration(Some(found)) => format!(
+                "Förväntade en typ eller en deklarationsoperator, hittade {:?}",
+                found
+            ),
+            ExpectedDeclaration(None) => {
+                "Förväntade en typ eller en deklarationsoperator".to_string()
+            }
+            ExpectedExpression => "Förväntade ett uttryck".to_string(),
+            ExpectedType(Some(found)) => {
+                format!("Förväntade en typ, hittade {:?}", found)
+            }
+            ExpectedType(None) => "Förväntade en typ".to_string(),
+            MissingAssignedValue => {
+                "Uttryck saknas efter tilldelningen".to_string()
+            }
+            ExpectedValue => "Förväntade ett värde".to_string(),
+            ExpectedIndex => "Förväntade ett index".to_string(),
+            ExpectedIdentifier(Some(found)) => {
+                format!("Förväntade ett namn, hittade {:?}", found)
+            }
+            ExpectedIdentifier(None) => {
+                "Förväntade ett namn, men koden tog slut".to_string()
+            }
+            ExpectedStatement(found) => {
+                format!("Förväntade en sats, hittade {:?}", found)
+            }
+
+            InvalidMetadata => {
+                "Förväntade 'nyckel: värde' i metadatan".to_string()
+            }
+            UnknownCapability(capability) => format!(
+                "Skriptet kräver '{}', som blixt inte har stöd för",
+                capability
+            ),
+            NoMetadata(file) => format!("{} saknar metadata", file),
+
+            ExpectedImportPath => {
+                "Förväntade modulens sökväg som en sträng".to_string()
+            }
+            ImportNotTopLevel => {
+                "Moduler kan bara importeras på översta nivån".to_string()
+            }
+            ModuleNotFound(path) => {
+                format!("Kan inte öppna modulen '{}'", path)
+            }
+            ImportCycle(cycle) => {
+                format!("Moduler importerar varandra: {}", cycle.join(" -> "))
+            }
+            DuplicateModule(name) => {
+                format!("Två olika moduler heter '{}'", name)
+            }
+
+            UnclosedTag(close) => {
+                format!("Förväntade '{}' som avslutar taggen", close)
+            }
+            UnclosedBlock(block) => {
+                format!("'{}' avslutas aldrig med {{% end %}}", block)
+            }
+            UnexpectedTag(tag) => {
+                format!("'{}' utan ett block att fortsätta eller avsluta", tag)
+            }
+
+            DuplicateFunction(name) => {
+                format!("Funktionen '{}' är definierad mer än en gång", name)
+            }
+            UpdateUndefined { name, op } => format!(
+                "Kan inte använda '{}' på '{}', den deklareras aldrig",
+                op, name
+            ),
+            UsedBeforeDeclared { function, variable } => format!(
+                "'{}' använder variabeln '{}', som inte är deklarerad när \
+                 den anropas här",
+                function, variable
+            ),
+
+            StructUnsupported => "Structar stöds inte än".to_string(),
+            RangeOutsideFor => {
+                "Ett intervall kan bara gås igenom med for".to_string()
+            }
+            FieldAccess(found) => format!(
+                "En {:?} har inga fält, bara metoder som anropas som \
+                 värde.namn()",
+                found
+            ),
+            NonBoolCondition(found) => {
+                format!("Villkoret måste vara en bool, hittade {:?}", found)
+            }
+            CannotIterate(found) => {
+                format!("Kan inte loopa över en {:?}", found)
+            }
+            PrintFormat(found) => format!(
+                "Första argumentet till print måste vara en formatsträng, \
+                 hittade {:?}",
+                found
+            ),
+            UndefinedVariable { name, suggestion } => format!(
+                "Variabeln '{}' är inte deklarerad{}",
+                name,
+                did_you_mean(suggestion, "Menade du")
+            ),
+            AssignConstant(name) => {
+                format!("Kan inte tilldela '{}', den är en konstant", name)
+            }
+            AssignMismatch {
+                name,
+                expected,
+                found,
+            } => format!(
+                "Kan inte tilldela ett värde av typen {:?} till variabeln \
+                 '{}' av typen {:?}",
+                found, name, expected
+            ),
+            ElementAssignMismatch {
+                name,
+                expected,
+                found,
+            } => format!(
+                "Kan inte tilldela ett värde av typen {:?} till ett element \
+                 i '{}' av typen {:?}",
+                found, name, expected
+            ),
+            ReturnOutsideFunction => {
+                "Kan inte returnera utanför en funktion".to_string()
+            }
+            ReturnMismatch { expected, found } => format!(
+                "Förväntade ett returvärde av typen {:?}, hittade {:?}",
+                expected, found
+            ),
+            NoReturnValue(name) => {
+                format!("Funktionen '{}' returnerar inget värde", name)
+            }
+            MissingReturnValue(expected) => {
+                format!("Förväntade ett returvärde av typen {:?}", expected)
+            }
+            Shadowing(name) => format!(
+                "Variabeln '{}' skuggar en tidigare deklaration",
+                name
+            ),
+            UnusedResult => "Resultatet av uttrycket används inte".to_string(),
+            AlwaysSame { lhs, rhs, result } => format!(
+                "Att jämföra {:?} med {:?} ger alltid {}",
+                lhs, rhs, result
+            ),
+            CannotOrder(lhs, rhs) => format!(
+                "Kan inte ordna värden av typerna {:?} och {:?}",
+                lhs, rhs
+            ),
+            DivisionByZero => "Division med noll".to_string(),
+            ImplicitConversion(op) => format!(
+                "Implicit konvertering mellan int och float för operatorn {:?}",
+                op
+            ),
+            ImplicitConversionIn(name) => format!(
+                "Implicit konvertering mellan int och float för {}",
+                name
+            ),
+            InvalidOperands(lhs, rhs, op) => format!(
+                "Ogiltiga typer {:?}, {:?} för operatorn {:?}",
+                lhs, rhs, op
+            ),
+            CannotNegate(found) => format!("Kan inte negera en {:?}", found),
+            InputMessage(found) => format!(
+                "Meddelandet till input måste vara en sträng, hittade {:?}",
+                found
+            ),
+            ResultArity { name, found } => format!(
+                "{} tar exakt ett argument, hittade {}",
+                name, found
+            ),
+            UndefinedFunction { name, suggestion } => format!(
+                "Funktionen '{}' är inte deklarerad{}",
+                name,
+                did_you_mean(suggestion, "Menade du")
+            ),
+            Arity {
+                name,
+                expected,
+                found,
+            } => format!(
+                "Funktionen '{}' tar {} argument, hittade {}",
+                name, expected, found
+            ),
+            ArgumentMismatch {
+                position,
+                name,
+                expected,
+                found,
+            } => format!(
+                "Argument {} till '{}' måste vara av typen {:?}, hittade {:?}",
+                position, name, expected, found
+            ),
+            TooFewArguments {
+                name,
+                at_least,
+                found,
+            } => format!(
+                "Funktionen '{}' tar minst {} argument, hittade {}",
+                name, at_least, found
+            ),
+            NotAFunction {
+                position,
+                name,
+                found,
+            } => format!(
+                "Argument {} till '{}' måste vara en funktion, hittade {:?}",
+                position, name, found
+            ),
+            TooManyBound { takes, found } => format!(
+                "Funktionen som ges till partial tar {} argument, hittade {}",
+                takes, found
+            ),
+            CannotTakeLength(found) => {
+                format!("Kan inte ta längden av en {:?}", found)
+            }
+            InvalidArguments { name, found } => format!(
+                "'{}' kan inte ta argument av typerna {:?}",
+                name, found
+            ),
+            NotAVariable(name) => format!(
+                "Första argumentet till {} måste vara en variabel",
+                name
+            ),
+            NotAnArray { name, found } => format!(
+                "Första argumentet till {} måste vara en array, hittade {:?}",
+                name, found
+            ),
+            PushMismatch { value, element } => format!(
+                "Kan inte lägga till ett värde av typen {:?} i en array av {:?}",
+                value, element
+            ),
+            NotNumbers { name, found } => format!(
+                "Argumenten till {} måste vara tal, hittade {:?}",
+                name, found
+            ),
+            NotAMap { name, found } => format!(
+                "Första argumentet till {} måste vara en map, hittade {:?}",
+                name, found
+            ),
+            KeyMismatch { found, key } => format!(
+                "Kan inte slå upp en {:?} i en map med nycklar av typen {:?}",
+                found, key
+            ),
+            InvalidMapKey(found) => format!(
+                "Nycklar i en map måste vara bool, int eller string, \
+                 hittade {:?}",
+                found
+            ),
+            MixedArrayElements(expected, found) => format!(
+                "Elementen i en array måste ha samma typ, förväntade {:?}, \
+                 hittade {:?}",
+                expected, found
+            ),
+            MixedMapKeys(expected, found) => format!(
+                "Nycklarna i en map måste ha samma typ, förväntade {:?}, \
+                 hittade {:?}",
+                expected, found
+            ),
+            MixedMapValues(expected, found) => format!(
+                "Värdena i en map måste ha samma typ, förväntade {:?}, \
+                 hittade {:?}",
+                expected, found
+            ),
+            CannotIndex(found) => format!("Kan inte indexera en {:?}", found),
+            AssignStringIndex => {
+                "Tecken i en sträng kan inte tilldelas".to_string()
+            }
+            IndexMismatch { expected, found } => format!(
+                "Index måste vara av typen {:?}, hittade {:?}",
+                expected, found
+            ),
+            TryOutsideFunction => {
+                "Operatorn ? kan bara användas i en funktion".to_string()
+            }
+            TryOnNonResult(found) => format!(
+                "Operatorn ? kan bara användas på resultat, hittade {:?}",
+                found
+            ),
+
+            Interrupted => "Avbruten".to_string(),
+            TooManyCalls => "För många nästlade funktionsanrop".to_string(),
+            NotEnoughFormatArgs => {
+                "För få argument till formatsträngen".to_string()
+            }
+            TooManyFormatArgs => {
+                "För många argument till formatsträngen".to_string()
+            }
+            IndexOutOfBounds { index, len } => format!(
+                "Index {} är utanför längden {}",
+                index, len
+            ),
+            IndexNotInt(found) => {
+                format!("Index måste vara en int, hittade {:?}", found)
+            }
+            MissingKey(key) => format!("Nyckeln '{}' finns inte i map:en", key),
+            ExpectedArray(found) => {
+                format!("Förväntade en array, hittade {:?}", found)
+            }
+            ExpectedDeque(found) => {
+                format!("Förväntade en deque, hittade {:?}", found)
+            }
+            CannotUnpack { count, found } => format!(
+                "Kan inte packa upp ett värde av typen {:?} i {} variabler",
+                found, count
+            ),
+            TupleIndex { len } => format!(
+                "En tupel kan bara indexeras med ett tal från 0 till {}",
+                len - 1
+            ),
+            PopEmpty => "Kan inte ta bort från en tom samling".to_string(),
+            NoItems(name) => format!("'{}' behöver minst ett element", name),
+            NegativeLength(n) => {
+                format!("Längden kan inte vara negativ, hittade {}", n)
+            }
+            ArrayTooLong(limit) => {
+                format!("Arrayen är för lång, gränsen är {} element", limit)
+            }
+            OutOfRange {
+                name,
+                found,
+                min,
+                max: Some(max),
+            } => format!(
+                "'{}' behöver ett värde från {} till {}, hittade {}",
+                name, min, max, found
+            ),
+            OutOfRange {
+                name,
+                found,
+                min,
+                max: None,
+            } => format!(
+                "'{}' behöver ett värde på minst {}, hittade {}",
+                name, min, found
+            ),
+            CapabilityRequired { name, capability } => format!(
+                "'{}' behöver förmågan '{}', bevilja den med --allow {} \
+                 eller kör med --prompt",
+                name, capability, capability
+            ),
+            CapabilityPrompt {
+                capability,
+                target: Some(target),
+            } => format!(
+                "Skriptet vill ha '{}'-åtkomst till '{}', tillåt? j/N",
+                capability, target
+            ),
+            CapabilityPrompt {
+                capability,
+                target: None,
+            } => format!(
+                "Skriptet vill ha '{}'-åtkomst, tillåt? j/N",
+                capability
+            ),
+            PermissionDenied(name) => {
+                format!("Tillstånd att anropa '{}' nekades", name)
+            }
+            RequirementNotGranted(capability) => format!(
+                "Skriptet kräver '{}', bevilja den med --allow {} eller kör \
+                 med --prompt",
+                capability, capability
+            ),
+            RequirementDenied(capability) => {
+                format!("Skriptet kräver '{}', som nekades", capability)
+            }
+            CalledAt {
+                name,
+                file,
+                line,
+                column,
+            } => format!("i '{}' anropad vid {}:{}:{}", name, file, line, column),
+            MoreCalls(count) => format!("... {} till", count),
+
+            PositionOutsideFile { line, column } => {
+                format!("Positionen {}:{} är utanför filen", line, column)
+            }
+            InvalidIdentifier(name) => format!("'{}' är inget giltigt namn", name),
+            RenameConflict(name) => format!(
+                "Namnet '{}' skulle ändra vad det här namnet syftar på",
+                name
+            ),
+            NotRenamable(name) => format!(
+                "'{}' är inte deklarerad i filen och kan inte byta namn",
+                name
+            ),
+            ExpectedIdentifierAt(name) => {
+                format!("Förväntade namnet '{}' på den här positionen", name)
+            }
+
+            CannotSave(name) => {
+                format!("Kan inte spara '{}', den innehåller en iterator", name)
+            }
+            InvalidSnapshot => {
+                "Ögonblicksbilden är skadad eller från en annan version"
+                    .to_string()
+            }
+
+            SavedCheckpoint(n) => format!("Sparade kontrollpunkt {}", n),
+            RolledBack => {
+                "Gick tillbaka till den senaste kontrollpunkten".to_string()
+            }
+            NoCheckpoint => {
+                "Det finns ingen kontrollpunkt att gå tillbaka till".to_string()
+            }
+            UnknownCommand(command) => format!(
+                "Okänt kommando '{}', förväntade :checkpoint eller :rollback",
+                command
+            ),
+
+            CannotReadFile { path, error } => {
+                format!("Kan inte läsa {}: {}", path, error)
+            }
+            CannotWriteFile { path, error } => {
+                format!("Kan inte skriva {}: {}", path, error)
+            }
+
+            Crashed(path) => format!(
+                "blixt kraschade, vilket är en bugg i blixt. En rapport med \
+                 ett minimerat exempel sparades i {}, bifoga den när du \
+                 rapporterar buggen",
+                path
+            ),
+            CrashReportUnsaved => "blixt kraschade, vilket är en bugg i \
+                blixt. Rapporten kunde inte sparas, bifoga den när du \
+                rapporterar buggen:"
+                .to_string(),
+        }
+    }
+}
+
+/// The end of a message about an undefined name, asking whether the
+/// suggestion was meant if there is one.
+fn did_you_mean(suggestion: &Option<String>, question: &str) -> String {
+    match suggestion {
+        Some(suggestion) => format!(". {} '{}'?", question, suggestion),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages() {
+        assert_eq!(Language::from_code("sv"), Some(Language::Swedish));
+        assert_eq!(Language::from_code("fr"), None);
+        for code in Language::CODES {
+            assert!(Language::from_code(code).is_some());
+        }
+
+        let message = Message::UndefinedVariable {
+            name: "a".to_string(),
+            suggestion: None,
+        };
+        assert_eq!(
+            message.text(Language::English),
+            "Variable 'a' is undefined"
+        );
+        assert_eq!(
+            message.text(Language::Swedish),
+            "Variabeln 'a' är inte deklarerad"
+        );
+
+        let message = Message::UndefinedFunction {
+            name: "prnt".to_string(),
+            suggestion: Some("print".to_string()),
+        };
+        assert_eq!(
+            message.text(Language::English),
+            "Function 'prnt' is undefined. Did you mean 'print'?"
+        );
+        assert_eq!(
+            message.text(Language::Swedish),
+            "Funktionen 'prnt' är inte deklarerad. Menade du 'print'?"
+        );
+
+        let message = Message::UnexpectedEnd(TokenKind::CloseParen);
+        assert_eq!(
+            message.text(Language::English),
+            "Expected CloseParen, but no tokens were left"
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt;
+use std::io::BufRead;
+use std::str;
+
+use crate::builtins;
+use crate::common::{Context, Symbol};
+use crate::location::{Location, Span};
+use crate::messages::Message;
+
+/// The capabilities a script can ask for with `//! requires:`.
+pub const CAPABILITIES: &[&str] = &["fs", "proc", "random", "net"];
+
+/// The block of `//!` lines at the top of a script, as in
+///
+/// ```text
+/// //! name: Hello
+/// //! requires: fs
+/// ```
+#[derive(Debug, Default)]
+pub struct Metadata {
+    pub name: Option<String>,
+    pub requires: Vec<Requirement>,
+    /// The other fields, in the order they are given.
+    pub fields: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub struct Requirement {
+    pub capability: String,
+    pub location: Location,
+}
+
+/// Reads the metadata block at the top of `source`. It ends at the first
+/// line that does not start with `//!`.
+#[allow(clippy::result_unit_err)]
+pub fn parse(
+    source: &[u8],
+    file: Symbol,
+    context: &mut Context,
+) -> Result<Metadata, ()> {
+    let mut metadata = Metadata::default();
+    let mut failed = false;
+    let mut start = 0;
+
+    for (i, line) in source.split(|&b| b == b'\n').enumerate() {
+        let location = Location {
+            file,
+            line: i as u32 + 1,
+            span: Span {
+                start: start as u32,
+                len: line.len() as u32,
+            },
+        };
+        start += line.len() + 1;
+
+        let line = match line.strip_prefix(b"//!") {
+            Some(line) => line,
+            None if metadata.is_empty() && line.trim_ascii().is_empty() => {
+                continue
+            }
+            None => break,
+        };
+
+        let line = match str::from_utf8(line) {
+            Ok(line) => line.trim(),
+            Err(_) => {
+                context.report_error(Message::InvalidUtf8, location);
+                failed = true;
+                continue;
+            }
+        };
+
+        match line.split_once(':') {
+            Some(("name", value)) => {
+                metadata.name = Some(value.trim().to_string());
+            }
+            Some(("requires", value)) => {
+                let capabilities = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|capability| !capability.is_empty());
+                for capability in capabilities {
+                    metadata.requires.push(Requirement {
+                        capability: capability.to_string(),
+                        location,
+                    });
+                }
+            }
+            Some((key, value)) if !key.trim().is_empty() => {
+                let field = (key.trim().to_string(), value.trim().to_string());
+                metadata.fields.push(field);
+            }
+            _ if line.is_empty() => {}
+            _ => {
+                context.report_error(Message::InvalidMetadata, location);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        Err(())
+    } else {
+        Ok(metadata)
+    }
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.requires.is_empty()
+            && self.fields.is_empty()
+    }
+
+    /// Fails with an error for every required capability that blixt does
+    /// not provide, so that a script is not run only to fail half way.
+    #[allow(clippy::result_unit_err)]
+    pub fn check_requirements(&self, context: &mut Context) -> Result<(), ()> {
+        let mut result = Ok(());
+
+        for requirement in &self.requires {
+            if !CAPABILITIES.contains(&requirement.capability.as_str()) {
+                context.report_error(
+                    Message::UnknownCapability(requirement.capability.clone()),
+                    requirement.location,
+                );
+                result = Err(());
+            }
+        }
+
+        result
+    }
+
+    /// Checks that the capabilities the script requires have been granted,
+    /// asking for the missing ones up front with `--prompt`. Requiring a
+    /// capability does not grant it.
+    #[allow(clippy::result_unit_err)]
+    pub fn check_grants(
+        &self,
+        context: &mut Context,
+        input: &mut dyn BufRead,
+    ) -> Result<(), ()> {
+        let mut result = Ok(());
+
+        for requirement in &self.requires {
+            let capability = &requirement.capability;
+            if context.grants.allows(capability) {
+                continue;
+            }
+            let message = if !context.prompt {
+                Message::RequirementNotGranted(capability.clone())
+            } else if builtins::ask_to_grant(capability, None, context, input) {
+                continue;
+            } else {
+                Message::RequirementDenied(capability.clone())
+            };
+            context.report_error(message, requirement.location);
+            result = Err(());
+        }
+
+        result
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = &self.name {
+            writeln!(f, "{:<12}{}", "name", name)?;
+        }
+
+        if !self.requires.is_empty() {
+            let capabilities: Vec<_> = self
+                .requires
+                .iter()
+                .map(|requirement| requirement.capability.as_str())
+                .collect();
+            writeln!(f, "{:<12}{}", "requires", capabilities.join(", "))?;
+        }
+
+        for (key, value) in &self.fields {
+            writeln!(f, "{:<12}{}", key, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Grants;
+
+    fn parse_str(source: &str) -> (Result<Metadata, ()>, Context) {
+        let mut context = Context::new();
+        context
+            .source_code
+            .insert("test.bl".into(), source.to_string());
+        let file = context.interner.intern("test.bl");
+        (parse(source.as_bytes(), file, &mut context), context)
+    }
+
+    #[test]
+    fn metadata_block() {
+        let source = "\n//! name: Hello\n//! requires: fs, gpu\n//!\n\
+                      //! author: Ann: B\n\
+                      a := 1\n\
+                      //! version: 2\n";
+        let (metadata, mut context) = parse_str(source);
+        let mut metadata = metadata.unwrap();
+
+        assert_eq!(metadata.name.as_deref(), Some("Hello"));
+        let lines: Vec<_> = metadata
+            .requires
+            .iter()
+            .map(|requirement| requirement.location.line)
+            .collect();
+        assert_eq!(lines, [3, 3]);
+        assert_eq!(
+            metadata.to_string(),
+            "name        Hello\n\
+             requires    fs, gpu\n\
+             author      Ann: B\n"
+        );
+
+        assert!(metadata.check_requirements(&mut context).is_err());
+        metadata.requires.truncate(1);
+        assert!(metadata.check_requirements(&mut context).is_ok());
+    }
+
+    #[test]
+    fn requiring_does_not_grant() {
+        let (metadata, mut context) = parse_str("//! requires: fs, net\n");
+        let metadata = metadata.unwrap();
+        context.grants = Grants::Only(vec!["net".to_string()]);
+
+        assert!(metadata.check_grants(&mut context, &mut &b""[..]).is_err());
+        assert!(!context.grants.allows("fs"));
+
+        context.prompt = true;
+        let mut input = &b"y\n"[..];
+        assert!(metadata.check_grants(&mut context, &mut input).is_ok());
+        assert!(context.grants.allows("fs"));
+    }
+
+    #[test]
+    fn no_metadata() {
+        assert!(parse_str("").0.unwrap().is_empty());
+        assert!(parse_str("a := 1 //! name: x").0.unwrap().is_empty());
+        assert!(parse_str("//! no value").0.is_err());
+    }
+}
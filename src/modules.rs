@@ -0,0 +1,426 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::common::{Context, Symbol};
+use crate::lexer;
+use crate::location::Location;
+use crate::messages::Message;
+use crate::token::{Token, TokenKind};
+
+/// Lexes a file along with the modules it imports, as one stream of tokens.
+///
+/// `import "util/math"` reads `util/math.bl`, relative to the importing
+/// file, and puts its tokens in place of the import. The top level
+/// functions and variables of a module are renamed to `math.name`, which
+/// is how the importing file refers to them. A module is only included the
+/// first time it is imported, and a module importing itself, directly or
+/// through others, is an error.
+#[allow(clippy::result_unit_err)]
+pub fn generate_tokens(
+    source: &[u8],
+    file: Symbol,
+    context: &mut Context,
+) -> Result<VecDeque<Token>, ()> {
+    let name = context.interner.get(file).to_string();
+    let path = PathBuf::from(&name);
+    let mut loader = Loader {
+        loading: vec![(fs::canonicalize(&path).unwrap_or(path), name)],
+        loaded: HashMap::default(),
+        namespaces: HashMap::default(),
+    };
+
+    loader.tokens(source, file, None, context)
+}
+
+struct Loader {
+    /// The files being loaded, with the names they were imported as. The
+    /// one being lexed is last.
+    loading: Vec<(PathBuf, String)>,
+    /// The namespace of every module that has been loaded.
+    loaded: HashMap<PathBuf, Symbol>,
+    namespaces: HashMap<Symbol, PathBuf>,
+}
+
+impl Loader {
+    /// Lexes `source` and replaces its imports with the modules. The top
+    /// level names are put in `namespace`, if it is a module.
+    fn tokens(
+        &mut self,
+        source: &[u8],
+        file: Symbol,
+        namespace: Option<Symbol>,
+        context: &mut Context,
+    ) -> Result<VecDeque<Token>, ()> {
+        let tokens = lexer::generate_tokens(source, file, context)?;
+        let mut result = VecDeque::with_capacity(tokens.len());
+        let mut imported = HashSet::new();
+        let mut depth = 0;
+        let mut failed = false;
+
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            match token.kind {
+                TokenKind::OpenBrace => depth += 1,
+                TokenKind::CloseBrace => depth -= 1,
+                TokenKind::Import => {
+                    let path = match tokens.next() {
+                        Some(Token {
+                            kind: TokenKind::String(path),
+                            ..
+                        }) => path,
+                        _ => {
+                            let message = Message::ExpectedImportPath;
+                            context.report_error(message, token.location);
+                            failed = true;
+                            continue;
+                        }
+                    };
+
+                    if depth > 0 {
+                        let message = Message::ImportNotTopLevel;
+                        context.report_error(message, token.location);
+                        failed = true;
+                        continue;
+                    }
+
+                    match self.import(path, file, token.location, context) {
+                        Ok((namespace, module)) => {
+                            imported.insert(namespace);
+                            result.extend(module);
+                        }
+                        Err(()) => failed = true,
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            result.push_back(token);
+        }
+
+        if failed {
+            return Err(());
+        }
+
+        qualify_names(&mut result, file, &imported, context);
+        if let Some(namespace) = namespace {
+            rename_top_level(&mut result, file, namespace, context);
+        }
+
+        Ok(result)
+    }
+
+    /// Loads the module at `path`, relative to `importer`. Returns its
+    /// namespace and tokens, which are empty if it was loaded before.
+    fn import(
+        &mut self,
+        path: Symbol,
+        importer: Symbol,
+        location: Location,
+        context: &mut Context,
+    ) -> Result<(Symbol, VecDeque<Token>), ()> {
+        let builtin = std_module(context.interner.get(path));
+        let mut path = match builtin {
+            Some((name, _)) => PathBuf::from(name),
+            None => Path::new(context.interner.get(importer))
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(context.interner.get(path)),
+        };
+        if path.extension().is_none() {
+            path.set_extension("bl");
+        }
+
+        let name = path.display().to_string();
+        let canonical = match builtin {
+            // Never the same as a canonical path, which is absolute.
+            Some(_) => path.clone(),
+            None => match fs::canonicalize(&path) {
+                Ok(canonical) => canonical,
+                Err(_) => {
+                    let message = Message::ModuleNotFound(name);
+                    context.report_error(message, location);
+                    return Err(());
+                }
+            },
+        };
+
+        if let Some(start) =
+            self.loading.iter().position(|(p, _)| *p == canonical)
+        {
+            let mut cycle: Vec<_> = self.loading[start..]
+                .iter()
+                .map(|(_, name)| name.clone())
+                .collect();
+            cycle.push(name);
+            context.report_error(Message::ImportCycle(cycle), location);
+            return Err(());
+        }
+
+        if let Some(namespace) = self.loaded.get(&canonical) {
+            return Ok((*namespace, VecDeque::new()));
+        }
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let namespace = context.interner.intern(&stem);
+        if self.namespaces.contains_key(&namespace) {
+            let message = Message::DuplicateModule(stem.to_string());
+            context.report_error(message, location);
+            return Err(());
+        }
+
+        let source = match builtin {
+            Some((_, source)) => source.as_bytes().to_vec(),
+            None => match fs::read(&path) {
+                Ok(source) => source,
+                Err(_) => {
+                    let message = Message::ModuleNotFound(name);
+                    context.report_error(message, location);
+                    return Err(());
+                }
+            },
+        };
+        let file = context.interner.intern(&name);
+
+        self.loading.push((canonical.clone(), name));
+        let tokens = self.tokens(&source, file, Some(namespace), context);
+        self.loading.pop();
+
+        self.loaded.insert(canonical.clone(), namespace);
+        self.namespaces.insert(namespace, canonical);
+        Ok((namespace, tokens?))
+    }
+}
+
+/// The modules that come with blixt, imported as `std/name` from any file
+/// instead of being read from disk.
+const STD_MODULES: &[(&str, &str)] =
+    &[("std/graph", include_str!("../std/graph.bl"))];
+
+/// The name and source of the module that comes with blixt that `path`
+/// imports, if it is one.
+fn std_module(path: &str) -> Option<(&'static str, &'static str)> {
+    let path = path.strip_suffix(".bl").unwrap_or(path);
+    STD_MODULES.iter().copied().find(|(name, _)| *name == path)
+}
+
+/// Joins `module.name` and `module::name` in the tokens of `file` into a single identifier,
+/// for every module in `imported`.
+fn qualify_names(
+    tokens: &mut VecDeque<Token>,
+    file: Symbol,
+    imported: &HashSet<Symbol>,
+    context: &mut Context,
+) {
+    let mut i = 0;
+    while i + 2 < tokens.len() {
+        let qualified =
+            match (&tokens[i].kind, &tokens[i + 1].kind, &tokens[i + 2].kind) {
+                (
+                    TokenKind::Ident(module),
+                    TokenKind::Field | TokenKind::PathSep,
+                    TokenKind::Ident(name),
+                ) if tokens[i].location.file == file
+                    && imported.contains(module) =>
+                {
+                    format!(
+                        "{}.{}",
+                        context.interner.get(*module),
+                        context.interner.get(*name)
+                    )
+                }
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+        let location = tokens[i].location + tokens[i + 2].location;
+        tokens.drain(i + 1..i + 3);
+        tokens[i] = Token {
+            kind: TokenKind::Ident(context.interner.intern(&qualified)),
+            location,
+            line_break: tokens[i].line_break,
+        };
+        i += 1;
+    }
+}
+
+/// Renames the functions and variables declared at the top level of a
+/// module to `namespace.name`, along with every use of them in the module.
+/// Locals with the same name are renamed too, which changes nothing since
+/// no identifier in the module can contain a dot.
+fn rename_top_level(
+    tokens: &mut VecDeque<Token>,
+    file: Symbol,
+    namespace: Symbol,
+    context: &mut Context,
+) {
+    let mut names = HashSet::new();
+    let mut depth = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::OpenBrace => depth += 1,
+            TokenKind::CloseBrace => depth -= 1,
+            TokenKind::Ident(name)
+                if depth == 0 && token.location.file == file =>
+            {
+                let previous = i.checked_sub(1).map(|i| &tokens[i].kind);
+                let next = tokens.get(i + 1).map(|token| &token.kind);
+                if let (Some(TokenKind::FunctionDecl), _)
+                | (_, Some(TokenKind::VarDecl))
+                | (_, Some(TokenKind::Colon)) = (previous, next)
+                {
+                    names.insert(name);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let namespace = context.interner.get(namespace).to_string();
+    for token in tokens.iter_mut() {
+        match token.kind {
+            TokenKind::Ident(name)
+                if token.location.file == file && names.contains(&name) =>
+            {
+                let qualified =
+                    format!("{}.{}", namespace, context.interner.get(name));
+                token.kind =
+                    TokenKind::Ident(context.interner.intern(&qualified));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    use crate::interpreter;
+    use crate::parser;
+    use crate::primitives::Value;
+    use crate::scope::Scope;
+    use crate::typecheck;
+
+    /// Writes `files` to a new directory and lexes the first one.
+    fn load(
+        dir: &str,
+        files: &[(&str, &str)],
+    ) -> (Result<VecDeque<Token>, ()>, Context) {
+        let dir = env::temp_dir().join(dir);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        for (name, source) in files {
+            fs::write(dir.join(name), source).unwrap();
+        }
+
+        let mut context = Context::new();
+        let path = dir.join(files[0].0).display().to_string();
+        let file = context.interner.intern(&path);
+        let result = generate_tokens(files[0].1.as_bytes(), file, &mut context);
+        (result, context)
+    }
+
+    #[test]
+    fn import_modules() {
+        let (tokens, mut context) = load(
+            "blixt-import-modules",
+            &[
+                (
+                    "main.bl",
+                    "import \"lib/a\" import \"lib/b\"\n\
+                             n := a.double(b.n) + a.n",
+                ),
+                (
+                    "lib/a.bl",
+                    "import \"b\" n := 1\n\
+                              fn double(n: int) -> int { return n * 2 + b.n }",
+                ),
+                ("lib/b.bl", "n := 10"),
+            ],
+        );
+        let tokens = tokens.unwrap();
+
+        let names: Vec<_> = tokens
+            .iter()
+            .filter_map(|token| match token.kind {
+                TokenKind::Ident(name) => Some(context.interner.get(name)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "b.n", "a.n", "a.double", "a.n", "a.n", "b.n", "n", "a.double",
+                "b.n", "a.n"
+            ]
+        );
+
+        let ast = parser::parse_ast(tokens, &mut context).unwrap();
+        assert!(typecheck::typecheck(&ast, &mut context).is_ok());
+    }
+
+    #[test]
+    fn std_modules() {
+        let source = "import \"std/graph\"\n\
+                      g := graph.add_edge({\"a\": [\"b\", \"c\"]}, \"b\", \"d\")\n\
+                      g = graph.add_edge(g, \"c\", \"d\")\n\
+                      a := \"a\" d := \"d\" sorted := graph.topological_sort(g)\n\
+                      g = graph.add_edge(g, d, a)\n\
+                      \"${graph.bfs(g, a)} ${graph.dfs(g, a)} \
+                       ${graph.shortest_path(g, a, d)} ${sorted} \
+                       ${graph.topological_sort(g)}\"";
+        let (tokens, mut context) =
+            load("blixt-import-std", &[("main.bl", source)]);
+        let ast = parser::parse_ast(tokens.unwrap(), &mut context).unwrap();
+        assert!(typecheck::typecheck(&ast, &mut context).is_ok());
+
+        let mut scope = Scope::new();
+        let value = interpreter::evaluate(
+            &ast,
+            &ast.statements,
+            &mut scope,
+            &mut context,
+        );
+        let value = match value {
+            Ok(Some(Value::String(s))) => context.interner.get(s).to_string(),
+            _ => panic!("Expected a string"),
+        };
+        assert_eq!(
+            value,
+            "[a, b, c, d] [a, b, d, c] Ok([a, b, d]) Ok([a, b, c, d]) \
+             Err(The graph has a cycle)"
+        );
+    }
+
+    #[test]
+    fn import_errors() {
+        let cycle = load(
+            "blixt-import-cycle",
+            &[
+                ("main.bl", "import \"lib/a\""),
+                ("lib/a.bl", "import \"b\""),
+                ("lib/b.bl", "import \"a\""),
+            ],
+        );
+        assert!(cycle.0.is_err());
+
+        let missing =
+            load("blixt-import-missing", &[("main.bl", "import \"x\"")]);
+        assert!(missing.0.is_err());
+
+        let nested = load(
+            "blixt-import-nested",
+            &[("main.bl", "{ import \"lib/a\" }"), ("lib/a.bl", "")],
+        );
+        assert!(nested.0.is_err());
+    }
+}
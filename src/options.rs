@@ -1,25 +1,606 @@
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, ErrorKind, SubCommand};
+
+use std::env;
+use std::ffi::OsString;
+
+use log::LevelFilter;
+
+use blixt::config::{Config, Layer};
+use blixt::messages::Language;
 
 pub struct Options {
+    pub command: Command,
+    /// Settings given as flags, like `("max_depth", "100")` for
+    /// `--max-depth 100`, which take precedence over the config file and
+    /// the environment.
+    pub runtime: Vec<(&'static str, String)>,
+    /// Print the settings and where each came from instead of running.
+    pub print_config: bool,
+    pub stats: bool,
+    /// Write each phase to standard error as a JSON line as it finishes.
+    pub trace_json: bool,
+    /// Run with the tree-walking interpreter instead of the VM.
+    pub interpret: bool,
+    /// Stages to write the output of instead of running the file.
+    pub emit: Vec<Emit>,
+    /// Ask before a builtin uses a capability that has not been granted.
+    pub prompt: bool,
+    /// File to append a line to for every call that needs a capability.
+    pub audit: Option<String>,
+    /// How much to log, before `RUST_LOG` is applied on top.
+    pub log_level: LevelFilter,
+    /// Everything after `--`, passed on to the script untouched.
+    pub args: Vec<String>,
+}
+
+pub enum Command {
+    /// Runs a file, or standard input if `file` is `-`.
+    Run {
+        file: String,
+    },
+    Eval {
+        code: String,
+    },
+    Info {
+        file: String,
+    },
+    Template {
+        file: String,
+    },
+    Repl,
+    Rename(Rename),
+    References {
+        name: String,
+        files: Vec<String>,
+    },
+}
+
+/// A stage of running a file whose output `--emit` can write, in the
+/// order they run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    Tokens,
+    Ast,
+    Bytecode,
+}
+
+impl Stage {
+    pub const NAMES: &'static [&'static str] = &["tokens", "ast", "bytecode"];
+
+    fn from_name(name: &str) -> Option<Stage> {
+        match name {
+            "tokens" => Some(Stage::Tokens),
+            "ast" => Some(Stage::Ast),
+            "bytecode" => Some(Stage::Bytecode),
+            _ => None,
+        }
+    }
+}
+
+/// The output of a stage to write, to a file or standard output.
+#[derive(Debug, PartialEq)]
+pub struct Emit {
+    pub stage: Stage,
+    pub file: Option<String>,
+}
+
+pub struct Rename {
+    pub old: String,
+    pub new: String,
     pub file: String,
+    pub line: u32,
+    pub column: u32,
 }
 
 impl Options {
     pub fn parse() -> Options {
+        Options::parse_from(env::args_os())
+    }
+
+    fn parse_from<I, T>(args: I) -> Options
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
         let matches = App::new("Blixt")
             .version("0.1")
             .author("Jonas Westlund <jonaswestlund101@gmail.com>")
             .about("A toy programming language")
             .arg(
                 Arg::with_name("INPUT")
-                    .help("File to run")
-                    .required(true)
+                    .help("File to run, - for standard input, starts a REPL if left out")
                     .index(1),
             )
-            .get_matches();
+            .arg(
+                Arg::with_name("eval")
+                    .help("Run CODE instead of a file")
+                    .short("e")
+                    .value_name("CODE")
+                    .takes_value(true)
+                    .allow_hyphen_values(true)
+                    .conflicts_with("INPUT"),
+            )
+            .arg(
+                Arg::with_name("ARGS")
+                    .help("Arguments for the script, given after --")
+                    .multiple(true)
+                    .last(true),
+            )
+            .arg(
+                Arg::with_name("strict")
+                    .help("Disallow implicit conversions, shadowing and unused results")
+                    .long("strict"),
+            )
+            .arg(
+                Arg::with_name("stats")
+                    .help("Print timings and counts for each phase at exit")
+                    .long("stats"),
+            )
+            .arg(
+                Arg::with_name("trace-format")
+                    .help("Write each phase to standard error as it finishes")
+                    .long("trace-format")
+                    .value_name("FORMAT")
+                    .takes_value(true)
+                    .possible_values(&["json"]),
+            )
+            .arg(
+                Arg::with_name("interpret")
+                    .help("Run the syntax tree directly instead of compiling to bytecode")
+                    .long("interpret"),
+            )
+            .arg(
+                Arg::with_name("emit")
+                    .help("Write the output of each STAGE, tokens, ast or bytecode, to FILE or standard output and exit")
+                    .long("emit")
+                    .value_name("STAGE[=FILE]")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .use_delimiter(true),
+            )
+            .arg(
+                Arg::with_name("dump-tokens")
+                    .help("Same as --emit=tokens")
+                    .long("dump-tokens"),
+            )
+            .arg(
+                Arg::with_name("dump-ast")
+                    .help("Same as --emit=ast")
+                    .long("dump-ast"),
+            )
+            .arg(
+                Arg::with_name("verbose")
+                    .help("Log what each phase does, -vv also logs tokens and syntax trees")
+                    .short("v")
+                    .long("verbose")
+                    .multiple(true),
+            )
+            .arg(
+                Arg::with_name("quiet")
+                    .help("Log nothing, not even internal errors")
+                    .short("q")
+                    .long("quiet")
+                    .conflicts_with("verbose"),
+            )
+            .arg(
+                Arg::with_name("prompt")
+                    .help("Ask before using a capability that has not been granted")
+                    .long("prompt"),
+            )
+            .arg(
+                Arg::with_name("allow")
+                    .help("Grant CAPABILITIES, like fs,net, to the script")
+                    .long("allow")
+                    .value_name("CAPABILITIES")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("audit")
+                    .help("Log every call that needs a capability to FILE as JSON lines")
+                    .long("audit")
+                    .value_name("FILE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("lang")
+                    .help("Language of error messages")
+                    .long("lang")
+                    .value_name("LANG")
+                    .takes_value(true)
+                    .possible_values(Language::CODES),
+            )
+            .arg(
+                Arg::with_name("print-config")
+                    .help("Print the settings from defaults, gentlemen.toml, BLIXT_ variables and flags, and exit")
+                    .long("print-config"),
+            )
+            .arg(
+                Arg::with_name("max-depth")
+                    .help("Maximum nesting depth of blocks and expressions")
+                    .long("max-depth")
+                    .value_name("N")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max-tokens")
+                    .help("Maximum number of tokens in a file")
+                    .long("max-tokens")
+                    .value_name("N")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max-string-length")
+                    .help("Maximum length in bytes of a string literal")
+                    .long("max-string-length")
+                    .value_name("N")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max-array-length")
+                    .help("Maximum length an array can be made room for or resized to at once")
+                    .long("max-array-length")
+                    .value_name("N")
+                    .takes_value(true),
+            )
+            .subcommand(
+                SubCommand::with_name("refactor")
+                    .about("Rewrites source code")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        SubCommand::with_name("rename")
+                            .about("Renames a variable or function")
+                            .arg(
+                                Arg::with_name("OLD")
+                                    .help("Current name")
+                                    .required(true)
+                                    .index(1),
+                            )
+                            .arg(
+                                Arg::with_name("NEW")
+                                    .help("New name")
+                                    .required(true)
+                                    .index(2),
+                            )
+                            .arg(
+                                Arg::with_name("at")
+                                    .help("Position of an occurrence of OLD")
+                                    .long("at")
+                                    .value_name("FILE:LINE:COL")
+                                    .takes_value(true)
+                                    .required(true),
+                            ),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("info")
+                    .about("Shows the metadata at the top of a script")
+                    .arg(
+                        Arg::with_name("FILE")
+                            .help("Script to show")
+                            .required(true)
+                            .index(1),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("template")
+                    .about("Prints a template, filling in {{ expressions }} and {% statements %}")
+                    .arg(
+                        Arg::with_name("FILE")
+                            .help("Template to print")
+                            .required(true)
+                            .index(1),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("refs")
+                    .about("Lists definitions and references of a name")
+                    .arg(
+                        Arg::with_name("NAME")
+                            .help("Variable or function to look up")
+                            .required(true)
+                            .index(1),
+                    )
+                    .arg(
+                        Arg::with_name("FILES")
+                            .help("Files to search")
+                            .required(true)
+                            .multiple(true)
+                            .index(2),
+                    ),
+            )
+            .get_matches_from(args);
+
+        let command = match matches.subcommand() {
+            ("refactor", Some(refactor)) => match refactor.subcommand() {
+                ("rename", Some(rename)) => {
+                    Command::Rename(parse_rename(rename))
+                }
+                _ => unreachable!(),
+            },
+            ("info", Some(info)) => Command::Info {
+                file: info.value_of("FILE").unwrap().to_string(),
+            },
+            ("template", Some(template)) => Command::Template {
+                file: template.value_of("FILE").unwrap().to_string(),
+            },
+            ("refs", Some(refs)) => Command::References {
+                name: refs.value_of("NAME").unwrap().to_string(),
+                files: refs
+                    .values_of("FILES")
+                    .unwrap()
+                    .map(str::to_string)
+                    .collect(),
+            },
+            _ => match (matches.value_of("eval"), matches.value_of("INPUT")) {
+                (Some(code), _) => Command::Eval {
+                    code: code.to_string(),
+                },
+                (None, Some(file)) => Command::Run {
+                    file: file.to_string(),
+                },
+                (None, None) => Command::Repl,
+            },
+        };
 
         Options {
-            file: matches.value_of("INPUT").unwrap().to_string(),
+            command,
+            runtime: parse_runtime(&matches),
+            print_config: matches.is_present("print-config"),
+            stats: matches.is_present("stats"),
+            trace_json: matches.value_of("trace-format") == Some("json"),
+            interpret: matches.is_present("interpret"),
+            emit: parse_emit(&matches),
+            prompt: matches.is_present("prompt"),
+            audit: matches.value_of("audit").map(str::to_string),
+            log_level: parse_log_level(&matches),
+            args: matches
+                .values_of("ARGS")
+                .map_or(vec![], |args| args.map(str::to_string).collect()),
+        }
+    }
+}
+
+/// The settings given as flags, checked the way the config file and the
+/// environment are.
+fn parse_runtime(matches: &ArgMatches) -> Vec<(&'static str, String)> {
+    let flags = [
+        ("max-depth", "max_depth"),
+        ("max-tokens", "max_tokens"),
+        ("max-string-length", "max_string_length"),
+        ("max-array-length", "max_array_length"),
+        ("lang", "lang"),
+        ("allow", "capabilities"),
+    ];
+
+    let mut runtime = Vec::new();
+    if matches.is_present("strict") {
+        runtime.push(("strict", "true".to_string()));
+    }
+    for (flag, key) in &flags {
+        if let Some(value) = matches.value_of(flag) {
+            if let Err(e) = Config::default().set(key, value, Layer::Flag) {
+                let message = e.replacen(key, &format!("--{}", flag), 1);
+                clap::Error::with_description(
+                    &message,
+                    ErrorKind::InvalidValue,
+                )
+                .exit();
+            }
+            runtime.push((*key, value.to_string()));
+        }
+    }
+    runtime
+}
+
+fn parse_emit(matches: &ArgMatches) -> Vec<Emit> {
+    let mut emit = Vec::new();
+    if matches.is_present("dump-tokens") {
+        emit.push(Emit {
+            stage: Stage::Tokens,
+            file: None,
+        });
+    }
+    if matches.is_present("dump-ast") {
+        emit.push(Emit {
+            stage: Stage::Ast,
+            file: None,
+        });
+    }
+
+    for value in matches.values_of("emit").into_iter().flatten() {
+        let mut parts = value.splitn(2, '=');
+        let name = parts.next().unwrap_or_default();
+        let file = parts.next().map(str::to_string);
+        match Stage::from_name(name) {
+            Some(stage) if file.as_deref() != Some("") => {
+                emit.push(Emit { stage, file })
+            }
+            _ => clap::Error::with_description(
+                &format!(
+                    "Invalid value '{}' for --emit, expected one of {} \
+                     with an optional =FILE",
+                    value,
+                    Stage::NAMES.join(", ")
+                ),
+                ErrorKind::InvalidValue,
+            )
+            .exit(),
+        }
+    }
+
+    emit
+}
+
+fn parse_log_level(matches: &ArgMatches) -> LevelFilter {
+    if matches.is_present("quiet") {
+        return LevelFilter::Off;
+    }
+
+    match matches.occurrences_of("verbose") {
+        0 => LevelFilter::Error,
+        1 => LevelFilter::Info,
+        _ => LevelFilter::Trace,
+    }
+}
+
+fn parse_rename(matches: &ArgMatches) -> Rename {
+    let at = matches.value_of("at").unwrap();
+    let (file, line, column) = match parse_position(at) {
+        Some(position) => position,
+        None => clap::Error::with_description(
+            &format!("Invalid position '{}', expected FILE:LINE:COL", at),
+            ErrorKind::InvalidValue,
+        )
+        .exit(),
+    };
+
+    Rename {
+        old: matches.value_of("OLD").unwrap().to_string(),
+        new: matches.value_of("NEW").unwrap().to_string(),
+        file,
+        line,
+        column,
+    }
+}
+
+fn parse_position(position: &str) -> Option<(String, u32, u32)> {
+    let mut parts = position.rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+    let file = parts.next()?;
+
+    if file.is_empty() || line == 0 || column == 0 {
+        return None;
+    }
+
+    Some((file.to_string(), line, column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_arguments() {
+        let options = Options::parse_from([
+            "blixt",
+            "--strict",
+            "--dump-ast",
+            "a.bl",
+            "--",
+            "--stats",
+            "x",
+        ]);
+
+        match options.command {
+            Command::Run { file } => assert_eq!(file, "a.bl"),
+            _ => panic!("Expected a file to run"),
         }
+        assert_eq!(options.runtime, [("strict", "true".to_string())]);
+        assert!(!options.stats);
+        assert!(!options.trace_json);
+        assert_eq!(
+            options.emit,
+            [Emit {
+                stage: Stage::Ast,
+                file: None
+            }]
+        );
+        assert_eq!(options.args, ["--stats", "x"]);
+
+        let options = Options::parse_from(["blixt", "a.bl"]);
+        assert!(options.args.is_empty());
+        assert!(options.runtime.is_empty());
+        assert!(!options.print_config);
+        assert!(!options.prompt);
+        assert_eq!(options.audit, None);
+
+        let options = Options::parse_from([
+            "blixt",
+            "--lang",
+            "sv",
+            "--prompt",
+            "--trace-format=json",
+            "--audit",
+            "log.jsonl",
+            "--max-depth=8",
+            "a.bl",
+        ]);
+        assert_eq!(
+            options.runtime,
+            [("max_depth", "8".to_string()), ("lang", "sv".to_string())]
+        );
+        assert!(options.prompt);
+        assert!(options.trace_json);
+        assert_eq!(options.audit.as_deref(), Some("log.jsonl"));
+    }
+
+    #[test]
+    fn emit_stages() {
+        let options = Options::parse_from([
+            "blixt",
+            "--emit=tokens,bytecode=out.txt",
+            "--emit",
+            "ast",
+            "a.bl",
+        ]);
+        let emit: Vec<_> = options
+            .emit
+            .iter()
+            .map(|emit| (emit.stage, emit.file.as_deref()))
+            .collect();
+        assert_eq!(
+            emit,
+            [
+                (Stage::Tokens, None),
+                (Stage::Bytecode, Some("out.txt")),
+                (Stage::Ast, None)
+            ]
+        );
+
+        let options = Options::parse_from(["blixt", "a.bl"]);
+        assert!(options.emit.is_empty());
+    }
+
+    #[test]
+    fn stdin_and_inline_code() {
+        let options =
+            Options::parse_from(["blixt", "--strict", "-", "--", "x"]);
+        match options.command {
+            Command::Run { file } => assert_eq!(file, "-"),
+            _ => panic!("Expected standard input to run"),
+        }
+        assert_eq!(options.runtime, [("strict", "true".to_string())]);
+        assert_eq!(options.args, ["x"]);
+
+        let options = Options::parse_from(["blixt", "--allow", "fs", "a.bl"]);
+        assert_eq!(options.runtime, [("capabilities", "fs".to_string())]);
+
+        let options =
+            Options::parse_from(["blixt", "-e", "print(-1)", "--", "a", "b"]);
+        match options.command {
+            Command::Eval { code } => assert_eq!(code, "print(-1)"),
+            _ => panic!("Expected code to run"),
+        }
+        assert_eq!(options.args, ["a", "b"]);
+    }
+
+    #[test]
+    fn log_levels() {
+        let level = |args: &[&str]| {
+            let args = ["blixt"].iter().chain(args).chain(&["a.bl"]);
+            Options::parse_from(args).log_level
+        };
+
+        assert_eq!(level(&[]), LevelFilter::Error);
+        assert_eq!(level(&["-v"]), LevelFilter::Info);
+        assert_eq!(level(&["-vv"]), LevelFilter::Trace);
+        assert_eq!(level(&["--verbose", "-v"]), LevelFilter::Trace);
+        assert_eq!(level(&["--quiet"]), LevelFilter::Off);
+        assert_eq!(
+            Options::parse_from(["blixt", "a.bl", "--", "-v"]).log_level,
+            LevelFilter::Error
+        );
     }
 }
@@ -1,50 +1,166 @@
+use std::cell::Cell;
 use std::collections::VecDeque;
+use std::mem;
 
 use log::trace;
 
 use crate::arena::Arena;
 use crate::ast::{
     ArgList, Assignment, AssignmentKind, Ast, AstNodeId, BinaryOp,
-    BinaryOpKind, Decl, Expr, ExprKind, FunctionCall, FunctionDecl, If, Input,
-    Param, ParamList, Print, Return, Stmt, StructDecl, UnaryOp, UnaryOpKind,
-    VarDecl,
+    BinaryOpKind, Decl, Expr, ExprKind, For, ForIter, FunctionCall,
+    FunctionDecl, If, Index, Input, Param, ParamList, Print, Range, Return,
+    Stmt, StmtList, StructDecl, Try, UnaryOp, UnaryOpKind, VarDecl, While,
 };
-use crate::common::{Context, Symbol};
+use crate::common::{grow_stack, Context, Diagnostic, Severity, Symbol};
+use crate::lexer;
 use crate::location::Location;
+use crate::messages::Message;
 use crate::primitives::ValueKind;
 use crate::token::{Token, TokenKind};
 
 pub type Result<T> = std::result::Result<T, ()>;
 
+#[allow(clippy::result_unit_err)]
 pub fn parse_ast(
     tokens: VecDeque<Token>,
     context: &mut Context,
 ) -> Result<Ast> {
     let mut arena = Arena::new();
+    let statements = parse_statements(tokens, &mut arena, context)?;
 
-    let location = match tokens.get(0) {
+    Ok(Ast { arena, statements })
+}
+
+/// Parses `tokens` into an existing arena, so that the REPL can keep
+/// earlier input alive while adding to it.
+pub(crate) fn parse_statements(
+    tokens: VecDeque<Token>,
+    arena: &mut Arena<Stmt>,
+    context: &mut Context,
+) -> Result<StmtList> {
+    let (statements, diagnostics) = parse_all(tokens, arena, context);
+    let failed = !diagnostics.is_empty();
+    context.report_all(diagnostics);
+
+    if failed {
+        Err(())
+    } else {
+        Ok(statements)
+    }
+}
+
+/// Lexes and parses `source` without reporting anything, for scripts that
+/// look at code. Returns the errors instead if there are any.
+pub fn parse_quietly(
+    source: &str,
+    file: Symbol,
+    context: &mut Context,
+) -> std::result::Result<Ast, Vec<Diagnostic>> {
+    let lexed = lexer::lex_all(source.as_bytes(), file, context);
+    if !lexed.diagnostics.is_empty() {
+        return Err(lexed.diagnostics);
+    }
+
+    let mut arena = Arena::new();
+    let (statements, diagnostics) =
+        parse_all(lexed.tokens, &mut arena, context);
+    if diagnostics.is_empty() {
+        Ok(Ast { arena, statements })
+    } else {
+        Err(diagnostics)
+    }
+}
+
+/// Parses as much of `tokens` as possible. A statement with an error is
+/// skipped, and parsing goes on after it to find more errors.
+fn parse_all(
+    tokens: VecDeque<Token>,
+    arena: &mut Arena<Stmt>,
+    context: &mut Context,
+) -> (StmtList, Vec<Diagnostic>) {
+    let (statements, diagnostics, _) = parse_until_end(tokens, arena, context);
+    (statements, diagnostics)
+}
+
+/// Like `parse_all`, but also tells whether every error was found after
+/// the parser had run out of tokens, so more input could have fixed them.
+fn parse_until_end(
+    tokens: VecDeque<Token>,
+    arena: &mut Arena<Stmt>,
+    context: &mut Context,
+) -> (StmtList, Vec<Diagnostic>, bool) {
+    let location = match tokens.front() {
         Some(token) => token.location,
-        None => {
-            return Ok(Ast {
-                arena,
-                statements: vec![],
-            });
-        }
+        None => return (vec![], vec![], false),
     };
 
     let mut parser = Parser {
-        arena: &mut arena,
+        arena,
         context,
         tokens,
         location,
+        line: location.line,
+        depth: 0,
+        loops: 0,
+        matches: 0,
+        ran_out: Cell::new(false),
+        errors_at_end: 0,
+        diagnostics: vec![],
     };
 
-    let statements = match parser.statement_list() {
-        Ok(stmts) => stmts,
-        Err(_) => return Err(()),
-    };
+    let mut statements = vec![];
+    loop {
+        if let Ok(list) = parser.statement_list() {
+            statements.extend(list);
+        }
 
-    Ok(Ast { arena, statements })
+        // The list ends at a token that can't start a statement, which is
+        // skipped so that the rest can still be checked.
+        match parser.next_token_kind() {
+            Some(kind) => {
+                parser.report_error(Message::ExpectedStatement(kind));
+            }
+            None => break,
+        }
+    }
+
+    let at_end = !parser.diagnostics.is_empty()
+        && parser.errors_at_end == parser.diagnostics.len();
+    (statements, parser.diagnostics, at_end)
+}
+
+/// Whether a source is a whole program, so that a REPL or an editor can
+/// wait for more input instead of reporting errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Completeness {
+    Complete,
+    /// Ends inside a string, comment or block, or in the middle of a
+    /// statement, like `a := 1 +`.
+    Incomplete,
+    /// Has errors that more input can't fix.
+    Invalid,
+}
+
+/// Lexes and parses `source` without reporting anything, to find out
+/// whether it is complete.
+pub fn completeness(
+    source: &str,
+    file: Symbol,
+    context: &mut Context,
+) -> Completeness {
+    let lexed = lexer::lex_all(source.as_bytes(), file, context);
+    if lexed.unterminated && lexed.diagnostics.len() == 1 {
+        return Completeness::Incomplete;
+    } else if !lexed.diagnostics.is_empty() {
+        return Completeness::Invalid;
+    }
+
+    let mut arena = Arena::with_block_size(4096);
+    match parse_until_end(lexed.tokens, &mut arena, context) {
+        (_, diagnostics, _) if diagnostics.is_empty() => Completeness::Complete,
+        (_, _, true) => Completeness::Incomplete,
+        _ => Completeness::Invalid,
+    }
 }
 
 struct Parser<'a> {
@@ -52,73 +168,206 @@ struct Parser<'a> {
     context: &'a mut Context,
     tokens: VecDeque<Token>,
     location: Location,
+    /// The line of the last token taken.
+    line: u32,
+    depth: usize,
+    /// How many loops the statement being parsed is in, within the
+    /// innermost function.
+    loops: usize,
+    /// Number of `match` statements so far, to name the variable each one
+    /// keeps its value in.
+    matches: usize,
+    /// Set once the parser has looked for a token after the last one.
+    ran_out: Cell<bool>,
+    /// Errors found after running out of tokens.
+    errors_at_end: usize,
+    /// Errors are collected rather than reported right away, so that all of
+    /// them can be shown in order once parsing is done.
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl<'a> Parser<'a> {
-    fn report_error(&mut self, message: &str) {
-        self.context.report_error(message, self.location);
+    fn report_error(&mut self, message: Message) {
+        if self.ran_out.get() {
+            self.errors_at_end += 1;
+        }
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            message,
+            location: self.location,
+        });
     }
 
     fn peek_token(&self, pos: usize) -> Option<&Token> {
+        if pos == 0 && self.tokens.is_empty() {
+            self.ran_out.set(true);
+        }
         self.tokens.get(pos)
     }
 
     fn peek_token_kind(&self, pos: usize) -> Option<&TokenKind> {
-        self.tokens.get(pos).map(|token| &token.kind)
+        self.peek_token(pos).map(|token| &token.kind)
+    }
+
+    /// The next token, unless a line break before it ends the statement,
+    /// for where a token would continue the expression before it.
+    fn peek_operator(&self) -> Option<&TokenKind> {
+        self.peek_token(0)
+            .filter(|token| !token.line_break)
+            .map(|token| &token.kind)
     }
 
     fn next_token(&mut self) -> Option<Token> {
-        self.tokens.pop_front().map(|token| {
+        self.peek_token(0);
+        self.tokens.pop_front().inspect(|token| {
+            self.line = token.location.line;
             if let Some(token) = self.peek_token(0) {
                 self.location = token.location;
             }
-            token
         })
     }
 
     fn next_token_kind(&mut self) -> Option<TokenKind> {
+        self.peek_token(0);
         self.tokens.pop_front().map(|token| {
             self.location = token.location;
+            self.line = token.location.line;
             token.kind
         })
     }
 
     fn expect_next(&mut self, kind: TokenKind) -> Result<Token> {
-        if let Some(token) = self.next_token() {
-            if token.kind == kind {
-                Ok(token)
-            } else {
-                self.report_error(&format!(
-                    "Expected {:?}, found {:?}",
-                    kind, token.kind
-                ));
+        // A wrong token is left in place, it may start the next statement.
+        if let Some(token) = self.peek_token(0) {
+            if token.kind != kind {
+                let found = token.kind.clone();
+                self.location = token.location;
+                self.report_error(Message::ExpectedToken {
+                    expected: kind,
+                    found,
+                });
+                return Err(());
+            }
+        }
+
+        match self.next_token() {
+            Some(token) => Ok(token),
+            None => {
+                self.report_error(Message::UnexpectedEnd(kind));
                 Err(())
             }
-        } else {
-            self.report_error("Expected {:?}, but no tokens were left");
-            Err(())
         }
     }
 
+    /// Runs `parse` one level deeper, failing once the nesting limit is
+    /// reached rather than overflowing the stack.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        if self.depth == self.context.limits.max_depth {
+            self.report_error(Message::NestedTooDeeply(
+                self.context.limits.max_depth,
+            ));
+            return Err(());
+        }
+
+        self.depth += 1;
+        let result = grow_stack(|| parse(self));
+        self.depth -= 1;
+
+        result
+    }
+
     fn statement_list(&mut self) -> Result<Vec<AstNodeId>> {
         trace!("Entered statement_list");
 
-        let mut stmts = Vec::new();
-        while let Some(stmt) = self.statement()? {
-            stmts.push(stmt);
+        self.nested(|parser| {
+            let mut stmts = Vec::new();
+            loop {
+                let remaining = parser.tokens.len();
+                match parser.statement() {
+                    Ok(Some(stmt)) => stmts.push(stmt),
+                    Ok(None) => break,
+                    Err(()) => {
+                        parser.synchronize(remaining);
+                        // Nothing could be skipped before a closing brace,
+                        // which ends the list.
+                        if parser.tokens.len() == remaining {
+                            break;
+                        }
+                    }
+                }
+            }
+            Ok(stmts)
+        })
+    }
+
+    /// Skips the rest of a statement that failed to parse, up to the next
+    /// line, closing brace or keyword that starts a statement. `remaining`
+    /// is the number of tokens left when the statement started.
+    fn synchronize(&mut self, remaining: usize) {
+        if self.tokens.len() == remaining
+            && self.peek_token_kind(0) != Some(&TokenKind::CloseBrace)
+        {
+            self.next_token();
+        }
+
+        while let Some(token) = self.peek_token(0) {
+            let boundary = token.location.line > self.line
+                || matches!(
+                    token.kind,
+                    TokenKind::CloseBrace
+                        | TokenKind::If
+                        | TokenKind::Match
+                        | TokenKind::For
+                        | TokenKind::While
+                        | TokenKind::FunctionDecl
+                        | TokenKind::StructDecl
+                        | TokenKind::Return
+                        | TokenKind::Break
+                        | TokenKind::Continue
+                        | TokenKind::At
+                );
+            if boundary {
+                break;
+            }
+
+            self.next_token();
+        }
+    }
+
+    /// Runs `parse` and fails with an error if it finds no expression.
+    fn required(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Option<AstNodeId>>,
+    ) -> Result<AstNodeId> {
+        match parse(self)? {
+            Some(expr) => Ok(expr),
+            None => {
+                self.report_error(Message::ExpectedExpression);
+                Err(())
+            }
         }
-        Ok(stmts)
     }
 
     fn statement(&mut self) -> Result<Option<AstNodeId>> {
         trace!("Entered statement: {:?}", self.peek_token_kind(0));
 
-        if let Some(print) = self.print()? {
+        if let Some(decl) = self.attributed()? {
+            Ok(Some(decl))
+        } else if let Some(print) = self.print()? {
             Ok(Some(print))
         } else if let Some(decl) = self.struct_decl()? {
             Ok(Some(decl))
         } else if let Some(if_stmt) = self.if_statement()? {
             Ok(Some(if_stmt))
+        } else if let Some(match_stmt) = self.match_statement()? {
+            Ok(Some(match_stmt))
+        } else if let Some(for_loop) = self.for_loop()? {
+            Ok(Some(for_loop))
+        } else if let Some(while_loop) = self.while_loop()? {
+            Ok(Some(while_loop))
         } else if let Some(keyword) = self.keyword()? {
             Ok(Some(keyword))
         } else if let Some(block) = self.block()? {
@@ -136,106 +385,220 @@ impl<'a> Parser<'a> {
         }
     }
 
-    // fn for_loop(&mut self) -> Result<Option<Stmt>> {
-    //     trace!("Entered for_loop");
-
-    //     match self.peek_token_kind(0) {
-    //         Some(TokenKind::For) => {}
-    //         _ => return Ok(None),
-    //     }
-
-    //     self.next_token();
-
-    //     let ident = self.ident()?;
-    //     self.expect_next(TokenKind::In)?;
-
-    //     let range = match self.expression()? {
-    //         Some(expr) => match expr.kind {
-    //             ExprKind::Range(Range { start, end }) => Range { start, end },
-    //             _ => {
-    //                 self.report_error("Expected range");
-    //                 return Err(());
-    //             }
-    //         },
-    //         None => {
-    //             self.report_error("Expected range");
-    //             return Err(());
-    //         }
-    //     };
-
-    //     let block = match self.block()? {
-    //         Some(block) => block,
-    //         None => {
-    //             self.report_error("Expected block");
-    //             return Err(());
-    //         }
-    //     };
-
-    //     Ok(Some(Stmt::For(For {
-    //         ident,
-    //         range,
-    //         block: match block {
-    //             Stmt::Block(b) => b,
-    //             _ => unreachable!(),
-    //         },
-    //     })))
-    // }
-
-    // fn range(&mut self) -> Result<Option<Expr>> {
-    //     match self.peek_token_kind(0) {
-    //         Some(TokenKind::Range(start, end)) => {
-    //             let start = *start;
-    //             let end = *end;
-    //             let token = self.next_token().unwrap();
-
-    //             let range = Expr {
-    //                 location: token.location,
-    //                 kind: ExprKind::Range(Range { start, end }),
-    //             };
-    //             Ok(Some(range))
-    //         }
-    //         _ => Ok(None),
-    //     }
-    // }
+    /// Parses a declaration with `@cfg(...)` in front of it. A declaration
+    /// whose conditions are not all true is left out, with an empty block
+    /// in its place, so that nothing after parsing sees it.
+    fn attributed(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered attributed");
 
-    fn declaration(&mut self) -> Result<Option<AstNodeId>> {
-        trace!("Entered declaration");
+        let mut enabled = true;
+        let mut found = false;
+        while self.peek_token_kind(0) == Some(&TokenKind::At) {
+            self.next_token();
+            found = true;
 
-        match (self.peek_token_kind(0), self.peek_token_kind(1)) {
-            (Some(TokenKind::Ident(_)), Some(TokenKind::VarDecl))
-            | (Some(TokenKind::Ident(_)), Some(TokenKind::Colon)) => {}
-            _ => return Ok(None),
+            let location = self.location;
+            let name = self.ident()?;
+            let name = self.context.interner.get(name);
+            if name != "cfg" {
+                let name = name.to_string();
+                self.location = location;
+                self.report_error(Message::UnknownAttribute(name));
+                return Err(());
+            }
+
+            self.expect_next(TokenKind::OpenParen)?;
+            enabled &= self.cfg_predicate()?;
+            self.expect_next(TokenKind::CloseParen)?;
         }
 
-        let ident = match self.next_token_kind() {
-            Some(TokenKind::Ident(n)) => n,
-            _ => unreachable!(),
+        if !found {
+            return Ok(None);
+        }
+
+        let location = self.location;
+        let decl = match self.statement()? {
+            Some(stmt) if matches!(self.arena[stmt], Stmt::Decl(_)) => stmt,
+            _ => {
+                self.location = location;
+                self.report_error(Message::AttributeWithoutDeclaration);
+                return Err(());
+            }
         };
 
-        let var_type = match self.next_token_kind() {
-            Some(TokenKind::Colon) => match self.next_token_kind() {
-                Some(TokenKind::BoolType) => ValueKind::Bool,
-                Some(TokenKind::StringType) => ValueKind::String,
-                Some(TokenKind::IntType) => ValueKind::Integer,
-                Some(TokenKind::FloatType) => ValueKind::Float,
-                Some(kind) => {
-                    self.report_error(&format!(
-                        "Expected type, found {:?}",
-                        kind
-                    ));
+        if enabled {
+            Ok(Some(decl))
+        } else {
+            Ok(Some(self.arena.alloc(Stmt::Block(vec![]))))
+        }
+    }
+
+    /// Parses the condition of a `@cfg`, which is `key = "value"`, or
+    /// `all(...)`, `any(...)` or `not(...)` around other conditions, and
+    /// checks it against the flags in the context.
+    fn cfg_predicate(&mut self) -> Result<bool> {
+        trace!("Entered cfg_predicate");
+
+        self.nested(|parser| {
+            let key = match parser.next_token_kind() {
+                Some(TokenKind::Ident(key)) => key,
+                _ => {
+                    parser.report_error(Message::ExpectedCfgPredicate);
+                    return Err(());
+                }
+            };
+            let key = parser.context.interner.get(key).to_string();
+
+            if let "all" | "any" | "not" = key.as_str() {
+                parser.expect_next(TokenKind::OpenParen)?;
+                let mut values = vec![];
+                while parser.peek_token_kind(0) != Some(&TokenKind::CloseParen)
+                {
+                    values.push(parser.cfg_predicate()?);
+                    if parser.peek_token_kind(0) != Some(&TokenKind::Comma) {
+                        break;
+                    }
+                    parser.next_token();
+                }
+                parser.expect_next(TokenKind::CloseParen)?;
+
+                return match (key.as_str(), values.as_slice()) {
+                    ("all", _) => Ok(values.iter().all(|&value| value)),
+                    ("any", _) => Ok(values.iter().any(|&value| value)),
+                    (_, [value]) => Ok(!value),
+                    _ => {
+                        parser.report_error(Message::ExpectedCfgPredicate);
+                        Err(())
+                    }
+                };
+            }
+
+            if !parser.context.cfg.iter().any(|(name, _)| *name == key) {
+                parser.report_error(Message::UnknownCfgKey(key));
+                return Err(());
+            }
+
+            parser.expect_next(TokenKind::Assign)?;
+            let value = match parser.next_token_kind() {
+                Some(TokenKind::String(value)) => value,
+                _ => {
+                    parser.report_error(Message::ExpectedCfgPredicate);
                     return Err(());
                 }
+            };
+            let value = parser.context.interner.get(value);
+
+            Ok(parser
+                .context
+                .cfg
+                .iter()
+                .any(|(name, flag)| *name == key && flag == value))
+        })
+    }
+
+    fn for_loop(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered for_loop");
+
+        match self.peek_token_kind(0) {
+            Some(TokenKind::For) => {}
+            _ => return Ok(None),
+        }
+
+        let location = self.expect_next(TokenKind::For)?.location;
+
+        let (ident, ident_location) = self.located_ident()?;
+        let mut unpack = Vec::new();
+        let mut locations = vec![ident_location];
+        while let Some(TokenKind::Comma) = self.peek_token_kind(0) {
+            self.next_token();
+            let (name, location) = self.located_ident()?;
+            unpack.push(name);
+            locations.push(location);
+        }
+        self.expect_next(TokenKind::In)?;
+
+        let iter = match self.peek_token_kind(0) {
+            Some(TokenKind::Range(start, end)) => {
+                let range = Range {
+                    start: *start as i32,
+                    end: *end as i32,
+                };
+                self.next_token();
+                ForIter::Range(range)
+            }
+            _ => match self.expression()? {
+                Some(expr) => ForIter::Expr(expr),
                 None => {
-                    self.report_error("Expected type");
+                    self.report_error(Message::ExpectedRangeOrExpression);
                     return Err(());
                 }
             },
+        };
+
+        self.expect_next(TokenKind::OpenBrace)?;
+        let block = self.loop_body()?;
+        self.expect_next(TokenKind::CloseBrace)?;
+
+        let node = self.arena.alloc(Stmt::For(For {
+            ident,
+            unpack,
+            iter,
+            block,
+            location,
+            locations,
+        }));
+
+        Ok(Some(node))
+    }
+
+    fn while_loop(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered while_loop");
+
+        match self.peek_token_kind(0) {
+            Some(TokenKind::While) => {}
+            _ => return Ok(None),
+        }
+
+        self.next_token();
+
+        let cond = match self.expression()? {
+            Some(cond) => cond,
+            None => {
+                self.report_error(Message::ExpectedWhileCondition);
+                return Err(());
+            }
+        };
+
+        self.expect_next(TokenKind::OpenBrace)?;
+        let body = self.loop_body()?;
+        self.expect_next(TokenKind::CloseBrace)?;
+
+        let node = self.arena.alloc(Stmt::While(While { cond, body }));
+        Ok(Some(node))
+    }
+
+    fn loop_body(&mut self) -> Result<Vec<AstNodeId>> {
+        self.loops += 1;
+        let body = self.statement_list();
+        self.loops -= 1;
+        body
+    }
+
+    fn declaration(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered declaration");
+
+        match (self.peek_token_kind(0), self.peek_token_kind(1)) {
+            (Some(TokenKind::Ident(_)), Some(TokenKind::VarDecl))
+            | (Some(TokenKind::Ident(_)), Some(TokenKind::Colon)) => {}
+            _ => return Ok(None),
+        }
+        let (ident, location) = self.located_ident()?;
+
+        let var_type = match self.next_token_kind() {
+            Some(TokenKind::Colon) => self.type_annotation()?,
             Some(TokenKind::VarDecl) => ValueKind::Nil,
             other => {
-                self.report_error(&format!(
-                    "Expected type or declaration operator, found {:?}",
-                    other
-                ));
+                self.report_error(Message::ExpectedDeclaration(other));
                 return Err(());
             }
         };
@@ -247,7 +610,7 @@ impl<'a> Parser<'a> {
         let value = match self.expression()? {
             Some(expr) => expr,
             None => {
-                self.report_error("Expected expression");
+                self.report_error(Message::ExpectedExpression);
                 return Err(());
             }
         };
@@ -256,6 +619,7 @@ impl<'a> Parser<'a> {
             name: ident,
             value,
             kind: var_type,
+            location,
         })));
         Ok(Some(node))
     }
@@ -269,6 +633,7 @@ impl<'a> Parser<'a> {
         }
 
         self.next_token();
+        let location = self.location;
         let name = self.ident()?;
 
         self.expect_next(TokenKind::OpenParen)?;
@@ -278,19 +643,17 @@ impl<'a> Parser<'a> {
         let return_type =
             if let Some(TokenKind::ReturnDecl) = self.peek_token_kind(0) {
                 self.next_token();
-                match self.next_token_kind() {
-                    Some(TokenKind::BoolType) => Some(ValueKind::Bool),
-                    Some(TokenKind::IntType) => Some(ValueKind::Integer),
-                    Some(TokenKind::FloatType) => Some(ValueKind::Float),
-                    Some(TokenKind::StringType) => Some(ValueKind::String),
-                    _ => panic!("Expected type after return decl"),
-                }
+                Some(self.type_annotation()?)
             } else {
                 None
             };
 
+        // A loop around the declaration cannot be left from the body.
         self.expect_next(TokenKind::OpenBrace)?;
-        let body = self.statement_list()?;
+        let loops = mem::replace(&mut self.loops, 0);
+        let body = self.statement_list();
+        self.loops = loops;
+        let body = body?;
         self.expect_next(TokenKind::CloseBrace)?;
 
         let node = self.arena.alloc(Stmt::Decl(Decl::Function(FunctionDecl {
@@ -298,6 +661,7 @@ impl<'a> Parser<'a> {
             body,
             params: param_list,
             return_type,
+            location,
         })));
 
         Ok(Some(node))
@@ -307,15 +671,17 @@ impl<'a> Parser<'a> {
         trace!("Entered struct_decl");
 
         if let Some(TokenKind::StructDecl) = self.peek_token_kind(0) {
-            self.next_token();
+            let location = self.expect_next(TokenKind::StructDecl)?.location;
             let name = self.ident()?;
             self.expect_next(TokenKind::OpenBrace)?;
             let fields = self.parameter_list()?;
             self.expect_next(TokenKind::CloseBrace)?;
 
-            let node = self
-                .arena
-                .alloc(Stmt::Decl(Decl::Struct(StructDecl { name, fields })));
+            let node = self.arena.alloc(Stmt::Decl(Decl::Struct(StructDecl {
+                name,
+                fields,
+                location,
+            })));
 
             Ok(Some(node))
         } else {
@@ -329,25 +695,16 @@ impl<'a> Parser<'a> {
         let mut params = ParamList::new();
 
         while let Some(TokenKind::Ident(_)) = self.peek_token_kind(0) {
-            let name = self.ident().expect("Expected ident");
+            let (name, location) = self.located_ident()?;
             self.expect_next(TokenKind::Colon)?;
 
-            let kind = match self.next_token() {
-                Some(Token { kind, .. }) => match kind {
-                    TokenKind::BoolType => ValueKind::Bool,
-                    TokenKind::IntType => ValueKind::Integer,
-                    TokenKind::FloatType => ValueKind::Float,
-                    TokenKind::StringType => ValueKind::String,
-                    TokenKind::Ident(name) => ValueKind::Struct(name),
-                    _ => {
-                        self.report_error("Expected type");
-                        return Err(());
-                    }
-                },
-                None => panic!("Sadasdas"),
-            };
+            let kind = self.type_annotation()?;
 
-            params.push(self.arena.alloc(Stmt::Param(Param { name, kind })));
+            params.push(self.arena.alloc(Stmt::Param(Param {
+                name,
+                kind,
+                location,
+            })));
 
             if let Some(TokenKind::Comma) = self.peek_token_kind(0) {
                 self.next_token();
@@ -357,14 +714,85 @@ impl<'a> Parser<'a> {
         Ok(params)
     }
 
+    /// A type such as `int`, a struct name, `[int]` for an array or
+    /// `{string: int}` for a map.
+    fn type_annotation(&mut self) -> Result<ValueKind> {
+        trace!("Entered type_annotation");
+
+        let kind = match self.next_token_kind() {
+            Some(TokenKind::BoolType) => ValueKind::Bool,
+            Some(TokenKind::IntType) => ValueKind::Integer,
+            Some(TokenKind::FloatType) => ValueKind::Float,
+            Some(TokenKind::StringType) => ValueKind::String,
+            Some(TokenKind::ResultType) => ValueKind::Result,
+            // `heap[int]` and `deque[int]`, any other name is a struct.
+            Some(TokenKind::Ident(name)) => {
+                let heap = self.context.interner.get(name) == "heap";
+                let deque = self.context.interner.get(name) == "deque";
+                if (heap || deque)
+                    && self.peek_token_kind(0) == Some(&TokenKind::OpenBracket)
+                {
+                    self.expect_next(TokenKind::OpenBracket)?;
+                    let element = self.nested(Self::type_annotation)?;
+                    self.expect_next(TokenKind::CloseBracket)?;
+                    if heap {
+                        ValueKind::Heap(Box::new(element))
+                    } else {
+                        ValueKind::Deque(Box::new(element))
+                    }
+                } else {
+                    ValueKind::Struct(name)
+                }
+            }
+            Some(TokenKind::OpenBracket) => {
+                let element = self.nested(Self::type_annotation)?;
+                self.expect_next(TokenKind::CloseBracket)?;
+                ValueKind::Array(Box::new(element))
+            }
+            Some(TokenKind::OpenBrace) => {
+                let key = self.nested(Self::type_annotation)?;
+                match key {
+                    ValueKind::Bool
+                    | ValueKind::Integer
+                    | ValueKind::String => {}
+                    other => {
+                        self.report_error(Message::InvalidMapKey(other));
+                        return Err(());
+                    }
+                }
+                self.expect_next(TokenKind::Colon)?;
+                let value = self.nested(Self::type_annotation)?;
+                self.expect_next(TokenKind::CloseBrace)?;
+                ValueKind::Map(Box::new(key), Box::new(value))
+            }
+            Some(kind) => {
+                self.report_error(Message::ExpectedType(Some(kind)));
+                return Err(());
+            }
+            None => {
+                self.report_error(Message::ExpectedType(None));
+                return Err(());
+            }
+        };
+
+        Ok(kind)
+    }
+
     fn function_call(&mut self) -> Result<Option<AstNodeId>> {
-        match (self.peek_token_kind(0), self.peek_token_kind(1)) {
-            (Some(TokenKind::Ident(_)), Some(TokenKind::OpenParen)) => {}
+        match (self.peek_token_kind(0), self.peek_token(1)) {
+            (
+                Some(TokenKind::Ident(_)),
+                Some(Token {
+                    kind: TokenKind::OpenParen,
+                    line_break: false,
+                    ..
+                }),
+            ) => {}
             _ => return Ok(None),
         }
 
-        let mut location = self.location;
-        let name = self.ident()?;
+        let (name, name_location) = self.located_ident()?;
+        let mut location = name_location;
         let args = self.argument_list()?;
 
         for arg in &args {
@@ -377,12 +805,45 @@ impl<'a> Parser<'a> {
 
         let funcall = self.arena.alloc(Stmt::Expr(Expr {
             location,
-            kind: ExprKind::FunctionCall(FunctionCall { name, args }),
+            kind: ExprKind::FunctionCall(FunctionCall {
+                name,
+                args,
+                location: name_location,
+            }),
         }));
 
         Ok(Some(funcall))
     }
 
+    /// Parses `.name(args)` after `value`, which is another way to write
+    /// `name(value, args)` so that calls can be chained, as in
+    /// `range(10).map(square).sum()`.
+    fn method_call(&mut self, value: AstNodeId) -> Result<AstNodeId> {
+        self.expect_next(TokenKind::Field)?;
+
+        let (name, name_location) = self.located_ident()?;
+        let mut location = self.arena[value].expr().location + name_location;
+        let mut args = vec![value];
+        args.extend(self.argument_list()?);
+
+        for arg in &args {
+            let node = self.arena[*arg].expr();
+            location += node.location;
+        }
+
+        // Include the closing parenthesis
+        location.span.len += 1;
+
+        Ok(self.arena.alloc(Stmt::Expr(Expr {
+            location,
+            kind: ExprKind::FunctionCall(FunctionCall {
+                name,
+                args,
+                location: name_location,
+            }),
+        })))
+    }
+
     fn argument_list(&mut self) -> Result<ArgList> {
         self.expect_next(TokenKind::OpenParen)?;
 
@@ -417,13 +878,34 @@ impl<'a> Parser<'a> {
                 }
                 TokenKind::Return => {
                     self.next_token();
-                    let expr = self.expression()?;
+                    let expr = match self.peek_operator() {
+                        Some(_) => self.expression()?,
+                        None => None,
+                    };
                     let node = self.arena.alloc(Stmt::Return(Return {
                         value: expr,
                         location,
                     }));
                     return Ok(Some(node));
                 }
+                TokenKind::Break | TokenKind::Continue => {
+                    let (stmt, keyword) = match token.kind {
+                        TokenKind::Break => (Stmt::Break(location), "break"),
+                        _ => (Stmt::Continue(location), "continue"),
+                    };
+                    self.next_token();
+
+                    // Parsing goes on, the statement is only misplaced.
+                    if self.loops == 0 {
+                        self.diagnostics.push(Diagnostic {
+                            severity: Severity::Error,
+                            message: Message::OutsideLoop(keyword),
+                            location,
+                        });
+                    }
+
+                    return Ok(Some(self.arena.alloc(stmt)));
+                }
                 _ => {}
             }
         }
@@ -442,17 +924,25 @@ impl<'a> Parser<'a> {
 
             self.next_token();
 
-            let cond =
-                self.expression()?.expect("Expected expression after if");
+            let cond = self.required(Self::expression)?;
             self.expect_next(TokenKind::OpenBrace)?;
             let body = self.statement_list()?;
             self.expect_next(TokenKind::CloseBrace)?;
 
-            let else_body = match self.peek_token(0) {
-                Some(Token { kind, .. }) if *kind == TokenKind::Else => {
+            // `else if` is an `if` alone in the body of the `else`, so that
+            // a chain of them is handled like any other nesting.
+            let else_body = match self.peek_token_kind(0) {
+                Some(TokenKind::Else) => {
                     self.next_token();
-                    let stmts = self.statement_list()?;
-                    Some(stmts)
+                    match self.nested(Self::if_statement)? {
+                        Some(else_if) => Some(vec![else_if]),
+                        None => {
+                            self.expect_next(TokenKind::OpenBrace)?;
+                            let stmts = self.statement_list()?;
+                            self.expect_next(TokenKind::CloseBrace)?;
+                            Some(stmts)
+                        }
+                    }
                 }
                 _ => None,
             };
@@ -469,41 +959,163 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn block(&mut self) -> Result<Option<AstNodeId>> {
-        trace!("Entered block");
-
-        if let Some(token) = self.peek_token(0) {
-            if token.kind != TokenKind::OpenBrace {
-                return Ok(None);
-            }
+    /// Parses `match value { pattern, ... => statement ... }`. It becomes a
+    /// block that keeps the value in a variable and compares it with the
+    /// patterns of each arm in turn, like a chain of `if`s. `_` matches
+    /// anything and has to come last.
+    fn match_statement(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered match_statement");
 
-            self.next_token();
-            let statements = self.statement_list()?;
-            self.expect_next(TokenKind::CloseBrace)?;
-            let node = self.arena.alloc(Stmt::Block(statements));
-            Ok(Some(node))
-        } else {
-            Ok(None)
+        match self.peek_token_kind(0) {
+            Some(TokenKind::Match) => {}
+            _ => return Ok(None),
         }
-    }
+        self.next_token();
 
-    fn print(&mut self) -> Result<Option<AstNodeId>> {
-        trace!("Entered print");
+        let value = self.required(Self::expression)?;
+        // `#` can't be in an identifier, so no script can use the name.
+        let name = format!("match#{}", self.matches);
+        let name = self.context.interner.intern(&name);
+        self.matches += 1;
 
-        let print = self.context.interner.intern("print");
-        match self.peek_token(0) {
-            Some(Token { kind, .. }) => {
-                if *kind != TokenKind::Ident(print) {
-                    return Ok(None);
-                }
+        let wildcard = self.context.interner.intern("_");
+        let mut arms = vec![];
+        let mut matches_all = false;
+        self.expect_next(TokenKind::OpenBrace)?;
+        while !matches!(
+            self.peek_token_kind(0),
+            Some(TokenKind::CloseBrace) | None
+        ) {
+            // Parsing goes on, the arm can only never be taken.
+            if matches_all {
+                self.report_error(Message::ArmAfterWildcard);
             }
-            _ => return Ok(None),
-        }
 
-        self.next_token();
-        let args = self.argument_list()?;
-        let node = self.arena.alloc(Stmt::Print(Print { args }));
-        Ok(Some(node))
+            let cond = match (self.peek_token_kind(0), self.peek_token_kind(1))
+            {
+                (Some(TokenKind::Ident(ident)), Some(TokenKind::FatArrow))
+                    if *ident == wildcard =>
+                {
+                    self.next_token();
+                    matches_all = true;
+                    None
+                }
+                _ => Some(self.patterns(name)?),
+            };
+            self.expect_next(TokenKind::FatArrow)?;
+
+            match self.statement()? {
+                Some(body) => arms.push((cond, body)),
+                None => {
+                    let message = match self.peek_token_kind(0) {
+                        Some(kind) => Message::ExpectedStatement(kind.clone()),
+                        None => Message::UnexpectedEnd(TokenKind::CloseBrace),
+                    };
+                    self.report_error(message);
+                    return Err(());
+                }
+            }
+        }
+        self.expect_next(TokenKind::CloseBrace)?;
+
+        // Each arm is the `else` of the one before it.
+        let mut chain = vec![];
+        for (cond, body) in arms.into_iter().rev() {
+            chain = match cond {
+                Some(cond) => vec![self.arena.alloc(Stmt::If(If {
+                    cond,
+                    body: vec![body],
+                    else_body: Some(chain).filter(|chain| !chain.is_empty()),
+                }))],
+                None => vec![body],
+            };
+        }
+
+        let location = self.arena[value].expr().location;
+        let decl = self.arena.alloc(Stmt::Decl(Decl::Variable(VarDecl {
+            name,
+            value,
+            kind: ValueKind::Nil,
+            location,
+        })));
+        chain.insert(0, decl);
+        Ok(Some(self.arena.alloc(Stmt::Block(chain))))
+    }
+
+    /// Parses the patterns of a `match` arm, separated by commas, into a
+    /// condition that is true when the variable `name` equals any of them.
+    fn patterns(&mut self, name: Symbol) -> Result<AstNodeId> {
+        let (mut cond, start) = self.pattern(name)?;
+        while self.peek_token_kind(0) == Some(&TokenKind::Comma) {
+            self.next_token();
+            let (equal, end) = self.pattern(name)?;
+            cond = self.arena.alloc(Stmt::Expr(Expr {
+                location: start + end,
+                kind: ExprKind::BinaryOp(BinaryOp {
+                    lhs: cond,
+                    rhs: equal,
+                    op: BinaryOpKind::Or,
+                }),
+            }));
+        }
+
+        Ok(cond)
+    }
+
+    fn pattern(&mut self, name: Symbol) -> Result<(AstNodeId, Location)> {
+        let location = self.location;
+        let pattern = self.required(Self::expression)?;
+        let value = self.arena.alloc(Stmt::Expr(Expr {
+            location,
+            kind: ExprKind::Ident(name),
+        }));
+        let equal = self.arena.alloc(Stmt::Expr(Expr {
+            location,
+            kind: ExprKind::BinaryOp(BinaryOp {
+                lhs: value,
+                rhs: pattern,
+                op: BinaryOpKind::Equal,
+            }),
+        }));
+
+        Ok((equal, location))
+    }
+
+    fn block(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered block");
+
+        if let Some(token) = self.peek_token(0) {
+            if token.kind != TokenKind::OpenBrace {
+                return Ok(None);
+            }
+
+            self.next_token();
+            let statements = self.statement_list()?;
+            self.expect_next(TokenKind::CloseBrace)?;
+            let node = self.arena.alloc(Stmt::Block(statements));
+            Ok(Some(node))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn print(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered print");
+
+        let print = self.context.interner.intern("print");
+        let println = self.context.interner.intern("println");
+        let newline = match self.peek_token(0) {
+            Some(Token {
+                kind: TokenKind::Ident(name),
+                ..
+            }) if *name == print || *name == println => *name == println,
+            _ => return Ok(None),
+        };
+
+        self.next_token();
+        let args = self.argument_list()?;
+        let node = self.arena.alloc(Stmt::Print(Print { args, newline }));
+        Ok(Some(node))
     }
 
     fn assignment(&mut self) -> Result<Option<AstNodeId>> {
@@ -518,7 +1130,7 @@ impl<'a> Parser<'a> {
             return Ok(None);
         };
 
-        let op = match self.peek_token_kind(1) {
+        let op = match self.peek_token_kind(self.skip_indices(1)) {
             Some(TokenKind::Assign) => AssignmentKind::Assign,
             Some(TokenKind::AddAssign) => AssignmentKind::Add,
             Some(TokenKind::SubAssign) => AssignmentKind::Sub,
@@ -530,11 +1142,17 @@ impl<'a> Parser<'a> {
 
         let mut location = self.location;
         let ident = self.ident()?;
+
+        let mut indices = vec![];
+        while let Some(TokenKind::OpenBracket) = self.peek_token_kind(0) {
+            indices.push(self.subscript()?.0);
+        }
+
         self.next_token();
         let value = match self.expression()? {
             Some(expr) => expr,
             None => {
-                self.report_error("Missing expr after assignment");
+                self.report_error(Message::MissingAssignedValue);
                 return Err(());
             }
         };
@@ -542,6 +1160,7 @@ impl<'a> Parser<'a> {
         location += self.arena[value].expr().location;
         let node = self.arena.alloc(Stmt::Assignment(Assignment {
             ident,
+            indices,
             value,
             op,
             location,
@@ -550,73 +1169,93 @@ impl<'a> Parser<'a> {
         Ok(Some(node))
     }
 
-    fn expression(&mut self) -> Result<Option<AstNodeId>> {
-        trace!("Entered expression");
+    /// Returns the position of the first token from `pos` that is not part
+    /// of a run of `[...]` indices.
+    fn skip_indices(&self, mut pos: usize) -> usize {
+        while let Some(TokenKind::OpenBracket) = self.peek_token_kind(pos) {
+            let mut depth = 0;
+            loop {
+                match self.peek_token_kind(pos) {
+                    Some(TokenKind::OpenBracket) => depth += 1,
+                    Some(TokenKind::CloseBracket) => depth -= 1,
+                    Some(_) => {}
+                    None => return pos,
+                }
+                pos += 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+        }
 
-        if let Some(lhs) = self.logical_expr_a()? {
-            if let Some(op) = self.peek_token(0) {
-                let op = match op.kind {
-                    TokenKind::And => BinaryOpKind::And,
-                    _ => {
-                        return Ok(Some(lhs));
-                    }
-                };
+        pos
+    }
 
-                self.next_token();
-                let rhs = self.expression()?.expect("No rhs in expression");
+    fn expression(&mut self) -> Result<Option<AstNodeId>> {
+        self.nested(Self::logical_or)
+    }
 
-                let location = self.arena[lhs].expr().location
-                    + self.arena[rhs].expr().location;
+    fn logical_or(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered expression");
 
-                let expr = self.arena.alloc(Stmt::Expr(Expr {
-                    location,
-                    kind: ExprKind::BinaryOp(BinaryOp { lhs, rhs, op }),
-                }));
+        let mut lhs = match self.logical_expr_a()? {
+            Some(lhs) => lhs,
+            None => return Ok(None),
+        };
 
-                Ok(Some(expr))
-            } else {
-                Ok(Some(lhs))
-            }
-        } else {
-            Ok(None)
+        // `||` binds looser than `&&`, so `a || b && c` is `a || (b && c)`.
+        while let Some(TokenKind::Or) = self.peek_operator() {
+            self.next_token();
+            let rhs = self.required(Self::logical_expr_a)?;
+            let location = self.arena[lhs].expr().location
+                + self.arena[rhs].expr().location;
+
+            lhs = self.arena.alloc(Stmt::Expr(Expr {
+                location,
+                kind: ExprKind::BinaryOp(BinaryOp {
+                    lhs,
+                    rhs,
+                    op: BinaryOpKind::Or,
+                }),
+            }));
         }
+
+        Ok(Some(lhs))
     }
 
     fn logical_expr_a(&mut self) -> Result<Option<AstNodeId>> {
         trace!("Entered expression");
 
-        if let Some(lhs) = self.logical_expr_b()? {
-            if let Some(op) = self.peek_token(0) {
-                let op = match op.kind {
-                    TokenKind::Or => BinaryOpKind::Or,
-                    _ => return Ok(Some(lhs)),
-                };
-
-                self.next_token();
-                let rhs = self.logical_expr_a()?.expect("No rhs in expression");
-                let location = self.arena[lhs].expr().location
-                    + self.arena[rhs].expr().location;
-
-                let expr = self.arena.alloc(Stmt::Expr(Expr {
-                    location,
-                    kind: ExprKind::BinaryOp(BinaryOp { lhs, rhs, op }),
-                }));
+        let mut lhs = match self.logical_expr_b()? {
+            Some(lhs) => lhs,
+            None => return Ok(None),
+        };
 
-                Ok(Some(expr))
-            } else {
-                Ok(Some(lhs))
-            }
-        } else {
-            Ok(None)
+        while let Some(TokenKind::And) = self.peek_operator() {
+            self.next_token();
+            let rhs = self.required(Self::logical_expr_b)?;
+            let location = self.arena[lhs].expr().location
+                + self.arena[rhs].expr().location;
+
+            lhs = self.arena.alloc(Stmt::Expr(Expr {
+                location,
+                kind: ExprKind::BinaryOp(BinaryOp {
+                    lhs,
+                    rhs,
+                    op: BinaryOpKind::And,
+                }),
+            }));
         }
+
+        Ok(Some(lhs))
     }
 
     fn logical_expr_b(&mut self) -> Result<Option<AstNodeId>> {
         trace!("Entered expression");
 
         if let Some(lhs) = self.logical_expr_c()? {
-            if let Some(op) = self.peek_token(0) {
-                let op = match op.kind {
+            if let Some(op) = self.peek_operator() {
+                let op = match op {
                     TokenKind::Equal => BinaryOpKind::Equal,
                     TokenKind::Greater => BinaryOpKind::Greater,
                     TokenKind::GreaterEqual => BinaryOpKind::GreaterEqual,
@@ -627,7 +1266,7 @@ impl<'a> Parser<'a> {
                 };
 
                 self.next_token();
-                let rhs = self.logical_expr_b()?.expect("No rhs in expression");
+                let rhs = self.required(Self::logical_expr_b)?;
                 let location = self.arena[lhs].expr().location
                     + self.arena[rhs].expr().location;
 
@@ -649,14 +1288,14 @@ impl<'a> Parser<'a> {
         trace!("Entered expression");
 
         if let Some(lhs) = self.factor()? {
-            if let Some(op) = self.peek_token(0) {
-                let op = match op.kind {
+            if let Some(op) = self.peek_operator() {
+                let op = match op {
                     TokenKind::Field => BinaryOpKind::Field,
                     _ => return Ok(Some(lhs)),
                 };
 
                 self.next_token();
-                let rhs = self.logical_expr_b()?.expect("No rhs in expression");
+                let rhs = self.required(Self::logical_expr_b)?;
                 let location = self.arena[lhs].expr().location
                     + self.arena[rhs].expr().location;
 
@@ -677,62 +1316,136 @@ impl<'a> Parser<'a> {
     fn factor(&mut self) -> Result<Option<AstNodeId>> {
         trace!("Entered factor");
 
-        if let Some(lhs) = self.term()? {
-            if let Some(op) = self.peek_token(0) {
-                let op = match op.kind {
-                    TokenKind::Add => BinaryOpKind::Add,
-                    TokenKind::Sub => BinaryOpKind::Sub,
-                    _ => return Ok(Some(lhs)),
-                };
+        let mut lhs = match self.term()? {
+            Some(lhs) => lhs,
+            None => return Ok(None),
+        };
 
-                self.next_token();
-                let rhs = self.factor()?.expect("No rhs in expression");
-                let location = self.arena[lhs].expr().location
-                    + self.arena[rhs].expr().location;
+        // Loop rather than recurse so that `a - b - c` is parsed as
+        // `(a - b) - c`.
+        while let Some(op) = self.peek_operator() {
+            let op = match op {
+                TokenKind::Add => BinaryOpKind::Add,
+                TokenKind::Sub => BinaryOpKind::Sub,
+                _ => break,
+            };
 
-                let expr = self.arena.alloc(Stmt::Expr(Expr {
-                    location,
-                    kind: ExprKind::BinaryOp(BinaryOp { lhs, rhs, op }),
-                }));
+            self.next_token();
+            let rhs = self.required(Self::term)?;
+            let location = self.arena[lhs].expr().location
+                + self.arena[rhs].expr().location;
 
-                Ok(Some(expr))
-            } else {
-                Ok(Some(lhs))
-            }
-        } else {
-            Ok(None)
+            lhs = self.arena.alloc(Stmt::Expr(Expr {
+                location,
+                kind: ExprKind::BinaryOp(BinaryOp { lhs, rhs, op }),
+            }));
         }
+
+        Ok(Some(lhs))
     }
 
     fn term(&mut self) -> Result<Option<AstNodeId>> {
         trace!("Entered term");
 
-        if let Some(lhs) = self.atom()? {
-            if let Some(op) = self.peek_token(0) {
-                let op = match op.kind {
-                    TokenKind::Mul => BinaryOpKind::Mul,
-                    TokenKind::Div => BinaryOpKind::Div,
-                    TokenKind::Mod => BinaryOpKind::Mod,
-                    _ => return Ok(Some(lhs)),
-                };
+        let mut lhs = match self.unary()? {
+            Some(lhs) => lhs,
+            None => return Ok(None),
+        };
 
-                self.next_token();
-                let rhs = self.term()?.expect("No rhs in expression");
-                let location = self.arena[lhs].expr().location
-                    + self.arena[rhs].expr().location;
+        while let Some(op) = self.peek_operator() {
+            let op = match op {
+                TokenKind::Mul => BinaryOpKind::Mul,
+                TokenKind::Div => BinaryOpKind::Div,
+                TokenKind::Mod => BinaryOpKind::Mod,
+                _ => break,
+            };
 
-                let expr = self.arena.alloc(Stmt::Expr(Expr {
-                    location,
-                    kind: ExprKind::BinaryOp(BinaryOp { lhs, rhs, op }),
-                }));
+            self.next_token();
+            let rhs = self.required(Self::unary)?;
+            let location = self.arena[lhs].expr().location
+                + self.arena[rhs].expr().location;
 
-                Ok(Some(expr))
-            } else {
-                Ok(Some(lhs))
-            }
-        } else {
-            Ok(None)
+            lhs = self.arena.alloc(Stmt::Expr(Expr {
+                location,
+                kind: ExprKind::BinaryOp(BinaryOp { lhs, rhs, op }),
+            }));
         }
+
+        Ok(Some(lhs))
+    }
+
+    /// Parses `-` and `!`, which bind tighter than `*` but looser than
+    /// indexing and calls, so `-xs[0]` is `-(xs[0])`. A `-` directly
+    /// before a number is folded into a negative literal.
+    fn unary(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered unary");
+
+        let (op, mut location) = match self.peek_token(0) {
+            Some(token) => match token.kind {
+                TokenKind::Sub => (UnaryOpKind::Neg, token.location),
+                TokenKind::Not => (UnaryOpKind::Not, token.location),
+                _ => return self.postfix(),
+            },
+            None => return Ok(None),
+        };
+        self.next_token();
+
+        let value = self.required(|parser| parser.nested(Self::unary))?;
+        location += self.arena[value].expr().location;
+
+        let kind = match (op, &self.arena[value].expr().kind) {
+            (UnaryOpKind::Neg, ExprKind::Integer(n)) => ExprKind::Integer(-n),
+            (UnaryOpKind::Neg, ExprKind::Float(n)) => ExprKind::Float(-n),
+            _ => ExprKind::UnaryOp(UnaryOp { value, op }),
+        };
+
+        Ok(Some(self.arena.alloc(Stmt::Expr(Expr { location, kind }))))
+    }
+
+    fn postfix(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered postfix");
+
+        let mut expr = match self.atom()? {
+            Some(expr) => expr,
+            None => return Ok(None),
+        };
+
+        loop {
+            expr = match self.peek_operator() {
+                Some(TokenKind::Try) => {
+                    let token = self.expect_next(TokenKind::Try)?;
+                    let location =
+                        self.arena[expr].expr().location + token.location;
+
+                    self.arena.alloc(Stmt::Expr(Expr {
+                        location,
+                        kind: ExprKind::Try(Try { value: expr }),
+                    }))
+                }
+                Some(TokenKind::Field) => {
+                    match (self.peek_token_kind(1), self.peek_token_kind(2)) {
+                        (
+                            Some(TokenKind::Ident(_)),
+                            Some(TokenKind::OpenParen),
+                        ) => self.method_call(expr)?,
+                        _ => break,
+                    }
+                }
+                Some(TokenKind::OpenBracket) => {
+                    let (index, close) = self.subscript()?;
+                    let location =
+                        self.arena[expr].expr().location + close.location;
+
+                    self.arena.alloc(Stmt::Expr(Expr {
+                        location,
+                        kind: ExprKind::Index(Index { value: expr, index }),
+                    }))
+                }
+                _ => break,
+            };
+        }
+
+        Ok(Some(expr))
     }
 
     fn atom(&mut self) -> Result<Option<AstNodeId>> {
@@ -746,56 +1459,26 @@ impl<'a> Parser<'a> {
                 | TokenKind::Bool(_)
                 | TokenKind::Range(_, _)
                 | TokenKind::Ident(_) => {}
-                TokenKind::Sub => {
-                    let mut location = token.location;
-                    self.expect_next(TokenKind::Sub)?;
-                    let expr = self.atom()?.expect("expected expression");
-                    location.span.len +=
-                        self.arena[expr].expr().location.span.len;
-                    let expr = self.arena.alloc(Stmt::Expr(Expr {
-                        location,
-                        kind: ExprKind::UnaryOp(UnaryOp {
-                            value: expr,
-                            op: UnaryOpKind::Neg,
-                        }),
-                    }));
-
-                    return Ok(Some(expr));
-                }
-                TokenKind::Not => {
-                    let mut location = token.location;
-                    self.expect_next(TokenKind::Sub)?;
-                    let expr = self.expression()?.expect("expected expression");
-                    location.span.len +=
-                        self.arena[expr].expr().location.span.len;
-
-                    let expr = self.arena.alloc(Stmt::Expr(Expr {
-                        location,
-                        kind: ExprKind::UnaryOp(UnaryOp {
-                            value: expr,
-                            op: UnaryOpKind::Not,
-                        }),
-                    }));
-
-                    return Ok(Some(expr));
-                }
                 TokenKind::OpenParen => {
                     self.expect_next(TokenKind::OpenParen)?;
-                    let expr = self.expression();
+                    let expr = self.expression()?;
                     self.expect_next(TokenKind::CloseParen)?;
-                    return expr;
+                    return Ok(expr);
                 }
+                TokenKind::OpenBracket => return self.array(),
+                TokenKind::OpenBrace => return self.map(),
+                TokenKind::InterpolationStart => return self.interpolation(),
                 _ => return Ok(None),
             }
 
-            // if let Some(range) = self.range()? {
-            //     return Ok(Some(range));
-            // }
-
             if let Some(input) = self.input()? {
                 return Ok(Some(input));
             }
 
+            if let Some(variant) = self.enum_variant()? {
+                return Ok(Some(variant));
+            }
+
             if let Some(function) = self.function_call()? {
                 return Ok(Some(function));
             }
@@ -853,23 +1536,206 @@ impl<'a> Parser<'a> {
         Ok(None)
     }
 
+    /// Parses `Enum.Variant`, for an enum registered by the host, as the
+    /// name of the constant that the variant is. `lang.name(args)` is a
+    /// call of the builtin with that name.
+    fn enum_variant(&mut self) -> Result<Option<AstNodeId>> {
+        let (namespace, variant) = match (
+            self.peek_token_kind(0),
+            self.peek_token_kind(1),
+            self.peek_token_kind(2),
+        ) {
+            (
+                Some(TokenKind::Ident(namespace)),
+                Some(TokenKind::Field | TokenKind::PathSep),
+                Some(TokenKind::Ident(variant)),
+            ) if self.context.namespaces.contains(namespace) => {
+                (*namespace, *variant)
+            }
+            _ => return Ok(None),
+        };
+
+        let first = self.next_token().ok_or(())?;
+        self.next_token();
+        let last = self.next_token().ok_or(())?;
+        let name_location = first.location + last.location;
+        let mut location = name_location;
+
+        let name = format!(
+            "{}.{}",
+            self.context.interner.get(namespace),
+            self.context.interner.get(variant)
+        );
+        let name = self.context.interner.intern(&name);
+
+        // Not `peek_token`, since a name at the end is complete.
+        let kind = match self.tokens.front() {
+            Some(Token {
+                kind: TokenKind::OpenParen,
+                line_break: false,
+                ..
+            }) => {
+                let args = self.argument_list()?;
+                for arg in &args {
+                    location += self.arena[*arg].expr().location;
+                }
+                // Include the closing parenthesis
+                location.span.len += 1;
+                ExprKind::FunctionCall(FunctionCall {
+                    name,
+                    args,
+                    location: name_location,
+                })
+            }
+            _ => ExprKind::Ident(name),
+        };
+
+        Ok(Some(self.arena.alloc(Stmt::Expr(Expr { location, kind }))))
+    }
+
+    /// Parses the tokens the lexer makes of a string literal with `${...}`
+    /// in it. Text and expressions take turns, starting and ending with
+    /// text.
+    fn interpolation(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered interpolation");
+
+        let start = self.expect_next(TokenKind::InterpolationStart)?;
+
+        let mut parts = ArgList::new();
+        loop {
+            // The lexer puts text after every expression, so anything else
+            // is left over from the expression before it.
+            let text = match self.next_token() {
+                Some(Token {
+                    kind: TokenKind::String(text),
+                    location,
+                    ..
+                }) => Expr {
+                    location,
+                    kind: ExprKind::StringLiteral(text),
+                },
+                Some(token) => {
+                    self.location = token.location;
+                    self.report_error(Message::ExpectedToken {
+                        expected: TokenKind::CloseBrace,
+                        found: token.kind,
+                    });
+                    return Err(());
+                }
+                None => {
+                    self.report_error(Message::UnexpectedEnd(
+                        TokenKind::InterpolationEnd,
+                    ));
+                    return Err(());
+                }
+            };
+            parts.push(self.arena.alloc(Stmt::Expr(text)));
+
+            if self.peek_token_kind(0) == Some(&TokenKind::InterpolationEnd) {
+                break;
+            }
+            parts.push(self.required(Self::expression)?);
+        }
+
+        let end = self.expect_next(TokenKind::InterpolationEnd)?;
+
+        Ok(Some(self.arena.alloc(Stmt::Expr(Expr {
+            location: start.location + end.location,
+            kind: ExprKind::Interpolation(parts),
+        }))))
+    }
+
+    fn array(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered array");
+
+        let open = self.expect_next(TokenKind::OpenBracket)?;
+
+        let mut elements = ArgList::new();
+        while let Some(expr) = self.expression()? {
+            elements.push(expr);
+
+            match self.peek_token_kind(0) {
+                Some(TokenKind::Comma) => {
+                    self.next_token();
+                }
+                _ => break,
+            }
+        }
+
+        let close = self.expect_next(TokenKind::CloseBracket)?;
+
+        Ok(Some(self.arena.alloc(Stmt::Expr(Expr {
+            location: open.location + close.location,
+            kind: ExprKind::Array(elements),
+        }))))
+    }
+
+    fn map(&mut self) -> Result<Option<AstNodeId>> {
+        trace!("Entered map");
+
+        let open = self.expect_next(TokenKind::OpenBrace)?;
+
+        let mut entries = vec![];
+        while let Some(key) = self.expression()? {
+            self.expect_next(TokenKind::Colon)?;
+            let value = match self.expression()? {
+                Some(value) => value,
+                None => {
+                    self.report_error(Message::ExpectedValue);
+                    return Err(());
+                }
+            };
+            entries.push((key, value));
+
+            match self.peek_token_kind(0) {
+                Some(TokenKind::Comma) => {
+                    self.next_token();
+                }
+                _ => break,
+            }
+        }
+
+        let close = self.expect_next(TokenKind::CloseBrace)?;
+
+        Ok(Some(self.arena.alloc(Stmt::Expr(Expr {
+            location: open.location + close.location,
+            kind: ExprKind::Map(entries),
+        }))))
+    }
+
+    /// Parses `[index]`, returning the index and the closing bracket.
+    fn subscript(&mut self) -> Result<(AstNodeId, Token)> {
+        self.expect_next(TokenKind::OpenBracket)?;
+
+        let index = match self.expression()? {
+            Some(index) => index,
+            None => {
+                self.report_error(Message::ExpectedIndex);
+                return Err(());
+            }
+        };
+
+        let close = self.expect_next(TokenKind::CloseBracket)?;
+        Ok((index, close))
+    }
+
     fn ident(&mut self) -> Result<Symbol> {
+        self.located_ident().map(|(name, _)| name)
+    }
+
+    /// Like `ident`, but also returns where the identifier is.
+    fn located_ident(&mut self) -> Result<(Symbol, Location)> {
         let token = match self.next_token() {
             Some(token) => token,
             None => {
-                self.report_error(
-                    "Tried to get an identifier when no tokens are left",
-                );
+                self.report_error(Message::ExpectedIdentifier(None));
                 return Err(());
             }
         };
         match token.kind {
-            TokenKind::Ident(n) => Ok(n),
+            TokenKind::Ident(n) => Ok((n, token.location)),
             kind => {
-                self.report_error(&format!(
-                    "Expected identifier, found {:?}",
-                    kind
-                ));
+                self.report_error(Message::ExpectedIdentifier(Some(kind)));
                 Err(())
             }
         }
@@ -888,7 +1754,7 @@ impl<'a> Parser<'a> {
             _ => return Ok(None),
         }
 
-        let token = self.next_token().unwrap();
+        let token = self.expect_next(TokenKind::Ident(input))?;
         let mut location = token.location;
         let message = self.expression()?;
 
@@ -909,6 +1775,7 @@ mod tests {
 
     use std::collections::VecDeque;
 
+    use crate::lexer;
     use crate::location::{Location, Span};
 
     fn token(kind: TokenKind) -> Token {
@@ -919,6 +1786,7 @@ mod tests {
                 line: 0,
                 span: Span { start: 0, len: 0 },
             },
+            line_break: false,
         }
     }
 
@@ -940,4 +1808,477 @@ mod tests {
         let result = result.unwrap();
         assert_eq!(result.statements.len(), 1);
     }
+
+    #[test]
+    fn test_try_operator() {
+        let mut context = Context::new();
+        let mut tokens = VecDeque::new();
+        tokens.push_back(token(TokenKind::Ident(
+            context.interner.intern("parse"),
+        )));
+        tokens.push_back(token(TokenKind::OpenParen));
+        tokens.push_back(token(TokenKind::CloseParen));
+        tokens.push_back(token(TokenKind::Try));
+        tokens.push_back(token(TokenKind::Add));
+        tokens.push_back(token(TokenKind::Integer(1)));
+
+        let result = parse_ast(tokens, &mut context);
+        assert!(result.is_ok());
+
+        let result = result.unwrap();
+        assert_eq!(result.statements.len(), 1);
+
+        let binop = match &result.arena[result.statements[0]].expr().kind {
+            ExprKind::BinaryOp(binop) => binop.clone(),
+            other => panic!("Expected binary op, found {:?}", other),
+        };
+
+        match &result.arena[binop.lhs].expr().kind {
+            ExprKind::Try(_) => {}
+            other => panic!("Expected try, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_division_is_left_associative() {
+        let mut context = Context::new();
+        let mut tokens = VecDeque::new();
+        tokens.push_back(token(TokenKind::Integer(8)));
+        tokens.push_back(token(TokenKind::Div));
+        tokens.push_back(token(TokenKind::Integer(4)));
+        tokens.push_back(token(TokenKind::Div));
+        tokens.push_back(token(TokenKind::Integer(2)));
+
+        let result = parse_ast(tokens, &mut context).unwrap();
+        assert_eq!(result.statements.len(), 1);
+
+        let binop = match &result.arena[result.statements[0]].expr().kind {
+            ExprKind::BinaryOp(binop) => binop.clone(),
+            other => panic!("Expected binary op, found {:?}", other),
+        };
+
+        match &result.arena[binop.lhs].expr().kind {
+            ExprKind::BinaryOp(_) => {}
+            other => panic!("Expected binary op, found {:?}", other),
+        }
+        match &result.arena[binop.rhs].expr().kind {
+            ExprKind::Integer(2) => {}
+            other => panic!("Expected 2, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_and_binds_tighter_than_or() {
+        let mut context = Context::new();
+        let mut tokens = VecDeque::new();
+        tokens.push_back(token(TokenKind::Not));
+        tokens.push_back(token(TokenKind::Bool(true)));
+        tokens.push_back(token(TokenKind::Or));
+        tokens.push_back(token(TokenKind::Bool(false)));
+        tokens.push_back(token(TokenKind::And));
+        tokens.push_back(token(TokenKind::Bool(true)));
+
+        let result = parse_ast(tokens, &mut context).unwrap();
+        assert_eq!(result.statements.len(), 1);
+
+        let binop = match &result.arena[result.statements[0]].expr().kind {
+            ExprKind::BinaryOp(binop) => binop.clone(),
+            other => panic!("Expected binary op, found {:?}", other),
+        };
+
+        match binop.op {
+            BinaryOpKind::Or => {}
+            other => panic!("Expected ||, found {:?}", other),
+        }
+        match &result.arena[binop.lhs].expr().kind {
+            ExprKind::UnaryOp(UnaryOp {
+                op: UnaryOpKind::Not,
+                ..
+            }) => {}
+            other => panic!("Expected !, found {:?}", other),
+        }
+        match &result.arena[binop.rhs].expr().kind {
+            ExprKind::BinaryOp(BinaryOp {
+                op: BinaryOpKind::And,
+                ..
+            }) => {}
+            other => panic!("Expected &&, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_index_assignment() {
+        let mut context = Context::new();
+        let source = "xs[i + 1][0] += xs[0]";
+        context
+            .source_code
+            .insert("test.bl".into(), source.to_string());
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+
+        let result = parse_ast(tokens, &mut context).unwrap();
+        assert_eq!(result.statements.len(), 1);
+
+        let assignment = result.arena[result.statements[0]].assignment();
+        assert_eq!(assignment.indices.len(), 2);
+        assert_eq!(assignment.op, AssignmentKind::Add);
+        match &result.arena[assignment.value].expr().kind {
+            ExprKind::Index(_) => {}
+            other => panic!("Expected index, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unary_precedence() {
+        let mut context = Context::new();
+        let source = "-xs[0] * -2";
+        context
+            .source_code
+            .insert("test.bl".into(), source.to_string());
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+
+        let result = parse_ast(tokens, &mut context).unwrap();
+        let binop = match &result.arena[result.statements[0]].expr().kind {
+            ExprKind::BinaryOp(binop) => binop.clone(),
+            other => panic!("Expected binary op, found {:?}", other),
+        };
+
+        assert_eq!(binop.op, BinaryOpKind::Mul);
+        match &result.arena[binop.lhs].expr().kind {
+            ExprKind::UnaryOp(UnaryOp {
+                op: UnaryOpKind::Neg,
+                value,
+            }) => match &result.arena[*value].expr().kind {
+                ExprKind::Index(_) => {}
+                other => panic!("Expected index, found {:?}", other),
+            },
+            other => panic!("Expected -, found {:?}", other),
+        }
+        match &result.arena[binop.rhs].expr().kind {
+            ExprKind::Integer(-2) => {}
+            other => panic!("Expected -2, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_method_call() {
+        let mut context = Context::new();
+        let source = "xs.map(f).sum()";
+        context
+            .source_code
+            .insert("test.bl".into(), source.to_string());
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+
+        let result = parse_ast(tokens, &mut context).unwrap();
+        let call = |node| match &result.arena[node].expr().kind {
+            ExprKind::FunctionCall(call) => call.clone(),
+            other => panic!("Expected call, found {:?}", other),
+        };
+
+        let sum = call(result.statements[0]);
+        assert_eq!(context.interner.get(sum.name), "sum");
+        assert_eq!(sum.args.len(), 1);
+        let map = call(sum.args[0]);
+        assert_eq!(context.interner.get(map.name), "map");
+        assert_eq!(map.args.len(), 2);
+        assert_eq!(result.arena[sum.args[0]].expr().location.span.len, 9);
+    }
+
+    #[test]
+    fn test_for_unpack() {
+        let mut context = Context::new();
+        let source = "for i, x, y in xs {}";
+        context
+            .source_code
+            .insert("test.bl".into(), source.to_string());
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+
+        let result = parse_ast(tokens, &mut context).unwrap();
+        match &result.arena[result.statements[0]] {
+            Stmt::For(for_loop) => {
+                assert_eq!(context.interner.get(for_loop.ident), "i");
+                let names: Vec<_> = for_loop
+                    .unpack
+                    .iter()
+                    .map(|name| context.interner.get(*name))
+                    .collect();
+                assert_eq!(names, ["x", "y"]);
+            }
+            other => panic!("Expected for loop, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_map_literal_and_block() {
+        let mut context = Context::new();
+        let source = "{ m := {1: {}} }";
+        context
+            .source_code
+            .insert("test.bl".into(), source.to_string());
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+
+        let result = parse_ast(tokens, &mut context).unwrap();
+        let block = match &result.arena[result.statements[0]] {
+            Stmt::Block(block) => block.clone(),
+            other => panic!("Expected block, found {:?}", other),
+        };
+
+        let value = match &result.arena[block[0]] {
+            Stmt::Decl(Decl::Variable(var)) => var.value,
+            other => panic!("Expected declaration, found {:?}", other),
+        };
+        match &result.arena[value].expr().kind {
+            ExprKind::Map(entries) => assert_eq!(entries.len(), 1),
+            other => panic!("Expected map, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let parse = |source: &str| {
+            let mut context = Context::new();
+            context.limits.max_depth = 4;
+            context
+                .source_code
+                .insert("test.bl".into(), source.to_string());
+            let file = context.interner.intern("test.bl");
+            let tokens =
+                lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                    .unwrap();
+            parse_ast(tokens, &mut context)
+        };
+
+        assert!(parse("a := ((1))").is_ok());
+        assert!(parse("a := ((((1))))").is_err());
+        assert!(parse("{ { { a := 1 } } }").is_err());
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let source = "a := (1\n\
+                      fn f() -> int {\n\
+                          b := 2 *\n\
+                          return b\n\
+                      }\n\
+                      c := 3 )\n\
+                      d := 4";
+        let mut context = Context::new();
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+        let mut arena = Arena::new();
+        let (statements, diagnostics) =
+            parse_all(tokens, &mut arena, &mut context);
+
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.location.line, &diagnostic.message))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (
+                    2,
+                    &Message::ExpectedToken {
+                        expected: TokenKind::CloseParen,
+                        found: TokenKind::FunctionDecl,
+                    }
+                ),
+                (4, &Message::ExpectedExpression),
+                (6, &Message::ExpectedStatement(TokenKind::CloseParen)),
+            ]
+        );
+
+        // The function, `c` and `d` are still parsed.
+        assert_eq!(statements.len(), 3);
+    }
+
+    #[test]
+    fn test_loop_control_outside_loop() {
+        let source = "break\n\
+                      while true { fn f() { continue } break }";
+        let mut context = Context::new();
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+        let mut arena = Arena::new();
+        let (statements, diagnostics) =
+            parse_all(tokens, &mut arena, &mut context);
+
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.location.line, &diagnostic.message))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (1, &Message::OutsideLoop("break")),
+                (2, &Message::OutsideLoop("continue")),
+            ]
+        );
+        assert_eq!(statements.len(), 2);
+    }
+
+    #[test]
+    fn test_cfg_attributes() {
+        let source = "@cfg(os = \"plan9\") a := 1\n\
+                      @cfg(all(os = \"dos\", not(feature = \"heaps\"))) b := 2\n\
+                      @cfg(any(os = \"dos\", feature = \"heaps\")) c := 3\n\
+                      @cfg(speed = \"fast\") d := 4\n\
+                      @cfg(os = \"dos\") print(\"%\", 5)\n\
+                      @inline fn f() {}";
+        let mut context = Context::new();
+        context.cfg.push(("os".to_string(), "plan9".to_string()));
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+        let mut arena = Arena::new();
+        let (statements, diagnostics) =
+            parse_all(tokens, &mut arena, &mut context);
+
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.location.line, &diagnostic.message))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (4, &Message::UnknownCfgKey("speed".to_string())),
+                (5, &Message::AttributeWithoutDeclaration),
+                (6, &Message::UnknownAttribute("inline".to_string())),
+            ]
+        );
+
+        let kept: Vec<_> = statements
+            .iter()
+            .map(|&stmt| matches!(arena[stmt], Stmt::Decl(_)))
+            .collect();
+        // `f` is still parsed after the unknown attribute.
+        assert_eq!(kept, [true, false, true, true]);
+    }
+
+    #[test]
+    fn test_match_arms() {
+        let source = "match x {\n\
+                          1, 2 => a := 1\n\
+                          _ => {}\n\
+                          3 => {}\n\
+                      }";
+        let mut context = Context::new();
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+        let mut arena = Arena::new();
+        let (statements, diagnostics) =
+            parse_all(tokens, &mut arena, &mut context);
+
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.location.line, &diagnostic.message))
+            .collect();
+        assert_eq!(errors, [(4, &Message::ArmAfterWildcard)]);
+
+        // The value is stored first, then compared with `1 || 2`.
+        let block = match &arena[statements[0]] {
+            Stmt::Block(block) => block.clone(),
+            _ => panic!("Match should be a block"),
+        };
+        assert_eq!(block.len(), 2);
+        assert!(matches!(arena[block[0]], Stmt::Decl(Decl::Variable(_))));
+        let cond = match &arena[block[1]] {
+            Stmt::If(If {
+                cond, else_body, ..
+            }) => {
+                assert!(else_body.is_some());
+                *cond
+            }
+            _ => panic!("Arms should be an if"),
+        };
+        assert!(matches!(
+            arena[cond].expr().kind,
+            ExprKind::BinaryOp(BinaryOp {
+                op: BinaryOpKind::Or,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_completeness() {
+        let mut context = Context::new();
+        let file = context.interner.intern("test.bl");
+        let mut check = |source| completeness(source, file, &mut context);
+
+        assert_eq!(check(""), Completeness::Complete);
+        assert_eq!(
+            check("a := 1 + 2\nprint(\"%\", a)"),
+            Completeness::Complete
+        );
+        for source in &[
+            "fn f() {",
+            "xs := [1,",
+            "a := 1 +",
+            "if a > 1",
+            "s := \"abc",
+            "s := \"${a",
+            "/* note",
+        ] {
+            assert_eq!(check(source), Completeness::Incomplete, "{}", source);
+        }
+        for source in
+            &["a := 1 )", "a := (1]", "s := \"abc\n", "a := := 1 {", "$"]
+        {
+            assert_eq!(check(source), Completeness::Invalid, "{}", source);
+        }
+        assert_eq!(context.errors, 0);
+    }
+
+    #[test]
+    fn test_else_if_chain() {
+        let source = "if a { x := 1 } else if b { x := 2 } else { x := 3 }\n\
+                      y := 4";
+        let mut context = Context::new();
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+        let ast = parse_ast(tokens, &mut context).unwrap();
+
+        // The statement after the chain is not part of the last `else`.
+        assert_eq!(ast.statements.len(), 2);
+        let else_body = match &ast.arena[ast.statements[0]] {
+            Stmt::If(If { else_body, .. }) => else_body.clone().unwrap(),
+            _ => panic!("Expected an if"),
+        };
+        assert_eq!(else_body.len(), 1);
+        match &ast.arena[else_body[0]] {
+            Stmt::If(If { else_body, .. }) => {
+                assert_eq!(else_body.as_ref().map(Vec::len), Some(1))
+            }
+            _ => panic!("Expected else if"),
+        }
+
+        let tokens =
+            lexer::generate_tokens(b"if a {} else x := 1", file, &mut context)
+                .unwrap();
+        assert!(parse_ast(tokens, &mut context).is_err());
+    }
 }
@@ -0,0 +1,274 @@
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::common::{Grants, Limits, Symbol};
+use crate::engine::{Engine, Error, NativeFunction};
+use crate::interrupt::Handlers;
+use crate::messages::Language;
+use crate::primitives::Value;
+
+/// Engines set up ahead of time and lent out one request at a time, so that
+/// registering host functions and evaluating a prelude happens once per
+/// engine rather than once per request.
+///
+/// An engine is reset to how `make` left it when it is returned, so nothing
+/// a request declares, registers or grants is seen by the next one, and the
+/// processes, sockets and temporary files it left open are closed. Engines can't be sent between
+/// threads, so a server keeps one pool per thread.
+pub struct EnginePool {
+    make: Box<dyn Fn() -> Result<Engine, Error>>,
+    idle: RefCell<Vec<(Engine, Baseline)>>,
+    capacity: usize,
+}
+
+/// The settings of an engine's context when `make` returned it, which
+/// scripts and the host can change while it is lent out.
+struct Baseline {
+    strict_mode: bool,
+    limits: Limits,
+    language: Language,
+    grants: Grants,
+    prompt: bool,
+    native_functions: HashMap<Symbol, Rc<NativeFunction>>,
+    constants: HashMap<Symbol, Value>,
+    namespaces: HashSet<Symbol>,
+    cfg: Vec<(String, String)>,
+    handlers: Handlers,
+}
+
+impl Baseline {
+    fn save(engine: &mut Engine) -> Self {
+        let context = engine.context();
+        Baseline {
+            strict_mode: context.strict_mode,
+            limits: context.limits,
+            language: context.language,
+            grants: context.grants.clone(),
+            prompt: context.prompt,
+            native_functions: context.native_functions.clone(),
+            constants: context.constants.clone(),
+            namespaces: context.namespaces.clone(),
+            cfg: context.cfg.clone(),
+            handlers: context.handlers.borrow().clone(),
+        }
+    }
+
+    fn restore(&self, engine: &mut Engine) {
+        let context = engine.context();
+        context.release();
+        context.strict_mode = self.strict_mode;
+        context.limits = self.limits;
+        context.language = self.language;
+        context.grants = self.grants.clone();
+        context.prompt = self.prompt;
+        context.native_functions = self.native_functions.clone();
+        context.constants = self.constants.clone();
+        context.namespaces = self.namespaces.clone();
+        context.cfg = self.cfg.clone();
+        // Shared with `on_exit` and `on_signal`, so it is changed in place.
+        *context.handlers.borrow_mut() = self.handlers.clone();
+        context.collected = None;
+    }
+}
+
+impl EnginePool {
+    /// Makes a pool with `capacity` engines made by `make`. More are made
+    /// when all of them are lent out, but only `capacity` are kept.
+    pub fn new(
+        capacity: usize,
+        make: impl Fn() -> Result<Engine, Error> + 'static,
+    ) -> Result<Self, Error> {
+        let pool = EnginePool {
+            make: Box::new(make),
+            idle: RefCell::new(Vec::with_capacity(capacity)),
+            capacity,
+        };
+
+        for _ in 0..capacity {
+            let made = pool.make()?;
+            pool.idle.borrow_mut().push(made);
+        }
+
+        Ok(pool)
+    }
+
+    /// Lends out an idle engine, or a new one if there is none.
+    pub fn get(&self) -> Result<PooledEngine<'_>, Error> {
+        let idle = self.idle.borrow_mut().pop();
+        let (engine, baseline) = match idle {
+            Some(idle) => idle,
+            None => self.make()?,
+        };
+
+        Ok(PooledEngine {
+            engine: Some(engine),
+            baseline: Some(baseline),
+            pool: self,
+        })
+    }
+
+    /// Number of engines ready to be lent out.
+    pub fn idle(&self) -> usize {
+        self.idle.borrow().len()
+    }
+
+    /// Makes an engine and saves a checkpoint and baseline to reset it to.
+    fn make(&self) -> Result<(Engine, Baseline), Error> {
+        let mut engine = (self.make)()?;
+        engine.checkpoint();
+        let baseline = Baseline::save(&mut engine);
+        Ok((engine, baseline))
+    }
+
+    fn give_back(&self, mut engine: Engine, baseline: Baseline) {
+        while engine.rollback() {}
+        engine.checkpoint();
+        baseline.restore(&mut engine);
+
+        let mut idle = self.idle.borrow_mut();
+        if idle.len() < self.capacity {
+            idle.push((engine, baseline));
+        }
+    }
+}
+
+/// An engine lent out by an `EnginePool`, given back when dropped.
+pub struct PooledEngine<'a> {
+    engine: Option<Engine>,
+    baseline: Option<Baseline>,
+    pool: &'a EnginePool,
+}
+
+impl<'a> Deref for PooledEngine<'a> {
+    type Target = Engine;
+
+    fn deref(&self) -> &Engine {
+        self.engine
+            .as_ref()
+            .expect("Engine given back while lent out")
+    }
+}
+
+impl<'a> DerefMut for PooledEngine<'a> {
+    fn deref_mut(&mut self) -> &mut Engine {
+        self.engine
+            .as_mut()
+            .expect("Engine given back while lent out")
+    }
+}
+
+impl<'a> Drop for PooledEngine<'a> {
+    fn drop(&mut self) {
+        if let (Some(engine), Some(baseline)) =
+            (self.engine.take(), self.baseline.take())
+        {
+            self.pool.give_back(engine, baseline);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crate::primitives::{Value, ValueKind};
+
+    fn make() -> Result<Engine, Error> {
+        let mut engine = Engine::new();
+        engine.register_fn("two", &[], Some(ValueKind::Integer), |_, _| {
+            Value::Int(2)
+        });
+        engine.eval_str("fn double(n: int) -> int { return n * two() }")?;
+        Ok(engine)
+    }
+
+    #[test]
+    fn requests_are_isolated() {
+        let pool = EnginePool::new(1, make).unwrap();
+
+        {
+            let mut engine = pool.get().unwrap();
+            assert_eq!(
+                engine.eval_str("a := double(3) a"),
+                Ok(Some(Value::Int(6)))
+            );
+            engine.checkpoint();
+            assert!(engine.eval_str("b := 1").is_ok());
+        }
+        assert_eq!(pool.idle(), 1);
+
+        let mut engine = pool.get().unwrap();
+        assert!(engine.eval_str("a").is_err());
+        assert!(engine.eval_str("b").is_err());
+        assert_eq!(engine.eval_str("double(5)"), Ok(Some(Value::Int(10))));
+    }
+
+    #[test]
+    fn tenants_do_not_share_settings() {
+        let pool = EnginePool::new(1, || {
+            let mut engine = make()?;
+            engine.context().grants = Grants::Only(vec![]);
+            Ok(engine)
+        })
+        .unwrap();
+
+        let temp = {
+            let mut engine = pool.get().unwrap();
+            engine.context().grants.grant("fs");
+            engine.set_global("secret", 7);
+            engine.register_fn(
+                "three",
+                &[],
+                Some(ValueKind::Integer),
+                |_, _| Value::Int(3),
+            );
+            let temp = engine.eval_str("fn f() {} on_exit(f) temp_file()");
+            assert!(!engine.context().handlers.borrow().exit.is_empty());
+            match temp {
+                Ok(Some(Value::Ok(path))) => match *path {
+                    Value::String(path) => {
+                        engine.context().interner.get(path).to_string()
+                    }
+                    other => panic!("Expected a path, found {:?}", other),
+                },
+                other => panic!("Expected a temporary file, found {:?}", other),
+            }
+        };
+        assert!(!std::path::Path::new(&temp).exists());
+
+        let mut engine = pool.get().unwrap();
+        assert!(engine.context().handlers.borrow().exit.is_empty());
+        assert!(!engine.context().grants.allows("fs"));
+        assert!(engine.eval_str("secret").is_err());
+        assert!(engine.eval_str("three()").is_err());
+        assert_eq!(engine.eval_str("double(5)"), Ok(Some(Value::Int(10))));
+    }
+
+    #[test]
+    fn makes_engines_when_empty() {
+        let made = Rc::new(Cell::new(0));
+        let counter = made.clone();
+        let pool = EnginePool::new(1, move || {
+            counter.set(counter.get() + 1);
+            make()
+        })
+        .unwrap();
+
+        let first = pool.get().unwrap();
+        let second = pool.get().unwrap();
+        assert_eq!((made.get(), pool.idle()), (2, 0));
+
+        drop(first);
+        drop(second);
+        assert_eq!(pool.idle(), 1);
+
+        let failing = || Engine::new().eval_str("x").map(|_| Engine::new());
+        assert!(EnginePool::new(2, failing).is_err());
+    }
+}
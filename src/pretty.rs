@@ -0,0 +1,536 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::ast::{Ast, AstNodeId, Decl, ExprKind, ForIter, Stmt};
+use crate::common::{StringInterner, Symbol};
+use crate::compiler::{Op, Place, Program};
+use crate::primitives::{Value, ValueKind};
+use crate::token::{Token, TokenKind};
+
+/// The tokens of a file, one per line with where they start.
+pub struct Tokens<'a> {
+    pub tokens: &'a VecDeque<Token>,
+    pub source: &'a [u8],
+    pub interner: &'a StringInterner,
+}
+
+impl fmt::Display for Tokens<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for token in self.tokens {
+            let position = format!(
+                "{}:{}",
+                token.location.line,
+                token.location.column(self.source)
+            );
+            write!(f, "{:<8}", position)?;
+
+            match &token.kind {
+                TokenKind::Ident(name) => {
+                    writeln!(f, "Ident {}", self.interner.get(*name))?
+                }
+                TokenKind::String(s) => {
+                    writeln!(f, "String {:?}", self.interner.get(*s))?
+                }
+                kind => writeln!(f, "{:?}", kind)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A syntax tree as an indented outline, with one node per line and the
+/// children of a node below it.
+pub struct Tree<'a> {
+    pub ast: &'a Ast,
+    pub interner: &'a StringInterner,
+}
+
+impl fmt::Display for Tree<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for node in outline(self.ast, self.interner) {
+            node.write(f, 0)?;
+        }
+        Ok(())
+    }
+}
+
+/// The bytecode of a program, one function after another with one
+/// instruction per line, after its index and the line of the source it
+/// comes from. Constants and names are shown instead of their numbers.
+pub struct Bytecode<'a> {
+    pub program: &'a Program,
+    pub interner: &'a StringInterner,
+}
+
+impl fmt::Display for Bytecode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, func) in self.program.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(
+                f,
+                "fn {} params {} slots {}",
+                self.interner.get(func.name),
+                func.params,
+                func.slots
+            )?;
+            for (i, (op, location)) in
+                func.code.iter().zip(&func.locations).enumerate()
+            {
+                let line =
+                    location.map_or(String::new(), |l| l.line.to_string());
+                writeln!(f, "{:>6} {:>5}  {}", i, line, self.op(op))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Bytecode<'_> {
+    fn op(&self, op: &Op) -> String {
+        let name = |name: &Symbol| self.interner.get(*name);
+        let place = |place: &Place| match place {
+            Place::Local(slot) => format!("local {}", slot),
+            Place::Global(name) => {
+                format!("global {}", self.interner.get(*name))
+            }
+        };
+
+        match op {
+            Op::Constant(i) => {
+                let constant = match &self.program.constants[*i as usize] {
+                    Value::String(s) => format!("{:?}", self.interner.get(*s)),
+                    value => value.format(self.interner),
+                };
+                format!("Constant {} {}", i, constant)
+            }
+            Op::Load(p) => format!("Load {}", place(p)),
+            Op::Store(p) => format!("Store {}", place(p)),
+            Op::DefineGlobal(n) => format!("DefineGlobal {}", name(n)),
+            Op::DropGlobal(n) => format!("DropGlobal {}", name(n)),
+            Op::Assign {
+                place: p,
+                indices,
+                op,
+            } => {
+                let op = op.map_or(String::new(), |op| format!(" {:?}", op));
+                format!("Assign {} indices {}{}", place(p), indices, op)
+            }
+            Op::ChangeArray {
+                builtin,
+                place: p,
+                indices,
+                args,
+            } => format!(
+                "ChangeArray {:?} {} indices {} args {}",
+                builtin,
+                place(p),
+                indices,
+                args
+            ),
+            Op::Call { func, args } => {
+                let func = &self.program.functions[*func as usize];
+                format!("Call {} args {}", name(&func.name), args)
+            }
+            Op::CallNative { name: n, args } => {
+                format!("CallNative {} args {}", name(n), args)
+            }
+            op => format!("{:?}", op),
+        }
+    }
+}
+
+/// A type as scripts write it, like `[string]` or `{string: int}`.
+pub struct Type<'a> {
+    pub kind: &'a ValueKind,
+    pub interner: &'a StringInterner,
+}
+
+impl Type<'_> {
+    /// Whether the whole type was inferred. Values the typechecker can't
+    /// follow, like what `eval` returns, have `Nil` somewhere in their
+    /// type, except as the return type of a function that returns nothing.
+    pub fn is_known(&self) -> bool {
+        fn known(kind: &ValueKind) -> bool {
+            match kind {
+                ValueKind::Nil => false,
+                ValueKind::Array(element)
+                | ValueKind::Iter(element)
+                | ValueKind::Heap(element)
+                | ValueKind::Deque(element) => known(element),
+                ValueKind::Map(key, value) => known(key) && known(value),
+                ValueKind::Function(params, ret) => {
+                    params.iter().all(known)
+                        && (**ret == ValueKind::Nil || known(ret))
+                }
+                ValueKind::Tuple(kinds) => kinds.iter().all(known),
+                _ => true,
+            }
+        }
+        known(self.kind)
+    }
+
+    fn nested<'b>(&'b self, kind: &'b ValueKind) -> Type<'b> {
+        Type {
+            kind,
+            interner: self.interner,
+        }
+    }
+}
+
+impl fmt::Display for Type<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ValueKind::Bool => write!(f, "bool"),
+            ValueKind::String => write!(f, "string"),
+            ValueKind::Integer => write!(f, "int"),
+            ValueKind::Float => write!(f, "float"),
+            ValueKind::Result => write!(f, "result"),
+            ValueKind::Nil => write!(f, "nil"),
+            ValueKind::Struct(name) => {
+                write!(f, "{}", self.interner.get(*name))
+            }
+            ValueKind::Array(element) => {
+                write!(f, "[{}]", self.nested(element))
+            }
+            ValueKind::Map(key, value) => {
+                write!(f, "{{{}: {}}}", self.nested(key), self.nested(value))
+            }
+            ValueKind::Iter(element) => {
+                write!(f, "iter[{}]", self.nested(element))
+            }
+            ValueKind::Heap(element) => {
+                write!(f, "heap[{}]", self.nested(element))
+            }
+            ValueKind::Deque(element) => {
+                write!(f, "deque[{}]", self.nested(element))
+            }
+            ValueKind::Tuple(kinds) => {
+                let kinds: Vec<_> =
+                    kinds.iter().map(|k| self.nested(k).to_string()).collect();
+                write!(f, "({})", kinds.join(", "))
+            }
+            ValueKind::Function(params, ret) => {
+                let params: Vec<_> =
+                    params.iter().map(|k| self.nested(k).to_string()).collect();
+                write!(f, "fn({})", params.join(", "))?;
+                if **ret != ValueKind::Nil {
+                    write!(f, " -> {}", self.nested(ret))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A node of the outline of a syntax tree: what kind of node it is, like
+/// `BinaryOp`, the details that set it apart, like `Mul`, and the nodes
+/// below it.
+pub struct Outline {
+    pub kind: &'static str,
+    pub text: String,
+    pub children: Vec<Outline>,
+}
+
+impl Outline {
+    fn new(kind: &'static str, text: String) -> Self {
+        Outline {
+            kind,
+            text,
+            children: vec![],
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        write!(f, "{}{}", "  ".repeat(depth), self.kind)?;
+        if !self.text.is_empty() {
+            write!(f, " {}", self.text)?;
+        }
+        writeln!(f)?;
+        for child in &self.children {
+            child.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// The outline of the top level statements of `ast`.
+pub fn outline(ast: &Ast, interner: &StringInterner) -> Vec<Outline> {
+    let outliner = Outliner { ast, interner };
+    outliner.list(&ast.statements)
+}
+
+struct Outliner<'a> {
+    ast: &'a Ast,
+    interner: &'a StringInterner,
+}
+
+impl Outliner<'_> {
+    fn name(&self, name: Symbol) -> &str {
+        self.interner.get(name)
+    }
+
+    fn list(&self, stmts: &[AstNodeId]) -> Vec<Outline> {
+        stmts.iter().map(|stmt| self.node(*stmt)).collect()
+    }
+
+    /// A node of `kind` with `children` below it.
+    fn group(
+        &self,
+        kind: &'static str,
+        text: String,
+        children: &[AstNodeId],
+    ) -> Outline {
+        Outline {
+            kind,
+            text,
+            children: self.list(children),
+        }
+    }
+
+    fn node(&self, id: AstNodeId) -> Outline {
+        let ast = self.ast;
+
+        match &ast.arena[id] {
+            Stmt::Assignment(assignment) => {
+                let text = format!(
+                    "{} {:?}",
+                    self.name(assignment.ident),
+                    assignment.op
+                );
+                let mut children = assignment.indices.clone();
+                children.push(assignment.value);
+                self.group("Assignment", text, &children)
+            }
+            Stmt::Block(stmts) => self.group("Block", String::new(), stmts),
+            Stmt::Decl(Decl::Variable(var)) => {
+                let text = match &var.kind {
+                    ValueKind::Nil => self.name(var.name).to_string(),
+                    kind => format!("{}: {:?}", self.name(var.name), kind),
+                };
+                self.group("VarDecl", text, &[var.value])
+            }
+            Stmt::Decl(Decl::Function(func)) => {
+                let params: Vec<_> = func
+                    .params
+                    .iter()
+                    .map(|param| {
+                        let param = ast.arena[*param].param();
+                        format!("{}: {:?}", self.name(param.name), param.kind)
+                    })
+                    .collect();
+                let mut text =
+                    format!("{}({})", self.name(func.name), params.join(", "));
+                if let Some(kind) = &func.return_type {
+                    text += &format!(" -> {:?}", kind);
+                }
+                self.group("FunctionDecl", text, &func.body)
+            }
+            Stmt::Decl(Decl::Struct(decl)) => {
+                let text = self.name(decl.name).to_string();
+                self.group("StructDecl", text, &decl.fields)
+            }
+            Stmt::Param(param) => Outline::new(
+                "Param",
+                format!("{}: {:?}", self.name(param.name), param.kind),
+            ),
+            Stmt::For(for_loop) => {
+                let mut text = self.name(for_loop.ident).to_string();
+                for name in &for_loop.unpack {
+                    text += &format!(", {}", self.name(*name));
+                }
+                let iter = match &for_loop.iter {
+                    ForIter::Range(range) => Outline::new(
+                        "Range",
+                        format!("{}..{}", range.start, range.end),
+                    ),
+                    ForIter::Expr(expr) => self.node(*expr),
+                };
+                let block = self.group("Do", String::new(), &for_loop.block);
+                Outline {
+                    kind: "For",
+                    text,
+                    children: vec![iter, block],
+                }
+            }
+            Stmt::While(while_loop) => Outline {
+                kind: "While",
+                text: String::new(),
+                children: vec![
+                    self.node(while_loop.cond),
+                    self.group("Do", String::new(), &while_loop.body),
+                ],
+            },
+            Stmt::If(if_stmt) => {
+                let mut children = vec![
+                    self.node(if_stmt.cond),
+                    self.group("Then", String::new(), &if_stmt.body),
+                ];
+                if let Some(else_body) = &if_stmt.else_body {
+                    children.push(self.group("Else", String::new(), else_body));
+                }
+                Outline {
+                    kind: "If",
+                    text: String::new(),
+                    children,
+                }
+            }
+            Stmt::Print(print) => {
+                let kind = if print.newline { "Println" } else { "Print" };
+                self.group(kind, String::new(), &print.args)
+            }
+            Stmt::Return(ret) => {
+                let value: Vec<_> = ret.value.into_iter().collect();
+                self.group("Return", String::new(), &value)
+            }
+            Stmt::Break(_) => Outline::new("Break", String::new()),
+            Stmt::Continue(_) => Outline::new("Continue", String::new()),
+            Stmt::Expr(expr) => self.expr(&expr.kind),
+        }
+    }
+
+    fn expr(&self, kind: &ExprKind) -> Outline {
+        match kind {
+            ExprKind::Float(n) => Outline::new("Float", n.to_string()),
+            ExprKind::Integer(n) => Outline::new("Integer", n.to_string()),
+            ExprKind::StringLiteral(s) => {
+                Outline::new("String", format!("{:?}", self.name(*s)))
+            }
+            ExprKind::Interpolation(parts) => {
+                self.group("Interpolation", String::new(), parts)
+            }
+            ExprKind::Ident(name) => {
+                Outline::new("Ident", self.name(*name).to_string())
+            }
+            ExprKind::Range(range) => {
+                Outline::new("Range", format!("{}..{}", range.start, range.end))
+            }
+            ExprKind::Bool(b) => Outline::new("Bool", b.to_string()),
+            ExprKind::Input(input) => {
+                let message: Vec<_> = input.message.into_iter().collect();
+                self.group("Input", String::new(), &message)
+            }
+            ExprKind::UnaryOp(op) => {
+                self.group("UnaryOp", format!("{:?}", op.op), &[op.value])
+            }
+            ExprKind::BinaryOp(op) => {
+                let text = format!("{:?}", op.op);
+                self.group("BinaryOp", text, &[op.lhs, op.rhs])
+            }
+            ExprKind::FunctionCall(call) => {
+                let text = self.name(call.name).to_string();
+                self.group("Call", text, &call.args)
+            }
+            ExprKind::Try(try_op) => {
+                self.group("Try", String::new(), &[try_op.value])
+            }
+            ExprKind::Array(elements) => {
+                self.group("Array", String::new(), elements)
+            }
+            ExprKind::Map(pairs) => Outline {
+                kind: "Map",
+                text: String::new(),
+                children: pairs
+                    .iter()
+                    .map(|(key, value)| {
+                        self.group("Entry", String::new(), &[*key, *value])
+                    })
+                    .collect(),
+            },
+            ExprKind::Index(index) => {
+                self.group("Index", String::new(), &[index.value, index.index])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::Context;
+    use crate::compiler;
+    use crate::lexer;
+    use crate::parser;
+
+    #[test]
+    fn tokens_and_tree() {
+        let mut context = Context::new();
+        let source = "fn f(n: int) -> int {\n  return -n * 2\n}\n\
+                      for x in [1] { if f(x) > 0 { print(\"a\") } }";
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+
+        let dump = Tokens {
+            tokens: &tokens,
+            source: source.as_bytes(),
+            interner: &context.interner,
+        }
+        .to_string();
+        let lines: Vec<_> = dump.lines().take(3).collect();
+        assert_eq!(
+            lines,
+            [
+                "1:1     FunctionDecl",
+                "1:4     Ident f",
+                "1:5     OpenParen"
+            ]
+        );
+
+        let ast = parser::parse_ast(tokens, &mut context).unwrap();
+        let tree = Tree {
+            ast: &ast,
+            interner: &context.interner,
+        };
+        assert_eq!(
+            tree.to_string(),
+            "FunctionDecl f(n: Integer) -> Integer\n\
+             \x20 Return\n\
+             \x20   BinaryOp Mul\n\
+             \x20     UnaryOp Neg\n\
+             \x20       Ident n\n\
+             \x20     Integer 2\n\
+             For x\n\
+             \x20 Array\n\
+             \x20   Integer 1\n\
+             \x20 Do\n\
+             \x20   If\n\
+             \x20     BinaryOp Greater\n\
+             \x20       Call f\n\
+             \x20         Ident x\n\
+             \x20       Integer 0\n\
+             \x20     Then\n\
+             \x20       Print\n\
+             \x20         String \"a\"\n"
+        );
+    }
+
+    #[test]
+    fn bytecode() {
+        let mut context = Context::new();
+        let source = "fn f(n: int) -> int {\n  return n + 1\n}\n\
+                      a := \"x\"\nprintln(\"%\", f(2))";
+        let file = context.interner.intern("test.bl");
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+        let ast = parser::parse_ast(tokens, &mut context).unwrap();
+        let program = compiler::compile(&ast, &mut context);
+
+        let dump = Bytecode {
+            program: &program,
+            interner: &context.interner,
+        }
+        .to_string();
+        let lines: Vec<_> = dump.lines().collect();
+        assert_eq!(lines[0], "fn <main> params 0 slots 0");
+        assert_eq!(lines[1], "     0     4  Constant 1 \"x\"");
+        assert_eq!(lines[2], "     1        DefineGlobal a");
+        assert!(dump.contains("Call f args 1"));
+        assert!(dump.contains("\nfn f params 1 slots 1\n"));
+    }
+}
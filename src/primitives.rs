@@ -3,36 +3,392 @@ use std::ops::{
     SubAssign,
 };
 
-use crate::common::Symbol;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::rc::Rc;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+use hashbrown::HashMap;
+
+use crate::arena::Id;
+use crate::common::{StringInterner, Symbol};
+use crate::messages::Message;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ValueKind {
     Bool,
     String,
     Integer,
     Float,
     Struct(Symbol),
+    Array(Box<ValueKind>),
+    /// Key and value types.
+    Map(Box<ValueKind>, Box<ValueKind>),
+    /// Parameter types and return type, `Nil` if it returns nothing.
+    Function(Vec<ValueKind>, Box<ValueKind>),
+    /// Element type.
+    Iter(Box<ValueKind>),
+    Heap(Box<ValueKind>),
+    Deque(Box<ValueKind>),
+    /// An array of fixed length with a type for each element, like the
+    /// pairs `zip` and `enumerate` give.
+    Tuple(Vec<ValueKind>),
+    Result,
     Nil,
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug)]
 pub enum Value {
     Bool(bool),
     Int(i32),
-    Float(f32),
+    Float(f64),
     String(Symbol),
     Struct(Symbol),
+    /// Copied when changed while shared, so arrays behave like any other
+    /// value when assigned or passed to a function.
+    Array(Rc<Vec<Value>>),
+    Map(Rc<Map>),
+    /// A binary heap with the smallest value first, copied when changed
+    /// while shared like arrays.
+    Heap(Rc<Vec<Value>>),
+    Deque(Rc<VecDeque<Value>>),
+    Function(Function),
+    Iter(Rc<Iter>),
+    Ok(Box<Value>),
+    Err(Box<Value>),
     Nil,
 }
 
+/// A function used as a value, like `double` in `map(xs, double)`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: Symbol,
+    pub callee: Callee,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Callee {
+    /// A function in the script, as the interpreter knows it.
+    Node(Id),
+    /// A function in the script, as its index in the compiled program.
+    Compiled(u32),
+    /// A function registered by the host or the builtins.
+    Native,
+    /// `partial(func, args...)`, which calls `func` with the arguments
+    /// before its own.
+    Partial(Rc<(Function, Vec<Value>)>),
+    /// `compose(f, g)`, which calls `g` with what `f` returns.
+    Compose(Rc<(Function, Function)>),
+}
+
+impl Function {
+    /// The name of the function, or how it was built from others.
+    pub fn describe(&self, interner: &StringInterner) -> String {
+        match &self.callee {
+            Callee::Partial(partial) => {
+                let (func, args) = &**partial;
+                let args: Vec<_> =
+                    args.iter().map(|arg| arg.format(interner)).collect();
+                format!(
+                    "partial({}, {})",
+                    func.describe(interner),
+                    args.join(", ")
+                )
+            }
+            Callee::Compose(funcs) => format!(
+                "compose({}, {})",
+                funcs.0.describe(interner),
+                funcs.1.describe(interner)
+            ),
+            _ => interner.get(self.name).to_string(),
+        }
+    }
+}
+
+/// A sequence of values that is only produced when something runs it,
+/// like `sum` or a for loop. Mapping or filtering an iterator makes a new
+/// one without calling any function.
+#[derive(Clone, Debug)]
+pub enum Iter {
+    /// The integers from the first up to the second.
+    Range(i32, i32),
+    Values(Rc<Vec<Value>>),
+    Map(Rc<Iter>, Function),
+    Filter(Rc<Iter>, Function),
+    /// At most this many values of the iterator.
+    Take(Rc<Iter>, usize),
+    /// Pairs of the values of both, until either ends.
+    Zip(Rc<Iter>, Rc<Iter>),
+    /// Pairs of the position and the value.
+    Enumerate(Rc<Iter>),
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Value {
+        Value::Int(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Value {
+        Value::Float(value)
+    }
+}
+
+impl PartialEq for Value {
+    /// Ints and floats are compared by their numeric value, all other values
+    /// are only equal to values of the same type.
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Int(a), Value::Float(b))
+            | (Value::Float(b), Value::Int(a)) => f64::from(*a) == *b,
+            // Strings are interned, so equal symbols means equal strings
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Struct(a), Value::Struct(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) => a == b,
+            (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::Heap(a), Value::Heap(b)) => a == b,
+            (Value::Deque(a), Value::Deque(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => a == b,
+            (Value::Iter(a), Value::Iter(b)) => Rc::ptr_eq(a, b),
+            (Value::Ok(a), Value::Ok(b)) => a == b,
+            (Value::Err(a), Value::Err(b)) => a == b,
+            (Value::Nil, Value::Nil) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    /// Whether the value counts as true when used as a condition.
+    ///
+    /// `false`, zero, the empty string, empty arrays and maps, `nil` and
+    /// `Err` results are false, every other value is true.
+    pub fn is_truthy(&self, interner: &StringInterner) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            Value::Int(n) => *n != 0,
+            Value::Float(n) => *n != 0.0,
+            Value::String(s) => !interner.get(*s).is_empty(),
+            Value::Array(a) => !a.is_empty(),
+            Value::Map(m) => !m.is_empty(),
+            Value::Heap(h) => !h.is_empty(),
+            Value::Deque(d) => !d.is_empty(),
+            Value::Struct(_)
+            | Value::Function(_)
+            | Value::Iter(_)
+            | Value::Ok(_) => true,
+            Value::Err(_) | Value::Nil => false,
+        }
+    }
+
+    /// The text `print` and the REPL show for the value.
+    pub fn format(&self, interner: &StringInterner) -> String {
+        match self {
+            Value::Bool(v) => v.to_string(),
+            Value::Int(v) => v.to_string(),
+            // Debug keeps the fraction of whole numbers, `2.0` not `2`
+            Value::Float(v) => format!("{:?}", v),
+            Value::String(v) | Value::Struct(v) => interner.get(*v).to_string(),
+            Value::Array(a) => format_list(a.iter(), interner),
+            Value::Map(m) => {
+                let entries: Vec<_> = m
+                    .iter()
+                    .map(|(key, value)| {
+                        format!(
+                            "{}: {}",
+                            key.to_value().format(interner),
+                            value.format(interner)
+                        )
+                    })
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Value::Heap(h) => {
+                let mut values: Vec<_> = h.iter().collect();
+                values.sort_by(|a, b| {
+                    a.compare(b, interner).unwrap_or(Ordering::Equal)
+                });
+                format!("heap{}", format_list(values.into_iter(), interner))
+            }
+            Value::Deque(d) => {
+                format!("deque{}", format_list(d.iter(), interner))
+            }
+            Value::Function(func) => format!("fn {}", func.describe(interner)),
+            Value::Iter(_) => "iter".to_string(),
+            Value::Ok(v) => format!("Ok({})", v.format(interner)),
+            Value::Err(v) => format!("Err({})", v.format(interner)),
+            Value::Nil => "nil".to_string(),
+        }
+    }
+
+    /// Orders numbers numerically, strings lexicographically by their bytes
+    /// and `false` before `true`. Any other combination has no ordering.
+    pub fn compare(
+        &self,
+        other: &Value,
+        interner: &StringInterner,
+    ) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Float(b)) => f64::from(*a).partial_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.partial_cmp(&f64::from(*b)),
+            (Value::String(a), Value::String(b)) => {
+                Some(interner.get(*a).cmp(interner.get(*b)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Formats values the way `print` shows an array of them.
+fn format_list<'v>(
+    values: impl Iterator<Item = &'v Value>,
+    interner: &StringInterner,
+) -> String {
+    let elements: Vec<_> = values.map(|v| v.format(interner)).collect();
+    format!("[{}]", elements.join(", "))
+}
+
+/// The values that can be used as map keys.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Bool(bool),
+    Int(i32),
+    String(Symbol),
+}
+
+impl MapKey {
+    pub fn from_value(value: &Value) -> Option<MapKey> {
+        match value {
+            Value::Bool(b) => Some(MapKey::Bool(*b)),
+            Value::Int(n) => Some(MapKey::Int(*n)),
+            Value::String(s) => Some(MapKey::String(*s)),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(self) -> Value {
+        match self {
+            MapKey::Bool(b) => Value::Bool(b),
+            MapKey::Int(n) => Value::Int(n),
+            MapKey::String(s) => Value::String(s),
+        }
+    }
+}
+
+/// A hash map that remembers the order keys were added in, so that
+/// printing and iterating over it is predictable.
+#[derive(Debug, Clone, Default)]
+pub struct Map {
+    entries: Vec<(MapKey, Value)>,
+    positions: HashMap<MapKey, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, key: MapKey) -> bool {
+        self.positions.contains_key(&key)
+    }
+
+    pub fn get(&self, key: MapKey) -> Option<&Value> {
+        self.positions.get(&key).map(|i| &self.entries[*i].1)
+    }
+
+    pub fn get_mut(&mut self, key: MapKey) -> Option<&mut Value> {
+        match self.positions.get(&key) {
+            Some(i) => Some(&mut self.entries[*i].1),
+            None => None,
+        }
+    }
+
+    /// Replaces the value of `key` if it is already in the map, otherwise
+    /// adds it last.
+    pub fn insert(&mut self, key: MapKey, value: Value) {
+        match self.positions.get(&key) {
+            Some(i) => self.entries[*i].1 = value,
+            None => {
+                self.positions.insert(key, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (MapKey, &Value)> {
+        self.entries.iter().map(|(key, value)| (*key, value))
+    }
+}
+
+impl PartialEq for Map {
+    /// Maps are equal when they have the same entries, in any order.
+    fn eq(&self, other: &Map) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+/// Integer division truncates towards zero, so `-7 / 2 == -3`.
+///
+/// `i32::MIN / -1` wraps around to `i32::MIN` instead of overflowing.
+pub fn int_div(a: i32, b: i32) -> Result<i32, Message> {
+    if b == 0 {
+        return Err(Message::DivisionByZero);
+    }
+    Ok(a.wrapping_div(b))
+}
+
+/// The remainder takes the sign of the dividend, so that
+/// `int_div(a, b) * b + int_rem(a, b) == a` always holds.
+pub fn int_rem(a: i32, b: i32) -> Result<i32, Message> {
+    if b == 0 {
+        return Err(Message::DivisionByZero);
+    }
+    Ok(a.wrapping_rem(b))
+}
+
+/// The quotient rounded down and the remainder with the sign of the
+/// divisor, so `int_divmod(-7, 2) == Ok((-4, 1))`. `q * b + r == a` holds
+/// here too.
+pub fn int_divmod(a: i32, b: i32) -> Result<(i32, i32), Message> {
+    let (q, r) = (int_div(a, b)?, int_rem(a, b)?);
+    if r != 0 && (r < 0) != (b < 0) {
+        Ok((q - 1, r + b))
+    } else {
+        Ok((q, r))
+    }
+}
+
 impl Add for Value {
     type Output = Value;
     fn add(self, other: Value) -> Self::Output {
         match (self, other) {
             (Value::Int(a), Value::Int(b)) => Value::Int(a + b),
             (Value::Float(a), Value::Float(b)) => Value::Float(a + b),
-            (Value::Int(a), Value::Float(b)) => Value::Float(a as f32 + b),
-            (Value::Float(a), Value::Int(b)) => Value::Float(a + b as f32),
+            (Value::Int(a), Value::Float(b)) => Value::Float(a as f64 + b),
+            (Value::Float(a), Value::Int(b)) => Value::Float(a + b as f64),
             (a, b) => panic!("Cannot add a {:?} with a {:?}", a, b),
         }
     }
@@ -45,12 +401,12 @@ impl AddAssign for Value {
             (Value::Float(a), Value::Float(b)) => *a = *a + b,
             value @ (Value::Float(_), Value::Int(_)) => {
                 if let (Value::Float(a), Value::Int(b)) = (&value.0, &value.1) {
-                    *value.0 = Value::Float(a + *b as f32);
+                    *value.0 = Value::Float(a + *b as f64);
                 }
             }
             value @ (Value::Int(_), Value::Float(_)) => {
                 if let (Value::Int(a), Value::Float(b)) = (&value.0, &value.1) {
-                    *value.0 = Value::Float(*a as f32 + b);
+                    *value.0 = Value::Float(*a as f64 + b);
                 }
             }
             (a, b) => panic!("Cannot add a {:?} with a {:?}", a, b),
@@ -64,8 +420,8 @@ impl Sub for Value {
         match (self, other) {
             (Value::Int(a), Value::Int(b)) => Value::Int(a - b),
             (Value::Float(a), Value::Float(b)) => Value::Float(a - b),
-            (Value::Int(a), Value::Float(b)) => Value::Float(a as f32 - b),
-            (Value::Float(a), Value::Int(b)) => Value::Float(a - b as f32),
+            (Value::Int(a), Value::Float(b)) => Value::Float(a as f64 - b),
+            (Value::Float(a), Value::Int(b)) => Value::Float(a - b as f64),
             (a, b) => panic!("Cannot sub a {:?} with a {:?}", a, b),
         }
     }
@@ -87,8 +443,8 @@ impl Mul for Value {
         match (self, other) {
             (Value::Int(a), Value::Int(b)) => Value::Int(a * b),
             (Value::Float(a), Value::Float(b)) => Value::Float(a * b),
-            (Value::Int(a), Value::Float(b)) => Value::Float(a as f32 * b),
-            (Value::Float(a), Value::Int(b)) => Value::Float(a * b as f32),
+            (Value::Int(a), Value::Float(b)) => Value::Float(a as f64 * b),
+            (Value::Float(a), Value::Int(b)) => Value::Float(a * b as f64),
             (a, b) => panic!("Cannot multiply a {:?} with a {:?}", a, b),
         }
     }
@@ -108,10 +464,12 @@ impl Div for Value {
     type Output = Value;
     fn div(self, other: Value) -> Self::Output {
         match (self, other) {
-            (Value::Int(a), Value::Int(b)) => Value::Int(a / b),
+            (Value::Int(a), Value::Int(b)) => {
+                Value::Int(int_div(a, b).expect("Division by zero"))
+            }
             (Value::Float(a), Value::Float(b)) => Value::Float(a / b),
-            (Value::Int(a), Value::Float(b)) => Value::Float(a as f32 / b),
-            (Value::Float(a), Value::Int(b)) => Value::Float(a / b as f32),
+            (Value::Int(a), Value::Float(b)) => Value::Float(a as f64 / b),
+            (Value::Float(a), Value::Int(b)) => Value::Float(a / b as f64),
             (a, b) => panic!("Cannot divide a {:?} with a {:?}", a, b),
         }
     }
@@ -120,7 +478,9 @@ impl Div for Value {
 impl DivAssign for Value {
     fn div_assign(&mut self, other: Value) {
         match (self, other) {
-            (Value::Int(a), Value::Int(b)) => *a = *a / b,
+            (Value::Int(a), Value::Int(b)) => {
+                *a = int_div(*a, b).expect("Division by zero")
+            }
             (Value::Float(a), Value::Float(b)) => *a = *a / b,
             (a, b) => panic!("Cannot add a {:?} with a {:?}", a, b),
         }
@@ -131,10 +491,12 @@ impl Rem for Value {
     type Output = Value;
     fn rem(self, other: Value) -> Self::Output {
         match (self, other) {
-            (Value::Int(a), Value::Int(b)) => Value::Int(a % b),
+            (Value::Int(a), Value::Int(b)) => {
+                Value::Int(int_rem(a, b).expect("Division by zero"))
+            }
             (Value::Float(a), Value::Float(b)) => Value::Float(a % b),
-            (Value::Int(a), Value::Float(b)) => Value::Float(a as f32 % b),
-            (Value::Float(a), Value::Int(b)) => Value::Float(a % b as f32),
+            (Value::Int(a), Value::Float(b)) => Value::Float(a as f64 % b),
+            (Value::Float(a), Value::Int(b)) => Value::Float(a % b as f64),
             (a, b) => panic!("Cannot rem a {:?} with a {:?}", a, b),
         }
     }
@@ -143,9 +505,178 @@ impl Rem for Value {
 impl RemAssign for Value {
     fn rem_assign(&mut self, other: Value) {
         match (self, other) {
-            (Value::Int(a), Value::Int(b)) => *a = *a % b,
+            (Value::Int(a), Value::Int(b)) => {
+                *a = int_rem(*a, b).expect("Division by zero")
+            }
             (Value::Float(a), Value::Float(b)) => *a = *a % b,
             (a, b) => panic!("Cannot add a {:?} with a {:?}", a, b),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn int_division_truncates() {
+        assert_eq!(Value::Int(7) / Value::Int(2), Value::Int(3));
+        assert_eq!(Value::Int(-7) / Value::Int(2), Value::Int(-3));
+        assert_eq!(Value::Int(7) / Value::Int(-2), Value::Int(-3));
+        assert_eq!(Value::Int(-7) / Value::Int(-2), Value::Int(3));
+    }
+
+    #[test]
+    fn int_remainder_follows_dividend() {
+        assert_eq!(Value::Int(7) % Value::Int(2), Value::Int(1));
+        assert_eq!(Value::Int(-7) % Value::Int(2), Value::Int(-1));
+        assert_eq!(Value::Int(7) % Value::Int(-2), Value::Int(1));
+        assert_eq!(Value::Int(-7) % Value::Int(-2), Value::Int(-1));
+
+        for &(a, b) in &[(7, 2), (-7, 2), (7, -2), (-7, -2), (6, 3)] {
+            assert_eq!(int_div(a, b).unwrap() * b + int_rem(a, b).unwrap(), a);
+        }
+    }
+
+    #[test]
+    fn int_division_overflow_wraps() {
+        assert_eq!(int_div(i32::MIN, -1), Ok(i32::MIN));
+        assert_eq!(int_rem(i32::MIN, -1), Ok(0));
+        assert_eq!(int_divmod(i32::MIN, -1), Ok((i32::MIN, 0)));
+    }
+
+    #[test]
+    fn int_divmod_floors() {
+        assert_eq!(int_divmod(7, 2), Ok((3, 1)));
+        assert_eq!(int_divmod(-7, 2), Ok((-4, 1)));
+        assert_eq!(int_divmod(7, -2), Ok((-4, -1)));
+        assert_eq!(int_divmod(-7, -2), Ok((3, -1)));
+        assert_eq!(int_divmod(-6, 3), Ok((-2, 0)));
+
+        for &(a, b) in &[(7, 2), (-7, 2), (7, -2), (-7, -2), (-6, 3)] {
+            let (q, r) = int_divmod(a, b).unwrap();
+            assert_eq!(q * b + r, a);
+        }
+    }
+
+    #[test]
+    fn int_division_by_zero_is_an_error() {
+        assert_eq!(int_div(1, 0), Err(Message::DivisionByZero));
+        assert_eq!(int_rem(1, 0), Err(Message::DivisionByZero));
+        assert_eq!(int_divmod(1, 0), Err(Message::DivisionByZero));
+    }
+
+    #[test]
+    fn assign_ops_match_binary_ops() {
+        let mut value = Value::Int(-7);
+        value /= Value::Int(2);
+        assert_eq!(value, Value::Int(-3));
+
+        let mut value = Value::Int(-7);
+        value %= Value::Int(2);
+        assert_eq!(value, Value::Int(-1));
+    }
+
+    #[test]
+    fn mixed_numeric_equality() {
+        assert_eq!(Value::Int(2), Value::Float(2.0));
+        assert_ne!(Value::Int(2), Value::Float(2.5));
+        assert_ne!(Value::Int(1), Value::Bool(true));
+        assert_ne!(Value::Nil, Value::Bool(false));
+    }
+
+    #[test]
+    fn array_values() {
+        let mut interner = StringInterner::new();
+        let array = |values: Vec<Value>| Value::Array(Rc::new(values));
+        let text = Value::String(interner.intern("text"));
+
+        assert_eq!(
+            array(vec![Value::Int(1), Value::Float(2.0)]),
+            array(vec![Value::Int(1), Value::Int(2)])
+        );
+        assert_ne!(array(vec![Value::Int(1)]), array(vec![]));
+        assert_eq!(
+            array(vec![array(vec![text]), array(vec![])]).format(&interner),
+            "[[text], []]"
+        );
+        assert!(array(vec![Value::Nil]).is_truthy(&interner));
+        assert!(!array(vec![]).is_truthy(&interner));
+    }
+
+    #[test]
+    fn map_values() {
+        let interner = StringInterner::new();
+        let map = |entries: &[(i32, i32)]| {
+            let mut map = Map::new();
+            for (key, value) in entries {
+                map.insert(MapKey::Int(*key), Value::Int(*value));
+            }
+            Value::Map(Rc::new(map))
+        };
+
+        assert_eq!(map(&[(1, 2), (3, 4)]), map(&[(3, 4), (1, 2)]));
+        assert_ne!(map(&[(1, 2)]), map(&[(1, 3)]));
+        assert_ne!(map(&[(1, 2)]), map(&[(1, 2), (3, 4)]));
+        assert_eq!(
+            map(&[(3, 4), (1, 2), (3, 5)]).format(&interner),
+            "{3: 5, 1: 2}"
+        );
+        assert!(!map(&[]).is_truthy(&interner));
+    }
+
+    #[test]
+    fn truthiness() {
+        let mut interner = StringInterner::new();
+        let empty = Value::String(interner.intern(""));
+        let text = Value::String(interner.intern("text"));
+
+        assert!(Value::Bool(true).is_truthy(&interner));
+        assert!(Value::Int(-1).is_truthy(&interner));
+        assert!(Value::Float(0.5).is_truthy(&interner));
+        assert!(text.is_truthy(&interner));
+        assert!(Value::Ok(Box::new(Value::Nil)).is_truthy(&interner));
+
+        assert!(!Value::Bool(false).is_truthy(&interner));
+        assert!(!Value::Int(0).is_truthy(&interner));
+        assert!(!Value::Float(0.0).is_truthy(&interner));
+        assert!(!empty.is_truthy(&interner));
+        assert!(!Value::Err(Box::new(Value::Int(1))).is_truthy(&interner));
+        assert!(!Value::Nil.is_truthy(&interner));
+    }
+
+    #[test]
+    fn compare_values() {
+        let mut interner = StringInterner::new();
+        let apple = Value::String(interner.intern("apple"));
+        let banana = Value::String(interner.intern("banana"));
+
+        // Interned in reverse order so symbol order differs from byte order
+        let mut reversed = StringInterner::new();
+        let b = Value::String(reversed.intern("b"));
+        let a = Value::String(reversed.intern("a"));
+
+        assert_eq!(
+            Value::Int(1).compare(&Value::Float(1.5), &interner),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            Value::Float(2.0).compare(&Value::Int(2), &interner),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(apple.compare(&banana, &interner), Some(Ordering::Less));
+        assert_eq!(b.compare(&a, &reversed), Some(Ordering::Greater));
+        assert_eq!(
+            Value::Bool(false).compare(&Value::Bool(true), &interner),
+            Some(Ordering::Less)
+        );
+        assert_eq!(Value::Int(1).compare(&apple, &interner), None);
+        assert_eq!(Value::Nil.compare(&Value::Nil, &interner), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Division by zero")]
+    fn int_division_by_zero() {
+        let _ = Value::Int(1) / Value::Int(0);
+    }
+}
@@ -0,0 +1,134 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::human;
+
+/// Characters the bar is drawn with.
+const WIDTH: usize = 30;
+
+/// Time to wait between redraws, so that fast loops are not slowed down by
+/// writing to the terminal.
+const REDRAW: Duration = Duration::from_millis(100);
+
+/// A progress bar on standard error, counting up to a total.
+pub struct Bar {
+    total: u64,
+    done: u64,
+    started: Instant,
+    drawn: Option<Instant>,
+    /// False when standard error is not a terminal, where the bar would
+    /// only fill logs with lines.
+    visible: bool,
+}
+
+impl Bar {
+    pub fn new(total: u64) -> Self {
+        Bar {
+            total,
+            done: 0,
+            started: Instant::now(),
+            drawn: None,
+            visible: stderr_is_terminal(),
+        }
+    }
+
+    /// Counts one more step done, and redraws the bar if it has not been
+    /// drawn for a while.
+    pub fn tick(&mut self) {
+        self.done += 1;
+
+        let now = Instant::now();
+        let due = match self.drawn {
+            Some(drawn) => now - drawn >= REDRAW,
+            None => true,
+        };
+        if self.visible && due {
+            self.drawn = Some(now);
+            self.draw(false);
+        }
+    }
+
+    /// Draws the bar a last time and moves to the next line.
+    pub fn finish(&mut self) {
+        if self.visible {
+            self.draw(true);
+        }
+    }
+
+    fn draw(&self, last: bool) {
+        let line = render(self.done, self.total, self.started.elapsed());
+        let end = if last { "\n" } else { "" };
+        // A bar that cannot be drawn is not worth stopping the script for.
+        let _ = write!(io::stderr(), "\r{}\x1b[K{}", line, end);
+    }
+}
+
+/// The bar with `done` of `total` steps taken in `elapsed`, followed by the
+/// rate and the time left at that rate.
+pub fn render(done: u64, total: u64, elapsed: Duration) -> String {
+    // An empty total counts as done.
+    let filled = (done.min(total) * WIDTH as u64)
+        .checked_div(total)
+        .map_or(WIDTH, |filled| filled as usize);
+
+    let seconds = elapsed.as_secs_f64();
+    let rate = if seconds > 0.0 {
+        done as f64 / seconds
+    } else {
+        0.0
+    };
+    let eta = if rate > 0.0 && done < total {
+        format!(" ETA {}", duration((total - done) as f64 / rate))
+    } else {
+        String::new()
+    };
+
+    format!(
+        "[{}{}] {}/{} {:.1}/s{}",
+        "#".repeat(filled),
+        "-".repeat(WIDTH - filled),
+        done,
+        total,
+        rate,
+        eta
+    )
+}
+
+/// Formats seconds rounded up to whole ones as `1h2m3s`, leaving out
+/// leading zero parts.
+fn duration(seconds: f64) -> String {
+    human::duration(seconds.ceil() as i64 * 1000)
+}
+
+#[cfg(unix)]
+fn stderr_is_terminal() -> bool {
+    unsafe { libc::isatty(libc::STDERR_FILENO) == 1 }
+}
+
+#[cfg(not(unix))]
+fn stderr_is_terminal() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendering() {
+        assert_eq!(
+            render(10, 40, Duration::from_secs(2)),
+            "[#######-----------------------] 10/40 5.0/s ETA 6s"
+        );
+        assert_eq!(
+            render(40, 40, Duration::from_secs(8)),
+            "[##############################] 40/40 5.0/s"
+        );
+        assert_eq!(
+            render(0, 0, Duration::from_secs(0)),
+            "[##############################] 0/0 0.0/s"
+        );
+        assert_eq!(duration(3723.0), "1h2m3s");
+        assert_eq!(duration(59.5), "1m0s");
+    }
+}
@@ -0,0 +1,82 @@
+use std::io;
+
+/// The characters of a token, which need no escaping in a URL. There are
+/// 64 of them, so that every byte picks one with the same chance.
+const TOKEN_CHARS: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Fills `bytes` with random bytes from the operating system, which are
+/// good enough for identifiers and secrets.
+#[cfg(unix)]
+pub fn fill(bytes: &mut [u8]) -> io::Result<()> {
+    use std::io::Read;
+
+    std::fs::File::open("/dev/urandom")?.read_exact(bytes)
+}
+
+#[cfg(not(unix))]
+pub fn fill(_: &mut [u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "No source of random bytes",
+    ))
+}
+
+/// A random version 4 UUID, like `"0b3c6a4e-9f0d-4c1a-8e2b-5d7f1a2c3b4d"`.
+pub fn uuid4() -> io::Result<String> {
+    let mut bytes = [0; 16];
+    fill(&mut bytes)?;
+    Ok(format_uuid4(bytes))
+}
+
+/// A random string of `len` characters that can be put in a URL as is.
+pub fn token(len: usize) -> io::Result<String> {
+    let mut bytes = vec![0; len];
+    fill(&mut bytes)?;
+    Ok(bytes
+        .iter()
+        .map(|b| TOKEN_CHARS[usize::from(b % 64)] as char)
+        .collect())
+}
+
+/// Formats `bytes` as a UUID after setting the bits that mark it as
+/// version 4, variant 1.
+fn format_uuid4(mut bytes: [u8; 16]) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let mut uuid = String::with_capacity(36);
+    for (i, b) in bytes.iter().enumerate() {
+        if i == 4 || i == 6 || i == 8 || i == 10 {
+            uuid.push('-');
+        }
+        uuid += &format!("{:02x}", b);
+    }
+    uuid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uuids() {
+        assert_eq!(
+            format_uuid4([0xff; 16]),
+            "ffffffff-ffff-4fff-bfff-ffffffffffff"
+        );
+        assert_eq!(
+            format_uuid4([0; 16]),
+            "00000000-0000-4000-8000-000000000000"
+        );
+        assert_ne!(uuid4().unwrap(), uuid4().unwrap());
+    }
+
+    #[test]
+    fn tokens() {
+        let token = token(100).unwrap();
+        assert_eq!(token.len(), 100);
+        assert!(token.bytes().all(|b| TOKEN_CHARS.contains(&b)));
+        assert_eq!(super::token(0).unwrap(), "");
+    }
+}
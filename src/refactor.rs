@@ -0,0 +1,369 @@
+use std::mem;
+
+use hashbrown::HashMap;
+
+use crate::common::{Context, Symbol};
+use crate::lexer;
+use crate::location::{Location, Span};
+use crate::messages::Message;
+use crate::parser;
+use crate::semantic::{self, Name};
+use crate::token::TokenKind;
+
+/// Renames the variable or function named at `line`:`column` to `new`,
+/// along with every reference to it, and returns the rewritten source.
+///
+/// Names are resolved the way the semantic checks resolve them, so a local
+/// that shadows the variable, or a variable with the same name in another
+/// function, is left alone. The name at the position has to be `old`, so
+/// that a stale position or a typo in the name is caught instead of
+/// silently rewriting the wrong thing. Only the names are touched,
+/// everything else in the source (comments, formatting, strings) is kept
+/// as is.
+#[allow(clippy::result_unit_err)]
+pub fn rename(
+    source: &[u8],
+    file: Symbol,
+    old: &str,
+    new: &str,
+    line: u32,
+    column: u32,
+    context: &mut Context,
+) -> Result<String, ()> {
+    let position = match offset(source, line, column) {
+        Some(offset) => Location {
+            file,
+            line,
+            span: Span {
+                start: offset as u32,
+                len: 1,
+            },
+        },
+        None => {
+            let message = Message::PositionOutsideFile { line, column };
+            eprintln!("{}", message.text(context.language));
+            return Err(());
+        }
+    };
+
+    if !is_identifier(new, file, context) {
+        context.report_error(
+            Message::InvalidIdentifier(new.to_string()),
+            position,
+        );
+        return Err(());
+    }
+
+    let (names, errors) = resolve(source, file, context)?;
+    let old_symbol = context.interner.intern(old);
+    let offset = position.span.start;
+    let target = names.iter().find(|name| {
+        let span = name.location.span;
+        span.start <= offset && offset < span.start + span.len
+    });
+
+    let binding = match target {
+        Some(name) if name.name == old_symbol => match name.binding {
+            Some(binding) => binding,
+            None => {
+                let message = Message::NotRenamable(old.to_string());
+                context.report_error(message, name.location);
+                return Err(());
+            }
+        },
+        _ => {
+            context.report_error(
+                Message::ExpectedIdentifierAt(old.to_string()),
+                position,
+            );
+            return Err(());
+        }
+    };
+
+    let mut output = Vec::with_capacity(source.len());
+    let mut pos = 0;
+
+    for name in names.iter().filter(|name| name.binding == Some(binding)) {
+        let span = name.location.span;
+        output.extend_from_slice(&source[pos..span.start as usize]);
+        output.extend_from_slice(new.as_bytes());
+        pos = (span.start + span.len) as usize;
+    }
+
+    output.extend_from_slice(&source[pos..]);
+
+    // The new name must not capture or be captured by another variable or
+    // function, so every name has to refer to the same one as before.
+    let outer = context.collected.replace(vec![]);
+    let renamed = resolve(&output, file, context);
+    context.collected = outer;
+
+    match renamed {
+        Ok((renamed, renamed_errors)) if renamed.len() == names.len() => {
+            if let Some(name) = changed_binding(&names, &renamed) {
+                let message = Message::RenameConflict(new.to_string());
+                context.report_error(message, name.location);
+                return Err(());
+            }
+            if renamed_errors > errors {
+                let message = Message::RenameConflict(new.to_string());
+                context.report_error(message, position);
+                return Err(());
+            }
+        }
+        _ => {
+            let message = Message::RenameConflict(new.to_string());
+            context.report_error(message, position);
+            return Err(());
+        }
+    }
+
+    match String::from_utf8(output) {
+        Ok(output) => Ok(output),
+        Err(_) => {
+            context.report_error(Message::InvalidUtf8, position);
+            Err(())
+        }
+    }
+}
+
+/// The variable and function names in `source`, in the order they appear,
+/// and how many errors the semantic checks find. Those errors are not
+/// reported, a file with mistakes in it can still be refactored.
+fn resolve(
+    source: &[u8],
+    file: Symbol,
+    context: &mut Context,
+) -> Result<(Vec<Name>, usize), ()> {
+    let tokens = lexer::generate_tokens(source, file, context)?;
+    let ast = parser::parse_ast(tokens, context)?;
+
+    let mut names = semantic::resolve(&ast, context);
+    names.sort_by_key(|name| name.location.span.start);
+
+    let outer = context.collected.replace(vec![]);
+    let _ = semantic::analyze(&ast, context);
+    let errors = mem::replace(&mut context.collected, outer)
+        .map_or(0, |diagnostics| diagnostics.len());
+
+    Ok((names, errors))
+}
+
+/// The first name in `before` that refers to a different variable or
+/// function than the name in the same place in `after` does.
+fn changed_binding<'a>(before: &'a [Name], after: &[Name]) -> Option<&'a Name> {
+    let mut forward = HashMap::new();
+    let mut backward = HashMap::new();
+
+    before.iter().zip(after).find_map(|(old, new)| {
+        let same = *forward.entry(old.binding).or_insert(new.binding)
+            == new.binding
+            && *backward.entry(new.binding).or_insert(old.binding)
+                == old.binding
+            && old.binding.is_some() == new.binding.is_some();
+        if same {
+            None
+        } else {
+            Some(old)
+        }
+    })
+}
+
+fn is_identifier(name: &str, file: Symbol, context: &mut Context) -> bool {
+    let mut chars = name.chars();
+    let valid_chars = chars.next().is_some_and(char::is_alphabetic)
+        && chars.all(|c| c.is_alphanumeric() || c == '_');
+
+    if !valid_chars {
+        return false;
+    }
+
+    // Keywords are made up of the same characters, let the lexer tell them
+    // apart from identifiers.
+    match lexer::generate_tokens(name.as_bytes(), file, context) {
+        Ok(tokens) => matches!(tokens[0].kind, TokenKind::Ident(_)),
+        Err(_) => false,
+    }
+}
+
+/// Converts a one-based line and column into a byte offset.
+fn offset(source: &[u8], line: u32, column: u32) -> Option<usize> {
+    let mut line_start = 0;
+
+    for _ in 1..line {
+        let newline = source[line_start..].iter().position(|&b| b == b'\n')?;
+        line_start += newline + 1;
+    }
+
+    let offset = line_start + column as usize - 1;
+    let line_len = source[line_start..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .unwrap_or(source.len() - line_start);
+
+    if offset < line_start + line_len {
+        Some(offset)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_rename(source: &str, old: &str, new: &str, expected: &str) {
+        let mut context = Context::new();
+        let file = context.interner.intern("test.bl");
+        let line = 1;
+        let column = source.find(old).unwrap() as u32 + 1;
+
+        let result = rename(
+            source.as_bytes(),
+            file,
+            old,
+            new,
+            line,
+            column,
+            &mut context,
+        );
+
+        assert_eq!(result, Ok(expected.to_string()));
+    }
+
+    #[test]
+    fn rename_variable() {
+        assert_rename(
+            "age := 20 age += 1 print(\"%\", age)",
+            "age",
+            "years",
+            "years := 20 years += 1 print(\"%\", years)",
+        );
+    }
+
+    #[test]
+    fn rename_keeps_strings_and_comments() {
+        assert_rename(
+            "n := \"n\" // n\nn = 2",
+            "n",
+            "count",
+            "count := \"n\" // n\ncount = 2",
+        );
+    }
+
+    #[test]
+    fn rename_only_whole_identifiers() {
+        assert_rename(
+            "fn add(a: int) -> int { return a } add_one := add(1)",
+            "add",
+            "plus",
+            "fn plus(a: int) -> int { return a } add_one := plus(1)",
+        );
+    }
+
+    /// Renames `old` where it ends `at`, and returns the new source or the
+    /// error.
+    fn rename_at(
+        source: &str,
+        at: &str,
+        old: &str,
+        new: &str,
+    ) -> Result<String, Message> {
+        let mut context = Context::new();
+        context.collected = Some(vec![]);
+        let file = context.interner.intern("test.bl");
+        let offset = source.find(at).unwrap() + at.rfind(old).unwrap();
+        let line = source[..offset].matches('\n').count() as u32 + 1;
+        let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let column = (offset - line_start) as u32 + 1;
+
+        let result = rename(
+            source.as_bytes(),
+            file,
+            old,
+            new,
+            line,
+            column,
+            &mut context,
+        );
+        result.map_err(|_| context.collected.unwrap().remove(0).message)
+    }
+
+    #[test]
+    fn rename_leaves_shadowing_locals() {
+        let source = "n := 1\n\
+                      fn f() -> int { n := 2 return n }\n\
+                      m := n + 1";
+        assert_eq!(
+            rename_at(source, "m := n", "n", "count"),
+            Ok("count := 1\n\
+             fn f() -> int { n := 2 return n }\n\
+             m := count + 1"
+                .to_string())
+        );
+        assert_eq!(
+            rename_at(source, "return n", "n", "local"),
+            Ok("n := 1\n\
+             fn f() -> int { local := 2 return local }\n\
+             m := n + 1"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn rename_parameter_of_one_function() {
+        let source = "fn f(x: int) -> int { return x }\n\
+                      fn g(x: int) -> int { return x * 2 }\n\
+                      m := {x: 1}";
+        assert_eq!(
+            rename_at(source, "f(x", "x", "value"),
+            Ok("fn f(value: int) -> int { return value }\n\
+             fn g(x: int) -> int { return x * 2 }\n\
+             m := {x: 1}"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn rename_conflicts() {
+        // `y` in another function is unaffected by a new `y` here.
+        let source = "fn f() { x := 1 print(x) }\n\
+                      fn g() { y := 2 print(y) }";
+        assert_eq!(
+            rename_at(source, "x := 1", "x", "y"),
+            Ok("fn f() { y := 1 print(y) }\n\
+             fn g() { y := 2 print(y) }"
+                .to_string())
+        );
+
+        // The global `total` would be hidden from the function.
+        let source = "total := 1\n\
+                      fn f() -> int { sum := 2 return sum + total }";
+        assert_eq!(
+            rename_at(source, "sum := 2", "sum", "total"),
+            Err(Message::RenameConflict("total".to_string()))
+        );
+
+        // Two functions with the same name.
+        let source = "fn f() { } fn g() { }";
+        assert_eq!(
+            rename_at(source, "fn g", "g", "f"),
+            Err(Message::RenameConflict("f".to_string()))
+        );
+
+        // Builtins are not declared in the file.
+        assert_eq!(
+            rename_at("n := len([1])", "len", "len", "size"),
+            Err(Message::NotRenamable("len".to_string()))
+        );
+    }
+
+    #[test]
+    fn offset_of_position() {
+        let source = b"a := 1\nbc := 2\n";
+        assert_eq!(offset(source, 1, 1), Some(0));
+        assert_eq!(offset(source, 2, 2), Some(8));
+        assert_eq!(offset(source, 2, 10), None);
+        assert_eq!(offset(source, 4, 1), None);
+    }
+}
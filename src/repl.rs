@@ -0,0 +1,271 @@
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::Ordering;
+
+use blixt::common::Context;
+use blixt::interrupt::{self, Interruptible};
+use blixt::messages::Message;
+use blixt::parser::{self, Completeness};
+use blixt::pretty::Type;
+use blixt::Engine;
+
+const FILE_NAME: &str = "<repl>";
+
+/// Terminals wrap pasted text in these when bracketed paste is enabled.
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Reads statements from stdin and runs them one at a time, printing the
+/// value of each expression. Unfinished input, like an unclosed brace or a
+/// trailing operator, continues on the next line, and pasted text runs as
+/// one unit. On a terminal, values are followed by their type when the
+/// typechecker could infer it.
+pub fn run(context: Context) -> Result<(), ()> {
+    let hints = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+    let mut repl = Repl::new(Engine::with_context(context), hints);
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let tty = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+
+    if tty {
+        print!("\x1b[?2004h");
+    }
+
+    loop {
+        if let Some(prompt) = repl.prompt() {
+            print!("{}", prompt);
+        }
+        io::stdout().flush().expect("Failed to write to stdout");
+
+        let mut line = String::new();
+        let read = Interruptible(stdin.lock()).read_line(&mut line);
+
+        // Ctrl-C while typing throws away the current input.
+        if interrupt::flag().swap(false, Ordering::SeqCst) {
+            println!();
+            repl.discard();
+            continue;
+        }
+        match read {
+            Ok(0) => {
+                println!();
+                break;
+            }
+            Ok(_) => {}
+            // Other signals are handled when the next input runs.
+            Err(_) if interrupt::pending() => continue,
+            Err(error) => panic!("Failed to read stdin: {}", error),
+        }
+
+        repl.feed(&line, &mut stdout.lock());
+    }
+
+    if tty {
+        print!("\x1b[?2004l");
+    }
+
+    Ok(())
+}
+
+struct Repl {
+    engine: Engine,
+    buffer: String,
+    pasting: bool,
+    /// Whether to show the type of echoed values, dimmed.
+    hints: bool,
+}
+
+impl Repl {
+    fn new(engine: Engine, hints: bool) -> Self {
+        Repl {
+            engine,
+            buffer: String::new(),
+            pasting: false,
+            hints,
+        }
+    }
+
+    /// The prompt to show before the next line, none in the middle of a
+    /// paste.
+    fn prompt(&self) -> Option<&'static str> {
+        if self.pasting {
+            None
+        } else if self.buffer.is_empty() {
+            Some(">> ")
+        } else {
+            Some(".. ")
+        }
+    }
+
+    fn discard(&mut self) {
+        self.buffer.clear();
+        self.pasting = false;
+    }
+
+    /// Adds a line of input and runs the buffered statements once they are
+    /// complete, writing the value of every expression among them to `out`.
+    fn feed(&mut self, line: &str, out: &mut dyn Write) {
+        let mut line = line.to_string();
+        if line.contains(PASTE_START) {
+            line = line.replace(PASTE_START, "");
+            self.pasting = true;
+        }
+        if line.contains(PASTE_END) {
+            line = line.replace(PASTE_END, "");
+            self.pasting = false;
+        }
+
+        if self.buffer.is_empty() && line.starts_with(':') {
+            writeln!(out, "{}", command(&mut self.engine, line.trim()))
+                .expect("Failed to write to stdout");
+            return;
+        }
+
+        self.buffer.push_str(&line);
+        if self.pasting {
+            return;
+        }
+
+        if self.buffer.trim().is_empty() {
+            self.buffer.clear();
+            return;
+        }
+
+        let context = self.engine.context();
+        let file = context.interner.intern(FILE_NAME);
+        if parser::completeness(&self.buffer, file, context)
+            == Completeness::Incomplete
+        {
+            return;
+        }
+
+        let hints = self.hints;
+        let _ = self.engine.eval_each(
+            FILE_NAME,
+            &self.buffer,
+            |value, kind, interner| {
+                let mut line = value.format(interner);
+                let kind = Type { kind, interner };
+                if hints && kind.is_known() {
+                    line.push_str(&format!(" {}: {}{}", DIM, kind, RESET));
+                }
+                writeln!(out, "{}", line).expect("Failed to write to stdout");
+            },
+        );
+
+        self.buffer.clear();
+    }
+}
+
+/// Runs a REPL command and returns the message to show.
+fn command(engine: &mut Engine, command: &str) -> String {
+    let message = match command {
+        ":checkpoint" => Message::SavedCheckpoint(engine.checkpoint()),
+        ":rollback" => {
+            if engine.rollback() {
+                Message::RolledBack
+            } else {
+                Message::NoCheckpoint
+            }
+        }
+        _ => Message::UnknownCommand(command.to_string()),
+    };
+
+    message.text(engine.context().language)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoint_commands() {
+        let mut engine = Engine::new();
+
+        assert_eq!(command(&mut engine, ":checkpoint"), "Saved checkpoint 1");
+        engine.eval_str("a := 1").unwrap();
+        assert_eq!(
+            command(&mut engine, ":rollback"),
+            "Rolled back to the last checkpoint"
+        );
+        assert!(engine.eval_str("a").is_err());
+        assert_eq!(
+            command(&mut engine, ":rollback"),
+            "There is no checkpoint to roll back to"
+        );
+        assert!(command(&mut engine, ":undo").starts_with("Unknown command"));
+    }
+
+    fn feed(repl: &mut Repl, line: &str) -> String {
+        let mut out = vec![];
+        repl.feed(line, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn echo_every_expression() {
+        let mut repl = Repl::new(Engine::new(), false);
+
+        assert_eq!(feed(&mut repl, "a := 1 a + 1 a * 2\n"), "2\n2\n");
+        assert_eq!(feed(&mut repl, "if a > 0 {\n"), "");
+        assert_eq!(repl.prompt(), Some(".. "));
+        assert_eq!(feed(&mut repl, "a = 5 }\n"), "");
+        assert_eq!(feed(&mut repl, "a\n"), "5\n");
+    }
+
+    #[test]
+    fn type_hints() {
+        let mut repl = Repl::new(Engine::new(), true);
+
+        assert_eq!(
+            feed(&mut repl, "a := [\"x\"] a len(a)\n"),
+            "[x] \x1b[2m: [string]\x1b[0m\n1 \x1b[2m: int\x1b[0m\n"
+        );
+        assert_eq!(
+            feed(&mut repl, "m := {1: 2.5} m zip(a, a)\n"),
+            "{1: 2.5} \x1b[2m: {int: float}\x1b[0m\n\
+             iter \x1b[2m: iter[(string, string)]\x1b[0m\n"
+        );
+        // Nothing is known about the elements of an empty array.
+        assert_eq!(feed(&mut repl, "[]\n"), "[]\n");
+    }
+
+    #[test]
+    fn paste_runs_as_one_unit() {
+        let mut repl = Repl::new(Engine::new(), false);
+
+        assert_eq!(
+            feed(&mut repl, "\x1b[200~fn double(n: int) -> int {\n"),
+            ""
+        );
+        assert_eq!(repl.prompt(), None);
+        assert_eq!(feed(&mut repl, "    return n * 2\n"), "");
+        assert_eq!(feed(&mut repl, "}\n"), "");
+        assert_eq!(feed(&mut repl, "double(3)\n"), "");
+        assert_eq!(feed(&mut repl, "double(4)\x1b[201~\n"), "6\n8\n");
+        assert_eq!(repl.prompt(), Some(">> "));
+
+        // A paste that fails as a whole declares nothing.
+        feed(&mut repl, "\x1b[200~b := 1\n");
+        feed(&mut repl, "c := b / 0\x1b[201~\n");
+        assert!(repl.engine.eval_str("b").is_err());
+    }
+
+    #[test]
+    fn unclosed_input() {
+        let mut repl = Repl::new(Engine::new(), false);
+
+        assert_eq!(feed(&mut repl, "a := [1,\n"), "");
+        assert_eq!(feed(&mut repl, "2] b := a[1] +\n"), "");
+        assert_eq!(repl.prompt(), Some(".. "));
+        assert_eq!(feed(&mut repl, "1 b\n"), "3\n");
+
+        // More input can't fix these, so they are reported right away.
+        feed(&mut repl, "c := (1]\n");
+        assert_eq!(repl.prompt(), Some(">> "));
+        feed(&mut repl, "d := \"abc\n");
+        assert_eq!(repl.prompt(), Some(">> "));
+    }
+}
@@ -0,0 +1,408 @@
+use crate::common::StringInterner;
+use crate::primitives::{MapKey, Value};
+
+/// Something wrong with a value that was checked against a schema, like
+/// `db.port` with `has to be at most 65535, found 70000`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub path: String,
+    pub message: String,
+}
+
+/// One entry of a schema: where in the value it applies and what has to
+/// hold there.
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    path: Vec<Segment>,
+    optional: bool,
+    kind: Kind,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// The value of a key in a map.
+    Key(String),
+    /// Every element of an array.
+    Each,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    Any,
+    Bool,
+    Int,
+    /// Integers are accepted too, since a number like `1` in a config file
+    /// is as good as `1.0`.
+    Float,
+    String,
+    /// An array with elements of the kind.
+    Array(Box<Kind>),
+    Map,
+}
+
+impl Kind {
+    fn parse(name: &str) -> Option<Kind> {
+        let kind = match name {
+            "any" => Kind::Any,
+            "bool" => Kind::Bool,
+            "int" => Kind::Int,
+            "float" => Kind::Float,
+            "string" => Kind::String,
+            "array" => Kind::Array(Box::new(Kind::Any)),
+            "map" => Kind::Map,
+            _ if name.starts_with('[') && name.ends_with(']') => {
+                let element = Kind::parse(name[1..name.len() - 1].trim())?;
+                Kind::Array(Box::new(element))
+            }
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    fn matches(&self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (Kind::Any, _)
+                | (Kind::Bool, Value::Bool(_))
+                | (Kind::Int, Value::Int(_))
+                | (Kind::Float, Value::Int(_))
+                | (Kind::Float, Value::Float(_))
+                | (Kind::String, Value::String(_))
+                | (Kind::Array(_), Value::Array(_))
+                | (Kind::Map, Value::Map(_))
+        )
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Kind::Any => "any".to_string(),
+            Kind::Bool => "bool".to_string(),
+            Kind::Int => "int".to_string(),
+            Kind::Float => "float".to_string(),
+            Kind::String => "string".to_string(),
+            Kind::Array(element) => format!("[{}]", element.name()),
+            Kind::Map => "map".to_string(),
+        }
+    }
+}
+
+/// Reads a schema, a list of paths and the rules for them. A path is keys
+/// separated by dots, where `[]` after a key means every element of the
+/// array there, and a `?` at the end means that the key may be missing. A
+/// rule is a type, optionally followed by an inclusive range like `1..10`,
+/// `..10` or `1..`, which limits numbers or the length of strings, arrays
+/// and maps.
+fn parse(schema: &[(String, String)]) -> Result<Vec<Rule>, String> {
+    schema
+        .iter()
+        .map(|(path, rule)| {
+            parse_rule(path, rule)
+                .map_err(|e| format!("Invalid rule for {}: {}", path, e))
+        })
+        .collect()
+}
+
+fn parse_rule(path: &str, rule: &str) -> Result<Rule, String> {
+    let (path, optional) = match path.strip_suffix('?') {
+        Some(path) => (path, true),
+        None => (path, false),
+    };
+
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let mut name = part;
+        let mut each = 0;
+        while let Some(rest) = name.strip_suffix("[]") {
+            name = rest;
+            each += 1;
+        }
+        if name.is_empty() && each == 0 {
+            return Err("the path has an empty key".to_string());
+        }
+        if !name.is_empty() {
+            segments.push(Segment::Key(name.to_string()));
+        }
+        segments.extend((0..each).map(|_| Segment::Each));
+    }
+
+    let rule = rule.trim();
+    let (kind, range) = match rule.rfind(' ') {
+        Some(i) if rule[i + 1..].contains("..") => {
+            (&rule[..i], Some(&rule[i + 1..]))
+        }
+        _ => (rule, None),
+    };
+    let kind = Kind::parse(kind.trim())
+        .ok_or_else(|| format!("no type {}", kind.trim()))?;
+
+    let (mut min, mut max) = (None, None);
+    if let Some(range) = range {
+        if let Kind::Any | Kind::Bool = kind {
+            return Err(format!("a {} can't have a range", kind.name()));
+        }
+        let bound = |text: &str| match text {
+            "" => Ok(None),
+            _ => text
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("{} is not a number", text)),
+        };
+        let dots = range.find("..").unwrap_or(0);
+        min = bound(&range[..dots])?;
+        max = bound(&range[dots + 2..])?;
+        if min.is_none() && max.is_none() {
+            return Err("the range has no bounds".to_string());
+        }
+    }
+
+    Ok(Rule {
+        path: segments,
+        optional,
+        kind,
+        min,
+        max,
+    })
+}
+
+/// Checks `value` against every rule of `schema`, see `parse` for how the
+/// rules are written. Returns every problem found, in the order of the
+/// rules, or an error if the schema itself is invalid. Keys that no rule
+/// mentions are allowed.
+pub fn validate(
+    value: &Value,
+    schema: &[(String, String)],
+    interner: &mut StringInterner,
+) -> Result<Vec<Problem>, String> {
+    let rules = parse(schema)?;
+    let mut problems = Vec::new();
+    for rule in &rules {
+        let mut checker = Checker {
+            rule,
+            interner: &mut *interner,
+            problems: &mut problems,
+        };
+        checker.visit(value, &rule.path, String::new());
+    }
+    Ok(problems)
+}
+
+struct Checker<'a> {
+    rule: &'a Rule,
+    interner: &'a mut StringInterner,
+    problems: &'a mut Vec<Problem>,
+}
+
+impl Checker<'_> {
+    /// Follows `segments` from `value`. When a map or array on the way is
+    /// missing or has the wrong type, nothing is reported, since a rule
+    /// for that path is what should say so.
+    fn visit(&mut self, value: &Value, segments: &[Segment], path: String) {
+        match segments.split_first() {
+            None => self.check(value, path),
+            Some((Segment::Key(name), rest)) => {
+                let map = match value {
+                    Value::Map(map) => map,
+                    _ => return,
+                };
+                let path = match path.as_str() {
+                    "" => name.clone(),
+                    _ => format!("{}.{}", path, name),
+                };
+                let key = MapKey::String(self.interner.intern(name));
+                match map.get(key) {
+                    Some(value) => self.visit(value, rest, path),
+                    None if rest.is_empty() && !self.rule.optional => {
+                        self.report(path, "is required".to_string())
+                    }
+                    None => {}
+                }
+            }
+            Some((Segment::Each, rest)) => {
+                if let Value::Array(values) = value {
+                    for (i, value) in values.iter().enumerate() {
+                        self.visit(value, rest, format!("{}[{}]", path, i));
+                    }
+                }
+            }
+        }
+    }
+
+    fn check(&mut self, value: &Value, path: String) {
+        let kind = &self.rule.kind;
+        if !kind.matches(value) {
+            let message = format!(
+                "has to be {}, found {}",
+                kind.name(),
+                type_name(value)
+            );
+            return self.report(path, message);
+        }
+        if let (Kind::Array(element), Value::Array(values)) = (kind, value) {
+            for (i, value) in values.iter().enumerate() {
+                if !element.matches(value) {
+                    let message = format!(
+                        "has to be {}, found {}",
+                        element.name(),
+                        type_name(value)
+                    );
+                    self.report(format!("{}[{}]", path, i), message);
+                }
+            }
+        }
+
+        let (what, size) = match value {
+            Value::Int(n) => ("", f64::from(*n)),
+            Value::Float(f) => ("", *f),
+            Value::String(s) => {
+                (" a length of", self.interner.get(*s).chars().count() as f64)
+            }
+            Value::Array(values) => (" a length of", values.len() as f64),
+            Value::Map(map) => (" a length of", map.len() as f64),
+            _ => return,
+        };
+        let verb = if what.is_empty() { "be" } else { "have" };
+        if let Some(min) = self.rule.min.filter(|min| size < *min) {
+            let message = format!(
+                "has to {}{} at least {}, found {}",
+                verb, what, min, size
+            );
+            self.report(path, message);
+        } else if let Some(max) = self.rule.max.filter(|max| size > *max) {
+            let message = format!(
+                "has to {}{} at most {}, found {}",
+                verb, what, max, size
+            );
+            self.report(path, message);
+        }
+    }
+
+    fn report(&mut self, path: String, message: String) {
+        self.problems.push(Problem { path, message });
+    }
+}
+
+/// The name of the type of `value` as scripts write it.
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Bool(_) => "bool",
+        Value::Int(_) => "int",
+        Value::Float(_) => "float",
+        Value::String(_) => "string",
+        Value::Struct(_) => "struct",
+        Value::Array(_) => "array",
+        Value::Map(_) => "map",
+        Value::Heap(_) => "heap",
+        Value::Deque(_) => "deque",
+        Value::Function(_) => "fn",
+        Value::Iter(_) => "iter",
+        Value::Ok(_) | Value::Err(_) => "result",
+        Value::Nil => "nil",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::from_json;
+
+    fn problems(json: &str, schema: &[(&str, &str)]) -> Vec<String> {
+        let mut interner = StringInterner::new();
+        let value = from_json(json, &mut interner).expect("Invalid JSON");
+        let schema: Vec<_> = schema
+            .iter()
+            .map(|(path, rule)| (path.to_string(), rule.to_string()))
+            .collect();
+        validate(&value, &schema, &mut interner)
+            .expect("Invalid schema")
+            .into_iter()
+            .map(|p| format!("{}: {}", p.path, p.message))
+            .collect()
+    }
+
+    #[test]
+    fn types_and_required_keys() {
+        let schema = [
+            ("name", "string"),
+            ("port", "int"),
+            ("ratio", "float"),
+            ("debug?", "bool"),
+            ("tags", "[string]"),
+        ];
+        let valid = r#"{"name": "a", "port": 80, "ratio": 1, "tags": []}"#;
+        assert!(problems(valid, &schema).is_empty());
+
+        let invalid = r#"{"port": "80", "ratio": 0.5, "tags": ["a", 1]}"#;
+        assert_eq!(
+            problems(invalid, &schema),
+            vec![
+                "name: is required",
+                "port: has to be int, found string",
+                "tags[1]: has to be string, found int",
+            ]
+        );
+    }
+
+    #[test]
+    fn ranges() {
+        let schema = [
+            ("port", "int 1..65535"),
+            ("ratio", "float ..1"),
+            ("name", "string 1.."),
+        ];
+        assert!(problems(r#"{"port": 1, "ratio": 1, "name": "a"}"#, &schema)
+            .is_empty());
+        assert_eq!(
+            problems(r#"{"port": 0, "ratio": 1.5, "name": ""}"#, &schema),
+            vec![
+                "port: has to be at least 1, found 0",
+                "ratio: has to be at most 1, found 1.5",
+                "name: has to have a length of at least 1, found 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_paths() {
+        let schema = [
+            ("db", "map"),
+            ("db.host", "string"),
+            ("servers[].port", "int"),
+            ("cache?", "map"),
+            ("cache.size", "int"),
+        ];
+        let config = r#"{
+            "db": {"port": 1},
+            "servers": [{"port": 1}, {"port": true}, {}]
+        }"#;
+        assert_eq!(
+            problems(config, &schema),
+            vec![
+                "db.host: is required",
+                "servers[1].port: has to be int, found bool",
+                "servers[2].port: is required",
+            ]
+        );
+        assert_eq!(
+            problems(r#"{"db": 1, "servers": []}"#, &schema),
+            vec!["db: has to be map, found int"]
+        );
+    }
+
+    #[test]
+    fn invalid_schemas() {
+        let mut interner = StringInterner::new();
+        for (path, rule) in &[
+            ("a", "integer"),
+            ("a", "bool 1..2"),
+            ("a", "int x..2"),
+            ("a", "int .."),
+            ("a..b", "int"),
+        ] {
+            let schema = [(path.to_string(), rule.to_string())];
+            let result = validate(&Value::Nil, &schema, &mut interner);
+            assert!(result.is_err(), "{} {}", path, rule);
+        }
+    }
+}
@@ -4,11 +4,13 @@ use crate::ast::AstNodeId;
 use crate::common::Symbol;
 use crate::primitives::{Value, ValueKind};
 
+#[derive(Clone)]
 pub struct Scope {
     scopes: Vec<InnerScope>,
     curr_scope: usize,
 }
 
+#[derive(Clone)]
 struct InnerScope {
     functions: HashMap<Symbol, AstNodeId>,
     user_defined_types: HashMap<Symbol, AstNodeId>,
@@ -29,6 +31,7 @@ impl InnerScope {
     }
 }
 
+#[derive(Clone)]
 pub struct Variable {
     pub name: Symbol,
     pub kind: ValueKind,
@@ -44,8 +47,10 @@ impl Scope {
         }
     }
 
+    /// Enters a function call. Only the global scope is visible from the new
+    /// one, not the locals of the caller.
     pub fn push_scope(&mut self) {
-        self.scopes.push(InnerScope::new(Some(self.curr_scope)));
+        self.scopes.push(InnerScope::new(Some(0)));
         self.curr_scope += 1;
     }
 
@@ -103,15 +108,39 @@ impl Scope {
     }
 
     pub fn get_variable_mut(&mut self, name: Symbol) -> Option<&mut Variable> {
-        for scope in &mut self.scopes {
-            for var in scope.variables.iter_mut().rev() {
-                if var.name == name {
-                    return Some(var);
-                }
+        let mut index = self.curr_scope;
+
+        loop {
+            let scope = &self.scopes[index];
+            if let Some(pos) =
+                scope.variables.iter().rposition(|var| var.name == name)
+            {
+                return Some(&mut self.scopes[index].variables[pos]);
             }
+
+            index = scope.parent?;
         }
+    }
 
-        None
+    /// The variables of the outermost scope, in the order they were
+    /// declared.
+    pub fn globals(&self) -> &[Variable] {
+        &self.scopes[0].variables
+    }
+
+    /// The names of the variables and functions visible from the current
+    /// scope, for suggesting one when a name is not found.
+    pub fn visible_names(&self) -> Vec<Symbol> {
+        let mut names = Vec::new();
+        let mut scope = &self.scopes[self.curr_scope];
+        loop {
+            names.extend(scope.variables.iter().map(|var| var.name));
+            names.extend(scope.functions.keys().copied());
+            match scope.parent {
+                Some(parent) => scope = &self.scopes[parent],
+                None => return names,
+            }
+        }
     }
 
     pub fn add_function(&mut self, name: Symbol, decl: AstNodeId) {
@@ -156,3 +185,9 @@ impl Scope {
         }
     }
 }
+
+impl Default for Scope {
+    fn default() -> Self {
+        Scope::new()
+    }
+}
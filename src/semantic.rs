@@ -0,0 +1,741 @@
+use hashbrown::{HashMap, HashSet};
+use log::trace;
+
+use std::mem;
+
+use crate::ast::{
+    Assignment, AssignmentKind, Ast, AstNodeId, Decl, ExprKind, For, ForIter,
+    FunctionCall, FunctionDecl, Stmt, StmtList,
+};
+use crate::common::{grow_stack, Context, Diagnostic, Severity, Symbol};
+use crate::fuzzy;
+use crate::location::{Location, Span};
+use crate::messages::Message;
+use crate::primitives::{Value, ValueKind};
+use crate::scope::{Scope, Variable};
+use crate::typecheck;
+
+/// Checks the names in a program before it runs. Every variable has to be
+/// declared before it is used, also by the functions called before the
+/// declaration, a function can only be defined once in a block and builtins
+/// have to get as many arguments as they take. Types are left to the
+/// typechecker.
+#[allow(clippy::result_unit_err)]
+pub fn analyze(ast: &Ast, context: &mut Context) -> Result<(), ()> {
+    let mut analyzer = Analyzer::new(ast, context);
+    analyzer.stmt_list(&ast.statements);
+    analyzer.check_call_order();
+
+    let diagnostics = analyzer.diagnostics;
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        context.report_all(diagnostics);
+        Err(())
+    }
+}
+
+/// Checks only that no function is called before a global variable it uses
+/// is declared, for `statements` added to a program whose earlier
+/// statements have been checked already. Those can't be declared too late,
+/// so names that are not found in `statements` are left alone.
+pub(crate) fn check_call_order(
+    ast: &Ast,
+    statements: &StmtList,
+    context: &mut Context,
+) -> Result<(), ()> {
+    let mut analyzer = Analyzer::new(ast, context);
+    analyzer.stmt_list(statements);
+    analyzer.diagnostics.clear();
+    analyzer.check_call_order();
+
+    let diagnostics = analyzer.diagnostics;
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        context.report_all(diagnostics);
+        Err(())
+    }
+}
+
+/// A variable or function name in a program, see `resolve`.
+#[derive(Debug, Clone, Copy)]
+pub struct Name {
+    pub name: Symbol,
+    /// Where the name itself is.
+    pub location: Location,
+    /// Whether the variable or function is declared here.
+    pub declaration: bool,
+    /// The declaration the name is or refers to, numbered in the order
+    /// they are found. Builtins, constants and undefined names have none.
+    pub binding: Option<usize>,
+}
+
+/// Finds the variable and function names in a program and what each one
+/// refers to, by the same rules as `analyze`. A local that shadows a
+/// global, or a parameter named like a variable of another function, gets
+/// a binding of its own. Errors in the program are left for `analyze` to
+/// report.
+pub fn resolve(ast: &Ast, context: &mut Context) -> Vec<Name> {
+    let mut analyzer = Analyzer::new(ast, context);
+    analyzer.names = Some(vec![]);
+    analyzer.stmt_list(&ast.statements);
+    analyzer.names.unwrap_or_default()
+}
+
+/// A declared name close enough to `name` that it was likely meant instead,
+/// from the variables and functions visible in `scope`, the constants and
+/// the builtins.
+pub(crate) fn suggestion(
+    name: Symbol,
+    scope: &Scope,
+    context: &Context,
+) -> Option<String> {
+    let interner = &context.interner;
+    let candidates = scope
+        .visible_names()
+        .into_iter()
+        .chain(context.constants.keys().copied())
+        .chain(context.native_functions.keys().copied())
+        .map(|candidate| interner.get(candidate));
+    fuzzy::closest(interner.get(name), candidates).map(str::to_string)
+}
+
+struct Analyzer<'a> {
+    ast: &'a Ast,
+    context: &'a mut Context,
+    scope: Scope,
+    /// Errors are collected so that they can be shown in order.
+    diagnostics: Vec<Diagnostic>,
+    /// The names found so far, when they are being resolved.
+    names: Option<Vec<Name>>,
+    /// The binding of each function, by its declaration.
+    function_bindings: HashMap<AstNodeId, usize>,
+    bindings: usize,
+    /// The functions whose bodies are being checked, innermost last.
+    functions: Vec<AstNodeId>,
+    /// The variables declared outside of functions, by binding.
+    globals: HashMap<usize, Global>,
+    /// What each function uses, by its declaration.
+    uses: HashMap<AstNodeId, Uses>,
+    /// Functions called or passed on outside of any function, with when
+    /// and where.
+    calls: Vec<(AstNodeId, u32, Location)>,
+    /// Counts declarations, calls and ends of blocks outside of functions,
+    /// to tell which globals are declared when a function is called.
+    clock: u32,
+}
+
+struct Global {
+    name: Symbol,
+    declared: u32,
+    /// When the block it was declared in ended, if it has.
+    dropped: Option<u32>,
+}
+
+#[derive(Default)]
+struct Uses {
+    globals: Vec<usize>,
+    /// Functions it calls or passes on.
+    functions: Vec<AstNodeId>,
+}
+
+impl<'a> Analyzer<'a> {
+    fn new(ast: &'a Ast, context: &'a mut Context) -> Self {
+        Analyzer {
+            ast,
+            context,
+            scope: Scope::new(),
+            diagnostics: vec![],
+            names: None,
+            function_bindings: HashMap::new(),
+            bindings: 0,
+            functions: vec![],
+            globals: HashMap::new(),
+            uses: HashMap::new(),
+            calls: vec![],
+            clock: 0,
+        }
+    }
+
+    fn report_error(&mut self, message: Message, location: Location) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            message,
+            location,
+        });
+    }
+
+    fn name(&self, name: Symbol) -> String {
+        self.context.interner.get(name).to_string()
+    }
+
+    /// Declares the variable `name` in the current scope.
+    fn declare(&mut self, name: Symbol, location: Location) {
+        let binding = self.bind(name, location);
+        if self.functions.is_empty() {
+            self.clock += 1;
+            let global = Global {
+                name,
+                declared: self.clock,
+                dropped: None,
+            };
+            self.globals.insert(binding, global);
+        }
+        // There are no values to keep track of here, so each variable
+        // holds its binding instead.
+        let value = Value::Int(binding as i32);
+        self.scope.add_variable(name, value, ValueKind::Nil);
+    }
+
+    /// Starts a new binding, declared by `name` at `location`.
+    fn bind(&mut self, name: Symbol, location: Location) -> usize {
+        let binding = self.bindings;
+        self.bindings += 1;
+        self.record(name, location, true, Some(binding));
+        binding
+    }
+
+    fn record(
+        &mut self,
+        name: Symbol,
+        location: Location,
+        declaration: bool,
+        binding: Option<usize>,
+    ) {
+        // Names made up by the parser, like the one a `match` keeps its
+        // value in, are not in the source.
+        if let Some(names) = &mut self.names {
+            if !self.context.interner.get(name).contains('#') {
+                names.push(Name {
+                    name,
+                    location,
+                    declaration,
+                    binding,
+                });
+            }
+        }
+    }
+
+    /// Looks up a use of the variable `name`.
+    fn variable_binding(&mut self, name: Symbol) -> Option<usize> {
+        let binding = match self.scope.get_variable(name) {
+            Some(Variable {
+                value: Value::Int(binding),
+                ..
+            }) => *binding as usize,
+            _ => return None,
+        };
+
+        if let Some(func) = self.functions.last() {
+            if self.globals.contains_key(&binding) {
+                let uses = self.uses.entry(*func).or_insert_with(Uses::default);
+                uses.globals.push(binding);
+            }
+        }
+        Some(binding)
+    }
+
+    /// Looks up a use of the function `name`, which may call it from
+    /// `location`.
+    fn function_binding(
+        &mut self,
+        name: Symbol,
+        location: Location,
+    ) -> Option<usize> {
+        let decl = self.scope.get_function(name)?;
+
+        match self.functions.last() {
+            Some(func) => {
+                let uses = self.uses.entry(*func).or_insert_with(Uses::default);
+                uses.functions.push(decl);
+            }
+            None => {
+                self.clock += 1;
+                self.calls.push((decl, self.clock, location));
+            }
+        }
+        self.function_bindings.get(&decl).copied()
+    }
+
+    /// Reports calls, outside of functions, that run a function before a
+    /// global variable it uses is declared, or after the block it was
+    /// declared in has ended. The functions it calls count too.
+    fn check_call_order(&mut self) {
+        for (decl, at, location) in mem::take(&mut self.calls) {
+            let binding = match self.missing_global(decl, at) {
+                Some(binding) => binding,
+                None => continue,
+            };
+            let message = Message::UsedBeforeDeclared {
+                function: self.name(self.ast.arena[decl].function().name),
+                variable: self.name(self.globals[&binding].name),
+            };
+            self.report_error(message, location);
+        }
+    }
+
+    /// A global variable used by the function declared at `decl`, or by
+    /// one it calls, that is not declared at `at`.
+    fn missing_global(&self, decl: AstNodeId, at: u32) -> Option<usize> {
+        let mut seen = HashSet::new();
+        let mut functions = vec![decl];
+
+        while let Some(decl) = functions.pop() {
+            if !seen.insert(decl) {
+                continue;
+            }
+            let uses = match self.uses.get(&decl) {
+                Some(uses) => uses,
+                None => continue,
+            };
+            for binding in &uses.globals {
+                let global = &self.globals[binding];
+                let dropped = global.dropped.is_some_and(|end| end < at);
+                if global.declared > at || dropped {
+                    return Some(*binding);
+                }
+            }
+            functions.extend(&uses.functions);
+        }
+
+        None
+    }
+
+    /// Ends a block level that was entered when `first` was the next
+    /// binding.
+    fn pop_scope_level(&mut self, first: usize) {
+        self.scope.pop_scope_level();
+        if !self.functions.is_empty() {
+            return;
+        }
+
+        self.clock += 1;
+        for binding in first..self.bindings {
+            if let Some(global) = self.globals.get_mut(&binding) {
+                global.dropped = global.dropped.or(Some(self.clock));
+            }
+        }
+    }
+
+    fn stmt_list(&mut self, stmts: &StmtList) {
+        trace!("stmt_list");
+
+        // Functions can be called before they are declared.
+        let mut functions = HashMap::new();
+        for stmt in stmts {
+            if let Stmt::Decl(Decl::Function(func)) = &self.ast.arena[*stmt] {
+                if functions.insert(func.name, *stmt).is_some() {
+                    let message =
+                        Message::DuplicateFunction(self.name(func.name));
+                    self.report_error(message, func.location);
+                }
+                self.scope.add_function(func.name, *stmt);
+                let binding = self.bind(func.name, func.location);
+                self.function_bindings.insert(*stmt, binding);
+            }
+        }
+
+        for stmt in stmts {
+            self.stmt(*stmt);
+        }
+    }
+
+    fn block(&mut self, stmts: &StmtList) {
+        self.scope.new_scope_level();
+        let first = self.bindings;
+        self.stmt_list(stmts);
+        self.pop_scope_level(first);
+    }
+
+    fn stmt(&mut self, id: AstNodeId) {
+        grow_stack(|| {
+            let ast = self.ast;
+
+            match &ast.arena[id] {
+                Stmt::Assignment(assignment) => self.assignment(assignment),
+                Stmt::Block(stmts) => self.block(stmts),
+                Stmt::Decl(Decl::Variable(var)) => {
+                    self.expr(var.value);
+                    self.declare(var.name, var.location);
+                }
+                Stmt::Decl(Decl::Function(func)) => {
+                    self.function_decl(id, func)
+                }
+                Stmt::Decl(Decl::Struct(_)) => {}
+                Stmt::Expr(_) => self.expr(id),
+                Stmt::For(for_loop) => self.for_loop(for_loop),
+                Stmt::While(while_loop) => {
+                    self.expr(while_loop.cond);
+                    self.block(&while_loop.body);
+                }
+                Stmt::Print(print) => {
+                    for arg in &print.args {
+                        self.expr(*arg);
+                    }
+                }
+                Stmt::If(if_stmt) => {
+                    self.expr(if_stmt.cond);
+                    self.block(&if_stmt.body);
+                    if let Some(else_body) = &if_stmt.else_body {
+                        self.block(else_body);
+                    }
+                }
+                Stmt::Return(ret) => {
+                    if let Some(value) = ret.value {
+                        self.expr(value);
+                    }
+                }
+                Stmt::Break(_) | Stmt::Continue(_) | Stmt::Param(_) => {}
+            }
+        })
+    }
+
+    fn assignment(&mut self, assignment: &Assignment) {
+        trace!("Assignment");
+
+        for index in &assignment.indices {
+            self.expr(*index);
+        }
+        self.expr(assignment.value);
+
+        let location = Location {
+            span: Span {
+                start: assignment.location.span.start,
+                len: self.context.interner.get(assignment.ident).len() as u32,
+            },
+            ..assignment.location
+        };
+        let binding = self.variable_binding(assignment.ident);
+        self.record(assignment.ident, location, false, binding);
+        if binding.is_some() {
+            return;
+        }
+
+        let name = self.name(assignment.ident);
+        if self.context.constants.contains_key(&assignment.ident) {
+            let message = Message::AssignConstant(name);
+            self.report_error(message, assignment.location);
+            return;
+        }
+
+        let op = match assignment.op {
+            AssignmentKind::Assign => None,
+            AssignmentKind::Add => Some("+="),
+            AssignmentKind::Sub => Some("-="),
+            AssignmentKind::Mul => Some("*="),
+            AssignmentKind::Div => Some("/="),
+            AssignmentKind::Mod => Some("%="),
+        };
+        let message = match op {
+            Some(op) => Message::UpdateUndefined { name, op },
+            None => Message::UndefinedVariable {
+                name,
+                suggestion: suggestion(
+                    assignment.ident,
+                    &self.scope,
+                    self.context,
+                ),
+            },
+        };
+        self.report_error(message, assignment.location);
+    }
+
+    fn function_decl(&mut self, id: AstNodeId, func: &FunctionDecl) {
+        trace!("Function decl");
+
+        self.functions.push(id);
+        self.scope.push_scope();
+        for param in &func.params {
+            let param = self.ast.arena[*param].param();
+            self.declare(param.name, param.location);
+        }
+        self.stmt_list(&func.body);
+        self.scope.pop_scope();
+        self.functions.pop();
+    }
+
+    fn for_loop(&mut self, for_loop: &For) {
+        trace!("For");
+
+        if let ForIter::Expr(iter) = for_loop.iter {
+            self.expr(iter);
+        }
+
+        self.scope.new_scope_level();
+        let first = self.bindings;
+        let names = Some(&for_loop.ident).into_iter().chain(&for_loop.unpack);
+        for (name, location) in names.zip(&for_loop.locations) {
+            self.declare(*name, *location);
+        }
+        self.block(&for_loop.block);
+        self.pop_scope_level(first);
+    }
+
+    fn expr(&mut self, id: AstNodeId) {
+        grow_stack(|| {
+            let ast = self.ast;
+            let expr = ast.arena[id].expr();
+
+            match &expr.kind {
+                // A function can be passed as a value, like to `map`.
+                ExprKind::Ident(name) => {
+                    let binding = match self.variable_binding(*name) {
+                        Some(binding) => Some(binding),
+                        None => self.function_binding(*name, expr.location),
+                    };
+                    self.record(*name, expr.location, false, binding);
+
+                    if binding.is_none()
+                        && !self.context.constants.contains_key(name)
+                        && !self.context.native_functions.contains_key(name)
+                    {
+                        let message = Message::UndefinedVariable {
+                            name: self.name(*name),
+                            suggestion: suggestion(
+                                *name,
+                                &self.scope,
+                                self.context,
+                            ),
+                        };
+                        self.report_error(message, expr.location);
+                    }
+                }
+                ExprKind::Input(input) => {
+                    if let Some(message) = input.message {
+                        self.expr(message);
+                    }
+                }
+                ExprKind::UnaryOp(op) => self.expr(op.value),
+                ExprKind::BinaryOp(op) => {
+                    self.expr(op.lhs);
+                    self.expr(op.rhs);
+                }
+                ExprKind::FunctionCall(call) => {
+                    let binding =
+                        self.function_binding(call.name, expr.location);
+                    self.record(call.name, call.location, false, binding);
+                    for arg in &call.args {
+                        self.expr(*arg);
+                    }
+                    self.check_arity(call, expr.location);
+                }
+                ExprKind::Try(try_op) => self.expr(try_op.value),
+                ExprKind::Array(elements)
+                | ExprKind::Interpolation(elements) => {
+                    for element in elements {
+                        self.expr(*element);
+                    }
+                }
+                ExprKind::Map(pairs) => {
+                    for (key, value) in pairs {
+                        self.expr(*key);
+                        self.expr(*value);
+                    }
+                }
+                ExprKind::Index(index) => {
+                    self.expr(index.value);
+                    self.expr(index.index);
+                }
+                ExprKind::Float(_)
+                | ExprKind::Integer(_)
+                | ExprKind::StringLiteral(_)
+                | ExprKind::Range(_)
+                | ExprKind::Bool(_) => {}
+            }
+        })
+    }
+
+    /// Checks the number of arguments in a call to a builtin. Calls to
+    /// functions in the script are left to the typechecker.
+    fn check_arity(&mut self, call: &FunctionCall, location: Location) {
+        if self.scope.get_function(call.name).is_some() {
+            return;
+        }
+
+        let name = self.name(call.name);
+        let found = call.args.len();
+        let expected = match name.as_str() {
+            "Ok" | "Err" => {
+                if found != 1 {
+                    let message = Message::ResultArity { name, found };
+                    self.report_error(message, location);
+                }
+                return;
+            }
+            "eval" => {
+                if found == 0 || found > 2 {
+                    let message = Message::Arity {
+                        name,
+                        expected: found.clamp(1, 2),
+                        found,
+                    };
+                    self.report_error(message, location);
+                }
+                return;
+            }
+            "partial" => {
+                if found < 2 {
+                    let message = Message::TooFewArguments {
+                        name,
+                        at_least: 2,
+                        found,
+                    };
+                    self.report_error(message, location);
+                }
+                return;
+            }
+            _ => match typecheck::generic_arity(&name) {
+                Some(arity) => arity,
+                None => match self.context.native_functions.get(&call.name) {
+                    Some(func) => func.params.len(),
+                    // Undefined functions are reported by the typechecker.
+                    None => return,
+                },
+            },
+        };
+
+        if found != expected {
+            let message = Message::Arity {
+                name,
+                expected,
+                found,
+            };
+            self.report_error(message, location);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::builtins;
+    use crate::lexer;
+    use crate::parser;
+
+    fn analyze_str(source: &str) -> bool {
+        messages(source).is_empty()
+    }
+
+    fn messages(source: &str) -> Vec<Message> {
+        let mut context = Context::new();
+        builtins::register(&mut context);
+        let file = context.interner.intern("test.bl");
+        context
+            .source_code
+            .insert(PathBuf::from("test.bl"), source.to_string());
+
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .expect("Lexing failed");
+        let ast =
+            parser::parse_ast(tokens, &mut context).expect("Parsing failed");
+
+        let mut analyzer = Analyzer::new(&ast, &mut context);
+        analyzer.stmt_list(&ast.statements);
+        analyzer.check_call_order();
+        analyzer
+            .diagnostics
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect()
+    }
+
+    #[test]
+    fn names() {
+        assert!(analyze_str("a := 1 a += 2 for i in [a] { a = i }"));
+        assert!(analyze_str("f() fn f() { } { fn f() { } }"));
+        assert!(analyze_str("n := 1 fn f(m: int) -> int { return m + n }"));
+        assert!(!analyze_str("b := a"));
+        assert!(!analyze_str("a = 1"));
+        assert!(!analyze_str("total += 1"));
+        assert!(!analyze_str("fn f(n: int) { } m := n"));
+        assert!(!analyze_str("for i in 0..3 { } n := i"));
+        assert!(!analyze_str("fn f() { } fn g() { } fn f() { }"));
+    }
+
+    #[test]
+    fn globals_used_by_called_functions() {
+        let early = |function: &str, variable: &str| {
+            vec![Message::UsedBeforeDeclared {
+                function: function.to_string(),
+                variable: variable.to_string(),
+            }]
+        };
+
+        assert!(analyze_str("n := 1 x := f() fn f() -> int { return n }"));
+        assert_eq!(
+            messages("x := f() n := 1 fn f() -> int { return n }"),
+            early("f", "n")
+        );
+        assert_eq!(messages("f() n := 1 fn f() { n += 1 }"), early("f", "n"));
+
+        // Through other functions and when passed on to be called later.
+        assert_eq!(
+            messages(
+                "x := g() n := 1 \
+                 fn g() -> int { return f() } \
+                 fn f() -> int { return n }"
+            ),
+            early("g", "n")
+        );
+        assert_eq!(
+            messages(
+                "ys := map([1], f) n := 1 \
+                 fn f(a: int) -> int { return a + n }"
+            ),
+            early("f", "n")
+        );
+
+        // Only calls that run code count, not calls between functions.
+        assert!(analyze_str(
+            "fn g() -> int { return f() } n := 1 \
+             fn f() -> int { return n } x := g()"
+        ));
+        assert!(analyze_str(
+            "n := 1 fn f(k: int) -> int { \
+                 if k > 0 { return f(k - 1) } return n \
+             } x := f(2)"
+        ));
+
+        // A global is gone after its block ends.
+        assert_eq!(
+            messages("{ n := 1 fn f() -> int { return n } } x := f()"),
+            early("f", "n")
+        );
+    }
+
+    #[test]
+    fn builtin_arity() {
+        assert!(analyze_str("xs := [1] push(xs, len(xs)) s := trim(\"a\")"));
+        assert!(analyze_str("fn len(a: int, b: int) { } len(1, 2)"));
+        assert!(!analyze_str("xs := [1] push(xs)"));
+        assert!(!analyze_str("s := trim(\"a\", \"b\")"));
+        assert!(!analyze_str("fn f() -> result { return Ok(1, 2) }"));
+        assert!(!analyze_str("f := partial(trim)"));
+    }
+
+    #[test]
+    fn suggestions() {
+        let undefined =
+            |name: &str, suggestion: Option<&str>| Message::UndefinedVariable {
+                name: name.to_string(),
+                suggestion: suggestion.map(str::to_string),
+            };
+        assert_eq!(
+            messages("counter := 1 n := countr"),
+            [undefined("countr", Some("counter"))]
+        );
+        assert_eq!(
+            messages("f := to_uppr fn total() { } n := totl"),
+            [
+                undefined("to_uppr", Some("to_upper")),
+                undefined("totl", Some("total"))
+            ]
+        );
+        assert_eq!(messages("x := yyyyy"), [undefined("yyyyy", None)]);
+    }
+}
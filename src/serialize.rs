@@ -0,0 +1,285 @@
+//! Converts values to and from any serde format, with the `serde` feature.
+//!
+//! Strings in a value are symbols of the interner they were made with, so
+//! a value is serialized together with its interner, and deserialized with
+//! a `ValueSeed` that interns the strings it reads.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+use serde::de::{
+    self, DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
+};
+use serde::ser::{self, SerializeMap, SerializeSeq};
+use serde::{Deserializer, Serialize, Serializer};
+
+use crate::common::StringInterner;
+use crate::primitives::{Map, MapKey, Value};
+
+/// A value that serializes with the strings of `interner`.
+///
+/// Nil is a unit, arrays, heaps and deques are sequences, maps are maps in
+/// the order their keys were added, and `Ok` and `Err` are variants of an
+/// enum named `Result`. Functions, iterators and structs have no
+/// serialized form and fail.
+pub struct Serializable<'a> {
+    pub value: &'a Value,
+    pub interner: &'a StringInterner,
+}
+
+impl<'a> Serializable<'a> {
+    pub fn new(value: &'a Value, interner: &'a StringInterner) -> Self {
+        Serializable { value, interner }
+    }
+
+    fn of(&self, value: &'a Value) -> Serializable<'a> {
+        Serializable::new(value, self.interner)
+    }
+}
+
+impl Serialize for Serializable<'_> {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match self.value {
+            Value::Nil => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Int(n) => serializer.serialize_i32(*n),
+            Value::Float(f) => serializer.serialize_f64(*f),
+            Value::String(s) => serializer.serialize_str(self.interner.get(*s)),
+            Value::Array(values) | Value::Heap(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values.iter() {
+                    seq.serialize_element(&self.of(value))?;
+                }
+                seq.end()
+            }
+            Value::Deque(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values.iter() {
+                    seq.serialize_element(&self.of(value))?;
+                }
+                seq.end()
+            }
+            Value::Map(map) => {
+                let mut entries = serializer.serialize_map(Some(map.len()))?;
+                for (key, value) in map.iter() {
+                    entries.serialize_entry(
+                        &self.of(&key.to_value()),
+                        &self.of(value),
+                    )?;
+                }
+                entries.end()
+            }
+            Value::Ok(value) => serializer.serialize_newtype_variant(
+                "Result",
+                0,
+                "Ok",
+                &self.of(value),
+            ),
+            Value::Err(value) => serializer.serialize_newtype_variant(
+                "Result",
+                1,
+                "Err",
+                &self.of(value),
+            ),
+            Value::Function(_) => {
+                Err(ser::Error::custom("functions can not be serialized"))
+            }
+            Value::Iter(_) => {
+                Err(ser::Error::custom("iterators can not be serialized"))
+            }
+            Value::Struct(_) => {
+                Err(ser::Error::custom("structs can not be serialized"))
+            }
+        }
+    }
+}
+
+/// Deserializes a value from any self-describing format, interning its
+/// strings.
+///
+/// Integers that fit in an int become ints and other numbers floats, as
+/// with `from_json`. Map keys have to be bools, ints or strings. Formats
+/// with enums read the variants `Ok` and `Err` as results, but a result
+/// written as JSON comes back as a map with one key.
+pub struct ValueSeed<'a> {
+    pub interner: &'a mut StringInterner,
+}
+
+impl<'a> ValueSeed<'a> {
+    pub fn new(interner: &'a mut StringInterner) -> Self {
+        ValueSeed { interner }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for ValueSeed<'_> {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ValueSeed<'_> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a value blixt has")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<Value, E> {
+        Ok(i32::try_from(n).map_or(Value::Float(n as f64), Value::Int))
+    }
+
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<Value, E> {
+        Ok(i32::try_from(n).map_or(Value::Float(n as f64), Value::Int))
+    }
+
+    fn visit_f64<E: de::Error>(self, f: f64) -> Result<Value, E> {
+        Ok(Value::Float(f))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::String(self.interner.intern(s)))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> Result<Value, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(value) =
+            seq.next_element_seed(ValueSeed::new(&mut *self.interner))?
+        {
+            values.push(value);
+        }
+        Ok(Value::Array(Rc::new(values)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut entries: A,
+    ) -> Result<Value, A::Error> {
+        let mut map = Map::new();
+        while let Some(key) =
+            entries.next_key_seed(ValueSeed::new(&mut *self.interner))?
+        {
+            let key = MapKey::from_value(&key).ok_or_else(|| {
+                de::Error::custom("map keys have to be bools, ints or strings")
+            })?;
+            let value =
+                entries.next_value_seed(ValueSeed::new(&mut *self.interner))?;
+            map.insert(key, value);
+        }
+        Ok(Value::Map(Rc::new(map)))
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(
+        self,
+        data: A,
+    ) -> Result<Value, A::Error> {
+        let (variant, data): (String, _) = data.variant()?;
+        let value = data.newtype_variant_seed(ValueSeed::new(self.interner))?;
+        match variant.as_str() {
+            "Ok" => Ok(Value::Ok(Box::new(value))),
+            "Err" => Ok(Value::Err(Box::new(value))),
+            other => Err(de::Error::unknown_variant(other, &["Ok", "Err"])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+
+    fn to_json(value: &Value, interner: &StringInterner) -> String {
+        serde_json::to_string(&Serializable::new(value, interner)).unwrap()
+    }
+
+    fn from_json(text: &str, interner: &mut StringInterner) -> Value {
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        ValueSeed::new(interner)
+            .deserialize(&mut deserializer)
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trip_through_json() {
+        let mut interner = StringInterner::new();
+        let text = r#"{"name":"blixt","tags":["a","b"],"size":3,"ratio":0.5,"ok":true,"none":null}"#;
+
+        let value = from_json(text, &mut interner);
+        assert_eq!(to_json(&value, &interner), text);
+    }
+
+    #[test]
+    fn numbers_that_do_not_fit_become_floats() {
+        let mut interner = StringInterner::new();
+        assert_eq!(from_json("[1, -2, 5000000000]", &mut interner), {
+            let values = vec![Value::Int(1), Value::Int(-2), Value::Float(5e9)];
+            Value::Array(Rc::new(values))
+        });
+    }
+
+    #[test]
+    fn sequences_and_results() {
+        let interner = StringInterner::new();
+        let deque: VecDeque<_> = vec![Value::Int(1), Value::Nil].into();
+        let ok = Value::Ok(Box::new(Value::Int(2)));
+
+        assert_eq!(
+            to_json(&Value::Deque(Rc::new(deque)), &interner),
+            "[1,null]"
+        );
+        assert_eq!(to_json(&ok, &interner), r#"{"Ok":2}"#);
+    }
+
+    #[test]
+    fn unserializable_values_fail() {
+        let mut interner = StringInterner::new();
+        let name = interner.intern("f");
+        let func = Value::Function(crate::primitives::Function {
+            name,
+            callee: crate::primitives::Callee::Native,
+        });
+
+        let result =
+            serde_json::to_string(&Serializable::new(&func, &interner));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use crate::common::{StringInterner, Symbol};
+use crate::messages::Message;
+use crate::primitives::{Callee, Function, Map, MapKey, Value};
+use crate::scope::{Scope, Variable};
+
+/// Written first, so that other files are not mistaken for snapshots.
+const MAGIC: &[u8] = b"blixt-snapshot";
+const VERSION: u8 = 1;
+
+/// Writes the sources an engine has evaluated, and the values of its global
+/// variables, as bytes that `Reader` can read back.
+///
+/// Functions are saved by name. Iterators cannot be saved.
+pub fn save(
+    sources: &[(String, String)],
+    globals: &[Variable],
+    interner: &StringInterner,
+) -> Result<Vec<u8>, Message> {
+    let mut writer = Writer {
+        bytes: MAGIC.to_vec(),
+        interner,
+    };
+    writer.bytes.push(VERSION);
+
+    writer.len(sources.len());
+    for (name, source) in sources {
+        writer.string(name);
+        writer.string(source);
+    }
+
+    writer.len(globals.len());
+    for var in globals {
+        writer.symbol(var.name);
+        if !writer.value(&var.value) {
+            let name = interner.get(var.name).to_string();
+            return Err(Message::CannotSave(name));
+        }
+    }
+
+    Ok(writer.bytes)
+}
+
+struct Writer<'a> {
+    bytes: Vec<u8>,
+    interner: &'a StringInterner,
+}
+
+impl<'a> Writer<'a> {
+    fn len(&mut self, len: usize) {
+        self.bytes.extend(&(len as u32).to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.len(s.len());
+        self.bytes.extend(s.as_bytes());
+    }
+
+    fn symbol(&mut self, symbol: Symbol) {
+        self.string(self.interner.get(symbol));
+    }
+
+    fn values<'v>(
+        &mut self,
+        values: impl ExactSizeIterator<Item = &'v Value>,
+    ) -> bool {
+        self.len(values.len());
+        let mut saved = true;
+        for value in values {
+            saved &= self.value(value);
+        }
+        saved
+    }
+
+    /// Returns false if `value` holds something that can't be saved.
+    fn value(&mut self, value: &Value) -> bool {
+        match value {
+            Value::Nil => self.bytes.push(0),
+            Value::Bool(b) => self.bytes.extend(&[1, *b as u8]),
+            Value::Int(n) => {
+                self.bytes.push(2);
+                self.bytes.extend(&n.to_le_bytes());
+            }
+            Value::Float(n) => {
+                self.bytes.push(3);
+                self.bytes.extend(&n.to_bits().to_le_bytes());
+            }
+            Value::String(s) => {
+                self.bytes.push(4);
+                self.symbol(*s);
+            }
+            Value::Array(values) => {
+                self.bytes.push(5);
+                return self.values(values.iter());
+            }
+            Value::Map(map) => {
+                self.bytes.push(6);
+                self.len(map.len());
+                let mut saved = true;
+                for (key, value) in map.iter() {
+                    saved &= self.value(&key.to_value());
+                    saved &= self.value(value);
+                }
+                return saved;
+            }
+            Value::Heap(values) => {
+                self.bytes.push(7);
+                return self.values(values.iter());
+            }
+            Value::Deque(values) => {
+                self.bytes.push(8);
+                return self.values(values.iter());
+            }
+            Value::Function(func) => {
+                self.bytes.push(9);
+                return self.function(func);
+            }
+            Value::Ok(value) => {
+                self.bytes.push(10);
+                return self.value(value);
+            }
+            Value::Err(value) => {
+                self.bytes.push(11);
+                return self.value(value);
+            }
+            Value::Iter(_) | Value::Struct(_) => return false,
+        }
+
+        true
+    }
+
+    fn function(&mut self, func: &Function) -> bool {
+        self.symbol(func.name);
+
+        match &func.callee {
+            Callee::Node(_) => self.bytes.push(0),
+            Callee::Native => self.bytes.push(1),
+            Callee::Partial(partial) => {
+                let (func, args) = &**partial;
+                self.bytes.push(2);
+                return self.function(func) && self.values(args.iter());
+            }
+            Callee::Compose(compose) => {
+                let (first, second) = &**compose;
+                self.bytes.push(3);
+                return self.function(first) && self.function(second);
+            }
+            // Engines only run the syntax tree.
+            Callee::Compiled(_) => return false,
+        }
+
+        true
+    }
+}
+
+/// Reads a snapshot written by `save`. The sources come first, so that the
+/// functions they declare are known before the values that refer to them
+/// are read.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, Message> {
+        let header = MAGIC.len() + 1;
+        if bytes.len() < header
+            || &bytes[..MAGIC.len()] != MAGIC
+            || bytes[MAGIC.len()] != VERSION
+        {
+            return Err(Message::InvalidSnapshot);
+        }
+
+        Ok(Reader { bytes, pos: header })
+    }
+
+    /// Returns the name and text of each source, in the order they were
+    /// evaluated.
+    pub fn sources(&mut self) -> Result<Vec<(String, String)>, Message> {
+        let count = self.len()?;
+        (0..count)
+            .map(|_| Ok((self.string()?, self.string()?)))
+            .collect()
+    }
+
+    /// Returns the global variables, with functions looked up by name in
+    /// `scope`.
+    pub fn globals(
+        &mut self,
+        interner: &mut StringInterner,
+        scope: &mut Scope,
+    ) -> Result<Vec<(Symbol, Value)>, Message> {
+        let count = self.len()?;
+        let globals = (0..count)
+            .map(|_| {
+                let name = self.string()?;
+                let name = interner.intern(&name);
+                Ok((name, self.value(interner, scope)?))
+            })
+            .collect::<Result<_, _>>()?;
+
+        if self.pos == self.bytes.len() {
+            Ok(globals)
+        } else {
+            Err(Message::InvalidSnapshot)
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Message> {
+        let end = self.pos.checked_add(len).ok_or(Message::InvalidSnapshot)?;
+        let bytes = self.bytes.get(self.pos..end);
+        self.pos = end;
+        bytes.ok_or(Message::InvalidSnapshot)
+    }
+
+    fn byte(&mut self) -> Result<u8, Message> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<[u8; 4], Message> {
+        <[u8; 4]>::try_from(self.take(4)?).map_err(|_| Message::InvalidSnapshot)
+    }
+
+    fn len(&mut self) -> Result<usize, Message> {
+        Ok(u32::from_le_bytes(self.word()?) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, Message> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Message::InvalidSnapshot)
+    }
+
+    fn values(
+        &mut self,
+        interner: &mut StringInterner,
+        scope: &mut Scope,
+    ) -> Result<Vec<Value>, Message> {
+        let len = self.len()?;
+        (0..len).map(|_| self.value(interner, scope)).collect()
+    }
+
+    fn value(
+        &mut self,
+        interner: &mut StringInterner,
+        scope: &mut Scope,
+    ) -> Result<Value, Message> {
+        let value = match self.byte()? {
+            0 => Value::Nil,
+            1 => Value::Bool(self.byte()? != 0),
+            2 => Value::Int(i32::from_le_bytes(self.word()?)),
+            3 => {
+                let bytes = <[u8; 8]>::try_from(self.take(8)?)
+                    .map_err(|_| Message::InvalidSnapshot)?;
+                Value::Float(f64::from_bits(u64::from_le_bytes(bytes)))
+            }
+            4 => Value::String(interner.intern(&self.string()?)),
+            5 => Value::Array(Rc::new(self.values(interner, scope)?)),
+            6 => {
+                let len = self.len()?;
+                let mut map = Map::new();
+                for _ in 0..len {
+                    let key = self.value(interner, scope)?;
+                    let key = MapKey::from_value(&key)
+                        .ok_or(Message::InvalidSnapshot)?;
+                    map.insert(key, self.value(interner, scope)?);
+                }
+                Value::Map(Rc::new(map))
+            }
+            7 => Value::Heap(Rc::new(self.values(interner, scope)?)),
+            8 => {
+                let values = self.values(interner, scope)?;
+                Value::Deque(Rc::new(VecDeque::from(values)))
+            }
+            9 => Value::Function(self.function(interner, scope)?),
+            10 => Value::Ok(Box::new(self.value(interner, scope)?)),
+            11 => Value::Err(Box::new(self.value(interner, scope)?)),
+            _ => return Err(Message::InvalidSnapshot),
+        };
+
+        Ok(value)
+    }
+
+    fn function(
+        &mut self,
+        interner: &mut StringInterner,
+        scope: &mut Scope,
+    ) -> Result<Function, Message> {
+        let name = interner.intern(&self.string()?);
+        let callee = match self.byte()? {
+            0 => match scope.get_function(name) {
+                Some(node) => Callee::Node(node),
+                None => return Err(Message::InvalidSnapshot),
+            },
+            1 => Callee::Native,
+            2 => {
+                let func = self.function(interner, scope)?;
+                let args = self.values(interner, scope)?;
+                Callee::Partial(Rc::new((func, args)))
+            }
+            3 => {
+                let first = self.function(interner, scope)?;
+                let second = self.function(interner, scope)?;
+                Callee::Compose(Rc::new((first, second)))
+            }
+            _ => return Err(Message::InvalidSnapshot),
+        };
+
+        Ok(Function { name, callee })
+    }
+}
@@ -0,0 +1,98 @@
+/// The middle of `values` once sorted, or the mean of the two middle ones
+/// if there is an even number of them. `None` if there are none.
+pub fn median(values: &[f64]) -> Option<f64> {
+    percentile(values, 50.0)
+}
+
+/// The population standard deviation of `values`, how far they are from
+/// their mean on average. `None` if there are none.
+pub fn stddev(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let count = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / count;
+    let variance =
+        values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / count;
+    Some(variance.sqrt())
+}
+
+/// The value that `p` percent of `values` are below, from 0 to 100,
+/// interpolating linearly between the two closest values when it falls
+/// between them. `None` if there are no values.
+pub fn percentile(values: &[f64], p: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+
+    let rank = p.clamp(0.0, 100.0) / 100.0 * (sorted.len() - 1) as f64;
+    let below = rank.floor() as usize;
+    let above = rank.ceil() as usize;
+    let fraction = rank - below as f64;
+    Some(sorted[below] + (sorted[above] - sorted[below]) * fraction)
+}
+
+/// Splits the range from the smallest to the largest of `values` into
+/// `buckets` of equal width and counts the values in each. Returns where
+/// each bucket starts and its count. The largest value goes in the last
+/// bucket, and if all values are equal they all go in the first.
+pub fn histogram(values: &[f64], buckets: usize) -> Vec<(f64, usize)> {
+    if values.is_empty() || buckets == 0 {
+        return Vec::new();
+    }
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let width = (max - min) / buckets as f64;
+
+    let mut counts = vec![0; buckets];
+    for value in values {
+        let bucket = if width > 0.0 {
+            ((value - min) / width) as usize
+        } else {
+            0
+        };
+        counts[bucket.min(buckets - 1)] += 1;
+    }
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| (min + width * i as f64, count))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn medians_and_percentiles() {
+        assert_eq!(median(&[3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(median(&[4.0, 1.0, 3.0, 2.0]), Some(2.5));
+        assert_eq!(median(&[]), None);
+
+        let values = [15.0, 20.0, 35.0, 40.0, 50.0];
+        assert_eq!(percentile(&values, 0.0), Some(15.0));
+        assert_eq!(percentile(&values, 100.0), Some(50.0));
+        assert_eq!(percentile(&values, 25.0), Some(20.0));
+        assert_eq!(percentile(&values, 62.5), Some(37.5));
+        assert_eq!(percentile(&[7.0], 90.0), Some(7.0));
+    }
+
+    #[test]
+    fn deviations() {
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(stddev(&values), Some(2.0));
+        assert_eq!(stddev(&[1.0]), Some(0.0));
+        assert_eq!(stddev(&[]), None);
+    }
+
+    #[test]
+    fn histograms() {
+        let values = [1.0, 2.0, 2.5, 3.0, 5.0];
+        assert_eq!(histogram(&values, 2), [(1.0, 3), (3.0, 2)],);
+        assert_eq!(histogram(&[4.0, 4.0], 3), [(4.0, 2), (4.0, 0), (4.0, 0)]);
+        assert_eq!(histogram(&[], 3), []);
+    }
+}
@@ -0,0 +1,314 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt;
+use std::io::Write;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use blixt::common::Context;
+use blixt::crash;
+
+/// Timings of the compiler phases and counts of what they produced, printed
+/// at exit when running with `--stats`.
+#[derive(Default)]
+pub struct Stats {
+    phases: Vec<Phase>,
+    counters: Vec<(&'static str, usize)>,
+    /// Where to write each phase as a JSON line, with `--trace-format=json`.
+    trace: Option<Box<dyn Write>>,
+    /// The last phase, if it has not been written to `trace` yet.
+    pending: bool,
+}
+
+struct Phase {
+    name: &'static str,
+    time: Duration,
+    /// Allocations made during the phase, if they are being counted.
+    allocations: Option<usize>,
+    counts: Vec<(&'static str, usize)>,
+    errors: usize,
+    warnings: usize,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats::default()
+    }
+
+    pub fn with_trace(trace: Box<dyn Write>) -> Self {
+        Stats {
+            trace: Some(trace),
+            ..Stats::default()
+        }
+    }
+
+    pub fn time<T>(
+        &mut self,
+        phase: &'static str,
+        context: &mut Context,
+        f: impl FnOnce(&mut Context) -> T,
+    ) -> T {
+        self.flush();
+        crash::enter(phase);
+
+        let (errors, warnings) = (context.errors, context.warnings);
+        let allocations = allocations();
+        let start = Instant::now();
+        let result = f(context);
+        self.phases.push(Phase {
+            name: phase,
+            time: start.elapsed(),
+            allocations: allocations.and_then(allocations_since),
+            counts: vec![],
+            errors: context.errors - errors,
+            warnings: context.warnings - warnings,
+        });
+        self.pending = true;
+
+        // What ran during the phase, the most run first.
+        if let Some(profile) = &mut context.profile {
+            let mut counts: Vec<_> = mem::take(profile).into_iter().collect();
+            counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            if !counts.is_empty() {
+                self.count("executed", counts.iter().map(|(_, n)| n).sum());
+            }
+            for (kind, count) in counts {
+                self.count(kind, count);
+            }
+        }
+
+        result
+    }
+
+    /// Counts something the last phase produced.
+    pub fn count(&mut self, name: &'static str, count: usize) {
+        self.counters.push((name, count));
+        if let Some(phase) = self.phases.last_mut() {
+            phase.counts.push((name, count));
+        }
+    }
+
+    /// Writes the last phase to the trace, once its counts are in.
+    pub fn flush(&mut self) {
+        if !self.pending {
+            return;
+        }
+        self.pending = false;
+
+        if let (Some(trace), Some(phase)) =
+            (&mut self.trace, self.phases.last())
+        {
+            // Tooling reading the trace should not stop the script.
+            let _ = writeln!(trace, "{}", phase.to_json());
+            let _ = trace.flush();
+        }
+    }
+}
+
+impl Phase {
+    fn to_json(&self) -> String {
+        let counts: Vec<String> = self
+            .counts
+            .iter()
+            .map(|(name, count)| format!("\"{}\":{}", name, count))
+            .collect();
+
+        let allocations = self
+            .allocations
+            .map_or(String::new(), |n| format!("\"allocations\":{},", n));
+
+        format!(
+            "{{\"phase\":\"{}\",\"duration_us\":{},{}\"counts\":{{{}}},\
+             \"diagnostics\":{{\"errors\":{},\"warnings\":{}}}}}",
+            self.name,
+            self.time.as_micros(),
+            allocations,
+            counts.join(","),
+            self.errors,
+            self.warnings
+        )
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total: Duration = self.phases.iter().map(|phase| phase.time).sum();
+
+        for phase in &self.phases {
+            write!(f, "{:<12}{:>12.3?}", phase.name, phase.time)?;
+            if let Some(allocations) = phase.allocations {
+                write!(f, "{:>12} allocations", allocations)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "{:<12}{:>12.3?}", "total", total)?;
+
+        for (name, count) in &self.counters {
+            writeln!(f, "{:<12}{:>12}", name, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The global allocator of the binary, which counts allocations once
+/// `count_allocations` is called. Values are reference counted, so there
+/// are no garbage collections to count, but allocations show where the
+/// memory goes.
+pub struct CountingAllocator;
+
+static COUNTING: AtomicBool = AtomicBool::new(false);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn count_allocation() {
+    if COUNTING.load(Ordering::Relaxed) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Starts counting allocations, which costs a little on every one of them
+/// and so is only done with `--stats`.
+pub fn count_allocations() {
+    COUNTING.store(true, Ordering::Relaxed);
+}
+
+/// Allocations so far, if they are being counted.
+fn allocations() -> Option<usize> {
+    if COUNTING.load(Ordering::Relaxed) {
+        Some(ALLOCATIONS.load(Ordering::Relaxed))
+    } else {
+        None
+    }
+}
+
+fn allocations_since(before: usize) -> Option<usize> {
+    allocations().map(|now| now - before)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn phase(name: &'static str, millis: u64) -> Phase {
+        Phase {
+            name,
+            time: Duration::from_millis(millis),
+            allocations: None,
+            counts: vec![],
+            errors: 0,
+            warnings: 0,
+        }
+    }
+
+    #[test]
+    fn display_stats() {
+        let mut stats = Stats::new();
+        stats.phases.push(phase("lex", 2));
+        stats.phases.push(phase("parse", 3));
+        stats.count("tokens", 42);
+
+        assert_eq!(
+            stats.to_string(),
+            "lex              2.000ms\n\
+             parse            3.000ms\n\
+             total            5.000ms\n\
+             tokens                42\n"
+        );
+    }
+
+    #[test]
+    fn count_what_ran() {
+        let mut stats = Stats::new();
+        let mut context = Context::new();
+        context.profile = Some(Default::default());
+
+        stats.time("run", &mut context, |context| {
+            for kind in ["Load", "Call", "Load", "Return"] {
+                context.profile(kind);
+            }
+        });
+        stats.time("idle", &mut context, |_| {});
+
+        assert_eq!(
+            stats.counters,
+            [("executed", 4), ("Load", 2), ("Call", 1), ("Return", 1)]
+        );
+        assert_eq!(stats.phases[1].counts, []);
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_trace() {
+        let buffer = Buffer::default();
+        let mut stats = Stats::with_trace(Box::new(buffer.clone()));
+        let mut context = Context::new();
+
+        stats.time("lex", &mut context, |context| context.warnings += 1);
+        stats.count("tokens", 3);
+        stats.count("lines", 1);
+        assert_eq!(buffer.0.borrow().len(), 0);
+        stats.time("run", &mut context, |context| context.errors += 2);
+        stats.flush();
+        stats.flush();
+
+        let trace = String::from_utf8(buffer.0.take()).unwrap();
+        let lines: Vec<_> = trace
+            .lines()
+            .map(|line| {
+                let start = line.find("\"duration_us\"").unwrap();
+                let end = start + line[start..].find(',').unwrap();
+                format!("{}{}", &line[..start], &line[end + 1..])
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "{\"phase\":\"lex\",\"counts\":{\"tokens\":3,\"lines\":1},\
+                 \"diagnostics\":{\"errors\":0,\"warnings\":1}}",
+                "{\"phase\":\"run\",\"counts\":{},\
+                 \"diagnostics\":{\"errors\":2,\"warnings\":0}}",
+            ]
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use crate::text;
+
+/// How `format_table` lays out a table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Style {
+    /// Columns padded with spaces, with a line of dashes under the headers.
+    Plain,
+    Markdown,
+    Csv,
+}
+
+impl Style {
+    pub fn from_name(name: &str) -> Option<Style> {
+        match name {
+            "plain" => Some(Style::Plain),
+            "markdown" => Some(Style::Markdown),
+            "csv" => Some(Style::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// Lays out `rows` under `headers`, which may be empty. Rows shorter than
+/// the others get empty cells at the end.
+pub fn format(
+    headers: &[String],
+    rows: &[Vec<String>],
+    style: Style,
+) -> String {
+    let columns = rows
+        .iter()
+        .map(Vec::len)
+        .chain(Some(headers.len()))
+        .max()
+        .unwrap_or(0);
+
+    if style == Style::Csv {
+        let mut output = String::new();
+        for row in Some(headers)
+            .filter(|h| !h.is_empty())
+            .into_iter()
+            .chain(rows.iter().map(Vec::as_slice))
+        {
+            let cells: Vec<_> = (0..columns)
+                .map(|i| csv_field(row.get(i).map_or("", String::as_str)))
+                .collect();
+            output += &cells.join(",");
+            output.push('\n');
+        }
+        return output;
+    }
+
+    let mut widths = vec![0; columns];
+    for row in rows.iter().map(Vec::as_slice).chain(Some(headers)) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(text::display_width(cell));
+        }
+    }
+    if style == Style::Markdown {
+        // Markdown needs at least three dashes under each header.
+        for width in &mut widths {
+            *width = (*width).max(3);
+        }
+    }
+
+    let line = |row: &[String]| {
+        let cells: Vec<_> = widths
+            .iter()
+            .enumerate()
+            .map(|(i, width)| {
+                let cell = row.get(i).map_or("", String::as_str);
+                let padding = width - text::display_width(cell);
+                format!("{}{}", cell, " ".repeat(padding))
+            })
+            .collect();
+        match style {
+            Style::Markdown => format!("| {} |\n", cells.join(" | ")),
+            _ => format!("{}\n", cells.join("  ").trim_end()),
+        }
+    };
+    let dashes: Vec<_> =
+        widths.iter().map(|width| "-".repeat(*width)).collect();
+
+    let mut output = String::new();
+    if !headers.is_empty() || style == Style::Markdown {
+        output += &line(headers);
+        output += &line(&dashes);
+    }
+    for row in rows {
+        output += &line(row);
+    }
+    output
+}
+
+/// Cuts every line of `output` to at most `width` columns.
+pub fn truncate(output: &str, width: usize) -> String {
+    output
+        .lines()
+        .map(|line| text::truncate(line, width) + "\n")
+        .collect()
+}
+
+/// The width of the terminal standard output is written to, or None if it
+/// is not a terminal.
+#[cfg(unix)]
+pub fn terminal_width() -> Option<usize> {
+    unsafe {
+        if libc::isatty(libc::STDOUT_FILENO) != 1 {
+            return None;
+        }
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) != 0
+            || size.ws_col == 0
+        {
+            return None;
+        }
+        Some(size.ws_col as usize)
+    }
+}
+
+#[cfg(not(unix))]
+pub fn terminal_width() -> Option<usize> {
+    None
+}
+
+/// Quotes a CSV field if it contains anything that would end it early.
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(cells: &[&str]) -> Vec<String> {
+        cells.iter().map(|cell| cell.to_string()).collect()
+    }
+
+    #[test]
+    fn styles() {
+        let headers = strings(&["name", "age"]);
+        let rows = vec![strings(&["ann", "31"]), strings(&["bob, jr."])];
+
+        assert_eq!(
+            format(&headers, &rows, Style::Plain),
+            "name      age\n\
+             --------  ---\n\
+             ann       31\n\
+             bob, jr.\n"
+        );
+        assert_eq!(
+            format(&headers, &rows, Style::Markdown),
+            "| name     | age |\n\
+             | -------- | --- |\n\
+             | ann      | 31  |\n\
+             | bob, jr. |     |\n"
+        );
+        assert_eq!(
+            format(&headers, &rows, Style::Csv),
+            "name,age\nann,31\n\"bob, jr.\",\n"
+        );
+        assert_eq!(format(&[], &rows[..1], Style::Plain), "ann  31\n");
+
+        let rows =
+            vec![strings(&["日本", "1"]), strings(&["\x1b[1mab\x1b[0m", "2"])];
+        assert_eq!(
+            format(&[], &rows, Style::Plain),
+            "日本  1\n\x1b[1mab\x1b[0m    2\n"
+        );
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate("abcdef\nåäö\n", 2), "ab\nåä\n");
+        assert_eq!(truncate("日本語\n", 3), "日\n");
+    }
+}
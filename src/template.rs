@@ -0,0 +1,244 @@
+use crate::common::{Context, Symbol};
+use crate::location::{Location, Span};
+use crate::messages::Message;
+
+/// The blocks that `{% end %}` closes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Block {
+    For,
+    While,
+    If,
+}
+
+impl Block {
+    fn name(self) -> &'static str {
+        match self {
+            Block::For => "for",
+            Block::While => "while",
+            Block::If => "if",
+        }
+    }
+}
+
+/// Turns a template into a script that prints it. Text is printed as it
+/// is, `{{ expr }}` prints the value of `expr` and `{% ... %}` holds
+/// statements:
+///
+/// ```text
+/// {% for name in names %}
+/// Hello {{ name }}!
+/// {% end %}
+/// ```
+///
+/// `for`, `while` and `if` tags open a block that `{% else %}` or
+/// `{% end %}` continues or closes, any other tag is copied into the
+/// script. A tag alone on its line takes the line with it. Lines of the
+/// script match those of the template, so errors point at the right line.
+#[allow(clippy::result_unit_err)]
+pub fn compile(
+    template: &str,
+    file: Symbol,
+    context: &mut Context,
+) -> Result<String, ()> {
+    let mut script = String::new();
+    let mut blocks = Vec::new();
+    let mut rest = template;
+
+    loop {
+        let start = match (rest.find("{{"), rest.find("{%")) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => {
+                print_text(&mut script, rest);
+                break;
+            }
+        };
+
+        let (text, tag) = rest.split_at(start);
+        let offset = template.len() - tag.len();
+        let is_statement = tag.starts_with("{%");
+        let close = if is_statement { "%}" } else { "}}" };
+
+        let (code, after) = match tag[2..].find(close) {
+            Some(end) => (&tag[2..2 + end], &tag[4 + end..]),
+            None => {
+                let location = location_at(template, offset, 2, file);
+                context.report_error(Message::UnclosedTag(close), location);
+                return Err(());
+            }
+        };
+        let location = location_at(template, offset, code.len() + 4, file);
+
+        if !is_statement {
+            print_text(&mut script, text);
+            script.push_str(&format!("print(\"%\", ({})) ", code));
+            rest = after;
+            continue;
+        }
+
+        let alone = alone_on_line(text, after);
+        let (text, after) = alone.unwrap_or((text, after));
+        print_text(&mut script, text);
+        statement(&mut script, code, location, &mut blocks, context)?;
+        if alone.is_some() {
+            script.push('\n');
+        }
+        rest = after;
+    }
+
+    if let Some((block, location)) = blocks.pop() {
+        let message = Message::UnclosedBlock(Block::name(block));
+        context.report_error(message, location);
+        return Err(());
+    }
+
+    Ok(script)
+}
+
+/// Appends the statement in a `{% ... %}` tag to the script.
+fn statement(
+    script: &mut String,
+    code: &str,
+    location: Location,
+    blocks: &mut Vec<(Block, Location)>,
+    context: &mut Context,
+) -> Result<(), ()> {
+    let trimmed = code.trim();
+    let block = match trimmed.split_whitespace().next() {
+        Some("for") => Some(Block::For),
+        Some("while") => Some(Block::While),
+        Some("if") => Some(Block::If),
+        _ => None,
+    };
+
+    match (trimmed, blocks.last()) {
+        // An if is wrapped in a block of its own, so that what follows its
+        // else is not parsed as part of it.
+        _ if block == Some(Block::If) => {
+            script.push_str(&format!("{{ {} {{ ", trimmed))
+        }
+        _ if block.is_some() => script.push_str(&format!("{} {{ ", trimmed)),
+        ("else", Some((Block::If, _))) => script.push_str("} else { "),
+        ("end", Some((Block::If, _))) => script.push_str("} } "),
+        ("end", Some(_)) => script.push_str("} "),
+        ("else", _) | ("end", _) => {
+            let tag = if trimmed == "else" { "else" } else { "end" };
+            context.report_error(Message::UnexpectedTag(tag), location);
+            return Err(());
+        }
+        _ => script.push_str(&format!("{} ", trimmed)),
+    }
+
+    match block {
+        Some(block) => blocks.push((block, location)),
+        None if trimmed == "end" => {
+            blocks.pop();
+        }
+        None => {}
+    }
+
+    // Keep the lines of the script in step with the template.
+    script.extend(code.matches('\n').map(|_| '\n'));
+
+    Ok(())
+}
+
+/// Appends a `print` of `text`, with a newline in the script for every one
+/// in the text.
+fn print_text(script: &mut String, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+
+    script.push_str("print(\"");
+    for c in text.chars() {
+        match c {
+            '"' => script.push_str("\\\""),
+            '\\' => script.push_str("\\\\"),
+            '\n' => script.push_str("\\n"),
+            '\r' => script.push_str("\\r"),
+            '\t' => script.push_str("\\t"),
+            '%' => script.push_str("%%"),
+            '$' => script.push_str("\\$"),
+            c => script.push(c),
+        }
+    }
+    script.push_str("\") ");
+
+    script.extend(text.matches('\n').map(|_| '\n'));
+}
+
+/// If a tag has only spaces around it on its line, returns `text` and
+/// `after` without them and without the newline ending the line.
+fn alone_on_line<'a>(
+    text: &'a str,
+    after: &'a str,
+) -> Option<(&'a str, &'a str)> {
+    let text = text.trim_end_matches([' ', '\t']);
+    if !(text.is_empty() || text.ends_with('\n')) {
+        return None;
+    }
+
+    let after = after.trim_start_matches([' ', '\t', '\r']);
+    match after.strip_prefix('\n') {
+        Some(after) => Some((text, after)),
+        None if after.is_empty() => Some((text, after)),
+        None => None,
+    }
+}
+
+fn location_at(
+    template: &str,
+    offset: usize,
+    len: usize,
+    file: Symbol,
+) -> Location {
+    Location {
+        file,
+        line: template[..offset].matches('\n').count() as u32 + 1,
+        span: Span {
+            start: offset as u32,
+            len: len as u32,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_str(template: &str) -> Result<String, ()> {
+        let mut context = Context::new();
+        context
+            .source_code
+            .insert("test.tmpl".into(), template.to_string());
+        let file = context.interner.intern("test.tmpl");
+        compile(template, file, &mut context)
+    }
+
+    #[test]
+    fn template_to_script() {
+        let template = "Hi {{ name }}, 100% ${x}\n\
+                        {% for x in xs %}\n\
+                        \x20 - {{ x }}\n\
+                        {% end %}\n\
+                        {% if ok %}yes{% else %}no{% end %}\"\n";
+        assert_eq!(
+            compile_str(template).unwrap(),
+            "print(\"Hi \") print(\"%\", ( name )) print(\", 100%% \\${x}\\n\") \n\
+             for x in xs { \n\
+             print(\"  - \") print(\"%\", ( x )) print(\"\\n\") \n\
+             } \n\
+             { if ok { print(\"yes\") } else { print(\"no\") } } \
+             print(\"\\\"\\n\") \n"
+        );
+    }
+
+    #[test]
+    fn template_errors() {
+        assert!(compile_str("{{ a ").is_err());
+        assert!(compile_str("{% for x in xs %}").is_err());
+        assert!(compile_str("{% end %}").is_err());
+        assert!(compile_str("{% for x in xs %}{% else %}{% end %}").is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use unicode_width::UnicodeWidthChar;
+
+/// Removes the ANSI escape sequences from `text`, like the ones that color
+/// it in a terminal.
+pub fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        match escape_len(rest) {
+            Some(len) => rest = &rest[len..],
+            None => {
+                stripped.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    stripped
+}
+
+/// The number of columns `text` takes up in a terminal. Wide characters
+/// like most CJK ones count twice, and combining marks, control
+/// characters and ANSI escape sequences not at all.
+pub fn display_width(text: &str) -> usize {
+    strip_ansi(text).chars().map(char_width).sum()
+}
+
+/// Cuts `text`, a single line, to at most `width` columns. Escape
+/// sequences are kept, so that a color is still reset after the cut.
+pub fn truncate(text: &str, width: usize) -> String {
+    let mut truncated = String::with_capacity(text.len());
+    let mut used = 0;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let len = match escape_len(rest) {
+            Some(len) => len,
+            None if used + char_width(c) <= width => {
+                used += char_width(c);
+                c.len_utf8()
+            }
+            None => {
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+        };
+        truncated += &rest[..len];
+        rest = &rest[len..];
+    }
+    truncated
+}
+
+/// Breaks the lines of `text` between words so that none is wider than
+/// `width` columns, or at least 1. Words that are wider on their own are
+/// split where the line ends.
+pub fn wrap(text: &str, width: usize) -> String {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        let mut used = 0;
+        for word in paragraph.split_whitespace() {
+            let word_width = display_width(word);
+            if used > 0 && used + 1 + word_width <= width {
+                line.push(' ');
+                line += word;
+                used += 1 + word_width;
+                continue;
+            }
+            if used > 0 {
+                lines.push(std::mem::take(&mut line));
+            }
+
+            line = word.to_string();
+            used = word_width;
+            while used > width {
+                let head = truncate(&line, width);
+                let head_len = visible_prefix_len(&line, &head);
+                // A character wider than the line still has to go somewhere.
+                let split = if head_len == 0 {
+                    line.chars().next().map_or(0, char::len_utf8)
+                } else {
+                    head_len
+                };
+                if split == line.len() {
+                    break;
+                }
+                lines.push(line[..split].to_string());
+                line = line[split..].to_string();
+                used = display_width(&line);
+            }
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+/// The length of the start of `text` that holds what `truncate` kept of it.
+fn visible_prefix_len(text: &str, truncated: &str) -> usize {
+    let mut len = 0;
+    let mut kept = truncated;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let piece = escape_len(rest).unwrap_or_else(|| c.len_utf8());
+        if !kept.starts_with(&rest[..piece]) {
+            break;
+        }
+        kept = &kept[piece..];
+        rest = &rest[piece..];
+        len += piece;
+    }
+    len
+}
+
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+/// The length of the escape sequence at the start of `text`, if there is
+/// one. Handles CSI sequences like `\x1b[31m`, OSC sequences like terminal
+/// titles and links, and two character escapes.
+fn escape_len(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    if bytes.first() != Some(&0x1b) {
+        return None;
+    }
+    match bytes.get(1) {
+        Some(b'[') => {
+            // Parameters and intermediates, then a final byte from @ to ~.
+            let end =
+                bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b))?;
+            Some(end + 3)
+        }
+        Some(b']') => {
+            // Ends with BEL or ESC \.
+            let end = bytes[2..].iter().enumerate().position(|(i, b)| {
+                *b == 0x07 || (*b == 0x1b && bytes.get(i + 3) == Some(&b'\\'))
+            })?;
+            let terminator = if bytes[end + 2] == 0x07 { 1 } else { 2 };
+            Some(end + 2 + terminator)
+        }
+        Some(b) if (0x40..=0x5f).contains(b) => Some(2),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stripping() {
+        assert_eq!(strip_ansi("\x1b[1;31mred\x1b[0m plain"), "red plain");
+        assert_eq!(
+            strip_ansi("\x1b]8;;http://a.b\x07link\x1b]8;;\x1b\\"),
+            "link"
+        );
+        assert_eq!(strip_ansi("a\x1bMb"), "ab");
+        assert_eq!(strip_ansi("cut \x1b[3"), "cut \x1b[3");
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("日本"), 4);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(display_width("\x1b[32mok\x1b[0m"), 2);
+        assert_eq!(display_width(""), 0);
+    }
+
+    #[test]
+    fn truncating() {
+        assert_eq!(truncate("日本語", 5), "日本");
+        assert_eq!(truncate("\x1b[31mabc\x1b[0m", 2), "\x1b[31mab\x1b[0m");
+        assert_eq!(truncate("ab", 5), "ab");
+    }
+
+    #[test]
+    fn wrapping() {
+        assert_eq!(wrap("the quick brown fox", 10), "the quick\nbrown fox");
+        assert_eq!(wrap("a\n\nb c", 1), "a\n\nb\nc");
+        assert_eq!(wrap("abcdefgh ij", 3), "abc\ndef\ngh\nij");
+        assert_eq!(wrap("日本語", 3), "日\n本\n語");
+        assert_eq!(wrap("日本", 1), "日\n本");
+        assert_eq!(
+            wrap("\x1b[1mbold\x1b[0m word", 4),
+            "\x1b[1mbold\x1b[0m\nword"
+        );
+        assert_eq!(wrap("x", 0), "x");
+    }
+}
@@ -5,9 +5,13 @@ use crate::location::Location;
 pub struct Token {
     pub kind: TokenKind,
     pub location: Location,
+    /// Set on the first token of a line when the line break before it ends
+    /// a statement, so that it can't continue an expression from the line
+    /// above. See `lexer::mark_line_breaks`.
+    pub line_break: bool,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenKind {
     // Logical Operators
     And,
@@ -38,6 +42,9 @@ pub enum TokenKind {
     Mod,
 
     Field,
+    Try,
+    /// Starts an attribute, like `@cfg(os = "linux")`.
+    At,
 
     // Declarations
     FunctionDecl,
@@ -53,6 +60,10 @@ pub enum TokenKind {
     Colon,
 
     ReturnDecl,
+    /// Between the patterns and the body of a `match` arm.
+    FatArrow,
+    /// Between a namespace and a name in it, as in `Color::Red`.
+    PathSep,
     OpenBrace,
     OpenBracket,
     OpenParen,
@@ -63,18 +74,26 @@ pub enum TokenKind {
     For,
     Return,
     While,
+    Break,
+    Continue,
+    Import,
+    Match,
     Range(i64, i64),
     In,
 
     Ident(Symbol),
     Bool(bool),
     Integer(i32),
-    Float(f32),
+    Float(f64),
     String(Symbol),
+    /// Around a string literal with `${...}` in it.
+    InterpolationStart,
+    InterpolationEnd,
 
     // Types
     BoolType,
     FloatType,
     IntType,
     StringType,
+    ResultType,
 }
@@ -1,31 +1,57 @@
+use std::mem;
+
 use log::trace;
 
 use crate::ast::{
-    ArgList, Assignment, AssignmentKind, Ast, AstNodeId, BinaryOp,
-    BinaryOpKind, Decl, Expr, ExprKind, For, FunctionCall, FunctionDecl, If,
-    Input, ParamList, Print, Return, Stmt, StmtList, UnaryOp,
+    Assignment, AssignmentKind, Ast, AstNodeId, BinaryOp, BinaryOpKind, Decl,
+    Expr, ExprKind, For, ForIter, FunctionCall, FunctionDecl, If, Index, Input,
+    Print, Return, Stmt, StmtList, Try, UnaryOp, UnaryOpKind, VarDecl, While,
 };
-use crate::common::{Context, Symbol};
+use crate::common::{grow_stack, Context, Symbol};
+use crate::interpreter::kind_of;
 use crate::location::Location;
+use crate::messages::Message;
 
 use crate::primitives::{Value, ValueKind};
 use crate::scope::Scope;
+use crate::semantic;
 
+#[allow(clippy::result_unit_err)]
 pub fn typecheck(ast: &Ast, context: &mut Context) -> Result<(), ()> {
+    typecheck_statements(ast, &ast.statements, &mut Scope::new(), context)
+        .map(|_| ())
+}
+
+/// Checks `statements` against the variables already declared in `scope`,
+/// which is left holding whatever they declare. Returns the types of the
+/// top level expression statements, in order.
+pub(crate) fn typecheck_statements(
+    ast: &Ast,
+    statements: &StmtList,
+    scope: &mut Scope,
+    context: &mut Context,
+) -> Result<Vec<ValueKind>, ()> {
     let mut checker = Typechecker {
         ast,
         check_passed: true,
-        scope: Scope::new(),
+        scope: mem::take(scope),
         context,
         location: vec![],
-        deferred_function_calls: vec![],
         current_function: None,
     };
 
-    checker.check_stmt_list(&ast.statements);
+    checker.declare_functions(statements);
+    let mut kinds = Vec::new();
+    for stmt in statements {
+        match &ast.arena[*stmt] {
+            Stmt::Expr(expr) => kinds.push(checker.check_expr_stmt(expr)),
+            stmt => checker.check_stmt(stmt),
+        }
+    }
+    *scope = checker.scope;
 
     if checker.check_passed {
-        Ok(())
+        Ok(kinds)
     } else {
         Err(())
     }
@@ -37,62 +63,406 @@ struct Typechecker<'ctxt> {
     scope: Scope,
     context: &'ctxt mut Context,
     location: Vec<Location>,
-    deferred_function_calls: Vec<FunctionCall>,
     current_function: Option<FunctionDecl>,
 }
 
 impl<'ctxt> Typechecker<'ctxt> {
-    fn report_error(&mut self, message: &str) {
+    fn report_error(&mut self, message: Message) {
         self.check_passed = false;
         self.context
             .report_error(message, self.location[self.location.len() - 1]);
     }
 
+    fn report_warning(&mut self, message: Message) {
+        self.context
+            .report_warning(message, self.location[self.location.len() - 1]);
+    }
+
     fn check_stmt_list(&mut self, stmts: &StmtList) {
         trace!("stmt_list");
 
-        use Stmt::*;
+        self.declare_functions(stmts);
 
+        let ast = self.ast;
         for stmt in stmts {
-            match &self.ast.arena[*stmt] {
-                Assignment(v) => unimplemented!(),
-                Block(v) => unimplemented!(),
-                Decl(v) => unimplemented!(),
-                Expr(v) => self.check_expr(v),
-                For(v) => unimplemented!(),
-                Print(v) => unimplemented!(),
-                If(v) => unimplemented!(),
-                Return(v) => unimplemented!(),
-                Param(v) => unimplemented!(),
+            self.check_stmt(&ast.arena[*stmt]);
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        grow_stack(|| {
+            use Stmt::*;
+
+            match stmt {
+                Assignment(v) => self.check_assignment(v),
+                Block(v) => self.check_block(v),
+                Decl(v) => self.check_decl(v),
+                Expr(v) => {
+                    self.check_expr_stmt(v);
+                }
+                For(v) => self.check_for(v),
+                While(v) => self.check_while(v),
+                Print(v) => self.check_print(v),
+                If(v) => self.check_if(v),
+                Return(v) => self.check_return(v),
+                // Only parsed inside loops.
+                Break(_) | Continue(_) => {}
+                // Only found in parameter lists.
+                Param(_) => {}
             };
+        })
+    }
+
+    /// Functions can be called before they are declared, so every function
+    /// in a statement list is known before any of it is checked.
+    fn declare_functions(&mut self, stmts: &StmtList) {
+        for stmt in stmts {
+            if let Stmt::Decl(Decl::Function(func)) = &self.ast.arena[*stmt] {
+                self.scope.add_function(func.name, *stmt);
+            }
         }
     }
 
-    fn check_expr(&mut self, expr: &Expr) -> ValueKind {
-        trace!("Expr");
+    fn check_block(&mut self, stmts: &StmtList) {
+        trace!("Block");
+
+        self.scope.new_scope_level();
+        self.check_stmt_list(stmts);
+        self.scope.pop_scope_level();
+    }
+
+    fn check_if(&mut self, if_stmt: &If) {
+        trace!("If");
+
+        self.check_condition(if_stmt.cond);
+        self.check_block(&if_stmt.body);
+
+        if let Some(else_body) = &if_stmt.else_body {
+            self.check_block(else_body);
+        }
+    }
+
+    fn check_condition(&mut self, cond: AstNodeId) {
+        let cond = self.ast.arena[cond].expr();
+        let kind = self.check_expr(cond);
+
+        // Any value can be used as a condition, see Value::is_truthy
+        if self.context.strict_mode
+            && kind != ValueKind::Bool
+            && kind != ValueKind::Nil
+        {
+            self.location.push(cond.location);
+            self.report_error(Message::NonBoolCondition(kind));
+            self.location.pop();
+        }
+    }
+
+    fn check_while(&mut self, while_loop: &While) {
+        trace!("While");
+
+        self.check_condition(while_loop.cond);
+        self.check_block(&while_loop.body);
+    }
+
+    fn check_for(&mut self, for_loop: &For) {
+        trace!("For");
+
+        let kind = match &for_loop.iter {
+            ForIter::Range(_) => ValueKind::Integer,
+            ForIter::Expr(node) => {
+                let expr = self.ast.arena[*node].expr();
+                match self.check_expr(expr) {
+                    ValueKind::Array(element)
+                    | ValueKind::Iter(element)
+                    | ValueKind::Deque(element) => *element,
+                    ValueKind::Map(key, _) => *key,
+                    ValueKind::Nil => ValueKind::Nil,
+                    other => {
+                        self.location.push(expr.location);
+                        self.report_error(Message::CannotIterate(other));
+                        self.location.pop();
+                        ValueKind::Nil
+                    }
+                }
+            }
+        };
+
+        let mut kinds = if for_loop.unpack.is_empty() {
+            vec![kind]
+        } else {
+            self.location.push(for_loop.location);
+            let kinds = self.unpack_kinds(kind, for_loop.unpack.len() + 1);
+            self.location.pop();
+            kinds
+        }
+        .into_iter();
+
+        self.scope.new_scope_level();
+        self.scope.add_variable(
+            for_loop.ident,
+            Value::Nil,
+            kinds.next().unwrap_or(ValueKind::Nil),
+        );
+        for (name, kind) in for_loop.unpack.iter().zip(kinds) {
+            self.scope.add_variable(*name, Value::Nil, kind);
+        }
+        self.check_block(&for_loop.block);
+        self.scope.pop_scope_level();
+    }
+
+    fn check_print(&mut self, print: &Print) {
+        trace!("Print");
+
+        for (i, arg) in print.args.iter().enumerate() {
+            let arg = self.ast.arena[*arg].expr();
+            let kind = self.check_expr(arg);
+
+            if i == 0 && kind != ValueKind::String && kind != ValueKind::Nil {
+                self.location.push(arg.location);
+                self.report_error(Message::PrintFormat(kind));
+                self.location.pop();
+            }
+        }
+    }
+
+    fn check_assignment(&mut self, assignment: &Assignment) {
+        trace!("Assignment");
+
+        let value = self.check_expr(self.ast.arena[assignment.value].expr());
+
+        self.location.push(assignment.location);
 
-        use ExprKind::*;
-
-        self.location.push(expr.location);
-
-        let kind = match &expr.kind {
-            Bool(_) => ValueKind::Bool,
-            Float(_) => ValueKind::Float,
-            Integer(_) => ValueKind::Integer,
-            StringLiteral(_) => ValueKind::String,
-            Ident(v) => self.check_ident(*v),
-            Range(_) => unimplemented!(),
-            Input(_) => unimplemented!(),
-            UnaryOp(_) => unimplemented!(),
-            BinaryOp(v) => self.check_binop(v),
-            FunctionCall(_) => unimplemented!(),
+        let mut var_kind = match self.scope.get_variable(assignment.ident) {
+            Some(var) => var.kind.clone(),
+            None => {
+                let name =
+                    self.context.interner.get(assignment.ident).to_string();
+                if self.context.constants.contains_key(&assignment.ident) {
+                    self.report_error(Message::AssignConstant(name));
+                } else {
+                    let suggestion = semantic::suggestion(
+                        assignment.ident,
+                        &self.scope,
+                        self.context,
+                    );
+                    self.report_error(Message::UndefinedVariable {
+                        name,
+                        suggestion,
+                    });
+                }
+                self.location.pop();
+                return;
+            }
         };
 
+        for index in &assignment.indices {
+            if var_kind == ValueKind::String {
+                self.report_error(Message::AssignStringIndex);
+            }
+            var_kind = self.check_indexing(var_kind, *index);
+        }
+
+        let kind = match assignment.op {
+            AssignmentKind::Assign => value,
+            AssignmentKind::Add => {
+                self.check_arithmetic(BinaryOpKind::Add, &var_kind, &value)
+            }
+            AssignmentKind::Sub => {
+                self.check_arithmetic(BinaryOpKind::Sub, &var_kind, &value)
+            }
+            AssignmentKind::Mul => {
+                self.check_arithmetic(BinaryOpKind::Mul, &var_kind, &value)
+            }
+            AssignmentKind::Div => {
+                self.check_arithmetic(BinaryOpKind::Div, &var_kind, &value)
+            }
+            AssignmentKind::Mod => {
+                self.check_arithmetic(BinaryOpKind::Mod, &var_kind, &value)
+            }
+        };
+
+        if !compatible(&var_kind, &kind) {
+            let name = self.context.interner.get(assignment.ident).to_string();
+            self.report_error(if assignment.indices.is_empty() {
+                Message::AssignMismatch {
+                    name,
+                    expected: var_kind,
+                    found: kind,
+                }
+            } else {
+                Message::ElementAssignMismatch {
+                    name,
+                    expected: var_kind,
+                    found: kind,
+                }
+            });
+        }
+
+        self.location.pop();
+    }
+
+    fn check_decl(&mut self, decl: &Decl) {
+        trace!("Decl");
+
+        match decl {
+            Decl::Variable(var) => self.check_var_decl(var),
+            Decl::Function(func) => self.check_function_decl(func),
+            Decl::Struct(decl) => {
+                self.location.push(decl.location);
+                self.report_error(Message::StructUnsupported);
+                self.location.pop();
+            }
+        }
+    }
+
+    fn check_function_decl(&mut self, func: &FunctionDecl) {
+        trace!("Function decl");
+
+        self.scope.push_scope();
+
+        for param in &func.params {
+            let param = self.ast.arena[*param].param();
+            self.scope
+                .add_variable(param.name, Value::Nil, param.kind.clone());
+        }
+
+        let outer = self.current_function.replace(func.clone());
+        self.check_stmt_list(&func.body);
+        self.current_function = outer;
+
+        self.scope.pop_scope();
+    }
+
+    fn check_return(&mut self, ret: &Return) {
+        trace!("Return");
+
+        let kind = ret
+            .value
+            .map(|value| self.check_expr(self.ast.arena[value].expr()));
+
+        self.location.push(ret.location);
+
+        match (&self.current_function, kind) {
+            (None, _) => {
+                self.report_error(Message::ReturnOutsideFunction);
+            }
+            (Some(func), Some(kind)) => match &func.return_type {
+                Some(expected) if !compatible(expected, &kind) => {
+                    self.report_error(Message::ReturnMismatch {
+                        expected: expected.clone(),
+                        found: kind,
+                    });
+                }
+                Some(_) => {}
+                None => self.report_error(Message::NoReturnValue(
+                    self.context.interner.get(func.name).to_string(),
+                )),
+            },
+            (Some(func), None) => {
+                if let Some(expected) = &func.return_type {
+                    self.report_error(Message::MissingReturnValue(
+                        expected.clone(),
+                    ));
+                }
+            }
+        }
+
         self.location.pop();
+    }
+
+    fn check_var_decl(&mut self, var: &VarDecl) {
+        trace!("Var decl");
+
+        let value = self.ast.arena[var.value].expr();
+        let kind = self.check_expr(value);
+
+        self.location.push(value.location);
+
+        if !compatible(&var.kind, &kind) {
+            self.report_error(Message::AssignMismatch {
+                name: self.context.interner.get(var.name).to_string(),
+                expected: var.kind.clone(),
+                found: kind.clone(),
+            });
+        }
+
+        if self.context.strict_mode
+            && self.scope.get_variable(var.name).is_some()
+        {
+            self.report_error(Message::Shadowing(
+                self.context.interner.get(var.name).to_string(),
+            ));
+        }
+
+        self.location.pop();
+
+        let kind = if var.kind == ValueKind::Nil {
+            kind
+        } else {
+            var.kind.clone()
+        };
+        self.scope.add_variable(var.name, Value::Nil, kind);
+    }
+
+    fn check_expr_stmt(&mut self, expr: &Expr) -> ValueKind {
+        trace!("Expr stmt");
+
+        let kind = self.check_expr(expr);
+
+        match expr.kind {
+            ExprKind::FunctionCall(_) | ExprKind::Input(_) => {}
+            _ if self.context.strict_mode => {
+                self.location.push(expr.location);
+                self.report_error(Message::UnusedResult);
+                self.location.pop();
+            }
+            _ => {}
+        }
 
         kind
     }
 
+    fn check_expr(&mut self, expr: &Expr) -> ValueKind {
+        trace!("Expr");
+
+        grow_stack(|| {
+            use ExprKind::*;
+
+            self.location.push(expr.location);
+
+            let kind = match &expr.kind {
+                Bool(_) => ValueKind::Bool,
+                Float(_) => ValueKind::Float,
+                Integer(_) => ValueKind::Integer,
+                StringLiteral(_) => ValueKind::String,
+                // Any value can be shown in a string.
+                Interpolation(v) => {
+                    for part in v {
+                        self.check_expr(self.ast.arena[*part].expr());
+                    }
+                    ValueKind::String
+                }
+                Ident(v) => self.check_ident(*v),
+                Range(_) => {
+                    self.report_error(Message::RangeOutsideFor);
+                    ValueKind::Nil
+                }
+                Input(v) => self.check_input(v),
+                UnaryOp(v) => self.check_unary_op(v),
+                BinaryOp(v) => self.check_binop(v),
+                FunctionCall(v) => self.check_function_call(v),
+                Try(v) => self.check_try(v),
+                Array(v) => self.check_array(v),
+                Map(v) => self.check_map(v),
+                Index(v) => self.check_index(v),
+            };
+
+            self.location.pop();
+
+            kind
+        })
+    }
+
     fn check_binop(&mut self, binop: &BinaryOp) -> ValueKind {
         trace!("Binop");
 
@@ -103,49 +473,1505 @@ impl<'ctxt> Typechecker<'ctxt> {
         let rhs = self.check_expr(self.ast.arena[binop.rhs].expr());
 
         match binop.op {
-            And | Or | Equal | Greater | GreaterEqual | Lesser
-            | LesserEqual | NotEqual => Bool,
-
-            Field => unimplemented!(),
-
-            Add | Sub | Mul | Div | Mod => match (lhs, rhs) {
-                (Bool, Bool) => Bool,
-                (Integer, Integer) => Integer,
-                (String, String) => String,
-                (Float, Float) => Float,
-                (Integer, Float) | (Float, Integer) => Float,
-                (Nil, other) | (other, Nil) => other,
-                (a, b) => {
-                    self.report_error(&format!(
-                        "Invalid types {:?}, {:?} for operator {:?}",
-                        a, b, binop.op
-                    ));
-                    Nil
+            And | Or => Bool,
+
+            Equal | NotEqual => {
+                if !comparable(&lhs, &rhs) {
+                    self.report_warning(Message::AlwaysSame {
+                        result: binop.op == NotEqual,
+                        lhs,
+                        rhs,
+                    });
+                }
+                Bool
+            }
+
+            Greater | GreaterEqual | Lesser | LesserEqual => {
+                if !orderable(&lhs, &rhs) {
+                    self.report_error(Message::CannotOrder(lhs, rhs));
+                }
+                Bool
+            }
+
+            Field => {
+                self.report_error(Message::FieldAccess(lhs));
+                Nil
+            }
+
+            Div | Mod
+                if matches!(
+                    self.ast.arena[binop.rhs].expr().kind,
+                    ExprKind::Integer(0)
+                ) =>
+            {
+                self.report_error(Message::DivisionByZero);
+                lhs
+            }
+
+            Add | Sub | Mul | Div | Mod => {
+                self.check_arithmetic(binop.op, &lhs, &rhs)
+            }
+        }
+    }
+
+    fn check_arithmetic(
+        &mut self,
+        op: BinaryOpKind,
+        lhs: &ValueKind,
+        rhs: &ValueKind,
+    ) -> ValueKind {
+        use ValueKind::*;
+
+        match (lhs, rhs) {
+            (Integer, Integer) => Integer,
+            (String, String | Integer | Float | Bool)
+            | (Integer | Float | Bool, String)
+                if matches!(op, BinaryOpKind::Add) =>
+            {
+                String
+            }
+            (Float, Float) => Float,
+            (Integer, Float) | (Float, Integer) => {
+                if self.context.strict_mode {
+                    self.report_error(Message::ImplicitConversion(op));
+                }
+                Float
+            }
+            (Nil, other) | (other, Nil) => other.clone(),
+            (a, b) => {
+                self.report_error(Message::InvalidOperands(
+                    a.clone(),
+                    b.clone(),
+                    op,
+                ));
+                Nil
+            }
+        }
+    }
+
+    fn check_unary_op(&mut self, unary_op: &UnaryOp) -> ValueKind {
+        trace!("Unary op");
+
+        let kind = self.check_expr(self.ast.arena[unary_op.value].expr());
+
+        match unary_op.op {
+            UnaryOpKind::Not => ValueKind::Bool,
+            UnaryOpKind::Neg => match kind {
+                ValueKind::Integer | ValueKind::Float | ValueKind::Nil => kind,
+                other => {
+                    self.report_error(Message::CannotNegate(other));
+                    ValueKind::Nil
                 }
             },
         }
     }
 
+    fn check_input(&mut self, input: &Input) -> ValueKind {
+        trace!("Input");
+
+        if let Some(message) = input.message {
+            let kind = self.check_expr(self.ast.arena[message].expr());
+            if kind != ValueKind::String && kind != ValueKind::Nil {
+                self.report_error(Message::InputMessage(kind));
+            }
+        }
+
+        ValueKind::String
+    }
+
+    fn check_function_call(&mut self, call: &FunctionCall) -> ValueKind {
+        trace!("Function call");
+
+        let ok = self.context.interner.intern("Ok");
+        let err = self.context.interner.intern("Err");
+
+        if call.name == ok || call.name == err {
+            if call.args.len() != 1 {
+                self.report_error(Message::ResultArity {
+                    name: self.context.interner.get(call.name).to_string(),
+                    found: call.args.len(),
+                });
+            }
+
+            for arg in &call.args {
+                self.check_expr(self.ast.arena[*arg].expr());
+            }
+
+            return ValueKind::Result;
+        }
+
+        // Functions declared by the script take precedence over builtins.
+        if self.scope.get_function(call.name).is_none() {
+            if let Some(kind) = self.check_builtin_call(call) {
+                return kind;
+            }
+        }
+
+        let arg_kinds: Vec<_> = call
+            .args
+            .iter()
+            .map(|arg| self.check_expr(self.ast.arena[*arg].expr()))
+            .collect();
+
+        let (params, return_type) = match self.signature(call.name) {
+            Some(signature) => signature,
+            None => {
+                self.report_error(Message::UndefinedFunction {
+                    name: self.context.interner.get(call.name).to_string(),
+                    suggestion: semantic::suggestion(
+                        call.name,
+                        &self.scope,
+                        self.context,
+                    ),
+                });
+                return ValueKind::Nil;
+            }
+        };
+
+        if arg_kinds.len() != params.len() {
+            self.report_error(Message::Arity {
+                name: self.context.interner.get(call.name).to_string(),
+                expected: params.len(),
+                found: arg_kinds.len(),
+            });
+        }
+
+        for (i, (param, kind)) in params.iter().zip(arg_kinds).enumerate() {
+            if !compatible(param, &kind) {
+                self.report_error(Message::ArgumentMismatch {
+                    position: i + 1,
+                    name: self.context.interner.get(call.name).to_string(),
+                    expected: param.clone(),
+                    found: kind,
+                });
+            }
+        }
+
+        // Calls to functions without a return type are only useful as
+        // statements, their value is unknown.
+        return_type.unwrap_or(ValueKind::Nil)
+    }
+
+    /// Checks a call to one of the builtins that work on several types.
+    /// Returns `None` if `call` is not one of them.
+    fn check_builtin_call(&mut self, call: &FunctionCall) -> Option<ValueKind> {
+        let name = self.context.interner.get(call.name).to_string();
+        if name == "partial" {
+            return Some(self.check_partial(call));
+        }
+        if name == "eval" {
+            return Some(self.check_eval(call));
+        }
+        let arity = generic_arity(&name)?;
+
+        let kinds: Vec<_> = call
+            .args
+            .iter()
+            .map(|arg| self.check_expr(self.ast.arena[*arg].expr()))
+            .collect();
+
+        if kinds.len() != arity {
+            self.report_error(Message::Arity {
+                name,
+                expected: arity,
+                found: kinds.len(),
+            });
+            return Some(ValueKind::Nil);
+        }
+
+        let kind = match name.as_str() {
+            "len" => match &kinds[0] {
+                ValueKind::Array(_)
+                | ValueKind::Heap(_)
+                | ValueKind::Deque(_)
+                | ValueKind::Map(..)
+                | ValueKind::String
+                | ValueKind::Nil => ValueKind::Integer,
+                other => {
+                    self.report_error(Message::CannotTakeLength(other.clone()));
+                    ValueKind::Nil
+                }
+            },
+            "push" | "pop" | "push_front" | "pop_front" | "reserve"
+            | "resize" | "insort" => {
+                self.check_array_change(&name, call, &kinds)
+            }
+            "heap" => ValueKind::Heap(Box::new(ValueKind::Nil)),
+            "deque" => ValueKind::Deque(Box::new(ValueKind::Nil)),
+            "array_with_capacity" => {
+                self.check_argument(&name, 1, &ValueKind::Integer, &kinds[0]);
+                ValueKind::Array(Box::new(ValueKind::Nil))
+            }
+            "abs" | "min" | "max" => self.check_numeric_builtin(&name, &kinds),
+            "divmod" => {
+                for (i, kind) in kinds.iter().enumerate() {
+                    self.check_argument(
+                        &name,
+                        i + 1,
+                        &ValueKind::Integer,
+                        kind,
+                    );
+                }
+                ValueKind::Array(Box::new(ValueKind::Integer))
+            }
+            "keys" | "values" | "contains" => {
+                self.check_map_lookup(&name, &kinds)
+            }
+            "compose" => self.check_compose(&kinds),
+            "arity" => match &kinds[0] {
+                ValueKind::Function(..) | ValueKind::Nil => ValueKind::Integer,
+                other => {
+                    self.report_error(Message::NotAFunction {
+                        position: 1,
+                        name: name.clone(),
+                        found: other.clone(),
+                    });
+                    ValueKind::Nil
+                }
+            },
+            "retry" | "with_timeout" => {
+                let returned = self.check_callback(&name, 1, vec![], &kinds[0]);
+                if name == "retry" {
+                    self.check_argument(
+                        &name,
+                        1,
+                        &ValueKind::Result,
+                        &returned,
+                    );
+                }
+                for (i, kind) in kinds.iter().enumerate().skip(1) {
+                    self.check_argument(
+                        &name,
+                        i + 1,
+                        &ValueKind::Integer,
+                        kind,
+                    );
+                }
+                ValueKind::Result
+            }
+            "binary_search" => {
+                match &kinds[0] {
+                    ValueKind::Array(element) => {
+                        self.check_argument(&name, 2, element, &kinds[1]);
+                        self.check_order(element, element);
+                    }
+                    ValueKind::Nil => {}
+                    other => self.report_error(Message::NotAnArray {
+                        name: name.clone(),
+                        found: other.clone(),
+                    }),
+                }
+                ValueKind::Result
+            }
+            "get" => {
+                let location = self.ast.arena[call.args[1]].expr().location;
+                let (value, index) = (kinds[0].clone(), kinds[1].clone());
+                self.check_index_kind(value, index, location);
+                ValueKind::Result
+            }
+            _ => self.check_iterator_builtin(&name, &kinds),
+        };
+
+        Some(kind)
+    }
+
+    /// Checks `partial(func, args...)`, which returns a function taking the
+    /// parameters of `func` that were not given.
+    fn check_partial(&mut self, call: &FunctionCall) -> ValueKind {
+        let kinds: Vec<_> = call
+            .args
+            .iter()
+            .map(|arg| self.check_expr(self.ast.arena[*arg].expr()))
+            .collect();
+
+        if kinds.len() < 2 {
+            self.report_error(Message::TooFewArguments {
+                name: "partial".to_string(),
+                at_least: 2,
+                found: kinds.len(),
+            });
+            return ValueKind::Nil;
+        }
+
+        let (params, returns) = match &kinds[0] {
+            ValueKind::Function(params, returns) => (params, returns),
+            ValueKind::Nil => return ValueKind::Nil,
+            other => {
+                self.report_error(Message::NotAFunction {
+                    position: 1,
+                    name: "partial".to_string(),
+                    found: other.clone(),
+                });
+                return ValueKind::Nil;
+            }
+        };
+
+        let bound = &kinds[1..];
+        if bound.len() > params.len() {
+            self.report_error(Message::TooManyBound {
+                takes: params.len(),
+                found: bound.len(),
+            });
+            return ValueKind::Nil;
+        }
+
+        for (i, (param, kind)) in params.iter().zip(bound).enumerate() {
+            self.check_argument("partial", i + 2, param, kind);
+        }
+
+        ValueKind::Function(params[bound.len()..].to_vec(), returns.clone())
+    }
+
+    /// Checks `eval(source)` or `eval(source, env)`, where `env` maps the
+    /// names of variables to their values.
+    fn check_eval(&mut self, call: &FunctionCall) -> ValueKind {
+        let kinds: Vec<_> = call
+            .args
+            .iter()
+            .map(|arg| self.check_expr(self.ast.arena[*arg].expr()))
+            .collect();
+
+        if kinds.is_empty() || kinds.len() > 2 {
+            self.report_error(Message::Arity {
+                name: "eval".to_string(),
+                expected: kinds.len().clamp(1, 2),
+                found: kinds.len(),
+            });
+            return ValueKind::Nil;
+        }
+
+        self.check_argument("eval", 1, &ValueKind::String, &kinds[0]);
+        if let Some(env) = kinds.get(1) {
+            let expected = ValueKind::Map(
+                Box::new(ValueKind::String),
+                Box::new(ValueKind::Nil),
+            );
+            self.check_argument("eval", 2, &expected, env);
+        }
+        ValueKind::Result
+    }
+
+    /// Checks `compose(f, g)`, which takes the parameters of `f` and
+    /// returns what `g` returns.
+    fn check_compose(&mut self, kinds: &[ValueKind]) -> ValueKind {
+        let mut functions = Vec::with_capacity(2);
+        for (i, kind) in kinds.iter().enumerate() {
+            match kind {
+                ValueKind::Function(params, returns) => {
+                    functions.push((params.clone(), (**returns).clone()))
+                }
+                ValueKind::Nil => return ValueKind::Nil,
+                other => {
+                    self.report_error(Message::NotAFunction {
+                        position: i + 1,
+                        name: "compose".to_string(),
+                        found: other.clone(),
+                    });
+                    return ValueKind::Nil;
+                }
+            }
+        }
+
+        let (params, returns) = functions.swap_remove(0);
+        let returns =
+            self.check_callback("compose", 2, vec![returns], &kinds[1]);
+        ValueKind::Function(params, Box::new(returns))
+    }
+
+    /// Checks a call to `push` or `pop`, which change an array in place.
+    fn check_array_change(
+        &mut self,
+        name: &str,
+        call: &FunctionCall,
+        kinds: &[ValueKind],
+    ) -> ValueKind {
+        if !self.is_place(call.args[0]) {
+            self.report_error(Message::NotAVariable(name.to_string()));
+        }
+
+        let front = name == "push_front" || name == "pop_front";
+        let push_or_pop = front || name == "push" || name == "pop";
+        let element = match &kinds[0] {
+            ValueKind::Deque(element) if push_or_pop => (**element).clone(),
+            other if front => {
+                if *other != ValueKind::Nil {
+                    self.report_error(Message::ExpectedDeque(other.clone()));
+                }
+                return ValueKind::Nil;
+            }
+            ValueKind::Heap(element) if push_or_pop => {
+                self.check_order(element, element);
+                (**element).clone()
+            }
+            ValueKind::Array(element) => (**element).clone(),
+            ValueKind::Nil => ValueKind::Nil,
+            other => {
+                self.report_error(Message::NotAnArray {
+                    name: name.to_string(),
+                    found: other.clone(),
+                });
+                return ValueKind::Nil;
+            }
+        };
+
+        match name {
+            "pop" | "pop_front" => return element,
+            "reserve" => {
+                self.check_argument(name, 2, &ValueKind::Integer, &kinds[1]);
+                return ValueKind::Nil;
+            }
+            "resize" => {
+                self.check_argument(name, 2, &ValueKind::Integer, &kinds[1]);
+                self.check_argument(name, 3, &element, &kinds[2]);
+                return ValueKind::Nil;
+            }
+            "insort" => {
+                self.check_argument(name, 2, &element, &kinds[1]);
+                self.check_order(&element, &element);
+                return ValueKind::Nil;
+            }
+            _ => {}
+        }
+
+        if !compatible(&element, &kinds[1]) {
+            self.report_error(Message::PushMismatch {
+                value: kinds[1].clone(),
+                element,
+            });
+        }
+
+        ValueKind::Nil
+    }
+
+    /// Checks a call to `abs`, `min` or `max`. Mixing int and float gives a
+    /// float, the way arithmetic does.
+    fn check_numeric_builtin(
+        &mut self,
+        name: &str,
+        kinds: &[ValueKind],
+    ) -> ValueKind {
+        if let Some(kind) = kinds
+            .iter()
+            .find(|k| !is_numeric(k) && **k != ValueKind::Nil)
+        {
+            self.report_error(Message::NotNumbers {
+                name: name.to_string(),
+                found: kind.clone(),
+            });
+            return ValueKind::Nil;
+        }
+
+        match kinds {
+            [ValueKind::Nil, other] | [other, ValueKind::Nil] => other.clone(),
+            [a, b] if a != b => {
+                if self.context.strict_mode {
+                    self.report_error(Message::ImplicitConversionIn(
+                        name.to_string(),
+                    ));
+                }
+                ValueKind::Float
+            }
+            _ => kinds[0].clone(),
+        }
+    }
+
+    /// Checks a call to one of the builtins that make or run iterators.
+    fn check_iterator_builtin(
+        &mut self,
+        name: &str,
+        kinds: &[ValueKind],
+    ) -> ValueKind {
+        if name == "range" {
+            self.check_argument(name, 1, &ValueKind::Integer, &kinds[0]);
+            return ValueKind::Iter(Box::new(ValueKind::Integer));
+        }
+
+        let element = self.item_kind(&kinds[0]);
+        let iter = |element| ValueKind::Iter(Box::new(element));
+
+        match name {
+            "iter" => iter(element),
+            "zip" => {
+                let other = self.item_kind(&kinds[1]);
+                iter(ValueKind::Tuple(vec![element, other]))
+            }
+            "enumerate" => {
+                iter(ValueKind::Tuple(vec![ValueKind::Integer, element]))
+            }
+            "group_by" => {
+                let params = vec![element.clone()];
+                let key = self.check_callback(name, 2, params, &kinds[1]);
+                if !is_key(&key) {
+                    self.report_error(Message::InvalidMapKey(key.clone()));
+                }
+                let group = ValueKind::Array(Box::new(element));
+                ValueKind::Map(Box::new(key), Box::new(group))
+            }
+            "map" => {
+                let params = vec![element];
+                iter(self.check_callback(name, 2, params, &kinds[1]))
+            }
+            "filter" => {
+                self.check_callback(name, 2, vec![element.clone()], &kinds[1]);
+                iter(element)
+            }
+            "take" => {
+                self.check_argument(name, 2, &ValueKind::Integer, &kinds[1]);
+                iter(element)
+            }
+            "fold" => {
+                let result = kinds[1].clone();
+                let params = vec![result.clone(), element];
+                let returned = self.check_callback(name, 3, params, &kinds[2]);
+                self.check_argument(name, 3, &result, &returned);
+                result
+            }
+            "sum" => {
+                if !is_numeric(&element) && element != ValueKind::Nil {
+                    self.report_error(Message::NotNumbers {
+                        name: name.to_string(),
+                        found: element.clone(),
+                    });
+                }
+                element
+            }
+            "count" => ValueKind::Integer,
+            "count_if" => {
+                self.check_callback(name, 2, vec![element], &kinds[1]);
+                ValueKind::Integer
+            }
+            "mean" | "median" | "stddev" | "percentile" | "histogram" => {
+                if !is_numeric(&element) && element != ValueKind::Nil {
+                    self.report_error(Message::NotNumbers {
+                        name: name.to_string(),
+                        found: element,
+                    });
+                }
+                match name {
+                    "percentile" => {
+                        if !is_numeric(&kinds[1]) && kinds[1] != ValueKind::Nil
+                        {
+                            self.report_error(Message::NotNumbers {
+                                name: name.to_string(),
+                                found: kinds[1].clone(),
+                            });
+                        }
+                        ValueKind::Float
+                    }
+                    "histogram" => {
+                        self.check_argument(
+                            name,
+                            2,
+                            &ValueKind::Integer,
+                            &kinds[1],
+                        );
+                        let bucket = vec![ValueKind::Float, ValueKind::Integer];
+                        ValueKind::Array(Box::new(ValueKind::Tuple(bucket)))
+                    }
+                    _ => ValueKind::Float,
+                }
+            }
+            "min_by" | "max_by" => {
+                let params = vec![element.clone()];
+                let key = self.check_callback(name, 2, params, &kinds[1]);
+                self.check_order(&key, &key);
+                element
+            }
+            "sort" => {
+                self.check_order(&element, &element);
+                ValueKind::Array(Box::new(element))
+            }
+            "sort_by_key" => {
+                let params = vec![element.clone()];
+                let key = self.check_callback(name, 2, params, &kinds[1]);
+                self.check_order(&key, &key);
+                ValueKind::Array(Box::new(element))
+            }
+            _ => ValueKind::Array(Box::new(element)),
+        }
+    }
+
+    /// Checks that values of types `a` and `b` can be ordered, for the
+    /// builtins that sort or search.
+    fn check_order(&mut self, a: &ValueKind, b: &ValueKind) {
+        if !orderable(a, b) {
+            self.report_error(Message::CannotOrder(a.clone(), b.clone()));
+        }
+    }
+
+    /// The type of the items a for loop or an iterator builtin goes over in
+    /// a value of type `kind`.
+    fn item_kind(&mut self, kind: &ValueKind) -> ValueKind {
+        match kind {
+            ValueKind::Array(element)
+            | ValueKind::Iter(element)
+            | ValueKind::Deque(element) => (**element).clone(),
+            ValueKind::Map(key, _) => (**key).clone(),
+            ValueKind::Nil => ValueKind::Nil,
+            other => {
+                self.report_error(Message::CannotIterate(other.clone()));
+                ValueKind::Nil
+            }
+        }
+    }
+
+    fn check_argument(
+        &mut self,
+        name: &str,
+        position: usize,
+        expected: &ValueKind,
+        found: &ValueKind,
+    ) {
+        if !compatible(expected, found) {
+            self.report_error(Message::ArgumentMismatch {
+                position,
+                name: name.to_string(),
+                expected: expected.clone(),
+                found: found.clone(),
+            });
+        }
+    }
+
+    /// Checks that `found`, argument `position` to `name`, is a function
+    /// that can be called with `params`. Returns what it returns.
+    fn check_callback(
+        &mut self,
+        name: &str,
+        position: usize,
+        params: Vec<ValueKind>,
+        found: &ValueKind,
+    ) -> ValueKind {
+        let expected = ValueKind::Function(params, Box::new(ValueKind::Nil));
+        self.check_argument(name, position, &expected, found);
+
+        match found {
+            ValueKind::Function(_, returns) => (**returns).clone(),
+            _ => ValueKind::Nil,
+        }
+    }
+
+    /// Checks a call to `keys`, `values` or `contains`.
+    fn check_map_lookup(
+        &mut self,
+        name: &str,
+        kinds: &[ValueKind],
+    ) -> ValueKind {
+        let (key, value) = match &kinds[0] {
+            ValueKind::Map(key, value) => ((**key).clone(), (**value).clone()),
+            ValueKind::Nil => (ValueKind::Nil, ValueKind::Nil),
+            other => {
+                self.report_error(Message::NotAMap {
+                    name: name.to_string(),
+                    found: other.clone(),
+                });
+                return ValueKind::Nil;
+            }
+        };
+
+        match name {
+            "keys" => ValueKind::Array(Box::new(key)),
+            "values" => ValueKind::Array(Box::new(value)),
+            _ => {
+                if !compatible(&key, &kinds[1]) {
+                    self.report_error(Message::KeyMismatch {
+                        found: kinds[1].clone(),
+                        key,
+                    });
+                }
+                ValueKind::Bool
+            }
+        }
+    }
+
+    /// Whether `node` names somewhere a value can be stored, a variable or
+    /// an element of an array or map stored in one.
+    fn is_place(&self, node: AstNodeId) -> bool {
+        match &self.ast.arena[node].expr().kind {
+            ExprKind::Ident(_) => true,
+            ExprKind::Index(index) => self.is_place(index.value),
+            _ => false,
+        }
+    }
+
+    fn check_array(&mut self, elements: &[AstNodeId]) -> ValueKind {
+        trace!("Array");
+
+        let mut element = ValueKind::Nil;
+        for node in elements {
+            element =
+                self.check_element(element, *node, Message::MixedArrayElements);
+        }
+
+        ValueKind::Array(Box::new(element))
+    }
+
+    fn check_map(&mut self, entries: &[(AstNodeId, AstNodeId)]) -> ValueKind {
+        trace!("Map");
+
+        let mut key = ValueKind::Nil;
+        let mut value = ValueKind::Nil;
+        for (key_node, value_node) in entries {
+            key = self.check_element(key, *key_node, Message::MixedMapKeys);
+            value =
+                self.check_element(value, *value_node, Message::MixedMapValues);
+        }
+
+        if !is_key(&key) {
+            self.report_error(Message::InvalidMapKey(key.clone()));
+        }
+
+        ValueKind::Map(Box::new(key), Box::new(value))
+    }
+
+    /// Checks one element of an array or map literal against the type of
+    /// the elements before it, and returns the type of them all.
+    fn check_element(
+        &mut self,
+        expected: ValueKind,
+        node: AstNodeId,
+        mismatch: fn(ValueKind, ValueKind) -> Message,
+    ) -> ValueKind {
+        let expr = self.ast.arena[node].expr();
+        let kind = self.check_expr(expr);
+
+        if !compatible(&expected, &kind) {
+            self.location.push(expr.location);
+            self.report_error(mismatch(expected.clone(), kind.clone()));
+            self.location.pop();
+        }
+
+        if expected == ValueKind::Nil {
+            kind
+        } else {
+            expected
+        }
+    }
+
+    /// The types of the variables an item of type `kind` is unpacked into
+    /// by `for a, b in items`.
+    fn unpack_kinds(
+        &mut self,
+        kind: ValueKind,
+        count: usize,
+    ) -> Vec<ValueKind> {
+        match kind {
+            ValueKind::Tuple(kinds) if kinds.len() == count => kinds,
+            ValueKind::Array(element) => vec![*element; count],
+            ValueKind::Nil => vec![ValueKind::Nil; count],
+            found => {
+                self.report_error(Message::CannotUnpack { count, found });
+                vec![ValueKind::Nil; count]
+            }
+        }
+    }
+
+    fn check_index(&mut self, index: &Index) -> ValueKind {
+        trace!("Index");
+
+        let kind = self.check_expr(self.ast.arena[index.value].expr());
+        self.check_indexing(kind, index.index)
+    }
+
+    /// Checks indexing a value of type `kind` with `index` and returns the
+    /// type of the element.
+    fn check_indexing(
+        &mut self,
+        kind: ValueKind,
+        index: AstNodeId,
+    ) -> ValueKind {
+        let index = self.ast.arena[index].expr();
+        let index_kind = self.check_expr(index);
+
+        // Each element of a tuple has its own type, so which one is meant
+        // has to be known here.
+        if let ValueKind::Tuple(mut kinds) = kind {
+            return match index.kind {
+                ExprKind::Integer(i) if (i as usize) < kinds.len() => {
+                    kinds.swap_remove(i as usize)
+                }
+                _ => {
+                    self.location.push(index.location);
+                    self.report_error(Message::TupleIndex { len: kinds.len() });
+                    self.location.pop();
+                    ValueKind::Nil
+                }
+            };
+        }
+
+        self.check_index_kind(kind, index_kind, index.location)
+    }
+
+    /// Checks that a value of type `kind` can be indexed by a value of type
+    /// `index` at `location` and returns the type of the element.
+    fn check_index_kind(
+        &mut self,
+        kind: ValueKind,
+        index: ValueKind,
+        location: Location,
+    ) -> ValueKind {
+        let (expected, element) = match kind {
+            ValueKind::Array(element) => (ValueKind::Integer, *element),
+            ValueKind::Map(key, value) => (*key, *value),
+            ValueKind::String => (ValueKind::Integer, ValueKind::String),
+            ValueKind::Nil => (ValueKind::Nil, ValueKind::Nil),
+            other => {
+                self.report_error(Message::CannotIndex(other));
+                return ValueKind::Nil;
+            }
+        };
+
+        if !compatible(&expected, &index) {
+            self.location.push(location);
+            self.report_error(Message::IndexMismatch {
+                expected,
+                found: index,
+            });
+            self.location.pop();
+        }
+
+        element
+    }
+
+    fn check_try(&mut self, try_expr: &Try) -> ValueKind {
+        trace!("Try");
+
+        let kind = self.check_expr(self.ast.arena[try_expr.value].expr());
+
+        if self.current_function.is_none() {
+            self.report_error(Message::TryOutsideFunction);
+        }
+
+        match kind {
+            // The payload of a result is not tracked, so the unwrapped value
+            // is treated as unknown.
+            ValueKind::Result | ValueKind::Nil => ValueKind::Nil,
+            other => {
+                self.report_error(Message::TryOnNonResult(other));
+                ValueKind::Nil
+            }
+        }
+    }
+
     fn check_ident(&mut self, ident: Symbol) -> ValueKind {
         trace!("ident");
 
-        match self.scope.get_variable(ident) {
-            Some(var) => var.kind,
+        if let Some(var) = self.scope.get_variable(ident) {
+            return var.kind.clone();
+        }
+
+        if let Some(value) = self.context.constants.get(&ident) {
+            return kind_of(value);
+        }
+
+        // A function can be passed as a value, like to `map`.
+        match self.signature(ident) {
+            Some((params, return_type)) => ValueKind::Function(
+                params,
+                Box::new(return_type.unwrap_or(ValueKind::Nil)),
+            ),
             None => {
-                self.report_error(&format!(
-                    "Variable '{}' is undefined",
-                    self.context.interner.get(ident)
-                ));
+                self.report_error(Message::UndefinedVariable {
+                    name: self.context.interner.get(ident).to_string(),
+                    suggestion: semantic::suggestion(
+                        ident,
+                        &self.scope,
+                        self.context,
+                    ),
+                });
                 ValueKind::Nil
             }
         }
     }
+
+    /// The parameter and return types of the function declared by the
+    /// script or registered as `name`.
+    fn signature(
+        &mut self,
+        name: Symbol,
+    ) -> Option<(Vec<ValueKind>, Option<ValueKind>)> {
+        match self.scope.get_function(name) {
+            Some(func) => {
+                let func = self.ast.arena[func].function();
+                let params = func
+                    .params
+                    .iter()
+                    .map(|param| self.ast.arena[*param].param().kind.clone())
+                    .collect();
+                Some((params, func.return_type.clone()))
+            }
+            None => self
+                .context
+                .native_functions
+                .get(&name)
+                .map(|func| (func.params.clone(), func.return_type.clone())),
+        }
+    }
+}
+
+/// How many arguments a builtin that works on several types takes. These
+/// are checked here instead of being registered with their types.
+pub(crate) fn generic_arity(name: &str) -> Option<usize> {
+    match name {
+        "heap" | "deque" => Some(0),
+        "len" | "pop" | "pop_front" | "keys" | "values" | "abs" => Some(1),
+        "array_with_capacity" => Some(1),
+        "push" | "contains" | "get" | "reserve" | "min" | "max" => Some(2),
+        "divmod" => Some(2),
+        "push_front" => Some(2),
+        "binary_search" | "insort" => Some(2),
+        "resize" => Some(3),
+        "range" | "iter" | "sum" | "count" | "collect" => Some(1),
+        "sort" | "reverse" => Some(1),
+        "map" | "filter" | "take" | "sort_by_key" | "compose" => Some(2),
+        "enumerate" => Some(1),
+        "zip" | "group_by" => Some(2),
+        "mean" | "median" | "stddev" => Some(1),
+        "percentile" | "histogram" => Some(2),
+        "count_if" | "min_by" | "max_by" => Some(2),
+        "fold" | "retry" => Some(3),
+        "with_timeout" => Some(2),
+        "arity" => Some(1),
+        _ => None,
+    }
+}
+
+fn is_numeric(kind: &ValueKind) -> bool {
+    *kind == ValueKind::Integer || *kind == ValueKind::Float
+}
+
+/// Whether a value of type `found` can be used where `expected` is wanted.
+/// `Nil` stands for a type that is not known statically, which is also the
+/// element type of an empty array or map.
+fn compatible(expected: &ValueKind, found: &ValueKind) -> bool {
+    match (expected, found) {
+        (ValueKind::Nil, _) | (_, ValueKind::Nil) => true,
+        (ValueKind::Array(a), ValueKind::Array(b)) => compatible(a, b),
+        (ValueKind::Map(a, b), ValueKind::Map(c, d)) => {
+            compatible(a, c) && compatible(b, d)
+        }
+        (ValueKind::Iter(a), ValueKind::Iter(b))
+        | (ValueKind::Heap(a), ValueKind::Heap(b))
+        | (ValueKind::Deque(a), ValueKind::Deque(b)) => compatible(a, b),
+        (ValueKind::Tuple(a), ValueKind::Tuple(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| compatible(a, b))
+        }
+        (ValueKind::Function(a, r), ValueKind::Function(b, s)) => {
+            a.len() == b.len()
+                && a.iter().zip(b).all(|(a, b)| compatible(a, b))
+                && compatible(r, s)
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// Whether values of the type can be used as map keys.
+fn is_key(kind: &ValueKind) -> bool {
+    matches!(
+        kind,
+        ValueKind::Bool
+            | ValueKind::Integer
+            | ValueKind::String
+            | ValueKind::Nil
+    )
+}
+
+/// Whether `==` between the two types can ever be true.
+fn comparable(a: &ValueKind, b: &ValueKind) -> bool {
+    compatible(a, b) || (is_numeric(a) && is_numeric(b))
+}
+
+fn orderable(a: &ValueKind, b: &ValueKind) -> bool {
+    match (a, b) {
+        (ValueKind::Nil, _) | (_, ValueKind::Nil) => true,
+        (ValueKind::String, ValueKind::String)
+        | (ValueKind::Bool, ValueKind::Bool) => true,
+        (a, b) => is_numeric(a) && is_numeric(b),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::path::PathBuf;
+
+    use crate::builtins;
+    use crate::lexer;
+    use crate::location::Span;
+    use crate::parser;
+
+    fn check(source: &str) -> bool {
+        run_check(source, false, false)
+    }
+
+    fn check_in_function(source: &str) -> bool {
+        run_check(source, true, false)
+    }
+
+    fn check_strict(source: &str) -> bool {
+        run_check(source, false, true)
+    }
+
+    fn run_check(source: &str, in_function: bool, strict: bool) -> bool {
+        let mut context = Context::new();
+        builtins::register(&mut context);
+        context.strict_mode = strict;
+        let file = context.interner.intern("test.bl");
+        context
+            .source_code
+            .insert(PathBuf::from("test.bl"), source.to_string());
+
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .expect("Lexing failed");
+        let ast =
+            parser::parse_ast(tokens, &mut context).expect("Parsing failed");
+
+        let current_function = if in_function {
+            Some(FunctionDecl {
+                name: context.interner.intern("main"),
+                body: vec![],
+                params: vec![],
+                return_type: Some(ValueKind::Result),
+                location: Location {
+                    file,
+                    line: 1,
+                    span: Span { start: 0, len: 0 },
+                },
+            })
+        } else {
+            None
+        };
+
+        let mut checker = Typechecker {
+            ast: &ast,
+            check_passed: true,
+            scope: Scope::new(),
+            context: &mut context,
+            location: vec![],
+            current_function,
+        };
+        checker.check_stmt_list(&ast.statements);
+        checker.check_passed
+    }
+
+    #[test]
+    fn unsupported_syntax() {
+        assert!(!check("struct A { x: int }"));
+        assert!(!check("a := 1 b := a.len"));
+
+        // The parser only makes ranges for loops, but a range anywhere else
+        // is an error rather than a crash.
+        let mut context = Context::new();
+        let file = context.interner.intern("test.bl");
+        let tokens = lexer::generate_tokens(b"a := 1", file, &mut context)
+            .expect("Lexing failed");
+        let mut ast =
+            parser::parse_ast(tokens, &mut context).expect("Parsing failed");
+        let value = match &ast.arena[ast.statements[0]] {
+            Stmt::Decl(Decl::Variable(var)) => var.value,
+            other => panic!("Expected a declaration, found {:?}", other),
+        };
+        let location = ast.arena[value].expr().location;
+        ast.arena[value] = Stmt::Expr(Expr {
+            location,
+            kind: ExprKind::Range(crate::ast::Range { start: 0, end: 3 }),
+        });
+        assert!(typecheck(&ast, &mut context).is_err());
+    }
+
+    #[test]
+    fn arithmetic_expr() {
+        assert!(check("a := 1 + 2 * 3 - 4 / 2 % 3"));
+        assert!(check("a := \"a\" + \"b\""));
+        assert!(!check("a := \"a\" - \"b\""));
+        assert!(!check("a := true + false"));
+    }
+
+    #[test]
+    fn assignment_types() {
+        assert!(check("a := 1 a = 2 a += 3 a *= -a"));
+        assert!(!check("a := 1 a = \"a\""));
+        assert!(!check("a := 1 a += 5.5"));
+        assert!(!check("b = 1"));
+    }
+
+    #[test]
+    fn loops() {
+        assert!(check("n := 0 for i in 0..10 { n += i }"));
+        assert!(!check("for i in 0..10 { } n := i"));
+        assert!(check("n := 0 while n < 10 { n += 1 }"));
+        assert!(!check_strict("n := 10 while n { n -= 1 }"));
+    }
+
+    #[test]
+    fn print_format_string() {
+        assert!(check("print(\"%\", 1)"));
+        assert!(!check("print(1)"));
+    }
+
+    #[test]
+    fn try_on_result() {
+        assert!(check_in_function("Ok(5)? + 1"));
+        assert!(check_in_function("Err(\"failed\")?"));
+    }
+
+    #[test]
+    fn try_on_non_result() {
+        assert!(!check_in_function("5?"));
+    }
+
+    #[test]
+    fn try_outside_function() {
+        assert!(!check("Ok(5)?"));
+    }
+
+    #[test]
+    fn var_decl_types() {
+        assert!(check("a := 5 b: int = a + 2"));
+        assert!(!check("a: string = 5"));
+    }
+
+    #[test]
+    fn mixed_comparisons() {
+        assert!(check("a := 1 < 2.5"));
+        assert!(check("a := \"a\" < \"b\""));
+        // Always false, but only a warning
+        assert!(check("a := 1 == \"a\""));
+        assert!(!check("a := 1 < \"a\""));
+        assert!(!check("a := true >= 2"));
+    }
+
+    #[test]
+    fn division_by_literal_zero() {
+        assert!(check("a := 5 / 1"));
+        assert!(!check("a := 5 / 0"));
+        assert!(!check("a := 5 % 0"));
+    }
+
+    #[test]
+    fn strict_implicit_conversion() {
+        assert!(check("a := 5 + 2.5"));
+        assert!(!check_strict("a := 5 + 2.5"));
+    }
+
+    #[test]
+    fn strict_shadowing() {
+        assert!(check("a := 5 a := 6"));
+        assert!(!check_strict("a := 5 a := 6"));
+    }
+
+    #[test]
+    fn strict_conditions() {
+        assert!(check("a := 1 if a { b := 2 }"));
+        assert!(check_strict("a := 1 if a > 0 { b := 2 }"));
+        assert!(!check_strict("a := 1 if a { b := 2 }"));
+    }
+
+    #[test]
+    fn if_body_scope() {
+        assert!(check("if true { a := 1 } a := \"a\""));
+        assert!(!check("if true { a := 1 } b := a"));
+    }
+
     #[test]
-    fn arithmetic_expr() {}
+    fn function_calls() {
+        assert!(check(
+            "fn add(a: int, b: int) -> int { return a + b } c := add(1, 2) + 3"
+        ));
+        assert!(check(
+            "a := twice(2) fn twice(n: int) -> int { return n * 2 }"
+        ));
+        assert!(check("fn fib(n: int) -> int { if n < 2 { return n } return fib(n - 1) + fib(n - 2) }"));
+        assert!(!check(
+            "fn add(a: int, b: int) -> int { return a + b } add(1)"
+        ));
+        assert!(!check(
+            "fn add(a: int, b: int) -> int { return a + b } add(1, \"b\")"
+        ));
+        assert!(!check(
+            "a: string = double(2) fn double(n: int) -> int { return n * 2 }"
+        ));
+        assert!(!check("missing()"));
+    }
+
+    #[test]
+    fn function_returns() {
+        assert!(check("fn greet() { print(\"hi\") return }"));
+        assert!(!check("fn greet() { return 5 }"));
+        assert!(!check("fn five() -> int { return \"5\" }"));
+        assert!(!check("fn five() -> int { return }"));
+        assert!(!check("return 5"));
+    }
+
+    #[test]
+    fn function_scope() {
+        assert!(check("fn f(n: int) { m := n } m := \"m\""));
+        assert!(!check("fn f() { a := 1 } fn g() { b := a }"));
+        assert!(!check("fn f(n: int) { } m := n"));
+    }
+
+    #[test]
+    fn arrays() {
+        assert!(check("xs := [1, 2] a: int = xs[0] + len(xs)"));
+        assert!(check("xs: [[int]] = [[1], []] xs[0][0] = 2 xs[1] = [3]"));
+        assert!(check(
+            "xs: [string] = [] push(xs, \"a\") s := pop(xs) + \"b\""
+        ));
+        assert!(check("fn first(xs: [float]) -> float { return xs[0] }"));
+        assert!(!check("xs := [1, \"2\"]"));
+        assert!(!check("xs := [1] xs[0] = \"a\""));
+        assert!(!check("xs := [1] a := xs[\"0\"]"));
+        assert!(!check("a := 1 b := a[0]"));
+        assert!(!check("xs := [1] push(xs, 1.5)"));
+        assert!(!check("push([1], 2)"));
+        assert!(!check("a := pop(5)"));
+        assert!(!check("xs: [int] = [\"a\"]"));
+    }
+
+    #[test]
+    fn growing_arrays() {
+        assert!(check(
+            "xs: [float] = array_with_capacity(4) resize(xs, 2, 0.5)"
+        ));
+        assert!(check("xs := [[1]] reserve(xs[0], 4) resize(xs, 0, [])"));
+        assert!(!check("xs := array_with_capacity(1.5)"));
+        assert!(!check("xs := [1] resize(xs, 2, \"a\")"));
+        assert!(!check("xs := [1] reserve(xs, \"a\")"));
+        assert!(!check("reserve([1], 4)"));
+        assert!(!check("xs := [1] resize(xs, 2)"));
+    }
+
+    #[test]
+    fn string_indices() {
+        assert!(check("s := \"ab\" c: string = s[-1] + s[0]"));
+        assert!(check("r: result = get(\"ab\", 0) r = get({1: 2}, 1)"));
+        assert!(!check("s := \"ab\" s[0] = \"c\""));
+        assert!(!check("s := \"ab\" c := s[\"0\"]"));
+        assert!(!check("r := get([1], \"0\")"));
+        assert!(!check("r := get(1, 0)"));
+    }
+
+    #[test]
+    fn maps() {
+        assert!(check(
+            "m := {\"a\": 1} m[\"b\"] = 2 n: int = m[\"a\"] + len(m)"
+        ));
+        assert!(check("m: {int: [string]} = {} m[1] = [] push(m[1], \"a\")"));
+        assert!(check(
+            "m := {true: 1.5} ks: [bool] = keys(m) vs: [float] = values(m)"
+        ));
+        assert!(check("m := {1: 2} b: bool = contains(m, 3)"));
+        assert!(!check("m := {\"a\": 1, 2: 3}"));
+        assert!(!check("m := {\"a\": 1, \"b\": true}"));
+        assert!(!check("m := {[1]: 1}"));
+        assert!(!check("m := {\"a\": 1} n := m[0]"));
+        assert!(!check("m := {\"a\": 1} m[\"b\"] = \"c\""));
+        assert!(!check("m := {\"a\": 1} b := contains(m, 1)"));
+        assert!(!check("k := keys([1])"));
+    }
+
+    #[test]
+    fn for_loops() {
+        assert!(check("for i in 0..3 { n: int = i }"));
+        assert!(check("for x in [1.5] { n: float = x }"));
+        assert!(check("for k in {\"a\": 1} { s: string = k }"));
+        assert!(!check("for x in [1] { s: string = x }"));
+        assert!(!check("n := 3 for x in n { }"));
+    }
+
+    #[test]
+    fn builtins() {
+        assert!(check("a: int = abs(-1) b: float = min(1.5, 2.0)"));
+        assert!(check(
+            "a: float = max(1, 2.5) xs: [string] = split(\"a\", \"\")"
+        ));
+        assert!(check("r := read_file(\"a\") println(\"%\", r)"));
+        assert!(!check("a := abs(\"1\")"));
+        assert!(!check("a := min(1)"));
+        assert!(!check("a: int = max(1, 2.5)"));
+        assert!(!check_strict("a := max(1, 2.5)"));
+        assert!(!check("s := trim(1)"));
+        assert!(!check("s := write_file(\"a\")"));
+    }
+
+    #[test]
+    fn iterators() {
+        assert!(check(
+            "fn half(n: int) -> float { return n / 2.0 } \
+             xs: [float] = collect(map(range(4), half)) s: float = sum(xs)"
+        ));
+        assert!(check("for s in map([\"a\"], trim) { t: string = s }"));
+        assert!(check(
+            "fn big(n: int) -> bool { return n > 1 } \
+             f := big g: int = count(filter({1: 2}, f))"
+        ));
+        assert!(!check("xs := map(range(4), trim)"));
+        assert!(!check("xs := map(5, trim)"));
+        assert!(!check("xs := take(range(4), \"2\")"));
+        assert!(!check("n := sum(map([\"a\"], trim))"));
+        assert!(!check("fn f(a: int) { } n := fold([1], 0, f)"));
+    }
+
+    #[test]
+    fn sorting() {
+        assert!(check("xs: [string] = sort(reverse([\"b\", \"a\"]))"));
+        assert!(check(
+            "fn neg(n: int) -> float { return -n * 1.0 } \
+             xs: [int] = sort_by_key(range(3), neg)"
+        ));
+        assert!(!check("xs := sort([[1]])"));
+        assert!(!check("xs := sort(1)"));
+        assert!(!check(
+            "fn id(n: int) -> [int] { return [n] } xs := sort_by_key([1], id)"
+        ));
+        assert!(!check(
+            "fn neg(n: int) -> int { return -n } xs := sort_by_key([\"a\"], neg)"
+        ));
+    }
+
+    #[test]
+    fn binary_search_and_insort() {
+        assert!(check(
+            "xs := [1.5] insort(xs, 2.5) r: result = binary_search(xs, 2.0)"
+        ));
+        assert!(!check("xs := [1] insort(xs, \"a\")"));
+        assert!(!check("xs := [[1]] insort(xs, [2])"));
+        assert!(!check("r := binary_search({1: 2}, 1)"));
+        assert!(!check("r := binary_search([true], 1)"));
+    }
+
+    #[test]
+    fn heaps_and_deques() {
+        assert!(check(
+            "h: heap[int] = heap() push(h, 1) n: int = pop(h) + len(h)"
+        ));
+        assert!(check(
+            "fn f(d: deque[string]) -> string { return pop_front(d) } \
+             d: deque[string] = deque() push_front(d, \"a\") s := f(d) \
+             for x in d { s = x }"
+        ));
+        assert!(!check("h: heap[int] = heap() push(h, \"a\")"));
+        assert!(!check("h: heap[[int]] = heap() push(h, [1])"));
+        assert!(!check("h := heap() push_front(h, 1)"));
+        assert!(!check("d := deque() reserve(d, 1)"));
+        assert!(!check("d: deque[int] = heap()"));
+    }
+
+    #[test]
+    fn zip_enumerate_and_group_by() {
+        assert!(check(
+            "for i, s in enumerate([\"a\"]) { n: int = i + len(s) } \
+             for a, b in [[1, 2]] { n: int = a + b } \
+             p := collect(zip([1], [\"a\"])) n: int = p[0][0] s: string = p[0][1]"
+        ));
+        assert!(check(
+            "fn len2(s: string) -> int { return len(s) } \
+             m: {int: [string]} = group_by([\"a\"], len2)"
+        ));
+        assert!(!check("for a, b in enumerate([1]) { s: string = a }"));
+        assert!(!check("for a, b, c in zip([1], [2]) {}"));
+        assert!(!check("p := collect(enumerate([1])) i := 0 x := p[0][i]"));
+        assert!(!check(
+            "fn f(n: int) -> [int] { return [n] } m := group_by([1], f)"
+        ));
+    }
+
+    #[test]
+    fn arities() {
+        assert!(check("fn f() {} n: int = arity(f) m: int = arity(trim)"));
+        assert!(!check("n := arity(1)"));
+        assert!(!check("fn f() {} n := arity(f, f)"));
+    }
+
+    #[test]
+    fn eval_arguments() {
+        assert!(check("r := eval(\"1\") s := eval(\"a\", {\"a\": 1})"));
+        assert!(!check("r := eval(1)"));
+        assert!(!check("r := eval(\"a\", {1: 1})"));
+        assert!(!check("r := eval(\"a\", {\"a\": 1}, 2)"));
+    }
+
+    #[test]
+    fn aggregates() {
+        assert!(check(
+            "fn neg(n: int) -> int { return -n } \
+             fn even(n: int) -> bool { return n % 2 == 0 } \
+             a: float = mean([1, 2]) b: int = count_if(range(3), even) \
+             c: int = min_by([1], neg) d: int = max_by(range(2), neg)"
+        ));
+        assert!(!check("x := mean([\"a\"])"));
+        assert!(check(
+            "a: float = median([1, 2]) b: float = stddev(range(3)) \
+             c: float = percentile([1.5], 90) h := histogram([1], 2) \
+             d: float = h[0][0] e: int = h[0][1]"
+        ));
+        assert!(!check("x := stddev([true])"));
+        assert!(!check("x := percentile([1], \"50\")"));
+        assert!(!check("x := histogram([1], 2.0)"));
+        assert!(!check(
+            "fn f(n: int) -> [int] { return [n] } x := min_by([1], f)"
+        ));
+        assert!(!check(
+            "fn f(s: string) -> bool { return true } n := count_if([1], f)"
+        ));
+        assert!(!check(
+            "fn f(n: int) -> int { return n } s: string = max_by([1], f)"
+        ));
+    }
+
+    #[test]
+    fn partial_and_compose() {
+        let check_with = |source: &str| {
+            check(&format!(
+                "fn add(a: float, b: int) -> float {{ return a + b }} \
+                 fn half(n: float) -> float {{ return n / 2.0 }} {}",
+                source
+            ))
+        };
+        assert!(check_with(
+            "xs: [float] = collect(map([1], partial(add, 0.5)))"
+        ));
+        assert!(check_with("n: float = fold([1], 0.0, compose(add, half))"));
+        assert!(!check_with("f := compose(partial(add, 0.5), trim)"));
+        assert!(!check_with("f := partial(add, 0.5, 2, 3)"));
+        assert!(!check_with("f := partial(add, \"a\")"));
+        assert!(!check("f := partial(1, 2)"));
+        assert!(!check("f := compose(trim, 2)"));
+    }
+
+    #[test]
+    fn retry_and_timeout() {
+        let check_with = |source: &str| {
+            check(&format!(
+                "fn f() -> result {{ return Ok(1) }} \
+                 fn g() -> int {{ return 1 }} {}",
+                source
+            ))
+        };
+        assert!(check_with("r := retry(f, 3, 100) s := with_timeout(g, 10)"));
+        assert!(!check_with("r := retry(g, 3, 100)"));
+        assert!(!check_with("r := retry(f, 3.0, 100)"));
+        assert!(!check_with("r := with_timeout(f, \"1s\")"));
+        assert!(!check(
+            "fn f(n: int) -> result { return Ok(n) } \
+                        r := retry(f, 1, 0)"
+        ));
+    }
+
+    #[test]
+    fn string_concatenation() {
+        assert!(check("s: string = \"a\" + 1 + 2.5 + true"));
+        assert!(check("s: string = 1 + \"a\""));
+        assert!(check("s: string = \"${[1]} ${{1: 2}}\""));
+        assert!(!check("s := \"a\" - 1"));
+        assert!(!check("n: int = \"${1}\""));
+        assert!(!check("s := \"${undefined}\""));
+    }
+
+    #[test]
+    fn strict_unused_result() {
+        assert!(check("a := 5 a + 1"));
+        assert!(!check_strict("a := 5 a + 1"));
+    }
 }
@@ -0,0 +1,183 @@
+use std::fmt::Write;
+
+/// The parts of a URL like `https://ann@example.com:8080/a/b?q=1#top`.
+/// Parts the URL does not have are empty.
+#[derive(Debug, Default, PartialEq)]
+pub struct Url {
+    pub scheme: String,
+    /// The user info before `@`, which may hold a password after `:`.
+    pub user: String,
+    pub host: String,
+    pub port: Option<u16>,
+    pub path: String,
+    /// The query without its `?`, still encoded.
+    pub query: String,
+    pub fragment: String,
+}
+
+/// Splits `text` into the parts of a URL. Returns the reason if it has no
+/// scheme or an invalid port.
+pub fn parse(text: &str) -> Result<Url, String> {
+    let colon = text.find(':').ok_or("Missing scheme")?;
+    let scheme = &text[..colon];
+    let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    if !valid_scheme {
+        return Err(format!("Invalid scheme '{}'", scheme));
+    }
+
+    let mut url = Url {
+        scheme: scheme.to_ascii_lowercase(),
+        ..Url::default()
+    };
+    let mut rest = &text[colon + 1..];
+
+    if let Some(fragment) = rest.find('#') {
+        url.fragment = rest[fragment + 1..].to_string();
+        rest = &rest[..fragment];
+    }
+    if let Some(query) = rest.find('?') {
+        url.query = rest[query + 1..].to_string();
+        rest = &rest[..query];
+    }
+
+    if let Some(after) = rest.strip_prefix("//") {
+        let end = after.find('/').unwrap_or(after.len());
+        let mut authority = &after[..end];
+        rest = &after[end..];
+
+        if let Some(at) = authority.rfind('@') {
+            url.user = authority[..at].to_string();
+            authority = &authority[at + 1..];
+        }
+
+        // An IPv6 address has colons of its own inside brackets.
+        let port_start = match authority.rfind(']') {
+            Some(bracket) => {
+                authority[bracket..].find(':').map(|i| bracket + i)
+            }
+            None => authority.find(':'),
+        };
+        if let Some(port_start) = port_start {
+            let port = &authority[port_start + 1..];
+            if !port.is_empty() {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("Invalid port '{}'", port))?;
+                url.port = Some(port);
+            }
+            authority = &authority[..port_start];
+        }
+        url.host = authority.to_ascii_lowercase();
+    }
+
+    url.path = rest.to_string();
+    Ok(url)
+}
+
+/// Percent-encodes every byte of `text` except letters, digits and `-`,
+/// `.`, `_` and `~`, so that it can be put anywhere in a URL.
+pub fn encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for b in text.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", b);
+        }
+    }
+    encoded
+}
+
+/// Turns the `%XX` escapes in `text` back into the bytes they stand for,
+/// and `+` into a space as in query strings. Returns the reason if an
+/// escape is cut short or the bytes are not UTF-8.
+pub fn decode(text: &str) -> Result<String, String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let byte = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("Invalid escape at byte {}", i))?;
+                decoded.push(byte);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            b => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| "Invalid UTF-8".to_string())
+}
+
+/// Joins `pairs` into a query string like `a=1&b=two%20words`.
+pub fn query(pairs: &[(String, String)]) -> String {
+    let pairs: Vec<_> = pairs
+        .iter()
+        .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+        .collect();
+    pairs.join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        assert_eq!(
+            parse("HTTPS://ann:pw@Example.com:8080/a/b?q=1&r=2#top"),
+            Ok(Url {
+                scheme: "https".to_string(),
+                user: "ann:pw".to_string(),
+                host: "example.com".to_string(),
+                port: Some(8080),
+                path: "/a/b".to_string(),
+                query: "q=1&r=2".to_string(),
+                fragment: "top".to_string(),
+            })
+        );
+
+        let url = parse("http://[::1]:80").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("[::1]", Some(80)));
+        assert_eq!(url.path, "");
+
+        let url = parse("mailto:ann@example.com").unwrap();
+        assert_eq!(
+            (url.host.as_str(), url.path.as_str()),
+            ("", "ann@example.com")
+        );
+
+        assert!(parse("example.com").is_err());
+        assert!(parse("1http://example.com").is_err());
+        assert!(parse("http://example.com:http").is_err());
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(encode("a b/å~"), "a%20b%2F%C3%A5~");
+        assert_eq!(decode("a%20b%2F%C3%A5~+c"), Ok("a b/å~ c".to_string()));
+        assert!(decode("%2").is_err());
+        assert!(decode("%zz").is_err());
+        assert!(decode("%FF").is_err());
+
+        let pairs = vec![
+            ("q".to_string(), "two words".to_string()),
+            ("&".to_string(), "=".to_string()),
+        ];
+        assert_eq!(query(&pairs), "q=two%20words&%26=%3D");
+    }
+}
@@ -0,0 +1,1335 @@
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
+
+use hashbrown::HashMap;
+
+use crate::ast::{Ast, BinaryOpKind, StmtList};
+use crate::builtins::{self, Caller};
+use crate::common::{grow_stack, Context, StringInterner, Symbol};
+use crate::compiler::{Op, Place, Program};
+use crate::interpreter::{
+    self, arithmetic, concat, element_mut, format_output, kind_of, lookup,
+    map_key, negate, order, unpack,
+};
+use crate::interrupt::{self, Interruptible};
+use crate::location::Location;
+use crate::messages::Message;
+use crate::primitives::{Callee, Function, Map, Value};
+use crate::scope::Scope;
+
+/// Runs a compiled program.
+#[allow(clippy::result_unit_err)]
+pub fn run(program: &Program, context: &mut Context) -> Result<(), ()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = Interruptible(stdin.lock());
+    let mut output = stdout.lock();
+
+    Vm::new(program, context, &mut input, &mut output).run()
+}
+
+/// Deeper recursion than this is most likely a bug in the script, and is
+/// stopped the same way the tree-walking interpreter stops it.
+const MAX_CALL_DEPTH: usize = 10_000;
+
+/// Number of calls shown in the trace printed with a runtime error.
+const MAX_TRACE_LEN: usize = 10;
+
+struct Frame {
+    func: usize,
+    ip: usize,
+    /// Where the slots of the function start.
+    base: usize,
+    /// Height of the stack when the function was called.
+    stack: usize,
+}
+
+struct Vm<'a> {
+    program: &'a Program,
+    context: &'a mut Context,
+    stack: Vec<Value>,
+    slots: Vec<Value>,
+    frames: Vec<Frame>,
+    /// Every global with a name, latest last.
+    globals: HashMap<Symbol, Vec<Value>>,
+    interrupted: &'a AtomicBool,
+    /// When the innermost call to `with_timeout` has to stop, and whether
+    /// it is being stopped.
+    deadline: Option<Instant>,
+    timed_out: bool,
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+}
+
+impl<'a> Vm<'a> {
+    fn new(
+        program: &'a Program,
+        context: &'a mut Context,
+        input: &'a mut dyn BufRead,
+        output: &'a mut dyn Write,
+    ) -> Self {
+        Vm {
+            program,
+            context,
+            stack: Vec::new(),
+            slots: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::default(),
+            interrupted: interrupt::flag(),
+            deadline: None,
+            timed_out: false,
+            input,
+            output,
+        }
+    }
+
+    fn run(&mut self) -> Result<(), ()> {
+        let main = &self.program.functions[0];
+        self.slots.resize(main.slots as usize, Value::Nil);
+        self.frames.push(Frame {
+            func: 0,
+            ip: 0,
+            base: 0,
+            stack: 0,
+        });
+
+        let mut result = Ok(());
+        while result.is_ok() && !self.frames.is_empty() {
+            result = self.step();
+        }
+
+        // The functions given to `on_exit` run even after an error, called
+        // from the top level where the script stopped.
+        self.frames.truncate(1);
+        if self.frames.is_empty() {
+            let ip = main.locations.iter().rposition(Option::is_some);
+            self.frames.push(Frame {
+                func: 0,
+                ip: ip.map_or(0, |ip| ip + 1),
+                base: 0,
+                stack: 0,
+            });
+        }
+        self.stack.clear();
+        self.slots.truncate(main.slots as usize);
+
+        let mut i = 0;
+        loop {
+            let handler = self.context.handlers.borrow().exit.get(i).cloned();
+            match handler {
+                Some(func) => self.call_function(&func, vec![])?,
+                None => return result,
+            };
+            i += 1;
+        }
+    }
+
+    /// The location of the instruction being run.
+    fn location(&self) -> Location {
+        let frame = &self.frames[self.frames.len() - 1];
+        self.program.functions[frame.func].locations[frame.ip - 1]
+            .expect("Instructions that can fail have a location")
+    }
+
+    fn report_error(&mut self, message: Message) {
+        let location = self.location();
+        self.context.report_error(message, location);
+
+        let calls = self.frames.len() - 1;
+        for i in (1..self.frames.len()).rev().take(MAX_TRACE_LEN) {
+            let caller = &self.frames[i - 1];
+            let location = self.program.functions[caller.func].locations
+                [caller.ip - 1]
+                .expect("Calls have a location");
+            let name = self.program.functions[self.frames[i].func].name;
+
+            let file = self.context.interner.get(location.file);
+            let column = self
+                .context
+                .source_code
+                .get(&PathBuf::from(file))
+                .map_or(1, |source| location.column(source.as_bytes()));
+
+            let message = Message::CalledAt {
+                name: self.context.interner.get(name).to_string(),
+                file: file.to_string(),
+                line: location.line,
+                column,
+            };
+            eprintln!("  {}", message.text(self.context.language));
+        }
+
+        if calls > MAX_TRACE_LEN {
+            let message = Message::MoreCalls(calls - MAX_TRACE_LEN);
+            eprintln!("  {}", message.text(self.context.language));
+        }
+    }
+
+    /// Reports `message` if there is one.
+    fn check<T>(&mut self, result: Result<T, Message>) -> Result<T, ()> {
+        result.map_err(|message| self.report_error(message))
+    }
+
+    /// Calls the handlers of the signals that arrived since the last check.
+    /// Fails with an error if Ctrl-C was pressed and there is no handler,
+    /// and without one if the deadline of `with_timeout` has passed.
+    fn check_interrupt(&mut self) -> Result<(), ()> {
+        while let Some(signal) = interrupt::take(self.interrupted) {
+            let handler = self.context.handlers.borrow().signal(signal);
+            match handler {
+                Some(func) => {
+                    self.call_function(&func, vec![])?;
+                }
+                None => {
+                    self.report_error(Message::Interrupted);
+                    return Err(());
+                }
+            }
+        }
+
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.timed_out = true;
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Stack underflow")
+    }
+
+    /// Pops the `n` values on top, in the order they were pushed.
+    fn pop_n(&mut self, n: u32) -> Vec<Value> {
+        let start = self.stack.len() - n as usize;
+        self.stack.split_off(start)
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        let last = self.frames.len() - 1;
+        &mut self.frames[last]
+    }
+
+    fn step(&mut self) -> Result<(), ()> {
+        let frame = self.frame();
+        let (func, ip, base) = (frame.func, frame.ip, frame.base);
+        frame.ip += 1;
+
+        let op = self.program.functions[func].code[ip];
+        self.context.profile(op.name());
+
+        match op {
+            Op::Constant(i) => {
+                self.stack.push(self.program.constants[i as usize].clone())
+            }
+            Op::Nil => self.stack.push(Value::Nil),
+            Op::Pop => {
+                self.pop();
+            }
+            Op::Load(Place::Local(slot)) => {
+                self.stack.push(self.slots[base + slot as usize].clone())
+            }
+            Op::Load(Place::Global(name)) => {
+                let value = self.global(name).clone();
+                self.stack.push(value);
+            }
+            Op::Store(place) => {
+                let value = self.pop();
+                *variable(&mut self.slots, &mut self.globals, place, base) =
+                    value;
+            }
+            Op::DefineGlobal(name) => {
+                let value = self.pop();
+                self.globals
+                    .entry(name)
+                    .or_insert_with(Vec::new)
+                    .push(value);
+            }
+            Op::DropGlobal(name) => {
+                self.globals.get_mut(&name).and_then(Vec::pop);
+            }
+            Op::Assign { place, indices, op } => {
+                let indices = self.pop_n(indices);
+                let value = self.pop();
+                self.assign(place, base, &indices, op, value)?;
+            }
+            Op::ChangeArray {
+                builtin,
+                place,
+                indices,
+                args,
+            } => {
+                let args = self.pop_n(args);
+                let indices = self.pop_n(indices);
+
+                let var =
+                    variable(&mut self.slots, &mut self.globals, place, base);
+                let limits = &self.context.limits;
+                let interner = &self.context.interner;
+                let result = element_mut(var, &indices, false, interner)
+                    .and_then(|place| {
+                        builtin.change(place, args, limits, interner)
+                    });
+                let result = self.check(result)?;
+                self.stack.push(result);
+            }
+            Op::Binary(op) => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                let result = self.binary(op, lhs, rhs)?;
+                self.stack.push(result);
+            }
+            Op::Not => {
+                let value = self.pop();
+                let truthy = value.is_truthy(&self.context.interner);
+                self.stack.push(Value::Bool(!truthy));
+            }
+            Op::Neg => {
+                let value = self.pop();
+                let result = self.check(negate(value))?;
+                self.stack.push(result);
+            }
+            Op::Truthy => {
+                let value = self.pop();
+                let truthy = value.is_truthy(&self.context.interner);
+                self.stack.push(Value::Bool(truthy));
+            }
+            Op::Jump(to) => self.frame().ip = to as usize,
+            Op::JumpIfFalse(to) => {
+                let value = self.pop();
+                if !value.is_truthy(&self.context.interner) {
+                    self.frame().ip = to as usize;
+                }
+            }
+            Op::Loop(to) => {
+                self.check_interrupt()?;
+                self.frame().ip = to as usize;
+            }
+            Op::Call { func, args } => self.call(func as usize, args)?,
+            Op::CallNative { name, args } => {
+                let args = self.pop_n(args);
+                let value = self.call_native(name, args)?;
+                self.stack.push(value);
+            }
+            Op::Builtin { builtin, args } => {
+                let args = self.pop_n(args);
+                let result = if builtin.calls_functions() {
+                    builtin.run(&args, self)?
+                } else {
+                    let result = builtin.call(
+                        &args,
+                        &self.context.limits,
+                        &mut self.context.interner,
+                    );
+                    self.check(result)?
+                };
+                self.stack.push(result);
+            }
+            Op::Return => {
+                let value = self.pop();
+                self.return_value(value);
+            }
+            Op::MakeOk => {
+                let value = self.pop();
+                self.stack.push(Value::Ok(Box::new(value)));
+            }
+            Op::MakeErr => {
+                let value = self.pop();
+                self.stack.push(Value::Err(Box::new(value)));
+            }
+            Op::Try => match self.pop() {
+                Value::Ok(value) => self.stack.push(*value),
+                err @ Value::Err(_) => self.return_value(err),
+                other => {
+                    self.report_error(Message::TryOnNonResult(kind_of(&other)));
+                    return Err(());
+                }
+            },
+            Op::Array(n) => {
+                let elements = self.pop_n(n);
+                self.stack.push(Value::Array(Rc::new(elements)));
+            }
+            Op::Concat(n) => {
+                let parts = self.pop_n(n);
+                let string = concat(&parts, &mut self.context.interner);
+                self.stack.push(string);
+            }
+            Op::Map(n) => {
+                let entries = self.pop_n(n * 2);
+                let mut map = Map::new();
+                let mut entries = entries.into_iter();
+                while let (Some(key), Some(value)) =
+                    (entries.next(), entries.next())
+                {
+                    let key = self.check(map_key(&key))?;
+                    map.insert(key, value);
+                }
+                self.stack.push(Value::Map(Rc::new(map)));
+            }
+            Op::Index => {
+                let index = self.pop();
+                let value = self.pop();
+                let element =
+                    lookup(&value, &index, &mut self.context.interner);
+                let element = self.check(element)?;
+                self.stack.push(element);
+            }
+            Op::Print { args, newline } => {
+                let args = self.pop_n(args);
+                let output =
+                    format_output(args, newline, &self.context.interner);
+                let output = self.check(output)?;
+                self.write(&output);
+            }
+            Op::Input { message } => {
+                if message {
+                    let message = self.pop();
+                    let message = message.format(&self.context.interner);
+                    self.write(&message);
+                }
+
+                let mut line = String::new();
+                while let Err(error) = self.input.read_line(&mut line) {
+                    // A signal handled by the script lets it go back to
+                    // waiting for the rest of the line.
+                    if !interrupt::pending() {
+                        let message = Message::CannotReadFile {
+                            path: "<stdin>".to_string(),
+                            error: error.to_string(),
+                        };
+                        self.report_error(message);
+                        return Err(());
+                    }
+                    self.check_interrupt()?;
+                }
+
+                let line = line.trim_end_matches(&['\r', '\n'][..]);
+                let line = self.context.interner.intern(line);
+                self.stack.push(Value::String(line));
+            }
+            Op::IntoIter => {
+                let items = match self.pop() {
+                    array @ Value::Array(_) => array,
+                    Value::Map(map) => Value::Array(Rc::new(
+                        map.iter().map(|(key, _)| key.to_value()).collect(),
+                    )),
+                    iter @ (Value::Iter(_) | Value::Deque(_)) => {
+                        Value::Array(Rc::new(builtins::items(&iter, self)?))
+                    }
+                    other => {
+                        self.report_error(Message::CannotIterate(kind_of(
+                            &other,
+                        )));
+                        return Err(());
+                    }
+                };
+                self.stack.push(items);
+            }
+            Op::Unpack(n) => {
+                let value = self.pop();
+                let elements = self.check(unpack(value, n as usize))?;
+                self.stack.extend(elements);
+            }
+            Op::ForNext { items, index, exit } => {
+                let items = base + items as usize;
+                let index = base + index as usize;
+
+                let i = match self.slots[index] {
+                    Value::Int(i) => i,
+                    _ => unreachable!("Loop position is an int"),
+                };
+
+                let item = match &self.slots[items] {
+                    Value::Int(end) if i < *end => Some(Value::Int(i)),
+                    Value::Array(array) if (i as usize) < array.len() => {
+                        Some(array[i as usize].clone())
+                    }
+                    _ => None,
+                };
+
+                match item {
+                    Some(item) => {
+                        self.slots[index] = Value::Int(i + 1);
+                        self.stack.push(item);
+                    }
+                    None => self.frame().ip = exit as usize,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn global(&self, name: Symbol) -> &Value {
+        self.globals
+            .get(&name)
+            .and_then(|values| values.last())
+            .expect("Undefined variables rejected by typechecker")
+    }
+
+    fn assign(
+        &mut self,
+        place: Place,
+        base: usize,
+        indices: &[Value],
+        op: Option<BinaryOpKind>,
+        value: Value,
+    ) -> Result<(), ()> {
+        let value = match op {
+            Some(op) => {
+                let var =
+                    variable(&mut self.slots, &mut self.globals, place, base);
+                let current =
+                    element_mut(var, indices, false, &self.context.interner)
+                        .map(|current| current.clone());
+                let current = self.check(current)?;
+                let result =
+                    arithmetic(op, current, value, &mut self.context.interner);
+                self.check(result)?
+            }
+            None => value,
+        };
+
+        let var = variable(&mut self.slots, &mut self.globals, place, base);
+        let result = element_mut(var, indices, true, &self.context.interner)
+            .map(|element| *element = value);
+        self.check(result)
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOpKind,
+        lhs: Value,
+        rhs: Value,
+    ) -> Result<Value, ()> {
+        use BinaryOpKind::*;
+
+        // Ints are by far the most common operands, so they skip the
+        // checks below when nothing can go wrong.
+        if let (Value::Int(a), Value::Int(b)) = (&lhs, &rhs) {
+            let (a, b) = (*a, *b);
+            match op {
+                Add => return Ok(Value::Int(a + b)),
+                Sub => return Ok(Value::Int(a - b)),
+                Mul => return Ok(Value::Int(a * b)),
+                Equal => return Ok(Value::Bool(a == b)),
+                NotEqual => return Ok(Value::Bool(a != b)),
+                Greater => return Ok(Value::Bool(a > b)),
+                GreaterEqual => return Ok(Value::Bool(a >= b)),
+                Lesser => return Ok(Value::Bool(a < b)),
+                LesserEqual => return Ok(Value::Bool(a <= b)),
+                _ => {}
+            }
+        }
+
+        let result = match op {
+            Equal => Ok(Value::Bool(lhs == rhs)),
+            NotEqual => Ok(Value::Bool(lhs != rhs)),
+            Greater | GreaterEqual | Lesser | LesserEqual => {
+                order(op, &lhs, &rhs, &self.context.interner).map(Value::Bool)
+            }
+            _ => arithmetic(op, lhs, rhs, &mut self.context.interner),
+        };
+
+        self.check(result)
+    }
+
+    fn call(&mut self, func: usize, args: u32) -> Result<(), ()> {
+        if self.frames.len() - 1 == MAX_CALL_DEPTH {
+            self.report_error(Message::TooManyCalls);
+            return Err(());
+        }
+        self.check_interrupt()?;
+
+        let base = self.slots.len();
+        let start = self.stack.len() - args as usize;
+        self.slots.extend(self.stack.drain(start..));
+
+        let slots = self.program.functions[func].slots as usize;
+        self.slots.resize(base + slots, Value::Nil);
+
+        self.frames.push(Frame {
+            func,
+            ip: 0,
+            base,
+            stack: self.stack.len(),
+        });
+
+        Ok(())
+    }
+
+    fn call_native(
+        &mut self,
+        name: Symbol,
+        args: Vec<Value>,
+    ) -> Result<Value, ()> {
+        let func = match self.context.native_functions.get(&name) {
+            Some(func) => Rc::clone(func),
+            None => unreachable!("Undefined functions rejected by typechecker"),
+        };
+
+        let checked = builtins::check_arguments(
+            name,
+            &func,
+            &args,
+            &self.context.interner,
+        );
+        self.check(checked)?;
+        let checked = builtins::check_capability(
+            name,
+            &func,
+            &args,
+            self.context,
+            self.input,
+        );
+        self.check(checked)?;
+        Ok((func.func)(&args, &mut self.context.interner))
+    }
+
+    /// Leaves the running function, passing `value` to the caller.
+    fn return_value(&mut self, value: Value) {
+        let frame = self.frames.pop().expect("Return without a frame");
+        self.stack.truncate(frame.stack);
+        self.slots.truncate(frame.base);
+
+        if !self.frames.is_empty() {
+            self.stack.push(value);
+        }
+    }
+
+    fn write(&mut self, output: &str) {
+        self.output
+            .write_all(output.as_bytes())
+            .and_then(|_| self.output.flush())
+            .expect("Failed to write to stdout");
+    }
+}
+
+impl Caller for Vm<'_> {
+    /// Runs the function until it returns, while the instruction that
+    /// called it waits.
+    fn call_declared(
+        &mut self,
+        func: &Function,
+        args: Vec<Value>,
+    ) -> Result<Value, ()> {
+        grow_stack(|| match func.callee {
+            Callee::Compiled(index) => {
+                let n = args.len() as u32;
+                self.stack.extend(args);
+                self.call(index as usize, n)?;
+
+                let depth = self.frames.len();
+                while self.frames.len() >= depth {
+                    self.step()?;
+                }
+                Ok(self.pop())
+            }
+            Callee::Native => self.call_native(func.name, args),
+            Callee::Node(_) => unreachable!("The VM only runs bytecode"),
+            Callee::Partial(_) | Callee::Compose(_) => {
+                unreachable!("Unwrapped by call_function")
+            }
+        })
+    }
+
+    fn call_until(
+        &mut self,
+        func: &Function,
+        args: Vec<Value>,
+        deadline: Instant,
+    ) -> Result<Option<Value>, ()> {
+        let heights = (self.frames.len(), self.stack.len(), self.slots.len());
+        let outer = self.deadline;
+        self.deadline = Some(outer.map_or(deadline, |d| d.min(deadline)));
+        let result = self.call_function(func, args);
+        self.deadline = outer;
+
+        // Stopping for an outer deadline stops the outer call as well.
+        let outer_passed = outer.is_some_and(|d| Instant::now() >= d);
+        match result {
+            Err(()) if self.timed_out && !outer_passed => {
+                self.timed_out = false;
+                // Drops what the stopped functions left behind.
+                let (frames, stack, slots) = heights;
+                self.frames.truncate(frames);
+                self.stack.truncate(stack);
+                self.slots.truncate(slots);
+                Ok(None)
+            }
+            result => result.map(Some),
+        }
+    }
+
+    fn poll_interrupt(&mut self) -> Result<(), ()> {
+        self.check_interrupt()
+    }
+
+    fn interner(&mut self) -> &mut StringInterner {
+        &mut self.context.interner
+    }
+
+    fn declared_arity(&mut self, func: &Function) -> usize {
+        match func.callee {
+            Callee::Compiled(index) => {
+                self.program.functions[index as usize].params as usize
+            }
+            _ => unreachable!("Not a function of the script: {:?}", func),
+        }
+    }
+
+    fn context(&mut self) -> &mut Context {
+        self.context
+    }
+
+    fn evaluate(
+        &mut self,
+        ast: &Ast,
+        statements: &StmtList,
+        scope: &mut Scope,
+    ) -> Result<Option<Value>, ()> {
+        let (input, output) = (&mut *self.input, &mut *self.output);
+        interpreter::evaluate_in(
+            ast,
+            statements,
+            scope,
+            self.context,
+            input,
+            output,
+            &mut |_, _| {},
+        )
+    }
+
+    fn report(&mut self, message: Message) {
+        self.report_error(message);
+    }
+}
+
+/// The variable at `place`, for the function whose slots start at `base`.
+fn variable<'v>(
+    slots: &'v mut [Value],
+    globals: &'v mut HashMap<Symbol, Vec<Value>>,
+    place: Place,
+    base: usize,
+) -> &'v mut Value {
+    match place {
+        Place::Local(slot) => &mut slots[base + slot as usize],
+        Place::Global(name) => globals
+            .get_mut(&name)
+            .and_then(|values| values.last_mut())
+            .expect("Undefined variables rejected by typechecker"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::builtins;
+    use crate::common::Grants;
+    use crate::compiler;
+    use crate::lexer;
+    use crate::parser;
+    use crate::semantic;
+    use crate::typecheck;
+
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::Ordering;
+
+    fn try_run(source: &str, input: &str) -> Result<String, ()> {
+        try_run_with(Context::new(), source, input)
+    }
+
+    fn try_run_with(
+        mut context: Context,
+        source: &str,
+        input: &str,
+    ) -> Result<String, ()> {
+        builtins::register(&mut context);
+        let file = context.interner.intern("test.bl");
+        context
+            .source_code
+            .insert(PathBuf::from("test.bl"), source.to_string());
+
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .expect("Lexing failed");
+        let ast =
+            parser::parse_ast(tokens, &mut context).expect("Parsing failed");
+        typecheck::typecheck(&ast, &mut context).expect("Typechecking failed");
+        let program = compiler::compile(&ast, &mut context);
+
+        let mut input = input.as_bytes();
+        let mut output = Vec::new();
+        Vm::new(&program, &mut context, &mut input, &mut output).run()?;
+
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn output(source: &str) -> String {
+        try_run(source, "").expect("Execution failed")
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(output("print(\"%\", 1 + 2 * 3)"), "7");
+        assert_eq!(output("print(\"%\", 7 / 2 + 1.5)"), "4.5");
+        assert_eq!(output("print(\"% %\", -7 % 3, -(2.5))"), "-1 -2.5");
+        assert_eq!(output("print(\"%\", \"a\" + \"b\" == \"ab\")"), "true");
+        assert_eq!(output("print(\"% %\", 2 <= 2.0, !(1 > 2))"), "true true");
+        let source = "xs := [2] t := true \
+                      print(\"% % %\", -xs[0] * 3, - -1, !t == false)";
+        assert_eq!(output(source), "-6 1 true");
+
+        // The right hand side would fail with a division by zero.
+        let source =
+            "z := 0 print(\"% %\", false && 1 / z > 0, true || 1 / z > 0)";
+        assert_eq!(output(source), "false true");
+    }
+
+    #[test]
+    fn divmod_floors() {
+        let source = "for d in [divmod(-7, 2), divmod(7, -2), divmod(6, 3)] { \
+                      print(\"%,% \", d[0], d[1]) }";
+        assert_eq!(output(source), "-4,1 -4,-1 2,0 ");
+    }
+
+    #[test]
+    fn variables_and_scopes() {
+        let source = "a := 1 a = 5 a += 2 a *= 3 \
+                      { a := 2 a = 3 } \
+                      if a > 20 { b := 1 a -= b } \
+                      if a > 20 { a = 0 } else { print(\"%\", a) }";
+        assert_eq!(output(source), "20");
+    }
+
+    #[test]
+    fn loops() {
+        let source = "n := 0 for i in 0..5 { n += i } \
+                      m := 1 while m < 100 { m *= 2 } \
+                      print(\"% %\", n, m)";
+        assert_eq!(output(source), "10 128");
+
+        let source = "xs := [1, 2, 3] \
+                      for x in xs { push(xs, x * 10) print(\"%\", x) } \
+                      for k in {\"b\": 1, \"a\": 2} { print(\" %\", k) }";
+        assert_eq!(output(source), "123 b a");
+
+        let source = "n := 0 for i in 0..5 { for j in 0..5 { \
+                      if j > i { break } if j == 1 { continue } n += 1 } } \
+                      x := 0 while true { x := 1 break } \
+                      fn f() -> int { n := 0 while true { n += 1 \
+                      if n % 2 == 1 { continue } if n > 5 { break } } \
+                      return n } \
+                      print(\"% % %\", n, x, f())";
+        assert_eq!(output(source), "11 0 6");
+    }
+
+    #[test]
+    fn functions() {
+        let source = "print(\"%\", fib(15)) \
+                      fn fib(n: int) -> int { \
+                          if n < 2 { return n } \
+                          return fib(n - 1) + fib(n - 2) \
+                      }";
+        assert_eq!(output(source), "610");
+
+        let source = "n := 1 \
+                      fn set(value: int) { n := value n = 5 } \
+                      fn bump() { n += 1 } \
+                      set(3) bump() bump() print(\"%\", n)";
+        assert_eq!(output(source), "3");
+
+        let source = "fn outer() -> int { \
+                          fn inner(a: int) -> int { return a * 2 } \
+                          x := 4 \
+                          for i in 0..2 { y := i x += inner(y) } \
+                          return x \
+                      } \
+                      print(\"%\", outer())";
+        assert_eq!(output(source), "6");
+    }
+
+    #[test]
+    fn results() {
+        let source = "fn half(n: int) -> result { \
+                          if n % 2 != 0 { return Err(n) } \
+                          return Ok(n / 2) \
+                      } \
+                      fn quarter(n: int) -> result { return Ok(half(half(n)?)?) } \
+                      print(\"% %\", quarter(12), quarter(6))";
+        assert_eq!(output(source), "Ok(3) Err(3)");
+    }
+
+    #[test]
+    fn arrays_and_maps() {
+        let source = "grid := [[1, 2], [3]] push(grid[1], 4) grid[0][1] = 0 \
+                      ys := grid ys[0] = [] \
+                      fn change(zs: [int]) { push(zs, 3) } change(grid[1]) \
+                      print(\"% % %\", grid, ys, pop(grid[1]))";
+        assert_eq!(output(source), "[[1, 0], [3, 4]] [[], [3, 4]] 4");
+
+        let source = "m := {2: [1]} push(m[2], 5) m[1] = [] m[2][0] += 1 \
+                      print(\"% % % %\", m, keys(m), values(m), contains(m, 3))";
+        assert_eq!(
+            output(source),
+            "{2: [2, 5], 1: []} [2, 1] [[2, 5], []] false"
+        );
+    }
+
+    #[test]
+    fn negative_and_string_indices() {
+        let source =
+            "fn last(xs: [int]) -> result { return Ok(get(xs, -1)?) } \
+                      grid := [[1, 2], [3]] grid[-2][-1] = 0 s := \"héj\" \
+                      print(\"% % % % %\", grid, s[0] + s[-1], last([]), \
+                            last([4]), get({1: 2}, 1))";
+        assert_eq!(output(source), "[[1, 0], [3]] hj Err(-1) Ok(4) Ok(2)");
+        assert!(try_run("s := \"ab\" print(s[-3])", "").is_err());
+    }
+
+    #[test]
+    fn growing_arrays() {
+        let source = "xs: [int] = array_with_capacity(8) reserve(xs, 100) \
+                      push(xs, 1) resize(xs, 3, 0) grid := [[1, 2]] \
+                      resize(grid[0], 1, 0) print(\"% %\", xs, grid)";
+        assert_eq!(output(source), "[1, 0, 0] [[1]]");
+        assert!(try_run("xs := [1] resize(xs, -1, 0)", "").is_err());
+        assert!(try_run("xs := array_with_capacity(-1)", "").is_err());
+        let huge = "xs := array_with_capacity(2147483647)";
+        assert!(try_run(huge, "").is_err());
+        assert!(try_run("xs := [1] resize(xs, 2147483647, 0)", "").is_err());
+    }
+
+    #[test]
+    fn unknown_types_fail_at_runtime() {
+        let sources = [
+            "fn f() -> result { return Ok(len(Ok(3)?)) } print(\"%\", f())",
+            "fn f() -> result { return Ok(trim(Ok(3)?)) } print(\"%\", f())",
+            "fn f() -> result { return Ok(min(Ok(\"a\")?, 1)) } print(\"%\", f())",
+            "fn f() -> result { return Ok(sum(Ok(3)?)) } print(\"%\", f())",
+            "fn f() -> result { return Ok(count(Ok(3)?)) } print(\"%\", f())",
+        ];
+        for source in &sources {
+            assert!(try_run(source, "").is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn input_and_builtins() {
+        let source = "name := input(\"Name: \") \
+                      println(\"% % %\", to_upper(name), abs(-2), max(1, 2.5))";
+        assert_eq!(try_run(source, "Ann\n").unwrap(), "Name: ANN 2 2.5\n");
+    }
+
+    #[test]
+    fn iterators() {
+        let source = "fn square(n: int) -> int { return n * n } \
+                      fn odd(n: int) -> bool { return n % 2 != 0 } \
+                      fn join(a: string, b: string) -> string { \
+                          return to_upper(a) + b \
+                      } \
+                      xs := filter(map(range(6), square), odd) \
+                      print(\"% % % \", sum(xs), count(xs), collect(xs)) \
+                      print(\"% \", fold([\"b\", \"c\"], \"a\", join)) \
+                      for x in take(xs, 2) { print(\"%\", x) }";
+        assert_eq!(output(source), "35 3 [1, 9, 25] ABc 19");
+
+        let source =
+            "fn sum(n: int) -> int { return n + 1 } print(\"%\", sum(1))";
+        assert_eq!(output(source), "2");
+
+        let source = "fn inverse(n: int) -> int { return 1 / n } \
+                      n := sum(map(range(2), inverse))";
+        assert!(try_run(source, "").is_err());
+    }
+
+    #[test]
+    fn sorting() {
+        let source = "fn size(p: [string]) -> int { return len(p[1]) } \
+                      people := [[\"a\", \"xx\"], [\"b\", \"x\"], \
+                                 [\"c\", \"xx\"], [\"d\", \"x\"]] \
+                      print(\"% % %\", sort_by_key(people, size), \
+                            [2.5, 1.0].sort(), range(3).reverse())";
+        assert_eq!(
+            output(source),
+            "[[b, x], [d, x], [a, xx], [c, xx]] [1.0, 2.5] [2, 1, 0]"
+        );
+    }
+
+    #[test]
+    fn binary_search_and_insort() {
+        let source = "xs := [[1, 3]] insort(xs[0], 2) insort(xs[0], 0) \
+                      print(\"% % %\", xs, binary_search(xs[0], 2), \
+                            xs[0].binary_search(5))";
+        assert_eq!(output(source), "[[0, 1, 2, 3]] Ok(2) Err(4)");
+    }
+
+    #[test]
+    fn heaps_and_deques() {
+        let source = "fn smallest(h: heap[float]) -> float { return pop(h) } \
+                      h: heap[float] = heap() \
+                      for n in [2.5, 0.5, 9.0, 1.5] { push(h, n) } \
+                      d := deque() d.push(1) d.push_front(0) d.push(2) \
+                      print(\"% % % % %\", smallest(h), h, d.pop_front(), \
+                            d, collect(d))";
+        assert_eq!(
+            output(source),
+            "0.5 heap[0.5, 1.5, 2.5, 9.0] 0 deque[1, 2] [1, 2]"
+        );
+        assert!(try_run("h := heap() n := pop(h)", "").is_err());
+    }
+
+    #[test]
+    fn zip_enumerate_and_group_by() {
+        let source = "fn total(xs: [int], ys: [float]) -> float { \
+                          t := 0.0 \
+                          for i, pair in enumerate(zip(xs, ys)) { \
+                              t += pair[0] * pair[1] + i \
+                          } \
+                          return t \
+                      } \
+                      fn odd(n: int) -> bool { return n % 2 == 1 } \
+                      print(\"% %\", total([1, 2, 3], [0.5, 1.5]), \
+                            range(5).group_by(odd))";
+        assert_eq!(output(source), "4.5 {false: [0, 2, 4], true: [1, 3]}");
+        assert!(try_run("for a, b in [[1]] {}", "").is_err());
+    }
+
+    #[test]
+    fn lang_namespace() {
+        let source = "fn supports(feature: string) -> bool { \
+                          return contains(lang.features, feature) \
+                      } \
+                      print(\"% % % %\", lang.version, lang.minor, \
+                            supports(\"heaps\"), supports(\"teleport\"))";
+        assert_eq!(
+            output(source),
+            format!(
+                "{} {} true false",
+                env!("CARGO_PKG_VERSION"),
+                env!("CARGO_PKG_VERSION_MINOR")
+            )
+        );
+        assert_eq!(output("print(\"%\", lang.os)"), std::env::consts::OS);
+    }
+
+    #[test]
+    fn line_breaks_end_statements() {
+        let source = "a := 5\n-1\n\
+                      b := a +\n2\n\
+                      xs := [1,\n2]\n\
+                      c := xs\n[0]\n\
+                      print(\"% % % %\", a, b, len(c),\n\
+                            range(3)\n.sum())";
+        assert_eq!(output(source), "5 7 2 3");
+
+        let source = "m := {\n\
+                          \"a\": 1\n+ 2,\n\
+                          \"b\": len([1]\n)\n\
+                      }\n\
+                      print(\"% %\", m[\"a\"]\n, m[\"b\"])";
+        assert_eq!(output(source), "3 1");
+    }
+
+    #[test]
+    fn else_if_chain() {
+        let source = "fn size(n: int) -> string { \
+                          if n < 10 { return \"small\" } \
+                          else if n < 100 { return \"medium\" } \
+                          else { return \"large\" } \
+                      } \
+                      if true { print(\"a \") } else { print(\"b \") } \
+                      print(\"% % %\", size(1), size(50), size(500))";
+        assert_eq!(output(source), "a small medium large");
+    }
+
+    #[test]
+    fn match_statement() {
+        let source = "fn name(n: int) -> string { \
+                          match n { \
+                              0 => return \"zero\" \
+                              1, 2 => { return \"few\" } \
+                              _ => return \"many\" \
+                          } \
+                      } \
+                      for i in 0..4 { print(\"% \", name(i)) } \
+                      match \"b\" { \"a\" => print(\"a\") } \
+                      match lang::os { lang.os => print(\"os\") }";
+        assert_eq!(output(source), "zero few few many os");
+    }
+
+    #[test]
+    fn cfg_attributes() {
+        let source = "@cfg(os = \"teleos\") \
+                      fn name() -> string { return \"tele\" } \
+                      @cfg(not(os = \"teleos\")) \
+                      fn name() -> string { return lost() } \
+                      @cfg(feature = \"tuples\") @cfg(family = \"none\") \
+                      x := lost() \
+                      print(\"%\", name())";
+        let mut context = Context::new();
+        context.cfg.push(("os".to_string(), "teleos".to_string()));
+        assert_eq!(try_run_with(context, source, ""), Ok("tele".to_string()));
+    }
+
+    #[test]
+    fn aggregates() {
+        let source = "fn neg(n: int) -> int { return -n } \
+                      fn even(n: int) -> bool { return n % 2 == 0 } \
+                      xs := [3, 1, 4, 1, 5] \
+                      print(\"% % % % %\", sum(xs), mean(xs), count_if(xs, even), \
+                            min_by(xs, neg), range(4).max_by(neg))";
+        assert_eq!(output(source), "14 2.8 1 5 0");
+        assert!(try_run("xs: [int] = [] n := mean(xs)", "").is_err());
+        let source = "xs := [1.5, 3.5, 2.5] \
+                      print(\"% % %\", median(xs), percentile(xs, 0), \
+                            histogram(range(4), 2))";
+        assert_eq!(output(source), "2.5 1.5 [[0.0, 2], [1.5, 2]]");
+        assert!(try_run("xs: [int] = [] n := median(xs)", "").is_err());
+        assert!(try_run(
+            "fn f(n: int) -> int { return n } x := min_by(range(0), f)",
+            ""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn arities() {
+        let source = "fn add(a: int, b: int) -> int { return a + b } \
+                      fn neg(n: int) -> int { return -n } \
+                      print(\"% % % %\", arity(add), arity(partial(add, 1)), \
+                            arity(compose(add, neg)), arity(split))";
+        assert_eq!(output(source), "2 1 2 2");
+    }
+
+    #[test]
+    fn partial_and_compose() {
+        let source = "fn add(a: int, b: int) -> int { return a + b } \
+                      fn double(n: int) -> int { return n * 2 } \
+                      inc := partial(add, 1) \
+                      f := compose(inc, double) \
+                      g := compose(f, partial(add, 10)) \
+                      print(\"% % \", collect(map([1, 2], f)), sum(map([0], g))) \
+                      print(\"%\", g)";
+        assert_eq!(
+            output(source),
+            "[4, 6] 12 fn compose(compose(partial(add, 1), double), partial(add, 10))"
+        );
+    }
+
+    #[test]
+    fn retry_and_timeout() {
+        let source = "n := 0 \
+                      fn flaky() -> result { n += 1 \
+                          if n < 3 { return Err(n) } return Ok(n) } \
+                      fn spin() -> int { while true {} return 0 } \
+                      fn slow() -> result { return with_timeout(spin, 1) } \
+                      print(\"% % \", retry(flaky, 2, 0), retry(flaky, 5, 1)) \
+                      print(\"% %\", with_timeout(slow, 1000), \
+                            with_timeout(flaky, 1000))";
+        assert_eq!(
+            output(source),
+            "Err(2) Ok(3) Ok(Err(Timed out after 1 ms)) Ok(Ok(4))"
+        );
+
+        let source = "fn spin() { while true {} } \
+                      fn nested() -> result { return with_timeout(spin, 1000) } \
+                      print(\"%\", with_timeout(nested, 1))";
+        assert_eq!(output(source), "Err(Timed out after 1 ms)");
+
+        // Waiting between tries is cut short by the deadline, and long
+        // backoffs don't overflow.
+        let source = "fn fail() -> result { return Err(0) } \
+                      fn patient() -> result { \
+                          return retry(fail, 40, 2000000000) } \
+                      print(\"%\", with_timeout(patient, 20))";
+        assert_eq!(output(source), "Err(Timed out after 20 ms)");
+    }
+
+    #[test]
+    fn method_calls() {
+        let source = "fn square(n: int) -> int { return n * n } \
+                      fn even(n: int) -> bool { return n % 2 == 0 } \
+                      xs := [\" a\"] \
+                      xs.push(\"b \") \
+                      print(\"% % \", range(10).map(square).filter(even).sum(), \
+                            xs.map(trim).collect()) \
+                      print(\"%\", -xs[0].trim().len() * 2)";
+        assert_eq!(output(source), "120 [a, b] -2");
+    }
+
+    #[test]
+    fn string_interpolation() {
+        let source = "name := \"ann\" xs := [1, 2] \
+                      print(\"Hi ${name}, ${len(xs)} items: ${xs} \\${x}\") \
+                      print(\"\" + 1 + \" \" + 2.5 + \" \" + true + \"${\"!\"}\")";
+        assert_eq!(output(source), "Hi ann, 2 items: [1, 2] ${x}1 2.5 true!");
+    }
+
+    #[test]
+    fn host_constants() {
+        let mut context = Context::new();
+        let limit = context.interner.intern("LIMIT");
+        let red = context.interner.intern("Color.Red");
+        let color = context.interner.intern("Color");
+        context.constants.insert(limit, Value::Int(3));
+        context.constants.insert(red, Value::Int(7));
+        context.namespaces.insert(color);
+
+        let source = "fn f() -> int { return LIMIT + Color.Red } \
+                      print(\"% \", f()) \
+                      { LIMIT := 1 print(\"%\", LIMIT) }";
+        assert_eq!(try_run_with(context, source, ""), Ok("10 1".to_string()));
+    }
+
+    #[test]
+    fn capabilities() {
+        let source = "a := read_file(\"missing.txt\") \
+                      b := read_file(\"missing.txt\") print(\"done\")";
+        let restricted = |prompt| {
+            let mut context = Context::new();
+            context.grants = Grants::Only(vec![]);
+            context.prompt = prompt;
+            context
+        };
+
+        assert!(try_run_with(restricted(false), source, "y\n").is_err());
+        assert!(try_run_with(restricted(true), source, "n\n").is_err());
+        assert_eq!(
+            try_run_with(restricted(true), source, "y\n"),
+            Ok("done".into())
+        );
+
+        let mut context = restricted(false);
+        context.grants.grant("fs");
+        assert!(try_run_with(context, source, "").is_ok());
+    }
+
+    #[test]
+    fn eval_sandbox() {
+        let source = "print(\"% \", eval(\"x * 2\", {\"x\": 21})) \
+                      print(\"% \", eval(\"print(\\\"hi \\\") [1, 2]\")) \
+                      print(\"% \", eval(\"y\")) \
+                      fn f() -> int { return 1 } \
+                      print(\"%\", eval(\"f()\"))";
+        assert_eq!(
+            output(source),
+            "Ok(42) hi Ok([1, 2]) Err(1:1: Variable 'y' is undefined) \
+             Err(1:1: Function 'f' is undefined)"
+        );
+
+        let mut context = Context::new();
+        context.grants = Grants::Only(vec![]);
+        let source = "print(\"%\", eval(\"read_file(\\\"x\\\")\"))";
+        let output = try_run_with(context, source, "").unwrap();
+        assert!(output.contains("needs the capability 'fs'"));
+    }
+
+    /// Handlers registered by eval'd code would outlive its functions.
+    #[test]
+    fn eval_keeps_no_handlers() {
+        let source = "print(\"%\", eval(\"fn f() {} on_exit(f)\")) \
+                      print(\" %\", eval(\"fn g() {} on_signal(\\\"HUP\\\", g)\"))";
+        assert_eq!(
+            output(source),
+            "Err(eval can't register handlers with on_exit or on_signal) \
+             Err(eval can't register handlers with on_exit or on_signal)"
+        );
+    }
+
+    /// Runs every phase on `source`, stopping at the first that fails.
+    fn try_all(source: &[u8]) -> Result<(), ()> {
+        let mut context = Context::new();
+        builtins::register(&mut context);
+        let file = context.interner.intern("fuzz.bl");
+        context.source_code.insert(
+            PathBuf::from("fuzz.bl"),
+            String::from_utf8_lossy(source).into_owned(),
+        );
+
+        let tokens = lexer::generate_tokens(source, file, &mut context)?;
+        let ast = parser::parse_ast(tokens, &mut context)?;
+        semantic::analyze(&ast, &mut context)?;
+        typecheck::typecheck(&ast, &mut context)?;
+        let program = compiler::compile(&ast, &mut context);
+
+        let mut output = Vec::new();
+        Vm::new(&program, &mut context, &mut &b""[..], &mut output).run()
+    }
+
+    /// Inputs in `test/fuzz` that once panicked. Each has to be reported as
+    /// an error or run, never panic.
+    #[test]
+    fn fuzz_corpus() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/fuzz");
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+
+        assert!(!paths.is_empty());
+        for path in paths {
+            let source = fs::read(&path).unwrap();
+            assert!(try_all(&source).is_err(), "{} ran", path.display());
+        }
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert!(try_run("xs := [1] a := xs[1]", "").is_err());
+        assert!(try_run("xs := [1] xs[2] = 3", "").is_err());
+        assert!(try_run("xs: [int] = [] pop(xs)", "").is_err());
+        assert!(try_run("m := {1: 2} m[3] += 1", "").is_err());
+        assert!(try_run("a := 0 b := 1 / a", "").is_err());
+        assert!(try_run("a := 0 b := 1 % a", "").is_err());
+        assert!(try_run("a := 0 b := divmod(1, a)", "").is_err());
+        assert!(try_run("print(\"% %\", 1)", "").is_err());
+        assert!(try_run("fn f(n: int) -> int { return f(n + 1) } f(0)", "")
+            .is_err());
+    }
+
+    #[test]
+    fn exit_handlers() {
+        let source = "n := 1 fn bye() { print(\"bye %\", n) } \
+                      on_exit(bye) n = 2 print(\"% \", n)";
+        assert_eq!(output(source), "2 bye 2");
+
+        let source = "fn bye() { print(\"bye\") } \
+                      z := 0 on_exit(bye) print(\"%\", 1 / z)";
+        assert!(try_run(source, "").is_err());
+    }
+
+    #[test]
+    fn interrupt_stops_execution() {
+        let mut context = Context::new();
+        let source = "n := 0 while true { n += 1 }";
+        let file = context.interner.intern("test.bl");
+        context
+            .source_code
+            .insert(PathBuf::from("test.bl"), source.to_string());
+
+        let tokens =
+            lexer::generate_tokens(source.as_bytes(), file, &mut context)
+                .unwrap();
+        let ast = parser::parse_ast(tokens, &mut context).unwrap();
+        let program = compiler::compile(&ast, &mut context);
+
+        let interrupted = AtomicBool::new(true);
+        let mut input = "".as_bytes();
+        let mut output = Vec::new();
+        let mut vm = Vm::new(&program, &mut context, &mut input, &mut output);
+        vm.interrupted = &interrupted;
+
+        assert!(vm.run().is_err());
+        assert!(!interrupted.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,418 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+
+use crate::random;
+use crate::url;
+
+/// Appended to the key of the handshake before hashing it, as RFC 6455
+/// says.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Messages larger than this are most likely not meant for a script, and
+/// would only use up memory.
+const MAX_MESSAGE: u64 = 64 * 1024 * 1024;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// A connection to a WebSocket server, over plain TCP. Nothing is
+/// encrypted, which is why `connect` refuses `wss://`.
+pub struct Socket {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    /// Set once a close frame has been sent, after which nothing else may
+    /// be.
+    closed: bool,
+}
+
+impl Socket {
+    /// Connects to a `ws://` URL and goes through the opening handshake.
+    /// `wss://` URLs are refused.
+    pub fn connect(text: &str) -> io::Result<Socket> {
+        let url = url::parse(text).map_err(invalid_input)?;
+        // There is no TLS, and sending in plain text what was meant to be
+        // encrypted would be worse than failing.
+        if url.scheme == "wss" {
+            let message = "Unsupported scheme 'wss', since connections \
+                           can't be encrypted; use ws:// through a proxy \
+                           that does TLS"
+                .to_string();
+            return Err(invalid_input(message));
+        }
+        if url.scheme != "ws" {
+            let message = format!("Unsupported scheme '{}'", url.scheme);
+            return Err(invalid_input(message));
+        }
+        if url.host.is_empty() {
+            return Err(invalid_input("Missing host".to_string()));
+        }
+
+        let host = url.host.trim_start_matches('[').trim_end_matches(']');
+        let writer = TcpStream::connect((host, url.port.unwrap_or(80)))?;
+        let mut socket = Socket {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            closed: false,
+        };
+
+        let mut target = if url.path.is_empty() {
+            "/".to_string()
+        } else {
+            url.path
+        };
+        if !url.query.is_empty() {
+            target = format!("{}?{}", target, url.query);
+        }
+        let host = match url.port {
+            Some(port) => format!("{}:{}", url.host, port),
+            None => url.host,
+        };
+
+        let mut nonce = [0; 16];
+        random::fill(&mut nonce)?;
+        let key = base64(&nonce);
+        write!(
+            socket.writer,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\nSec-WebSocket-Key: {}\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n",
+            target, host, key
+        )?;
+
+        socket.read_handshake(&key)?;
+        Ok(socket)
+    }
+
+    /// Reads the answer to the opening handshake, which has to accept
+    /// `key`.
+    fn read_handshake(&mut self, key: &str) -> io::Result<()> {
+        let mut status = String::new();
+        self.reader.read_line(&mut status)?;
+        if status.split_whitespace().nth(1) != Some("101") {
+            let message = format!("Handshake refused: {}", status.trim());
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                message,
+            ));
+        }
+
+        let mut accepted = false;
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("sec-websocket-accept") {
+                    accepted = value.trim() == accept(key);
+                }
+            }
+        }
+
+        if accepted {
+            Ok(())
+        } else {
+            Err(invalid_data("Handshake not accepted"))
+        }
+    }
+
+    /// Sends `text` as one text message.
+    pub fn send(&mut self, text: &str) -> io::Result<()> {
+        self.write_frame(TEXT, text.as_bytes())
+    }
+
+    /// Waits for the next message, answering pings while it does. Returns
+    /// None once the server has closed the connection.
+    pub fn recv(&mut self) -> io::Result<Option<String>> {
+        let mut message = Vec::new();
+        loop {
+            let (fin, opcode, payload) = self.read_frame()?;
+            match opcode {
+                CONTINUATION | TEXT | BINARY => {
+                    message.extend(payload);
+                    if message.len() as u64 > MAX_MESSAGE {
+                        return Err(invalid_data("Message too large"));
+                    }
+                    if fin {
+                        return String::from_utf8(message)
+                            .map(Some)
+                            .map_err(|_| invalid_data("Message not UTF-8"));
+                    }
+                }
+                CLOSE => {
+                    if !self.closed {
+                        // The close frame is echoed with its status code.
+                        let code = &payload[..payload.len().min(2)];
+                        self.write_frame(CLOSE, code)?;
+                    }
+                    let _ = self.writer.shutdown(Shutdown::Both);
+                    return Ok(None);
+                }
+                PING => self.write_frame(PONG, &payload)?,
+                PONG => {}
+                _ => return Err(invalid_data("Unknown frame")),
+            }
+        }
+    }
+
+    /// Tells the server the connection is done and closes it.
+    pub fn close(&mut self) -> io::Result<()> {
+        if !self.closed {
+            self.write_frame(CLOSE, &1000u16.to_be_bytes())?;
+        }
+        self.writer.shutdown(Shutdown::Both)
+    }
+
+    /// Sends a frame, masked with a random key as clients have to.
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        if self.closed {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        self.closed = opcode == CLOSE;
+
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= 0xffff => {
+                frame.push(0x80 | 126);
+                frame.extend(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend(&(len as u64).to_be_bytes());
+            }
+        }
+
+        let mut mask = [0; 4];
+        random::fill(&mut mask)?;
+        frame.extend(&mask);
+        frame.extend(
+            payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m),
+        );
+        self.writer.write_all(&frame)
+    }
+
+    /// Reads a frame, returning whether it ends a message, its opcode and
+    /// its payload.
+    fn read_frame(&mut self) -> io::Result<(bool, u8, Vec<u8>)> {
+        let mut head = [0; 2];
+        self.reader.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0f;
+        let masked = head[1] & 0x80 != 0;
+
+        let len = match head[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                self.reader.read_exact(&mut len)?;
+                u64::from(u16::from_be_bytes(len))
+            }
+            127 => {
+                let mut len = [0; 8];
+                self.reader.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => u64::from(len),
+        };
+        if len > MAX_MESSAGE {
+            return Err(invalid_data("Message too large"));
+        }
+
+        let mut mask = [0; 4];
+        if masked {
+            self.reader.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0; len as usize];
+        self.reader.read_exact(&mut payload)?;
+        if masked {
+            for (b, m) in payload.iter_mut().zip(mask.iter().cycle()) {
+                *b ^= m;
+            }
+        }
+
+        Ok((fin, opcode, payload))
+    }
+}
+
+/// What the server answers a handshake with `key` with.
+fn accept(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const CHARS: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | u32::from(*b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (n >> (18 - 6 * i)) & 0x3f;
+                encoded.push(CHARS[index as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// SHA-1, which the handshake uses. It is not used for anything that has
+/// to be secure.
+fn sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] =
+        [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut padded = bytes.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend(&(bytes.len() as u64 * 8).to_be_bytes());
+
+    for block in padded.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (word, value) in state.iter_mut().zip(&[a, b, c, d, e]) {
+            *word = word.wrapping_add(*value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(&state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn handshake_keys() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        // The example from RFC 6455.
+        assert_eq!(
+            accept("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn echo() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut socket = Socket {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+                closed: false,
+            };
+
+            let mut key = String::new();
+            loop {
+                let mut line = String::new();
+                socket.reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("Sec-WebSocket-Key:") {
+                    key = value.trim().to_string();
+                }
+            }
+            write!(
+                socket.writer,
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept(&key)
+            )
+            .unwrap();
+
+            // Servers do not mask their frames.
+            let (_, _, payload) = socket.read_frame().unwrap();
+            let mut reply = vec![0x80 | PING, 0, 0x80 | TEXT];
+            reply.push(payload.len() as u8);
+            reply.extend(payload);
+            reply.extend(&[0x80 | CLOSE, 0]);
+            socket.writer.write_all(&reply).unwrap();
+
+            let (_, opcode, _) = socket.read_frame().unwrap();
+            assert_eq!(opcode, PONG);
+            let (_, opcode, _) = socket.read_frame().unwrap();
+            assert_eq!(opcode, CLOSE);
+        });
+
+        let url = format!("ws://127.0.0.1:{}/chat", port);
+        let mut socket = Socket::connect(&url).unwrap();
+        socket.send("hej").unwrap();
+        assert_eq!(socket.recv().unwrap(), Some("hej".to_string()));
+        assert_eq!(socket.recv().unwrap(), None);
+        assert!(socket.send("again").is_err());
+        server.join().unwrap();
+
+        assert!(Socket::connect("http://127.0.0.1/").is_err());
+    }
+
+    #[test]
+    fn secure_urls_are_refused() {
+        let error = Socket::connect("wss://127.0.0.1/chat").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().starts_with("Unsupported scheme 'wss'"));
+    }
+}
@@ -0,0 +1,58 @@
+use std::process::{Command, Output, Stdio};
+
+fn blixt(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_blixt"))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to run blixt")
+}
+
+#[test]
+fn successful_scripts_exit_with_zero() {
+    let output = blixt(&["-e", "print(\"%\", 1 + 2)"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"3");
+}
+
+#[test]
+fn errors_exit_with_one() {
+    let scripts = [
+        // Lexing
+        "s := \"open",
+        // Parsing
+        "x := (1",
+        // Names
+        "print(\"%\", y)",
+        // Types
+        "x: int = \"one\"",
+        // Running
+        "z := 0 print(\"%\", 1 / z)",
+    ];
+    for script in &scripts {
+        let output = blixt(&["-e", script]);
+        assert_eq!(output.status.code(), Some(1), "{}", script);
+        assert!(!output.stderr.is_empty(), "{}", script);
+    }
+}
+
+#[test]
+fn missing_files_are_errors_not_crashes() {
+    let output = blixt(&["no/such/script.bl"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("no/such/script.bl"), "{}", stderr);
+    assert!(!stderr.contains("crashed"), "{}", stderr);
+}
+
+#[test]
+fn requiring_a_capability_does_not_grant_it() {
+    let script = "//! requires: fs\nprint(\"%\", 1)";
+    let output = blixt(&["-e", script]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+
+    let output = blixt(&["--allow", "fs", "-e", script]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"1");
+}